@@ -1,6 +1,6 @@
-use anyhow::{Context, Result};
-use calibre_conversion::plugins::epub_input::EpubInput;
-use calibre_conversion::plugins::epub_output::EpubOutput;
+use anyhow::Result;
+use calibre_conversion::registry::global_registry;
+use calibre_conversion::transform::cover::GenerateCover;
 use calibre_conversion::transform::html_roundtrip::HtmlRoundTrip;
 use calibre_conversion::{ConversionOptions, ConversionPipeline};
 use clap::Parser;
@@ -10,50 +10,59 @@ use std::path::PathBuf;
 #[command(author, version, about, long_about = None)]
 struct Cli {
     /// Input file path
-    #[arg(value_name = "INPUT")]
-    input: PathBuf,
+    #[arg(value_name = "INPUT", required_unless_present = "list_formats")]
+    input: Option<PathBuf>,
 
     /// Output file path
-    #[arg(value_name = "OUTPUT")]
-    output: PathBuf,
+    #[arg(value_name = "OUTPUT", required_unless_present = "list_formats")]
+    output: Option<PathBuf>,
+
+    /// List the registered input and output formats and exit
+    #[arg(long)]
+    list_formats: bool,
+
+    /// Generate a cover from the title, authors and series when the input
+    /// has no cover
+    #[arg(long)]
+    generate_cover: bool,
 }
 
 fn main() -> Result<()> {
     env_logger::init();
     let cli = Cli::parse();
+    let registry = global_registry();
 
-    println!("Converting {:?} to {:?}", cli.input, cli.output);
+    if cli.list_formats {
+        println!("Input formats: {}", registry.input_formats().join(", "));
+        println!("Output formats: {}", registry.output_formats().join(", "));
+        return Ok(());
+    }
 
-    // TODO: Dynamic plugin selection based on extension.
-    // For now, hardcoded to EPUB -> EPUB.
+    let (input, output) = match (cli.input, cli.output) {
+        (Some(input), Some(output)) => (input, output),
+        _ => anyhow::bail!("Both INPUT and OUTPUT are required"),
+    };
 
-    // Check extensions
-    let input_ext = cli.input.extension().and_then(|s| s.to_str()).unwrap_or("");
-    let output_ext = cli
-        .output
-        .extension()
-        .and_then(|s| s.to_str())
-        .unwrap_or("");
+    println!("Converting {:?} to {:?}", input, output);
 
-    if !input_ext.eq_ignore_ascii_case("epub") {
-        anyhow::bail!("Only EPUB input is supported currently.");
-    }
-    if !output_ext.eq_ignore_ascii_case("epub") {
-        anyhow::bail!("Only EPUB output is supported currently.");
-    }
-
-    // Setup Pipeline
-    let input_plugin = Box::new(EpubInput);
-    let output_plugin = Box::new(EpubOutput);
-    let mut pipeline = ConversionPipeline::new(input_plugin, output_plugin);
+    // Setup Pipeline, plugins are resolved from the file extensions
+    let mut pipeline = ConversionPipeline::from_registry(&registry, &input, &output)?;
+    println!(
+        "Using {} -> {}",
+        pipeline.input_plugin().name(),
+        pipeline.output_plugin().name()
+    );
 
     // Add Default Transforms
     // To demonstrate processing we use the HtmlRoundTrip transform
     pipeline.add_transform(Box::new(HtmlRoundTrip));
+    if cli.generate_cover {
+        pipeline.add_transform(Box::new(GenerateCover::new()));
+    }
 
     // Run
     let options = ConversionOptions::default();
-    pipeline.run(&cli.input, &cli.output, &options)?;
+    pipeline.run(&input, &output, &options)?;
 
     println!("Conversion complete!");
     Ok(())
@@ -1,10 +1,10 @@
-pub mod oeb;
 pub mod pipeline;
-pub mod plugins;
+pub mod registry;
 pub mod traits;
 pub mod transform; // New module
 
 // Re-export key items
-pub use oeb::OebBook;
+pub use calibre_ebooks::oeb::book::OEBBook;
 pub use pipeline::ConversionPipeline;
-pub use traits::{ConversionOptions, InputPlugin, OutputPlugin};
+pub use registry::PluginRegistry;
+pub use traits::{ConversionOptions, InputFormatPlugin, OutputFormatPlugin, Transform};
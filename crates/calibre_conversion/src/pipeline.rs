@@ -1,15 +1,17 @@
-use crate::traits::{ConversionOptions, InputPlugin, OutputPlugin, Transform};
-use anyhow::Result;
+use crate::registry::{global_registry, PluginRegistry};
+use crate::traits::{ConversionOptions, InputFormatPlugin, OutputFormatPlugin, Transform};
+use anyhow::{Context, Result};
 use std::path::Path;
+use std::sync::Arc;
 
 pub struct ConversionPipeline {
-    input: Box<dyn InputPlugin>,
-    output: Box<dyn OutputPlugin>,
+    input: Arc<dyn InputFormatPlugin>,
+    output: Arc<dyn OutputFormatPlugin>,
     transforms: Vec<Box<dyn Transform>>,
 }
 
 impl ConversionPipeline {
-    pub fn new(input: Box<dyn InputPlugin>, output: Box<dyn OutputPlugin>) -> Self {
+    pub fn new(input: Arc<dyn InputFormatPlugin>, output: Arc<dyn OutputFormatPlugin>) -> Self {
         Self {
             input,
             output,
@@ -17,6 +19,42 @@ impl ConversionPipeline {
         }
     }
 
+    /// Resolve the input and output plugins from the file extensions of the
+    /// given paths using the process wide registry.
+    pub fn for_paths(input_path: &Path, output_path: &Path) -> Result<Self> {
+        Self::from_registry(&global_registry(), input_path, output_path)
+    }
+
+    pub fn from_registry(
+        registry: &PluginRegistry,
+        input_path: &Path,
+        output_path: &Path,
+    ) -> Result<Self> {
+        let input = registry.input_for_path(input_path).with_context(|| {
+            format!(
+                "No input plugin for {:?}. Supported input formats: {}",
+                input_path,
+                registry.input_formats().join(", ")
+            )
+        })?;
+        let output = registry.output_for_path(output_path).with_context(|| {
+            format!(
+                "No output plugin for {:?}. Supported output formats: {}",
+                output_path,
+                registry.output_formats().join(", ")
+            )
+        })?;
+        Ok(Self::new(input, output))
+    }
+
+    pub fn input_plugin(&self) -> &dyn InputFormatPlugin {
+        self.input.as_ref()
+    }
+
+    pub fn output_plugin(&self) -> &dyn OutputFormatPlugin {
+        self.output.as_ref()
+    }
+
     pub fn add_transform(&mut self, transform: Box<dyn Transform>) {
         self.transforms.push(transform);
     }
@@ -27,8 +65,15 @@ impl ConversionPipeline {
         output_path: &Path,
         options: &ConversionOptions,
     ) -> Result<()> {
+        // Input plugins extract into this directory and the book's container
+        // keeps reading from it, so it must outlive the output step.
+        let temp_dir = tempfile::Builder::new()
+            .prefix("calibre_conversion_")
+            .tempdir()
+            .context("Failed to create temporary directory")?;
+
         // 1. Read input to OEB
-        let mut book = self.input.read(input_path, options)?;
+        let mut book = self.input.convert(input_path, temp_dir.path())?;
 
         // 2. Process Transforms
         for transform in &self.transforms {
@@ -36,7 +81,7 @@ impl ConversionPipeline {
         }
 
         // 3. Write output
-        self.output.write(&book, output_path, options)?;
+        self.output.convert(&mut book, output_path)?;
 
         Ok(())
     }
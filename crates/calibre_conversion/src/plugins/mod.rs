@@ -1,2 +0,0 @@
-pub mod epub_input;
-pub mod epub_output;
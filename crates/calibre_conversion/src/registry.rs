@@ -0,0 +1,6 @@
+// The format registry is shared with `calibre_ebooks::conversion::plumber`, so
+// both front ends resolve exactly the same set of formats.
+pub use calibre_ebooks::conversion::registry::{
+    global_registry, register_input_plugin, register_output_plugin, InputFormatPlugin,
+    OutputFormatPlugin, PluginRegistry,
+};
@@ -1,6 +1,7 @@
-use crate::oeb::OebBook;
 use anyhow::Result;
-use std::path::Path;
+use calibre_ebooks::oeb::book::OEBBook;
+
+pub use calibre_ebooks::conversion::registry::{InputFormatPlugin, OutputFormatPlugin};
 
 /// Options passed to the conversion process
 pub struct ConversionOptions {
@@ -18,17 +19,7 @@ impl Default for ConversionOptions {
     }
 }
 
-/// Trait for reading an input format into the OEB intermediate representation
-pub trait InputPlugin {
-    fn read(&self, path: &Path, options: &ConversionOptions) -> Result<OebBook>;
-}
-
-/// Trait for writing the OEB intermediate representation to an output format
-pub trait OutputPlugin {
-    fn write(&self, book: &OebBook, path: &Path, options: &ConversionOptions) -> Result<()>;
-}
-
 /// Trait for transforming the OEB intermediate representation
 pub trait Transform {
-    fn process(&self, book: &mut OebBook, options: &ConversionOptions) -> Result<()>;
+    fn process(&self, book: &mut OEBBook, options: &ConversionOptions) -> Result<()>;
 }
@@ -0,0 +1,75 @@
+use crate::traits::{ConversionOptions, Transform};
+use anyhow::{Context, Result};
+use calibre_ebooks::covers::{generate_cover, CoverPrefs};
+use calibre_ebooks::oeb::book::OEBBook;
+
+/// Adds a generated cover image to books that have none, built from the
+/// title, authors and series in their metadata.
+pub struct GenerateCover {
+    pub prefs: CoverPrefs,
+}
+
+impl GenerateCover {
+    /// Uses the saved cover generation preferences.
+    pub fn new() -> Self {
+        Self::with_prefs(CoverPrefs::load())
+    }
+
+    pub fn with_prefs(prefs: CoverPrefs) -> Self {
+        GenerateCover { prefs }
+    }
+}
+
+impl Default for GenerateCover {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn has_cover(book: &OEBBook) -> bool {
+    let is_image = |href: &str| {
+        book.manifest
+            .get_by_href(href.split('#').next().unwrap_or(""))
+            .is_some_and(|item| item.media_type.starts_with("image/"))
+    };
+    let from_metadata = book
+        .metadata
+        .first("cover")
+        .and_then(|id| book.manifest.get_by_id(id))
+        .is_some_and(|item| item.media_type.starts_with("image/"));
+    from_metadata
+        || book
+            .guide
+            .get("cover")
+            .is_some_and(|reference| is_image(&reference.href))
+}
+
+impl Transform for GenerateCover {
+    fn process(&self, book: &mut OEBBook, _options: &ConversionOptions) -> Result<()> {
+        if has_cover(book) {
+            return Ok(());
+        }
+        let mi = book.metadata.to_meta_information();
+        let data = generate_cover(&mi, &self.prefs).context("Failed to generate a cover")?;
+
+        let mut href = "cover.jpg".to_string();
+        let mut id = "cover".to_string();
+        let mut n = 0;
+        while book.manifest.get_by_href(&href).is_some() || book.manifest.get_by_id(&id).is_some() {
+            n += 1;
+            href = format!("cover{}.jpg", n);
+            id = format!("cover{}", n);
+        }
+        book.container
+            .write(&href, &data)
+            .context("Failed to write the generated cover")?;
+        book.manifest.add(&id, &href, "image/jpeg");
+        // Replace any stale cover entry pointing at something else
+        book.metadata.items.retain(|item| item.term != "cover");
+        book.metadata.add("cover", &id);
+        book.guide.add("cover", Some("Cover".to_string()), &href);
+
+        log::info!("Generated a cover for {:?}", mi.title);
+        Ok(())
+    }
+}
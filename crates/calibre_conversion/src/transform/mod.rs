@@ -1,2 +1,4 @@
+pub mod cover;
 pub mod html_roundtrip;
+pub use cover::GenerateCover;
 pub use html_roundtrip::HtmlRoundTrip;
@@ -0,0 +1,53 @@
+use anyhow::Result;
+use calibre_conversion::traits::{ConversionOptions, Transform};
+use calibre_conversion::transform::cover::GenerateCover;
+use calibre_conversion::OEBBook;
+use calibre_ebooks::covers::CoverPrefs;
+use calibre_ebooks::oeb::container::DirContainer;
+use tempfile::Builder;
+
+fn transform() -> GenerateCover {
+    GenerateCover::with_prefs(CoverPrefs::default().scaled(0.2))
+}
+
+#[test]
+fn test_generate_cover_for_book_without_cover() -> Result<()> {
+    let temp_dir = Builder::new().prefix("cover_test_").tempdir()?;
+    let mut book = OEBBook::new(Box::new(DirContainer::new(temp_dir.path())));
+    book.metadata.add("title", "A Book");
+    book.metadata.add("creator", "An Author");
+    // A stale cover entry that points nowhere
+    book.metadata.add("cover", "missing");
+    book.manifest
+        .add("cover", "cover.jpg", "application/xhtml+xml");
+
+    transform().process(&mut book, &ConversionOptions::default())?;
+
+    let id = book.metadata.first("cover").unwrap().to_string();
+    assert_eq!(id, "cover1");
+    let item = book.manifest.get_by_id(&id).unwrap();
+    assert_eq!(item.href, "cover1.jpg");
+    assert_eq!(item.media_type, "image/jpeg");
+    assert_eq!(book.guide.get("cover").unwrap().href, "cover1.jpg");
+
+    let data = std::fs::read(temp_dir.path().join("cover1.jpg"))?;
+    assert_eq!(&data[..3], &[0xff, 0xd8, 0xff]);
+    Ok(())
+}
+
+#[test]
+fn test_existing_cover_is_kept() -> Result<()> {
+    let temp_dir = Builder::new().prefix("cover_test_").tempdir()?;
+    let mut book = OEBBook::new(Box::new(DirContainer::new(temp_dir.path())));
+    book.metadata.add("title", "A Book");
+    book.manifest.add("img", "images/front.png", "image/png");
+    book.guide
+        .add("cover", Some("Cover".to_string()), "images/front.png");
+
+    transform().process(&mut book, &ConversionOptions::default())?;
+
+    assert!(book.metadata.first("cover").is_none());
+    assert!(book.manifest.get_by_href("cover.jpg").is_none());
+    assert!(!temp_dir.path().join("cover.jpg").exists());
+    Ok(())
+}
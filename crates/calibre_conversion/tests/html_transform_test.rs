@@ -1,7 +1,8 @@
 use anyhow::Result;
-use calibre_conversion::oeb::{ManifestItem, OebBook};
 use calibre_conversion::traits::{ConversionOptions, Transform};
 use calibre_conversion::transform::html_roundtrip::HtmlRoundTrip;
+use calibre_conversion::OEBBook;
+use calibre_ebooks::oeb::container::DirContainer;
 use std::fs::File;
 use std::io::{Read, Write};
 use tempfile::Builder;
@@ -20,17 +21,10 @@ fn test_html_roundtrip() -> Result<()> {
         f.write_all(original_html.as_bytes())?;
     }
 
-    // 2. Setup OebBook
-    let mut book = OebBook::new();
-    book.manifest.insert(
-        "item1".to_string(),
-        ManifestItem {
-            id: "item1".to_string(),
-            href: "chapter1.html".to_string(),
-            media_type: "application/xhtml+xml".to_string(),
-            path: html_path.clone(),
-        },
-    );
+    // 2. Setup OEBBook
+    let mut book = OEBBook::new(Box::new(DirContainer::new(temp_dir.path())));
+    book.manifest
+        .add("item1", "chapter1.html", "application/xhtml+xml");
 
     // 3. Run Transform
     let transform = HtmlRoundTrip;
@@ -1,6 +1,4 @@
 use anyhow::Result;
-use calibre_conversion::plugins::epub_input::EpubInput;
-use calibre_conversion::plugins::epub_output::EpubOutput;
 use calibre_conversion::{ConversionOptions, ConversionPipeline};
 use std::path::PathBuf;
 use tempfile::Builder;
@@ -19,17 +17,14 @@ fn test_identity_conversion() -> Result<()> {
         input_path
     );
 
-    // 2. Setup Pipeline
-    let input_plugin = Box::new(EpubInput);
-    let output_plugin = Box::new(EpubOutput);
-
-    let pipeline = ConversionPipeline::new(input_plugin, output_plugin);
-    let options = ConversionOptions::default();
-
-    // 3. Prepare Output
+    // 2. Prepare Output
     let temp_dir = Builder::new().prefix("calibre_test_output_").tempdir()?;
     let output_path = temp_dir.path().join("output.epub");
 
+    // 3. Setup Pipeline, EPUB -> EPUB resolved from the registry
+    let pipeline = ConversionPipeline::for_paths(&input_path, &output_path)?;
+    let options = ConversionOptions::default();
+
     // 4. Run
     pipeline.run(&input_path, &output_path, &options)?;
 
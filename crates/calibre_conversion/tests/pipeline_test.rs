@@ -1,41 +1,39 @@
 use anyhow::Result;
-use calibre_conversion::plugins::epub_input::EpubInput;
-use calibre_conversion::{
-    ConversionOptions, ConversionPipeline, InputPlugin, OebBook, OutputPlugin,
-};
+use calibre_conversion::registry::global_registry;
+use calibre_conversion::{ConversionOptions, ConversionPipeline, OEBBook, OutputFormatPlugin};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 // Mock Output Plugin
 struct MockOutput {
-    pub last_book: Arc<Mutex<Option<OebBook>>>,
+    pub last_title: Arc<Mutex<Option<String>>>,
 }
 
 impl MockOutput {
     fn new() -> Self {
         Self {
-            last_book: Arc::new(Mutex::new(None)),
+            last_title: Arc::new(Mutex::new(None)),
         }
     }
 }
 
-impl OutputPlugin for MockOutput {
-    fn write(&self, book: &OebBook, _path: &Path, _options: &ConversionOptions) -> Result<()> {
-        // Clone the book data we care about for verification
-        // Since OebBook isn't fully Clone yet (maybe?), we just inspect it here or store it.
-        // Actually OebBook derives Default/Debug, let's just store the title to verify.
-        let mut loop_lock = self.last_book.lock().unwrap();
-        // create a copy or just move fields if we could, but we can't consume.
-        // We'll just manufacture a copy for the test check
-        let copy = OebBook {
-            metadata: calibre_ebooks::opf::OpfMetadata {
-                title: book.metadata.title.clone(),
-                authors: book.metadata.authors.clone(),
-                ..Default::default()
-            },
-            ..Default::default()
-        };
-        *loop_lock = Some(copy);
+impl OutputFormatPlugin for MockOutput {
+    fn name(&self) -> &str {
+        "Mock Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["mock"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &[]
+    }
+
+    fn convert(&self, book: &mut OEBBook, _path: &Path) -> Result<()> {
+        // Just store the title to verify.
+        let title = book.metadata.get("title").first().map(|i| i.value.clone());
+        *self.last_title.lock().unwrap() = title;
         Ok(())
     }
 }
@@ -54,32 +52,43 @@ fn test_pipeline_epub_read() {
         input_path
     );
 
-    // 2. Setup Pipeline
-    let input_plugin = Box::new(EpubInput);
+    // 2. Setup Pipeline, input resolved through the registry
+    let input_plugin = global_registry()
+        .input_for_extension("epub")
+        .expect("EPUB input not registered");
     let mock_output = MockOutput::new();
-    let result_storage = mock_output.last_book.clone();
-    let output_plugin = Box::new(mock_output);
+    let result_storage = mock_output.last_title.clone();
 
-    let pipeline = ConversionPipeline::new(input_plugin, output_plugin);
+    let pipeline = ConversionPipeline::new(input_plugin, Arc::new(mock_output));
     let options = ConversionOptions::default();
 
     // 3. Run
-    let output_path = PathBuf::from("dummy_output.mobi"); // Won't be used by mock
+    let output_path = PathBuf::from("dummy_output.mock"); // Won't be used by mock
     let res = pipeline.run(&input_path, &output_path, &options);
 
     assert!(res.is_ok(), "Pipeline failed: {:?}", res.err());
 
     // 4. Verify
-    let db = result_storage.lock().unwrap();
-    let book = db.as_ref().expect("Output plugin was not called");
-
-    println!("Parsed Title: {}", book.metadata.title);
-    println!("Parsed Authors: {:?}", book.metadata.authors);
+    let title = result_storage.lock().unwrap().clone();
+    let title = title.expect("Output plugin was not called with a title");
 
     // Calibre Quick Start Guide usually has this title
     assert!(
-        book.metadata.title.contains("Quick Start Guide"),
+        title.contains("Quick Start Guide"),
         "Title mismatch: {}",
-        book.metadata.title
+        title
     );
 }
+
+#[test]
+fn test_pipeline_resolves_plugins_by_extension() {
+    let pipeline =
+        ConversionPipeline::for_paths(Path::new("book.epub"), Path::new("book.docx")).unwrap();
+    assert_eq!(pipeline.input_plugin().name(), "EPUB Input");
+    assert_eq!(pipeline.output_plugin().name(), "DOCX Output");
+
+    let err = ConversionPipeline::for_paths(Path::new("book.xyz"), Path::new("book.epub"))
+        .err()
+        .expect("unknown input format should fail");
+    assert!(err.to_string().contains("Supported input formats"));
+}
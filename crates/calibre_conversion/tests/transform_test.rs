@@ -1,21 +1,49 @@
 use anyhow::Result;
-use calibre_conversion::oeb::OebBook;
-use calibre_conversion::traits::{ConversionOptions, InputPlugin, OutputPlugin, Transform};
-use calibre_conversion::ConversionPipeline;
+use calibre_conversion::traits::{
+    ConversionOptions, InputFormatPlugin, OutputFormatPlugin, Transform,
+};
+use calibre_conversion::{ConversionPipeline, OEBBook};
+use calibre_ebooks::oeb::container::NullContainer;
 use std::path::Path;
+use std::sync::Arc;
 
 struct MockInputLib;
-impl InputPlugin for MockInputLib {
-    fn read(&self, _path: &Path, _options: &ConversionOptions) -> Result<OebBook> {
-        let mut book = OebBook::new();
-        book.metadata.title = "Original Title".to_string();
+impl InputFormatPlugin for MockInputLib {
+    fn name(&self) -> &str {
+        "Mock Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["mock"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &[]
+    }
+
+    fn convert(&self, _path: &Path, _output_dir: &Path) -> Result<OEBBook> {
+        let mut book = OEBBook::new(Box::new(NullContainer::new()));
+        book.metadata.add("title", "Original Title");
         Ok(book)
     }
 }
 
 struct MockOutputLib;
-impl OutputPlugin for MockOutputLib {
-    fn write(&self, _book: &OebBook, _path: &Path, _options: &ConversionOptions) -> Result<()> {
+impl OutputFormatPlugin for MockOutputLib {
+    fn name(&self) -> &str {
+        "Mock Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["mock"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &[]
+    }
+
+    fn convert(&self, book: &mut OEBBook, _path: &Path) -> Result<()> {
+        assert_eq!(title(book), "Processed: Original Title");
         Ok(())
     }
 }
@@ -25,38 +53,42 @@ struct TitlePrefixTransform {
 }
 
 impl Transform for TitlePrefixTransform {
-    fn process(&self, book: &mut OebBook, _options: &ConversionOptions) -> Result<()> {
-        book.metadata.title = format!("{} {}", self.prefix, book.metadata.title);
+    fn process(&self, book: &mut OEBBook, _options: &ConversionOptions) -> Result<()> {
+        for item in book.metadata.items.iter_mut().filter(|i| i.term == "title") {
+            item.value = format!("{} {}", self.prefix, item.value);
+        }
         Ok(())
     }
 }
 
+fn title(book: &OEBBook) -> String {
+    book.metadata
+        .get("title")
+        .first()
+        .map(|i| i.value.clone())
+        .unwrap_or_default()
+}
+
 #[test]
 fn test_pipeline_transform() -> Result<()> {
     // Setup
-    let input = Box::new(MockInputLib);
-    let output = Box::new(MockOutputLib);
-    let mut pipeline = ConversionPipeline::new(input, output);
+    let mut pipeline = ConversionPipeline::new(Arc::new(MockInputLib), Arc::new(MockOutputLib));
 
     // Add Transform
     pipeline.add_transform(Box::new(TitlePrefixTransform {
         prefix: "Processed:".to_string(),
     }));
 
-    // Run (we can't easily check the result inside the pipeline without a better spy,
-    // so we'll just check that it runs Ok for now,
-    // OR we could use a specific MockOutput that asserts the title.
-    // Let's do a quick manual check by calling the transform directly first to verify logic)
-
-    let mut book = OebBook::new();
-    book.metadata.title = "Test".to_string();
+    // Quick manual check by calling the transform directly first to verify logic
+    let mut book = OEBBook::new(Box::new(NullContainer::new()));
+    book.metadata.add("title", "Test");
     let t = TitlePrefixTransform {
         prefix: "Pre".to_string(),
     };
     t.process(&mut book, &ConversionOptions::default())?;
-    assert_eq!(book.metadata.title, "Pre Test");
+    assert_eq!(title(&book), "Pre Test");
 
-    // Test Pipeline Integration
+    // Test Pipeline Integration, the mock output asserts the transformed title
     let path = Path::new("dummy");
     pipeline.run(path, path, &ConversionOptions::default())?;
 
@@ -1,7 +1,19 @@
 use crate::cache::Cache;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use calibre_ebooks::constants::BOOK_EXTENSIONS;
+use calibre_ebooks::metadata::author_mapper::{compile_rules, map_authors, Rule as AuthorRule};
+use calibre_ebooks::metadata::tag_mapper::map_tags;
+use calibre_ebooks::metadata::MetaInformation;
+use calibre_utils::constants::config_dir;
+use indexmap::IndexMap;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use uuid::Uuid;
+use walkdir::WalkDir;
 
 /// Adds a new book to the database.
 ///
@@ -38,3 +50,208 @@ pub fn add_book(cache: &Arc<Mutex<Cache>>, title: &str, authors: &[String]) -> R
 
     Ok(book_id)
 }
+
+// --- Finding books in folders ---
+
+/// A filename rule from `calibredb add --ignore/--add`: files matching it
+/// are ignored or added regardless of their extension.
+pub struct FilenameRule {
+    pattern: Regex,
+    add: bool,
+}
+
+impl FilenameRule {
+    /// A case insensitive glob pattern such as `*.pdf`.
+    pub fn glob(pattern: &str, add: bool) -> Result<Self> {
+        let mut re = String::from("(?i)^");
+        for c in pattern.chars() {
+            match c {
+                '*' => re.push_str(".*"),
+                '?' => re.push('.'),
+                c => re.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        re.push('$');
+        let pattern = Regex::new(&re)
+            .with_context(|| format!("{:?} is not a valid filename pattern", pattern))?;
+        Ok(FilenameRule { pattern, add })
+    }
+}
+
+/// Whether the first rule matching `filename` adds or ignores it.
+pub fn filter_filename(rules: &[FilenameRule], filename: &str) -> Option<bool> {
+    rules
+        .iter()
+        .find(|r| r.pattern.is_match(filename))
+        .map(|r| r.add)
+}
+
+/// Known e-book files, and OPF files for their metadata, are added unless
+/// a rule says otherwise.
+fn allow_path(path: &Path, rules: &[FilenameRule]) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    filter_filename(rules, &name).unwrap_or_else(|| {
+        let ext = splitext(path).1;
+        ext == "opf" || BOOK_EXTENSIONS.contains(&ext.as_str())
+    })
+}
+
+/// The path without its extension, lowercased, and the lowercased
+/// extension.
+fn splitext(path: &Path) -> (String, String) {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let key = path.with_extension("").to_string_lossy().to_lowercase();
+    (key, ext)
+}
+
+/// The files directly inside `dir`, oldest first.
+fn files_by_mtime(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<(SystemTime, PathBuf)> = entries
+        .flatten()
+        .filter(|e| e.path().is_file())
+        .map(|e| {
+            let mtime = e
+                .metadata()
+                .and_then(|m| m.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            (mtime, e.path())
+        })
+        .collect();
+    files.sort();
+    files.into_iter().map(|(_, path)| path).collect()
+}
+
+/// The books in `dir`, each as the list of its format files. With
+/// `single_book_per_directory` all files are formats of one book, otherwise
+/// files with the same name and different extensions are grouped.
+pub fn find_books_in_directory(
+    dir: &Path,
+    single_book_per_directory: bool,
+    rules: &[FilenameRule],
+) -> Vec<Vec<PathBuf>> {
+    let mut books: IndexMap<String, IndexMap<String, PathBuf>> = IndexMap::new();
+    for path in files_by_mtime(dir) {
+        if !allow_path(&path, rules) {
+            continue;
+        }
+        let (key, ext) = splitext(&path);
+        let key = if single_book_per_directory {
+            String::new()
+        } else {
+            key
+        };
+        books.entry(key).or_default().insert(ext, path);
+    }
+    books
+        .into_values()
+        .filter(|formats| !formats.is_empty())
+        .map(|formats| formats.into_values().collect())
+        .collect()
+}
+
+/// The books in `root` and all folders below it.
+pub fn recursive_find(
+    root: &Path,
+    single_book_per_directory: bool,
+    rules: &[FilenameRule],
+) -> Vec<Vec<PathBuf>> {
+    WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        .flatten()
+        .filter(|e| e.file_type().is_dir())
+        .flat_map(|e| find_books_in_directory(e.path(), single_book_per_directory, rules))
+        .collect()
+}
+
+/// Upper case format to file, OPF files are only used for metadata.
+pub fn create_format_map(formats: &[PathBuf]) -> IndexMap<String, PathBuf> {
+    formats
+        .iter()
+        .filter_map(|path| {
+            let ext = splitext(path).1.to_uppercase();
+            (!ext.is_empty() && ext != "OPF").then(|| (ext, path.clone()))
+        })
+        .collect()
+}
+
+// --- Import time metadata rules ---
+
+/// The tag and author mapping rules applied to books when they are added,
+/// the `tag_map_on_add_rules` and `author_map_on_add_rules` of calibre's
+/// `gui.json`.
+#[derive(Debug, Clone, Default)]
+pub struct ImportRules {
+    pub tag_rules: Vec<HashMap<String, String>>,
+    pub author_rules: Vec<AuthorRule>,
+}
+
+impl ImportRules {
+    pub fn load() -> Self {
+        let path = config_dir().join("gui.json");
+        let Ok(content) = fs::read_to_string(&path) else {
+            return ImportRules::default();
+        };
+        match serde_json::from_str::<serde_json::Value>(&content) {
+            Ok(prefs) => ImportRules::from_prefs(&prefs),
+            Err(e) => {
+                log::warn!("Failed to read {:?}: {}", path, e);
+                ImportRules::default()
+            }
+        }
+    }
+
+    pub fn from_prefs(prefs: &serde_json::Value) -> Self {
+        let rules = |key: &str| -> Vec<HashMap<String, String>> {
+            prefs
+                .get(key)
+                .and_then(|r| r.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|rule| rule.as_object())
+                .map(|rule| {
+                    rule.iter()
+                        .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+                        .collect()
+                })
+                .collect()
+        };
+        let author_rules = rules("author_map_on_add_rules")
+            .into_iter()
+            .map(|mut rule| AuthorRule {
+                action: rule.remove("action").unwrap_or_default(),
+                query: rule.remove("query").unwrap_or_default(),
+                replace: rule.remove("replace"),
+                match_type: rule.remove("match_type").unwrap_or_default(),
+            })
+            .collect();
+        ImportRules {
+            tag_rules: rules("tag_map_on_add_rules"),
+            author_rules,
+        }
+    }
+
+    /// Map the tags and authors of `mi`.
+    pub fn apply(&self, mi: &mut MetaInformation) {
+        if !self.tag_rules.is_empty() {
+            mi.tags = map_tags(std::mem::take(&mut mi.tags), self.tag_rules.clone(), None);
+        }
+        if !self.author_rules.is_empty() {
+            let authors = map_authors(&mi.authors, &compile_rules(&self.author_rules));
+            if authors != mi.authors {
+                mi.authors = if authors.is_empty() {
+                    vec!["Unknown".to_string()]
+                } else {
+                    authors
+                };
+                mi.author_sort = None;
+            }
+        }
+    }
+}
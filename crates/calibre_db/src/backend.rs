@@ -22,7 +22,7 @@ impl Backend {
         conn.execute("PRAGMA temp_store=2", [])?;
         conn.execute("PRAGMA foreign_keys=ON", [])?;
 
-        // Run schema upgrades (stub for now)
+        // Refuses libraries newer than this code understands
         crate::schema_upgrades::SchemaUpgrade::upgrade_to_latest(&mut conn, &library_path)?;
 
         let backend = Backend {
@@ -128,6 +128,15 @@ impl Backend {
         Ok(series)
     }
 
+    pub fn set_pref(&mut self, key: &str, val: &str) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO preferences (key, val) VALUES (?1, ?2)",
+            (key, val),
+        )?;
+        self.prefs.insert(key.to_string(), val.to_string());
+        Ok(())
+    }
+
     pub fn load_prefs(&mut self) -> Result<()> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare("SELECT key, val FROM preferences")?;
@@ -0,0 +1,540 @@
+//! Editing the metadata of many books at once. All the changes of one bulk
+//! edit are made in a single transaction, and are journaled so that the
+//! last few edits can be undone.
+
+use crate::library::Library;
+use crate::listeners::{Event, EventDispatcher};
+use crate::schema_upgrades::table_exists;
+use anyhow::{anyhow, bail, Result};
+use calibre_ebooks::metadata::authors::authors_to_sort_string;
+use calibre_ebooks::metadata::{string_to_authors, title_sort};
+use calibre_utils::icu::{capitalize, lower, upper};
+use calibre_utils::titlecase::titlecase;
+use indexmap::IndexMap;
+use regex::Regex;
+use rusqlite::{OptionalExtension, Transaction};
+use std::collections::VecDeque;
+
+/// How many bulk edits can be undone by default.
+pub const DEFAULT_UNDO_LIMIT: usize = 10;
+
+/// The changes of case of `FieldOp::ChangeCase`, as in calibre's bulk
+/// metadata dialog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Case {
+    Lower,
+    Upper,
+    Title,
+    Capitalize,
+}
+
+impl Case {
+    fn apply(self, text: &str) -> String {
+        match self {
+            Case::Lower => lower(text),
+            Case::Upper => upper(text),
+            Case::Title => titlecase(text),
+            Case::Capitalize => capitalize(text),
+        }
+    }
+}
+
+/// One change made to every book of a bulk edit.
+#[derive(Debug, Clone)]
+pub enum FieldOp {
+    /// Replace the value of a field. Authors are separated by `&`, the
+    /// items of other multiple value fields by commas. An empty value
+    /// clears the field.
+    Set { field: String, value: String },
+    /// Add items to a multiple value field, such as tags.
+    Append { field: String, items: Vec<String> },
+    /// Remove items, in any case, from a multiple value field.
+    Remove { field: String, items: Vec<String> },
+    /// Replace the matches of a regular expression. Multiple value fields
+    /// have the replacement made in each item, items left empty are dropped.
+    SearchReplace {
+        field: String,
+        pattern: Regex,
+        replace: String,
+    },
+    /// Number the books in the order they are given, from `start` in steps
+    /// of `increment`.
+    RenumberSeries { start: f64, increment: f64 },
+    /// Change the case of a field.
+    ChangeCase { field: String, case: Case },
+}
+
+impl FieldOp {
+    fn field(&self) -> &str {
+        match self {
+            FieldOp::Set { field, .. }
+            | FieldOp::Append { field, .. }
+            | FieldOp::Remove { field, .. }
+            | FieldOp::SearchReplace { field, .. }
+            | FieldOp::ChangeCase { field, .. } => field,
+            FieldOp::RenumberSeries { .. } => "series_index",
+        }
+    }
+}
+
+/// The value of a field of one book.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Text(Option<String>),
+    List(Vec<String>),
+    Number(f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Text,
+    List,
+    Number,
+}
+
+/// Where a field is stored.
+enum Storage {
+    /// A column of the books table
+    Column(&'static str),
+    /// A table of values linked to the books, `(table, column, link table,
+    /// link column)`
+    Linked(&'static str, &'static str, &'static str, &'static str),
+    Comments,
+}
+
+fn field_info(field: &str) -> Result<(Kind, Storage)> {
+    use Storage::*;
+    Ok(match field {
+        "title" => (Kind::Text, Column("title")),
+        "sort" | "title_sort" => (Kind::Text, Column("sort")),
+        "author_sort" => (Kind::Text, Column("author_sort")),
+        "isbn" => (Kind::Text, Column("isbn")),
+        "lccn" => (Kind::Text, Column("lccn")),
+        "pubdate" => (Kind::Text, Column("pubdate")),
+        "timestamp" => (Kind::Text, Column("timestamp")),
+        "series_index" => (Kind::Number, Column("series_index")),
+        "authors" => (
+            Kind::List,
+            Linked("authors", "name", "books_authors_link", "author"),
+        ),
+        "tags" => (Kind::List, Linked("tags", "name", "books_tags_link", "tag")),
+        "languages" => (
+            Kind::List,
+            Linked(
+                "languages",
+                "lang_code",
+                "books_languages_link",
+                "lang_code",
+            ),
+        ),
+        "series" => (
+            Kind::Text,
+            Linked("series", "name", "books_series_link", "series"),
+        ),
+        "publisher" => (
+            Kind::Text,
+            Linked("publishers", "name", "books_publishers_link", "publisher"),
+        ),
+        "comments" => (Kind::Text, Comments),
+        _ => bail!("Bulk editing is not supported for the field: {}", field),
+    })
+}
+
+fn read_field(tx: &Transaction, book_id: i32, field: &str) -> Result<FieldValue> {
+    let (kind, storage) = field_info(field)?;
+    let value = match storage {
+        Storage::Column(column) => {
+            let sql = format!("SELECT {} FROM books WHERE id = ?1", column);
+            if kind == Kind::Number {
+                let val: Option<f64> = tx.query_row(&sql, [book_id], |row| row.get(0))?;
+                FieldValue::Number(val.unwrap_or(1.0))
+            } else {
+                FieldValue::Text(tx.query_row(&sql, [book_id], |row| row.get(0))?)
+            }
+        }
+        Storage::Linked(table, column, link_table, link_column) => {
+            let items = if table_exists(tx, table)? && table_exists(tx, link_table)? {
+                let order = if table == "languages" {
+                    "l.item_order"
+                } else {
+                    "l.id"
+                };
+                let sql = format!(
+                    "SELECT t.{column} FROM {table} t JOIN {link_table} l ON t.id = l.{link_column}
+                     WHERE l.book = ?1 ORDER BY {order}"
+                );
+                let mut stmt = tx.prepare(&sql)?;
+                let rows = stmt.query_map([book_id], |row| row.get(0))?;
+                rows.collect::<rusqlite::Result<Vec<String>>>()?
+            } else {
+                Vec::new()
+            };
+            match kind {
+                Kind::List => FieldValue::List(items),
+                _ => FieldValue::Text(items.into_iter().next()),
+            }
+        }
+        Storage::Comments => {
+            let text = if table_exists(tx, "comments")? {
+                tx.query_row(
+                    "SELECT text FROM comments WHERE book = ?1",
+                    [book_id],
+                    |row| row.get(0),
+                )
+                .optional()?
+            } else {
+                None
+            };
+            FieldValue::Text(text)
+        }
+    };
+    Ok(value)
+}
+
+fn write_field(tx: &Transaction, book_id: i32, field: &str, value: &FieldValue) -> Result<()> {
+    let (_, storage) = field_info(field)?;
+    match storage {
+        Storage::Column(column) => {
+            let sql = format!("UPDATE books SET {} = ?1 WHERE id = ?2", column);
+            match value {
+                FieldValue::Number(n) => tx.execute(&sql, (n, book_id))?,
+                FieldValue::Text(text) => tx.execute(&sql, (text, book_id))?,
+                FieldValue::List(_) => bail!("{} has a single value", field),
+            };
+        }
+        Storage::Linked(table, column, link_table, link_column) => {
+            if !table_exists(tx, table)? || !table_exists(tx, link_table)? {
+                bail!("The library has no {} table", table);
+            }
+            let items = match value {
+                FieldValue::List(items) => items.clone(),
+                FieldValue::Text(text) => text.iter().cloned().collect(),
+                FieldValue::Number(_) => bail!("{} is not a number", field),
+            };
+            tx.execute(
+                &format!("DELETE FROM {} WHERE book = ?1", link_table),
+                [book_id],
+            )?;
+            for (order, item) in items.iter().enumerate() {
+                let insert = match table {
+                    "authors" | "series" | "publishers" => {
+                        format!("INSERT OR IGNORE INTO {table} (name, sort) VALUES (?1, ?1)")
+                    }
+                    _ => format!("INSERT OR IGNORE INTO {table} ({column}) VALUES (?1)"),
+                };
+                tx.execute(&insert, [item])?;
+                // Tables compare names without case, as in calibre a change
+                // of case renames the item for all books
+                tx.execute(
+                    &format!("UPDATE {table} SET {column} = ?1 WHERE {column} = ?1"),
+                    [item],
+                )?;
+                if table == "languages" {
+                    tx.execute(
+                        "INSERT INTO books_languages_link (book, lang_code, item_order)
+                         SELECT ?1, id, ?3 FROM languages WHERE lang_code = ?2",
+                        (book_id, item, order as i32),
+                    )?;
+                } else {
+                    tx.execute(
+                        &format!(
+                            "INSERT INTO {link_table} (book, {link_column})
+                             SELECT ?1, id FROM {table} WHERE {column} = ?2"
+                        ),
+                        (book_id, item),
+                    )?;
+                }
+            }
+        }
+        Storage::Comments => {
+            if !table_exists(tx, "comments")? {
+                bail!("The library has no comments table");
+            }
+            tx.execute("DELETE FROM comments WHERE book = ?1", [book_id])?;
+            if let FieldValue::Text(Some(text)) = value {
+                tx.execute(
+                    "INSERT INTO comments (book, text) VALUES (?1, ?2)",
+                    (book_id, text),
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The value `op` gives a field of the book at `position` in the edit.
+fn apply_op(op: &FieldOp, position: usize, value: &FieldValue) -> Result<FieldValue> {
+    let field = op.field();
+    let map_text = |f: &dyn Fn(&str) -> String| -> FieldValue {
+        match value {
+            FieldValue::Text(text) => {
+                FieldValue::Text(text.as_deref().map(f).filter(|t| !t.is_empty()))
+            }
+            FieldValue::List(items) => {
+                let mut mapped: Vec<String> = Vec::new();
+                for item in items.iter().map(|i| f(i)) {
+                    let item = item.trim().to_string();
+                    if !item.is_empty() && !contains(&mapped, &item) {
+                        mapped.push(item);
+                    }
+                }
+                FieldValue::List(mapped)
+            }
+            FieldValue::Number(n) => FieldValue::Number(*n),
+        }
+    };
+
+    Ok(match op {
+        FieldOp::Set { value: new, .. } => match value {
+            FieldValue::Text(_) => FieldValue::Text(Some(new.clone()).filter(|v| !v.is_empty())),
+            FieldValue::List(_) if field == "authors" => FieldValue::List(string_to_authors(new)),
+            FieldValue::List(_) => FieldValue::List(split_items(new)),
+            FieldValue::Number(_) => FieldValue::Number(
+                new.trim()
+                    .parse()
+                    .map_err(|_| anyhow!("{:?} is not a valid {}", new, field))?,
+            ),
+        },
+        FieldOp::Append { items, .. } => {
+            let FieldValue::List(current) = value else {
+                bail!("{} does not have multiple values", field);
+            };
+            let mut current = current.clone();
+            for item in items {
+                if !contains(&current, item) {
+                    current.push(item.clone());
+                }
+            }
+            FieldValue::List(current)
+        }
+        FieldOp::Remove { items, .. } => {
+            let FieldValue::List(current) = value else {
+                bail!("{} does not have multiple values", field);
+            };
+            FieldValue::List(
+                current
+                    .iter()
+                    .filter(|c| !contains(items, c))
+                    .cloned()
+                    .collect(),
+            )
+        }
+        FieldOp::SearchReplace {
+            pattern, replace, ..
+        } => match value {
+            FieldValue::Number(n) => {
+                let n = n.to_string();
+                let text = pattern.replace_all(&n, replace.as_str());
+                FieldValue::Number(
+                    text.trim()
+                        .parse()
+                        .map_err(|_| anyhow!("{:?} is not a valid {}", text, field))?,
+                )
+            }
+            _ => map_text(&|t| pattern.replace_all(t, replace.as_str()).into_owned()),
+        },
+        FieldOp::RenumberSeries { start, increment } => {
+            FieldValue::Number(start + position as f64 * increment)
+        }
+        FieldOp::ChangeCase { case, .. } => map_text(&|t| case.apply(t)),
+    })
+}
+
+fn contains(items: &[String], item: &str) -> bool {
+    let item = item.to_lowercase();
+    items.iter().any(|i| i.to_lowercase() == item)
+}
+
+fn split_items(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    for item in value.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+        if !contains(&items, item) {
+            items.push(item.to_string());
+        }
+    }
+    items
+}
+
+/// Books always have a title and authors.
+fn normalize(field: &str, value: FieldValue) -> FieldValue {
+    match (field, value) {
+        ("title", FieldValue::Text(None)) => FieldValue::Text(Some("Unknown".to_string())),
+        ("authors", FieldValue::List(authors)) if authors.is_empty() => {
+            FieldValue::List(vec!["Unknown".to_string()])
+        }
+        (_, value) => value,
+    }
+}
+
+/// The sort fields that follow the title and authors.
+fn derived_change(field: &str, value: &FieldValue) -> Option<(&'static str, FieldValue)> {
+    match (field, value) {
+        ("title", FieldValue::Text(title)) => {
+            Some(("sort", FieldValue::Text(title.as_deref().map(title_sort))))
+        }
+        ("authors", FieldValue::List(authors)) => Some((
+            "author_sort",
+            FieldValue::Text(Some(authors_to_sort_string(authors))),
+        )),
+        _ => None,
+    }
+}
+
+/// The change a bulk edit made to one field of one book.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub book_id: i32,
+    pub field: String,
+    pub before: FieldValue,
+    pub after: FieldValue,
+}
+
+/// Applies bulk edits to a library and keeps the journal to undo them.
+pub struct BulkEditor {
+    dispatcher: EventDispatcher,
+    journal: VecDeque<Vec<Change>>,
+    undo_limit: usize,
+}
+
+impl BulkEditor {
+    pub fn new(dispatcher: EventDispatcher) -> Self {
+        Self::with_undo_limit(dispatcher, DEFAULT_UNDO_LIMIT)
+    }
+
+    pub fn with_undo_limit(dispatcher: EventDispatcher, undo_limit: usize) -> Self {
+        BulkEditor {
+            dispatcher,
+            journal: VecDeque::new(),
+            undo_limit,
+        }
+    }
+
+    /// How many bulk edits can be undone.
+    pub fn undo_len(&self) -> usize {
+        self.journal.len()
+    }
+
+    /// Apply `ops`, in order, to the books in `book_ids`. Nothing is changed
+    /// if any of them fails. Returns the changes made.
+    pub fn apply(
+        &mut self,
+        db: &mut Library,
+        book_ids: &[i32],
+        ops: &[FieldOp],
+    ) -> Result<Vec<Change>> {
+        for op in ops {
+            field_info(op.field())?;
+        }
+
+        let tx = db.conn_mut().transaction()?;
+        for &book_id in book_ids {
+            let exists: Option<i32> = tx
+                .query_row("SELECT id FROM books WHERE id = ?1", [book_id], |row| {
+                    row.get(0)
+                })
+                .optional()?;
+            if exists.is_none() {
+                bail!("Book {} not found", book_id);
+            }
+        }
+
+        let mut changes: IndexMap<(i32, String), Change> = IndexMap::new();
+        for op in ops {
+            for (position, &book_id) in book_ids.iter().enumerate() {
+                let field = op.field();
+                let before = read_field(&tx, book_id, field)?;
+                let after = normalize(field, apply_op(op, position, &before)?);
+                let mut updates = vec![(field, before, after)];
+                if let Some((sort_field, sort)) = derived_change(field, &updates[0].2) {
+                    updates.push((sort_field, read_field(&tx, book_id, sort_field)?, sort));
+                }
+
+                for (field, before, after) in updates {
+                    if before == after {
+                        continue;
+                    }
+                    write_field(&tx, book_id, field, &after)?;
+                    changes
+                        .entry((book_id, field.to_string()))
+                        .and_modify(|c| c.after = after.clone())
+                        .or_insert(Change {
+                            book_id,
+                            field: field.to_string(),
+                            before,
+                            after,
+                        });
+                }
+            }
+        }
+        tx.commit()?;
+
+        let changes: Vec<Change> = changes
+            .into_values()
+            .filter(|c| c.before != c.after)
+            .collect();
+        self.finish(db, &changes);
+        if !changes.is_empty() {
+            self.journal.push_back(changes.clone());
+            while self.journal.len() > self.undo_limit {
+                self.journal.pop_front();
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Revert the last bulk edit, returning the changes reverted. Fields
+    /// edited since then are overwritten.
+    pub fn undo(&mut self, db: &mut Library) -> Result<Option<Vec<Change>>> {
+        let Some(changes) = self.journal.pop_back() else {
+            return Ok(None);
+        };
+        let reverted: Vec<Change> = changes
+            .into_iter()
+            .rev()
+            .map(|c| Change {
+                before: c.after,
+                after: c.before,
+                ..c
+            })
+            .collect();
+
+        let tx = db.conn_mut().transaction()?;
+        for change in &reverted {
+            write_field(&tx, change.book_id, &change.field, &change.after)?;
+        }
+        tx.commit()?;
+
+        self.finish(db, &reverted);
+        Ok(Some(reverted))
+    }
+
+    /// Moves the files of renamed books and tells the listeners, once per
+    /// field, about the books that changed.
+    fn finish(&self, db: &mut Library, changes: &[Change]) {
+        let mut renamed: Vec<i32> = Vec::new();
+        let mut fields: IndexMap<&str, Vec<i32>> = IndexMap::new();
+        for change in changes {
+            if matches!(change.field.as_str(), "title" | "authors")
+                && !renamed.contains(&change.book_id)
+            {
+                renamed.push(change.book_id);
+            }
+            let ids = fields.entry(change.field.as_str()).or_default();
+            if !ids.contains(&change.book_id) {
+                ids.push(change.book_id);
+            }
+        }
+
+        for book_id in renamed {
+            if let Err(e) = db.move_book_files(book_id) {
+                log::warn!("Failed to move the files of book {}: {}", book_id, e);
+            }
+        }
+        for (field, book_ids) in fields {
+            self.dispatcher.dispatch(Event::MetadataChanged {
+                field: field.to_string(),
+                book_ids,
+            });
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use anyhow::Result;
+use calibre_ebooks::metadata::{set_metadata, METADATA_WRITERS};
 use clap::Parser;
 
 #[derive(Debug, Parser)]
@@ -25,14 +26,29 @@ impl CmdEmbedMetadata {
                 .collect()
         };
 
-        for id in ids {
-            // In the Python code, this calls `db.embed_metadata`.
-            // Currently Library::embed_metadata isn't fully separate, but `backup_metadata_to_opf`
-            // is the closest equivalent for updating the OPF file.
-            // Real embedding into EPUB/MOBI etc would require `calibre_ebooks` support for writing those formats with metadata.
-            // For now, we update the OPF which is a critical part of "embedding" in terms of saving metadata to disk.
+        let total = ids.len();
+        for (i, id) in ids.into_iter().enumerate() {
+            let (Some(book), Some(mi)) = (db.get_book(id)?, db.get_metadata(id)?) else {
+                eprintln!("Id #{} is not present in database.", id);
+                continue;
+            };
+            let book_dir = db.path().join(&book.path);
+            for (name, fmt) in db.format_files(id)? {
+                let ext = fmt.to_lowercase();
+                if !METADATA_WRITERS.contains(&ext.as_str()) {
+                    continue;
+                }
+                let path = book_dir.join(format!("{}.{}", name, ext));
+                if !path.exists() {
+                    continue;
+                }
+                // One broken file should not stop the others
+                if let Err(e) = set_metadata(&path, &mi) {
+                    eprintln!("Failed to embed metadata into {:?}: {:#}", path, e);
+                }
+            }
             db.backup_metadata_to_opf(id)?;
-            println!("Processed book id: {}", id);
+            println!("[{}/{}] Embedded metadata into: {}", i + 1, total, mi.title);
         }
         Ok(())
     }
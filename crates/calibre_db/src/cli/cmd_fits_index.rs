@@ -1,9 +1,33 @@
-use anyhow::Result;
+use crate::fts::connection::IndexingProgress;
+use crate::fts::indexer::FtsIndexer;
+use crate::Library;
+use anyhow::{anyhow, bail, Context, Result};
 use clap::Parser;
+use std::io::{self, Write};
+use std::sync::mpsc;
+use std::time::Duration;
 
+/// Pause between two formats when indexing slowly.
+const SLOW_THROTTLE: Duration = Duration::from_millis(250);
+
+/// Control the full text search indexing process.
 #[derive(Debug, Parser)]
 pub struct RunArgs {
-    // Add args as needed
+    /// enable, disable, status or reindex
+    #[arg(required = true)]
+    pub action: String,
+
+    /// Books to reindex, as ids or id:FMT,FMT to reindex only some formats.
+    /// Without any the entire library is reindexed.
+    pub items: Vec<String>,
+
+    /// Wait till all books are indexed, showing indexing progress periodically
+    #[arg(long)]
+    pub wait_for_completion: bool,
+
+    /// fast to index without pausing, slow to leave the machine usable
+    #[arg(long, value_parser = ["fast", "slow"])]
+    pub indexing_speed: Option<String>,
 }
 
 pub struct CmdFitsIndex;
@@ -13,8 +37,120 @@ impl CmdFitsIndex {
         CmdFitsIndex
     }
 
-    pub fn run(&self, _args: &RunArgs) -> Result<()> {
-        // Stub
-        Err(anyhow::anyhow!("CmdFitsIndex::run is a stub"))
+    pub fn run(&self, db: &mut Library, args: &RunArgs) -> Result<()> {
+        match args.action.as_str() {
+            "status" => {
+                if !db.is_fts_enabled()? {
+                    bail!("FTS Indexing is disabled");
+                }
+                println!("FTS Indexing is enabled");
+                print_indexed(db.fts_indexer()?.progress()?);
+            }
+            "enable" => {
+                db.enable_fts(true)?;
+                println!("FTS indexing has been enabled");
+                print_indexed(db.fts_indexer()?.progress()?);
+            }
+            "disable" => {
+                db.enable_fts(false)?;
+                println!("FTS indexing has been disabled");
+                return Ok(());
+            }
+            "reindex" => {
+                let indexer = enabled_indexer(db)?;
+                if args.items.is_empty() {
+                    indexer.reindex(&[])?;
+                } else {
+                    for item in &args.items {
+                        let (book_id, formats) = parse_item(item)?;
+                        let formats: Vec<&str> = formats.iter().map(|f| f.as_str()).collect();
+                        indexer.reindex_book(book_id, &formats)?;
+                    }
+                }
+                print_indexed(indexer.progress()?);
+            }
+            action => bail!(
+                "{} is not a known action, use enable, disable, status or reindex",
+                action
+            ),
+        }
+
+        if args.wait_for_completion {
+            let indexer = enabled_indexer(db)?;
+            println!("Waiting for FTS indexing to complete, press Ctrl-C to abort...");
+            wait_for_completion(&indexer, args.indexing_speed.as_deref())?;
+            println!("All books indexed!");
+        }
+        Ok(())
+    }
+}
+
+fn enabled_indexer(db: &Library) -> Result<FtsIndexer> {
+    if !db.is_fts_enabled()? {
+        bail!("Full text indexing is not enabled on this library");
     }
+    Ok(db.fts_indexer()?)
+}
+
+/// `12` or `12:EPUB,PDF`
+fn parse_item(item: &str) -> Result<(i32, Vec<String>)> {
+    let (id, formats) = match item.split_once(':') {
+        Some((id, formats)) => (
+            id,
+            formats
+                .split(',')
+                .filter(|f| !f.trim().is_empty())
+                .map(|f| f.trim().to_uppercase())
+                .collect(),
+        ),
+        None => (item, Vec::new()),
+    };
+    let book_id = id
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid book id: {}", id))?;
+    Ok((book_id, formats))
+}
+
+fn print_indexed(progress: IndexingProgress) {
+    println!(
+        "{} of {} books files indexed",
+        progress.indexed,
+        progress.indexed + progress.pending
+    );
+}
+
+/// Index in the background, reporting progress until the queue is empty.
+fn wait_for_completion(indexer: &FtsIndexer, speed: Option<&str>) -> Result<()> {
+    indexer.set_throttle(if speed == Some("slow") {
+        SLOW_THROTTLE
+    } else {
+        Duration::ZERO
+    });
+    let (tx, rx) = mpsc::channel();
+    indexer.set_progress_callback(move |progress| {
+        let _ = tx.send(progress);
+    });
+    indexer.start();
+
+    let result = (|| {
+        let mut progress = indexer.progress()?;
+        while progress.pending > 0 {
+            print!(
+                "\r\x1b[K{} of {} book files indexed ...",
+                progress.indexed,
+                progress.indexed + progress.pending
+            );
+            io::stdout().flush()?;
+            progress = match rx.recv_timeout(Duration::from_secs(1)) {
+                Ok(progress) => progress,
+                Err(mpsc::RecvTimeoutError::Timeout) => indexer.progress()?,
+                Err(e) => return Err(anyhow!(e)),
+            };
+        }
+        println!();
+        Ok(())
+    })();
+    indexer.stop();
+    result
 }
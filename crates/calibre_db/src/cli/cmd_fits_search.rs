@@ -1,11 +1,64 @@
-use anyhow::Result;
+use crate::fts::connection::SearchOptions;
+use crate::Library;
+use anyhow::{anyhow, bail, Result};
 use clap::Parser;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 
+/// Words of context around the matches in snippets.
+const SNIPPET_SIZE: usize = 64;
+
+/// Do a full text search on the entire library or a subset of it.
 #[derive(Debug, Parser)]
 pub struct RunArgs {
     /// Search query
     #[arg(required = true)]
     pub query: String,
+
+    /// Include snippets of the text surrounding each match. Note that this
+    /// makes searching much slower.
+    #[arg(long)]
+    pub include_snippets: bool,
+
+    /// The marker used to indicate the start of a matched word inside a snippet
+    #[arg(long, default_value = "\x1b[31m")]
+    pub match_start_marker: String,
+
+    /// The marker used to indicate the end of a matched word inside a snippet
+    #[arg(long, default_value = "\x1b[m")]
+    pub match_end_marker: String,
+
+    /// Only match on exact words not related words. So correction will not
+    /// match correcting.
+    #[arg(long)]
+    pub do_not_match_on_related_words: bool,
+
+    /// Restrict the searched books, either using a search expression or ids.
+    /// For example: ids:1,2,3 to restrict by ids or search:tag:foo to
+    /// restrict to books having the tag foo.
+    #[arg(long)]
+    pub restrict_to: Option<String>,
+
+    /// The format to output the search results in, text or json
+    #[arg(long, default_value = "text", value_parser = ["text", "json"])]
+    pub output_format: String,
+
+    /// How much of the library must be indexed before searching is allowed,
+    /// as a percentage
+    #[arg(long, default_value_t = 90.0)]
+    pub indexing_threshold: f64,
+}
+
+/// A format of a book matching the search. Fields are in alphabetical
+/// order, as calibre sorts the keys of its JSON output.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct FtsMatch {
+    pub authors: Vec<String>,
+    pub book_id: i32,
+    pub format: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    pub title: String,
 }
 
 pub struct CmdFitsSearch;
@@ -15,8 +68,126 @@ impl CmdFitsSearch {
         CmdFitsSearch
     }
 
-    pub fn run(&self, _args: &RunArgs) -> Result<()> {
-        // Stub
-        Err(anyhow::anyhow!("CmdFitsSearch::run is a stub"))
+    pub fn run(&self, db: &Library, args: &RunArgs) -> Result<()> {
+        let matches = self.search(db, args)?;
+        if args.output_format == "json" {
+            println!("{}", serde_json::to_string_pretty(&matches)?);
+        } else {
+            print_matches(&matches);
+        }
+        Ok(())
+    }
+
+    /// The matching formats, best matches first.
+    pub fn search(&self, db: &Library, args: &RunArgs) -> Result<Vec<FtsMatch>> {
+        if !db.is_fts_enabled()? {
+            bail!(
+                "Full text searching is not enabled on this library. Use the calibredb \
+                 fts_index enable --wait-for-completion command to enable it"
+            );
+        }
+        let restrict_to_book_ids = match args.restrict_to.as_deref() {
+            None | Some("") => None,
+            Some(restriction) => Some(match restriction.split_once(':') {
+                Some(("ids", ids)) => ids
+                    .split(',')
+                    .map(|id| {
+                        id.trim()
+                            .parse()
+                            .map_err(|_| anyhow!("Invalid book id: {}", id))
+                    })
+                    .collect::<Result<Vec<i32>>>()?,
+                Some(("search", query)) => db.search(query)?,
+                _ => bail!("The --restrict-to option must start with either ids: or search:"),
+            }),
+        };
+
+        let indexer = db.fts_indexer()?;
+        let progress = indexer.progress()?;
+        let total = progress.indexed + progress.pending;
+        let threshold = args.indexing_threshold.clamp(0.0, 100.0) / 100.0;
+        if total > 0 && progress.pending as f64 / total as f64 > 1.0 - threshold {
+            bail!(
+                "{} files out of {} are not yet indexed, searching is disabled",
+                progress.pending,
+                total
+            );
+        }
+
+        let options = SearchOptions {
+            use_stemming: !args.do_not_match_on_related_words,
+            return_text: args.include_snippets,
+            highlight: Some((
+                args.match_start_marker.clone(),
+                args.match_end_marker.clone(),
+            )),
+            snippet_size: Some(SNIPPET_SIZE),
+            restrict_to_book_ids,
+        };
+        let results = indexer
+            .fts()
+            .search_with(&args.query, &options)
+            .map_err(|e| anyhow!("Invalid full text search query {:?}: {}", args.query, e))?;
+
+        let mut metadata: HashMap<i32, (String, Vec<String>)> = HashMap::new();
+        let mut matches = Vec::with_capacity(results.len());
+        for result in results {
+            let (title, authors) = match metadata.entry(result.book_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(match db.get_metadata(result.book_id)? {
+                    Some(mi) => (mi.title, mi.authors),
+                    None => (String::new(), Vec::new()),
+                }),
+            };
+            matches.push(FtsMatch {
+                authors: authors.clone(),
+                book_id: result.book_id,
+                format: result.format,
+                text: args.include_snippets.then_some(result.text),
+                title: title.clone(),
+            });
+        }
+        Ok(matches)
+    }
+}
+
+/// One entry per book, or per distinct snippet of a book, listing the
+/// formats it was found in.
+fn print_matches(matches: &[FtsMatch]) {
+    let width = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(80usize)
+        .max(5);
+    let separator = "─".repeat(width);
+
+    let mut groups: Vec<(&FtsMatch, String, Vec<&str>)> = Vec::new();
+    for m in matches {
+        let text = m
+            .text
+            .as_deref()
+            .map(|t| t.split_whitespace().collect::<Vec<_>>().join(" "))
+            .unwrap_or_default();
+        let same_group = |(prev, prev_text, _): &&mut (&FtsMatch, String, Vec<&str>)| {
+            prev.book_id == m.book_id && (m.text.is_none() || *prev_text == text)
+        };
+        let existing = if m.text.is_none() {
+            groups.iter_mut().find(|g| same_group(g))
+        } else {
+            groups.last_mut().filter(same_group)
+        };
+        match existing {
+            Some((_, _, formats)) => formats.push(&m.format),
+            None => groups.push((m, text, vec![&m.format])),
+        }
+    }
+
+    for (m, text, formats) in groups {
+        println!("{} by {}", m.title, m.authors.join(" & "));
+        println!("Book id: {} Formats: {}", m.book_id, formats.join(", "));
+        if m.text.is_some() {
+            println!("{}", text);
+        }
+        println!("{}", separator);
     }
 }
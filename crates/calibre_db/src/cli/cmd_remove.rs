@@ -9,7 +9,7 @@ pub struct RunArgs {
     #[arg(required = true, value_delimiter = ',', num_args = 1..)]
     pub ids: Vec<String>,
 
-    /// Do not using the Recycle Bin
+    /// Delete the books permanently instead of moving them to the trash
     #[arg(long)]
     pub permanent: bool,
 }
@@ -9,21 +9,39 @@ impl CmdSetMetadata {
     }
 
     pub fn run(&self, db: &mut Library, args: &[String]) -> Result<()> {
-        if args.len() < 3 {
-            return Err(anyhow!("Usage: set_metadata <book_id> <field> <value>"));
+        // Replace the cover with a generated one, after setting the field
+        let generate_cover = args.iter().any(|a| a == "--generate-cover");
+        let args: Vec<&String> = args.iter().filter(|a| *a != "--generate-cover").collect();
+        if args.len() < 3 && !(generate_cover && args.len() == 1) {
+            return Err(anyhow!(
+                "Usage: set_metadata <book_id> <field> <value> [--generate-cover]"
+            ));
         }
 
         let book_id = args[0]
             .parse::<i32>()
             .map_err(|_| anyhow!("Invalid book_id"))?;
-        let field = &args[1];
-        let value = args[2..].join(" ");
+        if args.len() >= 3 {
+            let field = args[1];
+            let value = args[2..]
+                .iter()
+                .map(|a| a.as_str())
+                .collect::<Vec<_>>()
+                .join(" ");
 
-        db.set_metadata(book_id, field, &value)?;
-        println!(
-            "Set metadata '{}' for book {} to '{}'",
-            field, book_id, value
-        );
+            db.set_metadata(book_id, field, &value)?;
+            println!(
+                "Set metadata '{}' for book {} to '{}'",
+                field, book_id, value
+            );
+        }
+        if generate_cover {
+            if db.get_book(book_id)?.is_none() {
+                return Err(anyhow!("No book with id: {}", book_id));
+            }
+            db.generate_cover(book_id)?;
+            println!("Generated a cover for book {}", book_id);
+        }
         Ok(())
     }
 }
@@ -0,0 +1,122 @@
+use crate::constants::TrashEntry;
+use crate::Library;
+use anyhow::{bail, Context, Result};
+use chrono::{Local, TimeZone};
+use clap::{Parser, Subcommand};
+
+/// Manage the books and formats removed to the trash of the library.
+#[derive(Debug, Parser)]
+pub struct RunArgs {
+    #[command(subcommand)]
+    pub action: TrashAction,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TrashAction {
+    /// List the removed books and formats
+    List,
+    /// Put removed books back, given as ids, or removed formats, given as
+    /// id:FMT,FMT
+    Restore {
+        #[arg(required = true)]
+        items: Vec<String>,
+    },
+    /// Permanently delete everything in the trash
+    Empty,
+}
+
+pub struct CmdTrash;
+
+impl CmdTrash {
+    pub fn new() -> Self {
+        CmdTrash
+    }
+
+    pub fn run(&self, db: &mut Library, args: &RunArgs) -> Result<()> {
+        match &args.action {
+            TrashAction::List => {
+                let (books, files) = db.list_trash()?;
+                if books.is_empty() && files.is_empty() {
+                    println!("The trash is empty");
+                    return Ok(());
+                }
+                if !books.is_empty() {
+                    println!("Removed books:");
+                    for entry in &books {
+                        print_entry(entry);
+                    }
+                }
+                if !files.is_empty() {
+                    println!("Removed formats:");
+                    for entry in &files {
+                        print_entry(entry);
+                    }
+                }
+                let days = db.trash_expiry()?.as_secs() / 86400;
+                println!("Entries are deleted permanently after {} days", days);
+            }
+            TrashAction::Restore { items } => {
+                for item in items {
+                    let (book_id, formats) = parse_item(item)?;
+                    if formats.is_empty() {
+                        let new_id = db.restore_book_from_trash(book_id)?;
+                        if new_id == book_id {
+                            println!("Restored book {}", book_id);
+                        } else {
+                            println!("Restored book {} as book {}", book_id, new_id);
+                        }
+                    } else {
+                        for fmt in &formats {
+                            db.restore_format_from_trash(book_id, fmt)?;
+                            println!("Restored the {} format of book {}", fmt, book_id);
+                        }
+                    }
+                }
+            }
+            TrashAction::Empty => {
+                let removed = db.empty_trash()?;
+                println!("{} entries deleted from the trash", removed);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn print_entry(entry: &TrashEntry) {
+    let removed = Local
+        .timestamp_opt(entry.mtime as i64, 0)
+        .single()
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default();
+    println!(
+        "  {:>6}  {}  {} by {} [{}]",
+        entry.book_id,
+        removed,
+        entry.title,
+        entry.author,
+        entry.formats.join(", ")
+    );
+}
+
+/// `12` or `12:EPUB,PDF`
+fn parse_item(item: &str) -> Result<(i32, Vec<String>)> {
+    let (id, formats) = match item.split_once(':') {
+        Some((id, formats)) => (
+            id,
+            formats
+                .split(',')
+                .filter(|f| !f.trim().is_empty())
+                .map(|f| f.trim().to_uppercase())
+                .collect(),
+        ),
+        None => (item, Vec::new()),
+    };
+    let book_id = id
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid book id: {}", id))?;
+    if item.contains(':') && formats.is_empty() {
+        bail!("No formats given for book {}", book_id);
+    }
+    Ok((book_id, formats))
+}
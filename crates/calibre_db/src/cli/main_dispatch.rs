@@ -5,11 +5,11 @@ use std::path::PathBuf;
 
 // Existing commands modules
 use super::{
+    cmd_add,
     cmd_add_custom_column,
     cmd_add_format,
     // Add others if needed after implementation
     cmd_backup_metadata,
-    // cmd_fits_index,
     cmd_catalog,
     cmd_check_library,
     cmd_clone,
@@ -30,6 +30,7 @@ use super::{
     cmd_set_metadata,
     cmd_show_metadata,
     cmd_switch,
+    cmd_trash,
 };
 
 pub struct DBCtx {
@@ -68,10 +69,13 @@ pub fn run_command(cmd: &str, args: &[String], ctx: &DBCtx) -> Result<()> {
             cmd_show_metadata::CmdShowMetadata::new().run(&db, args)
         }
         // Stub all others to avoid import/signature issues during porting
-        "add" => Err(anyhow!(
-            "Command '{}' not yet implemented in main_dispatch",
-            cmd
-        )),
+        "add" => {
+            let mut db = ctx.db()?;
+            let cmd_name = "add".to_string();
+            let clap_args = std::iter::once(&cmd_name).chain(args.iter());
+            let run_args = cmd_add::RunArgs::parse_from(clap_args);
+            cmd_add::CmdAdd::new().run(&mut db, &run_args)
+        }
         "add_custom_column" => {
             let mut db = ctx.db()?;
             cmd_add_custom_column::CmdAddCustomColumn::new().run(&mut db, args)
@@ -109,17 +113,19 @@ pub fn run_command(cmd: &str, args: &[String], ctx: &DBCtx) -> Result<()> {
             let run_args = cmd_embed_metadata::RunArgs::parse_from(clap_args);
             cmd_embed_metadata::CmdEmbedMetadata::new().run(&db, &run_args)
         }
-        "fits_index" => {
-            let cmd_name = "fits_index".to_string();
+        "fts_index" | "fits_index" => {
+            let mut db = ctx.db()?;
+            let cmd_name = "fts_index".to_string();
             let clap_args = std::iter::once(&cmd_name).chain(args.iter());
             let run_args = cmd_fits_index::RunArgs::parse_from(clap_args);
-            cmd_fits_index::CmdFitsIndex::new().run(&run_args)
+            cmd_fits_index::CmdFitsIndex::new().run(&mut db, &run_args)
         }
-        "fits_search" => {
-            let cmd_name = "fits_search".to_string();
+        "fts_search" | "fits_search" => {
+            let db = ctx.db()?;
+            let cmd_name = "fts_search".to_string();
             let clap_args = std::iter::once(&cmd_name).chain(args.iter());
             let run_args = cmd_fits_search::RunArgs::parse_from(clap_args);
-            cmd_fits_search::CmdFitsSearch::new().run(&run_args)
+            cmd_fits_search::CmdFitsSearch::new().run(&db, &run_args)
         }
         "clone" => {
             let db = ctx.db()?;
@@ -159,6 +165,13 @@ pub fn run_command(cmd: &str, args: &[String], ctx: &DBCtx) -> Result<()> {
             let mut db = ctx.db()?;
             cmd_remove_format::CmdRemoveFormat::new().run(&mut db, args)
         }
+        "trash" => {
+            let mut db = ctx.db()?;
+            let cmd_name = "trash".to_string();
+            let clap_args = std::iter::once(&cmd_name).chain(args.iter());
+            let run_args = cmd_trash::RunArgs::parse_from(clap_args);
+            cmd_trash::CmdTrash::new().run(&mut db, &run_args)
+        }
         "switch" => cmd_switch::CmdSwitch::new().run(args),
         "restore_database" => cmd_restore_database::CmdRestoreDatabase::new().run(args),
 
@@ -22,5 +22,6 @@ pub mod cmd_set_custom;
 pub mod cmd_set_metadata;
 pub mod cmd_show_metadata;
 pub mod cmd_switch;
+pub mod cmd_trash;
 pub mod main_dispatch;
 pub mod utils;
@@ -1,5 +1,6 @@
 use crate::cache::Cache;
 use anyhow::{Context, Result};
+use calibre_utils::img::save_cover_data_to;
 use std::fs;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
@@ -25,13 +26,15 @@ pub fn cover_path(cache: &Arc<Mutex<Cache>>, book_id: i32) -> Result<PathBuf> {
     Ok(path)
 }
 
-/// Sets the cover image for a book.
+/// Sets the cover image for a book. The image is stored as a JPEG without
+/// EXIF metadata, whatever format it comes in.
 ///
 /// # Arguments
 /// * `cache` - The database cache.
 /// * `book_id` - The ID of the book.
-/// * `data` - The raw image data.
+/// * `data` - The raw image data, JPEG, PNG, GIF or WebP.
 pub fn set_cover(cache: &Arc<Mutex<Cache>>, book_id: i32, data: &[u8]) -> Result<()> {
+    let data = save_cover_data_to(data).context("Invalid cover image")?;
     let path = cover_path(cache, book_id)?;
 
     // Ensure parent directory exists
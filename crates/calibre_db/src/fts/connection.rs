@@ -1,6 +1,92 @@
-use rusqlite::{Connection, Result};
+use rusqlite::{Connection, OptionalExtension, Result};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Schema of `full-text-search.db`, as calibre creates it. The FTS tables
+/// use the builtin unicode61 tokenizer in place of calibre's own.
+const FTS_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS fts_db.dirtied_formats ( id INTEGER PRIMARY KEY,
+    book INTEGER NOT NULL,
+    format TEXT NOT NULL COLLATE NOCASE,
+    in_progress INTEGER NOT NULL DEFAULT FALSE,
+    UNIQUE(book, format)
+);
+
+CREATE TABLE IF NOT EXISTS fts_db.books_text ( id INTEGER PRIMARY KEY,
+    book INTEGER NOT NULL,
+    timestamp REAL NOT NULL,
+    format TEXT NOT NULL COLLATE NOCASE,
+    format_hash TEXT NOT NULL COLLATE NOCASE,
+    format_size INTEGER NOT NULL DEFAULT 0,
+    searchable_text TEXT NOT NULL DEFAULT '',
+    text_size INTEGER NOT NULL DEFAULT 0,
+    text_hash TEXT NOT NULL COLLATE NOCASE DEFAULT '',
+    err_msg TEXT DEFAULT '',
+    UNIQUE(book, format)
+);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS fts_db.books_fts USING fts5(searchable_text,
+    content = 'books_text', content_rowid = 'id', tokenize = 'unicode61 remove_diacritics 2');
+CREATE VIRTUAL TABLE IF NOT EXISTS fts_db.books_fts_stemmed USING fts5(searchable_text,
+    content = 'books_text', content_rowid = 'id', tokenize = 'porter unicode61 remove_diacritics 2');
+
+CREATE TRIGGER IF NOT EXISTS fts_db.books_fts_insert_trg AFTER INSERT ON fts_db.books_text
+BEGIN
+    INSERT INTO books_fts(rowid, searchable_text) VALUES (NEW.id, NEW.searchable_text);
+    INSERT INTO books_fts_stemmed(rowid, searchable_text) VALUES (NEW.id, NEW.searchable_text);
+    DELETE FROM dirtied_formats WHERE book=NEW.book AND format=NEW.format;
+END;
+
+CREATE TRIGGER IF NOT EXISTS fts_db.books_fts_delete_trg AFTER DELETE ON fts_db.books_text
+BEGIN
+    INSERT INTO books_fts(books_fts, rowid, searchable_text) VALUES('delete', OLD.id, OLD.searchable_text);
+    INSERT INTO books_fts_stemmed(books_fts_stemmed, rowid, searchable_text) VALUES('delete', OLD.id, OLD.searchable_text);
+END;
+
+CREATE TRIGGER IF NOT EXISTS fts_db.books_fts_update_trg AFTER UPDATE ON fts_db.books_text
+BEGIN
+    INSERT INTO books_fts(books_fts, rowid, searchable_text) VALUES('delete', OLD.id, OLD.searchable_text);
+    INSERT INTO books_fts(rowid, searchable_text) VALUES (NEW.id, NEW.searchable_text);
+    INSERT INTO books_fts_stemmed(books_fts_stemmed, rowid, searchable_text) VALUES('delete', OLD.id, OLD.searchable_text);
+    INSERT INTO books_fts_stemmed(rowid, searchable_text) VALUES (NEW.id, NEW.searchable_text);
+    DELETE FROM dirtied_formats WHERE book=NEW.book AND format=NEW.format;
+END;
+
+PRAGMA fts_db.user_version=1;
+";
+
+/// How `FtsConnection::search_with` reports its matches.
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    /// Match inflected forms too ("run" finds "running").
+    pub use_stemming: bool,
+    /// Return the text of each match, `SearchResult::text` is empty otherwise.
+    pub return_text: bool,
+    /// Markers put around the matched words in the returned text.
+    pub highlight: Option<(String, String)>,
+    /// Return about this many words around the best match instead of the
+    /// whole text. Only used together with `highlight`.
+    pub snippet_size: Option<usize>,
+    /// Only search these books.
+    pub restrict_to_book_ids: Option<Vec<i32>>,
+}
+
+/// One indexed format matching a search, best matches first.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct SearchResult {
+    pub id: i32,
+    pub book_id: i32,
+    pub format: String,
+    pub text: String,
+}
+
+/// Formats indexed and waiting to be indexed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct IndexingProgress {
+    pub indexed: usize,
+    pub pending: usize,
+}
 
 pub struct FtsConnection {
     conn: Arc<Mutex<Connection>>,
@@ -16,6 +102,13 @@ impl FtsConnection {
         FtsConnection { conn, fts_db_path }
     }
 
+    /// The library connection the index is attached to.
+    pub(crate) fn conn(&self) -> Arc<Mutex<Connection>> {
+        self.conn.clone()
+    }
+
+    /// Attach `full-text-search.db`, creating its tables the first time. A
+    /// new index queues every format of the library.
     pub fn initialize(&self) -> Result<()> {
         let conn = self.conn.lock().unwrap();
         // Check if already attached?
@@ -32,70 +125,313 @@ impl FtsConnection {
                 "ATTACH DATABASE ? AS fts_db",
                 [self.fts_db_path.to_str().unwrap()],
             )?;
+            let version: i32 =
+                conn.query_row("PRAGMA fts_db.user_version", [], |row| row.get(0))?;
+            if version < 1 {
+                conn.execute_batch(FTS_SCHEMA)?;
+            }
+            // Jobs interrupted by a shutdown are done again
+            conn.execute(
+                "UPDATE fts_db.dirtied_formats SET in_progress=FALSE WHERE in_progress=TRUE",
+                [],
+            )?;
+            let queued: i32 = conn.query_row(
+                "SELECT (SELECT COUNT(*) FROM fts_db.dirtied_formats) + \
+                 (SELECT COUNT(*) FROM fts_db.books_text)",
+                [],
+                |row| row.get(0),
+            )?;
+            if queued == 0 {
+                dirty_existing(&conn)?;
+            }
+        }
+        Ok(())
+    }
 
-            // Ensure schema exists (Basic subset for verification)
-            // In a real port, we'd use SchemaUpgrade logic here.
-            // For now, we assume if it didn't exist, we create the table.
+    /// Queue every format in the library for indexing.
+    pub fn dirty_existing(&self) -> Result<()> {
+        dirty_existing(&self.conn.lock().unwrap())
+    }
+
+    pub fn dirty_book(&self, book_id: i32, formats: &[&str]) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        for format in formats {
             conn.execute(
-                "CREATE VIRTUAL TABLE IF NOT EXISTS fts_db.books_fts USING fts5(text, content='books_text', content_rowid='id')", 
-                []
+                "INSERT OR IGNORE INTO fts_db.dirtied_formats (book, format) VALUES (?, ?)",
+                (book_id, format.to_uppercase()),
             )?;
+        }
+        Ok(())
+    }
+
+    pub fn remove_dirty(&self, book_id: i32, format: &str) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "DELETE FROM fts_db.dirtied_formats WHERE book=? AND format=?",
+            (book_id, format.to_uppercase()),
+        )?;
+        Ok(())
+    }
+
+    pub fn clear_all_dirty(&self) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM fts_db.dirtied_formats", [])?;
+        Ok(())
+    }
+
+    pub fn number_dirtied(&self) -> Result<usize> {
+        self.conn.lock().unwrap().query_row(
+            "SELECT COUNT(*) FROM fts_db.dirtied_formats",
+            [],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn all_currently_dirty(&self) -> Result<Vec<(i32, String)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT book, format FROM fts_db.dirtied_formats")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    /// Remove a book from the index, or only one of its formats. Formats
+    /// still waiting to be indexed are dropped as well.
+    pub fn unindex(&self, book_id: i32, format: Option<&str>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        match format {
+            Some(format) => {
+                let format = format.to_uppercase();
+                conn.execute(
+                    "DELETE FROM fts_db.books_text WHERE book=? AND format=?",
+                    (book_id, &format),
+                )?;
+                conn.execute(
+                    "DELETE FROM fts_db.dirtied_formats WHERE book=? AND format=?",
+                    (book_id, &format),
+                )?;
+            }
+            None => {
+                conn.execute("DELETE FROM fts_db.books_text WHERE book=?", [book_id])?;
+                conn.execute("DELETE FROM fts_db.dirtied_formats WHERE book=?", [book_id])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Next format waiting to be indexed, marked as in progress.
+    pub fn get_next_job(&self) -> Result<Option<(i32, String)>> {
+        let conn = self.conn.lock().unwrap();
+        let job: Option<(i32, String)> = conn
+            .query_row(
+                "SELECT book, format FROM fts_db.dirtied_formats WHERE in_progress=FALSE ORDER BY id",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        if let Some((book_id, format)) = &job {
             conn.execute(
-                "CREATE TABLE IF NOT EXISTS fts_db.books_text (id INTEGER PRIMARY KEY, book INTEGER, format TEXT, searchable_text TEXT)", 
-                []
+                "UPDATE fts_db.dirtied_formats SET in_progress=TRUE WHERE book=? AND format=?",
+                (book_id, format),
+            )?;
+        }
+        Ok(job)
+    }
+
+    /// Whether this exact file, going by its size and hash, is indexed.
+    pub fn is_indexed(&self, book_id: i32, format: &str, size: u64, hash: &str) -> Result<bool> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT id FROM fts_db.books_text WHERE book=? AND format=? AND format_size=? AND format_hash=?",
+                (book_id, format.to_uppercase(), size as i64, hash),
+                |_| Ok(()),
+            )
+            .optional()
+            .map(|found| found.is_some())
+    }
+
+    /// Store the text extracted from a format, or why there was none. Text
+    /// identical to what is indexed already leaves the index alone.
+    pub fn commit_result(
+        &self,
+        book_id: i32,
+        format: &str,
+        format_size: u64,
+        format_hash: &str,
+        text: &str,
+        err_msg: &str,
+    ) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let format = format.to_uppercase();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        let text_hash = if text.is_empty() {
+            String::new()
+        } else {
+            super::text::hash_bytes(text.as_bytes())
+        };
+        let unchanged = !text.is_empty()
+            && conn
+                .query_row(
+                    "SELECT id FROM fts_db.books_text WHERE book=? AND format=? AND text_hash=?",
+                    (book_id, &format, &text_hash),
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some();
+
+        if !err_msg.is_empty() {
+            conn.execute(
+                "INSERT OR REPLACE INTO fts_db.books_text \
+                 (book, timestamp, format, format_size, format_hash, err_msg) VALUES (?, ?, ?, ?, ?, ?)",
+                (book_id, timestamp, &format, format_size as i64, format_hash, err_msg),
+            )?;
+        } else if unchanged {
+            // Keep the text, but remember the new file so it is skipped next time
+            conn.execute(
+                "UPDATE fts_db.books_text SET timestamp=?, format_size=?, format_hash=? WHERE book=? AND format=?",
+                (timestamp, format_size as i64, format_hash, book_id, &format),
+            )?;
+        } else if !text.is_empty() {
+            conn.execute(
+                "INSERT OR REPLACE INTO fts_db.books_text \
+                 (book, timestamp, format, format_size, format_hash, searchable_text, text_size, text_hash) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                (
+                    book_id,
+                    timestamp,
+                    &format,
+                    format_size as i64,
+                    format_hash,
+                    text,
+                    text.chars().count() as i64,
+                    &text_hash,
+                ),
+            )?;
+        } else {
+            conn.execute(
+                "DELETE FROM fts_db.dirtied_formats WHERE book=? AND format=?",
+                (book_id, &format),
             )?;
         }
         Ok(())
     }
 
-    pub fn search(&self, query_text: &str) -> Result<Vec<(i32, i32, String)>> {
+    /// Formats indexed and still to be indexed, among those in the library.
+    pub fn indexing_progress(&self) -> Result<IndexingProgress> {
         let conn = self.conn.lock().unwrap();
+        let pending: usize =
+            conn.query_row("SELECT COUNT(*) FROM fts_db.dirtied_formats", [], |row| {
+                row.get(0)
+            })?;
+        let indexed: usize =
+            conn.query_row("SELECT COUNT(*) FROM fts_db.books_text", [], |row| {
+                row.get(0)
+            })?;
+        Ok(IndexingProgress { indexed, pending })
+    }
 
-        // Simple FTS5 query
-        // Matches Python logic: SELECT ... FROM books_text JOIN books_fts ...
-        let sql = r#"
-            SELECT books_text.id, books_text.book, books_text.format 
-            FROM fts_db.books_text 
-            JOIN fts_db.books_fts ON fts_db.books_text.id = fts_db.books_fts.rowid
-            WHERE books_fts MATCH ? 
-            ORDER BY books_fts.rank
-        "#;
-
-        let mut stmt = conn.prepare(sql)?;
-        let rows = stmt.query_map([query_text], |row| {
-            let id: i32 = row.get(0)?;
-            let book_id: i32 = row.get(1)?;
-            let format: String = row.get(2)?;
-            Ok((id, book_id, format))
-        })?;
+    pub fn search(&self, query_text: &str) -> Result<Vec<(i32, i32, String)>> {
+        let results = self.search_with(query_text, &SearchOptions::default())?;
+        Ok(results
+            .into_iter()
+            .map(|r| (r.id, r.book_id, r.format))
+            .collect())
+    }
 
-        let mut results = Vec::new();
-        for row in rows {
-            results.push(row?);
+    /// Run an FTS5 query, best matches first.
+    pub fn search_with(
+        &self,
+        query_text: &str,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        if options
+            .restrict_to_book_ids
+            .as_ref()
+            .is_some_and(|ids| ids.is_empty())
+        {
+            return Ok(Vec::new());
         }
-        Ok(results)
+        let fts_table = if options.use_stemming {
+            "books_fts_stemmed"
+        } else {
+            "books_fts"
+        };
+
+        let mut params: Vec<String> = Vec::new();
+        let text = if !options.return_text {
+            "''".to_string()
+        } else if let Some((start, end)) = &options.highlight {
+            params.push(start.clone());
+            params.push(end.clone());
+            match options.snippet_size {
+                Some(size) => format!(
+                    "snippet({}, 0, ?, ?, '…', {})",
+                    fts_table,
+                    size.clamp(1, 64)
+                ),
+                None => format!("highlight({}, 0, ?, ?)", fts_table),
+            }
+        } else {
+            "books_text.searchable_text".to_string()
+        };
+        let restriction = match &options.restrict_to_book_ids {
+            Some(ids) => {
+                let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+                format!("books_text.book IN ({}) AND ", ids.join(","))
+            }
+            None => String::new(),
+        };
+        params.push(query_text.to_string());
+
+        let sql = format!(
+            "SELECT books_text.id, books_text.book, books_text.format, {text}
+             FROM fts_db.books_text
+             JOIN fts_db.{fts_table} ON fts_db.books_text.id = fts_db.{fts_table}.rowid
+             WHERE {restriction}{fts_table} MATCH ?
+             ORDER BY {fts_table}.rank"
+        );
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(params.iter()), |row| {
+            Ok(SearchResult {
+                id: row.get(0)?,
+                book_id: row.get(1)?,
+                format: row.get(2)?,
+                text: row.get(3)?,
+            })
+        })?;
+        rows.collect()
     }
 
     // Helper for testing to add content
     pub fn add_document(&self, book_id: i32, format: &str, text: &str) -> Result<()> {
         let conn = self.conn.lock().unwrap();
+        // The triggers on books_text keep the FTS tables in step
         conn.execute(
-            "INSERT INTO fts_db.books_text (book, format, searchable_text) VALUES (?, ?, ?)",
-            (book_id, format, text),
+            "INSERT OR REPLACE INTO fts_db.books_text (book, timestamp, format, format_hash, searchable_text, text_size) \
+             VALUES (?, 0, ?, '', ?, ?)",
+            (book_id, format.to_uppercase(), text, text.chars().count() as i64),
         )?;
-        // Assuming FTS5 content='books_text', we typically need to rebuild or insert into FTS index
-        // depending on triggers. If external content table, we might need triggers or manual insert.
-        // For simple FTS5 with external content, inserts into the content table are NOT automatically indexed
-        // unless triggers are set up.
-        // Let's add manually to FTS table for this sprint's basic verification if triggers aren't there.
-        // Actually, simpler to just use normal FTS table for test if we don't assume existing triggers.
-        // But let's stick to the structure `books_fts` USING fts5(content='books_text').
-        // We need to insert into the FTS index to make it searchable.
-        let last_id = conn.last_insert_rowid();
+        Ok(())
+    }
+}
+
+fn dirty_existing(conn: &Connection) -> Result<()> {
+    let has_data: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM main.sqlite_master WHERE type='table' AND name='data'",
+        [],
+        |row| row.get(0),
+    )?;
+    if has_data {
         conn.execute(
-            "INSERT INTO fts_db.books_fts(rowid, text) VALUES (?, ?)",
-            (last_id, text),
+            "INSERT OR IGNORE INTO fts_db.dirtied_formats(book, format) SELECT book, format FROM main.data",
+            [],
         )?;
-        Ok(())
     }
+    Ok(())
 }
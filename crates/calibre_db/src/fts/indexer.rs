@@ -0,0 +1,314 @@
+use super::connection::{FtsConnection, IndexingProgress};
+use super::text::{extract_text, hash_file, is_fmt_extractable};
+use crate::listeners::{Event, EventListener};
+use anyhow::{Context, Result};
+use rusqlite::OptionalExtension;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How long an idle worker waits before looking for new work by itself.
+const IDLE_POLL: Duration = Duration::from_secs(5);
+
+type ProgressCallback = Box<dyn Fn(IndexingProgress) + Send>;
+
+/// What happened to one queued format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndexOutcome {
+    Indexed,
+    /// The file is the one already indexed.
+    Unchanged,
+    /// The format or its file is gone, or holds no text.
+    Skipped,
+    Failed(String),
+}
+
+struct WorkerState {
+    running: AtomicBool,
+    throttle: Mutex<Duration>,
+    wakeup: (Mutex<bool>, Condvar),
+    progress_callback: Mutex<Option<ProgressCallback>>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// Keeps the full text index of a library up to date: it works through
+/// the queue of dirtied formats, extracting their text with the input
+/// plugins, either on demand or from a background thread.
+///
+/// Clones share the same queue and worker, so one can be registered as an
+/// event listener while another drives the indexing.
+#[derive(Clone)]
+pub struct FtsIndexer {
+    fts: Arc<FtsConnection>,
+    library_path: PathBuf,
+    state: Arc<WorkerState>,
+}
+
+impl FtsIndexer {
+    pub fn new(fts: Arc<FtsConnection>, library_path: &Path) -> Self {
+        FtsIndexer {
+            fts,
+            library_path: library_path.to_path_buf(),
+            state: Arc::new(WorkerState {
+                running: AtomicBool::new(false),
+                throttle: Mutex::new(Duration::ZERO),
+                wakeup: (Mutex::new(false), Condvar::new()),
+                progress_callback: Mutex::new(None),
+                handle: Mutex::new(None),
+            }),
+        }
+    }
+
+    pub fn fts(&self) -> &FtsConnection {
+        &self.fts
+    }
+
+    /// Pause between two formats, so that indexing a large library leaves
+    /// the machine usable.
+    pub fn set_throttle(&self, delay: Duration) {
+        *self.state.throttle.lock().unwrap() = delay;
+    }
+
+    /// Called after every format the background worker handles.
+    pub fn set_progress_callback(&self, callback: impl Fn(IndexingProgress) + Send + 'static) {
+        *self.state.progress_callback.lock().unwrap() = Some(Box::new(callback));
+    }
+
+    pub fn progress(&self) -> Result<IndexingProgress> {
+        Ok(self.fts.indexing_progress()?)
+    }
+
+    /// Queue formats of a book, all of them when `formats` is empty.
+    pub fn dirty_book(&self, book_id: i32, formats: &[&str]) -> Result<()> {
+        if formats.is_empty() {
+            let formats = self.book_formats(book_id)?;
+            let formats: Vec<&str> = formats.iter().map(|f| f.as_str()).collect();
+            self.fts.dirty_book(book_id, &formats)?;
+        } else {
+            self.fts.dirty_book(book_id, formats)?;
+        }
+        self.wake();
+        Ok(())
+    }
+
+    /// Index books again even if their files did not change, the whole
+    /// library when `book_ids` is empty.
+    pub fn reindex(&self, book_ids: &[i32]) -> Result<()> {
+        if book_ids.is_empty() {
+            // Forget the hashes first, updating books_text takes formats
+            // off the queue
+            let conn = self.fts.conn();
+            conn.lock()
+                .unwrap()
+                .execute("UPDATE fts_db.books_text SET format_hash=''", [])?;
+            self.fts.dirty_existing()?;
+            self.wake();
+        } else {
+            for book_id in book_ids {
+                self.reindex_book(*book_id, &[])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Index formats of a book again, all of them when `formats` is empty.
+    pub fn reindex_book(&self, book_id: i32, formats: &[&str]) -> Result<()> {
+        {
+            let conn = self.fts.conn();
+            let conn = conn.lock().unwrap();
+            if formats.is_empty() {
+                conn.execute(
+                    "UPDATE fts_db.books_text SET format_hash='' WHERE book=?1",
+                    [book_id],
+                )?;
+            } else {
+                for format in formats {
+                    conn.execute(
+                        "UPDATE fts_db.books_text SET format_hash='' WHERE book=?1 AND format=?2",
+                        (book_id, format.to_uppercase()),
+                    )?;
+                }
+            }
+        }
+        self.dirty_book(book_id, formats)
+    }
+
+    /// Index the next queued format. `None` when the queue is empty.
+    pub fn index_next(&self) -> Result<Option<(i32, String, IndexOutcome)>> {
+        let Some((book_id, format)) = self.fts.get_next_job()? else {
+            return Ok(None);
+        };
+        let outcome = match self.index_format(book_id, &format) {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                // Leave it for a later run rather than retrying forever now
+                self.fts.remove_dirty(book_id, &format)?;
+                return Err(e);
+            }
+        };
+        Ok(Some((book_id, format, outcome)))
+    }
+
+    /// Work through the whole queue in this thread. Returns the number of
+    /// formats handled.
+    pub fn index_all(&self) -> Result<usize> {
+        let mut count = 0;
+        while self.index_next()?.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn index_format(&self, book_id: i32, format: &str) -> Result<IndexOutcome> {
+        let path = match self.format_path(book_id, format)? {
+            Some(path) if path.exists() => path,
+            _ => {
+                self.fts.remove_dirty(book_id, format)?;
+                return Ok(IndexOutcome::Skipped);
+            }
+        };
+        if !is_fmt_extractable(format) {
+            self.fts.remove_dirty(book_id, format)?;
+            return Ok(IndexOutcome::Skipped);
+        }
+        let (size, hash) =
+            hash_file(&path).with_context(|| format!("Failed to read {:?}", path))?;
+        if self.fts.is_indexed(book_id, format, size, &hash)? {
+            self.fts.remove_dirty(book_id, format)?;
+            return Ok(IndexOutcome::Unchanged);
+        }
+
+        let outcome = match extract_text(&path) {
+            Ok(text) if text.trim().is_empty() => {
+                self.fts
+                    .commit_result(book_id, format, size, &hash, "", "")?;
+                IndexOutcome::Skipped
+            }
+            Ok(text) => {
+                self.fts
+                    .commit_result(book_id, format, size, &hash, &text, "")?;
+                IndexOutcome::Indexed
+            }
+            Err(e) => {
+                let msg = format!("{:#}", e);
+                log::warn!("Failed to extract text from {:?}: {}", path, msg);
+                self.fts
+                    .commit_result(book_id, format, size, &hash, "", &msg)?;
+                IndexOutcome::Failed(msg)
+            }
+        };
+        Ok(outcome)
+    }
+
+    fn format_path(&self, book_id: i32, format: &str) -> Result<Option<PathBuf>> {
+        let conn = self.fts.conn();
+        let conn = conn.lock().unwrap();
+        let found: Option<(String, String)> = conn
+            .query_row(
+                "SELECT books.path, data.name FROM data JOIN books ON books.id = data.book
+                 WHERE data.book = ?1 AND data.format = ?2 COLLATE NOCASE",
+                (book_id, format),
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        Ok(found.map(|(dir, name)| {
+            self.library_path
+                .join(dir)
+                .join(format!("{}.{}", name, format.to_lowercase()))
+        }))
+    }
+
+    fn book_formats(&self, book_id: i32) -> Result<Vec<String>> {
+        let conn = self.fts.conn();
+        let conn = conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT format FROM data WHERE book = ?1")?;
+        let formats = stmt
+            .query_map([book_id], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(formats)
+    }
+
+    /// Start indexing in a background thread. Does nothing if it is
+    /// running already.
+    pub fn start(&self) {
+        if self.state.running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let worker = self.clone();
+        let handle = std::thread::spawn(move || worker.run());
+        *self.state.handle.lock().unwrap() = Some(handle);
+    }
+
+    /// Stop the background thread once it finished the current format.
+    pub fn stop(&self) {
+        self.state.running.store(false, Ordering::SeqCst);
+        self.wake();
+        if let Some(handle) = self.state.handle.lock().unwrap().take() {
+            let _ = handle.join();
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.running.load(Ordering::SeqCst)
+    }
+
+    fn wake(&self) {
+        let (lock, cvar) = &self.state.wakeup;
+        *lock.lock().unwrap() = true;
+        cvar.notify_all();
+    }
+
+    fn run(&self) {
+        while self.is_running() {
+            match self.index_next() {
+                Ok(Some(_)) => {
+                    if let Ok(progress) = self.progress() {
+                        if let Some(callback) = &*self.state.progress_callback.lock().unwrap() {
+                            callback(progress);
+                        }
+                    }
+                    let throttle = *self.state.throttle.lock().unwrap();
+                    if !throttle.is_zero() {
+                        self.sleep(throttle);
+                    }
+                }
+                Ok(None) => self.sleep(IDLE_POLL),
+                Err(e) => {
+                    log::error!("Full text indexing failed: {:#}", e);
+                    self.sleep(IDLE_POLL);
+                }
+            }
+        }
+    }
+
+    /// Wait for `timeout` or until woken by new work or `stop`.
+    fn sleep(&self, timeout: Duration) {
+        let (lock, cvar) = &self.state.wakeup;
+        let mut woken = lock.lock().unwrap();
+        if !*woken {
+            woken = cvar.wait_timeout(woken, timeout).unwrap().0;
+        }
+        *woken = false;
+    }
+}
+
+impl EventListener for FtsIndexer {
+    fn on_event(&self, event: &Event) {
+        let result = match event {
+            Event::FormatAdded { book_id, format } => self.dirty_book(*book_id, &[format]),
+            Event::FormatRemoved { book_id, format } => {
+                self.fts.unindex(*book_id, Some(format)).map_err(Into::into)
+            }
+            Event::BookRemoved { book_ids } => book_ids
+                .iter()
+                .try_for_each(|id| self.fts.unindex(*id, None))
+                .map_err(Into::into),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            log::error!("Failed to update the full text index: {:#}", e);
+        }
+    }
+}
@@ -1 +1,3 @@
 pub mod connection;
+pub mod indexer;
+pub mod text;
@@ -0,0 +1,258 @@
+//! Plain text of a book format, as it goes into the full text index.
+
+use anyhow::{bail, Context, Result};
+use calibre_ebooks::conversion::registry::global_registry;
+use sha1::{Digest, Sha1};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use unicode_normalization::UnicodeNormalization;
+
+/// Formats that are collections of images, nothing to index in them.
+const IMAGE_FORMATS: [&str; 4] = ["cbz", "cbr", "cb7", "cbc"];
+
+/// Elements whose content is not text of the book.
+const SKIPPED_TAGS: [&str; 10] = [
+    "style", "title", "script", "head", "img", "svg", "math", "rt", "rp", "rtc",
+];
+
+/// Elements that start a new paragraph of text.
+const BLOCK_TAGS: [&str; 30] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "br",
+    "center",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+];
+
+/// Whether text can be extracted from a format at all.
+pub fn is_fmt_extractable(format: &str) -> bool {
+    let format = format.to_lowercase();
+    !IMAGE_FORMATS.contains(&format.as_str())
+        && global_registry().input_for_extension(&format).is_some()
+}
+
+/// Convert the book through its input plugin and join the text of the
+/// spine documents, in reading order.
+pub fn extract_text(path: &Path) -> Result<String> {
+    let format = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+    if IMAGE_FORMATS.contains(&format.as_str()) {
+        return Ok(String::new());
+    }
+    let Some(plugin) = global_registry().input_for_extension(&format) else {
+        bail!("No input plugin for the {} format", format.to_uppercase());
+    };
+
+    let tdir = tempfile::tempdir()?;
+    let book = plugin
+        .convert(path, tdir.path())
+        .with_context(|| format!("Failed to convert {:?}", path))?;
+    let mut texts = Vec::new();
+    for item in book.spine.iter() {
+        let Some(manifest_item) = book.manifest.get_by_id(&item.idref) else {
+            continue;
+        };
+        let Ok(raw) = book.container.read(&manifest_item.href) else {
+            log::warn!("Missing spine item {} in {:?}", manifest_item.href, path);
+            continue;
+        };
+        let text = html_to_text(&String::from_utf8_lossy(&raw));
+        if !text.is_empty() {
+            texts.push(text);
+        }
+    }
+    Ok(texts
+        .join("\n\n\n")
+        .nfc()
+        .filter(|c| *c != '\u{ad}')
+        .collect())
+}
+
+/// Text of the body of an (X)HTML document, with blank lines between
+/// block elements and markup, scripts and styles removed.
+pub fn html_to_text(html: &str) -> String {
+    let body_start = find_tag(html, "body").unwrap_or(0);
+    let html = &html[body_start..];
+    let mut out = String::new();
+    let mut skip_depth: Vec<String> = Vec::new();
+    let mut rest = html;
+
+    while let Some(lt) = rest.find('<') {
+        if skip_depth.is_empty() {
+            out.push_str(&decode_entities(&rest[..lt]));
+        }
+        rest = &rest[lt..];
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map_or("", |end| &rest[end + 3..]);
+            continue;
+        }
+        let Some(gt) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[1..gt];
+        rest = &rest[gt + 1..];
+
+        let closing = tag.starts_with('/');
+        let self_closing = tag.ends_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| !c.is_whitespace() && *c != '/')
+            .collect::<String>()
+            .to_lowercase();
+        // Drop any namespace prefix, as in <html:p>
+        let name = name.rsplit(':').next().unwrap_or("").to_string();
+        if name.is_empty() || name.starts_with('!') || name.starts_with('?') {
+            continue;
+        }
+
+        if SKIPPED_TAGS.contains(&name.as_str()) {
+            if closing {
+                if skip_depth.last() == Some(&name) {
+                    skip_depth.pop();
+                }
+            } else if !self_closing && name != "img" {
+                skip_depth.push(name);
+            }
+            continue;
+        }
+        if name == "body" && closing {
+            break;
+        }
+        if skip_depth.is_empty() && BLOCK_TAGS.contains(&name.as_str()) {
+            out.push_str("\n\n");
+        }
+    }
+    if skip_depth.is_empty() && !rest.contains('<') {
+        out.push_str(&decode_entities(rest));
+    }
+    collapse_blank_lines(out.trim())
+}
+
+fn find_tag(html: &str, name: &str) -> Option<usize> {
+    let lower = html.to_lowercase();
+    let pattern = format!("<{}", name);
+    let mut from = 0;
+    while let Some(pos) = lower[from..].find(&pattern) {
+        let start = from + pos;
+        let next = lower[start + pattern.len()..].chars().next();
+        if matches!(next, Some(c) if c == '>' || c == '/' || c.is_whitespace()) {
+            return Some(start);
+        }
+        from = start + pattern.len();
+    }
+    None
+}
+
+fn collapse_blank_lines(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut newlines = 0;
+    for c in text.chars() {
+        if c == '\n' {
+            newlines += 1;
+            if newlines > 2 {
+                continue;
+            }
+        } else if !(c.is_whitespace() && newlines > 0) {
+            newlines = 0;
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| {
+                let entity = &rest[1..end + 1];
+                let c = match entity {
+                    "amp" => Some('&'),
+                    "lt" => Some('<'),
+                    "gt" => Some('>'),
+                    "quot" => Some('"'),
+                    "apos" => Some('\''),
+                    "nbsp" => Some('\u{a0}'),
+                    _ => entity
+                        .strip_prefix("#x")
+                        .or_else(|| entity.strip_prefix("#X"))
+                        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                        .or_else(|| entity.strip_prefix('#').and_then(|d| d.parse().ok()))
+                        .and_then(char::from_u32),
+                };
+                c.map(|c| (c, end + 2))
+            });
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// SHA-1 of a file and its size, which tell whether it changed since it
+/// was indexed.
+pub fn hash_file(path: &Path) -> Result<(u64, String)> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha1::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut size = 0u64;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        size += n as u64;
+        hasher.update(&buf[..n]);
+    }
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
+
+pub fn hash_bytes(data: &[u8]) -> String {
+    format!("{:x}", Sha1::digest(data))
+}
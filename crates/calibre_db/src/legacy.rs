@@ -1,4 +1,6 @@
-use anyhow::Result;
+use crate::schema_upgrades::{SchemaError, SchemaUpgrade, LATEST_VERSION};
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OpenFlags};
 use std::path::Path;
 
 pub struct LegacyDB;
@@ -8,23 +10,39 @@ impl LegacyDB {
         LegacyDB
     }
 
+    /// Whether the database at `db_path` can be used as it is. False means
+    /// it was written by an older calibre and needs `migrate` first. A
+    /// database from a newer calibre is an error, it cannot be opened at
+    /// all.
     pub fn check_compatibility(&self, db_path: &Path) -> Result<bool> {
-        // Placeholder: Checks if the database is an old legacy format that needs migration.
-        // For now, assume it's NOT (false) or just return Ok(true) if we assume modern.
-        // Let's assume we don't support legacy DBs, so return false if it looks legacy?
-        // Actually, let's just implement a stub check.
         if !db_path.exists() {
             return Ok(true); // New DB is fine
         }
 
-        // Real logic would check sqlite file header or specific table presence.
-        // Returning true implies "Compatible w/ current code" (i.e., not legacy needing migration)
-        Ok(true)
+        let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to open {:?}", db_path))?;
+        match SchemaUpgrade::detect_version(&conn)? {
+            Some(version) if version > LATEST_VERSION => Err(SchemaError::TooNew {
+                found: version,
+                supported: LATEST_VERSION,
+            }
+            .into()),
+            Some(version) => Ok(version == LATEST_VERSION),
+            // Not a calibre library, there is nothing to migrate
+            None => Ok(true),
+        }
     }
 
-    pub fn migrate(&self, _db_path: &Path) -> Result<()> {
-        Err(anyhow::anyhow!(
-            "Legacy database migration is not supported in this version."
-        ))
+    /// Upgrade the schema of an existing library database to the current
+    /// version, keeping a copy of the old file next to it.
+    pub fn migrate(&self, db_path: &Path) -> Result<()> {
+        if !db_path.exists() {
+            anyhow::bail!("No database to migrate at {:?}", db_path);
+        }
+        let mut conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+            .with_context(|| format!("Failed to open {:?}", db_path))?;
+        let library_path = db_path.parent().unwrap_or_else(|| Path::new("."));
+        SchemaUpgrade::upgrade_to_latest(&mut conn, library_path)?;
+        Ok(())
     }
 }
@@ -3,6 +3,7 @@ pub mod annotations;
 pub mod backend;
 pub mod backup;
 pub mod book;
+pub mod bulk_edit;
 pub mod cache;
 pub mod categories;
 pub mod check_library;
@@ -23,6 +24,7 @@ pub mod restore;
 pub mod schema_upgrades;
 pub mod search;
 pub mod tables;
+pub mod trash;
 pub mod utils;
 pub mod view;
 pub mod write;
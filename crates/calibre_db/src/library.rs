@@ -1,10 +1,38 @@
+use crate::backend::Backend;
 use crate::book::Book;
+use crate::constants::{
+    TrashEntry, COVER_FILE_NAME, DEFAULT_TRASH_EXPIRY_TIME_SECONDS, METADATA_FILE_NAME,
+};
+use crate::fts::connection::FtsConnection;
+use crate::fts::indexer::FtsIndexer;
+use crate::listeners::{Event, EventDispatcher};
+use crate::schema_upgrades::{SchemaError, SchemaUpgrade};
+use crate::search::Search;
+use crate::trash::{self, Trash};
+use calibre_ebooks::covers::{self, CoverPrefs};
 use calibre_ebooks::metadata::MetaInformation;
+use calibre_ebooks::opf::parse_opf;
+use calibre_utils::img::save_cover_data_to;
+use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
+use indexmap::IndexMap;
 use rusqlite::{Connection, OpenFlags, OptionalExtension, Result};
+use std::collections::HashSet;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 
+/// Preference holding whether full text indexing is on, as in calibre.
+const FTS_ENABLED_PREF: &str = "fts_enabled";
+
+/// Preference holding, in seconds, how long removed books and formats are
+/// kept in the trash, as in calibre.
+const TRASH_EXPIRY_PREF: &str = "expire_old_trash_after";
+
+/// The trash is never emptied faster than this, as in calibre.
+const MIN_TRASH_EXPIRY_SECONDS: u64 = 86400;
+
 #[derive(Error, Debug)]
 pub enum LibraryError {
     #[error("Database connection error: {0}")]
@@ -15,6 +43,14 @@ pub enum LibraryError {
     Io(#[from] std::io::Error),
     #[error("Transaction error: {0}")]
     Transaction(String),
+    #[error("Invalid search: {0}")]
+    Search(String),
+    #[error(transparent)]
+    Schema(#[from] SchemaError),
+    #[error("Invalid cover: {0}")]
+    Cover(String),
+    #[error("Trash error: {0}")]
+    Trash(String),
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -27,6 +63,9 @@ pub struct Category {
 pub struct Library {
     conn: Connection,
     path: PathBuf,
+    events: EventDispatcher,
+    /// Whether the full text indexer listens to `events` already
+    fts_listening: bool,
 }
 
 impl Library {
@@ -36,15 +75,29 @@ impl Library {
             return Err(LibraryError::InvalidPath);
         }
 
-        let conn = Connection::open_with_flags(
+        let mut conn = Connection::open_with_flags(
             &db_path,
             OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_URI,
         )?;
 
-        // Register custom functions expected by Calibre triggers
-        Self::register_functions(&conn)?;
+        // Registers the custom functions expected by Calibre triggers too
+        SchemaUpgrade::upgrade_to_latest(&mut conn, &path)?;
 
-        Ok(Library { conn, path })
+        let mut library = Library {
+            conn,
+            path,
+            events: EventDispatcher::new(),
+            fts_listening: false,
+        };
+        if let Err(e) = library.expire_old_trash() {
+            log::warn!("Failed to expire old trash entries: {}", e);
+        }
+        if library.is_fts_enabled().unwrap_or(false) {
+            if let Err(e) = library.listen_for_fts() {
+                log::warn!("Failed to open the full text index: {}", e);
+            }
+        }
+        Ok(library)
     }
 
     pub fn path(&self) -> &Path {
@@ -55,6 +108,15 @@ impl Library {
         &self.conn
     }
 
+    pub(crate) fn conn_mut(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+
+    /// Tells listeners about books and formats added or removed.
+    pub fn events(&self) -> &EventDispatcher {
+        &self.events
+    }
+
     /// Open an in-memory database for testing
     pub fn open_test() -> Result<Self, LibraryError> {
         let conn = Connection::open_in_memory()?;
@@ -62,6 +124,8 @@ impl Library {
         Ok(Library {
             conn,
             path: PathBuf::from(":memory:"),
+            events: EventDispatcher::new(),
+            fts_listening: false,
         })
     }
 
@@ -87,7 +151,12 @@ impl Library {
         // Register custom functions (same as open)
         Self::register_functions(&conn)?;
 
-        Ok(Library { conn, path })
+        Ok(Library {
+            conn,
+            path,
+            events: EventDispatcher::new(),
+            fts_listening: false,
+        })
     }
 
     fn init_schema(conn: &Connection) -> Result<(), rusqlite::Error> {
@@ -143,33 +212,7 @@ impl Library {
     }
 
     fn register_functions(conn: &Connection) -> Result<(), rusqlite::Error> {
-        conn.create_scalar_function(
-            "title_sort",
-            1,
-            rusqlite::functions::FunctionFlags::SQLITE_UTF8,
-            |ctx| {
-                let title: String = ctx.get(0)?;
-                Ok(title)
-            },
-        )?;
-
-        conn.create_scalar_function(
-            "author_to_author_sort",
-            1,
-            rusqlite::functions::FunctionFlags::SQLITE_UTF8,
-            |ctx| {
-                let author: String = ctx.get(0)?;
-                Ok(author)
-            },
-        )?;
-
-        conn.create_scalar_function(
-            "uuid4",
-            0,
-            rusqlite::functions::FunctionFlags::SQLITE_UTF8,
-            |_ctx| Ok(uuid::Uuid::new_v4().to_string()),
-        )?;
-        Ok(())
+        crate::schema_upgrades::register_functions(conn)
     }
 
     pub fn insert_test_book(&self, title: &str) -> Result<(), LibraryError> {
@@ -254,6 +297,40 @@ impl Library {
         &mut self,
         source_path: &Path,
         metadata: &MetaInformation,
+    ) -> Result<i32, LibraryError> {
+        let book_id = self.add_empty_book(metadata)?;
+
+        // 5. File System Operations
+        if self.path != Path::new(":memory:") {
+            let ext = source_path
+                .extension()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            self.add_format(book_id, source_path, &ext, true)?;
+
+            // Books without a cover of their own get a generated one
+            if metadata.cover_data.1.is_empty() {
+                if let Err(e) = self.generate_cover(book_id) {
+                    log::warn!("Failed to set the cover of {:?}: {}", metadata.title, e);
+                }
+            }
+        }
+
+        Ok(book_id)
+    }
+
+    /// Add a book without any format, with its folder and cover if
+    /// `metadata` has one.
+    pub fn add_empty_book(&mut self, metadata: &MetaInformation) -> Result<i32, LibraryError> {
+        self.create_book(metadata, None)
+    }
+
+    /// Add a book without any format, as `book_id` if it is given.
+    fn create_book(
+        &mut self,
+        metadata: &MetaInformation,
+        book_id: Option<i32>,
     ) -> Result<i32, LibraryError> {
         // Simple sanitization for folder name
         let author_name = metadata
@@ -263,23 +340,29 @@ impl Library {
             .unwrap_or("Unknown");
         let author_folder = sanitize_filename(author_name);
         let title_folder = sanitize_filename(&metadata.title);
-        let rel_path = Path::new(&author_folder).join(&title_folder);
+        let mut rel_path = Path::new(&author_folder).join(&title_folder);
         // "Author/Title"
         let rel_path_str = rel_path.to_string_lossy().replace("\\", "/");
 
-        let book_id = self.add_book_db_entry(metadata, &rel_path_str)?;
-
-        // 5. File System Operations
-        if self.path != PathBuf::from(":memory:") {
-            let dest_dir = self.path.join(&rel_path);
-            fs::create_dir_all(&dest_dir)?;
+        let book_id = self.insert_book_entry(metadata, &rel_path_str, book_id)?;
 
-            let ext = source_path.extension().unwrap_or_default();
-            let dest_file = dest_dir
-                .join(sanitize_filename(&metadata.title))
-                .with_extension(ext);
+        if self.path != Path::new(":memory:") {
+            // Another book with the same title and author has the folder
+            if self.path.join(&rel_path).exists() {
+                rel_path =
+                    Path::new(&author_folder).join(format!("{} ({})", title_folder, book_id));
+                self.conn.execute(
+                    "UPDATE books SET path = ?1 WHERE id = ?2",
+                    (rel_path.to_string_lossy().replace("\\", "/"), book_id),
+                )?;
+            }
+            fs::create_dir_all(self.path.join(&rel_path))?;
 
-            fs::copy(source_path, dest_file)?;
+            if !metadata.cover_data.1.is_empty() {
+                if let Err(e) = self.set_cover_data(book_id, &metadata.cover_data.1) {
+                    log::warn!("Failed to set the cover of {:?}: {}", metadata.title, e);
+                }
+            }
         }
 
         Ok(book_id)
@@ -291,6 +374,16 @@ impl Library {
         &mut self,
         metadata: &MetaInformation,
         rel_path: &str,
+    ) -> Result<i32, LibraryError> {
+        self.insert_book_entry(metadata, rel_path, None)
+    }
+
+    /// Insert the entry of a book, as `book_id` if it is given.
+    fn insert_book_entry(
+        &mut self,
+        metadata: &MetaInformation,
+        rel_path: &str,
+        book_id: Option<i32>,
     ) -> Result<i32, LibraryError> {
         let tx = self.conn.transaction()?;
 
@@ -301,43 +394,48 @@ impl Library {
             .map(|s| s.as_str())
             .unwrap_or("Unknown");
 
-        // 2. Insert Book
         // 2. Insert Book
         tx.execute(
-            "INSERT INTO books (title, sort, author_sort, path, has_cover, timestamp, pubdate, uuid, series_index)
-             VALUES (?1, ?1, ?2, ?3, 0, ?5, ?6, ?4, ?7)",
+            "INSERT INTO books (id, title, sort, author_sort, path, has_cover, timestamp, pubdate, uuid, series_index)
+             VALUES (?8, ?1, ?1, ?2, ?3, 0, ?5, ?6, ?4, ?7)",
             (
                 &metadata.title,
-                author_name,
+                metadata.author_sort.as_deref().unwrap_or(author_name),
                 rel_path,
                 metadata.uuid.as_deref().unwrap_or(""),
                 metadata.timestamp.unwrap_or(chrono::Utc::now()).to_rfc3339(),
                 metadata.pubdate.unwrap_or(chrono::Utc::now()).to_rfc3339(),
                 metadata.series_index,
+                book_id,
             ),
         )?;
         let book_id = tx.last_insert_rowid() as i32;
 
-        // 3. Insert/Get Author
-        let author_id: i32 = {
-            let mut stmt = tx.prepare("SELECT id FROM authors WHERE name = ?1")?;
-            let mut rows = stmt.query([author_name])?;
-            if let Some(row) = rows.next()? {
-                row.get(0)?
-            } else {
-                tx.execute(
-                    "INSERT INTO authors (name, sort) VALUES (?1, ?1)",
-                    [author_name],
-                )?;
-                tx.last_insert_rowid() as i32
-            }
+        // 3. Insert/Get Authors and link them
+        let authors: Vec<&str> = if metadata.authors.is_empty() {
+            vec![author_name]
+        } else {
+            metadata.authors.iter().map(|a| a.as_str()).collect()
         };
+        for author in authors {
+            let author_id: i32 = {
+                let mut stmt = tx.prepare("SELECT id FROM authors WHERE name = ?1")?;
+                let mut rows = stmt.query([author])?;
+                if let Some(row) = rows.next()? {
+                    row.get(0)?
+                } else {
+                    tx.execute("INSERT INTO authors (name, sort) VALUES (?1, ?1)", [author])?;
+                    tx.last_insert_rowid() as i32
+                }
+            };
+            tx.execute(
+                "INSERT INTO books_authors_link (book, author) VALUES (?1, ?2)",
+                (book_id, author_id),
+            )?;
+        }
 
-        // 4. Link
-        tx.execute(
-            "INSERT INTO books_authors_link (book, author) VALUES (?1, ?2)",
-            (book_id, author_id),
-        )?;
+        // 4. The other fields, for the tables the library has
+        write_linked_metadata(&tx, book_id, metadata)?;
 
         tx.commit()?;
         Ok(book_id)
@@ -441,7 +539,8 @@ impl Library {
                 return Ok(false);
             }
 
-            std::fs::copy(source_path, dest_path)?;
+            let size = std::fs::copy(source_path, dest_path)?;
+            self.record_format(book_id, format, size, &sanitize_filename(&book.title))?;
 
             // Update timestamp of the book
             self.conn.execute(
@@ -483,12 +582,21 @@ impl Library {
 
         let new_author_folder = sanitize_filename(new_author);
         let new_title_folder = sanitize_filename(new_title);
-        let new_rel_path = Path::new(&new_author_folder).join(&new_title_folder);
-        let new_full_dir = self.path.join(&new_rel_path);
+        let mut new_rel_path = Path::new(&new_author_folder).join(&new_title_folder);
+        let mut new_full_dir = self.path.join(&new_rel_path);
 
         if old_full_dir == new_full_dir {
             return Ok(());
         }
+        // Another book with the same title and author has the folder
+        if new_full_dir.exists() {
+            new_rel_path =
+                Path::new(&new_author_folder).join(format!("{} ({})", new_title_folder, book_id));
+            new_full_dir = self.path.join(&new_rel_path);
+            if old_full_dir == new_full_dir {
+                return Ok(());
+            }
+        }
 
         // Create new parent dir (Author) if needed
         let new_author_full_path = self.path.join(&new_author_folder);
@@ -543,6 +651,21 @@ impl Library {
         Ok(())
     }
 
+    /// Moves the folder and files of a book to match its title and first
+    /// author.
+    pub(crate) fn move_book_files(&mut self, book_id: i32) -> Result<(), LibraryError> {
+        if self.path == Path::new(":memory:") {
+            return Ok(());
+        }
+        let Some(book) = self.get_book(book_id)? else {
+            return Ok(());
+        };
+        let authors = self.get_authors(book_id)?;
+        let author = authors.first().map(|a| a.as_str()).unwrap_or("Unknown");
+        self.rename_book_files(book_id, &book.title, author)?;
+        Ok(())
+    }
+
     pub fn update_book_cover(
         &mut self,
         book_id: i32,
@@ -571,6 +694,39 @@ impl Library {
         Ok(())
     }
 
+    /// Store `data`, in any supported image format, as the cover of the
+    /// book.
+    pub fn set_cover_data(&mut self, book_id: i32, data: &[u8]) -> Result<(), LibraryError> {
+        let data = save_cover_data_to(data).map_err(|e| LibraryError::Cover(format!("{:#}", e)))?;
+        let rel_path: Option<String> = self
+            .conn
+            .query_row("SELECT path FROM books WHERE id = ?1", (book_id,), |row| {
+                row.get(0)
+            })
+            .optional()?;
+        if let Some(rel_path) = rel_path {
+            if self.path != Path::new(":memory:") && !rel_path.is_empty() {
+                let dir_path = self.path.join(rel_path);
+                fs::create_dir_all(&dir_path)?;
+                fs::write(dir_path.join("cover.jpg"), data)?;
+                self.conn
+                    .execute("UPDATE books SET has_cover = 1 WHERE id = ?1", (book_id,))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the cover of the book with one generated from its title,
+    /// authors and series, using the cover generation preferences.
+    pub fn generate_cover(&mut self, book_id: i32) -> Result<(), LibraryError> {
+        let Some(mi) = self.get_metadata(book_id)? else {
+            return Ok(());
+        };
+        let data = covers::generate_cover(&mi, &CoverPrefs::load())
+            .map_err(|e| LibraryError::Cover(format!("{:#}", e)))?;
+        self.set_cover_data(book_id, &data)
+    }
+
     pub fn delete_book(&mut self, book_id: i32) -> Result<(), LibraryError> {
         // Get path before deleting to remove files
         let path_query: Option<String> = self
@@ -584,10 +740,16 @@ impl Library {
 
         tx.execute("DELETE FROM books WHERE id = ?1", (book_id,))?;
         tx.execute("DELETE FROM books_authors_link WHERE book = ?1", (book_id,))?;
+        if crate::schema_upgrades::table_exists(&tx, "data")? {
+            tx.execute("DELETE FROM data WHERE book = ?1", (book_id,))?;
+        }
         // Note: Authors are left even if they have no books, typical Calibre behavior (or maybe cleanup?)
         // We leave them for now.
 
         tx.commit()?;
+        self.events.dispatch(Event::BookRemoved {
+            book_ids: vec![book_id],
+        });
 
         // File Cleanup
         if let Some(rel_path) = path_query {
@@ -650,20 +812,20 @@ impl Library {
         Ok(custom_columns)
     }
 
+    /// Ids of the books matching a calibre search expression, in id order.
     pub fn search(&self, query: &str) -> Result<Vec<i32>, LibraryError> {
-        // TODO: Implement full search syntax parsing.
-        // For now, simple LIKE on title
-        let mut stmt = self
-            .conn
-            .prepare("SELECT id FROM books WHERE title LIKE ?1 OR author_sort LIKE ?1")?;
-        let pattern = format!("%{}%", query);
-        let rows = stmt.query_map([&pattern], |row| row.get(0))?;
+        self.search_restricted(query, None)
+    }
 
-        let mut ids = Vec::new();
-        for row in rows {
-            ids.push(row?);
-        }
-        Ok(ids)
+    /// Like `search`, limited to the books of the named virtual library.
+    pub fn search_restricted(
+        &self,
+        query: &str,
+        virtual_library: Option<&str>,
+    ) -> Result<Vec<i32>, LibraryError> {
+        Search::new(&self.conn)
+            .search(query, virtual_library)
+            .map_err(|e| LibraryError::Search(format!("{:#}", e)))
     }
 
     pub fn get_book(&self, id: i32) -> Result<Option<Book>, LibraryError> {
@@ -748,6 +910,41 @@ impl Library {
         Ok(())
     }
 
+    /// Whether full text indexing is turned on for this library.
+    pub fn is_fts_enabled(&self) -> Result<bool, LibraryError> {
+        Ok(self.get_preference(FTS_ENABLED_PREF)?.as_deref() == Some("true"))
+    }
+
+    /// Turn full text indexing on or off. Turning it on queues every format
+    /// not indexed yet, the index is kept when it is turned off.
+    pub fn enable_fts(&mut self, enabled: bool) -> Result<(), LibraryError> {
+        self.set_preference(FTS_ENABLED_PREF, if enabled { "true" } else { "false" })?;
+        if enabled {
+            self.listen_for_fts()?;
+        }
+        Ok(())
+    }
+
+    /// Keep the index in step with the formats of the library. The
+    /// listener stays for the life of the library, so that removed books
+    /// leave the index even after indexing is turned off.
+    fn listen_for_fts(&mut self) -> Result<(), LibraryError> {
+        if !self.fts_listening && self.path != Path::new(":memory:") {
+            self.events.register(Box::new(self.fts_indexer()?));
+            self.fts_listening = true;
+        }
+        Ok(())
+    }
+
+    /// The full text index of this library, through a connection of its own
+    /// so that it can be indexed from another thread.
+    pub fn fts_indexer(&self) -> Result<FtsIndexer, LibraryError> {
+        let backend = Backend::new(&self.path)?;
+        let fts = Arc::new(FtsConnection::new(backend.conn.clone(), &backend.db_path));
+        fts.initialize()?;
+        Ok(FtsIndexer::new(fts, &self.path))
+    }
+
     pub fn get_categories(
         &self,
     ) -> Result<std::collections::HashMap<String, Vec<Category>>, LibraryError> {
@@ -810,7 +1007,9 @@ impl Library {
                     if path.is_file() {
                         if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
                             if ext.to_lowercase() == target_ext {
-                                fs::remove_file(path)?;
+                                let opf = self.metadata_opf(book_id)?;
+                                self.trash().add_format(book_id, fmt, &path, &opf)?;
+                                self.record_format(book_id, fmt, 0, "")?;
                                 // Only remove one? Or all matching?
                                 // Ideally there is only one per format.
                                 // We'll break after first match to match standard behavior?
@@ -846,6 +1045,86 @@ impl Library {
         Ok(authors)
     }
 
+    /// Keeps the `data` table in step with the format files of a book. An
+    /// empty `name` removes the format.
+    fn record_format(
+        &self,
+        book_id: i32,
+        format: &str,
+        size: u64,
+        name: &str,
+    ) -> Result<(), LibraryError> {
+        if !crate::schema_upgrades::table_exists(&self.conn, "data")? {
+            return Ok(());
+        }
+        let format = format.to_uppercase();
+        self.conn.execute(
+            "DELETE FROM data WHERE book = ?1 AND format = ?2",
+            (book_id, &format),
+        )?;
+        if name.is_empty() {
+            self.events.dispatch(Event::FormatRemoved { book_id, format });
+        } else {
+            self.conn.execute(
+                "INSERT INTO data (book, format, uncompressed_size, name) VALUES (?1, ?2, ?3, ?4)",
+                (book_id, &format, size as i64, name),
+            )?;
+            self.events.dispatch(Event::FormatAdded { book_id, format });
+        }
+        Ok(())
+    }
+
+    /// The formats of a book, upper case, as recorded in the `data` table.
+    pub fn formats(&self, book_id: i32) -> Result<Vec<String>, LibraryError> {
+        Ok(self
+            .format_files(book_id)?
+            .into_iter()
+            .map(|(_, fmt)| fmt.to_uppercase())
+            .collect())
+    }
+
+    /// Ids of the books with the same authors and a title that only
+    /// differs in case or punctuation.
+    pub fn find_identical_books(
+        &self,
+        title: &str,
+        authors: &[String],
+    ) -> Result<HashSet<i32>, LibraryError> {
+        let mut author_map: IndexMap<String, Vec<i32>> = IndexMap::new();
+        for (id, name) in self.all_authors()? {
+            author_map
+                .entry(name.trim().to_lowercase())
+                .or_default()
+                .push(id);
+        }
+
+        let mut aid_to_bids: IndexMap<i32, Vec<i32>> = IndexMap::new();
+        let mut stmt = self
+            .conn
+            .prepare("SELECT author, book FROM books_authors_link")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, i32>(0)?, row.get::<_, i32>(1)?)))?;
+        for row in rows {
+            let (aid, bid) = row?;
+            aid_to_bids.entry(aid).or_default().push(bid);
+        }
+
+        let mut stmt = self.conn.prepare("SELECT id, title FROM books")?;
+        let title_map = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, i32>(0)?, row.get::<_, Option<String>>(1)?))
+            })?
+            .map(|row| row.map(|(id, title)| (id, title.unwrap_or_default())))
+            .collect::<Result<IndexMap<i32, String>>>()?;
+
+        Ok(crate::utils::find_identical_books(
+            title,
+            authors,
+            &author_map,
+            &aid_to_bids,
+            &title_map,
+        ))
+    }
+
     pub fn format_files(&self, book_id: i32) -> Result<Vec<(String, String)>, LibraryError> {
         // Query 'data' table for formats
         let mut stmt = self
@@ -1160,27 +1439,250 @@ impl Library {
         Ok(authors)
     }
 
+    /// The metadata of `book_id` as stored in the database, with the cover.
+    /// Tables a minimal library lacks (tags, series, ...) are skipped.
+    pub fn get_metadata(&self, book_id: i32) -> Result<Option<MetaInformation>, LibraryError> {
+        let Some(book) = self.get_book(book_id)? else {
+            return Ok(None);
+        };
+        let mut mi = MetaInformation::default();
+        mi.title = book.title.clone();
+        let authors = self.get_authors(book_id)?;
+        if !authors.is_empty() {
+            mi.authors = authors;
+        }
+        mi.title_sort = book.sort.clone();
+        mi.author_sort = book.author_sort.clone();
+        mi.series_index = book.series_index;
+        mi.uuid = book.uuid.clone();
+        mi.timestamp = book.timestamp.as_deref().and_then(parse_db_date);
+        mi.pubdate = book.pubdate.as_deref().and_then(parse_db_date);
+        if let Some(isbn) = book.isbn.as_deref().filter(|i| !i.is_empty()) {
+            mi.set_identifier("isbn", isbn);
+        }
+
+        let linked = |sql: &str| -> Vec<String> {
+            self.conn
+                .prepare(sql)
+                .and_then(|mut stmt| {
+                    stmt.query_map([book_id], |row| row.get(0))?
+                        .collect::<Result<Vec<String>>>()
+                })
+                .unwrap_or_default()
+        };
+        mi.tags = linked(
+            "SELECT t.name FROM tags t JOIN books_tags_link l ON t.id = l.tag WHERE l.book = ?1",
+        );
+        mi.series = linked(
+            "SELECT s.name FROM series s JOIN books_series_link l ON s.id = l.series WHERE l.book = ?1",
+        )
+        .pop();
+        mi.publisher = linked(
+            "SELECT p.name FROM publishers p JOIN books_publishers_link l ON p.id = l.publisher WHERE l.book = ?1",
+        )
+        .pop();
+        mi.comments = linked("SELECT text FROM comments WHERE book = ?1").pop();
+        let languages = linked(
+            "SELECT g.lang_code FROM languages g JOIN books_languages_link l ON g.id = l.lang_code
+             WHERE l.book = ?1 ORDER BY l.item_order",
+        );
+        if !languages.is_empty() {
+            mi.languages = languages;
+        }
+        mi.rating = self
+            .conn
+            .query_row(
+                "SELECT r.rating FROM ratings r JOIN books_ratings_link l ON r.id = l.rating WHERE l.book = ?1",
+                [book_id],
+                |row| row.get::<_, f64>(0),
+            )
+            .ok();
+        if let Ok(mut stmt) = self
+            .conn
+            .prepare("SELECT type, val FROM identifiers WHERE book = ?1")
+        {
+            let rows = stmt.query_map([book_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            for row in rows {
+                let (key, val): (String, String) = row?;
+                mi.identifiers.insert(key, val);
+            }
+        }
+
+        if let Some(cover) = self.get_cover_path(&book) {
+            if let Ok(data) = fs::read(cover) {
+                mi.cover_data = (Some("jpg".to_string()), data);
+            }
+        }
+        Ok(Some(mi))
+    }
+
+    /// Remove books, to the trash unless `permanent`.
     pub fn remove_books(&mut self, ids: &[i32], permanent: bool) -> Result<(), LibraryError> {
+        for &id in ids {
+            if permanent || self.path == Path::new(":memory:") {
+                self.delete_book(id)?;
+            } else {
+                self.move_book_to_trash(id)?;
+            }
+        }
         if !permanent {
-            // TODO: Implement recycle bin / trash support
-            // For now, we will warn/log and proceed with permanent deletion or return error?
-            // Python implementation moves to trash.
-            // "trash_name()" is used.
-            // Since we don't have trash support yet, let's just delete but print a warning if we could.
-            // Or strictly speaking, we could error.
-            // But to unblock, let's treat as permanent for now or maybe implement a simple trash?
-            // "crates/calibre_utils/src/recycle_bin.rs" appears to be ported?
-            // But from modules_to_port.md it says [x] recycle_bin.py.
-            // Let's assume for this PR we just do permanent delete to match delete_book.
-            eprintln!("Warning: Trash not supported yet, deleting permanently.");
+            self.expire_old_trash()?;
         }
+        Ok(())
+    }
 
-        for &id in ids {
-            self.delete_book(id)?;
+    /// The trash of this library.
+    pub fn trash(&self) -> Trash {
+        Trash::new(&self.path)
+    }
+
+    /// How long removed books and formats are kept in the trash.
+    pub fn trash_expiry(&self) -> Result<Duration, LibraryError> {
+        let pref = if crate::schema_upgrades::table_exists(&self.conn, "preferences")? {
+            self.get_preference(TRASH_EXPIRY_PREF)?
+        } else {
+            None
+        };
+        let secs = pref
+            .and_then(|val| val.trim().parse::<f64>().ok())
+            .map(|secs| secs as u64)
+            .unwrap_or(DEFAULT_TRASH_EXPIRY_TIME_SECONDS);
+        Ok(Duration::from_secs(secs.max(MIN_TRASH_EXPIRY_SECONDS)))
+    }
+
+    pub fn set_trash_expiry(&mut self, expiry: Duration) -> Result<(), LibraryError> {
+        self.set_preference(TRASH_EXPIRY_PREF, &expiry.as_secs().to_string())
+    }
+
+    /// Remove the trash entries older than the trash expiry. Returns how
+    /// many were removed.
+    pub fn expire_old_trash(&self) -> Result<usize, LibraryError> {
+        let trash = self.trash();
+        if self.path == Path::new(":memory:") || !trash.path().exists() {
+            return Ok(0);
         }
+        Ok(trash.expire(self.trash_expiry()?)?)
+    }
+
+    /// The removed books and the removed formats in the trash, newest
+    /// first.
+    pub fn list_trash(&self) -> Result<(Vec<TrashEntry>, Vec<TrashEntry>), LibraryError> {
+        if self.path == Path::new(":memory:") {
+            return Ok((Vec::new(), Vec::new()));
+        }
+        Ok(self.trash().entries()?)
+    }
+
+    /// Remove everything in the trash. Returns how many entries were
+    /// removed.
+    pub fn empty_trash(&self) -> Result<usize, LibraryError> {
+        if self.path == Path::new(":memory:") {
+            return Ok(0);
+        }
+        Ok(self.trash().empty()?)
+    }
+
+    /// Move the folder of a book, with its metadata.opf, to the trash and
+    /// remove the book from the database.
+    pub fn move_book_to_trash(&mut self, book_id: i32) -> Result<(), LibraryError> {
+        let Some(book) = self.get_book(book_id)? else {
+            return Err(LibraryError::Transaction(format!(
+                "Book {} not found",
+                book_id
+            )));
+        };
+        let opf = self.metadata_opf(book_id)?;
+        if book.path.is_empty() {
+            self.trash().add_book(book_id, Path::new(""), &opf)?;
+        } else {
+            let book_dir = self.path.join(&book.path);
+            self.trash().add_book(book_id, &book_dir, &opf)?;
+            // The author folder goes with its last book
+            if let Some(parent) = book_dir.parent() {
+                if parent != self.path && fs::read_dir(parent)?.next().is_none() {
+                    let _ = fs::remove_dir(parent);
+                }
+            }
+        }
+        self.delete_book(book_id)
+    }
+
+    /// Put a removed book back in the library, with its formats and cover.
+    /// It keeps its id unless another book has it since. Returns the id of
+    /// the restored book.
+    pub fn restore_book_from_trash(&mut self, book_id: i32) -> Result<i32, LibraryError> {
+        let trash = self.trash();
+        let src = trash.book_dir(book_id);
+        let opf = fs::read_to_string(src.join(METADATA_FILE_NAME))
+            .map_err(|_| LibraryError::Trash(format!("Book {} is not in the trash", book_id)))?;
+        let metadata = parse_opf(&opf).map_err(|e| LibraryError::Trash(e.to_string()))?;
+
+        let taken = self
+            .conn
+            .query_row("SELECT 1 FROM books WHERE id=?1", [book_id], |_| Ok(()))
+            .optional()?
+            .is_some();
+        let force_id = if taken { None } else { Some(book_id) };
+        let new_id = self.create_book(&metadata, force_id)?;
+        let Some(book) = self.get_book(new_id)? else {
+            return Err(LibraryError::Transaction(format!(
+                "Book {} not found",
+                new_id
+            )));
+        };
+        let book_dir = self.path.join(&book.path);
+        trash::move_tree(&src, &book_dir)?;
+
+        for entry in fs::read_dir(&book_dir)? {
+            let path = entry?.path();
+            if !path.is_file() || trash::is_special_file(&path) {
+                continue;
+            }
+            let (Some(stem), Some(ext)) = (
+                path.file_stem().and_then(|s| s.to_str()),
+                path.extension().and_then(|e| e.to_str()),
+            ) else {
+                continue;
+            };
+            let size = fs::metadata(&path)?.len();
+            self.record_format(new_id, ext, size, stem)?;
+        }
+        if book_dir.join(COVER_FILE_NAME).exists() {
+            self.conn
+                .execute("UPDATE books SET has_cover = 1 WHERE id = ?1", (new_id,))?;
+        }
+        Ok(new_id)
+    }
+
+    /// Put a removed format back in its book, replacing the format of the
+    /// book if it has one again.
+    pub fn restore_format_from_trash(
+        &mut self,
+        book_id: i32,
+        fmt: &str,
+    ) -> Result<(), LibraryError> {
+        let trash = self.trash();
+        let path = trash.format_path(book_id, fmt);
+        if !path.is_file() {
+            return Err(LibraryError::Trash(format!(
+                "The {} format of book {} is not in the trash",
+                fmt.to_uppercase(),
+                book_id
+            )));
+        }
+        self.add_format(book_id, &path, fmt, true)?;
+        trash.remove_format(book_id, fmt)?;
         Ok(())
     }
 
+    /// The metadata of a book as the contents of a metadata.opf.
+    fn metadata_opf(&self, book_id: i32) -> Result<String, LibraryError> {
+        Ok(self
+            .get_metadata(book_id)?
+            .map(|mi| mi.to_xml())
+            .unwrap_or_default())
+    }
+
     pub fn set_metadata(
         &mut self,
         book_id: i32,
@@ -1261,6 +1763,98 @@ impl Library {
     }
 }
 
+/// Dates are stored as `2020-01-31 12:00:00+00:00`, the year 101 stands for
+/// an unset date.
+pub(crate) fn parse_db_date(raw: &str) -> Option<DateTime<Utc>> {
+    // SQLite's datetime('now') leaves out the offset, it is UTC
+    let dt = DateTime::parse_from_rfc3339(raw)
+        .or_else(|_| DateTime::parse_from_str(raw, "%Y-%m-%d %H:%M:%S%.f%:z"))
+        .map(|dt| dt.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(raw, "%Y-%m-%d %H:%M:%S%.f").map(|dt| dt.and_utc())
+        })
+        .ok()?;
+    (dt.year() > 101).then_some(dt)
+}
+
+/// Writes the fields of `mi` kept in their own tables. Libraries made by
+/// `Library::create` lack most of them, those fields are skipped.
+fn write_linked_metadata(
+    tx: &rusqlite::Transaction,
+    book_id: i32,
+    mi: &MetaInformation,
+) -> rusqlite::Result<()> {
+    let has = |table: &str| crate::schema_upgrades::table_exists(tx, table).unwrap_or(false);
+
+    if has("tags") && has("books_tags_link") {
+        for tag in &mi.tags {
+            tx.execute("INSERT OR IGNORE INTO tags (name) VALUES (?1)", [tag])?;
+            tx.execute(
+                "INSERT OR IGNORE INTO books_tags_link (book, tag)
+                 SELECT ?1, id FROM tags WHERE name = ?2",
+                (book_id, tag),
+            )?;
+        }
+    }
+    if let Some(series) = mi.series.as_deref().filter(|s| !s.is_empty()) {
+        if has("series") && has("books_series_link") {
+            tx.execute(
+                "INSERT OR IGNORE INTO series (name, sort) VALUES (?1, ?1)",
+                [series],
+            )?;
+            tx.execute(
+                "INSERT OR IGNORE INTO books_series_link (book, series)
+                 SELECT ?1, id FROM series WHERE name = ?2",
+                (book_id, series),
+            )?;
+        }
+    }
+    if let Some(publisher) = mi.publisher.as_deref().filter(|p| !p.is_empty()) {
+        if has("publishers") && has("books_publishers_link") {
+            tx.execute(
+                "INSERT OR IGNORE INTO publishers (name, sort) VALUES (?1, ?1)",
+                [publisher],
+            )?;
+            tx.execute(
+                "INSERT OR IGNORE INTO books_publishers_link (book, publisher)
+                 SELECT ?1, id FROM publishers WHERE name = ?2",
+                (book_id, publisher),
+            )?;
+        }
+    }
+    if has("languages") && has("books_languages_link") {
+        let languages = mi.languages.iter().filter(|l| l.as_str() != "und");
+        for (order, lang) in languages.enumerate() {
+            tx.execute(
+                "INSERT OR IGNORE INTO languages (lang_code) VALUES (?1)",
+                [lang],
+            )?;
+            tx.execute(
+                "INSERT OR IGNORE INTO books_languages_link (book, lang_code, item_order)
+                 SELECT ?1, id, ?3 FROM languages WHERE lang_code = ?2",
+                (book_id, lang, order as i32),
+            )?;
+        }
+    }
+    if has("identifiers") {
+        for (typ, val) in &mi.identifiers {
+            tx.execute(
+                "INSERT OR REPLACE INTO identifiers (book, type, val) VALUES (?1, ?2, ?3)",
+                (book_id, typ, val),
+            )?;
+        }
+    }
+    if let Some(comments) = mi.comments.as_deref().filter(|c| !c.is_empty()) {
+        if has("comments") {
+            tx.execute(
+                "INSERT OR REPLACE INTO comments (book, text) VALUES (?1, ?2)",
+                (book_id, comments),
+            )?;
+        }
+    }
+    Ok(())
+}
+
 fn sanitize_filename(name: &str) -> String {
     name.replace("/", "_")
         .replace("\\", "_")
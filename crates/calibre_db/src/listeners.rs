@@ -6,6 +6,7 @@ pub enum Event {
     BookAdded { book_id: i32 },
     BookRemoved { book_ids: Vec<i32> },
     FormatAdded { book_id: i32, format: String },
+    FormatRemoved { book_id: i32, format: String },
     // Add others as needed
 }
 
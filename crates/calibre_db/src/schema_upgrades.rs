@@ -1,19 +1,1103 @@
-use rusqlite::{Connection, Result};
+//! Versioned upgrades of the `metadata.db` schema.
+//!
+//! The version lives in `PRAGMA user_version` and the steps are numbered
+//! like calibre's, so a library upgraded here still opens in calibre and a
+//! library from any calibre release opens here.
+
+use rusqlite::functions::{Aggregate, Context, FunctionFlags};
+use rusqlite::{Connection, Transaction};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Schema version this code creates and understands.
+pub const LATEST_VERSION: i32 = 26;
+
+/// Date calibre stores for "never", used to fill new date columns.
+const DEFAULT_DATE: &str = "2000-01-01 00:00:00+00:00";
+
+/// Tables every calibre library has had since the first metadata.db.
+const CALIBRE_TABLES: [&str; 11] = [
+    "books",
+    "authors",
+    "books_authors_link",
+    "publishers",
+    "books_publishers_link",
+    "ratings",
+    "books_ratings_link",
+    "series",
+    "books_series_link",
+    "tags",
+    "books_tags_link",
+];
+
+/// Many-many and many-one fields as `(table, link column, value column,
+/// category sort)`, what the tag browser views are built from.
+const CATEGORY_TABLES: [(&str, &str, &str, &str); 6] = [
+    ("authors", "author", "name", "sort"),
+    ("languages", "lang_code", "lang_code", "lang_code"),
+    ("series", "series", "name", "(title_sort(name))"),
+    ("publishers", "publisher", "name", "name"),
+    ("ratings", "rating", "rating", "rating"),
+    ("tags", "tag", "name", "name"),
+];
+
+#[derive(Error, Debug)]
+pub enum SchemaError {
+    #[error("Database error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(
+        "The library was created by a newer version of calibre (schema version {found}), \
+         only versions up to {supported} can be opened"
+    )]
+    TooNew { found: i32, supported: i32 },
+}
+
+impl From<SchemaError> for rusqlite::Error {
+    fn from(e: SchemaError) -> Self {
+        match e {
+            SchemaError::Sqlite(e) => e,
+            other => rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CANTOPEN),
+                Some(other.to_string()),
+            ),
+        }
+    }
+}
+
+type Step = fn(&Transaction, &Path) -> rusqlite::Result<()>;
+
+/// Step `n` (counting from 1) takes the schema from version `n` to `n + 1`.
+const STEPS: [(&str, Step); 25] = [
+    ("Normalize indices", upgrade_version_1),
+    (
+        "Fix foreign key constraints of link tables",
+        upgrade_version_2,
+    ),
+    ("Add the meta view", upgrade_version_3),
+    ("Rationalize the books table", upgrade_version_4),
+    (
+        "Update indices and triggers of the books table",
+        upgrade_version_5,
+    ),
+    ("Show authors in order", upgrade_version_6),
+    ("Add the uuid column", upgrade_version_7),
+    ("Add tag browser views", upgrade_version_8),
+    ("Add custom columns", upgrade_version_9),
+    ("Add restricted tag browser views", upgrade_version_10),
+    (
+        "Add average ratings to tag browser views",
+        upgrade_version_11,
+    ),
+    ("Store preferences in the database", upgrade_version_12),
+    ("Track books whose OPF backup is stale", upgrade_version_13),
+    ("Cache has_cover", upgrade_version_14),
+    ("Remove commas from tags", upgrade_version_15),
+    (
+        "Only update the title sort when the title changes",
+        upgrade_version_16,
+    ),
+    ("Add plugin data for books", upgrade_version_17),
+    (
+        "Add identifiers, languages, library id and last_modified",
+        upgrade_version_18,
+    ),
+    ("Retire the feeds table", upgrade_version_19),
+    ("Add links to authors", upgrade_version_20),
+    ("Keep series sort in the series table", upgrade_version_21),
+    ("Add last read positions", upgrade_version_22),
+    ("Add annotations", upgrade_version_23),
+    ("Index annotations", upgrade_version_24),
+    ("Add links to the remaining categories", upgrade_version_25),
+];
 
 pub struct SchemaUpgrade;
 
 impl SchemaUpgrade {
-    pub fn upgrade_to_latest(conn: &mut Connection, library_path: &std::path::Path) -> Result<()> {
-        // stub: just read the user_version to ensure DB is valid/accessible
-        let user_version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
-        println!(
-            "Database version: {} (Library: {:?})",
-            user_version, library_path
-        );
+    /// Bring a library database up to `LATEST_VERSION`, one step and one
+    /// transaction at a time, after copying it aside. Returns the version
+    /// reached, or `None` for a database that is not a calibre library
+    /// (such as a brand new, empty one), which is left alone.
+    pub fn upgrade_to_latest(
+        conn: &mut Connection,
+        library_path: &Path,
+    ) -> Result<Option<i32>, SchemaError> {
+        register_functions(conn)?;
+        let Some(mut version) = Self::detect_version(conn)? else {
+            return Ok(None);
+        };
+        if version > LATEST_VERSION {
+            return Err(SchemaError::TooNew {
+                found: version,
+                supported: LATEST_VERSION,
+            });
+        }
+
+        if version < LATEST_VERSION {
+            if let Some(backup) = backup_database(conn, version)? {
+                log::info!("Saved a copy of the database to {:?}", backup);
+            }
+        }
+        while version < LATEST_VERSION {
+            let (description, step) = STEPS[version as usize - 1];
+            log::info!(
+                "Upgrading database to version {}: {}",
+                version + 1,
+                description
+            );
+            let tx = conn.transaction()?;
+            step(&tx, library_path)?;
+            tx.pragma_update(None, "user_version", version + 1)?;
+            tx.commit()?;
+            version += 1;
+        }
+        if conn.query_row("PRAGMA user_version", [], |row| row.get::<_, i32>(0))? != version {
+            // A library from a release that did not record its version
+            conn.pragma_update(None, "user_version", version)?;
+        }
+
+        recreate_author_triggers(conn)?;
+        Ok(Some(version))
+    }
 
-        // In the future, we would check user_version and run migrations here.
-        // For now, we assume the DB is compatible.
+    /// Schema version of a database: `PRAGMA user_version`, or for an
+    /// unversioned calibre library the version its layout matches. `None`
+    /// when the database holds no calibre library.
+    pub fn detect_version(conn: &Connection) -> rusqlite::Result<Option<i32>> {
+        if !table_exists(conn, "books")? {
+            return Ok(None);
+        }
+        let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > 0 {
+            return Ok(Some(version));
+        }
+        for table in CALIBRE_TABLES {
+            if !table_exists(conn, table)? {
+                return Ok(None);
+            }
+        }
 
+        // The newest change present tells the version, checked newest first
+        let version = if column_exists(conn, "tags", "link")? {
+            26
+        } else if table_exists(conn, "annotations")? {
+            24
+        } else if table_exists(conn, "last_read_positions")? {
+            23
+        } else if object_exists(conn, "trigger", "series_insert_trg")? {
+            22
+        } else if column_exists(conn, "authors", "link")? {
+            21
+        } else if table_exists(conn, "identifiers")? {
+            19
+        } else if table_exists(conn, "books_plugin_data")? {
+            18
+        } else if column_exists(conn, "books", "has_cover")? {
+            15
+        } else if table_exists(conn, "metadata_dirtied")? {
+            14
+        } else if table_exists(conn, "preferences")? {
+            13
+        } else if column_exists(conn, "tag_browser_tags", "avg_rating")? {
+            12
+        } else if object_exists(conn, "view", "tag_browser_filtered_tags")? {
+            11
+        } else if table_exists(conn, "custom_columns")? {
+            10
+        } else if object_exists(conn, "view", "tag_browser_tags")? {
+            9
+        } else if column_exists(conn, "books", "uuid")? {
+            8
+        } else if object_exists(conn, "trigger", "books_delete_trg")? {
+            6
+        } else if column_exists(conn, "books", "pubdate")? {
+            5
+        } else if object_exists(conn, "view", "meta")? {
+            4
+        } else if object_exists(conn, "trigger", "fkc_delete_on_authors")? {
+            3
+        } else {
+            1
+        };
+        Ok(Some(version))
+    }
+}
+
+/// Copy the database next to itself before changing its schema. In-memory
+/// databases have nothing to protect.
+fn backup_database(conn: &Connection, version: i32) -> Result<Option<PathBuf>, SchemaError> {
+    let Some(db_path) = conn.path().filter(|p| !p.is_empty()).map(PathBuf::from) else {
+        return Ok(None);
+    };
+    let stem = db_path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("metadata");
+    let backup = db_path.with_file_name(format!("{}_pre_v{}_upgrade.db", stem, version + 1));
+    if backup.exists() {
+        fs::remove_file(&backup)?;
+    }
+    conn.execute("VACUUM INTO ?1", [backup.to_string_lossy()])?;
+    Ok(Some(backup))
+}
+
+/// Register the SQL functions calibre's triggers and views call.
+pub fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+    let flags = || FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    conn.create_scalar_function("title_sort", 1, flags(), |ctx| {
+        let title: Option<String> = ctx.get(0)?;
+        Ok(title.map(|t| calibre_ebooks::metadata::title_sort(&t)))
+    })?;
+    conn.create_scalar_function("author_to_author_sort", 1, flags(), |ctx| {
+        let author: Option<String> = ctx.get(0)?;
+        Ok(author.map(|a| {
+            calibre_ebooks::metadata::author_to_author_sort(
+                &a.replace('|', ","),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+        }))
+    })?;
+    conn.create_scalar_function("uuid4", 0, FunctionFlags::SQLITE_UTF8, |_ctx| {
+        Ok(uuid::Uuid::new_v4().to_string())
+    })?;
+    // Stands in for the filter of the current view in the tag browser views
+    conn.create_scalar_function("books_list_filter", 1, flags(), |_ctx| Ok(1))?;
+
+    conn.create_aggregate_function("concat", 1, flags(), Concatenate)?;
+    for (name, sep) in [
+        ("sortconcat", ","),
+        ("sortconcat_bar", "|"),
+        ("sortconcat_amper", "&"),
+    ] {
+        conn.create_aggregate_function(name, 2, flags(), SortedConcatenate(sep))?;
+    }
+    conn.create_aggregate_function("identifiers_concat", 2, flags(), IdentifiersConcat)?;
+    Ok(())
+}
+
+/// `concat(value)`: the values joined by commas.
+struct Concatenate;
+
+impl Aggregate<Vec<String>, Option<String>> for Concatenate {
+    fn init(&self, _ctx: &mut Context<'_>) -> rusqlite::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, acc: &mut Vec<String>) -> rusqlite::Result<()> {
+        if let Some(value) = ctx.get::<Option<String>>(0)? {
+            acc.push(value);
+        }
         Ok(())
     }
+
+    fn finalize(
+        &self,
+        _ctx: &mut Context<'_>,
+        acc: Option<Vec<String>>,
+    ) -> rusqlite::Result<Option<String>> {
+        Ok(acc.filter(|a| !a.is_empty()).map(|a| a.join(",")))
+    }
+}
+
+/// `sortconcat(order, value)`: the values joined in the given order.
+struct SortedConcatenate(&'static str);
+
+impl Aggregate<Vec<(i64, String)>, Option<String>> for SortedConcatenate {
+    fn init(&self, _ctx: &mut Context<'_>) -> rusqlite::Result<Vec<(i64, String)>> {
+        Ok(Vec::new())
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, acc: &mut Vec<(i64, String)>) -> rusqlite::Result<()> {
+        if let Some(value) = ctx.get::<Option<String>>(1)? {
+            acc.push((ctx.get(0)?, value));
+        }
+        Ok(())
+    }
+
+    fn finalize(
+        &self,
+        _ctx: &mut Context<'_>,
+        acc: Option<Vec<(i64, String)>>,
+    ) -> rusqlite::Result<Option<String>> {
+        Ok(acc.filter(|a| !a.is_empty()).map(|mut a| {
+            a.sort_by_key(|(order, _)| *order);
+            let values: Vec<String> = a.into_iter().map(|(_, v)| v).collect();
+            values.join(self.0)
+        }))
+    }
+}
+
+/// `identifiers_concat(type, value)`: `type:value` pairs joined by commas.
+struct IdentifiersConcat;
+
+impl Aggregate<Vec<String>, Option<String>> for IdentifiersConcat {
+    fn init(&self, _ctx: &mut Context<'_>) -> rusqlite::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, acc: &mut Vec<String>) -> rusqlite::Result<()> {
+        let key: String = ctx.get(0)?;
+        let val: String = ctx.get(1)?;
+        acc.push(format!("{}:{}", key, val));
+        Ok(())
+    }
+
+    fn finalize(
+        &self,
+        _ctx: &mut Context<'_>,
+        acc: Option<Vec<String>>,
+    ) -> rusqlite::Result<Option<String>> {
+        Ok(acc.filter(|a| !a.is_empty()).map(|a| a.join(",")))
+    }
+}
+
+/// The author sort triggers are temporary, so every connection to a
+/// library sets them up again.
+fn recreate_author_triggers(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "DROP TRIGGER IF EXISTS author_insert_trg;
+        CREATE TEMP TRIGGER author_insert_trg
+            AFTER INSERT ON authors
+            BEGIN
+            UPDATE authors SET sort=author_to_author_sort(NEW.name) WHERE id=NEW.id;
+        END;
+        DROP TRIGGER IF EXISTS author_update_trg;
+        CREATE TEMP TRIGGER author_update_trg
+            BEFORE UPDATE ON authors
+            BEGIN
+            UPDATE authors SET sort=author_to_author_sort(NEW.name)
+            WHERE id=NEW.id AND name <> NEW.name;
+        END;
+        UPDATE authors SET sort=author_to_author_sort(name) WHERE sort IS NULL;",
+    )
+}
+
+fn object_exists(conn: &Connection, kind: &str, name: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = ?1 AND name = ?2",
+        [kind, name],
+        |row| row.get::<_, i32>(0),
+    )
+    .map(|n| n > 0)
+}
+
+pub(crate) fn table_exists(conn: &Connection, name: &str) -> rusqlite::Result<bool> {
+    object_exists(conn, "table", name)
+}
+
+/// Works for the columns of views as well.
+fn column_exists(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2",
+        [table, column],
+        |row| row.get::<_, i32>(0),
+    )
+    .map(|n| n > 0)
+}
+
+const CONCAT_AUTHORS: &str = "(SELECT concat(name) FROM authors WHERE authors.id IN \
+     (SELECT author from books_authors_link WHERE book=books.id))";
+const SORTED_AUTHORS: &str = "(SELECT sortconcat(bal.id, name) FROM books_authors_link AS bal \
+     JOIN authors ON(author = authors.id) WHERE book = books.id)";
+
+/// The `meta` view over books, `extra` lists the columns added over time.
+fn meta_view(authors: &str, extra: &str) -> String {
+    format!(
+        "DROP VIEW IF EXISTS meta;
+        CREATE VIEW meta AS
+        SELECT id, title,
+               {authors} authors,
+               (SELECT name FROM publishers WHERE publishers.id IN (SELECT publisher from books_publishers_link WHERE book=books.id)) publisher,
+               (SELECT rating FROM ratings WHERE ratings.id IN (SELECT rating from books_ratings_link WHERE book=books.id)) rating,
+               timestamp,
+               (SELECT MAX(uncompressed_size) FROM data WHERE book=books.id) size,
+               (SELECT concat(name) FROM tags WHERE tags.id IN (SELECT tag from books_tags_link WHERE book=books.id)) tags,
+               (SELECT text FROM comments WHERE book=books.id) comments,
+               (SELECT name FROM series WHERE series.id IN (SELECT series FROM books_series_link WHERE book=books.id)) series,
+               series_index,
+               sort,
+               author_sort,
+               (SELECT concat(format) FROM data WHERE data.book=books.id) formats,
+               isbn,
+               path{extra}
+        FROM books;"
+    )
+}
+
+/// The trigger removing everything that refers to a deleted book.
+fn books_delete_trigger(tables: &[&str]) -> String {
+    let deletes: String = tables
+        .iter()
+        .map(|t| format!("        DELETE FROM {} WHERE book=OLD.id;\n", t))
+        .collect();
+    format!(
+        "DROP TRIGGER IF EXISTS books_delete_trg;
+        CREATE TRIGGER books_delete_trg
+            AFTER DELETE ON books
+            BEGIN
+{deletes}        END;"
+    )
+}
+
+/// Refuse deleting a category item still linked to a book.
+fn foreign_key_trigger(table: &str, link_column: &str, what: &str) -> String {
+    let link_table = format!("books_{}_link", table);
+    format!(
+        "DROP TRIGGER IF EXISTS fkc_delete_on_{table};
+        CREATE TRIGGER fkc_delete_on_{table}
+        BEFORE DELETE ON {table}
+        BEGIN
+            SELECT CASE
+                WHEN (SELECT COUNT(id) FROM {link_table} WHERE {link_column}=OLD.id) > 0
+                THEN RAISE(ABORT, 'Foreign key violation: {what} is still referenced')
+            END;
+        END;"
+    )
+}
+
+/// Categories whose link table exists in this library.
+fn linked_categories(
+    tx: &Transaction,
+) -> rusqlite::Result<Vec<(&'static str, &'static str, &'static str, &'static str)>> {
+    let mut found = Vec::new();
+    for category in CATEGORY_TABLES {
+        if table_exists(tx, &format!("books_{}_link", category.0))? {
+            found.push(category);
+        }
+    }
+    Ok(found)
+}
+
+fn upgrade_version_1(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "DROP INDEX IF EXISTS authors_idx;
+        CREATE INDEX authors_idx ON books (author_sort COLLATE NOCASE, sort COLLATE NOCASE);
+        DROP INDEX IF EXISTS series_idx;
+        CREATE INDEX series_idx ON series (name COLLATE NOCASE);
+        DROP INDEX IF EXISTS series_sort_idx;
+        CREATE INDEX series_sort_idx ON books (series_index, id);",
+    )
+}
+
+fn upgrade_version_2(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    for (table, link_column) in [
+        ("authors", "author"),
+        ("publishers", "publisher"),
+        ("tags", "tag"),
+        ("series", "series"),
+    ] {
+        tx.execute_batch(&format!(
+            "DROP TRIGGER IF EXISTS fkc_delete_books_{table}_link;
+            {trigger}
+            DELETE FROM {table} WHERE
+                (SELECT COUNT(id) FROM books_{table}_link WHERE {link_column}={table}.id) < 1;",
+            trigger = foreign_key_trigger(table, link_column, table),
+        ))?;
+    }
+    Ok(())
+}
+
+fn upgrade_version_3(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(&meta_view(CONCAT_AUTHORS, ""))
+}
+
+fn upgrade_version_4(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"CREATE TEMPORARY TABLE
+        books_backup(id,title,sort,timestamp,series_index,author_sort,isbn,path);
+        INSERT INTO books_backup SELECT id,title,sort,timestamp,series_index,author_sort,isbn,path FROM books;
+        DROP TABLE books;
+        CREATE TABLE books ( id      INTEGER PRIMARY KEY AUTOINCREMENT,
+                             title     TEXT NOT NULL DEFAULT 'Unknown' COLLATE NOCASE,
+                             sort      TEXT COLLATE NOCASE,
+                             timestamp TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                             pubdate   TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                             series_index REAL NOT NULL DEFAULT 1.0,
+                             author_sort TEXT COLLATE NOCASE,
+                             isbn TEXT DEFAULT "" COLLATE NOCASE,
+                             lccn TEXT DEFAULT "" COLLATE NOCASE,
+                             path TEXT NOT NULL DEFAULT "",
+                             flags INTEGER NOT NULL DEFAULT 1
+                        );
+        INSERT INTO
+            books (id,title,sort,timestamp,pubdate,series_index,author_sort,isbn,path)
+            SELECT id,title,sort,timestamp,timestamp,series_index,author_sort,isbn,path FROM books_backup;
+        DROP TABLE books_backup;"#,
+    )?;
+    tx.execute_batch(&meta_view(
+        CONCAT_AUTHORS,
+        ",\n               lccn, pubdate, flags",
+    ))
+}
+
+fn upgrade_version_5(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "DROP INDEX IF EXISTS authors_idx;
+        CREATE INDEX authors_idx ON books (author_sort COLLATE NOCASE);
+        DROP INDEX IF EXISTS books_idx;
+        CREATE INDEX books_idx ON books (sort COLLATE NOCASE);",
+    )?;
+    tx.execute_batch(&books_delete_trigger(&[
+        "books_authors_link",
+        "books_publishers_link",
+        "books_ratings_link",
+        "books_series_link",
+        "books_tags_link",
+        "data",
+        "comments",
+        "conversion_options",
+    ]))?;
+    tx.execute_batch(
+        "DROP TRIGGER IF EXISTS books_insert_trg;
+        CREATE TRIGGER books_insert_trg
+            AFTER INSERT ON books
+            BEGIN
+            UPDATE books SET sort=title_sort(NEW.title) WHERE id=NEW.id;
+        END;
+        DROP TRIGGER IF EXISTS books_update_trg;
+        CREATE TRIGGER books_update_trg
+            AFTER UPDATE ON books
+            BEGIN
+            UPDATE books SET sort=title_sort(NEW.title) WHERE id=NEW.id;
+        END;
+
+        UPDATE books SET sort=title_sort(title) WHERE sort IS NULL;",
+    )
+}
+
+fn upgrade_version_6(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(&meta_view(
+        SORTED_AUTHORS,
+        ",\n               lccn, pubdate, flags",
+    ))
+}
+
+fn upgrade_version_7(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE books ADD COLUMN uuid TEXT;
+        DROP TRIGGER IF EXISTS books_insert_trg;
+        DROP TRIGGER IF EXISTS books_update_trg;
+        UPDATE books SET uuid=uuid4();
+
+        CREATE TRIGGER books_insert_trg AFTER INSERT ON books
+        BEGIN
+            UPDATE books SET sort=title_sort(NEW.title),uuid=uuid4() WHERE id=NEW.id;
+        END;
+
+        CREATE TRIGGER books_update_trg AFTER UPDATE ON books
+        BEGIN
+            UPDATE books SET sort=title_sort(NEW.title) WHERE id=NEW.id;
+        END;",
+    )?;
+    tx.execute_batch(&meta_view(
+        SORTED_AUTHORS,
+        ",\n               lccn, pubdate, flags, uuid",
+    ))
+}
+
+fn upgrade_version_8(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    for (table, link_column) in [
+        ("authors", "author"),
+        ("tags", "tag"),
+        ("publishers", "publisher"),
+        ("series", "series"),
+    ] {
+        tx.execute_batch(&format!(
+            "DROP VIEW IF EXISTS tag_browser_{table};
+            CREATE VIEW tag_browser_{table} AS SELECT
+                id,
+                name,
+                (SELECT COUNT(id) FROM books_{table}_link WHERE {link_column}={table}.id) count
+            FROM {table};"
+        ))?;
+    }
+    Ok(())
+}
+
+fn upgrade_version_9(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"CREATE TABLE custom_columns (
+            id       INTEGER PRIMARY KEY AUTOINCREMENT,
+            label    TEXT NOT NULL,
+            name     TEXT NOT NULL,
+            datatype TEXT NOT NULL,
+            mark_for_delete   BOOL DEFAULT 0 NOT NULL,
+            editable BOOL DEFAULT 1 NOT NULL,
+            display  TEXT DEFAULT "{}" NOT NULL,
+            is_multiple BOOL DEFAULT 0 NOT NULL,
+            normalized BOOL NOT NULL,
+            UNIQUE(label)
+        );
+        CREATE INDEX IF NOT EXISTS custom_columns_idx ON custom_columns (label);
+        CREATE INDEX IF NOT EXISTS formats_idx ON data (format);"#,
+    )
+}
+
+fn upgrade_version_10(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    for (table, link_column, column, _) in linked_categories(tx)? {
+        tx.execute_batch(&format!(
+            "DROP VIEW IF EXISTS tag_browser_{table};
+            CREATE VIEW tag_browser_{table} AS SELECT
+                id,
+                {column},
+                (SELECT COUNT(id) FROM books_{table}_link WHERE {link_column}={table}.id) count
+            FROM {table};
+            DROP VIEW IF EXISTS tag_browser_filtered_{table};
+            CREATE VIEW tag_browser_filtered_{table} AS SELECT
+                id,
+                {column},
+                (SELECT COUNT(books_{table}_link.id) FROM books_{table}_link WHERE
+                    {link_column}={table}.id AND books_list_filter(book)) count
+            FROM {table};"
+        ))?;
+    }
+    Ok(())
+}
+
+fn upgrade_version_11(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    for (table, link_column, column, sort) in linked_categories(tx)? {
+        tx.execute_batch(&format!(
+            "DROP VIEW IF EXISTS tag_browser_{table};
+            CREATE VIEW tag_browser_{table} AS SELECT
+                id,
+                {column},
+                (SELECT COUNT(id) FROM books_{table}_link WHERE {link_column}={table}.id) count,
+                (SELECT AVG(ratings.rating)
+                 FROM books_{table}_link AS tl, books_ratings_link AS bl, ratings
+                 WHERE tl.{link_column}={table}.id AND bl.book=tl.book AND
+                 ratings.id = bl.rating AND ratings.rating <> 0) avg_rating,
+                 {sort} AS sort
+            FROM {table};
+            DROP VIEW IF EXISTS tag_browser_filtered_{table};
+            CREATE VIEW tag_browser_filtered_{table} AS SELECT
+                id,
+                {column},
+                (SELECT COUNT(books_{table}_link.id) FROM books_{table}_link WHERE
+                    {link_column}={table}.id AND books_list_filter(book)) count,
+                (SELECT AVG(ratings.rating)
+                 FROM books_{table}_link AS tl, books_ratings_link AS bl, ratings
+                 WHERE tl.{link_column}={table}.id AND bl.book=tl.book AND
+                 ratings.id = bl.rating AND ratings.rating <> 0 AND
+                 books_list_filter(bl.book)) avg_rating,
+                 {sort} AS sort
+            FROM {table};"
+        ))?;
+    }
+
+    let tables: Vec<String> = tx
+        .prepare("SELECT name FROM sqlite_master WHERE type='table' ORDER BY name")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    for table in tables.iter().filter(|t| t.starts_with("custom_column_")) {
+        let link = format!("books_{}_link", table);
+        if !tables.contains(&link) {
+            continue;
+        }
+        tx.execute_batch(&format!(
+            "DROP VIEW IF EXISTS tag_browser_{table};
+            CREATE VIEW tag_browser_{table} AS SELECT
+                id,
+                value,
+                (SELECT COUNT(id) FROM {link} WHERE value={table}.id) count,
+                (SELECT AVG(r.rating)
+                 FROM {link}, books_ratings_link AS bl, ratings AS r
+                 WHERE {link}.value={table}.id AND bl.book={link}.book AND
+                       r.id = bl.rating AND r.rating <> 0) avg_rating,
+                 value AS sort
+            FROM {table};
+            DROP VIEW IF EXISTS tag_browser_filtered_{table};
+            CREATE VIEW tag_browser_filtered_{table} AS SELECT
+                id,
+                value,
+                (SELECT COUNT({link}.id) FROM {link} WHERE value={table}.id AND
+                books_list_filter(book)) count,
+                (SELECT AVG(r.rating)
+                 FROM {link}, books_ratings_link AS bl, ratings AS r
+                 WHERE {link}.value={table}.id AND bl.book={link}.book AND
+                       r.id = bl.rating AND r.rating <> 0 AND
+                       books_list_filter(bl.book)) avg_rating,
+                 value AS sort
+            FROM {table};"
+        ))?;
+    }
+
+    tx.execute_batch("UPDATE authors SET sort=author_to_author_sort(name)")
+}
+
+fn upgrade_version_12(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "DROP TABLE IF EXISTS preferences;
+        CREATE TABLE preferences(id INTEGER PRIMARY KEY,
+                                 key TEXT NOT NULL,
+                                 val TEXT NOT NULL,
+                                 UNIQUE(key));",
+    )
+}
+
+fn upgrade_version_13(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "DROP TABLE IF EXISTS metadata_dirtied;
+        CREATE TABLE metadata_dirtied(id INTEGER PRIMARY KEY,
+                             book INTEGER NOT NULL,
+                             UNIQUE(book));
+        INSERT INTO metadata_dirtied (book) SELECT id FROM books;",
+    )
+}
+
+fn upgrade_version_14(tx: &Transaction, library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE books ADD COLUMN has_cover BOOL DEFAULT 0")?;
+    let books: Vec<(i32, Option<String>)> = tx
+        .prepare("SELECT id, path FROM books")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    for (id, path) in books {
+        let has_cover = path
+            .filter(|p| !p.is_empty())
+            .is_some_and(|p| library_path.join(p).join("cover.jpg").exists());
+        if has_cover {
+            tx.execute("UPDATE books SET has_cover=1 WHERE id=?1", [id])?;
+        }
+    }
+    Ok(())
+}
+
+fn upgrade_version_15(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "UPDATE OR IGNORE tags SET name=REPLACE(name, ',', ';');
+        UPDATE OR IGNORE tags SET name=REPLACE(name, ',', ';;');
+        UPDATE OR IGNORE tags SET name=REPLACE(name, ',', '');",
+    )
+}
+
+fn upgrade_version_16(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "DROP TRIGGER IF EXISTS books_update_trg;
+        CREATE TRIGGER books_update_trg
+            AFTER UPDATE ON books
+            BEGIN
+            UPDATE books SET sort=title_sort(NEW.title)
+                         WHERE id=NEW.id AND OLD.title <> NEW.title;
+            END;",
+    )
+}
+
+fn upgrade_version_17(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "DROP TABLE IF EXISTS books_plugin_data;
+        CREATE TABLE books_plugin_data(id INTEGER PRIMARY KEY,
+                                     book INTEGER NOT NULL,
+                                     name TEXT NOT NULL,
+                                     val TEXT NOT NULL,
+                                     UNIQUE(book,name));",
+    )?;
+    tx.execute_batch(&books_delete_trigger(&[
+        "books_authors_link",
+        "books_publishers_link",
+        "books_ratings_link",
+        "books_series_link",
+        "books_tags_link",
+        "data",
+        "comments",
+        "conversion_options",
+        "books_plugin_data",
+    ]))
+}
+
+fn upgrade_version_18(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"DROP TABLE IF EXISTS library_id;
+        CREATE TABLE library_id ( id   INTEGER PRIMARY KEY,
+                                  uuid TEXT NOT NULL,
+                                  UNIQUE(uuid)
+        );
+
+        DROP TABLE IF EXISTS identifiers;
+        CREATE TABLE identifiers  ( id     INTEGER PRIMARY KEY,
+                                    book   INTEGER NOT NULL,
+                                    type   TEXT NOT NULL DEFAULT "isbn" COLLATE NOCASE,
+                                    val    TEXT NOT NULL COLLATE NOCASE,
+                                    UNIQUE(book, type)
+        );
+
+        DROP TABLE IF EXISTS languages;
+        CREATE TABLE languages    ( id        INTEGER PRIMARY KEY,
+                                    lang_code TEXT NOT NULL COLLATE NOCASE,
+                                    UNIQUE(lang_code)
+        );
+
+        DROP TABLE IF EXISTS books_languages_link;
+        CREATE TABLE books_languages_link ( id INTEGER PRIMARY KEY,
+                                            book INTEGER NOT NULL,
+                                            lang_code INTEGER NOT NULL,
+                                            item_order INTEGER NOT NULL DEFAULT 0,
+                                            UNIQUE(book, lang_code)
+        );
+
+        DROP TRIGGER IF EXISTS fkc_delete_on_languages;
+        CREATE TRIGGER fkc_delete_on_languages
+        BEFORE DELETE ON languages
+        BEGIN
+            SELECT CASE
+                WHEN (SELECT COUNT(id) FROM books_languages_link WHERE lang_code=OLD.id) > 0
+                THEN RAISE(ABORT, 'Foreign key violation: language is still referenced')
+            END;
+        END;
+
+        DROP TRIGGER IF EXISTS fkc_delete_on_languages_link;
+        CREATE TRIGGER fkc_delete_on_languages_link
+        BEFORE INSERT ON books_languages_link
+        BEGIN
+          SELECT CASE
+              WHEN (SELECT id from books WHERE id=NEW.book) IS NULL
+              THEN RAISE(ABORT, 'Foreign key violation: book not in books')
+              WHEN (SELECT id from languages WHERE id=NEW.lang_code) IS NULL
+              THEN RAISE(ABORT, 'Foreign key violation: lang_code not in languages')
+          END;
+        END;
+
+        DROP TRIGGER IF EXISTS fkc_update_books_languages_link_a;
+        CREATE TRIGGER fkc_update_books_languages_link_a
+        BEFORE UPDATE OF book ON books_languages_link
+        BEGIN
+            SELECT CASE
+                WHEN (SELECT id from books WHERE id=NEW.book) IS NULL
+                THEN RAISE(ABORT, 'Foreign key violation: book not in books')
+            END;
+        END;
+        DROP TRIGGER IF EXISTS fkc_update_books_languages_link_b;
+        CREATE TRIGGER fkc_update_books_languages_link_b
+        BEFORE UPDATE OF lang_code ON books_languages_link
+        BEGIN
+            SELECT CASE
+                WHEN (SELECT id from languages WHERE id=NEW.lang_code) IS NULL
+                THEN RAISE(ABORT, 'Foreign key violation: lang_code not in languages')
+            END;
+        END;
+
+        DROP INDEX IF EXISTS books_languages_link_aidx;
+        CREATE INDEX books_languages_link_aidx ON books_languages_link (lang_code);
+        DROP INDEX IF EXISTS books_languages_link_bidx;
+        CREATE INDEX books_languages_link_bidx ON books_languages_link (book);
+        DROP INDEX IF EXISTS languages_idx;
+        CREATE INDEX languages_idx ON languages (lang_code COLLATE NOCASE);"#,
+    )?;
+    tx.execute_batch(&books_delete_trigger(&[
+        "books_authors_link",
+        "books_publishers_link",
+        "books_ratings_link",
+        "books_series_link",
+        "books_tags_link",
+        "books_languages_link",
+        "data",
+        "comments",
+        "conversion_options",
+        "books_plugin_data",
+        "identifiers",
+    ]))?;
+    // SQLite only takes constant defaults in ALTER TABLE
+    tx.execute_batch(&format!(
+        r#"INSERT INTO identifiers (book, val) SELECT id,isbn FROM books WHERE isbn;
+
+        ALTER TABLE books ADD COLUMN last_modified TIMESTAMP NOT NULL DEFAULT "{}";"#,
+        DEFAULT_DATE
+    ))
+}
+
+fn upgrade_version_19(_tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    // calibre moved the custom recipes of the feeds table into its
+    // configuration folder here. Nothing in this port reads recipes, so the
+    // table is left as it is for calibre to find.
+    Ok(())
+}
+
+fn upgrade_version_20(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(r#"ALTER TABLE authors ADD COLUMN link TEXT NOT NULL DEFAULT "";"#)
+}
+
+fn upgrade_version_21(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "DROP TRIGGER IF EXISTS series_insert_trg;
+        DROP TRIGGER IF EXISTS series_update_trg;
+
+        UPDATE series SET sort=title_sort(name);
+
+        CREATE TRIGGER series_insert_trg
+            AFTER INSERT ON series
+            BEGIN
+              UPDATE series SET sort=title_sort(NEW.name) WHERE id=NEW.id;
+            END;
+
+        CREATE TRIGGER series_update_trg
+            AFTER UPDATE ON series
+            BEGIN
+              UPDATE series SET sort=title_sort(NEW.name) WHERE id=NEW.id;
+            END;",
+    )
+}
+
+/// Refuse rows of `table` that point at a book that does not exist.
+fn book_reference_triggers(prefix: &str, table: &str) -> String {
+    format!(
+        "DROP TRIGGER IF EXISTS {prefix}_insert;
+        DROP TRIGGER IF EXISTS {prefix}_update;
+        CREATE TRIGGER {prefix}_insert
+            BEFORE INSERT ON {table}
+            BEGIN
+                SELECT CASE
+                    WHEN (SELECT id from books WHERE id=NEW.book) IS NULL
+                    THEN RAISE(ABORT, 'Foreign key violation: book not in books')
+                END;
+            END;
+        CREATE TRIGGER {prefix}_update
+            BEFORE UPDATE OF book ON {table}
+            BEGIN
+                SELECT CASE
+                    WHEN (SELECT id from books WHERE id=NEW.book) IS NULL
+                    THEN RAISE(ABORT, 'Foreign key violation: book not in books')
+                END;
+            END;"
+    )
+}
+
+fn upgrade_version_22(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "DROP TABLE IF EXISTS last_read_positions;
+        CREATE TABLE last_read_positions ( id INTEGER PRIMARY KEY,
+            book INTEGER NOT NULL,
+            format TEXT NOT NULL COLLATE NOCASE,
+            user TEXT NOT NULL,
+            device TEXT NOT NULL,
+            cfi TEXT NOT NULL,
+            epoch REAL NOT NULL,
+            pos_frac REAL NOT NULL DEFAULT 0,
+            UNIQUE(user, device, book, format)
+        );
+        DROP INDEX IF EXISTS lrp_idx;
+        CREATE INDEX lrp_idx ON last_read_positions (book);",
+    )?;
+    tx.execute_batch(&books_delete_trigger(&[
+        "books_authors_link",
+        "books_publishers_link",
+        "books_ratings_link",
+        "books_series_link",
+        "books_tags_link",
+        "books_languages_link",
+        "data",
+        "last_read_positions",
+        "comments",
+        "conversion_options",
+        "books_plugin_data",
+        "identifiers",
+    ]))?;
+    tx.execute_batch(&book_reference_triggers("fkc_lrp", "last_read_positions"))
+}
+
+fn upgrade_version_23(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"DROP TABLE IF EXISTS annotations_dirtied;
+        CREATE TABLE annotations_dirtied(id INTEGER PRIMARY KEY,
+                                     book INTEGER NOT NULL,
+                                     UNIQUE(book));
+        DROP TABLE IF EXISTS annotations;
+        CREATE TABLE annotations ( id INTEGER PRIMARY KEY,
+            book INTEGER NOT NULL,
+            format TEXT NOT NULL COLLATE NOCASE,
+            user_type TEXT NOT NULL,
+            user TEXT NOT NULL,
+            timestamp REAL NOT NULL,
+            annot_id TEXT NOT NULL,
+            annot_type TEXT NOT NULL,
+            annot_data TEXT NOT NULL,
+            searchable_text TEXT NOT NULL DEFAULT "",
+            UNIQUE(book, user_type, user, format, annot_type, annot_id)
+        );
+
+        DROP INDEX IF EXISTS annot_idx;
+        CREATE INDEX annot_idx ON annotations (book);
+
+        DROP TABLE IF EXISTS annotations_fts;
+        DROP TABLE IF EXISTS annotations_fts_stemmed;
+        CREATE VIRTUAL TABLE annotations_fts USING fts5(searchable_text,
+            content = 'annotations', content_rowid = 'id', tokenize = 'unicode61 remove_diacritics 2');
+        CREATE VIRTUAL TABLE annotations_fts_stemmed USING fts5(searchable_text,
+            content = 'annotations', content_rowid = 'id', tokenize = 'porter unicode61 remove_diacritics 2');
+
+        DROP TRIGGER IF EXISTS annotations_fts_insert_trg;
+        CREATE TRIGGER annotations_fts_insert_trg AFTER INSERT ON annotations
+        BEGIN
+            INSERT INTO annotations_fts(rowid, searchable_text) VALUES (NEW.id, NEW.searchable_text);
+            INSERT INTO annotations_fts_stemmed(rowid, searchable_text) VALUES (NEW.id, NEW.searchable_text);
+        END;
+
+        DROP TRIGGER IF EXISTS annotations_fts_delete_trg;
+        CREATE TRIGGER annotations_fts_delete_trg AFTER DELETE ON annotations
+        BEGIN
+            INSERT INTO annotations_fts(annotations_fts, rowid, searchable_text) VALUES('delete', OLD.id, OLD.searchable_text);
+            INSERT INTO annotations_fts_stemmed(annotations_fts_stemmed, rowid, searchable_text) VALUES('delete', OLD.id, OLD.searchable_text);
+        END;
+
+        DROP TRIGGER IF EXISTS annotations_fts_update_trg;
+        CREATE TRIGGER annotations_fts_update_trg AFTER UPDATE ON annotations
+        BEGIN
+            INSERT INTO annotations_fts(annotations_fts, rowid, searchable_text) VALUES('delete', OLD.id, OLD.searchable_text);
+            INSERT INTO annotations_fts(rowid, searchable_text) VALUES (NEW.id, NEW.searchable_text);
+            INSERT INTO annotations_fts_stemmed(annotations_fts_stemmed, rowid, searchable_text) VALUES('delete', OLD.id, OLD.searchable_text);
+            INSERT INTO annotations_fts_stemmed(rowid, searchable_text) VALUES (NEW.id, NEW.searchable_text);
+        END;"#,
+    )?;
+    tx.execute_batch(&books_delete_trigger(&[
+        "books_authors_link",
+        "books_publishers_link",
+        "books_ratings_link",
+        "books_series_link",
+        "books_tags_link",
+        "books_languages_link",
+        "data",
+        "last_read_positions",
+        "annotations",
+        "comments",
+        "conversion_options",
+        "books_plugin_data",
+        "identifiers",
+    ]))?;
+    tx.execute_batch(&book_reference_triggers("fkc_annot", "annotations"))
+}
+
+fn upgrade_version_24(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "INSERT INTO annotations_fts(annotations_fts) VALUES('rebuild');
+        INSERT INTO annotations_fts_stemmed(annotations_fts_stemmed) VALUES('rebuild');",
+    )
+}
+
+fn upgrade_version_25(tx: &Transaction, _library_path: &Path) -> rusqlite::Result<()> {
+    let normalized: Vec<i32> = tx
+        .prepare("SELECT id FROM custom_columns WHERE normalized")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut tables: Vec<String> = normalized
+        .into_iter()
+        .map(|id| format!("custom_column_{}", id))
+        .collect();
+    // Languages and ratings have no links to edit, the column keeps the
+    // categories uniform
+    tables.extend(
+        ["publishers", "series", "tags", "languages", "ratings"]
+            .iter()
+            .map(|t| t.to_string()),
+    );
+    for table in tables {
+        tx.execute_batch(&format!(
+            "ALTER TABLE {} ADD COLUMN link TEXT NOT NULL DEFAULT '';",
+            table
+        ))?;
+    }
+    Ok(())
 }
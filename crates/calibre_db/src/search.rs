@@ -1,35 +1,660 @@
+//! Evaluation of calibre search expressions against the library database.
+//!
+//! The query is parsed by `calibre_utils::search_query_parser` and the tree
+//! is matched against field values read from SQLite. Tables a minimal
+//! library lacks (tags, ratings, ...) simply have no values.
+
 use crate::cache::Cache;
+use crate::library::parse_db_date;
+use anyhow::{bail, Context, Result};
+use calibre_utils::search_query_parser::{Parser, SearchNode};
+use chrono::{DateTime, Datelike, Duration, Utc};
+use regex::{Regex, RegexBuilder};
+use rusqlite::types::FromSql;
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::{BTreeSet, HashMap};
 use std::sync::{Arc, Mutex};
 
-/// A simplified search function that mimics `calibre.db.search.SearchQueryParser`.
-/// currently only supports basic case-insensitive substring matching on the 'title' field.
-pub fn search(cache: &Arc<Mutex<Cache>>, query: &str) -> anyhow::Result<Vec<i32>> {
-    let query_lower = query.to_lowercase();
-    let mut matched_ids = Vec::new();
+/// Fields an unqualified search term is matched against.
+const ALL_LOCATIONS: [&str; 8] = [
+    "title",
+    "authors",
+    "tags",
+    "series",
+    "publisher",
+    "comments",
+    "formats",
+    "languages",
+];
 
-    // Locking the cache/backend to iterate.
-    // In a real implementation we might want to iterate without holding the lock for the whole duration
-    // or use a snapshot. For now, we lock.
-    let cache_guard = cache.lock().unwrap();
+const BUILTIN_LOCATIONS: [&str; 20] = [
+    "all",
+    "author_sort",
+    "authors",
+    "comments",
+    "cover",
+    "date",
+    "formats",
+    "id",
+    "identifiers",
+    "isbn",
+    "languages",
+    "pubdate",
+    "publisher",
+    "rating",
+    "series",
+    "series_index",
+    "size",
+    "tags",
+    "title",
+    "uuid",
+];
 
-    // We need a way to iterate over all books.
-    // Backend doesn't expose iteration yet, so we probably need to fetch all IDs first.
-    // For this Sprint, let's assume valid IDs are 1..100 or implement `get_all_ids` in backend.
+/// Saved searches and virtual libraries can refer to each other, but not
+/// endlessly.
+const MAX_NESTING: usize = 20;
 
-    // Stub: Let's assume we can get all IDs. We need to add `all_book_ids` to Cache/Backend.
-    // For now, let's query the DB for all IDs.
+/// Values of one field for every book that has it.
+pub(crate) enum Values {
+    Text(HashMap<i32, Vec<String>>),
+    Identifiers(HashMap<i32, Vec<(String, String)>>),
+    /// Ratings are stored as half stars, searched as stars.
+    Number {
+        values: HashMap<i32, f64>,
+        rating: bool,
+    },
+    Date(HashMap<i32, DateTime<Utc>>),
+    Bool(HashMap<i32, bool>),
+}
 
-    // Use SQL LIKE for basic title matching
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Relop {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
 
-    // Alternative: Use SQL LIKE.
-    let conn = cache_guard.backend.conn.lock().unwrap();
-    let mut stmt = conn.prepare("SELECT id FROM books WHERE lower(title) LIKE ?")?;
-    let like_query = format!("%{}%", query_lower);
-    let rows = stmt.query_map([&like_query], |row| row.get(0))?;
+impl Relop {
+    fn split(query: &str) -> (Relop, &str) {
+        for (prefix, op) in [
+            (">=", Relop::Ge),
+            ("<=", Relop::Le),
+            ("!=", Relop::Ne),
+            ("=", Relop::Eq),
+            (">", Relop::Gt),
+            ("<", Relop::Lt),
+        ] {
+            if let Some(rest) = query.strip_prefix(prefix) {
+                return (op, rest.trim());
+            }
+        }
+        (Relop::Eq, query.trim())
+    }
+
+    fn compare<T: PartialOrd>(self, value: T, query: T) -> bool {
+        match self {
+            Relop::Eq => value == query,
+            Relop::Ne => value != query,
+            Relop::Gt => value > query,
+            Relop::Ge => value >= query,
+            Relop::Lt => value < query,
+            Relop::Le => value <= query,
+        }
+    }
+}
+
+/// How a text value is compared: `=` exact, `~` regular expression,
+/// otherwise a case-insensitive substring.
+enum Matcher {
+    Contains(String),
+    Equals(String),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn new(query: &str) -> Result<Self> {
+        Ok(if let Some(q) = query.strip_prefix('=') {
+            Matcher::Equals(q.to_lowercase())
+        } else if let Some(q) = query.strip_prefix('~') {
+            Matcher::Regex(
+                RegexBuilder::new(q)
+                    .case_insensitive(true)
+                    .build()
+                    .with_context(|| format!("Invalid regular expression: {}", q))?,
+            )
+        } else {
+            Matcher::Contains(query.to_lowercase())
+        })
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Matcher::Contains(q) => value.to_lowercase().contains(q.as_str()),
+            Matcher::Equals(q) => value.to_lowercase() == *q,
+            Matcher::Regex(re) => re.is_match(value),
+        }
+    }
+}
+
+/// A custom column as described in the `custom_columns` table.
+struct CustomColumn {
+    id: i32,
+    datatype: String,
+    normalized: bool,
+}
+
+/// Runs search expressions against one library connection. Field values
+/// are loaded on first use and kept for later searches.
+pub struct Search<'a> {
+    conn: &'a Connection,
+    saved_searches: HashMap<String, String>,
+    virtual_libraries: HashMap<String, String>,
+    custom_columns: HashMap<String, CustomColumn>,
+    values: HashMap<String, Values>,
+    marked_ids: HashMap<i32, String>,
+    nesting: Vec<String>,
+}
+
+impl<'a> Search<'a> {
+    pub fn new(conn: &'a Connection) -> Self {
+        let pref = |key: &str| -> HashMap<String, String> {
+            conn.query_row("SELECT val FROM preferences WHERE key = ?1", [key], |row| {
+                row.get::<_, String>(0)
+            })
+            .optional()
+            .ok()
+            .flatten()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+        };
+
+        let mut custom_columns = HashMap::new();
+        if let Ok(mut stmt) =
+            conn.prepare("SELECT label, id, datatype, normalized FROM custom_columns")
+        {
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    CustomColumn {
+                        id: row.get(1)?,
+                        datatype: row.get(2)?,
+                        normalized: row.get::<_, Option<bool>>(3)?.unwrap_or(false),
+                    },
+                ))
+            });
+            if let Ok(rows) = rows {
+                custom_columns.extend(
+                    rows.flatten()
+                        .map(|(label, col)| (format!("#{}", label.to_lowercase()), col)),
+                );
+            }
+        }
+
+        Search {
+            conn,
+            saved_searches: pref("saved_searches"),
+            virtual_libraries: pref("virtual_libraries"),
+            custom_columns,
+            values: HashMap::new(),
+            marked_ids: HashMap::new(),
+            nesting: Vec::new(),
+        }
+    }
+
+    /// Books found by `marked:`, each with its label (`true` if unlabelled).
+    pub fn set_marked_ids(&mut self, marked_ids: HashMap<i32, String>) {
+        self.marked_ids = marked_ids;
+        self.values.remove("marked");
+    }
+
+    /// Every location a query can name, custom columns as `#label`.
+    pub fn locations(&self) -> Vec<String> {
+        let mut locations: Vec<String> = BUILTIN_LOCATIONS
+            .iter()
+            .chain(&["search", "vl", "marked", "timestamp", "sort", "title_sort"])
+            .map(|l| l.to_string())
+            .collect();
+        locations.extend(self.custom_columns.keys().cloned());
+        locations
+    }
+
+    /// Ids of the books matching `query`, in id order. With a virtual
+    /// library only its books are considered. An empty query matches all.
+    pub fn search(&mut self, query: &str, virtual_library: Option<&str>) -> Result<Vec<i32>> {
+        let mut candidates = self.all_book_ids()?;
+        if let Some(vl) = virtual_library {
+            candidates = self.expand("vl", vl, &candidates)?;
+        }
+        if !query.trim().is_empty() {
+            let tree = self.parse(query)?;
+            candidates = self.evaluate(&tree, &candidates)?;
+        }
+        Ok(candidates.into_iter().collect())
+    }
+
+    fn parse(&self, query: &str) -> Result<SearchNode> {
+        Parser::new(self.locations())
+            .parse(query)
+            .with_context(|| format!("Failed to parse search: {}", query))
+    }
+
+    fn all_book_ids(&self) -> Result<BTreeSet<i32>> {
+        let mut stmt = self.conn.prepare("SELECT id FROM books")?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(ids)
+    }
+
+    /// Only books in `candidates` are tested, so `and` narrows the set the
+    /// right hand side looks at and `or` skips the books already matched.
+    fn evaluate(&mut self, node: &SearchNode, candidates: &BTreeSet<i32>) -> Result<BTreeSet<i32>> {
+        Ok(match node {
+            SearchNode::And(lhs, rhs) => {
+                let matched = self.evaluate(lhs, candidates)?;
+                self.evaluate(rhs, &matched)?
+            }
+            SearchNode::Or(lhs, rhs) => {
+                let mut matched = self.evaluate(lhs, candidates)?;
+                let rest = candidates.difference(&matched).copied().collect();
+                matched.extend(self.evaluate(rhs, &rest)?);
+                matched
+            }
+            SearchNode::Not(inner) => {
+                let matched = self.evaluate(inner, candidates)?;
+                candidates.difference(&matched).copied().collect()
+            }
+            SearchNode::Token { location, query } => {
+                self.match_token(location, query, candidates)?
+            }
+        })
+    }
 
-    for r in rows {
-        matched_ids.push(r?);
+    fn match_token(
+        &mut self,
+        location: &str,
+        query: &str,
+        candidates: &BTreeSet<i32>,
+    ) -> Result<BTreeSet<i32>> {
+        match location {
+            "search" | "vl" => self.expand(location, query, candidates),
+            "all" => {
+                let mut matched = BTreeSet::new();
+                for location in ALL_LOCATIONS {
+                    let rest = candidates.difference(&matched).copied().collect();
+                    matched.extend(self.match_location(location, query, &rest)?);
+                }
+                Ok(matched)
+            }
+            "isbn" => self.match_location("identifiers", &format!("isbn:{}", query), candidates),
+            _ => self.match_location(location, query, candidates),
+        }
     }
 
-    Ok(matched_ids)
+    /// Evaluate a saved search or virtual library by name.
+    fn expand(
+        &mut self,
+        kind: &str,
+        name: &str,
+        candidates: &BTreeSet<i32>,
+    ) -> Result<BTreeSet<i32>> {
+        let definitions = if kind == "vl" {
+            &self.virtual_libraries
+        } else {
+            &self.saved_searches
+        };
+        let what = if kind == "vl" {
+            "virtual library"
+        } else {
+            "saved search"
+        };
+        let name = name.trim();
+        let query = definitions
+            .get(name)
+            .or_else(|| {
+                definitions
+                    .iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                    .map(|(_, v)| v)
+            })
+            .cloned()
+            .with_context(|| format!("Unknown {}: {}", what, name))?;
+
+        let key = format!("{}:{}", kind, name.to_lowercase());
+        if self.nesting.contains(&key) || self.nesting.len() >= MAX_NESTING {
+            bail!("The {} {} refers to itself", what, name);
+        }
+        self.nesting.push(key);
+        let result = if query.trim().is_empty() {
+            Ok(candidates.clone())
+        } else {
+            self.parse(&query)
+                .and_then(|tree| self.evaluate(&tree, candidates))
+        };
+        self.nesting.pop();
+        result
+    }
+
+    fn match_location(
+        &mut self,
+        location: &str,
+        query: &str,
+        candidates: &BTreeSet<i32>,
+    ) -> Result<BTreeSet<i32>> {
+        let values = self.values(location)?;
+        let query = query.trim();
+        let lower = query.to_lowercase();
+        let filter = |test: &dyn Fn(i32) -> bool| -> BTreeSet<i32> {
+            candidates.iter().copied().filter(|id| test(*id)).collect()
+        };
+
+        Ok(match values {
+            Values::Text(values) => {
+                if lower == "true" || lower == "false" {
+                    let want = lower == "true";
+                    filter(&|id| values.contains_key(&id) == want)
+                } else {
+                    let matcher = Matcher::new(query)?;
+                    filter(&|id| {
+                        values
+                            .get(&id)
+                            .is_some_and(|vals| vals.iter().any(|v| matcher.matches(v)))
+                    })
+                }
+            }
+            Values::Identifiers(values) => match_identifiers(values, query, candidates)?,
+            Values::Number { values, rating } => {
+                if lower == "true" || lower == "false" {
+                    let want = lower == "true";
+                    filter(&|id| values.contains_key(&id) == want)
+                } else {
+                    let (op, number) = Relop::split(&lower);
+                    let mut number =
+                        parse_number(number).with_context(|| format!("Not a number: {}", query))?;
+                    if *rating {
+                        number *= 2.0;
+                    }
+                    filter(&|id| values.get(&id).is_some_and(|v| op.compare(*v, number)))
+                }
+            }
+            Values::Date(values) => {
+                if lower == "true" || lower == "false" {
+                    let want = lower == "true";
+                    filter(&|id| values.contains_key(&id) == want)
+                } else {
+                    let (op, date) = Relop::split(&lower);
+                    let (key, precision) =
+                        parse_date(date).with_context(|| format!("Not a date: {}", query))?;
+                    filter(&|id| {
+                        values
+                            .get(&id)
+                            .is_some_and(|v| op.compare(date_key(v, precision), key))
+                    })
+                }
+            }
+            Values::Bool(values) => match lower.as_str() {
+                "true" | "yes" => filter(&|id| values.get(&id) == Some(&true)),
+                "false" | "no" => filter(&|id| values.get(&id) == Some(&false)),
+                "empty" | "blank" => filter(&|id| !values.contains_key(&id)),
+                _ => bail!("Invalid boolean search: {}", query),
+            },
+        })
+    }
+
+    /// Values of a location for every book, loaded on first use.
+    pub(crate) fn values(&mut self, location: &str) -> Result<&Values> {
+        if !self.values.contains_key(location) {
+            let values = self.load(location)?;
+            self.values.insert(location.to_string(), values);
+        }
+        Ok(&self.values[location])
+    }
+
+    fn load(&self, location: &str) -> Result<Values> {
+        let text = |sql: &str| Values::Text(self.grouped(sql));
+        let number = |sql: &str, rating: bool| Values::Number {
+            values: self
+                .rows::<f64>(sql)
+                .into_iter()
+                .filter(|(_, v)| !rating || *v > 0.0)
+                .collect(),
+            rating,
+        };
+        let date = |sql: &str| {
+            Values::Date(
+                self.rows::<String>(sql)
+                    .into_iter()
+                    .filter_map(|(id, v)| Some((id, parse_db_date(&v)?)))
+                    .collect(),
+            )
+        };
+
+        Ok(match location {
+            "title" => text("SELECT id, title FROM books"),
+            "sort" | "title_sort" => text("SELECT id, sort FROM books"),
+            "author_sort" => text("SELECT id, author_sort FROM books"),
+            "uuid" => text("SELECT id, uuid FROM books"),
+            "authors" => text(
+                "SELECT l.book, a.name FROM books_authors_link l JOIN authors a ON a.id = l.author",
+            ),
+            "tags" => text("SELECT l.book, t.name FROM books_tags_link l JOIN tags t ON t.id = l.tag"),
+            "series" => text(
+                "SELECT l.book, s.name FROM books_series_link l JOIN series s ON s.id = l.series",
+            ),
+            "publisher" => text(
+                "SELECT l.book, p.name FROM books_publishers_link l
+                 JOIN publishers p ON p.id = l.publisher",
+            ),
+            "languages" => text(
+                "SELECT l.book, g.lang_code FROM books_languages_link l
+                 JOIN languages g ON g.id = l.lang_code",
+            ),
+            "comments" => text("SELECT book, text FROM comments"),
+            "formats" => text("SELECT book, format FROM data"),
+            "identifiers" => {
+                let mut values: HashMap<i32, Vec<(String, String)>> = HashMap::new();
+                if let Ok(mut stmt) = self.conn.prepare("SELECT book, type, val FROM identifiers")
+                {
+                    if let Ok(rows) = stmt.query_map([], |row| {
+                        Ok((row.get::<_, i32>(0)?, row.get(1)?, row.get(2)?))
+                    }) {
+                        for (id, key, val) in rows.flatten() {
+                            values.entry(id).or_default().push((key, val));
+                        }
+                    }
+                }
+                // Older libraries keep the ISBN on the book row
+                for (id, isbn) in self.rows::<String>("SELECT id, isbn FROM books") {
+                    let ids = values.entry(id).or_default();
+                    if !isbn.is_empty() && !ids.iter().any(|(k, _)| k == "isbn") {
+                        ids.push(("isbn".to_string(), isbn));
+                    }
+                }
+                values.retain(|_, ids| !ids.is_empty());
+                Values::Identifiers(values)
+            }
+            "rating" => number(
+                "SELECT l.book, r.rating FROM books_ratings_link l JOIN ratings r ON r.id = l.rating",
+                true,
+            ),
+            "series_index" => number("SELECT id, series_index FROM books", false),
+            "id" => number("SELECT id, id FROM books", false),
+            "size" => number(
+                "SELECT book, MAX(uncompressed_size) FROM data GROUP BY book",
+                false,
+            ),
+            "pubdate" => date("SELECT id, pubdate FROM books"),
+            "date" | "timestamp" => date("SELECT id, timestamp FROM books"),
+            "marked" => Values::Text(
+                self.marked_ids
+                    .iter()
+                    .map(|(id, label)| (*id, vec![label.clone()]))
+                    .collect(),
+            ),
+            "cover" => Values::Bool(
+                self.rows::<bool>("SELECT id, has_cover FROM books")
+                    .into_iter()
+                    .collect(),
+            ),
+            _ => {
+                let col = self
+                    .custom_columns
+                    .get(location)
+                    .with_context(|| format!("Unknown search location: {}", location))?;
+                let sql = if col.normalized {
+                    format!(
+                        "SELECT l.book, c.value FROM books_custom_column_{0}_link l
+                         JOIN custom_column_{0} c ON c.id = l.value",
+                        col.id
+                    )
+                } else {
+                    format!("SELECT book, value FROM custom_column_{}", col.id)
+                };
+                match col.datatype.as_str() {
+                    "int" | "float" => number(&sql, false),
+                    "rating" => number(&sql, true),
+                    "datetime" => date(&sql),
+                    "bool" => Values::Bool(self.rows::<bool>(&sql).into_iter().collect()),
+                    _ => text(&sql),
+                }
+            }
+        })
+    }
+
+    /// `(book, value)` pairs of a two column query, skipping NULLs. A
+    /// missing table gives no rows.
+    fn rows<T: FromSql>(&self, sql: &str) -> Vec<(i32, T)> {
+        let Ok(mut stmt) = self.conn.prepare(sql) else {
+            return Vec::new();
+        };
+        stmt.query_map([], |row| {
+            Ok((row.get::<_, i32>(0)?, row.get::<_, Option<T>>(1)?))
+        })
+        .map(|rows| {
+            rows.flatten()
+                .filter_map(|(id, v)| Some((id, v?)))
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    fn grouped(&self, sql: &str) -> HashMap<i32, Vec<String>> {
+        let mut values: HashMap<i32, Vec<String>> = HashMap::new();
+        for (id, v) in self.rows::<String>(sql) {
+            if !v.is_empty() {
+                values.entry(id).or_default().push(v);
+            }
+        }
+        values
+    }
+}
+
+/// `identifiers:isbn:123` matches the value of one type, `identifiers:isbn:`
+/// any book that has one, and `identifiers:isbn` the type names alone.
+fn match_identifiers(
+    values: &HashMap<i32, Vec<(String, String)>>,
+    query: &str,
+    candidates: &BTreeSet<i32>,
+) -> Result<BTreeSet<i32>> {
+    let lower = query.to_lowercase();
+    if lower == "true" || lower == "false" {
+        let want = lower == "true";
+        return Ok(candidates
+            .iter()
+            .copied()
+            .filter(|id| values.contains_key(id) == want)
+            .collect());
+    }
+
+    // The match mode prefixes the whole query but applies to both halves
+    let (mode, body) = match query.chars().next() {
+        Some(c @ ('=' | '~')) => (c.to_string(), &query[1..]),
+        _ => (String::new(), query),
+    };
+    let (key, value) = match body.split_once(':') {
+        Some((key, value)) => (key, Some(value)),
+        None => (body, None),
+    };
+    let key_matcher = Matcher::new(&format!("{}{}", mode, key))?;
+    let value_lower = value.map(str::to_lowercase);
+    let value_matcher = match value_lower.as_deref() {
+        None | Some("") | Some("true") | Some("false") => None,
+        Some(_) => Some(Matcher::new(&format!("{}{}", mode, value.unwrap_or("")))?),
+    };
+    let want = value_lower.as_deref() != Some("false");
+
+    Ok(candidates
+        .iter()
+        .copied()
+        .filter(|id| {
+            let found = values.get(id).is_some_and(|ids| {
+                ids.iter().any(|(k, v)| {
+                    key_matcher.matches(k) && value_matcher.as_ref().is_none_or(|m| m.matches(v))
+                })
+            });
+            found == want
+        })
+        .collect())
+}
+
+/// Numbers may carry a size suffix: `size:>1.5m`.
+fn parse_number(query: &str) -> Option<f64> {
+    let (digits, scale) = match query.chars().last()? {
+        'k' => (&query[..query.len() - 1], 1024.0),
+        'm' => (&query[..query.len() - 1], 1024.0 * 1024.0),
+        'g' => (&query[..query.len() - 1], 1024.0 * 1024.0 * 1024.0),
+        _ => (query, 1.0),
+    };
+    digits.trim().parse::<f64>().ok().map(|n| n * scale)
+}
+
+/// A date query as (year, month, day) and how many of those parts it
+/// names. Book dates are cut to the same precision before comparing, so
+/// `pubdate:2000` covers the whole year.
+fn parse_date(query: &str) -> Option<((i32, u32, u32), usize)> {
+    let today = Utc::now();
+    let days_ago = |n: i64| {
+        let d = today - Duration::days(n);
+        Some(((d.year(), d.month(), d.day()), 3))
+    };
+    match query {
+        "today" => return days_ago(0),
+        "yesterday" => return days_ago(1),
+        "thismonth" => return Some(((today.year(), today.month(), 0), 2)),
+        _ => {}
+    }
+    if let Some(n) = query.strip_suffix("daysago") {
+        return days_ago(n.trim().parse().ok()?);
+    }
+
+    let parts: Vec<&str> = query.split('-').collect();
+    if parts.is_empty() || parts.len() > 3 {
+        return None;
+    }
+    let year = parts[0].parse().ok()?;
+    let month = parts.get(1).map_or(Some(0), |m| m.parse().ok())?;
+    let day = parts.get(2).map_or(Some(0), |d| d.parse().ok())?;
+    if parts.len() > 1 && !(1..=12).contains(&month) || parts.len() > 2 && !(1..=31).contains(&day)
+    {
+        return None;
+    }
+    Some(((year, month, day), parts.len()))
+}
+
+fn date_key(date: &DateTime<Utc>, precision: usize) -> (i32, u32, u32) {
+    match precision {
+        1 => (date.year(), 0, 0),
+        2 => (date.year(), date.month(), 0),
+        _ => (date.year(), date.month(), date.day()),
+    }
+}
+
+/// Search the books of a cache's database, see `Search::search`.
+pub fn search(cache: &Arc<Mutex<Cache>>, query: &str) -> Result<Vec<i32>> {
+    let cache_guard = cache.lock().unwrap();
+    let conn = cache_guard.backend.conn.lock().unwrap();
+    Search::new(&conn).search(query, None)
 }
@@ -0,0 +1,273 @@
+//! The trash of a library. Removed books and removed formats are kept in
+//! `.caltrash` with their metadata.opf, laid out as in calibre: the folder
+//! of a removed book goes to `b/<book_id>`, the formats removed from a book
+//! still in the library go to `f/<book_id>`, one file per format named
+//! after it. Entries are expired by the modification time of their folder.
+
+use crate::constants::{TrashEntry, COVER_FILE_NAME, METADATA_FILE_NAME, TRASH_DIR_NAME};
+use calibre_ebooks::opf::parse_opf;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const BOOKS_DIR: &str = "b";
+const FORMATS_DIR: &str = "f";
+
+/// Written by calibre next to trashed formats, never a format itself.
+const METADATA_JSON_NAME: &str = "metadata.json";
+
+pub struct Trash {
+    root: PathBuf,
+}
+
+impl Trash {
+    pub fn new(library_path: &Path) -> Self {
+        Trash {
+            root: library_path.join(TRASH_DIR_NAME),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where the folder of the removed book `book_id` is kept.
+    pub fn book_dir(&self, book_id: i32) -> PathBuf {
+        self.root.join(BOOKS_DIR).join(book_id.to_string())
+    }
+
+    /// Where the formats removed from the book `book_id` are kept.
+    pub fn formats_dir(&self, book_id: i32) -> PathBuf {
+        self.root.join(FORMATS_DIR).join(book_id.to_string())
+    }
+
+    pub fn format_path(&self, book_id: i32, fmt: &str) -> PathBuf {
+        self.formats_dir(book_id).join(fmt.to_lowercase())
+    }
+
+    /// Move the contents of `book_dir` to the trash, replacing any earlier
+    /// entry for the same book, and remove `book_dir`. `opf` is written as
+    /// the metadata of the entry.
+    pub fn add_book(&self, book_id: i32, book_dir: &Path, opf: &str) -> io::Result<()> {
+        let dest = self.book_dir(book_id);
+        if dest.exists() {
+            fs::remove_dir_all(&dest)?;
+        }
+        if book_dir.is_dir() {
+            move_tree(book_dir, &dest)?;
+        } else {
+            fs::create_dir_all(&dest)?;
+        }
+        fs::write(dest.join(METADATA_FILE_NAME), opf)
+    }
+
+    /// Move the file of one format of a book to the trash, replacing the
+    /// same format trashed earlier.
+    pub fn add_format(&self, book_id: i32, fmt: &str, path: &Path, opf: &str) -> io::Result<()> {
+        let dir = self.formats_dir(book_id);
+        fs::create_dir_all(&dir)?;
+        let dest = self.format_path(book_id, fmt);
+        if dest.exists() {
+            fs::remove_file(&dest)?;
+        }
+        move_file(path, &dest)?;
+        fs::write(dir.join(METADATA_FILE_NAME), opf)
+    }
+
+    /// Forget the removed book `book_id`.
+    pub fn remove_book(&self, book_id: i32) -> io::Result<()> {
+        let dir = self.book_dir(book_id);
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+        Ok(())
+    }
+
+    /// Forget one removed format, and the entry of the book once it has no
+    /// formats left.
+    pub fn remove_format(&self, book_id: i32, fmt: &str) -> io::Result<()> {
+        let path = self.format_path(book_id, fmt);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        let dir = self.formats_dir(book_id);
+        if dir.is_dir() && format_names(&dir)?.is_empty() {
+            fs::remove_dir_all(dir)?;
+        }
+        Ok(())
+    }
+
+    /// The removed books and the removed formats, newest first. Entries
+    /// that cannot be read are skipped.
+    pub fn entries(&self) -> io::Result<(Vec<TrashEntry>, Vec<TrashEntry>)> {
+        let mut books = Vec::new();
+        for (book_id, dir, mtime) in self.entry_dirs(BOOKS_DIR)? {
+            let (title, author) = read_opf(&dir);
+            let formats = book_formats(&dir)?;
+            books.push(TrashEntry {
+                book_id,
+                title,
+                author,
+                cover_path: dir.join(COVER_FILE_NAME).to_string_lossy().into_owned(),
+                mtime,
+                formats,
+            });
+        }
+
+        let mut files = Vec::new();
+        for (book_id, dir, mtime) in self.entry_dirs(FORMATS_DIR)? {
+            let formats = format_names(&dir)?;
+            if formats.is_empty() {
+                continue;
+            }
+            let (title, author) = read_opf(&dir);
+            files.push(TrashEntry {
+                book_id,
+                title,
+                author,
+                cover_path: String::new(),
+                mtime,
+                formats,
+            });
+        }
+
+        for entries in [&mut books, &mut files] {
+            entries.sort_by(|a, b| b.mtime.total_cmp(&a.mtime));
+        }
+        Ok((books, files))
+    }
+
+    /// Remove the entries older than `max_age`, every entry for a zero
+    /// age. Returns how many entries were removed.
+    pub fn expire(&self, max_age: Duration) -> io::Result<usize> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let mut removed = 0;
+        for base in [BOOKS_DIR, FORMATS_DIR] {
+            for (_, dir, mtime) in self.entry_dirs(base)? {
+                if max_age.is_zero() || mtime + max_age.as_secs_f64() <= now {
+                    fs::remove_dir_all(dir)?;
+                    removed += 1;
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Remove everything in the trash.
+    pub fn empty(&self) -> io::Result<usize> {
+        self.expire(Duration::ZERO)
+    }
+
+    /// The `(book_id, folder, mtime)` of the entries under `base`, folders
+    /// not named after a book id are ignored.
+    fn entry_dirs(&self, base: &str) -> io::Result<Vec<(i32, PathBuf, f64)>> {
+        let base = self.root.join(base);
+        if !base.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut dirs = Vec::new();
+        for entry in fs::read_dir(base)? {
+            let entry = entry?;
+            let Ok(book_id) = entry.file_name().to_string_lossy().parse::<i32>() else {
+                continue;
+            };
+            let meta = entry.metadata()?;
+            if !meta.is_dir() {
+                continue;
+            }
+            let mtime = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs_f64())
+                .unwrap_or(0.0);
+            dirs.push((book_id, entry.path(), mtime));
+        }
+        Ok(dirs)
+    }
+}
+
+/// Title and first author from the metadata.opf of an entry.
+fn read_opf(dir: &Path) -> (String, String) {
+    let mi = fs::read_to_string(dir.join(METADATA_FILE_NAME))
+        .ok()
+        .and_then(|xml| parse_opf(&xml).ok());
+    let title = mi
+        .as_ref()
+        .map(|mi| mi.title.clone())
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| "Unknown".to_string());
+    let author = mi
+        .and_then(|mi| mi.authors.into_iter().next())
+        .unwrap_or_else(|| "Unknown".to_string());
+    (title, author)
+}
+
+/// The formats of a trashed book folder, from the extensions of its files.
+fn book_formats(dir: &Path) -> io::Result<Vec<String>> {
+    let mut formats = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() || is_special_file(&path) {
+            continue;
+        }
+        if let Some(ext) = path.extension() {
+            formats.push(ext.to_string_lossy().to_uppercase());
+        }
+    }
+    formats.sort();
+    Ok(formats)
+}
+
+/// The formats of an `f/<book_id>` entry, whose files are named after them.
+fn format_names(dir: &Path) -> io::Result<Vec<String>> {
+    let mut formats = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() || is_special_file(&path) {
+            continue;
+        }
+        if let Some(name) = path.file_name() {
+            formats.push(name.to_string_lossy().to_uppercase());
+        }
+    }
+    formats.sort();
+    Ok(formats)
+}
+
+pub(crate) fn is_special_file(path: &Path) -> bool {
+    matches!(
+        path.file_name().and_then(|n| n.to_str()),
+        Some(COVER_FILE_NAME | METADATA_FILE_NAME | METADATA_JSON_NAME)
+    )
+}
+
+/// Move the contents of `src` into a new folder `dest` and remove `src`.
+/// The contents are moved one by one, as in calibre, so that `dest` is
+/// dated from the move.
+pub(crate) fn move_tree(src: &Path, dest: &Path) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dest.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            move_tree(&entry.path(), &target)?;
+        } else {
+            move_file(&entry.path(), &target)?;
+        }
+    }
+    fs::remove_dir(src)
+}
+
+/// Rename `src` to `dest`, copying it when they are on different devices.
+fn move_file(src: &Path, dest: &Path) -> io::Result<()> {
+    if fs::rename(src, dest).is_err() {
+        fs::copy(src, dest)?;
+        fs::remove_file(src)?;
+    }
+    Ok(())
+}
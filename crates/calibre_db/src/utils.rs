@@ -80,6 +80,27 @@ impl ThumbnailCache {
         Ok(())
     }
 
+    pub fn thumbnail_size(&self) -> (u32, u32) {
+        self.thumbnail_size
+    }
+
+    /// Generate the thumbnail of a book's cover at the size of this cache
+    /// and store it. Returns the thumbnail data.
+    pub fn insert_cover(
+        &mut self,
+        book_id: i32,
+        timestamp: f64,
+        cover: &[u8],
+    ) -> anyhow::Result<Vec<u8>> {
+        let (data, _, _) = calibre_utils::img::thumbnail(
+            cover,
+            self.thumbnail_size,
+            calibre_utils::img::DEFAULT_QUALITY,
+        )?;
+        self.insert(book_id, timestamp, &data);
+        Ok(data)
+    }
+
     pub fn insert(&mut self, book_id: i32, timestamp: f64, data: &[u8]) {
         if self.max_size < data.len() as u64 {
             return;
@@ -1,57 +1,139 @@
 use crate::cache::Cache;
-use crate::search;
+use crate::search::{Search, Values};
+use anyhow::{Context, Result};
+use calibre_utils::icu::sort_key;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
+/// Preference holding the virtual libraries as a `{name: search}` object,
+/// stored like the saved searches.
+const VIRTUAL_LIBRARIES_PREF: &str = "virtual_libraries";
+
+/// Value a book is ordered by for one sort column. Books without a value
+/// come first in ascending order.
+#[derive(Debug, PartialEq, PartialOrd)]
+enum SortKey {
+    Missing,
+    Number(f64),
+    Text(String),
+    Series(String, f64),
+}
+
+/// The books of a library as a list is shown: restricted to a virtual
+/// library, filtered by a search and ordered by one or more columns.
 pub struct View {
     cache: Arc<Mutex<Cache>>,
     ids: Vec<i32>,
+    query: String,
+    base_restriction: Option<String>,
+    sort_columns: Vec<(String, bool)>,
+    marked_ids: HashMap<i32, String>,
 }
 
 impl View {
     pub fn new(cache: Arc<Mutex<Cache>>) -> Self {
-        // Initially, the view should probably show all books.
-        // We need a way to get all IDs.
-
-        let mut ids = Vec::new();
-        let conn_arc = {
-            let lock = cache.lock().unwrap();
-            lock.backend.conn.clone()
+        let mut view = View {
+            cache,
+            ids: Vec::new(),
+            query: String::new(),
+            base_restriction: None,
+            sort_columns: Vec::new(),
+            marked_ids: HashMap::new(),
         };
+        if let Err(e) = view.refresh() {
+            log::warn!("Failed to load the books of the library: {:#}", e);
+        }
+        view
+    }
 
-        if let Ok(conn) = conn_arc.lock() {
-            if let Ok(mut stmt) = conn.prepare("SELECT id FROM books") {
-                if let Ok(rows) = stmt.query_map([], |row| row.get(0)) {
-                    for r in rows {
-                        if let Ok(id) = r {
-                            ids.push(id);
-                        }
-                    }
-                }
+    fn with_search<T>(&self, f: impl FnOnce(&mut Search) -> Result<T>) -> Result<T> {
+        let cache = self.cache.lock().unwrap();
+        let conn = cache.backend.conn.lock().unwrap();
+        let mut search = Search::new(&conn);
+        search.set_marked_ids(self.marked_ids.clone());
+        f(&mut search)
+    }
+
+    /// Show the books matching `query` within the base restriction, in the
+    /// current sort order. An empty query shows them all.
+    pub fn search(&mut self, query: &str) -> Result<()> {
+        let base = self.base_restriction.clone();
+        let mut ids = self.with_search(|s| s.search(query, base.as_deref()))?;
+        self.sort_ids(&mut ids)?;
+        self.ids = ids;
+        self.query = query.to_string();
+        Ok(())
+    }
+
+    /// Run the current search again, after the library changed.
+    pub fn refresh(&mut self) -> Result<()> {
+        let query = self.query.clone();
+        self.search(&query)
+    }
+
+    /// Limit the view to a virtual library, or lift the limit with `None`.
+    /// Searches then only look at the books of that library.
+    pub fn set_base_restriction(&mut self, virtual_library: Option<&str>) -> Result<()> {
+        if let Some(name) = virtual_library {
+            if !self.virtual_libraries().contains_key(name) {
+                anyhow::bail!("Unknown virtual library: {}", name);
             }
         }
+        self.base_restriction = virtual_library.map(|s| s.to_string());
+        self.refresh()
+    }
 
-        View { cache, ids }
+    pub fn base_restriction(&self) -> Option<&str> {
+        self.base_restriction.as_deref()
     }
 
-    pub fn search(&mut self, query: &str) {
-        // Use our search module
-        if let Ok(results) = search::search(&self.cache, query) {
-            // In a real view, we might intersect with existing restriction.
-            // For this basic port, simply replace ids with search results.
-            self.ids = results;
-        }
+    pub fn sort(&mut self, field: &str, ascending: bool) -> Result<()> {
+        self.multisort(&[(field, ascending)])
     }
 
-    pub fn sort(&mut self, _field: &str, _ascending: bool) {
-        // Stub for sorting.
-        // In reality, this would query field values and sort the self.ids vector.
-        // For now, let's just sort by ID to ensure unstable sort doesn't mess things up randomly
-        self.ids.sort();
-        if !_ascending {
-            self.ids.reverse();
+    /// Order by several columns, the first one deciding first. The order
+    /// is kept for later searches.
+    pub fn multisort(&mut self, fields: &[(&str, bool)]) -> Result<()> {
+        let previous = std::mem::replace(
+            &mut self.sort_columns,
+            fields
+                .iter()
+                .map(|(f, asc)| (f.to_lowercase(), *asc))
+                .collect(),
+        );
+        let mut ids = std::mem::take(&mut self.ids);
+        let result = self.sort_ids(&mut ids);
+        self.ids = ids;
+        if result.is_err() {
+            self.sort_columns = previous;
         }
+        result
+    }
 
-        // TODO: Implement actual field sorting using field_for
+    fn sort_ids(&self, ids: &mut [i32]) -> Result<()> {
+        if self.sort_columns.is_empty() {
+            return Ok(());
+        }
+        let columns = self.with_search(|search| {
+            self.sort_columns
+                .iter()
+                .map(|(field, asc)| Ok((sort_keys(search, field)?, *asc)))
+                .collect::<Result<Vec<_>>>()
+        })?;
+        ids.sort_by(|a, b| {
+            for (keys, asc) in &columns {
+                let ka = keys.get(a).unwrap_or(&SortKey::Missing);
+                let kb = keys.get(b).unwrap_or(&SortKey::Missing);
+                let order = ka.partial_cmp(kb).unwrap_or(Ordering::Equal);
+                let order = if *asc { order } else { order.reverse() };
+                if order != Ordering::Equal {
+                    return order;
+                }
+            }
+            Ordering::Equal
+        });
+        Ok(())
     }
 
     pub fn count(&self) -> usize {
@@ -61,4 +143,135 @@ impl View {
     pub fn get_ids(&self) -> &[i32] {
         &self.ids
     }
+
+    /// Mark books, replacing the previous marks. The label is what
+    /// `marked:` searches match, `true` for a plain mark.
+    pub fn set_marked_ids(&mut self, marked_ids: HashMap<i32, String>) {
+        self.marked_ids = marked_ids;
+    }
+
+    /// Mark the unmarked books among `ids` and unmark the others.
+    pub fn toggle_marked_ids(&mut self, ids: &[i32]) {
+        for id in ids {
+            if self.marked_ids.remove(id).is_none() {
+                self.marked_ids.insert(*id, "true".to_string());
+            }
+        }
+    }
+
+    pub fn marked_ids(&self) -> &HashMap<i32, String> {
+        &self.marked_ids
+    }
+
+    pub fn is_marked(&self, book_id: i32) -> bool {
+        self.marked_ids.contains_key(&book_id)
+    }
+
+    /// The virtual libraries of the library, name to search.
+    pub fn virtual_libraries(&self) -> HashMap<String, String> {
+        let cache = self.cache.lock().unwrap();
+        cache
+            .backend
+            .prefs
+            .get(VIRTUAL_LIBRARIES_PREF)
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Create or redefine a virtual library.
+    pub fn set_virtual_library(&mut self, name: &str, query: &str) -> Result<()> {
+        let mut libraries = self.virtual_libraries();
+        libraries.insert(name.to_string(), query.to_string());
+        self.save_virtual_libraries(&libraries)?;
+        if self.base_restriction.as_deref() == Some(name) {
+            self.refresh()?;
+        }
+        Ok(())
+    }
+
+    /// Delete a virtual library, lifting the restriction if it was in use.
+    /// Returns false if there was no such library.
+    pub fn remove_virtual_library(&mut self, name: &str) -> Result<bool> {
+        let mut libraries = self.virtual_libraries();
+        if libraries.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save_virtual_libraries(&libraries)?;
+        if self.base_restriction.as_deref() == Some(name) {
+            self.set_base_restriction(None)?;
+        }
+        Ok(true)
+    }
+
+    fn save_virtual_libraries(&self, libraries: &HashMap<String, String>) -> Result<()> {
+        let json = serde_json::to_string(libraries)?;
+        self.cache
+            .lock()
+            .unwrap()
+            .backend
+            .set_pref(VIRTUAL_LIBRARIES_PREF, &json)
+            .context("Failed to save the virtual libraries")
+    }
+}
+
+/// Sort keys of one column for every book that has a value. Titles and
+/// authors sort by their sort fields, series by name then index.
+fn sort_keys(search: &mut Search, field: &str) -> Result<HashMap<i32, SortKey>> {
+    let location = match field {
+        "title" => "sort",
+        "authors" => "author_sort",
+        "timestamp" => "date",
+        f => f,
+    };
+    let mut keys = column_keys(search.values(location)?);
+    match field {
+        "title" => {
+            // Books added without a sort title still sort by their title
+            for (id, key) in column_keys(search.values("title")?) {
+                keys.entry(id).or_insert(key);
+            }
+        }
+        "series" => {
+            let indices = column_keys(search.values("series_index")?);
+            for (id, key) in keys.iter_mut() {
+                if let SortKey::Text(name) = key {
+                    let index = match indices.get(id) {
+                        Some(SortKey::Number(n)) => *n,
+                        _ => 1.0,
+                    };
+                    *key = SortKey::Series(std::mem::take(name), index);
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(keys)
+}
+
+fn column_keys(values: &Values) -> HashMap<i32, SortKey> {
+    match values {
+        Values::Text(values) => values
+            .iter()
+            .map(|(id, v)| (*id, SortKey::Text(sort_key(&v.join(", ")))))
+            .collect(),
+        Values::Identifiers(values) => values
+            .iter()
+            .map(|(id, v)| {
+                let joined: Vec<String> = v.iter().map(|(k, v)| format!("{}:{}", k, v)).collect();
+                (*id, SortKey::Text(joined.join(",")))
+            })
+            .collect(),
+        Values::Number { values, .. } => values
+            .iter()
+            .map(|(id, v)| (*id, SortKey::Number(*v)))
+            .collect(),
+        Values::Date(values) => values
+            .iter()
+            .map(|(id, v)| (*id, SortKey::Number(v.timestamp() as f64)))
+            .collect(),
+        Values::Bool(values) => values
+            .iter()
+            .map(|(id, v)| (*id, SortKey::Number(if *v { 1.0 } else { 0.0 })))
+            .collect(),
+    }
 }
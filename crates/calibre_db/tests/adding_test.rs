@@ -37,3 +37,63 @@ fn test_add_book() {
     assert!(uuid.is_some());
     println!("Generated UUID: {}", uuid.unwrap());
 }
+
+#[test]
+fn test_find_books_in_directory() {
+    let dir = tempdir().unwrap();
+    for name in ["One.epub", "One.PDF", "Two.txt", "notes.xyz", "cover.jpg"] {
+        std::fs::write(dir.path().join(name), name).unwrap();
+    }
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    std::fs::write(dir.path().join("sub").join("Three.mobi"), "x").unwrap();
+
+    let names = |books: Vec<Vec<std::path::PathBuf>>| {
+        let mut books: Vec<Vec<String>> = books
+            .into_iter()
+            .map(|formats| {
+                let mut names: Vec<String> = formats
+                    .iter()
+                    .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+                    .collect();
+                names.sort();
+                names
+            })
+            .collect();
+        books.sort();
+        books
+    };
+
+    // Known formats only, grouped by file name
+    assert_eq!(
+        names(adding::find_books_in_directory(dir.path(), false, &[])),
+        vec![
+            vec!["One.PDF".to_string(), "One.epub".to_string()],
+            vec!["Two.txt".to_string()],
+        ]
+    );
+
+    // Ignore rules come first, so the epub stays out
+    let rules = vec![
+        adding::FilenameRule::glob("*.EPUB", false).unwrap(),
+        adding::FilenameRule::glob("*.xyz", true).unwrap(),
+        adding::FilenameRule::glob("*", true).unwrap(),
+    ];
+    assert_eq!(
+        names(adding::find_books_in_directory(dir.path(), true, &rules)),
+        vec![vec![
+            "One.PDF".to_string(),
+            "Two.txt".to_string(),
+            "cover.jpg".to_string(),
+            "notes.xyz".to_string(),
+        ]]
+    );
+
+    assert_eq!(
+        names(adding::recursive_find(dir.path(), false, &[])),
+        vec![
+            vec!["One.PDF".to_string(), "One.epub".to_string()],
+            vec!["Three.mobi".to_string()],
+            vec!["Two.txt".to_string()],
+        ]
+    );
+}
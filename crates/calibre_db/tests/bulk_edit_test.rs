@@ -0,0 +1,244 @@
+use calibre_db::bulk_edit::{BulkEditor, Case, FieldOp, FieldValue};
+use calibre_db::listeners::{Event, EventDispatcher, EventListener};
+use calibre_db::Library;
+use calibre_ebooks::metadata::MetaInformation;
+use regex::Regex;
+use std::sync::{Arc, Mutex};
+use tempfile::{tempdir, TempDir};
+
+struct Recorder(Arc<Mutex<Vec<(String, Vec<i32>)>>>);
+
+impl EventListener for Recorder {
+    fn on_event(&self, event: &Event) {
+        if let Event::MetadataChanged { field, book_ids } = event {
+            self.0
+                .lock()
+                .unwrap()
+                .push((field.clone(), book_ids.clone()));
+        }
+    }
+}
+
+type Events = Arc<Mutex<Vec<(String, Vec<i32>)>>>;
+
+fn setup(undo_limit: usize) -> (TempDir, Library, BulkEditor, Events, Vec<i32>) {
+    let dir = tempdir().unwrap();
+    let mut lib = Library::create(dir.path().to_path_buf()).unwrap();
+    lib.conn()
+        .execute_batch(
+            "CREATE TABLE tags (id INTEGER PRIMARY KEY, name TEXT NOT NULL COLLATE NOCASE,
+                 UNIQUE (name));
+             CREATE TABLE books_tags_link (id INTEGER PRIMARY KEY, book INTEGER, tag INTEGER,
+                 UNIQUE (book, tag));
+             CREATE TABLE series (id INTEGER PRIMARY KEY, name TEXT NOT NULL, sort TEXT,
+                 UNIQUE (name));
+             CREATE TABLE books_series_link (id INTEGER PRIMARY KEY, book INTEGER, series INTEGER,
+                 UNIQUE (book));",
+        )
+        .unwrap();
+
+    let mut ids = Vec::new();
+    for (title, tags) in [
+        ("the first book", vec!["Fiction", "old"]),
+        ("the second book", vec!["fiction"]),
+        ("a third book", vec![]),
+    ] {
+        let mut mi = MetaInformation::new(title, vec!["Jane Doe".to_string()]);
+        mi.tags = tags.into_iter().map(String::from).collect();
+        ids.push(lib.add_empty_book(&mi).unwrap());
+    }
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let dispatcher = EventDispatcher::new();
+    dispatcher.register(Box::new(Recorder(events.clone())));
+    let editor = BulkEditor::with_undo_limit(dispatcher, undo_limit);
+    (dir, lib, editor, events, ids)
+}
+
+fn tags(lib: &Library, book_id: i32) -> Vec<String> {
+    let mut tags = lib.get_metadata(book_id).unwrap().unwrap().tags;
+    tags.sort();
+    tags
+}
+
+#[test]
+fn test_bulk_edit_tags_and_series() {
+    let (_dir, mut lib, mut editor, events, ids) = setup(10);
+
+    let changes = editor
+        .apply(
+            &mut lib,
+            &ids,
+            &[
+                FieldOp::Append {
+                    field: "tags".to_string(),
+                    items: vec!["FICTION".to_string(), "New".to_string()],
+                },
+                FieldOp::Remove {
+                    field: "tags".to_string(),
+                    items: vec!["OLD".to_string()],
+                },
+                FieldOp::Set {
+                    field: "series".to_string(),
+                    value: "Saga".to_string(),
+                },
+                FieldOp::RenumberSeries {
+                    start: 2.0,
+                    increment: 0.5,
+                },
+            ],
+        )
+        .unwrap();
+    assert_eq!(changes.len(), 9);
+
+    // Tags are shared by books, the last case given wins
+    for book_id in &ids {
+        assert_eq!(tags(&lib, *book_id), vec!["FICTION", "New"]);
+    }
+    for (book_id, index) in ids.iter().zip([2.0, 2.5, 3.0]) {
+        let mi = lib.get_metadata(*book_id).unwrap().unwrap();
+        assert_eq!(mi.series.as_deref(), Some("Saga"));
+        assert_eq!(mi.series_index, index);
+    }
+
+    // One event per field, with every book changed
+    let events = events.lock().unwrap();
+    assert_eq!(
+        *events,
+        vec![
+            ("tags".to_string(), ids.clone()),
+            ("series".to_string(), ids.clone()),
+            ("series_index".to_string(), ids.clone()),
+        ]
+    );
+}
+
+#[test]
+fn test_bulk_edit_search_replace_and_case() {
+    let (dir, mut lib, mut editor, _events, ids) = setup(10);
+    let old_path = lib.get_book(ids[0]).unwrap().unwrap().path;
+
+    editor
+        .apply(
+            &mut lib,
+            &ids[..2],
+            &[
+                FieldOp::SearchReplace {
+                    field: "title".to_string(),
+                    pattern: Regex::new(r"(?i)\bbook$").unwrap(),
+                    replace: "story".to_string(),
+                },
+                FieldOp::ChangeCase {
+                    field: "title".to_string(),
+                    case: Case::Title,
+                },
+                FieldOp::ChangeCase {
+                    field: "tags".to_string(),
+                    case: Case::Upper,
+                },
+            ],
+        )
+        .unwrap();
+
+    let book = lib.get_book(ids[0]).unwrap().unwrap();
+    assert_eq!(book.title, "The First Story");
+    assert_eq!(book.sort.as_deref(), Some("First Story, The"));
+    assert_eq!(tags(&lib, ids[0]), vec!["FICTION", "OLD"]);
+    assert_eq!(lib.get_book(ids[2]).unwrap().unwrap().title, "a third book");
+
+    // The files follow the new title
+    assert_ne!(book.path, old_path);
+    assert!(dir.path().join(&book.path).is_dir());
+    assert!(!dir.path().join(&old_path).exists());
+}
+
+#[test]
+fn test_bulk_edit_is_atomic() {
+    let (_dir, mut lib, mut editor, events, ids) = setup(10);
+
+    // The tags are changed before the failing operation
+    let err = editor.apply(
+        &mut lib,
+        &ids,
+        &[
+            FieldOp::Set {
+                field: "tags".to_string(),
+                value: "a, b".to_string(),
+            },
+            FieldOp::Append {
+                field: "title".to_string(),
+                items: vec!["x".to_string()],
+            },
+        ],
+    );
+    assert!(err.is_err());
+    assert_eq!(tags(&lib, ids[0]), vec!["Fiction", "old"]);
+
+    let set_tags = FieldOp::Set {
+        field: "tags".to_string(),
+        value: "a".to_string(),
+    };
+    assert!(editor
+        .apply(&mut lib, &[ids[0], 999], &[set_tags.clone()])
+        .is_err());
+    assert!(editor
+        .apply(
+            &mut lib,
+            &ids,
+            &[FieldOp::Set {
+                field: "nope".to_string(),
+                value: "a".to_string(),
+            }]
+        )
+        .is_err());
+    assert_eq!(tags(&lib, ids[0]), vec!["Fiction", "old"]);
+    assert!(events.lock().unwrap().is_empty());
+    assert_eq!(editor.undo_len(), 0);
+}
+
+#[test]
+fn test_bulk_edit_undo() {
+    let (dir, mut lib, mut editor, events, ids) = setup(2);
+    let set = |field: &str, value: &str| FieldOp::Set {
+        field: field.to_string(),
+        value: value.to_string(),
+    };
+    let old_path = lib.get_book(ids[0]).unwrap().unwrap().path;
+
+    editor.apply(&mut lib, &ids, &[set("tags", "one")]).unwrap();
+    editor
+        .apply(&mut lib, &ids, &[set("title", "Same Title")])
+        .unwrap();
+    let changes = editor
+        .apply(&mut lib, &ids[..1], &[set("authors", "A & B")])
+        .unwrap();
+    assert_eq!(
+        changes[0].after,
+        FieldValue::List(vec!["A".to_string(), "B".to_string()])
+    );
+    assert_eq!(editor.undo_len(), 2);
+
+    events.lock().unwrap().clear();
+    let reverted = editor.undo(&mut lib).unwrap().unwrap();
+    assert_eq!(reverted.len(), 2);
+    let book = lib.get_book(ids[0]).unwrap().unwrap();
+    assert_eq!(lib.get_authors(ids[0]).unwrap(), vec!["Jane Doe"]);
+    assert_eq!(book.author_sort.as_deref(), Some("Jane Doe"));
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            ("author_sort".to_string(), vec![ids[0]]),
+            ("authors".to_string(), vec![ids[0]]),
+        ]
+    );
+
+    editor.undo(&mut lib).unwrap().unwrap();
+    let book = lib.get_book(ids[0]).unwrap().unwrap();
+    assert_eq!(book.title, "the first book");
+    assert_eq!(book.path, old_path);
+    assert!(dir.path().join(&old_path).is_dir());
+
+    // The first edit fell out of the journal
+    assert!(editor.undo(&mut lib).unwrap().is_none());
+    assert_eq!(tags(&lib, ids[0]), vec!["one"]);
+}
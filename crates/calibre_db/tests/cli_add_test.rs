@@ -0,0 +1,236 @@
+use calibre_db::adding::ImportRules;
+use calibre_db::cli::cmd_add::{CmdAdd, RunArgs};
+use calibre_db::Library;
+use clap::Parser;
+use std::fs;
+use std::path::Path;
+use tempfile::{tempdir, TempDir};
+
+/// A library with the tables for tags and series, and a folder to add
+/// books from.
+fn setup() -> (TempDir, Library, TempDir) {
+    let lib_dir = tempdir().unwrap();
+    let lib = Library::create(lib_dir.path().to_path_buf()).unwrap();
+    lib.conn()
+        .execute_batch(
+            "CREATE TABLE tags (id INTEGER PRIMARY KEY, name TEXT NOT NULL, UNIQUE (name));
+             CREATE TABLE books_tags_link (id INTEGER PRIMARY KEY, book INTEGER, tag INTEGER,
+                 UNIQUE (book, tag));
+             CREATE TABLE series (id INTEGER PRIMARY KEY, name TEXT NOT NULL, sort TEXT,
+                 UNIQUE (name));
+             CREATE TABLE books_series_link (id INTEGER PRIMARY KEY, book INTEGER, series INTEGER,
+                 UNIQUE (book));",
+        )
+        .unwrap();
+    (lib_dir, lib, tempdir().unwrap())
+}
+
+fn write(dir: &Path, name: &str, content: &str) -> String {
+    let path = dir.join(name);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, content).unwrap();
+    path.to_string_lossy().to_string()
+}
+
+fn add(lib: &mut Library, args: &[&str]) -> calibre_db::cli::cmd_add::AddResult {
+    let args = RunArgs::parse_from(std::iter::once("add").chain(args.iter().copied()));
+    CmdAdd::with_rules(ImportRules::default())
+        .add(lib, &args)
+        .unwrap()
+}
+
+fn sorted_formats(lib: &Library, book_id: i32) -> Vec<String> {
+    let mut formats = lib.formats(book_id).unwrap();
+    formats.sort();
+    formats
+}
+
+#[test]
+fn test_add_folder_groups_formats() {
+    let (_lib_dir, mut lib, src) = setup();
+    write(src.path(), "Book One.pdf", "not a pdf");
+    write(src.path(), "Book One.txt", "Some text");
+    write(src.path(), "deeper/Book Two.txt", "More text");
+
+    // Without --recurse only the folder itself is scanned
+    let result = add(&mut lib, &[src.path().to_str().unwrap()]);
+    assert_eq!(result.added_ids.len(), 1);
+    let book_id = *result.added_ids.iter().next().unwrap();
+    assert_eq!(lib.get_book(book_id).unwrap().unwrap().title, "Book One");
+    assert_eq!(sorted_formats(&lib, book_id), vec!["PDF", "TXT"]);
+
+    let book = lib.get_book(book_id).unwrap().unwrap();
+    let book_dir = lib.path().join(&book.path);
+    assert!(book_dir.join("Book One.pdf").exists());
+    assert!(book_dir.join("Book One.txt").exists());
+
+    // Recursing finds the other book, the first one is a duplicate
+    let result = add(&mut lib, &["-r", src.path().to_str().unwrap()]);
+    assert_eq!(result.added_ids.len(), 1);
+    assert_eq!(result.duplicates.len(), 1);
+    assert_eq!(result.duplicates[0].0, "Book One");
+}
+
+#[test]
+fn test_duplicates_and_automerge() {
+    let (_lib_dir, mut lib, src) = setup();
+    let txt = write(src.path(), "The Novel.txt", "Version one");
+    let result = add(&mut lib, &["-a", "Jane Doe", &txt]);
+    let first = *result.added_ids.iter().next().unwrap();
+
+    // Same title up to case and punctuation, same author
+    let again = write(src.path(), "again/novel.txt", "Version two");
+    let epub = write(src.path(), "again/novel.epub", "not an epub");
+    let result = add(&mut lib, &["-a", "jane doe", "-t", "THE NOVEL", &again]);
+    assert!(result.added_ids.is_empty());
+    assert_eq!(result.duplicates.len(), 1);
+
+    let result = add(
+        &mut lib,
+        &["-d", "-a", "Jane Doe", "-t", "THE NOVEL", &again],
+    );
+    assert_eq!(result.added_ids.len(), 1);
+    let duplicate = *result.added_ids.iter().next().unwrap();
+    assert_ne!(duplicate, first);
+
+    let book = lib.get_book(first).unwrap().unwrap();
+    let first_txt = lib.path().join(&book.path).join("The Novel.txt");
+    lib.delete_book(duplicate).unwrap();
+
+    // ignore: the new format is merged, the existing one is kept
+    let result = add(
+        &mut lib,
+        &[
+            "-m",
+            "ignore",
+            "-a",
+            "Jane Doe",
+            "-t",
+            "THE NOVEL",
+            &again,
+            &epub,
+        ],
+    );
+    assert!(result.added_ids.is_empty());
+    assert_eq!(result.merged_ids.iter().collect::<Vec<_>>(), vec![&first]);
+    assert_eq!(result.duplicates.len(), 1);
+    assert_eq!(sorted_formats(&lib, first), vec!["EPUB", "TXT"]);
+    assert_eq!(fs::read_to_string(&first_txt).unwrap(), "Version one");
+
+    // overwrite: the existing format is replaced
+    let result = add(
+        &mut lib,
+        &[
+            "-m",
+            "overwrite",
+            "-a",
+            "Jane Doe",
+            "-t",
+            "THE NOVEL",
+            &again,
+        ],
+    );
+    assert!(result.added_ids.is_empty());
+    assert!(result.duplicates.is_empty());
+    assert_eq!(fs::read_to_string(&first_txt).unwrap(), "Version two");
+
+    // new_record: the existing format goes into a new book
+    let result = add(
+        &mut lib,
+        &[
+            "-m",
+            "new_record",
+            "-a",
+            "Jane Doe",
+            "-t",
+            "THE NOVEL",
+            &again,
+        ],
+    );
+    assert_eq!(result.added_ids.len(), 1);
+    assert!(result.merged_ids.is_empty());
+    let new_id = *result.added_ids.iter().next().unwrap();
+    assert_ne!(new_id, first);
+    assert_eq!(sorted_formats(&lib, new_id), vec!["TXT"]);
+    let new_book = lib.get_book(new_id).unwrap().unwrap();
+    assert_ne!(new_book.path, book.path);
+}
+
+#[test]
+fn test_add_empty_book() {
+    let (_lib_dir, mut lib, _src) = setup();
+    let result = add(
+        &mut lib,
+        &[
+            "--empty",
+            "-t",
+            "Placeholder",
+            "-a",
+            "A. Writer & B. Writer",
+            "-T",
+            "fiction, to read",
+            "-s",
+            "Saga",
+            "-S",
+            "3",
+            "-I",
+            "asin:B000",
+        ],
+    );
+    let book_id = *result.added_ids.iter().next().unwrap();
+    assert!(lib.formats(book_id).unwrap().is_empty());
+
+    let mi = lib.get_metadata(book_id).unwrap().unwrap();
+    assert_eq!(mi.title, "Placeholder");
+    assert_eq!(mi.authors, vec!["A. Writer", "B. Writer"]);
+    assert_eq!(mi.tags, vec!["fiction", "to read"]);
+    assert_eq!(mi.series.as_deref(), Some("Saga"));
+    assert_eq!(mi.series_index, 3.0);
+}
+
+#[test]
+fn test_import_rules_are_applied() {
+    let (_lib_dir, mut lib, src) = setup();
+    let folder = src.path().join("book");
+    write(&folder, "Mapped.txt", "text");
+    write(
+        &folder,
+        "metadata.opf",
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title>Mapped</dc:title>
+    <dc:creator>anon</dc:creator>
+    <dc:subject>scifi</dc:subject>
+    <dc:subject>other</dc:subject>
+  </metadata>
+</package>"#,
+    );
+    let rules = ImportRules::from_prefs(&serde_json::json!({
+        "tag_map_on_add_rules": [
+            {"action": "replace", "query": "scifi", "replace": "Science Fiction", "match_type": "one_of"}
+        ],
+        "author_map_on_add_rules": [
+            {"action": "replace", "query": "anon", "replace": "Anonymous", "match_type": "one_of"}
+        ]
+    }));
+    let cmd = CmdAdd::with_rules(rules);
+
+    let args = RunArgs::parse_from(["add", "-1", folder.to_str().unwrap()]);
+    let result = cmd.add(&mut lib, &args).unwrap();
+    let book_id = *result.added_ids.iter().next().unwrap();
+    let mi = lib.get_metadata(book_id).unwrap().unwrap();
+    assert_eq!(mi.title, "Mapped");
+    assert_eq!(mi.authors, vec!["Anonymous"]);
+    assert_eq!(mi.tags, vec!["Science Fiction", "other"]);
+    assert_eq!(sorted_formats(&lib, book_id), vec!["TXT"]);
+
+    // Options given on the command line are not mapped
+    let txt = write(src.path(), "Other.txt", "text");
+    let args = RunArgs::parse_from(["add", "-a", "anon", "-T", "scifi", &txt]);
+    let result = cmd.add(&mut lib, &args).unwrap();
+    let book_id = *result.added_ids.iter().next().unwrap();
+    let mi = lib.get_metadata(book_id).unwrap().unwrap();
+    assert_eq!(mi.authors, vec!["anon"]);
+    assert_eq!(mi.tags, vec!["scifi"]);
+}
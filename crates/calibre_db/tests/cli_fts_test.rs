@@ -0,0 +1,148 @@
+use calibre_db::cli::cmd_fits_index::{self, CmdFitsIndex};
+use calibre_db::cli::cmd_fits_search::{self, CmdFitsSearch};
+use calibre_db::Library;
+use clap::Parser;
+use std::fs;
+use tempfile::tempdir;
+
+fn library(dir: &std::path::Path) -> Library {
+    let lib = Library::create(dir.to_path_buf()).unwrap();
+    lib.conn()
+        .execute_batch(
+            "INSERT INTO books (id, title, sort, author_sort, series_index, has_cover, path) VALUES
+                (1, 'Fables', 'Fables', 'Aesop', 1.0, 0, 'Aesop/Fables (1)'),
+                (2, 'Notes', 'Notes', 'Anonymous', 1.0, 0, 'Anonymous/Notes (2)');
+             INSERT INTO authors (id, name, sort) VALUES (1, 'Aesop', 'Aesop');
+             INSERT INTO books_authors_link (book, author) VALUES (1, 1);
+             INSERT INTO data (book, format, uncompressed_size, name) VALUES
+                (1, 'TXT', 0, 'Fables'), (2, 'TXT', 0, 'Notes');",
+        )
+        .unwrap();
+    for (dir_name, name, text) in [
+        (
+            "Aesop/Fables (1)",
+            "Fables",
+            "The fox and the grapes. The fox jumped.",
+        ),
+        (
+            "Anonymous/Notes (2)",
+            "Notes",
+            "Grapes are sour, said nobody.",
+        ),
+    ] {
+        let book_dir = dir.join(dir_name);
+        fs::create_dir_all(&book_dir).unwrap();
+        fs::write(book_dir.join(format!("{}.txt", name)), text).unwrap();
+    }
+    lib
+}
+
+fn index_args(args: &[&str]) -> cmd_fits_index::RunArgs {
+    cmd_fits_index::RunArgs::parse_from(std::iter::once("fts_index").chain(args.iter().copied()))
+}
+
+fn search_args(args: &[&str]) -> cmd_fits_search::RunArgs {
+    cmd_fits_search::RunArgs::parse_from(std::iter::once("fts_search").chain(args.iter().copied()))
+}
+
+#[test]
+fn test_fts_index_and_search() {
+    let dir = tempdir().unwrap();
+    let mut lib = library(dir.path());
+    let index = CmdFitsIndex::new();
+    let search = CmdFitsSearch::new();
+
+    assert!(index.run(&mut lib, &index_args(&["status"])).is_err());
+    assert!(search.search(&lib, &search_args(&["fox"])).is_err());
+    assert!(index.run(&mut lib, &index_args(&["reindex"])).is_err());
+
+    index
+        .run(&mut lib, &index_args(&["enable", "--wait-for-completion"]))
+        .unwrap();
+    assert!(lib.is_fts_enabled().unwrap());
+    let progress = lib.fts_indexer().unwrap().progress().unwrap();
+    assert_eq!((progress.indexed, progress.pending), (2, 0));
+    index.run(&mut lib, &index_args(&["status"])).unwrap();
+
+    let matches = search
+        .search(
+            &lib,
+            &search_args(&[
+                "fox",
+                "--include-snippets",
+                "--match-start-marker",
+                "[",
+                "--match-end-marker",
+                "]",
+            ]),
+        )
+        .unwrap();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].book_id, 1);
+    assert_eq!(matches[0].title, "Fables");
+    assert_eq!(matches[0].authors, vec!["Aesop".to_string()]);
+    assert!(matches[0].text.as_deref().unwrap().contains("[fox]"));
+    let json = serde_json::to_string(&matches[0]).unwrap();
+    assert!(json.starts_with(r#"{"authors":["Aesop"],"book_id":1,"format":"TXT","text":"#));
+
+    let matches = search.search(&lib, &search_args(&["grapes"])).unwrap();
+    assert_eq!(matches.len(), 2);
+    assert!(matches.iter().all(|m| m.text.is_none()));
+    let restricted = search
+        .search(&lib, &search_args(&["grapes", "--restrict-to", "ids:2"]))
+        .unwrap();
+    assert_eq!(restricted.len(), 1);
+    assert_eq!(restricted[0].book_id, 2);
+    let restricted = search
+        .search(
+            &lib,
+            &search_args(&["grapes", "--restrict-to", "search:title:fables"]),
+        )
+        .unwrap();
+    assert_eq!(restricted.len(), 1);
+    assert_eq!(restricted[0].book_id, 1);
+    assert!(search
+        .search(&lib, &search_args(&["grapes", "--restrict-to", "tags:x"]))
+        .is_err());
+    // Related words match unless asked not to
+    assert_eq!(
+        search
+            .search(&lib, &search_args(&["jumping"]))
+            .unwrap()
+            .len(),
+        1
+    );
+    assert!(search
+        .search(
+            &lib,
+            &search_args(&["jumping", "--do-not-match-on-related-words"])
+        )
+        .unwrap()
+        .is_empty());
+    assert!(search.search(&lib, &search_args(&["\"fox"])).is_err());
+    search
+        .run(&lib, &search_args(&["fox", "--output-format", "json"]))
+        .unwrap();
+
+    // Reindexing queues the formats again, searching waits for the index
+    index
+        .run(&mut lib, &index_args(&["reindex", "1:txt", "2"]))
+        .unwrap();
+    let progress = lib.fts_indexer().unwrap().progress().unwrap();
+    assert_eq!(progress.pending, 2);
+    assert!(search.search(&lib, &search_args(&["fox"])).is_err());
+    assert_eq!(
+        search
+            .search(&lib, &search_args(&["fox", "--indexing-threshold", "0"]))
+            .unwrap()
+            .len(),
+        1
+    );
+    assert!(index
+        .run(&mut lib, &index_args(&["reindex", "one"]))
+        .is_err());
+
+    index.run(&mut lib, &index_args(&["disable"])).unwrap();
+    assert!(!lib.is_fts_enabled().unwrap());
+    assert!(index.run(&mut lib, &index_args(&["unknown"])).is_err());
+}
@@ -66,13 +66,22 @@ fn test_covers_and_categories() {
     // We assume ID 1
     let book_id = 1;
 
-    // Set Cover
-    let fake_image_data = b"fake image data";
-    covers::set_cover(&cache, book_id, fake_image_data).expect("set_cover failed");
+    // Set Cover, anything that is not an image is refused
+    assert!(covers::set_cover(&cache, book_id, b"fake image data").is_err());
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgba8(image::RgbaImage::new(30, 40))
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    covers::set_cover(&cache, book_id, &png).expect("set_cover failed");
 
-    // Verify File Exists
+    // Verify File Exists, as a JPEG
     let cover_path = covers::cover_path(&cache, book_id).expect("cover_path failed");
     assert!(cover_path.exists());
     let read_data = fs::read(cover_path).unwrap();
-    assert_eq!(read_data, fake_image_data);
+    assert_eq!(
+        image::guess_format(&read_data).unwrap(),
+        image::ImageFormat::Jpeg
+    );
+    let cover = image::load_from_memory(&read_data).unwrap();
+    assert_eq!((cover.width(), cover.height()), (30, 40));
 }
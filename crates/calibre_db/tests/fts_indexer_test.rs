@@ -0,0 +1,173 @@
+use calibre_db::backend::Backend;
+use calibre_db::fts::connection::{FtsConnection, SearchOptions};
+use calibre_db::fts::indexer::{FtsIndexer, IndexOutcome};
+use calibre_db::fts::text::html_to_text;
+use calibre_db::listeners::{Event, EventDispatcher};
+use calibre_db::Library;
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::tempdir;
+
+fn library(dir: &Path) -> (Arc<FtsConnection>, FtsIndexer) {
+    let backend = Backend::new(dir).unwrap();
+    backend
+        .conn
+        .lock()
+        .unwrap()
+        .execute_batch(
+            "CREATE TABLE books (id INTEGER PRIMARY KEY, title TEXT, path TEXT);
+             CREATE TABLE data (id INTEGER PRIMARY KEY, book INTEGER, format TEXT,
+                                uncompressed_size INTEGER, name TEXT);
+             INSERT INTO books (id, title, path) VALUES (1, 'Fox', 'Aesop/Fox (1)');
+             INSERT INTO data (book, format, uncompressed_size, name) VALUES (1, 'TXT', 0, 'Fox');",
+        )
+        .unwrap();
+    let book_dir = dir.join("Aesop/Fox (1)");
+    fs::create_dir_all(&book_dir).unwrap();
+    fs::write(
+        book_dir.join("Fox.txt"),
+        "The quick brown fox jumps over the lazy dog.",
+    )
+    .unwrap();
+
+    let fts = Arc::new(FtsConnection::new(backend.conn.clone(), &backend.db_path));
+    fts.initialize().unwrap();
+    let indexer = FtsIndexer::new(fts.clone(), dir);
+    (fts, indexer)
+}
+
+#[test]
+fn test_index_library_formats() {
+    let dir = tempdir().unwrap();
+    let (fts, indexer) = library(dir.path());
+
+    // A new index queues what is already in the library
+    let progress = indexer.progress().unwrap();
+    assert_eq!((progress.indexed, progress.pending), (0, 1));
+    let (book_id, format, outcome) = indexer.index_next().unwrap().unwrap();
+    assert_eq!((book_id, format.as_str()), (1, "TXT"));
+    assert_eq!(outcome, IndexOutcome::Indexed);
+    assert!(indexer.index_next().unwrap().is_none());
+    let progress = indexer.progress().unwrap();
+    assert_eq!((progress.indexed, progress.pending), (1, 0));
+
+    let options = SearchOptions {
+        return_text: true,
+        highlight: Some((">>>".to_string(), "<<<".to_string())),
+        snippet_size: Some(4),
+        ..Default::default()
+    };
+    let results = fts.search_with("fox", &options).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].book_id, 1);
+    assert!(results[0].text.contains(">>>fox<<<"), "{}", results[0].text);
+    let stemmed = SearchOptions {
+        use_stemming: true,
+        ..Default::default()
+    };
+    assert_eq!(fts.search_with("jumping", &stemmed).unwrap().len(), 1);
+    assert!(fts.search("jumping").unwrap().is_empty());
+    let elsewhere = SearchOptions {
+        restrict_to_book_ids: Some(vec![2]),
+        ..Default::default()
+    };
+    assert!(fts.search_with("fox", &elsewhere).unwrap().is_empty());
+
+    // An unchanged file is not extracted again
+    let dispatcher = EventDispatcher::new();
+    dispatcher.register(Box::new(indexer.clone()));
+    dispatcher.dispatch(Event::FormatAdded {
+        book_id: 1,
+        format: "txt".to_string(),
+    });
+    assert_eq!(
+        indexer.index_next().unwrap().unwrap().2,
+        IndexOutcome::Unchanged
+    );
+
+    // Until it changes or a reindex is asked for
+    fs::write(
+        dir.path().join("Aesop/Fox (1)/Fox.txt"),
+        "The cat sat on the mat.",
+    )
+    .unwrap();
+    indexer.dirty_book(1, &[]).unwrap();
+    assert_eq!(indexer.index_all().unwrap(), 1);
+    assert!(fts.search("fox").unwrap().is_empty());
+    assert_eq!(fts.search("cat").unwrap().len(), 1);
+    indexer.reindex(&[]).unwrap();
+    assert_eq!(
+        indexer.index_next().unwrap().unwrap().2,
+        IndexOutcome::Indexed
+    );
+
+    dispatcher.dispatch(Event::BookRemoved { book_ids: vec![1] });
+    assert!(fts.search("cat").unwrap().is_empty());
+    assert_eq!(indexer.progress().unwrap().indexed, 0);
+}
+
+#[test]
+fn test_library_keeps_index_current() {
+    let dir = tempdir().unwrap();
+    let mut lib = Library::create(dir.path().to_path_buf()).unwrap();
+    lib.conn()
+        .execute(
+            "INSERT INTO books (id, title, sort, author_sort, series_index, has_cover, path) \
+             VALUES (1, 'Fox', 'Fox', 'Aesop', 1.0, 0, 'Aesop/Fox (1)')",
+            [],
+        )
+        .unwrap();
+    lib.enable_fts(true).unwrap();
+    let indexer = lib.fts_indexer().unwrap();
+    assert_eq!(indexer.index_all().unwrap(), 0);
+
+    // Formats added after indexing was turned on are queued
+    let source = dir.path().join("fox.txt");
+    fs::write(&source, "The quick brown fox.").unwrap();
+    assert!(lib.add_format(1, &source, "TXT", true).unwrap());
+    assert_eq!(indexer.index_all().unwrap(), 1);
+    assert_eq!(indexer.fts().search("quick").unwrap().len(), 1);
+
+    lib.remove_format(1, "TXT").unwrap();
+    assert!(indexer.fts().search("quick").unwrap().is_empty());
+
+    // Books moved to the trash leave the index too
+    assert!(lib.add_format(1, &source, "TXT", true).unwrap());
+    assert_eq!(indexer.index_all().unwrap(), 1);
+    lib.remove_books(&[1], false).unwrap();
+    assert!(indexer.fts().search("quick").unwrap().is_empty());
+    assert_eq!(indexer.progress().unwrap().indexed, 0);
+}
+
+#[test]
+fn test_background_indexing() {
+    let dir = tempdir().unwrap();
+    let (fts, indexer) = library(dir.path());
+    let (tx, rx) = mpsc::channel();
+    indexer.set_throttle(Duration::from_millis(1));
+    indexer.set_progress_callback(move |progress| {
+        let _ = tx.send(progress);
+    });
+
+    indexer.start();
+    assert!(indexer.is_running());
+    let progress = rx.recv_timeout(Duration::from_secs(30)).unwrap();
+    assert_eq!((progress.indexed, progress.pending), (1, 0));
+    indexer.stop();
+    assert!(!indexer.is_running());
+    assert_eq!(fts.search("dog").unwrap().len(), 1);
+}
+
+#[test]
+fn test_html_to_text() {
+    let html = "<html><head><title>Skip me</title><style>p {}</style></head>\
+                <body><h1>Chapter&#160;1</h1><p>Fish &amp; chips<script>x()</script></p>\
+                <!-- note --><p>Second <i>para</i></p></body></html>";
+    assert_eq!(
+        html_to_text(html),
+        "Chapter\u{a0}1\n\nFish & chips\n\nSecond para"
+    );
+}
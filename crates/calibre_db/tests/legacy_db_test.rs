@@ -11,6 +11,6 @@ fn test_legacy_check() {
     // Non-existent DB is compatible (fresh start)
     assert!(legacy.check_compatibility(&db_path).unwrap());
 
-    // Migration always fails (stub)
+    // There is nothing to migrate
     assert!(legacy.migrate(&db_path).is_err());
 }
@@ -0,0 +1,108 @@
+use calibre_db::cli::cmd_set_metadata::CmdSetMetadata;
+use calibre_db::library::Library;
+use calibre_ebooks::metadata::MetaInformation;
+use std::fs;
+use std::io::Cursor;
+use tempfile::tempdir;
+
+fn book(title: &str) -> MetaInformation {
+    MetaInformation {
+        title: title.to_string(),
+        authors: vec!["Some Author".to_string()],
+        ..Default::default()
+    }
+}
+
+fn cover_of(lib: &Library, book_id: i32) -> image::DynamicImage {
+    let book = lib.get_book(book_id).unwrap().unwrap();
+    let path = lib.get_cover_path(&book).expect("book has no cover");
+    let data = fs::read(path).unwrap();
+    assert_eq!(
+        image::guess_format(&data).unwrap(),
+        image::ImageFormat::Jpeg
+    );
+    image::load_from_memory(&data).unwrap()
+}
+
+#[test]
+fn test_add_book_generates_missing_cover() {
+    let dir = tempdir().unwrap();
+    let mut lib = Library::create(dir.path().to_path_buf()).unwrap();
+    let source = dir.path().join("source.txt");
+    fs::write(&source, "Some text").unwrap();
+
+    let book_id = lib.add_book(&source, &book("Plain Text")).unwrap();
+
+    assert!(lib.has_cover(book_id).unwrap());
+    let cover = cover_of(&lib, book_id);
+    assert!(cover.height() > cover.width());
+}
+
+#[test]
+fn test_add_book_keeps_embedded_cover() {
+    let dir = tempdir().unwrap();
+    let mut lib = Library::create(dir.path().to_path_buf()).unwrap();
+    let source = dir.path().join("source.epub");
+    fs::write(&source, "dummy content").unwrap();
+
+    let mut png = Vec::new();
+    image::DynamicImage::new_rgb8(30, 40)
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .unwrap();
+    let mut mi = book("With Cover");
+    mi.cover_data = (Some("png".to_string()), png);
+
+    let book_id = lib.add_book(&source, &mi).unwrap();
+
+    assert!(lib.has_cover(book_id).unwrap());
+    let cover = cover_of(&lib, book_id);
+    assert_eq!((cover.width(), cover.height()), (30, 40));
+}
+
+#[test]
+fn test_set_metadata_generate_cover() {
+    let dir = tempdir().unwrap();
+    let mut lib = Library::create(dir.path().to_path_buf()).unwrap();
+    let source = dir.path().join("source.pdf");
+    fs::write(&source, "dummy content").unwrap();
+    let book_id = lib.add_book(&source, &book("Old Title")).unwrap();
+    let book = lib.get_book(book_id).unwrap().unwrap();
+    let cover_path = lib.get_cover_path(&book).unwrap();
+    let before = fs::read(&cover_path).unwrap();
+
+    CmdSetMetadata::new()
+        .run(
+            &mut lib,
+            &[
+                book_id.to_string(),
+                "title".to_string(),
+                "A Completely Different Title".to_string(),
+                "--generate-cover".to_string(),
+            ],
+        )
+        .unwrap();
+
+    assert_eq!(
+        lib.get_book(book_id).unwrap().unwrap().title,
+        "A Completely Different Title"
+    );
+    assert!(lib.has_cover(book_id).unwrap());
+    let book = lib.get_book(book_id).unwrap().unwrap();
+    let after = fs::read(lib.get_cover_path(&book).unwrap()).unwrap();
+    assert_ne!(before, after);
+
+    // Only generating a cover needs no field, but a valid book
+    let cmd = CmdSetMetadata::new();
+    cmd.run(
+        &mut lib,
+        &[book_id.to_string(), "--generate-cover".to_string()],
+    )
+    .unwrap();
+    assert!(cmd
+        .run(
+            &mut lib,
+            &["999".to_string(), "--generate-cover".to_string()]
+        )
+        .is_err());
+    assert!(cmd.run(&mut lib, &[book_id.to_string()]).is_err());
+}
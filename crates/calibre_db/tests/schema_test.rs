@@ -1,4 +1,5 @@
-use calibre_db::schema_upgrades::SchemaUpgrade;
+use calibre_db::legacy::LegacyDB;
+use calibre_db::schema_upgrades::{SchemaError, SchemaUpgrade, LATEST_VERSION};
 use rusqlite::Connection;
 use tempfile::tempdir;
 
@@ -19,3 +20,193 @@ fn test_schema_upgrade_stub() {
     let result = SchemaUpgrade::upgrade_to_latest(&mut conn, dir.path());
     assert!(result.is_ok());
 }
+
+/// A library as the first calibre releases with metadata.db left it,
+/// before the schema carried a version.
+fn create_v1_library(db_path: &std::path::Path) {
+    let conn = Connection::open(db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE books ( id INTEGER PRIMARY KEY AUTOINCREMENT,
+                              title TEXT NOT NULL DEFAULT 'Unknown' COLLATE NOCASE,
+                              sort TEXT COLLATE NOCASE,
+                              timestamp TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                              series_index INTEGER NOT NULL DEFAULT 1,
+                              author_sort TEXT COLLATE NOCASE,
+                              isbn TEXT DEFAULT '' COLLATE NOCASE,
+                              path TEXT NOT NULL DEFAULT '');
+         CREATE TABLE authors ( id INTEGER PRIMARY KEY, name TEXT NOT NULL COLLATE NOCASE,
+                                sort TEXT COLLATE NOCASE, UNIQUE(name));
+         CREATE TABLE books_authors_link ( id INTEGER PRIMARY KEY, book INTEGER NOT NULL,
+                                           author INTEGER NOT NULL, UNIQUE(book, author));
+         CREATE TABLE publishers ( id INTEGER PRIMARY KEY, name TEXT NOT NULL COLLATE NOCASE,
+                                   sort TEXT COLLATE NOCASE, UNIQUE(name));
+         CREATE TABLE books_publishers_link ( id INTEGER PRIMARY KEY, book INTEGER NOT NULL,
+                                              publisher INTEGER NOT NULL, UNIQUE(book));
+         CREATE TABLE ratings ( id INTEGER PRIMARY KEY, rating INTEGER CHECK(rating > -1 AND rating < 11),
+                                UNIQUE (rating));
+         CREATE TABLE books_ratings_link ( id INTEGER PRIMARY KEY, book INTEGER NOT NULL,
+                                           rating INTEGER NOT NULL, UNIQUE(book, rating));
+         CREATE TABLE tags ( id INTEGER PRIMARY KEY, name TEXT NOT NULL COLLATE NOCASE, UNIQUE (name));
+         CREATE TABLE books_tags_link ( id INTEGER PRIMARY KEY, book INTEGER NOT NULL,
+                                        tag INTEGER NOT NULL, UNIQUE(book, tag));
+         CREATE TABLE series ( id INTEGER PRIMARY KEY, name TEXT NOT NULL COLLATE NOCASE,
+                               sort TEXT COLLATE NOCASE, UNIQUE (name));
+         CREATE TABLE books_series_link ( id INTEGER PRIMARY KEY, book INTEGER NOT NULL,
+                                          series INTEGER NOT NULL, UNIQUE(book));
+         CREATE TABLE data ( id INTEGER PRIMARY KEY, book INTEGER NOT NULL, format TEXT NOT NULL,
+                             uncompressed_size INTEGER NOT NULL, name TEXT NOT NULL);
+         CREATE TABLE comments ( id INTEGER PRIMARY KEY, book INTEGER NOT NULL, text TEXT NOT NULL,
+                                 UNIQUE(book));
+         CREATE TABLE conversion_options ( id INTEGER PRIMARY KEY, format TEXT NOT NULL,
+                                           book INTEGER, data BLOB NOT NULL, UNIQUE(format,book));
+         CREATE TABLE feeds ( id INTEGER PRIMARY KEY, title TEXT NOT NULL, script TEXT NOT NULL,
+                              UNIQUE(title));
+
+         INSERT INTO books (id, title, sort, series_index, author_sort, isbn, path) VALUES
+            (1, 'The Hobbit', NULL, 1, 'Tolkien, J. R. R.', '9780261102217', 'Tolkien/The Hobbit (1)'),
+            (2, 'Dune', 'Dune', 1, 'Herbert, Frank', '', 'Frank Herbert/Dune (2)');
+         INSERT INTO authors (id, name, sort) VALUES
+            (1, 'J. R. R. Tolkien', NULL), (2, 'Frank Herbert', NULL), (3, 'Nobody', NULL);
+         INSERT INTO books_authors_link (book, author) VALUES (1, 1), (2, 2);
+         INSERT INTO tags (id, name) VALUES (1, 'Fantasy, Classic');
+         INSERT INTO books_tags_link (book, tag) VALUES (1, 1);
+         INSERT INTO series (id, name) VALUES (1, 'The Middle-earth');
+         INSERT INTO books_series_link (book, series) VALUES (1, 1);
+         INSERT INTO data (book, format, uncompressed_size, name) VALUES (1, 'EPUB', 1000, 'hobbit');
+         INSERT INTO comments (book, text) VALUES (1, 'There and back again');",
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_upgrade_unversioned_library() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("metadata.db");
+    create_v1_library(&db_path);
+    let cover_dir = dir.path().join("Tolkien/The Hobbit (1)");
+    std::fs::create_dir_all(&cover_dir).unwrap();
+    std::fs::write(cover_dir.join("cover.jpg"), b"jpeg").unwrap();
+
+    let mut conn = Connection::open(&db_path).unwrap();
+    assert_eq!(SchemaUpgrade::detect_version(&conn).unwrap(), Some(1));
+    let version = SchemaUpgrade::upgrade_to_latest(&mut conn, dir.path()).unwrap();
+    assert_eq!(version, Some(LATEST_VERSION));
+    assert!(dir.path().join("metadata_pre_v2_upgrade.db").exists());
+
+    let user_version: i32 = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(user_version, LATEST_VERSION);
+
+    // Data carried over and new columns filled in
+    let (sort, uuid, has_cover, pubdate): (String, String, bool, String) = conn
+        .query_row(
+            "SELECT sort, uuid, has_cover, pubdate FROM books WHERE id = 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .unwrap();
+    assert_eq!(sort, "Hobbit, The");
+    assert_eq!(uuid.len(), 36);
+    assert!(has_cover);
+    assert!(!pubdate.is_empty());
+    let isbn: String = conn
+        .query_row(
+            "SELECT val FROM identifiers WHERE book = 1 AND type = 'isbn'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(isbn, "9780261102217");
+    let tag: String = conn
+        .query_row("SELECT name FROM tags WHERE id = 1", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(tag, "Fantasy; Classic");
+    let series_sort: String = conn
+        .query_row("SELECT sort FROM series WHERE id = 1", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(series_sort, "Middle-earth, The");
+    // Step 2 dropped authors without books
+    let authors: i32 = conn
+        .query_row("SELECT COUNT(*) FROM authors", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(authors, 2);
+
+    // Views and triggers work with the registered functions
+    let (authors, tags, formats): (String, String, String) = conn
+        .query_row(
+            "SELECT authors, tags, formats FROM meta WHERE id = 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!(authors, "J. R. R. Tolkien");
+    assert_eq!(tags, "Fantasy; Classic");
+    assert_eq!(formats, "EPUB");
+    conn.execute(
+        "INSERT INTO books (title) VALUES ('A Wizard of Earthsea')",
+        [],
+    )
+    .unwrap();
+    let sort: String = conn
+        .query_row("SELECT sort FROM books WHERE id = 3", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(sort, "Wizard of Earthsea, A");
+    conn.execute("INSERT INTO authors (name) VALUES ('Ursula Vernon')", [])
+        .unwrap();
+    let sort: String = conn
+        .query_row(
+            "SELECT sort FROM authors WHERE name = 'Ursula Vernon'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(sort, "Vernon, Ursula");
+    conn.execute("DELETE FROM books WHERE id = 1", []).unwrap();
+    let links: i32 = conn
+        .query_row("SELECT COUNT(*) FROM books_tags_link", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(links, 0);
+
+    // Opening again is a no-op
+    drop(conn);
+    let mut conn = Connection::open(&db_path).unwrap();
+    assert_eq!(
+        SchemaUpgrade::detect_version(&conn).unwrap(),
+        Some(LATEST_VERSION)
+    );
+    assert_eq!(
+        SchemaUpgrade::upgrade_to_latest(&mut conn, dir.path()).unwrap(),
+        Some(LATEST_VERSION)
+    );
+}
+
+#[test]
+fn test_refuse_newer_library() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("metadata.db");
+    create_v1_library(&db_path);
+    {
+        let conn = Connection::open(&db_path).unwrap();
+        conn.pragma_update(None, "user_version", LATEST_VERSION + 1)
+            .unwrap();
+    }
+
+    let mut conn = Connection::open(&db_path).unwrap();
+    let err = SchemaUpgrade::upgrade_to_latest(&mut conn, dir.path()).unwrap_err();
+    assert!(matches!(err, SchemaError::TooNew { found: 27, .. }));
+    assert!(LegacyDB::new().check_compatibility(&db_path).is_err());
+    assert!(calibre_db::Library::open(dir.path().to_path_buf()).is_err());
+}
+
+#[test]
+fn test_legacy_migrate() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("metadata.db");
+    create_v1_library(&db_path);
+
+    let legacy = LegacyDB::new();
+    assert!(!legacy.check_compatibility(&db_path).unwrap());
+    legacy.migrate(&db_path).unwrap();
+    assert!(legacy.check_compatibility(&db_path).unwrap());
+}
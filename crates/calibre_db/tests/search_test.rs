@@ -0,0 +1,136 @@
+use calibre_db::search::Search;
+use calibre_db::Library;
+
+fn library() -> Library {
+    let mut lib = Library::open_test().unwrap();
+    lib.conn()
+        .execute_batch(
+            "CREATE TABLE tags (id INTEGER PRIMARY KEY, name TEXT);
+             CREATE TABLE books_tags_link (id INTEGER PRIMARY KEY, book INTEGER, tag INTEGER);
+             CREATE TABLE series (id INTEGER PRIMARY KEY, name TEXT);
+             CREATE TABLE books_series_link (id INTEGER PRIMARY KEY, book INTEGER, series INTEGER);
+             CREATE TABLE ratings (id INTEGER PRIMARY KEY, rating INTEGER);
+             CREATE TABLE books_ratings_link (id INTEGER PRIMARY KEY, book INTEGER, rating INTEGER);
+             CREATE TABLE identifiers (id INTEGER PRIMARY KEY, book INTEGER, type TEXT, val TEXT);
+
+             INSERT INTO books (id, title, sort, author_sort, pubdate, series_index, has_cover, path) VALUES
+                (1, 'The Rust Book', 'Rust Book, The', 'Klabnik, Steve', '1999-05-03 00:00:00+00:00', 1.0, 1, ''),
+                (2, 'Programming Python', 'Programming Python', 'Lutz, Mark', '2010-01-01 00:00:00+00:00', 2.0, 0, ''),
+                (3, 'Dune', 'Dune', 'Herbert, Frank', '1965-08-01 00:00:00+00:00', 1.0, 1, '');
+             INSERT INTO authors (id, name, sort) VALUES
+                (1, 'Steve Klabnik', 'Klabnik, Steve'), (2, 'Carol Nichols', 'Nichols, Carol'),
+                (3, 'Mark Lutz', 'Lutz, Mark'), (4, 'Frank Herbert', 'Herbert, Frank');
+             INSERT INTO books_authors_link (book, author) VALUES (1, 1), (1, 2), (2, 3), (3, 4);
+             INSERT INTO tags (id, name) VALUES (1, 'Programming'), (2, 'Fiction'), (3, 'Science Fiction');
+             INSERT INTO books_tags_link (book, tag) VALUES (1, 1), (2, 1), (3, 2), (3, 3);
+             INSERT INTO series (id, name) VALUES (1, 'Dune Chronicles');
+             INSERT INTO books_series_link (book, series) VALUES (3, 1);
+             INSERT INTO ratings (id, rating) VALUES (1, 8), (2, 4);
+             INSERT INTO books_ratings_link (book, rating) VALUES (1, 1), (3, 1), (2, 2);
+             INSERT INTO identifiers (book, type, val) VALUES
+                (1, 'isbn', '9781593278281'), (3, 'goodreads', '234225');
+             INSERT INTO data (book, format, uncompressed_size, name) VALUES
+                (1, 'EPUB', 2000000, 'rust'), (1, 'PDF', 500, 'rust'), (3, 'MOBI', 1000, 'dune');",
+        )
+        .unwrap();
+    lib.add_custom_column("read", "Read", "bool", false)
+        .unwrap();
+    lib.add_custom_column("shelf", "Shelf", "text", false)
+        .unwrap();
+    lib.set_custom_column_value(1, "read", "true").unwrap();
+    lib.set_custom_column_value(2, "read", "false").unwrap();
+    lib.set_custom_column_value(3, "shelf", "Living room")
+        .unwrap();
+    lib
+}
+
+#[test]
+fn test_search_text_fields() {
+    let lib = library();
+    assert_eq!(lib.search("").unwrap(), vec![1, 2, 3]);
+    assert_eq!(lib.search("rust").unwrap(), vec![1]);
+    assert_eq!(lib.search("programming").unwrap(), vec![1, 2]);
+    assert_eq!(lib.search("authors:nichols").unwrap(), vec![1]);
+    assert_eq!(lib.search("tags:=fiction").unwrap(), vec![3]);
+    assert_eq!(lib.search("tags:fiction").unwrap(), vec![3]);
+    assert_eq!(lib.search("series:dune").unwrap(), vec![3]);
+    assert_eq!(lib.search("series:false").unwrap(), vec![1, 2]);
+    assert_eq!(lib.search("title:~^the\\s").unwrap(), vec![1]);
+    assert_eq!(lib.search("title:\"=dune\"").unwrap(), vec![3]);
+    assert_eq!(lib.search("formats:pdf").unwrap(), vec![1]);
+    assert_eq!(lib.search("formats:false").unwrap(), vec![2]);
+    assert!(lib.search("title:~(").is_err());
+}
+
+#[test]
+fn test_search_boolean_operators() {
+    let lib = library();
+    assert_eq!(
+        lib.search("tags:programming and not rust").unwrap(),
+        vec![2]
+    );
+    assert_eq!(lib.search("rust or dune").unwrap(), vec![1, 3]);
+    assert_eq!(lib.search("tags:programming python").unwrap(), vec![2]);
+    assert_eq!(lib.search("not (rust or dune)").unwrap(), vec![2]);
+    assert!(lib.search("(rust").is_err());
+}
+
+#[test]
+fn test_search_numbers_and_dates() {
+    let lib = library();
+    assert_eq!(lib.search("rating:>3").unwrap(), vec![1, 3]);
+    assert_eq!(lib.search("rating:2").unwrap(), vec![2]);
+    assert_eq!(lib.search("rating:<=2").unwrap(), vec![2]);
+    assert_eq!(lib.search("series_index:>=2").unwrap(), vec![2]);
+    assert_eq!(lib.search("size:>1m").unwrap(), vec![1]);
+    assert_eq!(lib.search("id:!=2").unwrap(), vec![1, 3]);
+    assert_eq!(lib.search("pubdate:<2000").unwrap(), vec![1, 3]);
+    assert_eq!(lib.search("pubdate:1999").unwrap(), vec![1]);
+    assert_eq!(lib.search("pubdate:>=1999-06").unwrap(), vec![2]);
+    assert_eq!(lib.search("pubdate:=1965-08-01").unwrap(), vec![3]);
+    assert_eq!(lib.search("cover:true").unwrap(), vec![1, 3]);
+    assert!(lib.search("rating:>many").is_err());
+}
+
+#[test]
+fn test_search_identifiers_and_custom_columns() {
+    let lib = library();
+    assert_eq!(lib.search("identifiers:isbn:978159").unwrap(), vec![1]);
+    assert_eq!(lib.search("identifiers:goodreads:").unwrap(), vec![3]);
+    assert_eq!(lib.search("identifiers:true").unwrap(), vec![1, 3]);
+    assert_eq!(lib.search("identifiers:isbn:false").unwrap(), vec![2, 3]);
+    assert_eq!(lib.search("isbn:9781593278281").unwrap(), vec![1]);
+    assert_eq!(lib.search("#read:true").unwrap(), vec![1]);
+    assert_eq!(lib.search("#read:false").unwrap(), vec![2]);
+    assert_eq!(lib.search("#shelf:living").unwrap(), vec![3]);
+    assert_eq!(lib.search("#shelf:false").unwrap(), vec![1, 2]);
+}
+
+#[test]
+fn test_saved_searches_and_virtual_libraries() {
+    let mut lib = library();
+    lib.set_preference(
+        "saved_searches",
+        r#"{"Code": "tags:programming", "Loop": "search:loop"}"#,
+    )
+    .unwrap();
+    lib.set_preference("virtual_libraries", r#"{"Good": "rating:>=4"}"#)
+        .unwrap();
+
+    assert_eq!(lib.search("search:code").unwrap(), vec![1, 2]);
+    assert_eq!(lib.search("search:code and rating:>3").unwrap(), vec![1]);
+    assert_eq!(lib.search("vl:Good").unwrap(), vec![1, 3]);
+    assert_eq!(
+        lib.search_restricted("tags:programming", Some("Good"))
+            .unwrap(),
+        vec![1]
+    );
+    assert!(lib.search("search:loop").is_err());
+    assert!(lib.search("search:missing").is_err());
+
+    // The engine keeps loaded fields between searches
+    let mut search = Search::new(lib.conn());
+    assert_eq!(search.search("tags:fiction", None).unwrap(), vec![3]);
+    assert_eq!(search.search("tags:programming", None).unwrap(), vec![1, 2]);
+    assert!(search.locations().contains(&"#shelf".to_string()));
+}
@@ -0,0 +1,142 @@
+use calibre_db::cli::cmd_trash::{CmdTrash, RunArgs};
+use calibre_db::Library;
+use calibre_ebooks::metadata::MetaInformation;
+use clap::Parser;
+use std::fs;
+use std::time::Duration;
+use tempfile::{tempdir, TempDir};
+
+fn setup() -> (TempDir, Library, i32) {
+    let dir = tempdir().unwrap();
+    let mut lib = Library::create(dir.path().to_path_buf()).unwrap();
+    let src = dir.path().join("source.txt");
+    fs::write(&src, "Once upon a time").unwrap();
+    let meta = MetaInformation {
+        title: "Fables".to_string(),
+        authors: vec!["Aesop".to_string()],
+        ..Default::default()
+    };
+    let book_id = lib.add_book(&src, &meta).unwrap();
+    fs::remove_file(&src).unwrap();
+    (dir, lib, book_id)
+}
+
+fn trash_args(args: &[&str]) -> RunArgs {
+    RunArgs::parse_from(std::iter::once("trash").chain(args.iter().copied()))
+}
+
+#[test]
+fn test_remove_and_restore_book() {
+    let (dir, mut lib, book_id) = setup();
+    let book_dir = dir
+        .path()
+        .join(lib.get_book(book_id).unwrap().unwrap().path);
+
+    lib.remove_books(&[book_id], false).unwrap();
+    assert!(lib.get_book(book_id).unwrap().is_none());
+    assert!(!book_dir.exists());
+    assert!(!dir.path().join("Aesop").exists());
+
+    let (books, files) = lib.list_trash().unwrap();
+    assert!(files.is_empty());
+    assert_eq!(books.len(), 1);
+    assert_eq!(books[0].book_id, book_id);
+    assert_eq!(books[0].title, "Fables");
+    assert_eq!(books[0].author, "Aesop");
+    assert_eq!(books[0].formats, vec!["TXT"]);
+    assert!(lib.trash().book_dir(book_id).join("metadata.opf").exists());
+
+    CmdTrash::new()
+        .run(&mut lib, &trash_args(&["restore", &book_id.to_string()]))
+        .unwrap();
+    let book = lib.get_book(book_id).unwrap().unwrap();
+    assert_eq!(book.title, "Fables");
+    assert_eq!(lib.get_authors(book_id).unwrap(), vec!["Aesop"]);
+    assert_eq!(lib.formats(book_id).unwrap(), vec!["TXT"]);
+    assert_eq!(
+        fs::read_to_string(dir.path().join(&book.path).join("Fables.txt")).unwrap(),
+        "Once upon a time"
+    );
+    let (books, _) = lib.list_trash().unwrap();
+    assert!(books.is_empty());
+}
+
+#[test]
+fn test_permanent_remove_skips_trash() {
+    let (_dir, mut lib, book_id) = setup();
+    lib.remove_books(&[book_id], true).unwrap();
+    assert!(lib.get_book(book_id).unwrap().is_none());
+    let (books, files) = lib.list_trash().unwrap();
+    assert!(books.is_empty() && files.is_empty());
+}
+
+#[test]
+fn test_remove_and_restore_format() {
+    let (dir, mut lib, book_id) = setup();
+    lib.remove_format(book_id, "txt").unwrap();
+    assert!(lib.formats(book_id).unwrap().is_empty());
+
+    let (books, files) = lib.list_trash().unwrap();
+    assert!(books.is_empty());
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].book_id, book_id);
+    assert_eq!(files[0].title, "Fables");
+    assert_eq!(files[0].formats, vec!["TXT"]);
+
+    // A format cannot be restored into a book that is not there
+    assert!(lib.restore_format_from_trash(book_id + 1, "TXT").is_err());
+    assert!(lib.restore_format_from_trash(book_id, "EPUB").is_err());
+
+    CmdTrash::new()
+        .run(
+            &mut lib,
+            &trash_args(&["restore", &format!("{}:txt", book_id)]),
+        )
+        .unwrap();
+    assert_eq!(lib.formats(book_id).unwrap(), vec!["TXT"]);
+    let book = lib.get_book(book_id).unwrap().unwrap();
+    assert!(dir.path().join(&book.path).join("Fables.txt").exists());
+    assert!(!lib.trash().formats_dir(book_id).exists());
+}
+
+#[test]
+fn test_restore_keeps_id_only_when_free() {
+    let (_dir, mut lib, book_id) = setup();
+    lib.remove_books(&[book_id], false).unwrap();
+    lib.conn()
+        .execute(
+            "INSERT INTO books (id, title, sort, path, series_index) \
+                 VALUES (?1, 'Other', 'Other', '', 1.0)",
+            [book_id],
+        )
+        .unwrap();
+
+    let restored = lib.restore_book_from_trash(book_id).unwrap();
+    assert_ne!(restored, book_id);
+    assert_eq!(lib.get_book(restored).unwrap().unwrap().title, "Fables");
+    assert_eq!(lib.formats(restored).unwrap(), vec!["TXT"]);
+    assert!(lib.restore_book_from_trash(book_id).is_err());
+}
+
+#[test]
+fn test_expiry_and_empty() {
+    let (_dir, mut lib, book_id) = setup();
+    lib.remove_format(book_id, "txt").unwrap();
+    lib.remove_books(&[book_id], false).unwrap();
+
+    // Fresh entries outlive the expiry
+    assert_eq!(lib.trash_expiry().unwrap(), Duration::from_secs(14 * 86400));
+    assert_eq!(lib.expire_old_trash().unwrap(), 0);
+    // and the expiry is never shorter than a day
+    lib.set_trash_expiry(Duration::from_secs(60)).unwrap();
+    assert_eq!(lib.trash_expiry().unwrap(), Duration::from_secs(86400));
+    assert_eq!(lib.expire_old_trash().unwrap(), 0);
+
+    let (books, files) = lib.list_trash().unwrap();
+    assert_eq!((books.len(), files.len()), (1, 1));
+    CmdTrash::new()
+        .run(&mut lib, &trash_args(&["empty"]))
+        .unwrap();
+    let (books, files) = lib.list_trash().unwrap();
+    assert!(books.is_empty() && files.is_empty());
+}
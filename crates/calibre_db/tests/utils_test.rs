@@ -48,3 +48,26 @@ fn test_thumbnail_cache() {
 
     fs::remove_dir_all(&temp_dir).unwrap();
 }
+
+#[test]
+fn test_thumbnail_cache_generates_thumbnails() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let mut cache = ThumbnailCache::new(temp_dir.path().to_path_buf(), 1, (60, 80));
+    assert_eq!(cache.thumbnail_size(), (60, 80));
+
+    let mut cover = Vec::new();
+    image::DynamicImage::ImageRgb8(image::RgbImage::new(300, 200))
+        .write_to(
+            &mut std::io::Cursor::new(&mut cover),
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+    let thumb = cache.insert_cover(1, 100.0, &cover).unwrap();
+    let img = image::load_from_memory(&thumb).unwrap();
+    assert_eq!((img.width(), img.height()), (60, 40));
+
+    let (data, timestamp) = cache.get(1).unwrap();
+    assert_eq!((data, timestamp), (thumb, 100.0));
+    assert!(cache.insert_cover(2, 100.0, b"not an image").is_err());
+    assert!(cache.get(2).is_none());
+}
@@ -33,23 +33,100 @@ fn test_view_search_and_sort() {
     assert_eq!(view.count(), 3);
 
     // Test Search
-    view.search("Rust");
+    view.search("Rust").unwrap();
     assert_eq!(view.count(), 1);
     assert_eq!(view.get_ids(), &[1]);
 
     // Reset (re-create view or implementing clear logic, for now re-create)
     // We didn't implement 'clear_search' in View yet, so simpler to make new view or assume 'search("")' might work if we implemented it that way.
     // Our search implementation uses LIKE, so "%"" is ALL.
-    view.search("");
+    view.search("").unwrap();
     assert_eq!(view.count(), 3);
 
-    view.search("Book");
+    view.search("Book").unwrap();
     assert_eq!(view.count(), 2); // Rust Book, Python Book
 
     // Test Sort (ID only for now)
-    view.sort("id", false); // Descending
+    view.sort("id", false).unwrap(); // Descending
     let ids = view.get_ids();
     assert_eq!(ids, &[2, 1]); // Based on the "Book" search result, IDs were 1 and 2.
                               // Wait, IDs might be returned in DB order (1, 2).
                               // Sort desc -> 2, 1.
 }
+
+#[test]
+fn test_view_multisort_restriction_and_marks() {
+    let dir = tempdir().unwrap();
+    {
+        let backend = Backend::new(dir.path()).unwrap();
+        let conn = backend.conn.lock().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE books (id INTEGER PRIMARY KEY, title TEXT, sort TEXT, author_sort TEXT,
+                 series_index REAL, pubdate TEXT);
+             CREATE TABLE series (id INTEGER PRIMARY KEY, name TEXT);
+             CREATE TABLE books_series_link (id INTEGER PRIMARY KEY, book INTEGER, series INTEGER);
+             CREATE TABLE preferences (id INTEGER PRIMARY KEY, key TEXT UNIQUE, val TEXT);
+             INSERT INTO books (id, title, sort, author_sort, series_index, pubdate) VALUES
+                (1, 'Zebra', 'Zebra', 'Émile, Ann', 2.0, '2001-01-01 00:00:00+00:00'),
+                (2, 'The Apple', 'Apple, The', 'Brown, Bob', 1.0, '1999-01-01 00:00:00+00:00'),
+                (3, 'apricot', '', 'emile, Zed', 1.0, '2005-01-01 00:00:00+00:00'),
+                (4, 'Mango', 'Mango', 'Brown, Bob', 3.0, NULL);
+             INSERT INTO series (id, name) VALUES (1, 'Fruit');
+             INSERT INTO books_series_link (book, series) VALUES (1, 1), (2, 1);",
+        )
+        .unwrap();
+    }
+
+    let cache = Arc::new(Mutex::new(Cache::new(dir.path()).unwrap()));
+    let mut view = View::new(cache);
+    assert_eq!(view.count(), 4);
+
+    // Sort titles ignore articles and case, books without one use the title
+    view.sort("title", true).unwrap();
+    assert_eq!(view.get_ids(), &[2, 3, 4, 1]);
+
+    // Accents do not move an author, ties fall to the second key
+    view.multisort(&[("authors", true), ("title", false)])
+        .unwrap();
+    assert_eq!(view.get_ids(), &[4, 2, 1, 3]);
+
+    view.sort("series", true).unwrap();
+    assert_eq!(view.get_ids(), &[4, 3, 2, 1]);
+    view.sort("pubdate", false).unwrap();
+    assert_eq!(view.get_ids(), &[3, 1, 2, 4]);
+    assert!(view.sort("nonsense", true).is_err());
+    assert_eq!(view.get_ids(), &[3, 1, 2, 4]);
+
+    // Searches keep the sort order and stay inside the virtual library
+    view.set_virtual_library("Brown", "author_sort:brown")
+        .unwrap();
+    view.set_base_restriction(Some("Brown")).unwrap();
+    assert_eq!(view.get_ids(), &[2, 4]);
+    view.search("mango or zebra").unwrap();
+    assert_eq!(view.get_ids(), &[4]);
+    view.search("").unwrap();
+    assert_eq!(view.get_ids(), &[2, 4]);
+    assert!(view.set_base_restriction(Some("Missing")).is_err());
+
+    view.toggle_marked_ids(&[2, 3]);
+    assert!(view.is_marked(3));
+    view.search("marked:true").unwrap();
+    assert_eq!(view.get_ids(), &[2]);
+
+    assert!(view.remove_virtual_library("Brown").unwrap());
+    assert_eq!(view.base_restriction(), None);
+    assert_eq!(view.get_ids(), &[3, 2]);
+    view.toggle_marked_ids(&[2]);
+    assert_eq!(view.marked_ids().len(), 1);
+
+    // The definitions are stored with the library
+    view.set_virtual_library("Old", "pubdate:<2000").unwrap();
+    let cache = Arc::new(Mutex::new(Cache::new(dir.path()).unwrap()));
+    let mut view = View::new(cache);
+    assert_eq!(
+        view.virtual_libraries().get("Old").map(String::as_str),
+        Some("pubdate:<2000")
+    );
+    view.set_base_restriction(Some("Old")).unwrap();
+    assert_eq!(view.get_ids(), &[2]);
+}
@@ -1,2 +1,57 @@
-// Stub for Kobo bookmarks
-pub struct Bookmark;
+//! Reading position and annotations of a book on a Kobo.
+
+use crate::kobo::books::ReadStatus;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnotationKind {
+    Highlight,
+    Note,
+    /// A dog-eared page
+    Dogear,
+}
+
+impl AnnotationKind {
+    /// Older databases have no `Type` column, so fall back on the content.
+    pub fn from_db(kind: Option<&str>, text: Option<&str>, note: Option<&str>) -> Self {
+        match kind {
+            Some("note") => AnnotationKind::Note,
+            Some("dogear") => AnnotationKind::Dogear,
+            Some("highlight") => AnnotationKind::Highlight,
+            _ if note.is_some_and(|n| !n.is_empty()) => AnnotationKind::Note,
+            _ if text.is_some_and(|t| !t.is_empty()) => AnnotationKind::Highlight,
+            _ => AnnotationKind::Dogear,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub id: String,
+    /// The chapter the annotation is in
+    pub content_id: String,
+    pub kind: AnnotationKind,
+    pub text: Option<String>,
+    pub note: Option<String>,
+    /// Position in the chapter, 0.0 to 1.0
+    pub chapter_progress: f64,
+    pub date_created: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bookmark {
+    pub content_id: String,
+    pub read_status: ReadStatus,
+    pub percent_read: i64,
+    pub last_read: Option<String>,
+    /// Chapter the book was last open at
+    pub chapter: Option<String>,
+    pub annotations: Vec<Annotation>,
+}
+
+impl Bookmark {
+    pub fn highlights(&self) -> impl Iterator<Item = &Annotation> {
+        self.annotations
+            .iter()
+            .filter(|a| a.kind != AnnotationKind::Dogear)
+    }
+}
@@ -1,2 +1,98 @@
-// Stub for Kobo books
-pub struct Book;
+//! Books as the Kobo firmware records them in the `content` table.
+
+/// ContentID prefix of books sideloaded into main memory.
+pub const MAIN_CONTENT_PREFIX: &str = "file:///mnt/onboard/";
+/// ContentID prefix of books sideloaded onto the SD card.
+pub const CARD_CONTENT_PREFIX: &str = "file:///mnt/sd/";
+/// Where the firmware keeps the kepubs bought from the Kobo store.
+pub const KEPUB_DIR: &str = ".kobo/kepub";
+
+/// `content.ContentType` of a book, chapters use 9.
+pub const CONTENT_TYPE_BOOK: i64 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadStatus {
+    Unread,
+    Reading,
+    Finished,
+}
+
+impl ReadStatus {
+    pub fn from_db(value: i64) -> Self {
+        match value {
+            1 => ReadStatus::Reading,
+            2 => ReadStatus::Finished,
+            _ => ReadStatus::Unread,
+        }
+    }
+
+    pub fn to_db(self) -> i64 {
+        match self {
+            ReadStatus::Unread => 0,
+            ReadStatus::Reading => 1,
+            ReadStatus::Finished => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Book {
+    pub content_id: String,
+    pub title: String,
+    pub authors: Vec<String>,
+    pub mime_type: Option<String>,
+    pub image_id: Option<String>,
+    pub read_status: ReadStatus,
+    pub percent_read: i64,
+    pub date_last_read: Option<String>,
+    pub series: Option<String>,
+    pub series_index: Option<f64>,
+    pub favourite: bool,
+    /// Names of the shelves the book is on
+    pub collections: Vec<String>,
+}
+
+impl Book {
+    /// Books copied over USB rather than bought from the store.
+    pub fn is_sideloaded(&self) -> bool {
+        self.content_id.starts_with("file://")
+    }
+
+    pub fn on_card(&self) -> bool {
+        self.content_id.starts_with(CARD_CONTENT_PREFIX)
+    }
+
+    /// Path of the book file relative to its storage root.
+    pub fn lpath(&self) -> String {
+        if let Some(rest) = self.content_id.strip_prefix(MAIN_CONTENT_PREFIX) {
+            rest.to_string()
+        } else if let Some(rest) = self.content_id.strip_prefix(CARD_CONTENT_PREFIX) {
+            rest.to_string()
+        } else {
+            format!("{}/{}", KEPUB_DIR, self.content_id)
+        }
+    }
+
+    /// The id the cover images are stored under.
+    pub fn image_id(&self) -> String {
+        match &self.image_id {
+            Some(id) if !id.is_empty() => id.clone(),
+            _ => image_id_from_content_id(&self.content_id),
+        }
+    }
+}
+
+/// The ImageId the firmware derives for a sideloaded book.
+pub fn image_id_from_content_id(content_id: &str) -> String {
+    content_id.replace(['/', ' ', ':', '.'], "_")
+}
+
+/// Kobo joins multiple authors with " & " in `Attribution`.
+pub fn split_authors(attribution: &str) -> Vec<String> {
+    attribution
+        .split(" & ")
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(String::from)
+        .collect()
+}
@@ -1,2 +1,449 @@
-// Stub for Kobo database interaction
-pub struct Database;
+//! Access to `.kobo/KoboReader.sqlite`, the database the Kobo firmware keeps
+//! its library, shelves and reading state in.
+
+use crate::kobo::bookmark::{Annotation, AnnotationKind, Bookmark};
+use crate::kobo::books::{split_authors, Book, ReadStatus, CONTENT_TYPE_BOOK};
+use anyhow::{Context, Result};
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const KOBO_DB_PATH: &str = ".kobo/KoboReader.sqlite";
+
+/// Database versions from which the firmware knows about series and
+/// shelves with an `Id` and `Type`.
+const SERIES_DB_VERSION: i64 = 65;
+const SHELF_ID_DB_VERSION: i64 = 64;
+
+const NOW: &str = "strftime('%Y-%m-%dT%H:%M:%SZ', 'now')";
+
+const BOOK_COLUMNS: &str = "ContentID, Title, Attribution, MimeType, ImageId, ReadStatus, \
+     ___PercentRead, DateLastRead, FavouritesIndex";
+
+pub struct Database {
+    conn: Connection,
+    version: i64,
+}
+
+impl Database {
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open Kobo database {:?}", path))?;
+        let version = conn
+            .query_row("SELECT version FROM dbversion", [], |row| row.get(0))
+            .optional()?
+            .unwrap_or(0);
+        Ok(Self { conn, version })
+    }
+
+    /// Open the database of the device mounted at `prefix`.
+    pub fn open_device(prefix: &Path) -> Result<Self> {
+        Self::open(&prefix.join(KOBO_DB_PATH))
+    }
+
+    pub fn version(&self) -> i64 {
+        self.version
+    }
+
+    pub fn supports_series(&self) -> bool {
+        self.version >= SERIES_DB_VERSION && self.has_column("content", "Series")
+    }
+
+    fn has_column(&self, table: &str, column: &str) -> bool {
+        self.conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2 COLLATE NOCASE",
+                [table, column],
+                |row| row.get::<_, i64>(0),
+            )
+            .is_ok_and(|n| n > 0)
+    }
+
+    /// All the books on the device, leaving out entries the firmware has
+    /// marked as removed.
+    pub fn books(&self) -> Result<Vec<Book>> {
+        let series_columns = if self.supports_series() {
+            ", Series, SeriesNumber"
+        } else {
+            ", NULL, NULL"
+        };
+        let sql = format!(
+            "SELECT {}{} FROM content WHERE ContentType = ?1 AND BookID IS NULL \
+             AND (___ExpirationStatus IS NULL OR ___ExpirationStatus <> 3) \
+             ORDER BY ContentID",
+            BOOK_COLUMNS, series_columns
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut books = stmt
+            .query_map([CONTENT_TYPE_BOOK], book_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut shelves = self.shelves_by_content()?;
+        for book in &mut books {
+            book.collections = shelves.remove(&book.content_id).unwrap_or_default();
+        }
+        Ok(books)
+    }
+
+    pub fn book(&self, content_id: &str) -> Result<Option<Book>> {
+        Ok(self
+            .books()?
+            .into_iter()
+            .find(|b| b.content_id == content_id))
+    }
+
+    /// Names of the shelves that have not been deleted.
+    pub fn shelves(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT Name FROM Shelf WHERE _IsDeleted = 'false' ORDER BY Name")?;
+        let names = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(names)
+    }
+
+    fn shelves_by_content(&self) -> Result<HashMap<String, Vec<String>>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT sc.ContentId, sc.ShelfName FROM ShelfContent sc \
+             WHERE sc._IsDeleted = 'false' AND sc.ShelfName IN \
+             (SELECT Name FROM Shelf WHERE _IsDeleted = 'false') \
+             ORDER BY sc.ShelfName",
+        )?;
+        let mut map: HashMap<String, Vec<String>> = HashMap::new();
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in rows {
+            let (content_id, shelf): (String, String) = row?;
+            map.entry(content_id).or_default().push(shelf);
+        }
+        Ok(map)
+    }
+
+    /// The reading position and annotations of a book.
+    pub fn bookmark(&self, content_id: &str) -> Result<Option<Bookmark>> {
+        let state = self
+            .conn
+            .query_row(
+                "SELECT ReadStatus, ___PercentRead, DateLastRead, ChapterIDBookmarked \
+                 FROM content WHERE ContentID = ?1 AND BookID IS NULL",
+                [content_id],
+                |row| {
+                    Ok((
+                        row.get::<_, Option<i64>>(0)?,
+                        row.get::<_, Option<i64>>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((status, percent, last_read, chapter)) = state else {
+            return Ok(None);
+        };
+
+        let kind_column = if self.has_column("Bookmark", "Type") {
+            "Type"
+        } else {
+            "NULL"
+        };
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT BookmarkID, ContentID, Text, Annotation, ChapterProgress, DateCreated, {} \
+             FROM Bookmark WHERE VolumeID = ?1 ORDER BY ContentID, ChapterProgress",
+            kind_column
+        ))?;
+        let annotations = stmt
+            .query_map([content_id], |row| {
+                let text: Option<String> = row.get(2)?;
+                let note: Option<String> = row.get(3)?;
+                let kind: Option<String> = row.get(6)?;
+                Ok(Annotation {
+                    id: row.get(0)?,
+                    content_id: row.get(1)?,
+                    kind: AnnotationKind::from_db(
+                        kind.as_deref(),
+                        text.as_deref(),
+                        note.as_deref(),
+                    ),
+                    text,
+                    note,
+                    chapter_progress: row.get::<_, Option<f64>>(4)?.unwrap_or(0.0),
+                    date_created: row.get(5)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(Some(Bookmark {
+            content_id: content_id.to_string(),
+            read_status: ReadStatus::from_db(status.unwrap_or(0)),
+            percent_read: percent.unwrap_or(0),
+            last_read,
+            chapter,
+            annotations,
+        }))
+    }
+
+    /// Set or clear (`None`) the series of a book.
+    pub fn set_series(&self, content_id: &str, series: Option<(&str, f64)>) -> Result<()> {
+        if !self.supports_series() {
+            return Ok(());
+        }
+        let (name, number) = match series {
+            Some((name, index)) => (Some(name.to_string()), Some(format_series_index(index))),
+            None => (None, None),
+        };
+        self.conn.execute(
+            "UPDATE content SET Series = ?1, SeriesNumber = ?2 \
+             WHERE BookID IS NULL AND ContentID = ?3",
+            params![name, number, content_id],
+        )?;
+        if self.has_column("content", "SeriesNumberFloat") {
+            self.conn.execute(
+                "UPDATE content SET SeriesNumberFloat = ?1 \
+                 WHERE BookID IS NULL AND ContentID = ?2",
+                params![series.map(|(_, index)| index), content_id],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Put the book on exactly `shelves`, except that shelves in `keep` are
+    /// never taken away. Missing shelves are created when `create` is set and
+    /// skipped otherwise.
+    pub fn set_book_shelves(
+        &self,
+        content_id: &str,
+        shelves: &[String],
+        keep: &[String],
+        create: bool,
+    ) -> Result<()> {
+        let existing = self.shelves()?;
+        for shelf in shelves {
+            if !existing.contains(shelf) {
+                if !create {
+                    continue;
+                }
+                self.create_shelf(shelf)?;
+            }
+            self.add_to_shelf(content_id, shelf)?;
+        }
+
+        let current: Vec<String> = self
+            .shelves_by_content()?
+            .remove(content_id)
+            .unwrap_or_default();
+        for shelf in current {
+            if !shelves.contains(&shelf) && !keep.contains(&shelf) {
+                self.conn.execute(
+                    "DELETE FROM ShelfContent WHERE ShelfName = ?1 AND ContentId = ?2",
+                    params![shelf, content_id],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn create_shelf(&self, name: &str) -> Result<()> {
+        // A shelf deleted on the device comes back rather than being duplicated
+        let revived = self.conn.execute(
+            &format!(
+                "UPDATE Shelf SET _IsDeleted = 'false', _IsSynced = 'false', LastModified = {} \
+                 WHERE Name = ?1",
+                NOW
+            ),
+            [name],
+        )?;
+        if revived > 0 {
+            return Ok(());
+        }
+        if self.version >= SHELF_ID_DB_VERSION {
+            self.conn.execute(
+                &format!(
+                    "INSERT INTO Shelf (CreationDate, InternalName, LastModified, Name, \
+                     _IsDeleted, _IsVisible, _IsSynced, Id, Type) \
+                     VALUES ({now}, ?1, {now}, ?1, 'false', 'true', 'false', ?1, 'UserTag')",
+                    now = NOW
+                ),
+                [name],
+            )?;
+        } else {
+            self.conn.execute(
+                &format!(
+                    "INSERT INTO Shelf (CreationDate, InternalName, LastModified, Name, \
+                     _IsDeleted, _IsVisible, _IsSynced) \
+                     VALUES ({now}, ?1, {now}, ?1, 'false', 'true', 'false')",
+                    now = NOW
+                ),
+                [name],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn add_to_shelf(&self, content_id: &str, shelf: &str) -> Result<()> {
+        let deleted: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT _IsDeleted FROM ShelfContent WHERE ShelfName = ?1 AND ContentId = ?2",
+                params![shelf, content_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match deleted.as_deref() {
+            Some("false") => {}
+            Some(_) => {
+                self.conn.execute(
+                    &format!(
+                        "UPDATE ShelfContent SET _IsDeleted = 'false', _IsSynced = 'false', \
+                         DateModified = {} WHERE ShelfName = ?1 AND ContentId = ?2",
+                        NOW
+                    ),
+                    params![shelf, content_id],
+                )?;
+            }
+            None => {
+                self.conn.execute(
+                    &format!(
+                        "INSERT INTO ShelfContent (ShelfName, ContentId, DateModified, \
+                         _IsDeleted, _IsSynced) VALUES (?1, ?2, {}, 'false', 'false')",
+                        NOW
+                    ),
+                    params![shelf, content_id],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a book, its chapters and the rows that hang off it. Store
+    /// books keep their row so that the next sync with Kobo can archive them.
+    /// Returns the ImageId so the caller can remove the cover images.
+    pub fn delete_book(&self, content_id: &str) -> Result<Option<String>> {
+        let image_id: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT ImageId FROM content WHERE ContentID = ?1",
+                [content_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+
+        self.conn.execute(
+            "DELETE FROM volume_shortcovers WHERE volumeId = ?1",
+            [content_id],
+        )?;
+        self.conn
+            .execute("DELETE FROM content_keys WHERE volumeId = ?1", [content_id])?;
+        self.conn
+            .execute("DELETE FROM content WHERE BookID = ?1", [content_id])?;
+        self.conn
+            .execute("DELETE FROM Bookmark WHERE VolumeID = ?1", [content_id])?;
+        self.conn.execute(
+            "DELETE FROM ShelfContent WHERE ContentId = ?1",
+            [content_id],
+        )?;
+        if content_id.starts_with("file://") {
+            self.conn
+                .execute("DELETE FROM content WHERE ContentID = ?1", [content_id])?;
+        } else {
+            self.conn.execute(
+                "UPDATE content SET ReadStatus = 0, FirstTimeReading = 'true', \
+                 ___PercentRead = 0, ___ExpirationStatus = 3 \
+                 WHERE BookID IS NULL AND ContentID = ?1",
+                [content_id],
+            )?;
+        }
+        Ok(image_id)
+    }
+
+    /// Drop rows that point at books which no longer exist. Returns the
+    /// number of rows removed.
+    pub fn remove_orphans(&self) -> Result<usize> {
+        let mut removed = 0;
+        removed += self.conn.execute(
+            "DELETE FROM ShelfContent WHERE ContentId NOT IN \
+             (SELECT ContentID FROM content WHERE BookID IS NULL)",
+            [],
+        )?;
+        removed += self.conn.execute(
+            "DELETE FROM content WHERE BookID IS NOT NULL AND BookID NOT IN \
+             (SELECT ContentID FROM content WHERE BookID IS NULL)",
+            [],
+        )?;
+        removed += self.conn.execute(
+            "DELETE FROM volume_shortcovers WHERE volumeId NOT IN \
+             (SELECT ContentID FROM content)",
+            [],
+        )?;
+        removed += self.conn.execute(
+            "DELETE FROM content_keys WHERE volumeId NOT IN (SELECT ContentID FROM content)",
+            [],
+        )?;
+        removed += self.conn.execute(
+            "DELETE FROM Bookmark WHERE VolumeID NOT IN (SELECT ContentID FROM content)",
+            [],
+        )?;
+        Ok(removed)
+    }
+
+    /// Remove the shelves with no books on them. Shelves Kobo has synced are
+    /// only marked as deleted so the removal reaches the Kobo account.
+    pub fn delete_empty_shelves(&self) -> Result<usize> {
+        let empty = "Shelf.InternalName NOT IN ('Shortlist', 'Wishlist') \
+             AND NOT EXISTS (SELECT 1 FROM ShelfContent c \
+             WHERE Shelf.Name = c.ShelfName AND c._IsDeleted <> 'true')";
+        let deleted = self.conn.execute(
+            &format!("DELETE FROM Shelf WHERE _IsSynced = 'false' AND {}", empty),
+            [],
+        )?;
+        let marked = self.conn.execute(
+            &format!(
+                "UPDATE Shelf SET _IsDeleted = 'true' \
+                 WHERE _IsSynced = 'true' AND _IsDeleted = 'false' AND {}",
+                empty
+            ),
+            [],
+        )?;
+        Ok(deleted + marked)
+    }
+}
+
+fn book_from_row(row: &Row) -> rusqlite::Result<Book> {
+    let attribution: Option<String> = row.get(2)?;
+    // SeriesNumber is text, but do not choke on numbers put there by others
+    let series_index = match row.get_ref(10)? {
+        ValueRef::Text(text) => std::str::from_utf8(text)
+            .ok()
+            .and_then(|n| n.trim().parse().ok()),
+        ValueRef::Integer(n) => Some(n as f64),
+        ValueRef::Real(n) => Some(n),
+        _ => None,
+    };
+    Ok(Book {
+        content_id: row.get(0)?,
+        title: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+        authors: attribution
+            .as_deref()
+            .map(split_authors)
+            .unwrap_or_default(),
+        mime_type: row.get(3)?,
+        image_id: row.get(4)?,
+        read_status: ReadStatus::from_db(row.get::<_, Option<i64>>(5)?.unwrap_or(0)),
+        percent_read: row.get::<_, Option<i64>>(6)?.unwrap_or(0),
+        date_last_read: row.get(7)?,
+        favourite: row.get::<_, Option<i64>>(8)?.is_some_and(|i| i >= 0),
+        series: row.get(9)?,
+        series_index,
+        collections: Vec::new(),
+    })
+}
+
+/// Series numbers are stored as text, "2" rather than "2.0".
+fn format_series_index(index: f64) -> String {
+    if index.fract() == 0.0 {
+        format!("{}", index as i64)
+    } else {
+        format!("{}", index)
+    }
+}
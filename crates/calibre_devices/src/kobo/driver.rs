@@ -1,19 +1,224 @@
 use crate::interface::{Device, DeviceBook, DeviceInfo};
+use crate::kobo::books::{
+    image_id_from_content_id, Book, CARD_CONTENT_PREFIX, MAIN_CONTENT_PREFIX,
+};
+use crate::kobo::db::{Database, KOBO_DB_PATH};
+use crate::kobo::kobotouch_config::KoboTouchConfig;
 use crate::scanner::USBDevice;
 use crate::usbms::driver::USBMSDevice;
-use anyhow::Result;
-use std::path::PathBuf;
+use anyhow::{Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Cover images the firmware looks for, with the size they are shown at.
+const COVER_FILE_ENDINGS: [(&str, u32, u32); 3] = [
+    (" - N3_FULL.parsed", 600, 800),
+    (" - N3_LIBRARY_FULL.parsed", 355, 473),
+    (" - N3_LIBRARY_GRID.parsed", 149, 198),
+];
+
+/// Newer firmware keeps covers in a two level tree under this directory,
+/// older firmware flat in `.kobo/images`.
+const IMAGES_TREE_DIR: &str = ".kobo-images";
+const IMAGES_DIR: &str = ".kobo/images";
 
 pub struct KoboDevice {
     usbms: USBMSDevice,
+    config: KoboTouchConfig,
 }
 
 impl KoboDevice {
     pub fn new(usbms: USBMSDevice) -> Self {
-        Self { usbms }
+        Self::with_config(usbms, KoboTouchConfig::default())
+    }
+
+    pub fn with_config(usbms: USBMSDevice, config: KoboTouchConfig) -> Self {
+        Self { usbms, config }
+    }
+
+    pub fn config(&self) -> &KoboTouchConfig {
+        &self.config
+    }
+
+    /// The firmware database, which is always in main memory.
+    pub fn database(&self) -> Result<Database> {
+        Database::open_device(self.usbms.get_prefix(None)?)
+    }
+
+    fn has_database(&self) -> bool {
+        self.usbms
+            .get_prefix(None)
+            .is_ok_and(|prefix| prefix.join(KOBO_DB_PATH).exists())
+    }
+
+    /// The books in the database that are stored on `on_card`.
+    pub fn kobo_books(&self, on_card: Option<&str>) -> Result<Vec<Book>> {
+        let on_card = on_card.is_some();
+        Ok(self
+            .database()?
+            .books()?
+            .into_iter()
+            .filter(|book| book.on_card() == on_card)
+            .collect())
+    }
+
+    /// Where the file of `book` is on the mounted device.
+    pub fn book_path(&self, book: &Book) -> Result<PathBuf> {
+        let on_card = book.on_card().then_some("carda");
+        Ok(self.usbms.get_prefix(on_card)?.join(book.lpath()))
+    }
+
+    /// The ContentID the firmware uses for the book file at `path`.
+    pub fn content_id_from_path(&self, path: &Path) -> Result<String> {
+        let main = self.usbms.get_prefix(None)?;
+        let (prefix, content_prefix) = match self.usbms.get_prefix(Some("carda")) {
+            Ok(card) if path.starts_with(card) => (card, CARD_CONTENT_PREFIX),
+            _ => (main, MAIN_CONTENT_PREFIX),
+        };
+        let relative = path
+            .strip_prefix(prefix)
+            .with_context(|| format!("{:?} is not on the device", path))?;
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        // Store kepubs are known by their bare ContentID
+        if let Some(id) = relative.strip_prefix(".kobo/kepub/") {
+            return Ok(id.to_string());
+        }
+        Ok(format!("{}{}", content_prefix, relative))
+    }
+
+    /// Set or clear the series of the book at `path`.
+    pub fn set_series(&self, path: &Path, series: Option<(&str, f64)>) -> Result<()> {
+        if !self.config.update_series {
+            return Ok(());
+        }
+        let content_id = self.content_id_from_path(path)?;
+        self.database()?.set_series(&content_id, series)
+    }
+
+    /// Put the book at `path` on the shelves named by `collections`.
+    pub fn set_collections(&self, path: &Path, collections: &[String]) -> Result<()> {
+        if !self.config.manage_collections {
+            return Ok(());
+        }
+        let content_id = self.content_id_from_path(path)?;
+        let db = self.database()?;
+        db.set_book_shelves(
+            &content_id,
+            collections,
+            &self.config.ignore_collections,
+            self.config.create_collections,
+        )?;
+        if self.config.delete_empty_collections {
+            db.delete_empty_shelves()?;
+        }
+        Ok(())
+    }
+
+    /// Directory the cover images of `image_id` go in.
+    fn images_dir(&self, image_id: &str) -> Result<PathBuf> {
+        let main = self.usbms.get_prefix(None)?;
+        let tree = main.join(IMAGES_TREE_DIR);
+        if tree.is_dir() {
+            let hash = qhash(image_id);
+            Ok(tree
+                .join((hash & 0xff).to_string())
+                .join(((hash & 0xff00) >> 8).to_string()))
+        } else {
+            Ok(main.join(IMAGES_DIR))
+        }
+    }
+
+    /// Write `cover` (any supported image format) as the cover images the
+    /// firmware shows for the book at `path`.
+    pub fn upload_cover(&self, path: &Path, cover: &[u8]) -> Result<()> {
+        if !self.config.upload_covers {
+            return Ok(());
+        }
+        let content_id = self.content_id_from_path(path)?;
+        let image_id = match self.database()?.book(&content_id)? {
+            Some(book) => book.image_id(),
+            None => image_id_from_content_id(&content_id),
+        };
+        let mut img = image::load_from_memory(cover).context("Failed to read cover image")?;
+        if self.config.upload_grayscale {
+            img = DynamicImage::ImageLuma8(img.to_luma8());
+        }
+
+        let dir = self.images_dir(&image_id)?;
+        fs::create_dir_all(&dir)?;
+        for (ending, width, height) in COVER_FILE_ENDINGS {
+            let resized = if self.config.keep_cover_aspect_ratio {
+                img.resize(width, height, FilterType::Triangle)
+            } else {
+                img.resize_exact(width, height, FilterType::Triangle)
+            };
+            let resized = match resized {
+                DynamicImage::ImageLuma8(_) => resized,
+                other => DynamicImage::ImageRgb8(other.to_rgb8()),
+            };
+            let dest = dir.join(format!("{}{}", image_id, ending));
+            let mut out =
+                fs::File::create(&dest).with_context(|| format!("Failed to create {:?}", dest))?;
+            JpegEncoder::new_with_quality(&mut out, 90).encode_image(&resized)?;
+        }
+        Ok(())
+    }
+
+    fn delete_covers(&self, image_id: &str) -> Result<()> {
+        let dir = self.images_dir(image_id)?;
+        for (ending, _, _) in COVER_FILE_ENDINGS {
+            let path = dir.join(format!("{}{}", image_id, ending));
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete the book files at `paths` along with their database entries
+    /// and covers, then clear out whatever was left pointing at them.
+    pub fn delete_books(&mut self, paths: &[PathBuf]) -> Result<()> {
+        let db = if self.has_database() {
+            Some(self.database()?)
+        } else {
+            None
+        };
+        for path in paths {
+            if path.exists() {
+                fs::remove_file(path).with_context(|| format!("Failed to delete {:?}", path))?;
+            }
+            let Some(db) = &db else { continue };
+            let content_id = self.content_id_from_path(path)?;
+            let image_id = db
+                .delete_book(&content_id)?
+                .unwrap_or_else(|| image_id_from_content_id(&content_id));
+            self.delete_covers(&image_id)?;
+        }
+        if let Some(db) = &db {
+            db.remove_orphans()?;
+            if self.config.delete_empty_collections {
+                db.delete_empty_shelves()?;
+            }
+        }
+        Ok(())
     }
 }
 
+/// The hash the firmware uses to spread cover images over directories.
+fn qhash(input: &str) -> u32 {
+    let mut h: u32 = 0;
+    for &b in input.as_bytes() {
+        h = (h << 4).wrapping_add(b as u32);
+        h ^= (h & 0xf000_0000) >> 23;
+        h &= 0x0fff_ffff;
+    }
+    h
+}
+
 impl Default for KoboDevice {
     fn default() -> Self {
         Self::new(USBMSDevice::new())
@@ -23,7 +228,7 @@ impl Default for KoboDevice {
 impl Device for KoboDevice {
     fn can_handle(&self, device_info: &USBDevice, _debug: bool) -> bool {
         let vendor_id = 0x2237;
-        let product_ids = vec![0x4165, 0x4161, 0x4162];
+        let product_ids = [0x4165, 0x4161, 0x4162];
 
         if device_info.vendor_id == vendor_id && product_ids.contains(&device_info.product_id) {
             return true;
@@ -49,7 +254,32 @@ impl Device for KoboDevice {
     }
 
     fn books(&self, on_card: Option<&str>) -> Result<Vec<DeviceBook>> {
-        self.usbms.books(on_card)
+        if !self.has_database() {
+            return self.usbms.books(on_card);
+        }
+
+        // Books the firmware knows about, then files it has not imported yet
+        let mut books = Vec::new();
+        let mut seen = HashSet::new();
+        for book in self.kobo_books(on_card)? {
+            let path = self.book_path(&book)?;
+            let Ok(meta) = fs::metadata(&path) else {
+                continue;
+            };
+            seen.insert(path.clone());
+            books.push(DeviceBook {
+                title: book.title,
+                authors: book.authors,
+                path,
+                size: meta.len(),
+            });
+        }
+        for book in self.usbms.books(on_card)? {
+            if !seen.contains(&book.path) {
+                books.push(book);
+            }
+        }
+        Ok(books)
     }
 
     fn upload_books(
@@ -1,2 +1,32 @@
-// Stub for KoboTouch configuration
-pub struct KoboTouchConfig;
+//! Options for what the Kobo driver writes back to the device.
+
+#[derive(Debug, Clone)]
+pub struct KoboTouchConfig {
+    /// Keep the device shelves in step with the book collections
+    pub manage_collections: bool,
+    /// Create shelves that do not exist yet, otherwise only use existing ones
+    pub create_collections: bool,
+    /// Remove shelves that end up with no books
+    pub delete_empty_collections: bool,
+    /// Shelves that are never taken away from a book
+    pub ignore_collections: Vec<String>,
+    pub upload_covers: bool,
+    pub keep_cover_aspect_ratio: bool,
+    pub upload_grayscale: bool,
+    pub update_series: bool,
+}
+
+impl Default for KoboTouchConfig {
+    fn default() -> Self {
+        Self {
+            manage_collections: true,
+            create_collections: true,
+            delete_empty_collections: false,
+            ignore_collections: Vec::new(),
+            upload_covers: true,
+            keep_cover_aspect_ratio: true,
+            upload_grayscale: false,
+            update_series: true,
+        }
+    }
+}
@@ -1,49 +1,744 @@
+//! Driver for reading apps that connect over the network with the calibre
+//! wireless device protocol. The app finds us through a UDP broadcast,
+//! connects over TCP and then answers the opcodes we send it.
+
 use crate::interface::{Device, DeviceBook, DeviceInfo};
 use crate::scanner::USBDevice;
-use anyhow::Result;
-use std::path::PathBuf;
+use crate::smart_device_app::protocol::{
+    broadcast_reply, read_message, write_message, Opcode, BROADCAST_PORTS, PROTOCOL_VERSION,
+};
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_THUMBNAIL_HEIGHT: u32 = 240;
+const BASE_PACKET_LEN: usize = 4096;
+const MESSAGE_PASSWORD_ERROR: i64 = 1;
+const CALIBRE_VERSION: [u32; 3] = [7, 0, 0];
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone)]
+pub struct SmartDeviceConfig {
+    /// Apps must answer the password challenge with this password
+    pub password: Option<String>,
+    /// TCP port to listen on, 0 picks a free one
+    pub port: u16,
+    /// UDP ports to answer discovery broadcasts on
+    pub broadcast_ports: Vec<u16>,
+    pub content_server_port: Option<u16>,
+    pub library_name: String,
+    pub formats: Vec<String>,
+    pub timeout: Duration,
+}
+
+impl Default for SmartDeviceConfig {
+    fn default() -> Self {
+        Self {
+            password: None,
+            port: 0,
+            broadcast_ports: BROADCAST_PORTS.to_vec(),
+            content_server_port: None,
+            library_name: "Calibre Library".to_string(),
+            formats: ["epub", "mobi", "azw3", "pdf", "cbz", "fb2", "txt"]
+                .iter()
+                .map(|f| f.to_string())
+                .collect(),
+            timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// What the app told us about itself when it connected.
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub app_name: String,
+    pub device_kind: String,
+    pub device_name: String,
+    pub accepted_extensions: Vec<String>,
+    pub can_stream_books: bool,
+    pub can_stream_metadata: bool,
+    pub can_receive_book_binary: bool,
+    pub can_delete_multiple: bool,
+    pub can_send_ok_to_sendbook: bool,
+    pub can_accept_library_info: bool,
+    pub cover_height: u32,
+    pub max_packet_len: usize,
+}
+
+impl ClientInfo {
+    fn from_init(result: &Value) -> Self {
+        let flag = |key: &str| result.get(key).and_then(Value::as_bool).unwrap_or(false);
+        let text = |key: &str| {
+            result
+                .get(key)
+                .and_then(Value::as_str)
+                .unwrap_or("")
+                .to_string()
+        };
+        let device_kind = text("deviceKind");
+        let device_name = result
+            .get("deviceName")
+            .and_then(Value::as_str)
+            .map(String::from)
+            .unwrap_or_else(|| device_kind.clone());
+        Self {
+            app_name: text("appName"),
+            device_kind,
+            device_name,
+            accepted_extensions: result
+                .get("acceptedExtensions")
+                .and_then(Value::as_array)
+                .map(|exts| {
+                    exts.iter()
+                        .filter_map(Value::as_str)
+                        .map(str::to_lowercase)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            can_stream_books: flag("canStreamBooks"),
+            can_stream_metadata: flag("canStreamMetadata"),
+            can_receive_book_binary: flag("canReceiveBookBinary"),
+            can_delete_multiple: flag("canDeleteMultipleBooks"),
+            can_send_ok_to_sendbook: flag("canSendOkToSendbook"),
+            can_accept_library_info: flag("canAcceptLibraryInfo"),
+            cover_height: result
+                .get("coverHeight")
+                .and_then(Value::as_u64)
+                .map(|h| h as u32)
+                .unwrap_or(DEFAULT_THUMBNAIL_HEIGHT),
+            max_packet_len: result
+                .get("maxBookContentPacketLen")
+                .and_then(Value::as_u64)
+                .map(|l| l as usize)
+                .filter(|&l| l > 0)
+                .unwrap_or(BASE_PACKET_LEN),
+        }
+    }
+}
 
-pub struct SmartDevice;
+/// Cover thumbnail as sent on the wire: width, height and base64 JPEG.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Thumbnail(pub u32, pub u32, pub String);
+
+/// Book metadata as exchanged with the app. Fields we do not use are kept
+/// in `extra` so they go back unchanged.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SmartBook {
+    pub lpath: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series_index: Option<f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<Thumbnail>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl SmartBook {
+    pub fn new(lpath: &str, title: &str, authors: Vec<String>) -> Self {
+        Self {
+            lpath: lpath.to_string(),
+            title: title.to_string(),
+            authors,
+            uuid: None,
+            size: 0,
+            series: None,
+            series_index: None,
+            tags: Vec::new(),
+            last_modified: None,
+            thumbnail: None,
+            extra: Map::new(),
+        }
+    }
+
+    /// Attach `cover` scaled down to `height` as the thumbnail the app shows.
+    pub fn set_cover(&mut self, cover: &[u8], height: u32) -> Result<()> {
+        let img = image::load_from_memory(cover).context("Failed to read cover image")?;
+        let img = if img.height() > height {
+            let width = (img.width() as u64 * height as u64 / img.height() as u64).max(1) as u32;
+            img.resize_exact(width, height, FilterType::Triangle)
+        } else {
+            img
+        };
+        let rgb = image::DynamicImage::ImageRgb8(img.to_rgb8());
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, 70).encode_image(&rgb)?;
+        self.thumbnail = Some(Thumbnail(
+            rgb.width(),
+            rgb.height(),
+            general_purpose::STANDARD.encode(jpeg),
+        ));
+        Ok(())
+    }
+}
+
+pub struct SmartDevice {
+    config: SmartDeviceConfig,
+    listener: Option<TcpListener>,
+    stream: Option<TcpStream>,
+    client: Option<ClientInfo>,
+    discovery_ports: Vec<u16>,
+    discovery_threads: Vec<JoinHandle<()>>,
+    stop: Arc<AtomicBool>,
+}
 
 impl SmartDevice {
     pub fn new() -> Self {
-        SmartDevice
+        Self::with_config(SmartDeviceConfig::default())
+    }
+
+    pub fn with_config(config: SmartDeviceConfig) -> Self {
+        Self {
+            config,
+            listener: None,
+            stream: None,
+            client: None,
+            discovery_ports: Vec::new(),
+            discovery_threads: Vec::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Listen for apps, answering discovery broadcasts on whichever of the
+    /// broadcast ports are free. Returns the TCP port apps connect to.
+    pub fn start_listening(&mut self) -> Result<u16> {
+        let listener = TcpListener::bind(("0.0.0.0", self.config.port))
+            .with_context(|| format!("Failed to listen on port {}", self.config.port))?;
+        // Polled by accept, so that opening gives up when no app connects
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+        self.listener = Some(listener);
+
+        self.stop.store(false, Ordering::SeqCst);
+        let reply = broadcast_reply(&hostname(), self.config.content_server_port, port);
+        for &broadcast_port in &self.config.broadcast_ports {
+            let socket = match UdpSocket::bind(("0.0.0.0", broadcast_port)) {
+                Ok(socket) => socket,
+                Err(e) => {
+                    log::debug!("Cannot answer broadcasts on {}: {}", broadcast_port, e);
+                    continue;
+                }
+            };
+            socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+            self.discovery_ports.push(socket.local_addr()?.port());
+            let stop = self.stop.clone();
+            let reply = reply.clone();
+            self.discovery_threads.push(thread::spawn(move || {
+                answer_broadcasts(socket, &reply, &stop)
+            }));
+        }
+        Ok(port)
+    }
+
+    /// The UDP ports discovery broadcasts are answered on.
+    pub fn discovery_ports(&self) -> &[u16] {
+        &self.discovery_ports
+    }
+
+    pub fn stop_listening(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for handle in self.discovery_threads.drain(..) {
+            let _ = handle.join();
+        }
+        self.discovery_ports.clear();
+        self.listener = None;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    pub fn client(&self) -> Option<&ClientInfo> {
+        self.client.as_ref()
+    }
+
+    /// Wait up to the configured timeout for an app to connect and go
+    /// through the handshake with it.
+    pub fn accept(&mut self, library_uuid: Option<&str>) -> Result<()> {
+        let listener = self
+            .listener
+            .as_ref()
+            .context("Not listening for devices")?;
+        let deadline = Instant::now() + self.config.timeout;
+        let (stream, addr) = loop {
+            match listener.accept() {
+                Ok(connection) => break connection,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    if Instant::now() >= deadline {
+                        bail!(
+                            "No wireless device connected within {:?}",
+                            self.config.timeout
+                        );
+                    }
+                    thread::sleep(ACCEPT_POLL_INTERVAL);
+                }
+                Err(e) => return Err(e).context("Failed to accept a wireless device"),
+            }
+        };
+        log::info!("Wireless device connected from {}", addr);
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(self.config.timeout))?;
+        stream.set_write_timeout(Some(self.config.timeout))?;
+        stream.set_nodelay(true)?;
+        self.stream = Some(stream);
+        if let Err(e) = self.handshake(library_uuid) {
+            self.close();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn handshake(&mut self, library_uuid: Option<&str>) -> Result<()> {
+        let challenge = format!(
+            "{:.6}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64()
+        );
+        let (op, result) = self.call(
+            Opcode::GetInitializationInfo,
+            json!({
+                "serverProtocolVersion": PROTOCOL_VERSION,
+                "validExtensions": self.config.formats,
+                "passwordChallenge": challenge,
+                "currentLibraryName": self.config.library_name,
+                "currentLibraryUUID": library_uuid,
+                "pubdateFormat": "MMM yyyy",
+                "timestampFormat": "dd MMM yyyy",
+                "lastModifiedFormat": "dd MMM yyyy",
+                "calibre_version": CALIBRE_VERSION,
+                "canSupportUpdateBooks": true,
+                "canSupportLpathChanges": true,
+            }),
+        )?;
+        if op != Opcode::Ok {
+            bail!("Device answered the initialization with {:?}", op);
+        }
+        if !result
+            .get("versionOK")
+            .and_then(Value::as_bool)
+            .unwrap_or(false)
+        {
+            bail!(
+                "Device does not support protocol version {}",
+                PROTOCOL_VERSION
+            );
+        }
+
+        let expected = match &self.config.password {
+            Some(password) if !password.is_empty() => {
+                let mut hasher = Sha1::new();
+                hasher.update(password.as_bytes());
+                hasher.update(challenge.as_bytes());
+                format!("{:x}", hasher.finalize())
+            }
+            _ => String::new(),
+        };
+        let given = result
+            .get("passwordHash")
+            .and_then(Value::as_str)
+            .unwrap_or("");
+        if given != expected {
+            self.send(
+                Opcode::DisplayMessage,
+                json!({
+                    "messageKind": MESSAGE_PASSWORD_ERROR,
+                    "currentLibraryName": self.config.library_name,
+                    "currentLibraryUUID": library_uuid,
+                }),
+            )?;
+            bail!("Device sent the wrong password");
+        }
+
+        self.client = Some(ClientInfo::from_init(&result));
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+        self.client = None;
+    }
+
+    fn stream(&self) -> Result<&TcpStream> {
+        self.stream.as_ref().context("No device connected")
+    }
+
+    fn client_info(&self) -> Result<&ClientInfo> {
+        self.client.as_ref().context("No device connected")
+    }
+
+    fn send(&self, op: Opcode, arg: Value) -> Result<()> {
+        write_message(&mut self.stream()?, op, &arg)
+    }
+
+    fn receive(&self) -> Result<(Opcode, Value)> {
+        let (op, result) = read_message(&mut self.stream()?)?;
+        if op == Opcode::Error {
+            bail!(
+                "Device reported an error: {}",
+                result
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or("unknown")
+            );
+        }
+        Ok((op, result))
+    }
+
+    fn call(&self, op: Opcode, arg: Value) -> Result<(Opcode, Value)> {
+        self.send(op, arg)?;
+        self.receive()
+    }
+
+    fn expect_ok(&self, op: Opcode, arg: Value) -> Result<Value> {
+        match self.call(op, arg)? {
+            (Opcode::Ok, result) => Ok(result),
+            (other, _) => bail!("Device answered {:?} with {:?}", op, other),
+        }
+    }
+
+    /// Height of the cover thumbnails the app wants.
+    pub fn thumbnail_height(&self) -> u32 {
+        self.client
+            .as_ref()
+            .map_or(DEFAULT_THUMBNAIL_HEIGHT, |c| c.cover_height)
+    }
+
+    /// The metadata of every book on the device.
+    pub fn device_books(&self) -> Result<Vec<SmartBook>> {
+        let result = self.expect_ok(
+            Opcode::GetBookCount,
+            json!({
+                "canStream": true,
+                "canScan": true,
+                "willUseCachedMetadata": false,
+                "supportsSync": false,
+                "canSupportBookFormatSync": true,
+            }),
+        )?;
+        let count = result.get("count").and_then(Value::as_u64).unwrap_or(0);
+        let mut books = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let (op, mut book) = self.receive()?;
+            if op != Opcode::Ok {
+                bail!("Expected book metadata, got {:?}", op);
+            }
+            if let Value::Object(map) = &mut book {
+                map.remove("_series_sort_");
+            }
+            books.push(serde_json::from_value(book).context("Bad book metadata")?);
+        }
+        Ok(books)
+    }
+
+    /// Push metadata (and covers) of `books`, and the collections as lists
+    /// of lpaths.
+    pub fn send_booklists(
+        &self,
+        books: &[SmartBook],
+        collections: &HashMap<String, Vec<String>>,
+    ) -> Result<()> {
+        self.send(
+            Opcode::SendBooklists,
+            json!({
+                "count": books.len(),
+                "collections": collections,
+                "willStreamMetadata": true,
+                "supportsSync": false,
+            }),
+        )?;
+        for (index, book) in books.iter().enumerate() {
+            let mut data = serde_json::to_value(book)?;
+            data["_series_sort_"] = book.series.clone().unwrap_or_default().into();
+            self.send(
+                Opcode::SendBookMetadata,
+                json!({
+                    "index": index,
+                    "count": books.len(),
+                    "data": data,
+                    "supportsSync": false,
+                }),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Send the file at `path` with `book` as its metadata. The app may
+    /// store it under another lpath, which is returned and set on `book`.
+    pub fn send_book(
+        &self,
+        path: &Path,
+        book: &mut SmartBook,
+        this_book: usize,
+        total_books: usize,
+    ) -> Result<String> {
+        let client = self.client_info()?;
+        let ext = Path::new(&book.lpath)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if !client.accepted_extensions.is_empty() && !client.accepted_extensions.contains(&ext) {
+            bail!("{} does not accept {} files", client.device_name, ext);
+        }
+
+        let mut file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+        let length = file.metadata()?.len();
+        book.size = length;
+        let arg = json!({
+            "lpath": book.lpath,
+            "length": length,
+            "metadata": book,
+            "thisBook": this_book,
+            "totalBooks": total_books,
+            "willStreamBooks": true,
+            "willStreamBinary": true,
+            "wantsSendOkToSendbook": client.can_send_ok_to_sendbook,
+            "canSupportLpathChanges": true,
+        });
+        if client.can_send_ok_to_sendbook {
+            let result = self.expect_ok(Opcode::SendBook, arg)?;
+            if let Some(lpath) = result.get("lpath").and_then(Value::as_str) {
+                book.lpath = lpath.to_string();
+            }
+        } else {
+            self.send(Opcode::SendBook, arg)?;
+        }
+
+        let mut stream = self.stream()?;
+        let mut buf = vec![0u8; client.max_packet_len];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            stream.write_all(&buf[..n])?;
+        }
+        stream.flush()?;
+        Ok(book.lpath.clone())
+    }
+
+    /// Delete the books at `lpaths`, returning the uuids the app reports.
+    pub fn delete_books(&self, lpaths: &[String]) -> Result<Vec<String>> {
+        let mut uuids = Vec::new();
+        let mut collect = |result: &Value| {
+            if let Some(uuid) = result.get("uuid").and_then(Value::as_str) {
+                uuids.push(uuid.to_string());
+            }
+        };
+        if self.client_info()?.can_delete_multiple {
+            self.expect_ok(Opcode::DeleteBook, json!({ "lpaths": lpaths }))?;
+            for _ in lpaths {
+                match self.receive()? {
+                    (Opcode::Ok, result) => collect(&result),
+                    (op, _) => bail!("Expected delete confirmation, got {:?}", op),
+                }
+            }
+        } else {
+            for lpath in lpaths {
+                let result = self.expect_ok(Opcode::DeleteBook, json!({ "lpaths": [lpath] }))?;
+                collect(&result);
+            }
+        }
+        Ok(uuids)
+    }
+
+    /// Copy the book at `lpath` from the device into `out`.
+    pub fn get_file<W: Write>(&self, lpath: &str, out: &mut W) -> Result<u64> {
+        let result = self.expect_ok(
+            Opcode::GetBookFileSegment,
+            json!({
+                "lpath": lpath,
+                "position": 0,
+                "thisBook": 0,
+                "totalBooks": 1,
+                "canStream": true,
+                "canStreamBinary": true,
+            }),
+        )?;
+        let length = result
+            .get("fileLength")
+            .and_then(Value::as_u64)
+            .context("Device did not send the file length")?;
+        let copied = std::io::copy(&mut self.stream()?.take(length), out)?;
+        if copied != length {
+            bail!("Connection closed after {} of {} bytes", copied, length);
+        }
+        Ok(length)
+    }
+
+    pub fn free_space(&self) -> Result<u64> {
+        let result = self.expect_ok(Opcode::FreeSpace, json!({}))?;
+        Ok(result
+            .get("free_space_on_device")
+            .and_then(Value::as_u64)
+            .unwrap_or(0))
+    }
+
+    /// Tell the app which library it is talking to, if it wants to know.
+    pub fn set_library_info(&self, library_name: &str, library_uuid: &str) -> Result<()> {
+        if !self.client_info()?.can_accept_library_info {
+            return Ok(());
+        }
+        self.expect_ok(
+            Opcode::SetLibraryInfo,
+            json!({
+                "libraryName": library_name,
+                "libraryUuid": library_uuid,
+                "fieldMetadata": {},
+                "otherInfo": {},
+            }),
+        )?;
+        Ok(())
+    }
+}
+
+impl Default for SmartDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SmartDevice {
+    fn drop(&mut self) {
+        self.stop_listening();
     }
 }
 
+fn answer_broadcasts(socket: UdpSocket, reply: &str, stop: &AtomicBool) {
+    let mut buf = [0u8; 100];
+    while !stop.load(Ordering::SeqCst) {
+        if let Ok((_, remote)) = socket.recv_from(&mut buf) {
+            if let Err(e) = socket.send_to(reply.as_bytes(), remote) {
+                log::debug!("Failed to answer broadcast from {}: {}", remote, e);
+            }
+        }
+    }
+}
+
+/// Short host name, shown by the app in its list of servers.
+fn hostname() -> String {
+    std::fs::read_to_string("/etc/hostname")
+        .ok()
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|h| h.trim().split('.').next().unwrap_or("").to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "calibre".to_string())
+}
+
 impl Device for SmartDevice {
     fn can_handle(&self, _device: &USBDevice, _debug: bool) -> bool {
-        // Stub
+        // Apps connect over the network, never over USB
         false
     }
 
-    fn open(&mut self, _device: &USBDevice, _library_uuid: Option<&str>) -> Result<()> {
-        anyhow::bail!("SmartDevice support is a stub")
+    fn open(&mut self, _device: &USBDevice, library_uuid: Option<&str>) -> Result<()> {
+        if self.is_connected() {
+            return Ok(());
+        }
+        if self.listener.is_none() {
+            bail!("Not listening for wireless devices");
+        }
+        self.accept(library_uuid)
     }
 
     fn eject(&mut self) -> Result<()> {
+        if self.is_connected() {
+            let _ = self.send(Opcode::Noop, json!({ "ejecting": true }));
+        }
+        self.close();
         Ok(())
     }
 
     fn get_device_information(&self) -> Result<DeviceInfo> {
+        if !self.is_connected() {
+            return Ok(DeviceInfo {
+                name: "Smart Device App".to_string(),
+                version: "0.0".to_string(),
+                software_version: "0.0".to_string(),
+                model: "Generic Smart Device".to_string(),
+            });
+        }
+        let result = self.expect_ok(Opcode::GetDeviceInformation, json!({}))?;
+        let device_info = result.get("device_info").cloned().unwrap_or(json!({}));
+        self.expect_ok(Opcode::SetCalibreDeviceInfo, device_info)?;
+        let client = self.client_info()?;
+        let text = |key: &str| {
+            result
+                .get(key)
+                .map(|v| v.as_str().map_or_else(|| v.to_string(), String::from))
+                .unwrap_or_default()
+        };
         Ok(DeviceInfo {
-            name: "Smart Device App".to_string(),
-            version: "0.0".to_string(),
-            software_version: "0.0".to_string(),
-            model: "Generic Smart Device".to_string(),
+            name: client.device_name.clone(),
+            version: text("device_version"),
+            software_version: text("version"),
+            model: client.device_kind.clone(),
         })
     }
 
     fn books(&self, _on_card: Option<&str>) -> Result<Vec<DeviceBook>> {
-        Ok(vec![])
+        if !self.is_connected() {
+            return Ok(vec![]);
+        }
+        Ok(self
+            .device_books()?
+            .into_iter()
+            .map(|book| DeviceBook {
+                title: book.title,
+                authors: book.authors,
+                path: PathBuf::from(book.lpath),
+                size: book.size,
+            })
+            .collect())
     }
 
     fn upload_books(
         &mut self,
-        _files: &[PathBuf],
-        _names: &[String],
+        files: &[PathBuf],
+        names: &[String],
         _on_card: Option<&str>,
     ) -> Result<()> {
-        anyhow::bail!("Not supported")
+        if files.len() != names.len() {
+            bail!("Files and names length mismatch");
+        }
+        for (i, (file, name)) in files.iter().zip(names).enumerate() {
+            let title = Path::new(name)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| name.clone());
+            let mut book = SmartBook::new(name, &title, vec!["Unknown".to_string()]);
+            self.send_book(file, &mut book, i, files.len())?;
+        }
+        Ok(())
     }
 }
@@ -1,2 +1,3 @@
 pub mod driver;
+pub mod protocol;
 pub use driver::SmartDevice;
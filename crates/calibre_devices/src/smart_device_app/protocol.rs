@@ -0,0 +1,181 @@
+//! Wire format of the calibre wireless device protocol.
+//!
+//! Every message is a JSON array `[opcode, {arguments}]` preceded by its
+//! length in ASCII digits. Book files travel as raw bytes right after the
+//! message that announces them.
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::io::{Read, Write};
+
+pub const PROTOCOL_VERSION: i64 = 1;
+
+/// UDP ports the server listens on for devices looking for it.
+pub const BROADCAST_PORTS: [u16; 5] = [54982, 48123, 39001, 44044, 59678];
+
+/// Start of the reply to a discovery broadcast.
+pub const CLIENT_STRING: &str = "calibre wireless device client";
+
+/// Longest message we accept, anything bigger is a broken stream.
+const MAX_MESSAGE_LEN: usize = 100 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Ok,
+    SetCalibreDeviceInfo,
+    SetCalibreDeviceName,
+    GetDeviceInformation,
+    TotalSpace,
+    FreeSpace,
+    GetBookCount,
+    SendBooklists,
+    SendBook,
+    GetInitializationInfo,
+    BookDone,
+    Noop,
+    DeleteBook,
+    GetBookFileSegment,
+    GetBookMetadata,
+    SendBookMetadata,
+    DisplayMessage,
+    CalibreBusy,
+    SetLibraryInfo,
+    Error,
+}
+
+impl Opcode {
+    pub fn code(self) -> i64 {
+        match self {
+            Opcode::Ok => 0,
+            Opcode::SetCalibreDeviceInfo => 1,
+            Opcode::SetCalibreDeviceName => 2,
+            Opcode::GetDeviceInformation => 3,
+            Opcode::TotalSpace => 4,
+            Opcode::FreeSpace => 5,
+            Opcode::GetBookCount => 6,
+            Opcode::SendBooklists => 7,
+            Opcode::SendBook => 8,
+            Opcode::GetInitializationInfo => 9,
+            Opcode::BookDone => 11,
+            Opcode::Noop => 12,
+            Opcode::DeleteBook => 13,
+            Opcode::GetBookFileSegment => 14,
+            Opcode::GetBookMetadata => 15,
+            Opcode::SendBookMetadata => 16,
+            Opcode::DisplayMessage => 17,
+            Opcode::CalibreBusy => 18,
+            Opcode::SetLibraryInfo => 19,
+            Opcode::Error => 20,
+        }
+    }
+
+    pub fn from_code(code: i64) -> Option<Self> {
+        Some(match code {
+            0 => Opcode::Ok,
+            1 => Opcode::SetCalibreDeviceInfo,
+            2 => Opcode::SetCalibreDeviceName,
+            3 => Opcode::GetDeviceInformation,
+            4 => Opcode::TotalSpace,
+            5 => Opcode::FreeSpace,
+            6 => Opcode::GetBookCount,
+            7 => Opcode::SendBooklists,
+            8 => Opcode::SendBook,
+            9 => Opcode::GetInitializationInfo,
+            11 => Opcode::BookDone,
+            12 => Opcode::Noop,
+            13 => Opcode::DeleteBook,
+            14 => Opcode::GetBookFileSegment,
+            15 => Opcode::GetBookMetadata,
+            16 => Opcode::SendBookMetadata,
+            17 => Opcode::DisplayMessage,
+            18 => Opcode::CalibreBusy,
+            19 => Opcode::SetLibraryInfo,
+            20 => Opcode::Error,
+            _ => return None,
+        })
+    }
+}
+
+/// Frame `[opcode, arg]` for the wire.
+pub fn encode_message(op: Opcode, arg: &Value) -> Result<Vec<u8>> {
+    let json = serde_json::to_vec(&Value::Array(vec![op.code().into(), arg.clone()]))?;
+    let mut out = json.len().to_string().into_bytes();
+    out.extend_from_slice(&json);
+    Ok(out)
+}
+
+pub fn write_message<W: Write>(writer: &mut W, op: Opcode, arg: &Value) -> Result<()> {
+    writer
+        .write_all(&encode_message(op, arg)?)
+        .context("Failed to send message")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read one message. The length prefix counts the JSON, which starts at
+/// the first `[`.
+pub fn read_message<R: Read>(reader: &mut R) -> Result<(Opcode, Value)> {
+    let mut digits = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        reader
+            .read_exact(&mut byte)
+            .context("Connection closed while reading a message")?;
+        match byte[0] {
+            b'[' => break,
+            b'0'..=b'9' if digits.len() < 12 => digits.push(byte[0]),
+            other => bail!("Unexpected byte {:#04x} in message length", other),
+        }
+    }
+    let len: usize = std::str::from_utf8(&digits)?
+        .parse()
+        .context("Missing message length")?;
+    if len == 0 || len > MAX_MESSAGE_LEN {
+        bail!("Invalid message length {}", len);
+    }
+
+    let mut json = vec![0u8; len];
+    json[0] = b'[';
+    reader
+        .read_exact(&mut json[1..])
+        .context("Connection closed in the middle of a message")?;
+    let value: Value = serde_json::from_slice(&json).context("Malformed message")?;
+    let (code, arg) = match value {
+        Value::Array(mut items) if items.len() == 2 => {
+            let arg = items.pop().unwrap();
+            (items[0].as_i64(), arg)
+        }
+        _ => bail!("Message is not an [opcode, arguments] pair"),
+    };
+    let op = code
+        .and_then(Opcode::from_code)
+        .with_context(|| format!("Unknown opcode {:?}", code))?;
+    Ok((op, arg))
+}
+
+/// What the server answers a discovery broadcast with: its name, the port
+/// of the content server (if running) and the port to connect to.
+pub fn broadcast_reply(hostname: &str, content_server_port: Option<u16>, port: u16) -> String {
+    format!(
+        "{} (on {});{},{}",
+        CLIENT_STRING,
+        hostname,
+        content_server_port
+            .map(|p| p.to_string())
+            .unwrap_or_default(),
+        port
+    )
+}
+
+/// Inverse of `broadcast_reply`, as a device reads it.
+pub fn parse_broadcast_reply(reply: &str) -> Option<(String, Option<u16>, u16)> {
+    let rest = reply.strip_prefix(CLIENT_STRING)?.strip_prefix(" (on ")?;
+    let (hostname, ports) = rest.rsplit_once(");")?;
+    let (content, port) = ports.split_once(',')?;
+    let content = if content.is_empty() {
+        None
+    } else {
+        Some(content.parse().ok()?)
+    };
+    Some((hostname.to_string(), content, port.trim().parse().ok()?))
+}
@@ -1,6 +1,14 @@
 use calibre_devices::interface::Device;
+use calibre_devices::kobo::bookmark::AnnotationKind;
+use calibre_devices::kobo::books::ReadStatus;
+use calibre_devices::kobo::db::KOBO_DB_PATH;
 use calibre_devices::kobo::driver::KoboDevice;
+use calibre_devices::kobo::kobotouch_config::KoboTouchConfig;
 use calibre_devices::scanner::USBDevice;
+use calibre_devices::usbms::driver::USBMSDevice;
+use rusqlite::Connection;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 #[test]
 fn test_can_handle_kobo() {
@@ -37,3 +45,405 @@ fn test_can_handle_kobo() {
     assert!(device.can_handle(&kobo_wifi, false));
     assert!(!device.can_handle(&unknown_device, false));
 }
+
+/// A device with a cut-down KoboReader.sqlite: two sideloaded books (one on
+/// a shelf, one with a chapter and a highlight) and a store kepub.
+fn fixture_device() -> (tempfile::TempDir, KoboDevice) {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir_all(root.join(".kobo/kepub")).unwrap();
+    fs::create_dir_all(root.join("books")).unwrap();
+    fs::write(root.join("books/alpha.epub"), b"alpha").unwrap();
+    fs::write(root.join("books/beta.epub"), b"beta").unwrap();
+    fs::write(root.join(".kobo/kepub/store-1"), b"store").unwrap();
+    fs::write(root.join("gamma.epub"), b"not imported yet").unwrap();
+
+    let conn = Connection::open(root.join(KOBO_DB_PATH)).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE dbversion (version INTEGER);
+         INSERT INTO dbversion VALUES (170);
+         CREATE TABLE content (ContentID TEXT PRIMARY KEY, ContentType TEXT, MimeType TEXT,
+             BookID TEXT, Title TEXT, Attribution TEXT, ImageId TEXT, DateLastRead TEXT,
+             ReadStatus INTEGER DEFAULT 0, ___PercentRead INTEGER DEFAULT 0,
+             ___ExpirationStatus INTEGER, FirstTimeReading TEXT DEFAULT 'true',
+             ChapterIDBookmarked TEXT, FavouritesIndex INTEGER DEFAULT -1,
+             Series TEXT, SeriesNumber TEXT, SeriesNumberFloat REAL);
+         CREATE TABLE Shelf (CreationDate TEXT, Id TEXT, InternalName TEXT, LastModified TEXT,
+             Name TEXT, Type TEXT, _IsDeleted BOOL, _IsVisible BOOL, _IsSynced BOOL);
+         CREATE TABLE ShelfContent (ShelfName TEXT, ContentId TEXT, DateModified TEXT,
+             _IsDeleted BOOL, _IsSynced BOOL);
+         CREATE TABLE volume_shortcovers (volumeId TEXT, shortcoverId TEXT, VolumeIndex INTEGER);
+         CREATE TABLE content_keys (volumeId TEXT, elementId TEXT, elementKey TEXT);
+         CREATE TABLE Bookmark (BookmarkID TEXT, VolumeID TEXT, ContentID TEXT, Text TEXT,
+             Annotation TEXT, ChapterProgress REAL, DateCreated TEXT, Type TEXT);
+
+         INSERT INTO content (ContentID, ContentType, MimeType, Title, Attribution, ReadStatus,
+             ___PercentRead, DateLastRead, ChapterIDBookmarked, FavouritesIndex)
+         VALUES ('file:///mnt/onboard/books/alpha.epub', '6', 'application/epub+zip', 'Alpha',
+             'Ann Author & Bob Writer', 1, 42, '2024-05-01T10:00:00Z',
+             'file:///mnt/onboard/books/alpha.epub#ch2', 0);
+         INSERT INTO content (ContentID, ContentType, MimeType, Title, Attribution,
+             Series, SeriesNumber)
+         VALUES ('file:///mnt/onboard/books/beta.epub', '6', 'application/epub+zip', 'Beta',
+             'Cat Critic', 'Old Series', '1');
+         INSERT INTO content (ContentID, ContentType, BookID, Title)
+         VALUES ('file:///mnt/onboard/books/beta.epub#ch1', '9',
+             'file:///mnt/onboard/books/beta.epub', 'Chapter 1');
+         INSERT INTO content (ContentID, ContentType, MimeType, Title, Attribution, ImageId,
+             ReadStatus)
+         VALUES ('store-1', '6', 'application/x-kobo-epub+zip', 'Store Book', 'Dee Seller',
+             'store-image', 2);
+         INSERT INTO volume_shortcovers VALUES ('file:///mnt/onboard/books/beta.epub',
+             'file:///mnt/onboard/books/beta.epub#ch1', 0);
+         INSERT INTO Bookmark VALUES ('bm1', 'file:///mnt/onboard/books/alpha.epub',
+             'file:///mnt/onboard/books/alpha.epub#ch1', 'Some words', NULL, 0.5,
+             '2024-05-01T09:00:00Z', 'highlight');
+         INSERT INTO Bookmark VALUES ('bm2', 'file:///mnt/onboard/books/alpha.epub',
+             'file:///mnt/onboard/books/alpha.epub#ch2', 'More words', 'My note', 0.1,
+             '2024-05-01T09:30:00Z', 'note');
+         INSERT INTO Bookmark VALUES ('bm3', 'file:///mnt/onboard/books/beta.epub',
+             'file:///mnt/onboard/books/beta.epub#ch1', NULL, NULL, 0.2, NULL, 'dogear');
+         INSERT INTO Shelf VALUES ('2024-01-01T00:00:00Z', 'Favourites', 'Favourites',
+             '2024-01-01T00:00:00Z', 'Favourites', 'UserTag', 'false', 'true', 'false');
+         INSERT INTO Shelf VALUES ('2024-01-01T00:00:00Z', 'Gone', 'Gone',
+             '2024-01-01T00:00:00Z', 'Gone', 'UserTag', 'true', 'true', 'false');
+         INSERT INTO ShelfContent VALUES ('Favourites', 'file:///mnt/onboard/books/beta.epub',
+             '2024-01-01T00:00:00Z', 'false', 'false');
+         INSERT INTO ShelfContent VALUES ('Gone', 'file:///mnt/onboard/books/beta.epub',
+             '2024-01-01T00:00:00Z', 'false', 'false');",
+    )
+    .unwrap();
+
+    let device = KoboDevice::new(USBMSDevice::with_path(root.to_path_buf()));
+    (dir, device)
+}
+
+fn db(dir: &tempfile::TempDir) -> Connection {
+    Connection::open(dir.path().join(KOBO_DB_PATH)).unwrap()
+}
+
+fn count(conn: &Connection, sql: &str) -> i64 {
+    conn.query_row(sql, [], |row| row.get(0)).unwrap()
+}
+
+#[test]
+fn test_kobo_books_from_database() {
+    let (dir, device) = fixture_device();
+    let books = device.kobo_books(None).unwrap();
+    assert_eq!(books.len(), 3);
+
+    let alpha = &books[0];
+    assert_eq!(alpha.title, "Alpha");
+    assert_eq!(alpha.authors, ["Ann Author", "Bob Writer"]);
+    assert_eq!(alpha.read_status, ReadStatus::Reading);
+    assert_eq!(alpha.percent_read, 42);
+    assert!(alpha.favourite);
+    assert!(alpha.collections.is_empty());
+    assert_eq!(alpha.lpath(), "books/alpha.epub");
+
+    let beta = &books[1];
+    assert_eq!(beta.series.as_deref(), Some("Old Series"));
+    assert_eq!(beta.series_index, Some(1.0));
+    // Shelves deleted on the device are left out
+    assert_eq!(beta.collections, ["Favourites"]);
+
+    let store = &books[2];
+    assert!(!store.is_sideloaded());
+    assert_eq!(store.read_status, ReadStatus::Finished);
+    assert_eq!(store.lpath(), ".kobo/kepub/store-1");
+    assert_eq!(store.image_id(), "store-image");
+    assert_eq!(books[0].image_id(), "file____mnt_onboard_books_alpha_epub");
+
+    // The generic listing uses the database and adds files not imported yet
+    let listed = device.books(None).unwrap();
+    let titles: Vec<&str> = listed.iter().map(|b| b.title.as_str()).collect();
+    assert_eq!(titles, ["Alpha", "Beta", "Store Book", "gamma"]);
+    assert_eq!(listed[2].path, dir.path().join(".kobo/kepub/store-1"));
+    assert_eq!(listed[0].size, 5);
+}
+
+#[test]
+fn test_kobo_bookmark() {
+    let (_dir, device) = fixture_device();
+    let db = device.database().unwrap();
+    assert_eq!(db.version(), 170);
+    let bookmark = db
+        .bookmark("file:///mnt/onboard/books/alpha.epub")
+        .unwrap()
+        .unwrap();
+    assert_eq!(bookmark.read_status, ReadStatus::Reading);
+    assert_eq!(bookmark.percent_read, 42);
+    assert_eq!(bookmark.last_read.as_deref(), Some("2024-05-01T10:00:00Z"));
+    assert_eq!(
+        bookmark.chapter.as_deref(),
+        Some("file:///mnt/onboard/books/alpha.epub#ch2")
+    );
+    let kinds: Vec<AnnotationKind> = bookmark.annotations.iter().map(|a| a.kind).collect();
+    assert_eq!(kinds, [AnnotationKind::Highlight, AnnotationKind::Note]);
+    assert_eq!(bookmark.annotations[1].note.as_deref(), Some("My note"));
+    assert_eq!(bookmark.highlights().count(), 2);
+    assert!(db.bookmark("missing").unwrap().is_none());
+
+    assert_eq!(
+        AnnotationKind::from_db(None, None, Some("note")),
+        AnnotationKind::Note
+    );
+    assert_eq!(
+        AnnotationKind::from_db(None, None, None),
+        AnnotationKind::Dogear
+    );
+}
+
+#[test]
+fn test_kobo_set_series_and_collections() {
+    let (dir, device) = fixture_device();
+    let alpha = dir.path().join("books/alpha.epub");
+    let beta = dir.path().join("books/beta.epub");
+
+    device
+        .set_series(&alpha, Some(("New Series", 2.0)))
+        .unwrap();
+    device.set_series(&beta, None).unwrap();
+    device
+        .set_collections(&alpha, &["Favourites".to_string(), "To Read".to_string()])
+        .unwrap();
+    device
+        .set_collections(&beta, &["Gone".to_string()])
+        .unwrap();
+
+    let books = device.kobo_books(None).unwrap();
+    assert_eq!(books[0].series.as_deref(), Some("New Series"));
+    assert_eq!(books[0].series_index, Some(2.0));
+    assert_eq!(books[0].collections, ["Favourites", "To Read"]);
+    assert_eq!(books[1].series, None);
+    // The deleted shelf comes back and Favourites is taken away
+    assert_eq!(books[1].collections, ["Gone"]);
+
+    let conn = db(&dir);
+    let float: f64 = conn
+        .query_row(
+            "SELECT SeriesNumberFloat FROM content WHERE Title = 'Alpha'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(float, 2.0);
+    let number: String = conn
+        .query_row(
+            "SELECT SeriesNumber FROM content WHERE Title = 'Alpha'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(number, "2");
+    assert_eq!(
+        count(
+            &conn,
+            "SELECT COUNT(*) FROM Shelf WHERE Name = 'To Read' AND Id = 'To Read' \
+             AND _IsSynced = 'false'"
+        ),
+        1
+    );
+}
+
+#[test]
+fn test_kobo_collections_config() {
+    let (dir, _device) = fixture_device();
+    let config = KoboTouchConfig {
+        create_collections: false,
+        ignore_collections: vec!["Favourites".to_string()],
+        delete_empty_collections: true,
+        ..KoboTouchConfig::default()
+    };
+    let device = KoboDevice::with_config(USBMSDevice::with_path(dir.path().to_path_buf()), config);
+    db(&dir)
+        .execute_batch(
+            "INSERT INTO Shelf (Name, InternalName, _IsDeleted, _IsSynced)
+                 VALUES ('Empty', 'Empty', 'false', 'false');
+             INSERT INTO Shelf (Name, InternalName, _IsDeleted, _IsSynced)
+                 VALUES ('Synced', 'Synced', 'false', 'true');",
+        )
+        .unwrap();
+    let beta = dir.path().join("books/beta.epub");
+    device
+        .set_collections(&beta, &["Brand New".to_string()])
+        .unwrap();
+
+    let books = device.kobo_books(None).unwrap();
+    // Not created, and the ignored shelf stays
+    assert_eq!(books[1].collections, ["Favourites"]);
+    let conn = db(&dir);
+    assert_eq!(
+        count(&conn, "SELECT COUNT(*) FROM Shelf WHERE Name = 'Brand New'"),
+        0
+    );
+    // Empty shelves go, the synced one is only marked as deleted
+    assert_eq!(
+        count(&conn, "SELECT COUNT(*) FROM Shelf WHERE Name = 'Empty'"),
+        0
+    );
+    assert_eq!(
+        count(
+            &conn,
+            "SELECT COUNT(*) FROM Shelf WHERE Name = 'Synced' AND _IsDeleted = 'true'"
+        ),
+        1
+    );
+
+    let off = KoboDevice::with_config(
+        USBMSDevice::with_path(dir.path().to_path_buf()),
+        KoboTouchConfig {
+            manage_collections: false,
+            update_series: false,
+            ..KoboTouchConfig::default()
+        },
+    );
+    off.set_collections(&beta, &[]).unwrap();
+    off.set_series(&beta, Some(("Ignored", 3.0))).unwrap();
+    let books = off.kobo_books(None).unwrap();
+    assert_eq!(books[1].collections, ["Favourites"]);
+    assert_eq!(books[1].series.as_deref(), Some("Old Series"));
+}
+
+#[test]
+fn test_kobo_upload_cover() {
+    let (dir, device) = fixture_device();
+    let mut png = Vec::new();
+    image::RgbImage::from_pixel(300, 400, image::Rgb([10, 200, 10]))
+        .write_to(
+            &mut std::io::Cursor::new(&mut png),
+            image::ImageOutputFormat::Png,
+        )
+        .unwrap();
+
+    // Flat layout for older firmware
+    let alpha = dir.path().join("books/alpha.epub");
+    device.upload_cover(&alpha, &png).unwrap();
+    let full = dir
+        .path()
+        .join(".kobo/images/file____mnt_onboard_books_alpha_epub - N3_FULL.parsed");
+    let img = image::load_from_memory(&fs::read(&full).unwrap()).unwrap();
+    assert_eq!((img.width(), img.height()), (600, 800));
+    let grid = dir
+        .path()
+        .join(".kobo/images/file____mnt_onboard_books_alpha_epub - N3_LIBRARY_GRID.parsed");
+    assert!(grid.exists());
+
+    // Hashed tree when the device has one
+    fs::create_dir_all(dir.path().join(".kobo-images")).unwrap();
+    let store = dir.path().join(".kobo/kepub/store-1");
+    device.upload_cover(&store, &png).unwrap();
+    let found: Vec<_> = walk(&dir.path().join(".kobo-images"));
+    assert_eq!(found.len(), 3);
+    assert!(found.iter().all(|p| p
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .starts_with("store-image - ")));
+    // Two directory levels below the root
+    let rel = found[0]
+        .strip_prefix(dir.path().join(".kobo-images"))
+        .unwrap();
+    assert_eq!(rel.components().count(), 3);
+
+    assert!(device.upload_cover(&alpha, b"not an image").is_err());
+}
+
+fn walk(dir: &Path) -> Vec<PathBuf> {
+    let mut out = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            out.extend(walk(&path));
+        } else {
+            out.push(path);
+        }
+    }
+    out
+}
+
+#[test]
+fn test_kobo_delete_books_cleans_up() {
+    let (dir, mut device) = fixture_device();
+    let beta = dir.path().join("books/beta.epub");
+    let store = dir.path().join(".kobo/kepub/store-1");
+    let mut png = Vec::new();
+    image::RgbImage::from_pixel(30, 40, image::Rgb([0, 0, 0]))
+        .write_to(
+            &mut std::io::Cursor::new(&mut png),
+            image::ImageOutputFormat::Png,
+        )
+        .unwrap();
+    device.upload_cover(&beta, &png).unwrap();
+
+    // Rows left behind by an earlier, interrupted delete
+    let conn = db(&dir);
+    conn.execute_batch(
+        "INSERT INTO ShelfContent VALUES ('Favourites', 'file:///mnt/onboard/old.epub',
+             NULL, 'false', 'false');
+         INSERT INTO content (ContentID, ContentType, BookID)
+             VALUES ('file:///mnt/onboard/old.epub#c', '9', 'file:///mnt/onboard/old.epub');",
+    )
+    .unwrap();
+
+    device.delete_books(&[beta.clone(), store.clone()]).unwrap();
+    assert!(!beta.exists());
+    assert!(!store.exists());
+    assert!(!dir
+        .path()
+        .join(".kobo/images/file____mnt_onboard_books_beta_epub - N3_FULL.parsed")
+        .exists());
+
+    let titles: Vec<String> = device
+        .kobo_books(None)
+        .unwrap()
+        .into_iter()
+        .map(|b| b.title)
+        .collect();
+    assert_eq!(titles, ["Alpha"]);
+    // The store book stays for the next sync, but reset and archived
+    assert_eq!(
+        count(
+            &conn,
+            "SELECT COUNT(*) FROM content WHERE ContentID = 'store-1' \
+             AND ___ExpirationStatus = 3 AND ReadStatus = 0"
+        ),
+        1
+    );
+    assert_eq!(
+        count(
+            &conn,
+            "SELECT COUNT(*) FROM content WHERE BookID IS NOT NULL"
+        ),
+        0
+    );
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM ShelfContent"), 0);
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM volume_shortcovers"), 0);
+    assert_eq!(
+        count(
+            &conn,
+            "SELECT COUNT(*) FROM Bookmark WHERE VolumeID LIKE '%beta%'"
+        ),
+        0
+    );
+    assert_eq!(count(&conn, "SELECT COUNT(*) FROM Bookmark"), 2);
+    // Shelves themselves stay unless configured otherwise
+    assert_eq!(
+        count(
+            &conn,
+            "SELECT COUNT(*) FROM Shelf WHERE Name = 'Favourites'"
+        ),
+        1
+    );
+}
+
+#[test]
+fn test_kobo_without_database_uses_usbms() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("plain.epub"), b"x").unwrap();
+    let device = KoboDevice::new(USBMSDevice::with_path(dir.path().to_path_buf()));
+    let books = device.books(None).unwrap();
+    assert_eq!(books.len(), 1);
+    assert_eq!(books[0].title, "plain");
+    assert!(device.database().is_err());
+}
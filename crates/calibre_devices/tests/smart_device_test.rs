@@ -0,0 +1,354 @@
+use calibre_devices::interface::Device;
+use calibre_devices::scanner::USBDevice;
+use calibre_devices::smart_device_app::driver::{SmartBook, SmartDeviceConfig};
+use calibre_devices::smart_device_app::protocol::{
+    broadcast_reply, encode_message, parse_broadcast_reply, read_message, write_message, Opcode,
+};
+use calibre_devices::smart_device_app::SmartDevice;
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+fn usb() -> USBDevice {
+    USBDevice::new(0, 0, 0, String::new(), String::new(), String::new())
+}
+
+fn listening_device(password: Option<&str>) -> (SmartDevice, u16) {
+    let mut device = SmartDevice::with_config(SmartDeviceConfig {
+        password: password.map(String::from),
+        broadcast_ports: vec![0],
+        timeout: Duration::from_secs(10),
+        ..SmartDeviceConfig::default()
+    });
+    let port = device.start_listening().unwrap();
+    (device, port)
+}
+
+/// What the mock app saw during a session.
+#[derive(Default, Debug)]
+struct Session {
+    device_info: Option<Value>,
+    collections: Value,
+    metadata: Vec<Value>,
+    files: HashMap<String, Vec<u8>>,
+    deleted: Vec<String>,
+    messages: Vec<i64>,
+    library: Option<String>,
+    ejected: bool,
+}
+
+/// An app with two books that answers like the real ones do.
+fn mock_app(port: u16, password: &'static str) -> JoinHandle<Session> {
+    thread::spawn(move || {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut session = Session::default();
+        session
+            .files
+            .insert("books/one.epub".to_string(), b"first book".to_vec());
+        let books = [
+            json!({"lpath": "books/one.epub", "title": "One", "authors": ["A. Writer"],
+                   "uuid": "uuid-1", "size": 10, "_series_sort_": "", "rating": 4}),
+            json!({"lpath": "books/two.epub", "title": "Two", "authors": ["B. Writer"],
+                   "uuid": "uuid-2", "size": 20, "series": "Saga", "series_index": 2.0}),
+        ];
+        let ok = |stream: &mut TcpStream, arg: Value| write_message(stream, Opcode::Ok, &arg);
+
+        while let Ok((op, arg)) = read_message(&mut stream) {
+            match op {
+                Opcode::GetInitializationInfo => {
+                    let challenge = arg["passwordChallenge"].as_str().unwrap();
+                    let hash = if password.is_empty() {
+                        String::new()
+                    } else {
+                        let mut hasher = Sha1::new();
+                        hasher.update(password.as_bytes());
+                        hasher.update(challenge.as_bytes());
+                        format!("{:x}", hasher.finalize())
+                    };
+                    assert_eq!(arg["serverProtocolVersion"], 1);
+                    assert_eq!(arg["currentLibraryUUID"], "lib-uuid");
+                    ok(
+                        &mut stream,
+                        json!({
+                            "appName": "Mock Reader", "deviceKind": "Phone",
+                            "deviceName": "Test Phone", "versionOK": true,
+                            "acceptedExtensions": ["epub", "pdf"],
+                            "canStreamBooks": true, "canStreamMetadata": true,
+                            "canReceiveBookBinary": true, "canDeleteMultipleBooks": true,
+                            "canSendOkToSendbook": true, "canAcceptLibraryInfo": true,
+                            "coverHeight": 60, "maxBookContentPacketLen": 7,
+                            "passwordHash": hash,
+                        }),
+                    )
+                    .unwrap();
+                }
+                Opcode::GetDeviceInformation => ok(
+                    &mut stream,
+                    json!({"device_info": {"device_store_uuid": "store-1",
+                           "device_name": "Test Phone"},
+                           "device_version": "14", "version": "1.2.3"}),
+                )
+                .unwrap(),
+                Opcode::SetCalibreDeviceInfo => {
+                    session.device_info = Some(arg);
+                    ok(&mut stream, json!({})).unwrap();
+                }
+                Opcode::GetBookCount => {
+                    ok(
+                        &mut stream,
+                        json!({"count": books.len(), "willStream": true, "willScan": true}),
+                    )
+                    .unwrap();
+                    for book in &books {
+                        ok(&mut stream, book.clone()).unwrap();
+                    }
+                }
+                Opcode::SendBooklists => {
+                    session.collections = arg["collections"].clone();
+                    for _ in 0..arg["count"].as_u64().unwrap() {
+                        let (op, meta) = read_message(&mut stream).unwrap();
+                        assert_eq!(op, Opcode::SendBookMetadata);
+                        session.metadata.push(meta["data"].clone());
+                    }
+                }
+                Opcode::SendBook => {
+                    assert!(arg["wantsSendOkToSendbook"].as_bool().unwrap());
+                    let lpath = format!("sent/{}", arg["lpath"].as_str().unwrap());
+                    ok(&mut stream, json!({ "lpath": lpath })).unwrap();
+                    let mut data = vec![0u8; arg["length"].as_u64().unwrap() as usize];
+                    stream.read_exact(&mut data).unwrap();
+                    session.metadata.push(arg["metadata"].clone());
+                    session.files.insert(lpath, data);
+                }
+                Opcode::DeleteBook => {
+                    ok(&mut stream, json!({})).unwrap();
+                    for lpath in arg["lpaths"].as_array().unwrap() {
+                        let lpath = lpath.as_str().unwrap().to_string();
+                        session.files.remove(&lpath);
+                        let uuid = if lpath == "books/one.epub" {
+                            "uuid-1"
+                        } else {
+                            ""
+                        };
+                        ok(&mut stream, json!({ "uuid": uuid })).unwrap();
+                        session.deleted.push(lpath);
+                    }
+                }
+                Opcode::GetBookFileSegment => {
+                    let data = session.files[arg["lpath"].as_str().unwrap()].clone();
+                    ok(&mut stream, json!({ "fileLength": data.len() })).unwrap();
+                    stream.write_all(&data).unwrap();
+                }
+                Opcode::FreeSpace => {
+                    ok(&mut stream, json!({"free_space_on_device": 123456})).unwrap()
+                }
+                Opcode::SetLibraryInfo => {
+                    session.library = arg["libraryName"].as_str().map(String::from);
+                    ok(&mut stream, json!({})).unwrap();
+                }
+                Opcode::DisplayMessage => {
+                    session.messages.push(arg["messageKind"].as_i64().unwrap())
+                }
+                Opcode::Noop => {
+                    if arg["ejecting"] == true {
+                        session.ejected = true;
+                        break;
+                    }
+                }
+                other => panic!("unexpected opcode {:?}", other),
+            }
+        }
+        session
+    })
+}
+
+#[test]
+fn test_message_framing() {
+    let arg = json!({"lpath": "a/b.epub", "count": 2});
+    let bytes = encode_message(Opcode::GetBookCount, &arg).unwrap();
+    let text = String::from_utf8(bytes.clone()).unwrap();
+    let (len, json) = text.split_at(text.find('[').unwrap());
+    assert_eq!(len.parse::<usize>().unwrap(), json.len());
+    assert!(json.starts_with("[6,"));
+
+    let mut two = bytes.clone();
+    two.extend(encode_message(Opcode::Ok, &json!({})).unwrap());
+    let mut cursor = Cursor::new(two);
+    assert_eq!(
+        read_message(&mut cursor).unwrap(),
+        (Opcode::GetBookCount, arg)
+    );
+    assert_eq!(read_message(&mut cursor).unwrap(), (Opcode::Ok, json!({})));
+    assert!(read_message(&mut cursor).is_err());
+
+    assert!(read_message(&mut Cursor::new(b"5[99,{}]".to_vec())).is_err());
+    assert!(read_message(&mut Cursor::new(b"x[0,{}]".to_vec())).is_err());
+    assert!(read_message(&mut Cursor::new(b"20[0,{}]".to_vec())).is_err());
+}
+
+#[test]
+fn test_broadcast_reply() {
+    let reply = broadcast_reply("desk", Some(8080), 9090);
+    assert_eq!(reply, "calibre wireless device client (on desk);8080,9090");
+    assert_eq!(
+        parse_broadcast_reply(&reply),
+        Some(("desk".to_string(), Some(8080), 9090))
+    );
+    assert_eq!(
+        parse_broadcast_reply(&broadcast_reply("desk", None, 9090)),
+        Some(("desk".to_string(), None, 9090))
+    );
+    assert_eq!(parse_broadcast_reply("something else"), None);
+}
+
+#[test]
+fn test_udp_discovery() {
+    let (mut device, port) = listening_device(None);
+    let udp_port = device.discovery_ports()[0];
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    socket.send_to(b"hello", ("127.0.0.1", udp_port)).unwrap();
+    let mut buf = [0u8; 200];
+    let (n, _) = socket.recv_from(&mut buf).unwrap();
+    let (_, content_port, tcp_port) =
+        parse_broadcast_reply(std::str::from_utf8(&buf[..n]).unwrap()).unwrap();
+    assert_eq!(content_port, None);
+    assert_eq!(tcp_port, port);
+    device.stop_listening();
+    assert!(device.discovery_ports().is_empty());
+}
+
+#[test]
+fn test_wireless_session() {
+    let (mut device, port) = listening_device(Some("secret"));
+    let app = mock_app(port, "secret");
+    device.open(&usb(), Some("lib-uuid")).unwrap();
+    assert!(device.is_connected());
+    let client = device.client().unwrap();
+    assert_eq!(client.app_name, "Mock Reader");
+    assert_eq!(client.cover_height, 60);
+
+    let info = device.get_device_information().unwrap();
+    assert_eq!(info.name, "Test Phone");
+    assert_eq!(info.model, "Phone");
+    assert_eq!(info.version, "14");
+    assert_eq!(info.software_version, "1.2.3");
+    device.set_library_info("My Books", "lib-uuid").unwrap();
+    assert_eq!(device.free_space().unwrap(), 123456);
+
+    // Book list, keeping fields we do not know about
+    let mut books = device.device_books().unwrap();
+    assert_eq!(books.len(), 2);
+    assert_eq!(books[0].title, "One");
+    assert_eq!(books[0].extra["rating"], 4);
+    assert!(!books[0].extra.contains_key("_series_sort_"));
+    assert_eq!(books[1].series.as_deref(), Some("Saga"));
+    let listed = device.books(None).unwrap();
+    assert_eq!(listed[1].path.to_str(), Some("books/two.epub"));
+    assert_eq!(listed[1].size, 20);
+
+    // Metadata and cover push
+    let mut png = Vec::new();
+    image::RgbImage::from_pixel(120, 240, image::Rgb([1, 2, 3]))
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .unwrap();
+    books[0].title = "One, Revised".to_string();
+    books[0].set_cover(&png, device.thumbnail_height()).unwrap();
+    let mut collections = HashMap::new();
+    collections.insert("Favourites".to_string(), vec!["books/one.epub".to_string()]);
+    device.send_booklists(&books, &collections).unwrap();
+
+    // Books going both ways
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("new.epub");
+    std::fs::write(&file, b"a book that spans several packets").unwrap();
+    let mut new_book = SmartBook::new("new.epub", "New", vec!["C. Writer".to_string()]);
+    let lpath = device.send_book(&file, &mut new_book, 0, 1).unwrap();
+    assert_eq!(lpath, "sent/new.epub");
+    assert_eq!(new_book.size, 33);
+    let mut fetched = Vec::new();
+    assert_eq!(device.get_file("sent/new.epub", &mut fetched).unwrap(), 33);
+    assert_eq!(fetched, b"a book that spans several packets");
+    let pdf = dir.path().join("x.cbz");
+    std::fs::write(&pdf, b"x").unwrap();
+    assert!(device
+        .send_book(&pdf, &mut SmartBook::new("x.cbz", "X", vec![]), 0, 1)
+        .is_err());
+
+    let uuids = device
+        .delete_books(&["books/one.epub".to_string(), "books/two.epub".to_string()])
+        .unwrap();
+    assert_eq!(uuids, ["uuid-1", ""]);
+
+    device.eject().unwrap();
+    assert!(!device.is_connected());
+    let session = app.join().unwrap();
+
+    assert!(session.ejected);
+    assert_eq!(session.library.as_deref(), Some("My Books"));
+    assert_eq!(session.device_info.unwrap()["device_store_uuid"], "store-1");
+    assert_eq!(session.collections["Favourites"], json!(["books/one.epub"]));
+    let pushed = &session.metadata[0];
+    assert_eq!(pushed["title"], "One, Revised");
+    assert_eq!(pushed["rating"], 4);
+    assert_eq!(pushed["thumbnail"][0], 30);
+    assert_eq!(pushed["thumbnail"][1], 60);
+    assert!(!pushed["thumbnail"][2].as_str().unwrap().is_empty());
+    assert_eq!(session.metadata[1]["_series_sort_"], "Saga");
+    assert_eq!(session.metadata[2]["title"], "New");
+    assert_eq!(
+        session.files["sent/new.epub"],
+        b"a book that spans several packets"
+    );
+    assert_eq!(session.deleted, ["books/one.epub", "books/two.epub"]);
+}
+
+#[test]
+fn test_wrong_password_is_refused() {
+    let (mut device, port) = listening_device(Some("secret"));
+    let app = mock_app(port, "guess");
+    assert!(device.open(&usb(), Some("lib-uuid")).is_err());
+    assert!(!device.is_connected());
+    let session = app.join().unwrap();
+    assert_eq!(session.messages, [1]);
+}
+
+#[test]
+fn test_open_times_out_without_app() {
+    let mut device = SmartDevice::with_config(SmartDeviceConfig {
+        broadcast_ports: vec![0],
+        timeout: Duration::from_millis(300),
+        ..SmartDeviceConfig::default()
+    });
+    let port = device.start_listening().unwrap();
+    let started = Instant::now();
+    assert!(device.open(&usb(), Some("lib-uuid")).is_err());
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(!device.is_connected());
+
+    // An app arriving later is still accepted
+    let app = mock_app(port, "");
+    device.open(&usb(), Some("lib-uuid")).unwrap();
+    assert!(device.is_connected());
+    device.eject().unwrap();
+    assert!(app.join().unwrap().ejected);
+}
+
+#[test]
+fn test_not_listening() {
+    let mut device = SmartDevice::new();
+    assert!(!device.can_handle(&usb(), false));
+    assert!(device.open(&usb(), None).is_err());
+    assert!(device.books(None).unwrap().is_empty());
+    assert_eq!(
+        device.get_device_information().unwrap().name,
+        "Smart Device App"
+    );
+    assert!(device.eject().is_ok());
+}
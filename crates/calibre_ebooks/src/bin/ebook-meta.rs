@@ -1,5 +1,6 @@
 use anyhow::{Context, Result};
-use calibre_ebooks::metadata::{get_metadata, MetaInformation};
+use calibre_ebooks::metadata::{get_metadata, set_metadata, string_to_authors, MetaInformation};
+use chrono::NaiveDate;
 use clap::Parser;
 use std::fs::File;
 use std::io::Write;
@@ -20,6 +21,67 @@ struct Args {
     /// Get the cover from the e-book and save it as the specified file.
     #[arg(long)]
     get_cover: Option<PathBuf>,
+
+    /// Set the title.
+    #[arg(short, long)]
+    title: Option<String>,
+
+    /// Set the authors. Multiple authors should be separated by the & character.
+    #[arg(short, long)]
+    authors: Option<String>,
+
+    /// The version of the title to be used for sorting.
+    #[arg(long)]
+    title_sort: Option<String>,
+
+    /// String to be used when sorting by author.
+    #[arg(long)]
+    author_sort: Option<String>,
+
+    /// Set the cover to the specified file.
+    #[arg(long)]
+    cover: Option<PathBuf>,
+
+    /// Set the e-book description.
+    #[arg(short, long)]
+    comments: Option<String>,
+
+    /// Set the e-book publisher.
+    #[arg(short, long)]
+    publisher: Option<String>,
+
+    /// Set the series this e-book belongs to.
+    #[arg(short, long)]
+    series: Option<String>,
+
+    /// Set the index of the book in this series.
+    #[arg(short, long)]
+    index: Option<f64>,
+
+    /// Set the rating. Should be a number between 1 and 5.
+    #[arg(short, long)]
+    rating: Option<f64>,
+
+    /// Set the ISBN of the book.
+    #[arg(long)]
+    isbn: Option<String>,
+
+    /// Set the identifiers for the book, can be specified multiple times.
+    /// For example: --identifier uri:https://acme.com --identifier isbn:12345
+    #[arg(long)]
+    identifier: Vec<String>,
+
+    /// Set the tags for the book. Should be a comma separated list.
+    #[arg(short = 'k', long)]
+    tags: Option<String>,
+
+    /// Set the language.
+    #[arg(short, long)]
+    language: Option<String>,
+
+    /// Set the published date (YYYY-MM-DD).
+    #[arg(short, long)]
+    date: Option<String>,
 }
 
 fn main() -> Result<()> {
@@ -37,6 +99,14 @@ fn main() -> Result<()> {
     // Print Metadata to stdout (Mimic legacy output style)
     print_metadata(&mi);
 
+    let mut mi = mi;
+    if apply_changes(&args, &mut mi)? {
+        set_metadata(&args.input_file, &mi).context("Failed to write metadata")?;
+        println!("\nChanged metadata:");
+        mi = get_metadata(&args.input_file).context("Failed to read metadata")?;
+        print_metadata(&mi);
+    }
+
     // Handle --to-opf
     if let Some(opf_path) = args.to_opf {
         println!("Writing OPF to: {:?}", opf_path);
@@ -66,6 +136,91 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Apply the metadata options given on the command line to `mi`. Returns
+/// whether anything was set.
+fn apply_changes(args: &Args, mi: &mut MetaInformation) -> Result<bool> {
+    let mut changed = false;
+    if let Some(title) = &args.title {
+        mi.title = title.clone();
+        changed = true;
+    }
+    if let Some(authors) = &args.authors {
+        mi.authors = string_to_authors(authors);
+        changed = true;
+    }
+    if let Some(title_sort) = &args.title_sort {
+        mi.title_sort = Some(title_sort.clone());
+        changed = true;
+    }
+    if let Some(author_sort) = &args.author_sort {
+        mi.author_sort = Some(author_sort.clone());
+        changed = true;
+    }
+    if let Some(cover) = &args.cover {
+        let data = std::fs::read(cover).with_context(|| format!("Failed to read {:?}", cover))?;
+        let ext = cover
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        mi.cover_data = (ext, data);
+        changed = true;
+    }
+    if let Some(comments) = &args.comments {
+        mi.comments = Some(comments.clone());
+        changed = true;
+    }
+    if let Some(publisher) = &args.publisher {
+        mi.publisher = Some(publisher.clone());
+        changed = true;
+    }
+    if let Some(series) = &args.series {
+        mi.series = Some(series.clone());
+        changed = true;
+    }
+    if let Some(index) = args.index {
+        mi.series_index = index;
+        changed = true;
+    }
+    if let Some(rating) = args.rating {
+        mi.rating = Some(rating.clamp(0.0, 5.0));
+        changed = true;
+    }
+    if let Some(isbn) = &args.isbn {
+        mi.set_identifier("isbn", isbn);
+        changed = true;
+    }
+    for identifier in &args.identifier {
+        let (key, value) = identifier
+            .split_once(':')
+            .with_context(|| format!("Invalid identifier {:?}, use type:value", identifier))?;
+        if value.is_empty() {
+            mi.identifiers.remove(key);
+        } else {
+            mi.set_identifier(key, value);
+        }
+        changed = true;
+    }
+    if let Some(tags) = &args.tags {
+        mi.tags = tags
+            .split(',')
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect();
+        changed = true;
+    }
+    if let Some(language) = &args.language {
+        mi.languages = vec![language.clone()];
+        changed = true;
+    }
+    if let Some(date) = &args.date {
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .with_context(|| format!("Invalid date {:?}, use YYYY-MM-DD", date))?;
+        mi.pubdate = Some(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+        changed = true;
+    }
+    Ok(changed)
+}
+
 fn print_metadata(mi: &MetaInformation) {
     println!("Title               : {}", mi.title);
     if !mi.authors.is_empty() {
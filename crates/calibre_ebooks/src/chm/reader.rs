@@ -0,0 +1,440 @@
+//! Reader for Microsoft Compiled HTML Help files: the ITSF container, its
+//! ITSP directory and the LZX compressed `MSCompressed` section.
+use crate::compression::lzx::LzxDecoder;
+use anyhow::{anyhow, bail, Context, Result};
+use encoding_rs::{Encoding, WINDOWS_1252};
+use lazy_static::lazy_static;
+use regex::bytes::Regex;
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+
+pub const ITSF_MAGIC: &[u8] = b"ITSF";
+const ITSP_MAGIC: &[u8] = b"ITSP";
+const PMGL_MAGIC: &[u8] = b"PMGL";
+const LZXC_MAGIC: &[u8] = b"LZXC";
+
+const CONTENT_PATH: &str = "::DataSpace/Storage/MSCompressed/Content";
+const CONTROL_PATH: &str = "::DataSpace/Storage/MSCompressed/ControlData";
+const RESET_TABLE_PATH: &str = "::DataSpace/Storage/MSCompressed/Transform/\
+     {7FC28940-9D31-11D0-9B27-00A0C91E9C7C}/InstanceData/ResetTable";
+const SYSTEM_PATH: &str = "/#SYSTEM";
+
+lazy_static! {
+    static ref CHARSET: Regex = Regex::new(r#"(?i)<meta[^>]+charset\s*=\s*["']?([\w-]+)"#).unwrap();
+}
+
+/// Size of the unit that version 2 control data counts in.
+const LZX_FRAME: usize = 0x8000;
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow!("Truncated CHM structure"))
+}
+
+fn u64_at(data: &[u8], pos: usize) -> Result<u64> {
+    data.get(pos..pos + 8)
+        .map(|b| u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+        .ok_or_else(|| anyhow!("Truncated CHM structure"))
+}
+
+/// Read a big-endian, 7 bits per byte integer from a directory chunk.
+fn encint(data: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut val = 0u64;
+    loop {
+        let b = *data
+            .get(*pos)
+            .ok_or_else(|| anyhow!("Read past end of directory chunk"))?;
+        *pos += 1;
+        val = (val << 7) | (b & 0x7f) as u64;
+        if b & 0x80 == 0 {
+            return Ok(val);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectoryEntry {
+    pub path: String,
+    pub section: u64,
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl DirectoryEntry {
+    /// Whether this is a content file, as opposed to a directory or one of
+    /// the `#` and `$` prefixed system files or a `::DataSpace` stream.
+    pub fn is_normal(&self) -> bool {
+        self.path.starts_with('/')
+            && !self.path.ends_with('/')
+            && !self.path.starts_with("/#")
+            && !self.path.starts_with("/$")
+    }
+}
+
+/// The values of the `#SYSTEM` file used for conversion.
+#[derive(Debug, Clone, Default)]
+pub struct SystemInfo {
+    /// Path of the `.hhc` topics file
+    pub contents_file: Option<String>,
+    /// Path of the `.hhk` index file
+    pub index_file: Option<String>,
+    pub default_topic: Option<String>,
+    pub title: Option<String>,
+    /// `name,size,charset` of the font the viewer uses
+    pub default_font: Option<String>,
+    pub lcid: Option<u32>,
+}
+
+pub struct ChmReader<R> {
+    reader: R,
+    data_offset: u64,
+    entries: Vec<DirectoryEntry>,
+    lookup: HashMap<String, usize>,
+    compressed_section: Option<Vec<u8>>,
+    pub system: SystemInfo,
+    encoding: &'static Encoding,
+}
+
+impl<R: Read + Seek> ChmReader<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let mut header = Vec::new();
+        reader.by_ref().take(0x60).read_to_end(&mut header)?;
+        if !header.starts_with(ITSF_MAGIC) {
+            bail!("Not a valid CHM file");
+        }
+        let version = u32_at(&header, 4)?;
+        let header_len = u32_at(&header, 8)?;
+        let dir_offset = u64_at(&header, 0x48)?;
+        let dir_len = u64_at(&header, 0x50)?;
+        // Version 2 files have no content offset, the content follows the
+        // directory
+        let data_offset = if version >= 3 && header_len >= 0x60 {
+            u64_at(&header, 0x58)?
+        } else {
+            dir_offset + dir_len
+        };
+
+        let mut chm = ChmReader {
+            reader,
+            data_offset,
+            entries: Vec::new(),
+            lookup: HashMap::new(),
+            compressed_section: None,
+            system: SystemInfo::default(),
+            encoding: WINDOWS_1252,
+        };
+        let directory = chm.read_raw(dir_offset, dir_len)?;
+        chm.read_directory(&directory)?;
+        chm.read_system()?;
+        Ok(chm)
+    }
+
+    fn read_raw(&mut self, offset: u64, size: u64) -> Result<Vec<u8>> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut data = Vec::new();
+        self.reader.by_ref().take(size).read_to_end(&mut data)?;
+        if (data.len() as u64) < size {
+            bail!("CHM file is truncated");
+        }
+        Ok(data)
+    }
+
+    /// Walk the chain of PMGL listing chunks of the ITSP directory.
+    fn read_directory(&mut self, directory: &[u8]) -> Result<()> {
+        if !directory.starts_with(ITSP_MAGIC) {
+            bail!("Invalid CHM directory header");
+        }
+        let header_len = u32_at(directory, 8)? as usize;
+        let block_len = u32_at(directory, 0x10)? as usize;
+        let mut chunk_index = u32_at(directory, 0x20)? as i32;
+        let num_blocks = u32_at(directory, 0x2C)? as usize;
+        if block_len < 0x14 {
+            bail!("Invalid CHM directory chunk size {}", block_len);
+        }
+
+        for _ in 0..num_blocks {
+            if chunk_index < 0 {
+                break;
+            }
+            let start = header_len + chunk_index as usize * block_len;
+            let chunk = directory
+                .get(start..start + block_len)
+                .ok_or_else(|| anyhow!("Directory chunk {} is truncated", chunk_index))?;
+            if !chunk.starts_with(PMGL_MAGIC) {
+                bail!("Directory chunk {} is not a listing chunk", chunk_index);
+            }
+            let end = block_len.saturating_sub(u32_at(chunk, 4)? as usize);
+            let mut pos = 0x14;
+            while pos < end {
+                let name_len = encint(chunk, &mut pos)? as usize;
+                let name = chunk
+                    .get(pos..pos + name_len)
+                    .ok_or_else(|| anyhow!("Read past end of directory chunk"))?;
+                pos += name_len;
+                let entry = DirectoryEntry {
+                    path: String::from_utf8_lossy(name).into_owned(),
+                    section: encint(chunk, &mut pos)?,
+                    offset: encint(chunk, &mut pos)?,
+                    length: encint(chunk, &mut pos)?,
+                };
+                self.lookup
+                    .insert(entry.path.to_lowercase(), self.entries.len());
+                self.entries.push(entry);
+            }
+            chunk_index = u32_at(chunk, 0x10)? as i32;
+        }
+        Ok(())
+    }
+
+    fn read_system(&mut self) -> Result<()> {
+        if self.resolve(SYSTEM_PATH).is_none() {
+            return Ok(());
+        }
+        let data = self.get_file(SYSTEM_PATH)?;
+        let mut records = Vec::new();
+        let mut pos = 4;
+        while pos + 4 <= data.len() {
+            let code = u16::from_le_bytes([data[pos], data[pos + 1]]);
+            let len = u16::from_le_bytes([data[pos + 2], data[pos + 3]]) as usize;
+            pos += 4;
+            let Some(value) = data.get(pos..pos + len) else {
+                break;
+            };
+            records.push((code, value));
+            pos += len;
+        }
+
+        // Strings are stored in the code page of the help file
+        for (code, value) in &records {
+            match code {
+                4 if value.len() >= 4 => self.system.lcid = Some(u32_at(value, 0)?),
+                16 => {
+                    self.system.default_font = Some(String::from_utf8_lossy(trim_nul(value)).into())
+                }
+                _ => {}
+            }
+        }
+        self.encoding = self.detect_encoding();
+        for (code, value) in records {
+            let value = self.encoding.decode(trim_nul(value)).0.into_owned();
+            if value.is_empty() {
+                continue;
+            }
+            match code {
+                0 => self.system.contents_file = Some(value),
+                1 => self.system.index_file = Some(value),
+                2 => self.system.default_topic = Some(value),
+                3 => self.system.title = Some(value),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The code page from the charset of the default font, or failing that
+    /// from the language of the help file.
+    fn detect_encoding(&self) -> &'static Encoding {
+        let charset = self
+            .system
+            .default_font
+            .as_deref()
+            .and_then(|font| font.split(',').nth(2))
+            .and_then(|c| c.trim().parse::<u32>().ok());
+        let label = match charset {
+            Some(128) => "shift_jis",
+            Some(129) => "euc-kr",
+            Some(134) => "gbk",
+            Some(136) => "big5",
+            Some(161) => "windows-1253",
+            Some(162) => "windows-1254",
+            Some(177) => "windows-1255",
+            Some(178) => "windows-1256",
+            Some(186) => "windows-1257",
+            Some(204) => "windows-1251",
+            Some(222) => "windows-874",
+            Some(238) => "windows-1250",
+            _ => match self.system.lcid {
+                Some(lcid) => lcid_encoding(lcid),
+                None => "windows-1252",
+            },
+        };
+        Encoding::for_label(label.as_bytes()).unwrap_or(WINDOWS_1252)
+    }
+
+    /// The encoding of text in the help file, its HTML files may declare
+    /// their own.
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    /// Decode an HTML or sitemap file in the character set it declares,
+    /// or else in the encoding of the help file.
+    pub fn decode_text(&self, raw: &[u8]) -> String {
+        let head = &raw[..raw.len().min(2048)];
+        let encoding = CHARSET
+            .captures(head)
+            .and_then(|c| Encoding::for_label(&c[1]))
+            .unwrap_or(self.encoding);
+        encoding.decode(raw).0.into_owned()
+    }
+
+    /// Every entry of the directory, in directory order.
+    pub fn entries(&self) -> &[DirectoryEntry] {
+        &self.entries
+    }
+
+    /// Look up a file by its path within the CHM, ignoring case as the
+    /// help viewer does.
+    pub fn resolve(&self, path: &str) -> Option<&DirectoryEntry> {
+        let key = if path.starts_with('/') || path.starts_with("::") {
+            path.to_lowercase()
+        } else {
+            format!("/{}", path.to_lowercase())
+        };
+        self.lookup.get(&key).map(|&i| &self.entries[i])
+    }
+
+    pub fn get_file(&mut self, path: &str) -> Result<Vec<u8>> {
+        let entry = self
+            .resolve(path)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unable to locate {} within CHM file", path))?;
+        match entry.section {
+            0 => self.read_raw(self.data_offset + entry.offset, entry.length),
+            1 => {
+                let section = self.compressed_section()?;
+                let start = entry.offset as usize;
+                section
+                    .get(start..start + entry.length as usize)
+                    .map(|d| d.to_vec())
+                    .ok_or_else(|| anyhow!("{} lies outside the compressed section", path))
+            }
+            n => bail!("Unknown CHM section {} for {}", n, path),
+        }
+    }
+
+    /// The decompressed `MSCompressed` section, decoded on first use.
+    fn compressed_section(&mut self) -> Result<&[u8]> {
+        if self.compressed_section.is_none() {
+            let content = self.get_file(CONTENT_PATH)?;
+            let control = self.get_file(CONTROL_PATH)?;
+            let reset_table = self.get_file(RESET_TABLE_PATH)?;
+            let section = decompress(&content, &control, &reset_table)
+                .context("Failed to decompress the MSCompressed section")?;
+            self.compressed_section = Some(section);
+        }
+        Ok(self.compressed_section.as_deref().unwrap_or_default())
+    }
+
+    /// Path of the `.hhc` topics file, from `#SYSTEM` or the first one in
+    /// the directory.
+    pub fn contents_path(&self) -> Option<String> {
+        self.system_path(self.system.contents_file.as_deref(), ".hhc")
+    }
+
+    /// Path of the `.hhk` index file, from `#SYSTEM` or the first one in
+    /// the directory.
+    pub fn index_path(&self) -> Option<String> {
+        self.system_path(self.system.index_file.as_deref(), ".hhk")
+    }
+
+    /// Path of the topic the viewer opens first.
+    pub fn home_path(&self) -> Option<String> {
+        self.system_path(self.system.default_topic.as_deref(), ".htm")
+            .or_else(|| self.system_path(None, ".html"))
+    }
+
+    fn system_path(&self, path: Option<&str>, ext: &str) -> Option<String> {
+        if let Some(entry) = path.and_then(|p| self.resolve(p)) {
+            return Some(entry.path.clone());
+        }
+        self.entries
+            .iter()
+            .find(|e| e.is_normal() && e.path.to_lowercase().ends_with(ext))
+            .map(|e| e.path.clone())
+    }
+}
+
+fn trim_nul(value: &[u8]) -> &[u8] {
+    let end = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+    &value[..end]
+}
+
+/// The ANSI code page of a Windows locale.
+fn lcid_encoding(lcid: u32) -> &'static str {
+    match lcid & 0x3ff {
+        0x02 | 0x19 | 0x22 | 0x23 => "windows-1251",
+        0x05 | 0x0e | 0x15 | 0x18 | 0x1a | 0x1b | 0x24 => "windows-1250",
+        0x08 => "windows-1253",
+        0x1f => "windows-1254",
+        0x0d => "windows-1255",
+        0x01 => "windows-1256",
+        0x25..=0x27 => "windows-1257",
+        0x1e => "windows-874",
+        0x11 => "shift_jis",
+        0x12 => "euc-kr",
+        0x04 if lcid == 0x0804 || lcid == 0x1004 => "gbk",
+        0x04 => "big5",
+        _ => "windows-1252",
+    }
+}
+
+/// Decompress the LZX stream of the `MSCompressed` section. The stream is
+/// reset at regular intervals of the output, each run is decoded on its own
+/// starting from the offset the reset table gives for it.
+fn decompress(content: &[u8], control: &[u8], reset_table: &[u8]) -> Result<Vec<u8>> {
+    if control.len() < 0x18 || &control[4..8] != LZXC_MAGIC {
+        bail!("Invalid LZXC control data");
+    }
+    let mut reset_interval = u32_at(control, 0xC)? as usize;
+    let mut window_size = u32_at(control, 0x10)? as usize;
+    if u32_at(control, 8)? == 2 {
+        reset_interval *= LZX_FRAME;
+        window_size *= LZX_FRAME;
+    }
+    if !window_size.is_power_of_two() {
+        bail!("Invalid LZX window size {}", window_size);
+    }
+
+    let block_count = u32_at(reset_table, 4)? as usize;
+    let table_offset = u32_at(reset_table, 0xC)? as usize;
+    let uncompressed_len = u64_at(reset_table, 0x10)? as usize;
+    let compressed_len = u64_at(reset_table, 0x18)? as usize;
+    let block_len = u64_at(reset_table, 0x20)? as usize;
+    if block_len == 0 || reset_interval == 0 || !reset_interval.is_multiple_of(block_len) {
+        bail!(
+            "Reset interval {} is not a multiple of the block size {}",
+            reset_interval,
+            block_len
+        );
+    }
+    let blocks_per_reset = reset_interval / block_len;
+    let offsets = (0..block_count)
+        .map(|i| u64_at(reset_table, table_offset + 8 * i).map(|o| o as usize))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut lzx =
+        LzxDecoder::new(window_size.trailing_zeros()).context("Invalid window in ControlData")?;
+    let mut result = Vec::with_capacity(uncompressed_len);
+    for (run, &start) in offsets.iter().step_by(blocks_per_reset).enumerate() {
+        if result.len() >= uncompressed_len {
+            break;
+        }
+        let end = offsets
+            .get((run + 1) * blocks_per_reset)
+            .copied()
+            .unwrap_or(compressed_len)
+            .min(content.len());
+        let input = content
+            .get(start..end)
+            .ok_or_else(|| anyhow!("Reset table entry {} is out of range", run))?;
+        let len = reset_interval.min(uncompressed_len - result.len());
+        result.extend(lzx.decompress(input, len)?);
+    }
+    if result.len() < uncompressed_len {
+        bail!("Failed to completely decompress section");
+    }
+    Ok(result)
+}
@@ -0,0 +1,99 @@
+//! The HTML sitemap format of `.hhc` topics and `.hhk` index files: nested
+//! `<ul>` lists of `<object type="text/sitemap">` elements whose `<param>`
+//! children give the name and target of each entry.
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref SITEMAP_TAG: Regex = Regex::new(r"(?is)<(/?)(ul|object|param)\b([^>]*)>").unwrap();
+    static ref ATTRIBUTE: Regex =
+        Regex::new(r#"(?s)([\w:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap();
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SitemapEntry {
+    /// Nesting depth, 1 for entries of the outermost list
+    pub level: usize,
+    pub name: String,
+    /// Targets of the entry, index keywords can point at several topics
+    pub locals: Vec<String>,
+}
+
+fn attributes(raw: &str) -> Vec<(String, String)> {
+    ATTRIBUTE
+        .captures_iter(raw)
+        .map(|c| {
+            let value = c
+                .get(2)
+                .or_else(|| c.get(3))
+                .or_else(|| c.get(4))
+                .map_or("", |m| m.as_str());
+            (
+                c[1].to_lowercase(),
+                html_escape::decode_html_entities(value).into_owned(),
+            )
+        })
+        .collect()
+}
+
+/// The sitemap entries in document order. The lists are rarely well formed,
+/// so nesting is tracked from the `<ul>` tags alone.
+pub fn parse_sitemap(html: &str) -> Vec<SitemapEntry> {
+    let mut entries = Vec::new();
+    let mut level = 0usize;
+    let mut current: Option<SitemapEntry> = None;
+    for cap in SITEMAP_TAG.captures_iter(html) {
+        let closing = !cap[1].is_empty();
+        let attrs = attributes(&cap[3]);
+        let attr = |name: &str| {
+            attrs
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str())
+        };
+        match (cap[2].to_lowercase().as_str(), closing) {
+            ("ul", false) => level += 1,
+            ("ul", true) => level = level.saturating_sub(1),
+            ("object", false) => {
+                let is_sitemap =
+                    attr("type").is_some_and(|t| t.eq_ignore_ascii_case("text/sitemap"));
+                current = is_sitemap.then(|| SitemapEntry {
+                    level: level.max(1),
+                    ..Default::default()
+                });
+            }
+            ("object", true) => entries.extend(current.take()),
+            ("param", false) => {
+                let (Some(entry), Some(name)) = (current.as_mut(), attr("name")) else {
+                    continue;
+                };
+                let value = attr("value").unwrap_or_default().trim();
+                if name.eq_ignore_ascii_case("name") && entry.name.is_empty() {
+                    entry.name = value.to_string();
+                } else if name.eq_ignore_ascii_case("local") && !value.is_empty() {
+                    entry.locals.push(value.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    entries.extend(current);
+    entries
+}
+
+/// Split the target of an entry into the path of a file within the CHM and
+/// a fragment. Targets in other help files or on the web give `None`.
+pub fn split_local(local: &str) -> Option<(String, Option<String>)> {
+    // "ms-its:book.chm::/topic.htm" points into this or another CHM
+    let local = match local.find("::") {
+        Some(pos) => &local[pos + 2..],
+        None if local.contains(':') => return None,
+        None => local,
+    };
+    let local = local.trim_start_matches('/').replace('\\', "/");
+    let (path, fragment) = match local.split_once('#') {
+        Some((path, fragment)) => (path.to_string(), Some(fragment.to_string())),
+        None => (local, None),
+    };
+    (!path.is_empty()).then_some((path, fragment))
+}
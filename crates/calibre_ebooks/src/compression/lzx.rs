@@ -0,0 +1,504 @@
+//! LZX decompression, ported from libmspack's lzxd.c.
+//!
+//! Used by the LIT and CHM readers, which store their content in LZX
+//! compressed sections that are reset at regular intervals. Each call to
+//! [`LzxDecoder::decompress`] decodes one such independent run.
+use anyhow::{bail, Result};
+
+const FRAME_SIZE: usize = 32768;
+const MIN_MATCH: usize = 2;
+const NUM_CHARS: usize = 256;
+const NUM_PRIMARY_LENGTHS: usize = 7;
+const NUM_SECONDARY_LENGTHS: usize = 249;
+
+const BLOCKTYPE_INVALID: u8 = 0;
+const BLOCKTYPE_VERBATIM: u8 = 1;
+const BLOCKTYPE_ALIGNED: u8 = 2;
+const BLOCKTYPE_UNCOMPRESSED: u8 = 3;
+
+const PRETREE_MAXSYMBOLS: usize = 20;
+const MAINTREE_MAXSYMBOLS: usize = NUM_CHARS + 50 * 8;
+const LENGTH_MAXSYMBOLS: usize = NUM_SECONDARY_LENGTHS + 1;
+const ALIGNED_MAXSYMBOLS: usize = 8;
+
+/// A canonical Huffman code, decoded by walking code lengths.
+struct Huffman {
+    counts: [u16; 17],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Build the code for `lens`. A table where every length is zero is
+    /// accepted (it is simply never used), anything else must be complete.
+    fn new(lens: &[u8]) -> Result<Self> {
+        let mut counts = [0u16; 17];
+        for &len in lens {
+            counts[len as usize] += 1;
+        }
+        let mut symbols = Vec::with_capacity(lens.len());
+        if counts[0] as usize != lens.len() {
+            let mut left: i32 = 1;
+            for &count in &counts[1..] {
+                left = (left << 1) - count as i32;
+                if left < 0 {
+                    bail!("LZX: over-subscribed Huffman table");
+                }
+            }
+            if left > 0 {
+                bail!("LZX: incomplete Huffman table");
+            }
+            for len in 1..=16u8 {
+                for (sym, _) in lens.iter().enumerate().filter(|(_, l)| **l == len) {
+                    symbols.push(sym as u16);
+                }
+            }
+        }
+        counts[0] = 0;
+        Ok(Huffman { counts, symbols })
+    }
+}
+
+/// Reads 16-bit little-endian words, most significant bit first.
+struct BitReader<'a> {
+    input: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    left: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        BitReader {
+            input,
+            pos: 0,
+            buffer: 0,
+            left: 0,
+        }
+    }
+
+    /// The decoder may look up to 16 bits past the real end of the stream,
+    /// so two zero bytes are faked there, as libmspack does.
+    fn byte(&mut self) -> Result<u8> {
+        let b = match self.input.get(self.pos) {
+            Some(&b) => b,
+            None if self.pos < self.input.len() + 2 => 0,
+            None => bail!("LZX: out of input bytes"),
+        };
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn ensure(&mut self, n: u32) -> Result<()> {
+        while self.left < n {
+            let b0 = self.byte()? as u32;
+            let b1 = self.byte()? as u32;
+            self.buffer |= ((b1 << 8) | b0) << (16 - self.left);
+            self.left += 16;
+        }
+        Ok(())
+    }
+
+    fn peek(&self, n: u32) -> u32 {
+        if n == 0 {
+            0
+        } else {
+            self.buffer >> (32 - n)
+        }
+    }
+
+    fn remove(&mut self, n: u32) {
+        self.buffer = if n >= 32 { 0 } else { self.buffer << n };
+        self.left -= n;
+    }
+
+    fn read(&mut self, n: u32) -> Result<u32> {
+        if n == 0 {
+            return Ok(0);
+        }
+        self.ensure(n)?;
+        let v = self.peek(n);
+        self.remove(n);
+        Ok(v)
+    }
+
+    fn decode(&mut self, table: &Huffman) -> Result<usize> {
+        self.ensure(16)?;
+        let bits = self.peek(16);
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=16u32 {
+            code |= ((bits >> (16 - len)) & 1) as i32;
+            let count = table.counts[len as usize] as i32;
+            if code - first < count {
+                self.remove(len);
+                return Ok(table.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        bail!("LZX: out of bits in Huffman decode")
+    }
+}
+
+pub struct LzxDecoder {
+    window: Vec<u8>,
+    posn_slots: usize,
+    position_base: [u32; 51],
+    extra_bits: [u8; 51],
+    r: [u32; 3],
+    block_type: u8,
+    block_length: usize,
+    block_remaining: usize,
+    intel_started: bool,
+    maintree_len: Vec<u8>,
+    length_len: Vec<u8>,
+}
+
+impl LzxDecoder {
+    /// Create a decoder for a window of `2^window_bits` bytes (15 to 21).
+    pub fn new(window_bits: u32) -> Result<Self> {
+        if !(15..=21).contains(&window_bits) {
+            bail!("LZX: invalid window size {}", window_bits);
+        }
+        let mut extra_bits = [0u8; 51];
+        let mut j = 0;
+        for i in (0..50).step_by(2) {
+            extra_bits[i] = j;
+            extra_bits[i + 1] = j;
+            if i != 0 && j < 17 {
+                j += 1;
+            }
+        }
+        extra_bits[50] = 17;
+        let mut position_base = [0u32; 51];
+        let mut base = 0u32;
+        for (i, pb) in position_base.iter_mut().enumerate() {
+            *pb = base;
+            base += 1 << extra_bits[i];
+        }
+        let posn_slots = match window_bits {
+            21 => 50,
+            20 => 42,
+            bits => bits as usize * 2,
+        };
+        Ok(LzxDecoder {
+            window: vec![0; 1 << window_bits],
+            posn_slots,
+            position_base,
+            extra_bits,
+            r: [1, 1, 1],
+            block_type: BLOCKTYPE_INVALID,
+            block_length: 0,
+            block_remaining: 0,
+            intel_started: false,
+            maintree_len: vec![0; MAINTREE_MAXSYMBOLS],
+            length_len: vec![0; LENGTH_MAXSYMBOLS],
+        })
+    }
+
+    fn reset(&mut self) {
+        self.r = [1, 1, 1];
+        self.block_type = BLOCKTYPE_INVALID;
+        self.block_length = 0;
+        self.block_remaining = 0;
+        self.intel_started = false;
+        self.maintree_len.iter_mut().for_each(|l| *l = 0);
+        self.length_len.iter_mut().for_each(|l| *l = 0);
+    }
+
+    /// Decode `out_len` bytes from `input`, which must start at a reset
+    /// point of the compressed stream.
+    pub fn decompress(&mut self, input: &[u8], out_len: usize) -> Result<Vec<u8>> {
+        self.reset();
+        let mut bits = BitReader::new(input);
+        let mut out = Vec::with_capacity(out_len);
+
+        let intel_filesize = if bits.read(1)? != 0 {
+            let hi = bits.read(16)?;
+            let lo = bits.read(16)?;
+            ((hi << 16) | lo) as i32
+        } else {
+            0
+        };
+        let mut intel_curpos: i32 = 0;
+
+        let window_size = self.window.len();
+        let mut window_posn = 0usize;
+        let mut frame_posn = 0usize;
+        let mut frame = 0usize;
+        let (mut pretree, mut maintree, mut lengths, mut aligned) = (None, None, None, None);
+
+        while out.len() < out_len {
+            let frame_size = FRAME_SIZE.min(out_len - out.len());
+            let mut bytes_todo = (frame_posn + frame_size) as isize - window_posn as isize;
+
+            while bytes_todo > 0 {
+                if self.block_remaining == 0 {
+                    if self.block_type == BLOCKTYPE_UNCOMPRESSED && self.block_length & 1 == 1 {
+                        bits.byte()?;
+                    }
+                    self.block_type = bits.read(3)? as u8;
+                    let hi = bits.read(16)? as usize;
+                    let lo = bits.read(8)? as usize;
+                    self.block_length = (hi << 8) | lo;
+                    self.block_remaining = self.block_length;
+
+                    match self.block_type {
+                        BLOCKTYPE_ALIGNED | BLOCKTYPE_VERBATIM => {
+                            if self.block_type == BLOCKTYPE_ALIGNED {
+                                let mut lens = [0u8; ALIGNED_MAXSYMBOLS];
+                                for len in lens.iter_mut() {
+                                    *len = bits.read(3)? as u8;
+                                }
+                                aligned = Some(Huffman::new(&lens)?);
+                            }
+                            let main_syms = NUM_CHARS + (self.posn_slots << 3);
+                            read_lens(&mut bits, &mut pretree, &mut self.maintree_len, 0, 256)?;
+                            read_lens(
+                                &mut bits,
+                                &mut pretree,
+                                &mut self.maintree_len,
+                                256,
+                                main_syms,
+                            )?;
+                            maintree = Some(Huffman::new(&self.maintree_len)?);
+                            if self.maintree_len[0xE8] != 0 {
+                                self.intel_started = true;
+                            }
+                            read_lens(
+                                &mut bits,
+                                &mut pretree,
+                                &mut self.length_len,
+                                0,
+                                NUM_SECONDARY_LENGTHS,
+                            )?;
+                            lengths = Some(Huffman::new(&self.length_len)?);
+                        }
+                        BLOCKTYPE_UNCOMPRESSED => {
+                            self.intel_started = true;
+                            // Read 1-16 (not 0-15) bits to align to bytes
+                            bits.ensure(16)?;
+                            if bits.left > 16 {
+                                bits.pos -= 2;
+                            }
+                            bits.left = 0;
+                            bits.buffer = 0;
+                            for r in self.r.iter_mut() {
+                                let mut b = [0u8; 4];
+                                for byte in b.iter_mut() {
+                                    *byte = bits.byte()?;
+                                }
+                                *r = u32::from_le_bytes(b);
+                            }
+                        }
+                        t => bail!("LZX: bad block type {}", t),
+                    }
+                }
+
+                let mut this_run = (self.block_remaining as isize).min(bytes_todo);
+                bytes_todo -= this_run;
+                self.block_remaining -= this_run as usize;
+
+                match self.block_type {
+                    BLOCKTYPE_VERBATIM | BLOCKTYPE_ALIGNED => {
+                        let is_aligned = self.block_type == BLOCKTYPE_ALIGNED;
+                        let (main, len_tree) = match (&maintree, &lengths) {
+                            (Some(m), Some(l)) => (m, l),
+                            _ => bail!("LZX: missing Huffman tables"),
+                        };
+                        while this_run > 0 {
+                            let element = bits.decode(main)?;
+                            if element < NUM_CHARS {
+                                self.window[window_posn] = element as u8;
+                                window_posn += 1;
+                                this_run -= 1;
+                                continue;
+                            }
+                            let element = element - NUM_CHARS;
+                            let mut match_length = element & NUM_PRIMARY_LENGTHS;
+                            if match_length == NUM_PRIMARY_LENGTHS {
+                                match_length += bits.decode(len_tree)?;
+                            }
+                            match_length += MIN_MATCH;
+
+                            let slot = element >> 3;
+                            let match_offset = match slot {
+                                0 => self.r[0],
+                                1 => {
+                                    self.r.swap(0, 1);
+                                    self.r[0]
+                                }
+                                2 => {
+                                    self.r.swap(0, 2);
+                                    self.r[0]
+                                }
+                                3 if !is_aligned => {
+                                    self.r = [1, self.r[0], self.r[1]];
+                                    1
+                                }
+                                _ => {
+                                    let extra = self.extra_bits[slot] as u32;
+                                    let base = self.position_base[slot] - 2;
+                                    let offset = if !is_aligned {
+                                        base + bits.read(extra)?
+                                    } else if extra > 3 {
+                                        let verbatim = bits.read(extra - 3)?;
+                                        let aligned_bits = decode_aligned(&mut bits, &aligned)?;
+                                        base + (verbatim << 3) + aligned_bits
+                                    } else if extra == 3 {
+                                        base + decode_aligned(&mut bits, &aligned)?
+                                    } else if extra > 0 {
+                                        base + bits.read(extra)?
+                                    } else {
+                                        1
+                                    };
+                                    self.r = [offset, self.r[0], self.r[1]];
+                                    offset
+                                }
+                            } as usize;
+
+                            if window_posn + match_length > window_size {
+                                bail!("LZX: match ran over window wrap");
+                            }
+                            if match_offset > window_posn {
+                                let j = match_offset - window_posn;
+                                if j > window_size {
+                                    bail!("LZX: match offset beyond window boundaries");
+                                }
+                                let src = window_size - j;
+                                for k in 0..match_length {
+                                    let from = if k < j { src + k } else { k - j };
+                                    self.window[window_posn + k] = self.window[from];
+                                }
+                            } else {
+                                let src = window_posn - match_offset;
+                                for k in 0..match_length {
+                                    self.window[window_posn + k] = self.window[src + k];
+                                }
+                            }
+                            this_run -= match_length as isize;
+                            window_posn += match_length;
+                        }
+                    }
+                    BLOCKTYPE_UNCOMPRESSED => {
+                        for k in 0..this_run as usize {
+                            self.window[window_posn + k] = bits.byte()?;
+                        }
+                        window_posn += this_run as usize;
+                        this_run = 0;
+                    }
+                    t => bail!("LZX: bad block type {}", t),
+                }
+
+                // The final match may overrun the desired run length
+                if this_run < 0 {
+                    let overrun = (-this_run) as usize;
+                    if overrun > self.block_remaining {
+                        bail!("LZX: overrun went past end of block");
+                    }
+                    self.block_remaining -= overrun;
+                }
+            }
+
+            // Re-align the input bitstream
+            if bits.left > 0 {
+                bits.ensure(16)?;
+            }
+            if bits.left & 15 != 0 {
+                bits.remove(bits.left & 15);
+            }
+
+            let start = out.len();
+            out.extend_from_slice(&self.window[frame_posn..frame_posn + frame_size]);
+            if intel_filesize != 0 {
+                if self.intel_started && frame <= 32768 && frame_size > 10 {
+                    translate_e8(&mut out[start..], intel_curpos, intel_filesize);
+                }
+                intel_curpos += frame_size as i32;
+            }
+
+            frame_posn += frame_size;
+            frame += 1;
+            if window_posn == window_size {
+                window_posn = 0;
+            }
+            if frame_posn == window_size {
+                frame_posn = 0;
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// Read a run of code lengths, delta-coded against the previous ones with
+/// a pretree.
+fn read_lens(
+    bits: &mut BitReader,
+    pretree: &mut Option<Huffman>,
+    lens: &mut [u8],
+    first: usize,
+    last: usize,
+) -> Result<()> {
+    let mut pre_lens = [0u8; PRETREE_MAXSYMBOLS];
+    for len in pre_lens.iter_mut() {
+        *len = bits.read(4)? as u8;
+    }
+    let tree = pretree.insert(Huffman::new(&pre_lens)?);
+
+    let mut x = first;
+    while x < last {
+        let z = bits.decode(tree)?;
+        let (run, value) = match z {
+            17 => (bits.read(4)? as usize + 4, None),
+            18 => (bits.read(5)? as usize + 20, None),
+            19 => {
+                let run = bits.read(1)? as usize + 4;
+                (run, Some(bits.decode(tree)?))
+            }
+            z => (1, Some(z)),
+        };
+        if x + run > lens.len() {
+            bail!("LZX: code lengths run past end of table");
+        }
+        let len = match value {
+            Some(z) => ((lens[x] as usize + 17 - z) % 17) as u8,
+            None => 0,
+        };
+        lens[x..x + run].iter_mut().for_each(|l| *l = len);
+        x += run;
+    }
+    Ok(())
+}
+
+fn decode_aligned(bits: &mut BitReader, aligned: &Option<Huffman>) -> Result<u32> {
+    match aligned {
+        Some(tree) => Ok(bits.decode(tree)? as u32),
+        None => bail!("LZX: missing aligned offset table"),
+    }
+}
+
+/// Undo the encoder's translation of x86 CALL targets in one frame.
+fn translate_e8(data: &mut [u8], mut curpos: i32, filesize: i32) {
+    let end = data.len() - 10;
+    let mut i = 0;
+    while i < end {
+        if data[i] != 0xE8 {
+            i += 1;
+            curpos += 1;
+            continue;
+        }
+        i += 1;
+        let abs_off = i32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        if abs_off >= -curpos && abs_off < filesize {
+            let rel_off = if abs_off >= 0 {
+                abs_off - curpos
+            } else {
+                abs_off + filesize
+            };
+            data[i..i + 4].copy_from_slice(&rel_off.to_le_bytes());
+        }
+        i += 4;
+        curpos += 5;
+    }
+}
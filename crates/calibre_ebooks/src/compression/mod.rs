@@ -1 +1,2 @@
+pub mod lzx;
 pub mod palmdoc;
@@ -1,6 +1,16 @@
 use lazy_static::lazy_static;
 use std::collections::HashSet;
 
+/// File extensions of the e-book formats calibre knows about.
+pub const BOOK_EXTENSIONS: [&str; 62] = [
+    "lrf", "rar", "zip", "rtf", "lit", "txt", "txtz", "text", "htm", "xhtm", "html", "htmlz",
+    "xhtml", "pdf", "pdb", "updb", "pdr", "prc", "mobi", "azw", "doc", "epub", "fb2", "fbz", "djv",
+    "djvu", "lrx", "cbr", "cb7", "cbz", "cbc", "oebzip", "rb", "imp", "odt", "chm", "tpz", "azw1",
+    "pml", "pmlz", "mbp", "tan", "snb", "xps", "oxps", "azw4", "book", "zbf", "pobi", "docx",
+    "docm", "md", "textile", "markdown", "ibook", "ibooks", "iba", "azw3", "ps", "kepub", "kfx",
+    "kpf",
+];
+
 lazy_static! {
     /// HTML5 tag names as a set for quick lookup
     pub static ref HTML5_TAGS: HashSet<&'static str> = {
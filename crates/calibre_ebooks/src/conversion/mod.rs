@@ -1,5 +1,6 @@
 pub mod archives;
 pub mod plumber;
 pub mod preprocess;
+pub mod registry;
 pub mod search_replace;
 pub mod utils;
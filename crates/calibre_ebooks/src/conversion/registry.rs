@@ -0,0 +1,204 @@
+use crate::oeb::book::OEBBook;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+/// Reads a source format into an `OEBBook`, extracting into `output_dir`.
+pub trait InputFormatPlugin: Send + Sync {
+    fn name(&self) -> &str;
+    /// Lower case file extensions handled by this plugin, without the dot.
+    fn file_types(&self) -> &[&str];
+    /// MIME types of the files handled by this plugin.
+    fn mime_types(&self) -> &[&str];
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook>;
+}
+
+/// Writes an `OEBBook` to a target format.
+pub trait OutputFormatPlugin: Send + Sync {
+    fn name(&self) -> &str;
+    /// Lower case file extensions produced by this plugin, without the dot.
+    /// The first entry is the canonical one.
+    fn file_types(&self) -> &[&str];
+    /// MIME types of the files produced by this plugin.
+    fn mime_types(&self) -> &[&str];
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()>;
+}
+
+/// Maps file extensions and MIME types to conversion plugins.
+///
+/// Plugins registered later take precedence over earlier ones for the same
+/// extension or MIME type, so third parties can override builtin formats.
+#[derive(Clone, Default)]
+pub struct PluginRegistry {
+    inputs: Vec<Arc<dyn InputFormatPlugin>>,
+    outputs: Vec<Arc<dyn OutputFormatPlugin>>,
+}
+
+impl PluginRegistry {
+    pub fn new() -> Self {
+        PluginRegistry {
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn with_builtins() -> Self {
+        use crate::input::*;
+        use crate::output::*;
+
+        let mut registry = PluginRegistry::new();
+
+        registry.register_input(Arc::new(azw4_input::AZW4Input::new()));
+        registry.register_input(Arc::new(chm_input::CHMInput::new()));
+        registry.register_input(Arc::new(comic_input::ComicInput::new()));
+        registry.register_input(Arc::new(djvu_input::DJVUInput::new()));
+        registry.register_input(Arc::new(docx_input::DOCXInput::new()));
+        registry.register_input(Arc::new(epub_input::EPUBInput::new()));
+        registry.register_input(Arc::new(fb2_input::FB2Input::new()));
+        registry.register_input(Arc::new(html_input::HTMLInput::new()));
+        registry.register_input(Arc::new(htmlz_input::HTMLZInput::new()));
+        registry.register_input(Arc::new(lit_input::LitInput::new()));
+        registry.register_input(Arc::new(lrf_input::LRFInput::new()));
+        registry.register_input(Arc::new(mobi_input::MOBIInput::new()));
+        registry.register_input(Arc::new(odt_input::ODTInput::new()));
+        registry.register_input(Arc::new(pdb_input::PDBInput::new()));
+        registry.register_input(Arc::new(pdf_input::PDFInput::new()));
+        registry.register_input(Arc::new(pml_input::PMLInput::new()));
+        registry.register_input(Arc::new(rar_input::RARInput::new()));
+        registry.register_input(Arc::new(rb_input::RBInput::new()));
+        registry.register_input(Arc::new(recipe_input::RecipeInput::new()));
+        registry.register_input(Arc::new(rtf_input::RTFInput::new()));
+        registry.register_input(Arc::new(snb_input::SnbInput::new()));
+        registry.register_input(Arc::new(tcr_input::TCRInput::new()));
+        registry.register_input(Arc::new(txt_input::TXTInput::new()));
+        registry.register_input(Arc::new(zip_input::ZIPInput::new()));
+
+        registry.register_output(Arc::new(azw3_output::AZW3Output::new()));
+        registry.register_output(Arc::new(docx_output::DOCXOutput::new()));
+        registry.register_output(Arc::new(epub_output::EPUBOutput::new()));
+        registry.register_output(Arc::new(fb2_output::FB2Output::new()));
+        registry.register_output(Arc::new(html_output::HTMLOutput::new()));
+        registry.register_output(Arc::new(htmlz_output::HTMLZOutput::new()));
+        registry.register_output(Arc::new(lit_output::LitOutput::new()));
+        registry.register_output(Arc::new(lrf_output::LRFOutput::new()));
+        registry.register_output(Arc::new(mobi_output::MOBIOutput::new()));
+        registry.register_output(Arc::new(odt_output::ODTOutput::new()));
+        registry.register_output(Arc::new(oeb_output::OEBOutput::new()));
+        registry.register_output(Arc::new(pdb_output::PDBOutput::new()));
+        registry.register_output(Arc::new(pdf_output::PDFOutput::new()));
+        registry.register_output(Arc::new(pml_output::PMLOutput::new()));
+        registry.register_output(Arc::new(rb_output::RBOutput::new()));
+        registry.register_output(Arc::new(rtf_output::RTFOutput::new()));
+        registry.register_output(Arc::new(snb_output::SnbOutput::new()));
+        registry.register_output(Arc::new(tcr_output::TCROutput::new()));
+        registry.register_output(Arc::new(txt_output::TXTOutput::new()));
+
+        registry
+    }
+
+    pub fn register_input(&mut self, plugin: Arc<dyn InputFormatPlugin>) {
+        self.inputs.push(plugin);
+    }
+
+    pub fn register_output(&mut self, plugin: Arc<dyn OutputFormatPlugin>) {
+        self.outputs.push(plugin);
+    }
+
+    pub fn input_for_extension(&self, ext: &str) -> Option<Arc<dyn InputFormatPlugin>> {
+        let ext = normalize_ext(ext);
+        self.inputs
+            .iter()
+            .rev()
+            .find(|p| p.file_types().iter().any(|t| t.eq_ignore_ascii_case(&ext)))
+            .cloned()
+    }
+
+    pub fn input_for_mime(&self, mime: &str) -> Option<Arc<dyn InputFormatPlugin>> {
+        self.inputs
+            .iter()
+            .rev()
+            .find(|p| p.mime_types().iter().any(|m| m.eq_ignore_ascii_case(mime)))
+            .cloned()
+    }
+
+    pub fn input_for_path(&self, path: &Path) -> Option<Arc<dyn InputFormatPlugin>> {
+        path_ext(path).and_then(|ext| self.input_for_extension(&ext))
+    }
+
+    pub fn output_for_extension(&self, ext: &str) -> Option<Arc<dyn OutputFormatPlugin>> {
+        let ext = normalize_ext(ext);
+        self.outputs
+            .iter()
+            .rev()
+            .find(|p| p.file_types().iter().any(|t| t.eq_ignore_ascii_case(&ext)))
+            .cloned()
+    }
+
+    pub fn output_for_mime(&self, mime: &str) -> Option<Arc<dyn OutputFormatPlugin>> {
+        self.outputs
+            .iter()
+            .rev()
+            .find(|p| p.mime_types().iter().any(|m| m.eq_ignore_ascii_case(mime)))
+            .cloned()
+    }
+
+    pub fn output_for_path(&self, path: &Path) -> Option<Arc<dyn OutputFormatPlugin>> {
+        path_ext(path).and_then(|ext| self.output_for_extension(&ext))
+    }
+
+    /// Sorted list of every extension that can be read.
+    pub fn input_formats(&self) -> Vec<String> {
+        collect_types(self.inputs.iter().flat_map(|p| p.file_types().iter()))
+    }
+
+    /// Sorted list of every extension that can be written.
+    pub fn output_formats(&self) -> Vec<String> {
+        collect_types(self.outputs.iter().flat_map(|p| p.file_types().iter()))
+    }
+}
+
+lazy_static! {
+    static ref GLOBAL_REGISTRY: RwLock<PluginRegistry> =
+        RwLock::new(PluginRegistry::with_builtins());
+}
+
+/// Snapshot of the process wide registry (builtins plus anything registered
+/// through `register_input_plugin` / `register_output_plugin`).
+pub fn global_registry() -> PluginRegistry {
+    GLOBAL_REGISTRY
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+pub fn register_input_plugin(plugin: Arc<dyn InputFormatPlugin>) {
+    GLOBAL_REGISTRY
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .register_input(plugin);
+}
+
+pub fn register_output_plugin(plugin: Arc<dyn OutputFormatPlugin>) {
+    GLOBAL_REGISTRY
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .register_output(plugin);
+}
+
+fn normalize_ext(ext: &str) -> String {
+    ext.trim_start_matches('.').to_lowercase()
+}
+
+fn path_ext(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_lowercase())
+}
+
+fn collect_types<'a>(types: impl Iterator<Item = &'a &'a str>) -> Vec<String> {
+    let mut ans: Vec<String> = types.map(|t| t.to_lowercase()).collect();
+    ans.sort();
+    ans.dedup();
+    ans
+}
@@ -0,0 +1,353 @@
+//! Default covers for books that have none: the title, series and authors
+//! drawn on a background in one of a few styles and colour themes.
+//! Ported from calibre/ebooks/covers.py.
+//!
+//! calibre picks the theme and style at random; here they are picked from a
+//! hash of the title and authors, so that a book always gets the same cover.
+
+mod styles;
+mod text;
+
+pub use styles::CoverStyle;
+
+use crate::metadata::MetaInformation;
+use anyhow::{Context, Result};
+use calibre_utils::constants::config_dir;
+use calibre_utils::img::{image_to_data, DEFAULT_QUALITY};
+use fontdb::Family;
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use text::{Block, FontSet};
+use tiny_skia::{Mask, Paint, Pixmap, Rect, Transform};
+
+pub type Rgb = [u8; 3];
+
+/// Colours used when a theme has an invalid colour.
+const FALLBACK_COLORS: &str = "ffffff 000000 000000 ffffff";
+
+pub const DEFAULT_COLOR_THEMES: [(&str, &str); 4] = [
+    ("Earth", "e8d9ac c7b07b 564628 382d1a"),
+    ("Grass", "d8edb5 abc8a4 375d3b 183128"),
+    ("Water", "d3dcf2 829fe4 00448d 00305a"),
+    ("Silver", "e6f1f5 aab3b6 6e7476 3b3e40"),
+];
+
+/// The `cover_generation` preferences.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CoverPrefs {
+    pub title_font_size: u32,
+    pub subtitle_font_size: u32,
+    pub footer_font_size: u32,
+    pub cover_width: u32,
+    pub cover_height: u32,
+    pub title_font_family: Option<String>,
+    pub subtitle_font_family: Option<String>,
+    pub footer_font_family: Option<String>,
+    /// User defined themes, name to four space separated hex colours
+    pub color_themes: BTreeMap<String, String>,
+    pub disabled_color_themes: Vec<String>,
+    pub disabled_styles: Vec<String>,
+}
+
+impl Default for CoverPrefs {
+    fn default() -> Self {
+        CoverPrefs {
+            title_font_size: 120,
+            subtitle_font_size: 80,
+            footer_font_size: 80,
+            cover_width: 1200,
+            cover_height: 1600,
+            title_font_family: None,
+            subtitle_font_family: None,
+            footer_font_family: None,
+            color_themes: BTreeMap::new(),
+            disabled_color_themes: Vec::new(),
+            disabled_styles: Vec::new(),
+        }
+    }
+}
+
+impl CoverPrefs {
+    /// The preferences saved in `cover_generation.json` in the config
+    /// directory, or the defaults.
+    pub fn load() -> CoverPrefs {
+        let path = config_dir().join("cover_generation.json");
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                log::warn!("Failed to load cover preferences from {:?}: {}", path, e);
+                CoverPrefs::default()
+            }),
+            Err(_) => CoverPrefs::default(),
+        }
+    }
+
+    /// Only use the colour theme `name`, if it exists.
+    pub fn with_color_theme(mut self, name: &str) -> CoverPrefs {
+        let themes = self.all_color_themes();
+        if themes.iter().any(|(n, _)| n == name) {
+            self.disabled_color_themes = themes
+                .into_iter()
+                .map(|(n, _)| n)
+                .filter(|n| n != name)
+                .collect();
+        }
+        self
+    }
+
+    /// Only use the style `name`, if it exists.
+    pub fn with_style(mut self, name: &str) -> CoverPrefs {
+        if CoverStyle::from_name(name).is_some() {
+            self.disabled_styles = CoverStyle::ALL
+                .iter()
+                .map(|s| s.name().to_string())
+                .filter(|n| !n.eq_ignore_ascii_case(name))
+                .collect();
+        }
+        self
+    }
+
+    /// Scale the cover and its fonts, keeping its layout.
+    pub fn scaled(mut self, scale: f32) -> CoverPrefs {
+        for value in [
+            &mut self.cover_width,
+            &mut self.cover_height,
+            &mut self.title_font_size,
+            &mut self.subtitle_font_size,
+            &mut self.footer_font_size,
+        ] {
+            *value = ((*value as f32 * scale) as u32).max(1);
+        }
+        self
+    }
+
+    fn all_color_themes(&self) -> Vec<(String, String)> {
+        let mut themes: BTreeMap<String, String> = DEFAULT_COLOR_THEMES
+            .iter()
+            .map(|(n, t)| (n.to_string(), t.to_string()))
+            .collect();
+        themes.extend(self.color_themes.clone());
+        themes.into_iter().collect()
+    }
+
+    /// The enabled colour themes, or the built-in ones when all are
+    /// disabled.
+    pub fn color_themes(&self) -> Vec<[Rgb; 4]> {
+        let themes: Vec<[Rgb; 4]> = self
+            .all_color_themes()
+            .into_iter()
+            .filter(|(name, _)| !self.disabled_color_themes.contains(name))
+            .map(|(_, theme)| parse_theme(&theme))
+            .collect();
+        if themes.is_empty() {
+            DEFAULT_COLOR_THEMES
+                .iter()
+                .map(|(_, theme)| parse_theme(theme))
+                .collect()
+        } else {
+            themes
+        }
+    }
+
+    /// The enabled styles, or all of them when all are disabled.
+    pub fn styles(&self) -> Vec<CoverStyle> {
+        let styles: Vec<CoverStyle> = CoverStyle::ALL
+            .into_iter()
+            .filter(|s| !self.disabled_styles.iter().any(|d| d == s.name()))
+            .collect();
+        if styles.is_empty() {
+            CoverStyle::ALL.to_vec()
+        } else {
+            styles
+        }
+    }
+}
+
+/// Four colours from a string of space separated hex values, falling back
+/// to black and white for invalid ones.
+fn parse_theme(theme: &str) -> [Rgb; 4] {
+    let parse = |hex: Option<&str>| -> Option<Rgb> {
+        let hex = hex?.trim_start_matches('#');
+        if hex.len() != 6 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+    };
+    let mut parts = theme.split_whitespace();
+    let mut fallback = FALLBACK_COLORS.split_whitespace();
+    std::array::from_fn(|_| {
+        let default = parse(fallback.next()).unwrap_or([0, 0, 0]);
+        parse(parts.next()).unwrap_or(default)
+    })
+}
+
+/// calibre's formatted series index: whole numbers without decimals.
+pub fn fmt_sidx(index: f64) -> String {
+    if index.fract() == 0.0 {
+        format!("{}", index as i64)
+    } else {
+        let s = format!("{:.2}", index);
+        s.trim_end_matches('0').trim_end_matches('.').to_string()
+    }
+}
+
+fn escape_formatting(val: &str) -> String {
+    val.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The title, subtitle and footer of the default templates.
+fn format_text(mi: &MetaInformation) -> (String, String, String) {
+    let title = format!("<b>{}", escape_formatting(&mi.title));
+    let subtitle = match mi.series.as_deref().filter(|s| !s.is_empty()) {
+        Some(series) => format!(
+            "<i>{}</i> - {}",
+            escape_formatting(series),
+            fmt_sidx(mi.series_index)
+        ),
+        None => String::new(),
+    };
+    let footer = mi
+        .authors
+        .iter()
+        .filter(|a| a.as_str() != "Unknown")
+        .take(2)
+        .map(|a| format!("<b>{}", escape_formatting(a)))
+        .collect::<Vec<_>>()
+        .join("<br>");
+    (title, subtitle, footer)
+}
+
+/// FNV-1a, so the same book picks the same theme and style everywhere.
+fn pick(mi: &MetaInformation, salt: u8, count: usize) -> usize {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let key = std::iter::once(salt)
+        .chain(mi.title.bytes())
+        .chain(mi.authors.iter().flat_map(|a| a.bytes()));
+    for byte in key {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    (hash % count.max(1) as u64) as usize
+}
+
+/// Generate a JPEG cover for `mi`.
+pub fn generate_cover(mi: &MetaInformation, prefs: &CoverPrefs) -> Result<Vec<u8>> {
+    let themes = prefs.color_themes();
+    let theme = themes[pick(mi, 0, themes.len())];
+    let styles = prefs.styles();
+    let style = styles[pick(mi, 1, styles.len())];
+
+    let (width, height) = (prefs.cover_width.max(1), prefs.cover_height.max(1));
+    let mut pixmap = Pixmap::new(width, height).context("Invalid cover size")?;
+    let (title, subtitle, footer) = format_text(mi);
+
+    // Layout
+    let (hmargin, vmargin) = style.margins(prefs);
+    let text_width = (width as f32 - 2.0 * hmargin).max(1.0);
+    let max_height = (height / 3) as f32;
+    let family = |f: &Option<String>, default: &str| f.clone().unwrap_or(default.to_string());
+    let title_fonts = FontSet::new(
+        &family(&prefs.title_font_family, "Liberation Serif"),
+        Family::Serif,
+        prefs.title_font_size as f32,
+    );
+    let subtitle_fonts = FontSet::new(
+        &family(&prefs.subtitle_font_family, "Liberation Sans"),
+        Family::SansSerif,
+        prefs.subtitle_font_size as f32,
+    );
+    let footer_fonts = FontSet::new(
+        &family(&prefs.footer_font_family, "Liberation Serif"),
+        Family::Serif,
+        prefs.footer_font_size as f32,
+    );
+
+    let mut title_block = Block::new(
+        &title,
+        text_width,
+        &title_fonts,
+        max_height,
+        text::Align::Center,
+    );
+    (title_block.x, title_block.y) = (hmargin, vmargin);
+    let mut subtitle_block = Block::empty();
+    if !subtitle.is_empty() {
+        let gap = 2.0 * title_block.leading;
+        subtitle_block = Block::new(
+            &subtitle,
+            text_width,
+            &subtitle_fonts,
+            max_height - title_block.height - gap,
+            text::Align::Center,
+        );
+        (subtitle_block.x, subtitle_block.y) = (hmargin, vmargin + title_block.height + gap);
+    }
+    let mut footer_block = Block::new(
+        &footer,
+        text_width,
+        &footer_fonts,
+        max_height,
+        style.footer_align(),
+    );
+    (footer_block.x, footer_block.y) = (hmargin, height as f32 - vmargin - footer_block.height);
+
+    // Painting
+    let colors = style.draw(&mut pixmap, &theme, &title_block, &subtitle_block);
+    let mut mask = Mask::new(width, height).context("Invalid cover size")?;
+    let full = Rect::from_xywh(0.0, 0.0, width as f32, height as f32).unwrap();
+    for (block, color) in [&title_block, &subtitle_block, &footer_block]
+        .into_iter()
+        .zip(colors)
+    {
+        // Etched: a faint white copy one pixel down and right, then the text
+        for (offset, ink) in [
+            ((1.0, 1.0), tiny_skia::Color::from_rgba8(255, 255, 255, 125)),
+            ((0.0, 0.0), styles::color(color)),
+        ] {
+            mask.clear();
+            block.draw(&mut mask, offset);
+            let mut paint = Paint::default();
+            paint.set_color(ink);
+            pixmap.fill_rect(full, &paint, Transform::identity(), Some(&mask));
+        }
+    }
+
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|p| {
+            let c = p.demultiply();
+            [c.red(), c.green(), c.blue(), c.alpha()]
+        })
+        .collect();
+    let img = RgbaImage::from_raw(width, height, pixels).context("Invalid cover size")?;
+    image_to_data(
+        &DynamicImage::ImageRgba8(img),
+        ImageFormat::Jpeg,
+        DEFAULT_QUALITY,
+    )
+}
+
+/// Generate a cover from the given title, authors and series, whatever the
+/// rest of the book's metadata is.
+pub fn create_cover(
+    title: &str,
+    authors: &[String],
+    series: Option<&str>,
+    series_index: f64,
+    prefs: &CoverPrefs,
+) -> Result<Vec<u8>> {
+    let mi = MetaInformation {
+        title: title.to_string(),
+        authors: authors.to_vec(),
+        series: series.map(|s| s.to_string()),
+        series_index,
+        ..MetaInformation::default()
+    };
+    generate_cover(&mi, prefs)
+}
@@ -0,0 +1,454 @@
+//! The backgrounds generated covers are drawn on.
+
+use super::text::{Align, Block};
+use super::{CoverPrefs, Rgb};
+use tiny_skia::{
+    Color, FillRule, GradientStop, LineJoin, LinearGradient, Paint, Path, PathBuilder, Pixmap,
+    Point, RadialGradient, Rect, Shader, SpreadMode, Stroke, Transform,
+};
+
+/// Outline of the ornaments in the corners of the `Ornamental` style, in
+/// the coordinates of a 400x500 cover.
+const CORNER_VECTOR: &str = "m 67.791903,64.260958 c -4.308097,-2.07925 -4.086719,-8.29575 0.334943,-9.40552 4.119758,-1.03399 8.732363,5.05239 5.393055,7.1162 -0.55,0.33992 -1,1.04147 -1,1.55902 0,1.59332 2.597425,1.04548 5.365141,-1.1316 1.999416,-1.57274 2.634859,-2.96609 2.634859,-5.7775 0,-9.55787 -9.827495,-13.42961 -24.43221,-9.62556 -3.218823,0.83839 -5.905663,1.40089 -5.970755,1.25 -0.06509,-0.1509 -0.887601,-1.19493 -1.827799,-2.32007 -1.672708,-2.00174 -1.636693,-2.03722 1.675668,-1.65052 1.861815,0.21736 6.685863,-0.35719 10.720107,-1.27678 12.280767,-2.79934 20.195487,-0.0248 22.846932,8.0092 3.187273,9.65753 -6.423297,17.7497 -15.739941,13.25313 z m 49.881417,-20.53932 c -3.19204,-2.701 -3.72967,-6.67376 -1.24009,-9.16334 2.48236,-2.48236 5.35141,-2.67905 7.51523,-0.51523 1.85966,1.85966 2.07045,6.52954 0.37143,8.22857 -2.04025,2.04024 3.28436,1.44595 6.92316,-0.77272 9.66959,-5.89579 0.88581,-18.22422 -13.0777,-18.35516 -5.28594,-0.0496 -10.31098,1.88721 -14.26764,5.4991 -1.98835,1.81509 -2.16454,1.82692 -2.7936,0.18763 -0.40973,-1.06774 0.12141,-2.82197 1.3628,-4.50104 2.46349,-3.33205 1.67564,-4.01299 -2.891784,-2.49938 -2.85998,0.94777 -3.81038,2.05378 -5.59837,6.51495 -1.184469,2.95536 -3.346819,6.86882 -4.805219,8.69657 -1.4584,1.82776 -2.65164,4.02223 -2.65164,4.87662 0,3.24694 -4.442667,0.59094 -5.872557,-3.51085 -1.361274,-3.90495 0.408198,-8.63869 4.404043,-11.78183 5.155844,-4.05558 1.612374,-3.42079 -9.235926,1.65457 -12.882907,6.02725 -16.864953,7.18038 -24.795556,7.18038 -8.471637,0 -13.38802,-1.64157 -17.634617,-5.88816 -2.832233,-2.83224 -3.849773,-4.81378 -4.418121,-8.6038 -1.946289,-12.9787795 8.03227,-20.91713135 19.767685,-15.7259993 5.547225,2.4538018 6.993631,6.1265383 3.999564,10.1557393 -5.468513,7.35914 -15.917883,-0.19431 -10.657807,-7.7041155 1.486298,-2.1219878 1.441784,-2.2225068 -0.984223,-2.2225068 -1.397511,0 -4.010527,1.3130878 -5.806704,2.9179718 -2.773359,2.4779995 -3.265777,3.5977995 -3.265777,7.4266705 0,5.10943 2.254112,8.84197 7.492986,12.40748 8.921325,6.07175 19.286666,5.61396 37.12088,-1.63946 15.35037,-6.24321 21.294999,-7.42408 34.886123,-6.92999 11.77046,0.4279 19.35803,3.05537 24.34054,8.42878 4.97758,5.3681 2.53939,13.58271 -4.86733,16.39873 -4.17361,1.58681 -11.00702,1.19681 -13.31978,-0.76018 z m 26.50156,-0.0787 c -2.26347,-2.50111 -2.07852,-7.36311 0.39995,-10.51398 2.68134,-3.40877 10.49035,-5.69409 18.87656,-5.52426 l 6.5685,0.13301 -7.84029,0.82767 c -8.47925,0.89511 -12.76997,2.82233 -16.03465,7.20213 -1.92294,2.57976 -1.96722,3.00481 -0.57298,5.5 1.00296,1.79495 2.50427,2.81821 4.46514,3.04333 2.92852,0.33623 2.93789,0.32121 1.08045,-1.73124 -1.53602,-1.69728 -1.64654,-2.34411 -0.61324,-3.58916 2.84565,-3.4288 7.14497,-0.49759 5.03976,3.43603 -1.86726,3.48903 -8.65528,4.21532 -11.3692,1.21647 z m -4.17462,-14.20302 c -0.38836,-0.62838 -0.23556,-1.61305 0.33954,-2.18816 1.3439,-1.34389 4.47714,-0.17168 3.93038,1.47045 -0.5566,1.67168 -3.38637,2.14732 -4.26992,0.71771 z m -8.48037,-9.1829 c -12.462,-4.1101 -12.53952,-4.12156 -25.49998,-3.7694 -24.020921,0.65269 -32.338219,0.31756 -37.082166,-1.49417 -5.113999,-1.95305 -8.192504,-6.3647405 -6.485463,-9.2940713 0.566827,-0.972691 1.020091,-1.181447 1.037211,-0.477701 0.01685,0.692606 1.268676,1.2499998 2.807321,1.2499998 1.685814,0 4.868609,1.571672 8.10041,4.0000015 4.221481,3.171961 6.182506,3.999221 9.473089,3.996261 l 4.149585,-0.004 -3.249996,-1.98156 c -3.056252,-1.863441 -4.051566,-3.8760635 -2.623216,-5.3044145 0.794,-0.794 6.188222,1.901516 9.064482,4.5295635 1.858669,1.698271 3.461409,1.980521 10.559493,1.859621 11.30984,-0.19266 20.89052,1.29095 31.97905,4.95208 7.63881,2.52213 11.51931,3.16471 22.05074,3.65141 7.02931,0.32486 13.01836,0.97543 13.30902,1.44571 0.29065,0.47029 -5.2356,0.83436 -12.28056,0.80906 -12.25942,-0.044 -13.34537,-0.2229 -25.30902,-4.16865 z";
+
+/// Size of the cover the `Ornamental` style is designed for.
+const VIEWPORT: (f32, f32) = (400.0, 500.0);
+
+/// How much the ends of the `Banner` slant.
+const BANNER_GRADE: f32 = 0.07;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverStyle {
+    Cross,
+    Half,
+    Banner,
+    Ornamental,
+    Blocks,
+}
+
+impl CoverStyle {
+    pub const ALL: [CoverStyle; 5] = [
+        CoverStyle::Banner,
+        CoverStyle::Blocks,
+        CoverStyle::Half,
+        CoverStyle::Ornamental,
+        CoverStyle::Cross,
+    ];
+
+    /// The name used in the preferences.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CoverStyle::Cross => "The Cross",
+            CoverStyle::Half => "Half and Half",
+            CoverStyle::Banner => "Banner",
+            CoverStyle::Ornamental => "Ornamental",
+            CoverStyle::Blocks => "Blocks",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<CoverStyle> {
+        CoverStyle::ALL
+            .into_iter()
+            .find(|s| s.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Horizontal and vertical space left around the text.
+    pub(super) fn margins(&self, prefs: &CoverPrefs) -> (f32, f32) {
+        let (width, height) = (prefs.cover_width as f32, prefs.cover_height as f32);
+        match self {
+            CoverStyle::Banner => ((0.15 * width).floor(), (50.0 / 800.0 * height).floor()),
+            CoverStyle::Ornamental => (
+                (51.0 / VIEWPORT.0 * width).floor(),
+                (83.0 / VIEWPORT.1 * height).floor(),
+            ),
+            _ => (
+                (50.0 / 600.0 * width).floor(),
+                (50.0 / 800.0 * height).floor(),
+            ),
+        }
+    }
+
+    pub(super) fn footer_align(&self) -> Align {
+        match self {
+            CoverStyle::Blocks => Align::Right,
+            _ => Align::Center,
+        }
+    }
+
+    /// Paint the background and return the colours of the title, subtitle
+    /// and footer text.
+    pub(super) fn draw(
+        &self,
+        pixmap: &mut Pixmap,
+        theme: &[Rgb; 4],
+        title: &Block,
+        subtitle: &Block,
+    ) -> [Rgb; 3] {
+        let [color1, color2, ccolor1, ccolor2] = *theme;
+        let (width, height) = (pixmap.width() as f32, pixmap.height() as f32);
+        let full = Rect::from_xywh(0.0, 0.0, width, height).unwrap();
+        match self {
+            CoverStyle::Cross => {
+                pixmap.fill(color(color1));
+                let band_height = title.height
+                    + subtitle.height
+                    + (subtitle.line_spacing / 2.0).floor()
+                    + title.leading;
+                if let Some(band) = rounded_rect(0.0, title.y, width, band_height, 0.05 * width) {
+                    pixmap.fill_path(
+                        &band,
+                        &paint(color2),
+                        FillRule::Winding,
+                        Transform::identity(),
+                        None,
+                    );
+                }
+                if let Some(rect) = Rect::from_xywh(0.0, 0.0, title.x, height) {
+                    pixmap.fill_rect(rect, &paint(color2), Transform::identity(), None);
+                }
+                [ccolor2, ccolor2, ccolor1]
+            }
+            CoverStyle::Half => {
+                let shader = LinearGradient::new(
+                    Point::from_xy(0.0, 0.0),
+                    Point::from_xy(0.0, height),
+                    vec![
+                        GradientStop::new(0.0, color(color1)),
+                        GradientStop::new(0.7, color(color2)),
+                        GradientStop::new(1.0, color(color1)),
+                    ],
+                    SpreadMode::Pad,
+                    Transform::identity(),
+                );
+                fill_with(pixmap, full, shader, color1);
+                [ccolor1, ccolor1, ccolor1]
+            }
+            CoverStyle::Banner => {
+                draw_banner(pixmap, theme, title, subtitle);
+                [ccolor2, ccolor2, ccolor1]
+            }
+            CoverStyle::Ornamental => {
+                draw_ornamental(pixmap, theme);
+                [ccolor2, ccolor2, ccolor1]
+            }
+            CoverStyle::Blocks => {
+                pixmap.fill(color(color1));
+                let top = height - (height / 3.0).floor();
+                if let Some(rect) = Rect::from_xywh(0.0, top, width, height - top) {
+                    pixmap.fill_rect(rect, &paint(color2), Transform::identity(), None);
+                }
+                [ccolor1, ccolor1, ccolor2]
+            }
+        }
+    }
+}
+
+pub(super) fn color(rgb: Rgb) -> Color {
+    Color::from_rgba8(rgb[0], rgb[1], rgb[2], 255)
+}
+
+fn paint(rgb: Rgb) -> Paint<'static> {
+    let mut paint = Paint::default();
+    paint.set_color(color(rgb));
+    paint.anti_alias = true;
+    paint
+}
+
+fn fill_with(pixmap: &mut Pixmap, rect: Rect, shader: Option<Shader<'static>>, fallback: Rgb) {
+    let mut paint = paint(fallback);
+    if let Some(shader) = shader {
+        paint.shader = shader;
+    }
+    pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+}
+
+/// Qt's `QColor::darker()`, half the brightness.
+fn darker(rgb: Rgb) -> Rgb {
+    rgb.map(|c| c / 2)
+}
+
+fn rounded_rect(x: f32, y: f32, width: f32, height: f32, radius: f32) -> Option<Path> {
+    let r = radius.min(width / 2.0).min(height / 2.0).max(0.0);
+    // Control point distance approximating a quarter circle
+    let k = r * 0.447_715;
+    let (right, bottom) = (x + width, y + height);
+    let mut pb = PathBuilder::new();
+    pb.move_to(x + r, y);
+    pb.line_to(right - r, y);
+    pb.cubic_to(right - k, y, right, y + k, right, y + r);
+    pb.line_to(right, bottom - r);
+    pb.cubic_to(right, bottom - k, right - k, bottom, right - r, bottom);
+    pb.line_to(x + r, bottom);
+    pb.cubic_to(x + k, bottom, x, bottom - k, x, bottom - r);
+    pb.line_to(x, y + r);
+    pb.cubic_to(x, y + k, x + k, y, x + r, y);
+    pb.close();
+    pb.finish()
+}
+
+/// A path builder that knows where it is, for the relative curves of the
+/// banner.
+struct Pen {
+    pb: PathBuilder,
+    pos: (f32, f32),
+}
+
+impl Pen {
+    fn new(x: f32, y: f32) -> Pen {
+        let mut pb = PathBuilder::new();
+        pb.move_to(x, y);
+        Pen { pb, pos: (x, y) }
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.pb.line_to(x, y);
+        self.pos = (x, y);
+    }
+
+    /// A cubic curve to `pos + (dx, dy)` whose control points are at
+    /// fractions of its length along it and amplitudes across it.
+    fn curve(&mut self, dx: f32, dy: f32, c1: (f32, f32), c2: (f32, f32)) {
+        let length = (dx * dx + dy * dy).sqrt();
+        let angle = dy.atan2(dx);
+        let rotate = |x: f32, y: f32| {
+            (
+                x * angle.cos() - y * angle.sin(),
+                x * angle.sin() + y * angle.cos(),
+            )
+        };
+        let (c1x, c1y) = rotate(c1.0 * length, c1.1 * length);
+        let (c2x, c2y) = rotate(c2.0 * length, c2.1 * length);
+        let (x, y) = self.pos;
+        self.pb
+            .cubic_to(x + c1x, y + c1y, x + c2x, y + c2y, x + dx, y + dy);
+        self.pos = (x + dx, y + dy);
+    }
+
+    fn finish(mut self, close: bool) -> Option<Path> {
+        if close {
+            self.pb.close();
+        }
+        self.pb.finish()
+    }
+}
+
+fn draw_banner(pixmap: &mut Pixmap, theme: &[Rgb; 4], title: &Block, subtitle: &Block) {
+    let [color1, color2, _, ccolor2] = *theme;
+    let cover_width = pixmap.width() as f32;
+    let hmargin = (0.15 * cover_width).floor();
+    let fold_width = (0.1 * cover_width).floor();
+    pixmap.fill(color(color1));
+
+    let top = title.y + 2.0;
+    let extra_spacing = if subtitle.line_spacing > 0.0 {
+        (subtitle.line_spacing / 2.0).floor()
+    } else {
+        (title.line_spacing / 3.0).floor()
+    };
+    let height = title.height + subtitle.height + extra_spacing + title.leading;
+    let right = cover_width - 1.0 - hmargin;
+    let width = right - hmargin;
+
+    // The main banner
+    let mut main = Pen::new(hmargin, top);
+    main.curve(cover_width - 2.0 * hmargin, 0.0, (0.1, -0.1), (0.9, -0.1));
+    let deltax = BANNER_GRADE * height;
+    main.line_to(right + deltax, top + height);
+    let right_corner = main.pos;
+    main.curve(-width - 2.0 * deltax, 0.0, (0.1, 0.05), (0.9, 0.05));
+    let left_corner = main.pos;
+
+    // The folded ends sticking out behind it
+    let rtop = top + height * 0.1;
+    let width23 = (0.67 * fold_width).floor();
+    let fold = |x: f32, m: f32, corner: (f32, f32)| {
+        let mut outer = Pen::new(x, rtop);
+        outer.curve(fold_width * m, 0.0, (0.1, 0.1 * m), (0.5, -0.2 * m));
+        let fold_upper = outer.pos;
+        outer.line_to(fold_upper.0 - deltax * m, fold_upper.1 + height);
+        let fold_corner = outer.pos;
+        outer.curve(-fold_width * m, 0.0, (0.2, -0.1 * m), (0.8, -0.1 * m));
+        outer.curve(deltax * m, -height, (0.2, 0.1 * m), (0.8, 0.1 * m));
+        let mut inner = Pen::new(corner.0, corner.1);
+        inner.curve(
+            fold_corner.0 - corner.0,
+            fold_corner.1 - corner.1,
+            (0.5, 0.3 * m),
+            (1.0, 0.0),
+        );
+        inner.line_to(fold_upper.0, fold_upper.1);
+        (outer.finish(false), inner.finish(true))
+    };
+    let (left_fold, left_inner) = fold(hmargin - width23, 1.0, left_corner);
+    let (right_fold, right_inner) = fold(right + width23, -1.0, right_corner);
+
+    let stroke = Stroke {
+        width: 3.0,
+        line_join: LineJoin::Round,
+        ..Stroke::default()
+    };
+    let outline = paint(ccolor2);
+    let mut draw = |path: Option<Path>, fill: Rgb| {
+        if let Some(path) = path {
+            let t = Transform::identity();
+            pixmap.fill_path(&path, &paint(fill), FillRule::Winding, t, None);
+            pixmap.stroke_path(&path, &outline, &stroke, t, None);
+        }
+    };
+    draw(left_fold, color2);
+    draw(right_fold, color2);
+    draw(left_inner, darker(color2));
+    draw(right_inner, darker(color2));
+    draw(main.finish(true), color2);
+}
+
+fn draw_ornamental(pixmap: &mut Pixmap, theme: &[Rgb; 4]) {
+    let [color1, color2, ccolor1, _] = *theme;
+    let (width, height) = (pixmap.width() as f32, pixmap.height() as f32);
+    let center = Point::from_xy(width / 2.0, height / 2.0);
+    let shader = RadialGradient::new(
+        center,
+        center,
+        width,
+        vec![
+            GradientStop::new(0.0, color(color1)),
+            GradientStop::new(1.0, color(color2)),
+        ],
+        SpreadMode::Pad,
+        Transform::identity(),
+    );
+    if let Some(full) = Rect::from_xywh(0.0, 0.0, width, height) {
+        fill_with(pixmap, full, shader, color1);
+    }
+
+    // Draw in the coordinates of the design
+    let window = Transform::from_scale(width / VIEWPORT.0, height / VIEWPORT.1);
+    let ink = paint(ccolor1);
+    if let Some(corner) = svg_path(CORNER_VECTOR) {
+        let mirrors = [
+            window,
+            window.pre_scale(-1.0, 1.0).pre_translate(-VIEWPORT.0, 0.0),
+            window.pre_scale(1.0, -1.0).pre_translate(0.0, -VIEWPORT.1),
+            window
+                .pre_scale(-1.0, -1.0)
+                .pre_translate(-VIEWPORT.0, -VIEWPORT.1),
+        ];
+        for t in mirrors {
+            pixmap.fill_path(&corner, &ink, FillRule::Winding, t, None);
+            // The same ornament, turned to run down the side
+            let turned = t
+                .pre_rotate(90.0)
+                .pre_translate(100.0, -100.0)
+                .pre_scale(1.0, -1.0)
+                .pre_translate(-103.0, -97.0);
+            pixmap.fill_path(&corner, &ink, FillRule::Winding, turned, None);
+        }
+    }
+
+    for (stroke_width, ys, xs) in [
+        (1.0, [28.4, 471.7], [31.3, 368.7]),
+        (1.8, [23.8, 476.7], [26.3, 373.7]),
+    ] {
+        let stroke = Stroke {
+            width: stroke_width,
+            ..Stroke::default()
+        };
+        let mut pb = PathBuilder::new();
+        for y in ys {
+            pb.move_to(160.0, y);
+            pb.line_to(240.0, y);
+        }
+        for x in xs {
+            pb.move_to(x, 155.0);
+            pb.line_to(x, 345.0);
+        }
+        if let Some(lines) = pb.finish() {
+            pixmap.stroke_path(&lines, &ink, &stroke, window, None);
+        }
+    }
+}
+
+/// Parse the subset of SVG path data the ornaments use: move, line and
+/// cubic curve commands, absolute or relative, and close path.
+fn svg_path(data: &str) -> Option<Path> {
+    let mut pb = PathBuilder::new();
+    let mut tokens = SvgTokens { rest: data };
+    let mut command = ' ';
+    let (mut x, mut y) = (0.0f32, 0.0f32);
+    let mut start = (0.0f32, 0.0f32);
+    while let Some(token) = tokens.next() {
+        let token = match token {
+            SvgToken::Command(c) => {
+                command = c;
+                if c.eq_ignore_ascii_case(&'z') {
+                    pb.close();
+                    (x, y) = start;
+                    continue;
+                }
+                tokens.number()?
+            }
+            SvgToken::Number(n) => n,
+        };
+        let relative = command.is_ascii_lowercase();
+        let (ox, oy) = if relative { (x, y) } else { (0.0, 0.0) };
+        match command.to_ascii_lowercase() {
+            'm' => {
+                (x, y) = (ox + token, oy + tokens.number()?);
+                pb.move_to(x, y);
+                start = (x, y);
+                // Further coordinate pairs are lines
+                command = if relative { 'l' } else { 'L' };
+            }
+            'l' => {
+                (x, y) = (ox + token, oy + tokens.number()?);
+                pb.line_to(x, y);
+            }
+            'c' => {
+                let c1 = (ox + token, oy + tokens.number()?);
+                let c2 = (ox + tokens.number()?, oy + tokens.number()?);
+                (x, y) = (ox + tokens.number()?, oy + tokens.number()?);
+                pb.cubic_to(c1.0, c1.1, c2.0, c2.1, x, y);
+            }
+            _ => return None,
+        }
+    }
+    pb.finish()
+}
+
+enum SvgToken {
+    Command(char),
+    Number(f32),
+}
+
+struct SvgTokens<'a> {
+    rest: &'a str,
+}
+
+impl SvgTokens<'_> {
+    fn next(&mut self) -> Option<SvgToken> {
+        self.rest = self
+            .rest
+            .trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        let c = self.rest.chars().next()?;
+        if c.is_ascii_alphabetic() {
+            self.rest = &self.rest[1..];
+            return Some(SvgToken::Command(c));
+        }
+        let end = self
+            .rest
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| !(c.is_ascii_digit() || *c == '.'))
+            .map_or(self.rest.len(), |(i, _)| i);
+        let number = self.rest[..end].parse().ok()?;
+        self.rest = &self.rest[end..];
+        Some(SvgToken::Number(number))
+    }
+
+    fn number(&mut self) -> Option<f32> {
+        match self.next()? {
+            SvgToken::Number(n) => Some(n),
+            SvgToken::Command(_) => None,
+        }
+    }
+}
@@ -0,0 +1,574 @@
+//! Fonts and text layout for generated covers.
+//!
+//! Fonts come from the system (and the `fonts` resources directory) through
+//! fontdb and are rasterized with ab_glyph. When no font at all is
+//! installed a small built-in bitmap font is used, so that a cover always
+//! shows its text.
+
+use crate::pdf::fonts::FONT_DB;
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont};
+use calibre_utils::filenames::ascii_text;
+use fontdb::{Family, Query, Style, Weight};
+use tiny_skia::Mask;
+
+/// Horizontal placement of the lines of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct TextStyle {
+    bold: bool,
+    italic: bool,
+}
+
+enum Face {
+    Outline {
+        font: FontVec,
+        scale: PxScale,
+        /// Embolden by drawing twice, the family has no bold face
+        synthetic_bold: bool,
+    },
+    Builtin {
+        /// Size of a pixel of the bitmap font
+        unit: f32,
+        bold: bool,
+        italic: bool,
+    },
+}
+
+impl Face {
+    /// `generic` is used when `family` is not installed, any installed
+    /// font after that.
+    fn load(family: &str, generic: Family, size: f32, style: TextStyle) -> Face {
+        let builtin = Face::Builtin {
+            unit: size / 8.0,
+            bold: style.bold,
+            italic: style.italic,
+        };
+        let weight = if style.bold {
+            Weight::BOLD
+        } else {
+            Weight::NORMAL
+        };
+        let query = Query {
+            families: &[Family::Name(family), generic],
+            weight,
+            style: if style.italic {
+                Style::Italic
+            } else {
+                Style::Normal
+            },
+            ..Query::default()
+        };
+        let Some(id) = FONT_DB
+            .query(&query)
+            .or_else(|| FONT_DB.faces().next().map(|f| f.id))
+        else {
+            return builtin;
+        };
+        let synthetic_bold = style.bold
+            && FONT_DB
+                .face(id)
+                .is_some_and(|f| f.weight.0 < Weight::SEMIBOLD.0);
+        let font = FONT_DB.with_face_data(id, |data, index| {
+            FontVec::try_from_vec_and_index(data.to_vec(), index).ok()
+        });
+        match font.flatten() {
+            Some(font) => {
+                // Sizes are em sizes, as for a QFont pixel size
+                let units_per_em = font.units_per_em().unwrap_or(1000.0);
+                let scale = PxScale::from(size * font.height_unscaled() / units_per_em);
+                Face::Outline {
+                    font,
+                    scale,
+                    synthetic_bold,
+                }
+            }
+            None => builtin,
+        }
+    }
+
+    fn ascent(&self) -> f32 {
+        match self {
+            Face::Outline { font, scale, .. } => font.as_scaled(*scale).ascent(),
+            Face::Builtin { unit, .. } => 7.0 * unit,
+        }
+    }
+
+    /// Negative, below the baseline.
+    fn descent(&self) -> f32 {
+        match self {
+            Face::Outline { font, scale, .. } => font.as_scaled(*scale).descent(),
+            Face::Builtin { unit, .. } => -unit,
+        }
+    }
+
+    fn leading(&self) -> f32 {
+        match self {
+            Face::Outline { font, scale, .. } => font.as_scaled(*scale).line_gap().max(0.0),
+            Face::Builtin { unit, .. } => *unit,
+        }
+    }
+
+    fn advance(&self, c: char, next: Option<char>) -> f32 {
+        match self {
+            Face::Outline {
+                font,
+                scale,
+                synthetic_bold,
+            } => {
+                let font = font.as_scaled(*scale);
+                let id = font.glyph_id(c);
+                let kern = next.map_or(0.0, |n| font.kern(id, font.glyph_id(n)));
+                let bold = if *synthetic_bold {
+                    bold_offset(scale.y)
+                } else {
+                    0.0
+                };
+                font.h_advance(id) + kern + bold
+            }
+            Face::Builtin { unit, .. } => 6.0 * unit,
+        }
+    }
+
+    /// Add the coverage of `c` drawn with its origin on the baseline at
+    /// (`x`, `y`) to `mask`.
+    fn draw(&self, c: char, x: f32, y: f32, mask: &mut Mask) {
+        match self {
+            Face::Outline {
+                font,
+                scale,
+                synthetic_bold,
+            } => {
+                let offsets: &[f32] = if *synthetic_bold {
+                    &[0.0, bold_offset(scale.y)]
+                } else {
+                    &[0.0]
+                };
+                for dx in offsets {
+                    let glyph = font
+                        .glyph_id(c)
+                        .with_scale_and_position(*scale, ab_glyph::point(x + dx, y));
+                    if let Some(outline) = font.outline_glyph(glyph) {
+                        let bounds = outline.px_bounds();
+                        outline.draw(|gx, gy, coverage| {
+                            add_coverage(
+                                mask,
+                                bounds.min.x as i32 + gx as i32,
+                                bounds.min.y as i32 + gy as i32,
+                                coverage,
+                            );
+                        });
+                    }
+                }
+            }
+            Face::Builtin { unit, bold, italic } => {
+                let Some(columns) = builtin_glyph(c) else {
+                    return;
+                };
+                let top = y - 7.0 * unit;
+                for (col, bits) in columns.iter().enumerate() {
+                    for row in 0..8 {
+                        if bits & (1 << row) == 0 {
+                            continue;
+                        }
+                        let shear = if *italic {
+                            (7 - row) as f32 * unit * 0.2
+                        } else {
+                            0.0
+                        };
+                        let px = x + col as f32 * unit + shear;
+                        let py = top + row as f32 * unit;
+                        let width = if *bold { unit * 1.6 } else { *unit };
+                        fill_cell(mask, px, py, width, *unit);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn bold_offset(size: f32) -> f32 {
+    (size / 30.0).max(1.0)
+}
+
+fn add_coverage(mask: &mut Mask, x: i32, y: i32, coverage: f32) {
+    let (width, height) = (mask.width() as i32, mask.height() as i32);
+    if x < 0 || y < 0 || x >= width || y >= height {
+        return;
+    }
+    let value = (coverage.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let pixel = &mut mask.data_mut()[(y * width + x) as usize];
+    *pixel = (*pixel).max(value);
+}
+
+fn fill_cell(mask: &mut Mask, x: f32, y: f32, width: f32, height: f32) {
+    let (x0, y0) = (x.round() as i32, y.round() as i32);
+    let (x1, y1) = ((x + width).round() as i32, (y + height).round() as i32);
+    for py in y0..y1.max(y0 + 1) {
+        for px in x0..x1.max(x0 + 1) {
+            add_coverage(mask, px, py, 1.0);
+        }
+    }
+}
+
+/// The regular, bold, italic and bold italic faces of a family at a size.
+pub struct FontSet {
+    faces: [Face; 4],
+}
+
+impl FontSet {
+    pub fn new(family: &str, generic: Family, size: f32) -> FontSet {
+        let face = |bold, italic| Face::load(family, generic, size, TextStyle { bold, italic });
+        FontSet {
+            faces: [
+                face(false, false),
+                face(true, false),
+                face(false, true),
+                face(true, true),
+            ],
+        }
+    }
+
+    fn face(&self, style: TextStyle) -> &Face {
+        &self.faces[style.bold as usize + 2 * style.italic as usize]
+    }
+
+    fn regular(&self) -> &Face {
+        &self.faces[0]
+    }
+
+    /// The text as `face` can draw it. The built-in font only has ASCII.
+    fn drawable(&self, text: &str) -> String {
+        match self.regular() {
+            Face::Outline { .. } => text.to_string(),
+            Face::Builtin { .. } if text.is_ascii() => text.to_string(),
+            Face::Builtin { .. } => ascii_text(text),
+        }
+    }
+}
+
+struct Line {
+    chars: Vec<(char, TextStyle)>,
+    /// Top of the line, relative to the top of the block
+    top: f32,
+    width: f32,
+}
+
+/// A laid out paragraph of text, calibre's `Block`.
+pub struct Block<'a> {
+    fonts: Option<&'a FontSet>,
+    lines: Vec<Line>,
+    width: f32,
+    align: Align,
+    pub x: f32,
+    pub y: f32,
+    pub height: f32,
+    pub leading: f32,
+    pub line_spacing: f32,
+}
+
+impl<'a> Block<'a> {
+    pub fn empty() -> Block<'static> {
+        Block {
+            fonts: None,
+            lines: Vec::new(),
+            width: 0.0,
+            align: Align::Center,
+            x: 0.0,
+            y: 0.0,
+            height: 0.0,
+            leading: 0.0,
+            line_spacing: 0.0,
+        }
+    }
+
+    /// Lay out `text`, whose paragraphs are separated by `<br>` and which
+    /// may use `<b>` and `<i>`, in lines of at most `width` pixels, stopping
+    /// when `max_height` is reached.
+    pub fn new(
+        text: &str,
+        width: f32,
+        fonts: &'a FontSet,
+        max_height: f32,
+        align: Align,
+    ) -> Block<'a> {
+        let regular = fonts.regular();
+        let leading = regular.leading();
+        let line_height = regular.ascent() - regular.descent();
+        let mut block = Block {
+            fonts: Some(fonts),
+            lines: Vec::new(),
+            width,
+            align,
+            x: 0.0,
+            y: 0.0,
+            height: 0.0,
+            leading,
+            line_spacing: line_height + leading,
+        };
+
+        let mut max_height = max_height;
+        let mut top = 0.0;
+        let paragraphs: Vec<&str> = if text.is_empty() {
+            Vec::new()
+        } else {
+            text.split("<br>").collect()
+        };
+        for (i, paragraph) in paragraphs.iter().enumerate() {
+            if i > 0 {
+                top += leading;
+            }
+            let chars = parse_text_formatting(&fonts.drawable(paragraph));
+            let mut height = 0.0;
+            for line in wrap(&chars, width, fonts) {
+                if height + 3.0 * leading >= max_height {
+                    break;
+                }
+                height += leading;
+                let width = line_width(&line, fonts);
+                block.lines.push(Line {
+                    chars: line,
+                    top: top + height,
+                    width,
+                });
+                height += line_height;
+            }
+            max_height -= height;
+            top += height;
+        }
+        if !paragraphs.is_empty() {
+            top += leading;
+        }
+        block.height = top.ceil();
+        block
+    }
+
+    /// Add the coverage of the text, moved by `offset`, to `mask`.
+    pub fn draw(&self, mask: &mut Mask, offset: (f32, f32)) {
+        let Some(fonts) = self.fonts else {
+            return;
+        };
+        for line in &self.lines {
+            let mut x = self.x
+                + offset.0
+                + match self.align {
+                    Align::Center => (self.width - line.width) / 2.0,
+                    Align::Right => self.width - line.width,
+                };
+            let baseline = self.y + offset.1 + line.top + fonts.regular().ascent();
+            for (i, (c, style)) in line.chars.iter().enumerate() {
+                let face = fonts.face(*style);
+                face.draw(*c, x, baseline, mask);
+                let next = line.chars.get(i + 1).filter(|(_, s)| s == style);
+                x += face.advance(*c, next.map(|(n, _)| *n));
+            }
+        }
+    }
+}
+
+/// The characters of `text` with the style `<b>`, `<strong>`, `<i>` and
+/// `<em>` tags give them. Other tags are dropped.
+fn parse_text_formatting(text: &str) -> Vec<(char, TextStyle)> {
+    let mut out = Vec::new();
+    let mut open: Vec<&str> = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        if rest.starts_with('<') {
+            if let Some(end) = rest.find('>') {
+                let tag = rest[1..end].trim_end_matches('/');
+                let is_tag = !tag.is_empty()
+                    && tag
+                        .trim_start_matches('/')
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric());
+                if is_tag {
+                    match tag.strip_prefix('/') {
+                        Some(_) => {
+                            open.pop();
+                        }
+                        None => {
+                            let name = tag.to_ascii_lowercase();
+                            open.push(match name.as_str() {
+                                "b" | "strong" => "b",
+                                "i" | "em" => "i",
+                                _ => "",
+                            });
+                        }
+                    }
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+        let style = TextStyle {
+            bold: open.contains(&"b"),
+            italic: open.contains(&"i"),
+        };
+        if let Some((c, len)) = [("&amp;", '&'), ("&lt;", '<'), ("&gt;", '>')]
+            .iter()
+            .find(|(entity, _)| rest.starts_with(entity))
+            .map(|(entity, c)| (*c, entity.len()))
+        {
+            out.push((c, style));
+            rest = &rest[len..];
+            continue;
+        }
+        let c = rest.chars().next().unwrap();
+        out.push((c, style));
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+fn line_width(line: &[(char, TextStyle)], fonts: &FontSet) -> f32 {
+    line.iter()
+        .enumerate()
+        .map(|(i, (c, style))| {
+            let next = line.get(i + 1).filter(|(_, s)| s == style);
+            fonts.face(*style).advance(*c, next.map(|(n, _)| *n))
+        })
+        .sum()
+}
+
+/// Break text into lines at word boundaries, or anywhere in words too
+/// long for a line.
+fn wrap(chars: &[(char, TextStyle)], width: f32, fonts: &FontSet) -> Vec<Vec<(char, TextStyle)>> {
+    let mut lines = Vec::new();
+    let mut line: Vec<(char, TextStyle)> = Vec::new();
+    for word in chars.split(|(c, _)| c.is_whitespace()) {
+        if word.is_empty() {
+            continue;
+        }
+        let mut candidate = line.clone();
+        if !candidate.is_empty() {
+            candidate.push((' ', word[0].1));
+        }
+        candidate.extend_from_slice(word);
+        if line_width(&candidate, fonts) <= width {
+            line = candidate;
+            continue;
+        }
+        if !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+        }
+        for &c in word {
+            line.push(c);
+            if line.len() > 1 && line_width(&line, fonts) > width {
+                let last = line.pop().unwrap();
+                lines.push(std::mem::replace(&mut line, vec![last]));
+            }
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Columns of the built-in 5x7 font, least significant bit at the top.
+fn builtin_glyph(c: char) -> Option<&'static [u8; 5]> {
+    let index = (c as u32).checked_sub(0x20)? as usize;
+    BUILTIN_FONT.get(index)
+}
+
+const BUILTIN_FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x00, 0x00, 0x5f, 0x00, 0x00], // !
+    [0x00, 0x07, 0x00, 0x07, 0x00], // "
+    [0x14, 0x7f, 0x14, 0x7f, 0x14], // #
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], // $
+    [0x23, 0x13, 0x08, 0x64, 0x62], // %
+    [0x36, 0x49, 0x55, 0x22, 0x50], // &
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '
+    [0x00, 0x1c, 0x22, 0x41, 0x00], // (
+    [0x00, 0x41, 0x22, 0x1c, 0x00], // )
+    [0x14, 0x08, 0x3e, 0x08, 0x14], // *
+    [0x08, 0x08, 0x3e, 0x08, 0x08], // +
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ,
+    [0x08, 0x08, 0x08, 0x08, 0x08], // -
+    [0x00, 0x60, 0x60, 0x00, 0x00], // .
+    [0x20, 0x10, 0x08, 0x04, 0x02], // /
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], // 0
+    [0x00, 0x42, 0x7f, 0x40, 0x00], // 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], // 2
+    [0x21, 0x41, 0x45, 0x4b, 0x31], // 3
+    [0x18, 0x14, 0x12, 0x7f, 0x10], // 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], // 5
+    [0x3c, 0x4a, 0x49, 0x49, 0x30], // 6
+    [0x01, 0x71, 0x09, 0x05, 0x03], // 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], // 8
+    [0x06, 0x49, 0x49, 0x29, 0x1e], // 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], // :
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], // <
+    [0x14, 0x14, 0x14, 0x14, 0x14], // =
+    [0x00, 0x41, 0x22, 0x14, 0x08], // >
+    [0x02, 0x01, 0x51, 0x09, 0x06], // ?
+    [0x32, 0x49, 0x79, 0x41, 0x3e], // @
+    [0x7e, 0x11, 0x11, 0x11, 0x7e], // A
+    [0x7f, 0x49, 0x49, 0x49, 0x36], // B
+    [0x3e, 0x41, 0x41, 0x41, 0x22], // C
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], // D
+    [0x7f, 0x49, 0x49, 0x49, 0x41], // E
+    [0x7f, 0x09, 0x09, 0x09, 0x01], // F
+    [0x3e, 0x41, 0x49, 0x49, 0x7a], // G
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], // H
+    [0x00, 0x41, 0x7f, 0x41, 0x00], // I
+    [0x20, 0x40, 0x41, 0x3f, 0x01], // J
+    [0x7f, 0x08, 0x14, 0x22, 0x41], // K
+    [0x7f, 0x40, 0x40, 0x40, 0x40], // L
+    [0x7f, 0x02, 0x0c, 0x02, 0x7f], // M
+    [0x7f, 0x04, 0x08, 0x10, 0x7f], // N
+    [0x3e, 0x41, 0x41, 0x41, 0x3e], // O
+    [0x7f, 0x09, 0x09, 0x09, 0x06], // P
+    [0x3e, 0x41, 0x51, 0x21, 0x5e], // Q
+    [0x7f, 0x09, 0x19, 0x29, 0x46], // R
+    [0x46, 0x49, 0x49, 0x49, 0x31], // S
+    [0x01, 0x01, 0x7f, 0x01, 0x01], // T
+    [0x3f, 0x40, 0x40, 0x40, 0x3f], // U
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], // V
+    [0x3f, 0x40, 0x38, 0x40, 0x3f], // W
+    [0x63, 0x14, 0x08, 0x14, 0x63], // X
+    [0x07, 0x08, 0x70, 0x08, 0x07], // Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], // Z
+    [0x00, 0x7f, 0x41, 0x41, 0x00], // [
+    [0x02, 0x04, 0x08, 0x10, 0x20], // backslash
+    [0x00, 0x41, 0x41, 0x7f, 0x00], // ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], // ^
+    [0x40, 0x40, 0x40, 0x40, 0x40], // _
+    [0x00, 0x01, 0x02, 0x04, 0x00], // `
+    [0x20, 0x54, 0x54, 0x54, 0x78], // a
+    [0x7f, 0x48, 0x44, 0x44, 0x38], // b
+    [0x38, 0x44, 0x44, 0x44, 0x20], // c
+    [0x38, 0x44, 0x44, 0x48, 0x7f], // d
+    [0x38, 0x54, 0x54, 0x54, 0x18], // e
+    [0x08, 0x7e, 0x09, 0x01, 0x02], // f
+    [0x0c, 0x52, 0x52, 0x52, 0x3e], // g
+    [0x7f, 0x08, 0x04, 0x04, 0x78], // h
+    [0x00, 0x44, 0x7d, 0x40, 0x00], // i
+    [0x20, 0x40, 0x44, 0x3d, 0x00], // j
+    [0x7f, 0x10, 0x28, 0x44, 0x00], // k
+    [0x00, 0x41, 0x7f, 0x40, 0x00], // l
+    [0x7c, 0x04, 0x18, 0x04, 0x78], // m
+    [0x7c, 0x08, 0x04, 0x04, 0x78], // n
+    [0x38, 0x44, 0x44, 0x44, 0x38], // o
+    [0x7c, 0x14, 0x14, 0x14, 0x08], // p
+    [0x08, 0x14, 0x14, 0x18, 0x7c], // q
+    [0x7c, 0x08, 0x04, 0x04, 0x08], // r
+    [0x48, 0x54, 0x54, 0x54, 0x20], // s
+    [0x04, 0x3f, 0x44, 0x40, 0x20], // t
+    [0x3c, 0x40, 0x40, 0x20, 0x7c], // u
+    [0x1c, 0x20, 0x40, 0x20, 0x1c], // v
+    [0x3c, 0x40, 0x30, 0x40, 0x3c], // w
+    [0x44, 0x28, 0x10, 0x28, 0x44], // x
+    [0x0c, 0x50, 0x50, 0x50, 0x3c], // y
+    [0x44, 0x64, 0x54, 0x4c, 0x44], // z
+    [0x00, 0x08, 0x36, 0x41, 0x00], // {
+    [0x00, 0x00, 0x7f, 0x00, 0x00], // |
+    [0x00, 0x41, 0x36, 0x08, 0x00], // }
+    [0x08, 0x04, 0x08, 0x10, 0x08], // ~
+];
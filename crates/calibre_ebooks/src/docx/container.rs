@@ -9,6 +9,15 @@ use zip::ZipArchive; // Reusing OEB Metadata? Or need conversion?
                      // Here we will extract info into a subset of Metadata or just return fields.
                      // For now, let's use OEBMetadata for convenience or create a struct.
 
+/// A relationship of a part, with an internal target resolved to its name
+/// in the package.
+#[derive(Debug, Clone)]
+pub struct Relationship {
+    pub kind: String,
+    pub target: String,
+    pub external: bool,
+}
+
 pub struct DOCX<R: Read + Seek> {
     zip: ZipArchive<R>,
     pub content_types: HashMap<String, String>,
@@ -90,6 +99,48 @@ impl<R: Read + Seek> DOCX<R> {
         Ok(())
     }
 
+    /// The relationships of the part `part_name`, by id. A part without
+    /// relationships has none.
+    pub fn part_relationships(&mut self, part_name: &str) -> HashMap<String, Relationship> {
+        let (dir, file) = match part_name.rsplit_once('/') {
+            Some((dir, file)) => (dir, file),
+            None => ("", part_name),
+        };
+        let rels_name = if dir.is_empty() {
+            format!("_rels/{}.rels", file)
+        } else {
+            format!("{}/_rels/{}.rels", dir, file)
+        };
+        let mut rels = HashMap::new();
+        let Ok(content) = self.read_file(&rels_name) else {
+            return rels;
+        };
+        let text = String::from_utf8_lossy(&content);
+        let Ok(doc) = Document::parse(&text) else {
+            return rels;
+        };
+        for node in doc.descendants().filter(|n| n.has_tag_name("Relationship")) {
+            let (Some(id), Some(target)) = (node.attribute("Id"), node.attribute("Target")) else {
+                continue;
+            };
+            let external = node.attribute("TargetMode") == Some("External");
+            let target = if external {
+                target.to_string()
+            } else {
+                resolve_part_name(dir, target)
+            };
+            rels.insert(
+                id.to_string(),
+                Relationship {
+                    kind: node.attribute("Type").unwrap_or_default().to_string(),
+                    target,
+                    external,
+                },
+            );
+        }
+        rels
+    }
+
     pub fn document_name(&self) -> Result<String, DocxError> {
         if let Some(target) = self.relationships.get(DOCXNamespaces::DOCUMENT) {
             Ok(target.clone())
@@ -137,3 +188,26 @@ impl<R: Read + Seek> DOCX<R> {
         Ok(meta)
     }
 }
+
+/// The package name of `target`, relative to the folder `dir` unless it
+/// starts with a slash.
+fn resolve_part_name(dir: &str, target: &str) -> String {
+    let target = urlencoding::decode(target)
+        .map(|t| t.into_owned())
+        .unwrap_or_else(|_| target.to_string())
+        .replace('\\', "/");
+    let mut parts: Vec<&str> = match target.strip_prefix('/') {
+        Some(_) => Vec::new(),
+        None => dir.split('/').filter(|p| !p.is_empty()).collect(),
+    };
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
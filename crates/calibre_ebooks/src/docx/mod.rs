@@ -1,4 +1,7 @@
 pub mod container;
 pub mod error;
 pub mod names;
+pub mod numbering;
+pub mod styles;
 pub mod to_html;
+pub mod writer;
@@ -0,0 +1,130 @@
+//! List definitions from `numbering.xml`: the abstract numberings with the
+//! format of each level, and the numbering instances paragraphs refer to.
+
+use super::error::DocxError;
+use super::styles::{attr, child, child_val};
+use roxmltree::Document;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Level {
+    pub start: i64,
+    /// The `w:numFmt` value: decimal, bullet, lowerRoman, ...
+    pub format: String,
+    /// The `w:lvlText` pattern, the bullet character for bullets.
+    pub text: String,
+}
+
+impl Level {
+    pub fn is_ordered(&self) -> bool {
+        !matches!(self.format.as_str(), "bullet" | "none")
+    }
+
+    /// The CSS `list-style-type` closest to this level.
+    pub fn list_style(&self) -> &'static str {
+        match self.format.as_str() {
+            "decimal" => "decimal",
+            "decimalZero" => "decimal-leading-zero",
+            "lowerLetter" => "lower-alpha",
+            "upperLetter" => "upper-alpha",
+            "lowerRoman" => "lower-roman",
+            "upperRoman" => "upper-roman",
+            "none" => "none",
+            "bullet" => match self.text.trim() {
+                "o" | "\u{25e6}" | "\u{25cb}" => "circle",
+                "\u{25aa}" | "\u{25a0}" | "\u{f0a7}" | "\u{a7}" => "square",
+                _ => "disc",
+            },
+            _ => "decimal",
+        }
+    }
+}
+
+struct Num {
+    abstract_id: String,
+    /// `w:lvlOverride/w:startOverride` by level.
+    start_overrides: HashMap<u8, i64>,
+}
+
+#[derive(Default)]
+pub struct Numbering {
+    abstracts: HashMap<String, HashMap<u8, Level>>,
+    nums: HashMap<String, Num>,
+}
+
+impl Numbering {
+    pub fn parse(xml: &str) -> Result<Self, DocxError> {
+        let doc = Document::parse(xml)?;
+        let mut numbering = Numbering::default();
+        for node in doc.root_element().children().filter(|c| c.is_element()) {
+            match node.tag_name().name() {
+                "abstractNum" => {
+                    let Some(id) = attr(node, "abstractNumId") else {
+                        continue;
+                    };
+                    let mut levels = HashMap::new();
+                    for lvl in node
+                        .children()
+                        .filter(|c| c.is_element() && c.tag_name().name() == "lvl")
+                    {
+                        let Some(ilvl) = attr(lvl, "ilvl").and_then(|v| v.parse::<u8>().ok())
+                        else {
+                            continue;
+                        };
+                        levels.insert(
+                            ilvl,
+                            Level {
+                                start: child_val(lvl, "start")
+                                    .and_then(|v| v.parse().ok())
+                                    .unwrap_or(1),
+                                format: child_val(lvl, "numFmt").unwrap_or("decimal").to_string(),
+                                text: child_val(lvl, "lvlText").unwrap_or_default().to_string(),
+                            },
+                        );
+                    }
+                    numbering.abstracts.insert(id.to_string(), levels);
+                }
+                "num" => {
+                    let (Some(id), Some(abstract_id)) =
+                        (attr(node, "numId"), child_val(node, "abstractNumId"))
+                    else {
+                        continue;
+                    };
+                    let mut start_overrides = HashMap::new();
+                    for o in node
+                        .children()
+                        .filter(|c| c.is_element() && c.tag_name().name() == "lvlOverride")
+                    {
+                        let level = attr(o, "ilvl").and_then(|v| v.parse::<u8>().ok());
+                        let start = child(o, "startOverride")
+                            .and_then(|s| attr(s, "val"))
+                            .and_then(|v| v.parse::<i64>().ok());
+                        if let (Some(level), Some(start)) = (level, start) {
+                            start_overrides.insert(level, start);
+                        }
+                    }
+                    numbering.nums.insert(
+                        id.to_string(),
+                        Num {
+                            abstract_id: abstract_id.to_string(),
+                            start_overrides,
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+        Ok(numbering)
+    }
+
+    /// The format of `level` in the numbering `num_id`. Paragraphs whose
+    /// numbering is not defined are not list items.
+    pub fn level(&self, num_id: &str, level: u8) -> Option<Level> {
+        let num = self.nums.get(num_id)?;
+        let mut lvl = self.abstracts.get(&num.abstract_id)?.get(&level)?.clone();
+        if let Some(start) = num.start_overrides.get(&level) {
+            lvl.start = *start;
+        }
+        Some(lvl)
+    }
+}
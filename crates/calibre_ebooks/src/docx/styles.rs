@@ -0,0 +1,559 @@
+//! Paragraph, character and table styles from `styles.xml`, with their
+//! `basedOn` inheritance, and the conversion of resolved formatting to CSS.
+
+use super::error::DocxError;
+use roxmltree::{Document, Node};
+use std::collections::{HashMap, HashSet};
+
+/// An attribute of a WordprocessingML element by local name, whatever
+/// namespace (transitional or strict) the document uses.
+pub(crate) fn attr<'a>(node: Node<'a, '_>, local: &str) -> Option<&'a str> {
+    node.attributes()
+        .find(|a| a.name() == local)
+        .map(|a| a.value())
+}
+
+/// The first child element with the local name `local`.
+pub(crate) fn child<'a, 'input>(node: Node<'a, 'input>, local: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|c| c.is_element() && c.tag_name().name() == local)
+}
+
+/// The `w:val` of the child `local`.
+pub(crate) fn child_val<'a>(node: Node<'a, '_>, local: &str) -> Option<&'a str> {
+    child(node, local).and_then(|c| attr(c, "val"))
+}
+
+/// A toggle property such as `<w:b/>` or `<w:b w:val="false"/>`.
+fn toggle(node: Node) -> bool {
+    !matches!(attr(node, "val"), Some("0" | "false" | "off" | "none"))
+}
+
+fn twips(value: Option<&str>) -> Option<f32> {
+    value.and_then(|v| v.trim().parse::<f32>().ok())
+}
+
+/// Paragraph properties, every field set only when specified.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParaProps {
+    pub align: Option<&'static str>,
+    /// Indents in twips; a negative first line indent is a hanging one.
+    pub left: Option<f32>,
+    pub right: Option<f32>,
+    pub first_line: Option<f32>,
+    pub space_before: Option<f32>,
+    pub space_after: Option<f32>,
+    /// CSS `line-height`.
+    pub line_height: Option<String>,
+    pub page_break_before: Option<bool>,
+    pub background: Option<String>,
+    /// Zero based; 9 is body text.
+    pub outline_level: Option<u8>,
+    /// `(numId, ilvl)`; a numId of 0 removes inherited numbering.
+    pub num: Option<(String, u8)>,
+}
+
+impl ParaProps {
+    pub fn parse(ppr: Node) -> Self {
+        let mut p = ParaProps::default();
+        p.update(ppr);
+        p
+    }
+
+    fn update(&mut self, ppr: Node) {
+        for c in ppr.children().filter(|c| c.is_element()) {
+            match c.tag_name().name() {
+                "jc" => {
+                    self.align = match attr(c, "val") {
+                        Some("left" | "start") => Some("left"),
+                        Some("right" | "end") => Some("right"),
+                        Some("center") => Some("center"),
+                        Some("both" | "distribute") => Some("justify"),
+                        _ => self.align,
+                    }
+                }
+                "ind" => {
+                    if let Some(v) = twips(attr(c, "left").or_else(|| attr(c, "start"))) {
+                        self.left = Some(v);
+                    }
+                    if let Some(v) = twips(attr(c, "right").or_else(|| attr(c, "end"))) {
+                        self.right = Some(v);
+                    }
+                    if let Some(v) = twips(attr(c, "hanging")) {
+                        self.first_line = Some(-v);
+                    } else if let Some(v) = twips(attr(c, "firstLine")) {
+                        self.first_line = Some(v);
+                    }
+                }
+                "spacing" => {
+                    if let Some(v) = twips(attr(c, "before")) {
+                        self.space_before = Some(v);
+                    }
+                    if let Some(v) = twips(attr(c, "after")) {
+                        self.space_after = Some(v);
+                    }
+                    if let Some(line) = twips(attr(c, "line")) {
+                        self.line_height = match attr(c, "lineRule") {
+                            Some("exact" | "atLeast") => Some(pt(line / 20.0)),
+                            _ => Some(format_number(line / 240.0)),
+                        };
+                    }
+                }
+                "pageBreakBefore" => self.page_break_before = Some(toggle(c)),
+                "shd" => {
+                    if let Some(fill) = attr(c, "fill").and_then(color) {
+                        self.background = Some(fill);
+                    }
+                }
+                "outlineLvl" => {
+                    self.outline_level = attr(c, "val").and_then(|v| v.parse().ok());
+                }
+                "numPr" => {
+                    let id = child_val(c, "numId").map(str::to_string);
+                    let level = child_val(c, "ilvl")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(0);
+                    match (id, &mut self.num) {
+                        (Some(id), _) => self.num = Some((id, level)),
+                        // A level alone keeps the inherited list
+                        (None, Some(num)) => num.1 = level,
+                        (None, None) => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Apply the properties set in `other` over these.
+    pub fn merge(&mut self, other: &ParaProps) {
+        macro_rules! take {
+            ($($f:ident),*) => {$(
+                if other.$f.is_some() {
+                    self.$f = other.$f.clone();
+                }
+            )*};
+        }
+        take!(
+            align,
+            left,
+            right,
+            first_line,
+            space_before,
+            space_after,
+            line_height,
+            page_break_before,
+            background,
+            outline_level,
+            num
+        );
+    }
+
+    /// The declarations differing from `base`.
+    pub fn css(&self, base: &ParaProps) -> Vec<(&'static str, String)> {
+        let mut css = Vec::new();
+        let length = |v: Option<f32>| pt(v.unwrap_or(0.0) / 20.0);
+        if self.align != base.align {
+            css.push(("text-align", self.align.unwrap_or("left").to_string()));
+        }
+        if self.space_before.unwrap_or(0.0) != base.space_before.unwrap_or(0.0) {
+            css.push(("margin-top", length(self.space_before)));
+        }
+        if self.space_after.unwrap_or(0.0) != base.space_after.unwrap_or(0.0) {
+            css.push(("margin-bottom", length(self.space_after)));
+        }
+        if self.left.unwrap_or(0.0) != base.left.unwrap_or(0.0) {
+            css.push(("margin-left", length(self.left)));
+        }
+        if self.right.unwrap_or(0.0) != base.right.unwrap_or(0.0) {
+            css.push(("margin-right", length(self.right)));
+        }
+        if self.first_line.unwrap_or(0.0) != base.first_line.unwrap_or(0.0) {
+            css.push(("text-indent", length(self.first_line)));
+        }
+        if self.line_height != base.line_height {
+            css.push((
+                "line-height",
+                self.line_height.clone().unwrap_or_else(|| "normal".into()),
+            ));
+        }
+        if self.background != base.background {
+            if let Some(bg) = &self.background {
+                css.push(("background-color", bg.clone()));
+            }
+        }
+        if self.page_break_before == Some(true) && base.page_break_before != Some(true) {
+            css.push(("page-break-before", "always".into()));
+        }
+        css
+    }
+}
+
+/// Character properties, every field set only when specified.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunProps {
+    pub bold: Option<bool>,
+    pub italic: Option<bool>,
+    pub underline: Option<bool>,
+    pub strike: Option<bool>,
+    pub caps: Option<bool>,
+    pub small_caps: Option<bool>,
+    pub hidden: Option<bool>,
+    pub color: Option<String>,
+    /// In points.
+    pub size: Option<f32>,
+    pub font: Option<String>,
+    pub highlight: Option<String>,
+    /// `superscript` or `subscript`; `baseline` clears an inherited one.
+    pub vert_align: Option<String>,
+}
+
+impl RunProps {
+    pub fn parse(rpr: Node) -> Self {
+        let mut r = RunProps::default();
+        for c in rpr.children().filter(|c| c.is_element()) {
+            match c.tag_name().name() {
+                "b" => r.bold = Some(toggle(c)),
+                "i" => r.italic = Some(toggle(c)),
+                "strike" | "dstrike" => r.strike = Some(toggle(c)),
+                "caps" => r.caps = Some(toggle(c)),
+                "smallCaps" => r.small_caps = Some(toggle(c)),
+                "vanish" => r.hidden = Some(toggle(c)),
+                "u" => r.underline = Some(toggle(c)),
+                "color" => r.color = attr(c, "val").and_then(color),
+                "sz" => r.size = twips(attr(c, "val")).map(|v| v / 2.0),
+                "rFonts" => {
+                    if let Some(font) = attr(c, "ascii").or_else(|| attr(c, "hAnsi")) {
+                        r.font = Some(font.to_string());
+                    }
+                }
+                "highlight" => r.highlight = attr(c, "val").and_then(highlight),
+                "shd" if r.highlight.is_none() => r.highlight = attr(c, "fill").and_then(color),
+                "vertAlign" => r.vert_align = attr(c, "val").map(str::to_string),
+                _ => {}
+            }
+        }
+        r
+    }
+
+    pub fn merge(&mut self, other: &RunProps) {
+        macro_rules! take {
+            ($($f:ident),*) => {$(
+                if other.$f.is_some() {
+                    self.$f = other.$f.clone();
+                }
+            )*};
+        }
+        take!(
+            bold, italic, underline, strike, caps, small_caps, hidden, color, size, font,
+            highlight, vert_align
+        );
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden == Some(true)
+    }
+
+    /// `sup` or `sub` for vertically aligned text.
+    pub fn vertical_tag(&self) -> Option<&'static str> {
+        match self.vert_align.as_deref() {
+            Some("superscript") => Some("sup"),
+            Some("subscript") => Some("sub"),
+            _ => None,
+        }
+    }
+
+    /// The declarations differing from `base`. With `explicit` a specified
+    /// weight and size are always given, for elements the browser styles
+    /// itself.
+    pub fn css(&self, base: &RunProps, explicit: bool) -> Vec<(&'static str, String)> {
+        let mut css = Vec::new();
+        let on = |v: Option<bool>| v.unwrap_or(false);
+        if (explicit && self.bold.is_some()) || on(self.bold) != on(base.bold) {
+            let weight = if on(self.bold) { "bold" } else { "normal" };
+            css.push(("font-weight", weight.to_string()));
+        }
+        if on(self.italic) != on(base.italic) {
+            let style = if on(self.italic) { "italic" } else { "normal" };
+            css.push(("font-style", style.to_string()));
+        }
+        if on(self.underline) != on(base.underline) || on(self.strike) != on(base.strike) {
+            let decoration = match (on(self.underline), on(self.strike)) {
+                (true, true) => "underline line-through",
+                (true, false) => "underline",
+                (false, true) => "line-through",
+                (false, false) => "none",
+            };
+            css.push(("text-decoration", decoration.to_string()));
+        }
+        if on(self.small_caps) != on(base.small_caps) {
+            let variant = if on(self.small_caps) {
+                "small-caps"
+            } else {
+                "normal"
+            };
+            css.push(("font-variant", variant.to_string()));
+        }
+        if on(self.caps) != on(base.caps) {
+            let transform = if on(self.caps) { "uppercase" } else { "none" };
+            css.push(("text-transform", transform.to_string()));
+        }
+        if self.color != base.color {
+            if let Some(color) = &self.color {
+                css.push(("color", color.clone()));
+            }
+        }
+        if self.highlight != base.highlight {
+            if let Some(bg) = &self.highlight {
+                css.push(("background-color", bg.clone()));
+            }
+        }
+        if self.size != base.size || (explicit && self.size.is_some()) {
+            if let Some(size) = self.size {
+                css.push(("font-size", pt(size)));
+            }
+        }
+        if self.font != base.font {
+            if let Some(font) = &self.font {
+                css.push(("font-family", font_family(font)));
+            }
+        }
+        css
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StyleKind {
+    Paragraph,
+    Character,
+    Table,
+    Numbering,
+}
+
+#[derive(Debug, Clone)]
+pub struct Style {
+    pub id: String,
+    pub name: String,
+    pub kind: StyleKind,
+    pub based_on: Option<String>,
+    pub para: ParaProps,
+    pub run: RunProps,
+    /// Table styles only: whether the table has visible borders.
+    pub borders: Option<bool>,
+}
+
+#[derive(Debug, Default)]
+pub struct Styles {
+    styles: HashMap<String, Style>,
+    default_para: Option<String>,
+    default_table: Option<String>,
+    defaults_para: ParaProps,
+    defaults_run: RunProps,
+}
+
+impl Styles {
+    pub fn parse(xml: &str) -> Result<Self, DocxError> {
+        let doc = Document::parse(xml)?;
+        let mut styles = Styles::default();
+        for node in doc.root_element().children().filter(|c| c.is_element()) {
+            match node.tag_name().name() {
+                "docDefaults" => {
+                    for d in node.descendants() {
+                        match d.tag_name().name() {
+                            "pPr" => styles.defaults_para = ParaProps::parse(d),
+                            "rPr" => styles.defaults_run = RunProps::parse(d),
+                            _ => {}
+                        }
+                    }
+                }
+                "style" => {
+                    let Some(id) = attr(node, "styleId") else {
+                        continue;
+                    };
+                    let kind = match attr(node, "type") {
+                        Some("character") => StyleKind::Character,
+                        Some("table") => StyleKind::Table,
+                        Some("numbering") => StyleKind::Numbering,
+                        _ => StyleKind::Paragraph,
+                    };
+                    let default = attr(node, "default").map(|v| v == "1" || v == "true");
+                    if default == Some(true) {
+                        match kind {
+                            StyleKind::Paragraph => styles.default_para = Some(id.to_string()),
+                            StyleKind::Table => styles.default_table = Some(id.to_string()),
+                            _ => {}
+                        }
+                    }
+                    let style = Style {
+                        id: id.to_string(),
+                        name: child_val(node, "name").unwrap_or(id).to_string(),
+                        kind,
+                        based_on: child_val(node, "basedOn").map(str::to_string),
+                        para: child(node, "pPr").map(ParaProps::parse).unwrap_or_default(),
+                        run: child(node, "rPr").map(RunProps::parse).unwrap_or_default(),
+                        borders: child(node, "tblPr")
+                            .and_then(|t| child(t, "tblBorders"))
+                            .map(has_borders),
+                    };
+                    styles.styles.insert(id.to_string(), style);
+                }
+                _ => {}
+            }
+        }
+        Ok(styles)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Style> {
+        self.styles.get(id)
+    }
+
+    /// `id` and the styles it is based on, most basic first.
+    fn chain(&self, id: &str) -> Vec<&Style> {
+        let mut chain = Vec::new();
+        let mut seen = HashSet::new();
+        let mut next = Some(id);
+        while let Some(id) = next {
+            if !seen.insert(id) {
+                break;
+            }
+            let Some(style) = self.styles.get(id) else {
+                break;
+            };
+            chain.push(style);
+            next = style.based_on.as_deref();
+        }
+        chain.reverse();
+        chain
+    }
+
+    /// The formatting of a paragraph in the style `id` (the default
+    /// paragraph style for None), within a table in the style `table`.
+    pub fn paragraph(&self, id: Option<&str>, table: Option<&str>) -> (ParaProps, RunProps) {
+        let mut para = self.defaults_para.clone();
+        let mut run = self.defaults_run.clone();
+        if let Some(table) = table {
+            for style in self.chain(table) {
+                para.merge(&style.para);
+                run.merge(&style.run);
+            }
+        }
+        let id = id
+            .filter(|id| self.styles.contains_key(*id))
+            .or(self.default_para.as_deref());
+        if let Some(id) = id {
+            for style in self.chain(id) {
+                para.merge(&style.para);
+                run.merge(&style.run);
+            }
+        }
+        (para, run)
+    }
+
+    /// The formatting the character style `id` adds.
+    pub fn character(&self, id: &str) -> RunProps {
+        let mut run = RunProps::default();
+        for style in self.chain(id) {
+            if style.kind == StyleKind::Character {
+                run.merge(&style.run);
+            }
+        }
+        run
+    }
+
+    /// The table style in effect for `id`, the default one for None.
+    pub fn table_style<'a>(&'a self, id: Option<&'a str>) -> Option<&'a str> {
+        id.filter(|id| self.styles.contains_key(*id))
+            .or(self.default_table.as_deref())
+    }
+
+    /// Whether tables in the style `id` have visible borders.
+    pub fn table_borders(&self, id: &str) -> bool {
+        self.chain(id)
+            .iter()
+            .rev()
+            .find_map(|s| s.borders)
+            .unwrap_or(false)
+    }
+
+    /// The heading level (1-6) of paragraphs in the style `id`, from its
+    /// outline level or failing that from the built-in heading names.
+    pub fn heading_level(&self, id: &str) -> Option<u8> {
+        let outline = self
+            .chain(id)
+            .iter()
+            .rev()
+            .find_map(|s| s.para.outline_level);
+        if let Some(level) = outline {
+            return (level < 6).then_some(level + 1);
+        }
+        let name = self
+            .styles
+            .get(id)
+            .map(|s| s.name.to_ascii_lowercase())
+            .unwrap_or_else(|| id.to_ascii_lowercase());
+        let digits = name
+            .strip_prefix("heading")
+            .map(|rest| rest.trim())
+            .filter(|rest| !rest.is_empty())?;
+        digits.parse::<u8>().ok().filter(|l| (1..=6).contains(l))
+    }
+}
+
+/// Whether a `tblBorders` or `tcBorders` element has any visible border.
+pub fn has_borders(borders: Node) -> bool {
+    borders
+        .children()
+        .filter(|c| c.is_element())
+        .any(|c| !matches!(attr(c, "val"), Some("nil" | "none") | None))
+}
+
+/// A CSS colour for a WordprocessingML hex colour, None for `auto`.
+pub fn color(value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.len() == 6 && value.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(format!("#{}", value.to_ascii_uppercase()))
+    } else {
+        None
+    }
+}
+
+/// A CSS colour for a `w:highlight` name.
+fn highlight(value: &str) -> Option<String> {
+    let css = match value {
+        "none" => return None,
+        "darkYellow" => "olive",
+        "darkGray" => "darkgray",
+        "lightGray" => "lightgray",
+        other => return Some(other.to_ascii_lowercase()),
+    };
+    Some(css.to_string())
+}
+
+fn font_family(font: &str) -> String {
+    let generic = {
+        let lower = font.to_ascii_lowercase();
+        if lower.contains("courier") || lower.contains("mono") || lower.contains("consolas") {
+            "monospace"
+        } else if lower.contains("arial")
+            || lower.contains("helvetica")
+            || lower.contains("calibri")
+            || lower.contains("verdana")
+            || lower.contains("sans")
+        {
+            "sans-serif"
+        } else {
+            "serif"
+        }
+    };
+    format!("\"{}\", {}", font.replace('"', ""), generic)
+}
+
+/// A length in points, without needless decimals.
+pub fn pt(points: f32) -> String {
+    format!("{}pt", format_number(points))
+}
+
+fn format_number(n: f32) -> String {
+    let s = format!("{:.2}", n);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
@@ -1,170 +1,1360 @@
-use super::container::DOCX;
+//! Converts the main document of a DOCX package to XHTML. Formatting is
+//! resolved through the style inheritance of `styles.xml` and emitted as
+//! CSS classes, numbered paragraphs become lists, and footnotes and endnotes
+//! are collected after the text as EPUB 3 notes.
+
+use super::container::{Relationship, DOCX};
 use super::error::DocxError;
-use super::names::DOCXNamespaces;
+use super::numbering::{Level, Numbering};
+use super::styles::{attr, child, child_val, color, has_borders, pt, ParaProps, RunProps, Styles};
+use image::ImageFormat;
 use roxmltree::{Document, Node};
-use std::collections::HashMap;
-use std::io::{Read, Seek};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Cursor, Read, Seek};
 use std::path::Path;
 
+/// The name the generated HTML links its stylesheet as.
+pub const STYLESHEET: &str = "docx.css";
+/// Folder of the extracted images, in the destination folder.
+const IMAGES_DIR: &str = "images";
+const EMU_PER_PT: f32 = 12700.0;
+
+/// A heading of the body text, for the table of contents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heading {
+    /// 1 to 6.
+    pub level: u8,
+    pub title: String,
+    pub id: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ConvertedDocument {
+    pub html: String,
+    pub css: String,
+    pub headings: Vec<Heading>,
+    /// The extracted images, relative to the destination folder.
+    pub images: Vec<String>,
+}
+
 pub struct DOCXToHTML;
 
 impl DOCXToHTML {
+    /// Convert the document, writing its images under `dest_dir`. The HTML
+    /// expects its CSS next to it as `STYLESHEET`.
     pub fn convert<R: Read + Seek>(
         docx: &mut DOCX<R>,
         dest_dir: &Path,
-    ) -> Result<String, DocxError> {
+    ) -> Result<ConvertedDocument, DocxError> {
         let doc_name = docx.document_name()?;
+        let doc_rels = docx.part_relationships(&doc_name);
+        let part = |suffix: &str| {
+            doc_rels
+                .values()
+                .find(|r| !r.external && r.kind.ends_with(suffix))
+                .map(|r| r.target.clone())
+        };
+        let styles_name = part("/styles");
+        let numbering_name = part("/numbering");
+        let footnotes_name = part("/footnotes");
+        let endnotes_name = part("/endnotes");
+
+        // Damaged auxiliary parts only lose their formatting
+        let styles = read_part(docx, styles_name.as_deref())
+            .and_then(|xml| Styles::parse(&xml).ok())
+            .unwrap_or_default();
+        let numbering = read_part(docx, numbering_name.as_deref())
+            .and_then(|xml| Numbering::parse(&xml).ok())
+            .unwrap_or_default();
+        let footnotes_xml = read_part(docx, footnotes_name.as_deref()).unwrap_or_default();
+        let endnotes_xml = read_part(docx, endnotes_name.as_deref()).unwrap_or_default();
+        let footnotes_doc = Document::parse(&footnotes_xml).ok();
+        let endnotes_doc = Document::parse(&endnotes_xml).ok();
+        let note_rels = [
+            footnotes_name
+                .map(|n| docx.part_relationships(&n))
+                .unwrap_or_default(),
+            endnotes_name
+                .map(|n| docx.part_relationships(&n))
+                .unwrap_or_default(),
+        ];
+
+        let content = docx.read_file(&doc_name)?;
+        let text = String::from_utf8(content).map_err(|e| DocxError::InvalidDocx(e.to_string()))?;
+        let doc = Document::parse(&text)?;
+        let body = doc
+            .descendants()
+            .find(|n| n.is_element() && n.tag_name().name() == "body")
+            .ok_or_else(|| DocxError::InvalidDocx("The document has no body".into()))?;
+
+        let title = docx
+            .get_metadata()
+            .ok()
+            .and_then(|m| m.first("title").map(str::to_string))
+            .unwrap_or_else(|| "Unknown".to_string());
+
+        let mut converter = Converter::new(docx, dest_dir, styles, numbering, doc_rels);
+        converter.notes[0] = notes(footnotes_doc.as_ref());
+        converter.notes[1] = notes(endnotes_doc.as_ref());
+        converter.note_rels = note_rels;
+        converter.blocks(body);
+        converter.write_notes();
+
+        let html = format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\">\
+             <head><meta http-equiv=\"Content-Type\" content=\"text/html; charset=utf-8\"/>\
+             <title>{}</title><link rel=\"stylesheet\" type=\"text/css\" href=\"{}\"/></head>\
+             <body>{}</body></html>",
+            html_escape::encode_text(&title),
+            STYLESHEET,
+            converter.out
+        );
+        Ok(ConvertedDocument {
+            html,
+            css: converter.css(),
+            headings: converter.headings,
+            images: converter.image_hrefs,
+        })
+    }
+}
 
-        // 1. Read Document Relationships
-        // Construct path: word/_rels/document.xml.rels
-        // Simple logic: assume doc_name has a parent dir
-        let path_obj = Path::new(&doc_name);
-        let file_name = path_obj.file_name().unwrap_or_default().to_string_lossy();
-        let parent = path_obj.parent().unwrap_or(Path::new(""));
-        let rels_path = parent.join("_rels").join(format!("{}.rels", file_name));
-        let rels_path_str = rels_path.to_string_lossy().replace("\\", "/");
+fn read_part<R: Read + Seek>(docx: &mut DOCX<R>, name: Option<&str>) -> Option<String> {
+    let data = docx.read_file(name?).ok()?;
+    Some(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// The notes of a footnotes or endnotes part by id, without the separators.
+fn notes<'a, 'input>(doc: Option<&'a Document<'input>>) -> HashMap<String, Node<'a, 'input>> {
+    let Some(doc) = doc else {
+        return HashMap::new();
+    };
+    doc.root_element()
+        .children()
+        .filter(|n| n.is_element() && matches!(attr(*n, "type"), None | Some("normal")))
+        .filter_map(|n| Some((attr(n, "id")?.to_string(), n)))
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum NoteKind {
+    Foot,
+    End,
+}
+
+impl NoteKind {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn prefix(self) -> &'static str {
+        match self {
+            NoteKind::Foot => "fn",
+            NoteKind::End => "en",
+        }
+    }
+}
+
+/// A complex field, between its `begin` and `end` characters.
+struct Field {
+    instr: String,
+    /// Past the instruction, into the displayed result.
+    separated: bool,
+    /// Whether the result is wrapped in a link.
+    link: bool,
+}
+
+struct OpenList {
+    num: String,
+    level: u8,
+    tag: &'static str,
+}
 
-        let mut doc_rels = HashMap::new();
-        if let Ok(content) = docx.read_file(&rels_path_str) {
-            let text = String::from_utf8(content).unwrap_or_default();
-            if let Ok(doc) = Document::parse(&text) {
-                for node in doc.descendants() {
-                    if node.has_tag_name("Relationship") {
-                        let id = node.attribute("Id").unwrap_or_default().to_string();
-                        let target = node.attribute("Target").unwrap_or_default().to_string();
-                        doc_rels.insert(id, target);
+/// A table cell placed on the grid of table columns.
+#[derive(Clone, Copy)]
+struct GridCell<'a, 'input> {
+    node: Node<'a, 'input>,
+    col: usize,
+    span: usize,
+    /// Some(true) starts a vertically merged cell, Some(false) continues one
+    vmerge: Option<bool>,
+}
+
+/// A converted paragraph, before it is placed as a heading, list item or
+/// plain paragraph.
+struct Paragraph {
+    heading: Option<u8>,
+    num: Option<(String, u8, Level)>,
+    props: ParaProps,
+    run: RunProps,
+    inner: String,
+    text: String,
+    has_content: bool,
+    break_before: bool,
+}
+
+/// CSS classes, shared by every element with the same declarations.
+#[derive(Default)]
+struct Classes {
+    names: HashMap<String, String>,
+    rules: Vec<(String, String)>,
+    counts: HashMap<&'static str, usize>,
+}
+
+impl Classes {
+    fn get(&mut self, prefix: &'static str, decls: &[(&'static str, String)]) -> Option<String> {
+        if decls.is_empty() {
+            return None;
+        }
+        let css = decls
+            .iter()
+            .map(|(p, v)| format!("{}: {}", p, v))
+            .collect::<Vec<_>>()
+            .join("; ");
+        let key = format!("{}|{}", prefix, css);
+        if let Some(name) = self.names.get(&key) {
+            return Some(name.clone());
+        }
+        let count = self.counts.entry(prefix).or_insert(0);
+        *count += 1;
+        let name = format!("{}{}", prefix, count);
+        self.names.insert(key, name.clone());
+        self.rules.push((name.clone(), css));
+        Some(name)
+    }
+}
+
+struct Converter<'a, 'input, 'd, R: Read + Seek> {
+    docx: &'d mut DOCX<R>,
+    dest_dir: &'d Path,
+    styles: Styles,
+    numbering: Numbering,
+    /// Relationships of the part being converted.
+    rels: HashMap<String, Relationship>,
+    /// Footnotes and endnotes by id, and the relationships of their parts.
+    notes: [HashMap<String, Node<'a, 'input>>; 2],
+    note_rels: [HashMap<String, Relationship>; 2],
+    /// Referenced notes with their numbers, in order.
+    note_refs: Vec<(NoteKind, Node<'a, 'input>, usize)>,
+    note_counts: [usize; 2],
+    current_note: Option<(NoteKind, usize)>,
+
+    out: String,
+    classes: Classes,
+    base_para: ParaProps,
+    base_run: RunProps,
+    /// Table style of the cell being converted.
+    table_style: Option<String>,
+    /// Inside tables, notes or text boxes, where headings are not part of
+    /// the table of contents.
+    depth: usize,
+    headings: Vec<Heading>,
+    ids: HashSet<String>,
+    pending_break: bool,
+    pending_anchors: Vec<String>,
+    counters: HashMap<(String, u8), i64>,
+    boxes: Vec<Node<'a, 'input>>,
+    images: HashMap<String, Option<String>>,
+    image_hrefs: Vec<String>,
+    used_tab: bool,
+    used_textbox: bool,
+
+    // State of the paragraph being converted
+    para: String,
+    para_text: String,
+    para_run: RunProps,
+    has_content: bool,
+    break_before: bool,
+    last_space: bool,
+    /// Class and `sup`/`sub` wrapper of the open formatting span.
+    span: Option<(Option<String>, Option<&'static str>)>,
+    in_link: bool,
+    fields: Vec<Field>,
+}
+
+impl<'a, 'input, 'd, R: Read + Seek> Converter<'a, 'input, 'd, R> {
+    fn new(
+        docx: &'d mut DOCX<R>,
+        dest_dir: &'d Path,
+        styles: Styles,
+        numbering: Numbering,
+        rels: HashMap<String, Relationship>,
+    ) -> Self {
+        let (base_para, base_run) = styles.paragraph(None, None);
+        Converter {
+            docx,
+            dest_dir,
+            styles,
+            numbering,
+            rels,
+            notes: [HashMap::new(), HashMap::new()],
+            note_rels: [HashMap::new(), HashMap::new()],
+            note_refs: Vec::new(),
+            note_counts: [0, 0],
+            current_note: None,
+            out: String::new(),
+            classes: Classes::default(),
+            base_para,
+            base_run,
+            table_style: None,
+            depth: 0,
+            headings: Vec::new(),
+            ids: HashSet::new(),
+            pending_break: false,
+            pending_anchors: Vec::new(),
+            counters: HashMap::new(),
+            boxes: Vec::new(),
+            images: HashMap::new(),
+            image_hrefs: Vec::new(),
+            used_tab: false,
+            used_textbox: false,
+            para: String::new(),
+            para_text: String::new(),
+            para_run: RunProps::default(),
+            has_content: false,
+            break_before: false,
+            last_space: false,
+            span: None,
+            in_link: false,
+            fields: Vec::new(),
+        }
+    }
+
+    /// Convert the paragraphs and tables in `parent`.
+    fn blocks(&mut self, parent: Node<'a, 'input>) {
+        let mut lists = Vec::new();
+        self.block_children(parent, &mut lists);
+        self.close_lists(&mut lists);
+    }
+
+    fn block_children(&mut self, parent: Node<'a, 'input>, lists: &mut Vec<OpenList>) {
+        for c in parent.children().filter(|c| c.is_element()) {
+            match c.tag_name().name() {
+                "p" => {
+                    let para = self.paragraph(c);
+                    self.write_paragraph(para, lists);
+                    self.flush_boxes();
+                }
+                "tbl" => {
+                    self.close_lists(lists);
+                    self.table(c);
+                }
+                "sdt" => {
+                    if let Some(content) = child(c, "sdtContent") {
+                        self.block_children(content, lists);
+                    }
+                }
+                "customXml" | "ins" | "moveTo" | "smartTag" => self.block_children(c, lists),
+                "AlternateContent" => {
+                    if let Some(choice) = child(c, "Choice") {
+                        self.block_children(choice, lists);
                     }
                 }
+                "bookmarkStart" => {
+                    if let Some(name) = attr(c, "name") {
+                        self.pending_anchors.push(name.to_string());
+                    }
+                }
+                _ => {}
             }
         }
+    }
 
-        // 2. Read Document Content
-        let content = docx.read_file(&doc_name)?;
-        let text = String::from_utf8(content).map_err(|e| DocxError::InvalidDocx(e.to_string()))?;
-        let doc = Document::parse(&text)?;
+    fn paragraph(&mut self, p: Node<'a, 'input>) -> Paragraph {
+        let ppr = child(p, "pPr");
+        let style_id = ppr.and_then(|n| child_val(n, "pStyle"));
+        let (mut props, run) = self.styles.paragraph(style_id, self.table_style.as_deref());
+        let direct = ppr.map(ParaProps::parse).unwrap_or_default();
+        props.merge(&direct);
 
-        // 3. Generate HTML
-        let mut html = String::from("<html><head><meta charset=\"utf-8\"/></head><body>");
+        let heading = if self.depth > 0 {
+            None
+        } else if let Some(level) = direct.outline_level {
+            (level < 6).then_some(level + 1)
+        } else {
+            style_id.and_then(|id| self.styles.heading_level(id))
+        };
+        let num = props
+            .num
+            .clone()
+            .filter(|(id, _)| id != "0")
+            .and_then(|(id, level)| {
+                let lvl = self.numbering.level(&id, level)?;
+                Some((id, level, lvl))
+            });
 
-        for node in doc.descendants() {
-            if node.tag_name().name() == "p" {
-                Self::process_paragraph(node, &mut html, &doc_rels, docx, dest_dir);
+        self.para.clear();
+        self.para_text.clear();
+        self.para_run = run.clone();
+        self.has_content = false;
+        self.break_before = false;
+        self.last_space = false;
+        self.span = None;
+        self.in_link = false;
+        for name in std::mem::take(&mut self.pending_anchors) {
+            self.anchor(&name);
+        }
+        self.inline(p);
+        self.close_span();
+        if self.in_link {
+            self.close_link();
+        }
+        // Links of fields spanning paragraphs are reopened as needed
+        for field in &mut self.fields {
+            field.link = false;
+        }
+
+        Paragraph {
+            heading,
+            num,
+            props,
+            run,
+            inner: std::mem::take(&mut self.para),
+            text: self
+                .para_text
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+            has_content: self.has_content,
+            break_before: self.break_before,
+        }
+    }
+
+    fn write_paragraph(&mut self, para: Paragraph, lists: &mut Vec<OpenList>) {
+        if !para.has_content && para.inner.is_empty() && para.break_before {
+            // Only a page break
+            self.pending_break = true;
+            return;
+        }
+        let heading = para.heading.filter(|_| !para.text.is_empty());
+        let mut decls = para.props.css(&self.base_para);
+        decls.extend(para.run.css(&self.base_run, heading.is_some()));
+        let page_break = std::mem::take(&mut self.pending_break) || para.break_before;
+        if page_break && !decls.iter().any(|(p, _)| *p == "page-break-before") {
+            decls.push(("page-break-before", "always".into()));
+        }
+        let inner = if para.has_content {
+            para.inner
+        } else {
+            format!("{}&#160;", para.inner)
+        };
+
+        if let Some(level) = heading {
+            self.close_lists(lists);
+            let class = self.classes.get("p", &decls);
+            let id = self.unique_id(&format!("heading_{}", self.headings.len() + 1));
+            self.out.push_str(&format!(
+                "<h{l} id=\"{}\"{}>{}</h{l}>",
+                id,
+                class_attr(&class),
+                inner,
+                l = level
+            ));
+            self.headings.push(Heading {
+                level,
+                title: para.text,
+                id,
+            });
+        } else if let Some((num, level, lvl)) = para.num {
+            // The list supplies the indentation
+            decls.retain(|(p, _)| !matches!(*p, "margin-left" | "text-indent"));
+            let class = self.classes.get("p", &decls);
+            self.list_item(num, level, lvl, lists);
+            self.out
+                .push_str(&format!("<li{}>{}", class_attr(&class), inner));
+        } else {
+            self.close_lists(lists);
+            let class = self.classes.get("p", &decls);
+            self.out
+                .push_str(&format!("<p{}>{}</p>", class_attr(&class), inner));
+        }
+    }
+
+    /// Open the list of a numbered paragraph, or close the previous item
+    /// of it, leaving the new item to be written.
+    fn list_item(&mut self, num: String, level: u8, lvl: Level, lists: &mut Vec<OpenList>) {
+        while let Some(top) = lists.last() {
+            if top.level > level || (top.level == level && top.num != num) {
+                let top = lists.pop().expect("checked above");
+                self.out.push_str(&format!("</li></{}>", top.tag));
+            } else {
+                break;
             }
         }
 
-        html.push_str("</body></html>");
-        Ok(html)
+        // Numbering continues across interruptions; deeper levels restart
+        let key = (num.clone(), level);
+        let value = self.counters.get(&key).map_or(lvl.start, |v| v + 1);
+        self.counters.insert(key, value);
+        self.counters.retain(|(n, l), _| !(n == &num && *l > level));
+
+        if lists.last().is_some_and(|t| t.level == level) {
+            self.out.push_str("</li>");
+            return;
+        }
+        let tag = if lvl.is_ordered() { "ol" } else { "ul" };
+        let class = self
+            .classes
+            .get("l", &[("list-style-type", lvl.list_style().to_string())]);
+        let start = if tag == "ol" && value != 1 {
+            format!(" start=\"{}\"", value)
+        } else {
+            String::new()
+        };
+        self.out
+            .push_str(&format!("<{}{}{}>", tag, class_attr(&class), start));
+        lists.push(OpenList { num, level, tag });
     }
 
-    fn process_paragraph<R: Read + Seek>(
-        node: Node,
-        html: &mut String,
-        rels: &HashMap<String, String>,
-        docx: &mut DOCX<R>,
-        dest_dir: &Path,
-    ) {
-        // Determine Tag (p, h1-h6) based on pPr/pStyle
-        let mut tag = "p";
-
-        for child in node.children() {
-            if child.tag_name().name() == "pPr" {
-                for p_prop in child.children() {
-                    if p_prop.tag_name().name() == "pStyle" {
-                        if let Some(val) = p_prop.attribute("val") {
-                            match val {
-                                "Heading1" => tag = "h1",
-                                "Heading2" => tag = "h2",
-                                "Heading3" => tag = "h3",
-                                "Heading4" => tag = "h4",
-                                "Heading5" => tag = "h5",
-                                "Heading6" => tag = "h6",
-                                _ => {}
-                            }
-                        }
-                    }
+    fn close_lists(&mut self, lists: &mut Vec<OpenList>) {
+        while let Some(list) = lists.pop() {
+            self.out.push_str(&format!("</li></{}>", list.tag));
+        }
+    }
+
+    fn table(&mut self, tbl: Node<'a, 'input>) {
+        let tbl_pr = child(tbl, "tblPr");
+        let style = self
+            .styles
+            .table_style(tbl_pr.and_then(|t| child_val(t, "tblStyle")))
+            .map(str::to_string);
+        let borders = tbl_pr
+            .and_then(|t| child(t, "tblBorders"))
+            .map(has_borders)
+            .or_else(|| style.as_deref().map(|s| self.styles.table_borders(s)))
+            .unwrap_or(false);
+
+        let mut decls = vec![("border-collapse", "collapse".to_string())];
+        if let Some(width) = tbl_pr.and_then(|t| child(t, "tblW")).and_then(width) {
+            decls.push(("width", width));
+        }
+        if tbl_pr.and_then(|t| child_val(t, "jc")) == Some("center") {
+            decls.push(("margin-left", "auto".into()));
+            decls.push(("margin-right", "auto".into()));
+        }
+        let class = self.classes.get("t", &decls);
+
+        // Place the cells on the grid to find the vertically merged ones
+        let rows: Vec<Node> = tbl
+            .children()
+            .filter(|r| r.is_element() && r.tag_name().name() == "tr")
+            .collect();
+        let mut grid: Vec<Vec<GridCell>> = Vec::new();
+        for row in &rows {
+            let mut col = child(*row, "trPr")
+                .and_then(|t| child_val(t, "gridBefore"))
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(0);
+            let mut cells = Vec::new();
+            for tc in row
+                .children()
+                .filter(|c| c.is_element() && c.tag_name().name() == "tc")
+            {
+                let tc_pr = child(tc, "tcPr");
+                let span = tc_pr
+                    .and_then(|t| child_val(t, "gridSpan"))
+                    .and_then(|v| v.parse::<usize>().ok())
+                    .unwrap_or(1)
+                    .max(1);
+                let vmerge = tc_pr
+                    .and_then(|t| child(t, "vMerge"))
+                    .map(|m| attr(m, "val") == Some("restart"));
+                cells.push(GridCell {
+                    node: tc,
+                    col,
+                    span,
+                    vmerge,
+                });
+                col += span;
+            }
+            grid.push(cells);
+        }
+
+        self.out.push_str(&format!("<table{}>", class_attr(&class)));
+        let header_rows = rows
+            .iter()
+            .take_while(|r| {
+                child(**r, "trPr")
+                    .and_then(|t| child(t, "tblHeader"))
+                    .is_some()
+            })
+            .count();
+        let saved_style = std::mem::replace(&mut self.table_style, style);
+        self.depth += 1;
+        for (r, cells) in grid.iter().enumerate() {
+            if r == 0 && header_rows > 0 {
+                self.out.push_str("<thead>");
+            } else if r == header_rows {
+                self.out.push_str("<tbody>");
+            }
+            self.out.push_str("<tr>");
+            for cell in cells {
+                if cell.vmerge == Some(false) {
+                    continue;
+                }
+                let rowspan = if cell.vmerge == Some(true) {
+                    1 + grid[r + 1..]
+                        .iter()
+                        .take_while(|cells| {
+                            cells
+                                .iter()
+                                .any(|c| c.col == cell.col && c.vmerge == Some(false))
+                        })
+                        .count()
+                } else {
+                    1
+                };
+                let class = self.cell_class(cell.node, borders);
+                let mut attrs = class_attr(&class);
+                if cell.span > 1 {
+                    attrs.push_str(&format!(" colspan=\"{}\"", cell.span));
                 }
+                if rowspan > 1 {
+                    attrs.push_str(&format!(" rowspan=\"{}\"", rowspan));
+                }
+                self.out.push_str(&format!("<td{}>", attrs));
+                self.blocks(cell.node);
+                self.out.push_str("</td>");
+            }
+            self.out.push_str("</tr>");
+            if r + 1 == header_rows {
+                self.out.push_str("</thead>");
             }
         }
+        if grid.len() > header_rows {
+            self.out.push_str("</tbody>");
+        }
+        self.out.push_str("</table>");
+        self.depth -= 1;
+        self.table_style = saved_style;
+    }
 
-        html.push('<');
-        html.push_str(tag);
-        html.push('>');
+    fn cell_class(&mut self, tc: Node, table_borders: bool) -> Option<String> {
+        let tc_pr = child(tc, "tcPr");
+        let borders = tc_pr
+            .and_then(|t| child(t, "tcBorders"))
+            .map(has_borders)
+            .unwrap_or(table_borders);
+        let mut decls = Vec::new();
+        if borders {
+            decls.push(("border", "0.5pt solid black".to_string()));
+        }
+        decls.push(("padding", "0 5.4pt".to_string()));
+        let align = match tc_pr.and_then(|t| child_val(t, "vAlign")) {
+            Some("center") => "middle",
+            Some("bottom") => "bottom",
+            _ => "top",
+        };
+        decls.push(("vertical-align", align.to_string()));
+        if let Some(fill) = tc_pr
+            .and_then(|t| child(t, "shd"))
+            .and_then(|s| attr(s, "fill"))
+            .and_then(color)
+        {
+            decls.push(("background-color", fill));
+        }
+        if let Some(width) = tc_pr.and_then(|t| child(t, "tcW")).and_then(width) {
+            decls.push(("width", width));
+        }
+        self.classes.get("c", &decls)
+    }
 
-        for child in node.children() {
-            if child.tag_name().name() == "r" {
-                Self::process_run(child, html, rels, docx, dest_dir);
-            } else if child.tag_name().name() == "hyperlink" {
-                // Handle hyperlink
-                let rid = child.attribute((DOCXNamespaces::R, "id"));
-                if let Some(rid) = rid {
-                    if let Some(target) = rels.get(rid) {
-                        html.push_str(&format!("<a href=\"{}\">", target));
-                        for sub in child.children() {
-                            if sub.tag_name().name() == "r" {
-                                Self::process_run(sub, html, rels, docx, dest_dir);
-                            }
-                        }
-                        html.push_str("</a>");
+    /// Convert the inline content of a paragraph, or of an element in it.
+    fn inline(&mut self, parent: Node<'a, 'input>) {
+        for c in parent.children().filter(|c| c.is_element()) {
+            match c.tag_name().name() {
+                "r" => self.run(c),
+                "hyperlink" => {
+                    let mut href = attr(c, "id")
+                        .and_then(|id| self.rels.get(id))
+                        .map(|r| r.target.clone());
+                    if let Some(anchor) = attr(c, "anchor") {
+                        href = Some(format!(
+                            "{}#{}",
+                            href.unwrap_or_default(),
+                            anchor_id(anchor)
+                        ));
+                    }
+                    let opened = href.is_some_and(|h| self.open_link(&h));
+                    self.inline(c);
+                    if opened {
+                        self.close_link();
+                    }
+                }
+                "fldSimple" => {
+                    let opened = attr(c, "instr")
+                        .and_then(field_href)
+                        .is_some_and(|h| self.open_link(&h));
+                    self.inline(c);
+                    if opened {
+                        self.close_link();
+                    }
+                }
+                "bookmarkStart" => {
+                    if let Some(name) = attr(c, "name") {
+                        self.anchor(name);
                     }
                 }
+                "ins" | "moveTo" | "smartTag" | "customXml" | "dir" | "bdo" => self.inline(c),
+                "sdt" => {
+                    if let Some(content) = child(c, "sdtContent") {
+                        self.inline(content);
+                    }
+                }
+                "AlternateContent" => {
+                    if let Some(choice) = child(c, "Choice") {
+                        self.inline(choice);
+                    }
+                }
+                "oMath" | "oMathPara" => {
+                    let text: String = c
+                        .descendants()
+                        .filter(|n| n.is_element() && n.tag_name().name() == "t")
+                        .filter_map(|n| n.text())
+                        .collect();
+                    let props = self.para_run.clone();
+                    self.text(&text, &props);
+                }
+                _ => {}
             }
         }
+    }
 
-        html.push_str(&format!("</{}>", tag));
+    fn run(&mut self, r: Node<'a, 'input>) {
+        let mut props = self.para_run.clone();
+        if let Some(rpr) = child(r, "rPr") {
+            if let Some(style) = child_val(rpr, "rStyle") {
+                props.merge(&self.styles.character(style));
+            }
+            props.merge(&RunProps::parse(rpr));
+        }
+        self.run_content(r, &props);
     }
 
-    fn process_run<R: Read + Seek>(
-        node: Node,
-        html: &mut String,
-        rels: &HashMap<String, String>,
-        docx: &mut DOCX<R>,
-        dest_dir: &Path,
-    ) {
-        for child in node.children() {
-            match child.tag_name().name() {
-                "t" => {
-                    if let Some(text) = child.text() {
-                        html.push_str(&html_escape::encode_text(text));
-                    }
-                }
-                "br" => html.push_str("<br/>"),
-                "drawing" => {
-                    // Extract image
-                    // This is complex in OOXML. drawing -> inline -> graphic -> graphicData -> pic -> blipFill -> blip -> embed
-                    // Or similar structure
-                    for desc in child.descendants() {
-                        if desc.tag_name().name() == "blip" {
-                            if let Some(rid) = desc.attribute((DOCXNamespaces::R, "embed")) {
-                                if let Some(target) = rels.get(rid) {
-                                    // target is relative to document.xml usually, e.g. "media/image1.jpeg"
-                                    // We need to resolve it relative to DOCX root (word/media/image1.jpeg)
-                                    // Assuming document is at word/document.xml
-                                    let image_path = Path::new("word")
-                                        .join(target)
-                                        .to_string_lossy()
-                                        .replace("\\", "/");
-
-                                    if let Ok(data) = docx.read_file(&image_path) {
-                                        // Write to dest_dir
-                                        let file_name =
-                                            Path::new(target).file_name().unwrap_or_default();
-                                        let dest_path = dest_dir.join(file_name);
-                                        if std::fs::write(&dest_path, data).is_ok() {
-                                            html.push_str(&format!(
-                                                "<img src=\"{}\" />",
-                                                file_name.to_string_lossy()
-                                            ));
-                                        }
-                                    }
-                                }
-                            }
+    fn run_content(&mut self, r: Node<'a, 'input>, props: &RunProps) {
+        for c in r.children().filter(|c| c.is_element()) {
+            let name = c.tag_name().name();
+            match name {
+                "fldChar" => {
+                    self.field_char(attr(c, "fldCharType").unwrap_or_default());
+                    continue;
+                }
+                "instrText" => {
+                    if let Some(field) = self.fields.last_mut() {
+                        field.instr.push_str(c.text().unwrap_or_default());
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            if props.is_hidden() || self.fields.iter().any(|f| !f.separated) {
+                continue;
+            }
+            match name {
+                "t" => self.text(c.text().unwrap_or_default(), props),
+                "tab" | "ptab" => {
+                    self.format(props);
+                    self.para.push_str("<span class=\"tab\">\t</span>");
+                    self.para_text.push(' ');
+                    self.used_tab = true;
+                    self.has_content = true;
+                    self.last_space = false;
+                }
+                "br" | "cr" => match attr(c, "type") {
+                    Some("page") => {
+                        if self.has_content {
+                            self.pending_break = true;
+                        } else {
+                            self.break_before = true;
                         }
                     }
+                    Some("column") => {}
+                    _ => {
+                        self.format(props);
+                        self.para.push_str("<br/>");
+                        self.para_text.push(' ');
+                        self.has_content = true;
+                        self.last_space = false;
+                    }
+                },
+                "noBreakHyphen" => self.text("\u{2011}", props),
+                "softHyphen" => self.text("\u{ad}", props),
+                "sym" => {
+                    // Symbol fonts put their glyphs in the private use area
+                    let c = attr(c, "char")
+                        .and_then(|v| u32::from_str_radix(v, 16).ok())
+                        .map(|v| {
+                            if (0xf020..0xf100).contains(&v) {
+                                v - 0xf000
+                            } else {
+                                v
+                            }
+                        })
+                        .and_then(char::from_u32);
+                    if let Some(c) = c {
+                        self.text(&c.to_string(), props);
+                    }
+                }
+                "drawing" => self.drawing(c),
+                "pict" | "object" => self.vml(c),
+                "AlternateContent" => {
+                    if let Some(choice) = child(c, "Choice") {
+                        self.run_content(choice, props);
+                    }
+                }
+                "footnoteReference" => self.note_ref(NoteKind::Foot, attr(c, "id")),
+                "endnoteReference" => self.note_ref(NoteKind::End, attr(c, "id")),
+                "footnoteRef" | "endnoteRef" => {
+                    if let Some((kind, n)) = self.current_note {
+                        self.close_span();
+                        self.para.push_str(&format!(
+                            "<a href=\"#{}ref{n}\">{n}</a>",
+                            kind.prefix(),
+                            n = n
+                        ));
+                        self.para_text.push_str(&n.to_string());
+                        self.has_content = true;
+                        self.last_space = false;
+                    }
                 }
                 _ => {}
             }
         }
     }
+
+    fn field_char(&mut self, kind: &str) {
+        match kind {
+            "begin" => self.fields.push(Field {
+                instr: String::new(),
+                separated: false,
+                link: false,
+            }),
+            "separate" => {
+                let href = match self.fields.last_mut() {
+                    Some(field) if !field.separated => {
+                        field.separated = true;
+                        field_href(&field.instr)
+                    }
+                    _ => None,
+                };
+                if let Some(href) = href {
+                    let opened = self.open_link(&href);
+                    if let Some(field) = self.fields.last_mut() {
+                        field.link = opened;
+                    }
+                }
+            }
+            "end" => {
+                if let Some(field) = self.fields.pop() {
+                    if field.link && self.in_link {
+                        self.close_link();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn text(&mut self, text: &str, props: &RunProps) {
+        if text.is_empty() {
+            return;
+        }
+        self.format(props);
+        for c in text.chars() {
+            match c {
+                // Keep runs of spaces, which HTML would collapse
+                ' ' if self.last_space || self.para_text.is_empty() => {
+                    self.para.push('\u{a0}');
+                    self.last_space = true;
+                }
+                ' ' => {
+                    self.para.push(' ');
+                    self.last_space = true;
+                }
+                '&' => self.para.push_str("&amp;"),
+                '<' => self.para.push_str("&lt;"),
+                '>' => self.para.push_str("&gt;"),
+                c if c.is_control() => continue,
+                c => self.para.push(c),
+            }
+            if c != ' ' {
+                self.last_space = false;
+            }
+            self.para_text.push(c);
+        }
+        self.has_content = true;
+    }
+
+    /// Switch the open formatting span to the one for `props`.
+    fn format(&mut self, props: &RunProps) {
+        let vertical = props.vertical_tag();
+        let mut flat = props.clone();
+        flat.vert_align = None;
+        let mut base = self.para_run.clone();
+        base.vert_align = None;
+        let class = self.classes.get("s", &flat.css(&base, false));
+        if self.span.as_ref() == Some(&(class.clone(), vertical)) {
+            return;
+        }
+        self.close_span();
+        if let Some(tag) = vertical {
+            self.para.push_str(&format!("<{}>", tag));
+        }
+        if let Some(class) = &class {
+            self.para.push_str(&format!("<span class=\"{}\">", class));
+        }
+        self.span = Some((class, vertical));
+    }
+
+    fn close_span(&mut self) {
+        if let Some((class, vertical)) = self.span.take() {
+            if class.is_some() {
+                self.para.push_str("</span>");
+            }
+            if let Some(tag) = vertical {
+                self.para.push_str(&format!("</{}>", tag));
+            }
+        }
+    }
+
+    fn open_link(&mut self, href: &str) -> bool {
+        if self.in_link {
+            return false;
+        }
+        self.close_span();
+        self.para
+            .push_str(&format!("<a href=\"{}\">", html_escape::encode_text(href)));
+        self.in_link = true;
+        true
+    }
+
+    fn close_link(&mut self) {
+        self.close_span();
+        self.para.push_str("</a>");
+        self.in_link = false;
+    }
+
+    /// The target of a bookmark.
+    fn anchor(&mut self, name: &str) {
+        // Word's marker of the last edit
+        if name == "_GoBack" {
+            return;
+        }
+        let id = anchor_id(name);
+        if self.ids.insert(id.clone()) {
+            self.para.push_str(&format!("<span id=\"{}\"></span>", id));
+        }
+    }
+
+    fn unique_id(&mut self, base: &str) -> String {
+        let mut id = base.to_string();
+        let mut n = 1;
+        while !self.ids.insert(id.clone()) {
+            n += 1;
+            id = format!("{}_{}", base, n);
+        }
+        id
+    }
+
+    fn note_ref(&mut self, kind: NoteKind, id: Option<&str>) {
+        let Some(note) = id.and_then(|id| self.notes[kind.index()].get(id)).copied() else {
+            return;
+        };
+        self.note_counts[kind.index()] += 1;
+        let n = self.note_counts[kind.index()];
+        self.note_refs.push((kind, note, n));
+        self.close_span();
+        if self.in_link {
+            self.para.push_str(&format!("<sup>{}</sup>", n));
+        } else {
+            self.para.push_str(&format!(
+                "<a class=\"noteref\" epub:type=\"noteref\" href=\"#{p}{n}\" id=\"{p}ref{n}\"><sup>{n}</sup></a>",
+                p = kind.prefix(),
+                n = n
+            ));
+        }
+        self.has_content = true;
+        self.last_space = false;
+    }
+
+    /// Write the referenced footnotes, then the endnotes, after the text.
+    fn write_notes(&mut self) {
+        for kind in [NoteKind::Foot, NoteKind::End] {
+            let mut i = 0;
+            let mut opened = false;
+            // Notes can reference more notes, the list grows as it is read
+            while i < self.note_refs.len() {
+                let (k, note, n) = self.note_refs[i];
+                i += 1;
+                if k != kind {
+                    continue;
+                }
+                if !opened {
+                    let section = match kind {
+                        NoteKind::Foot => "footnotes",
+                        NoteKind::End => "endnotes",
+                    };
+                    self.out.push_str(&format!(
+                        "<section class=\"notes\" epub:type=\"{}\">",
+                        section
+                    ));
+                    opened = true;
+                }
+                let note_type = match kind {
+                    NoteKind::Foot => "footnote",
+                    NoteKind::End => "endnote",
+                };
+                self.out.push_str(&format!(
+                    "<aside epub:type=\"{}\" id=\"{}{}\">",
+                    note_type,
+                    kind.prefix(),
+                    n
+                ));
+                let rels = std::mem::replace(&mut self.rels, self.note_rels[kind.index()].clone());
+                self.current_note = Some((kind, n));
+                self.depth += 1;
+                self.blocks(note);
+                self.depth -= 1;
+                self.current_note = None;
+                self.rels = rels;
+                self.out.push_str("</aside>");
+            }
+            if opened {
+                self.out.push_str("</section>");
+            }
+        }
+    }
+
+    fn drawing(&mut self, drawing: Node<'a, 'input>) {
+        self.boxes.extend(text_boxes(drawing));
+        let Some(blip) = drawing
+            .descendants()
+            .find(|n| n.tag_name().name() == "blip" && !in_fallback(*n, drawing))
+        else {
+            return;
+        };
+        let size = drawing
+            .descendants()
+            .find(|n| n.tag_name().name() == "extent")
+            .and_then(|e| {
+                let cx = attr(e, "cx")?.parse::<f32>().ok()?;
+                let cy = attr(e, "cy")?.parse::<f32>().ok()?;
+                Some((cx / EMU_PER_PT, cy / EMU_PER_PT))
+            });
+        let alt = drawing
+            .descendants()
+            .find(|n| n.tag_name().name() == "docPr")
+            .and_then(|d| attr(d, "descr").or_else(|| attr(d, "title")))
+            .unwrap_or_default();
+        if let Some(id) = attr(blip, "embed").or_else(|| attr(blip, "link")) {
+            self.image(id, size, alt);
+        }
+    }
+
+    /// Pictures and text boxes in the legacy VML markup.
+    fn vml(&mut self, pict: Node<'a, 'input>) {
+        self.boxes.extend(text_boxes(pict));
+        let Some(data) = pict
+            .descendants()
+            .find(|n| n.tag_name().name() == "imagedata")
+        else {
+            return;
+        };
+        let shape = data.parent().filter(|p| p.is_element());
+        let size = shape.and_then(|s| attr(s, "style")).and_then(|style| {
+            let length = |prop: &str| {
+                style
+                    .split(';')
+                    .filter_map(|d| d.split_once(':'))
+                    .find(|(p, _)| p.trim() == prop)
+                    .and_then(|(_, v)| vml_length(v))
+            };
+            Some((length("width")?, length("height")?))
+        });
+        let alt = shape
+            .and_then(|s| attr(s, "alt"))
+            .or_else(|| attr(data, "title"))
+            .unwrap_or_default();
+        if let Some(id) = attr(data, "id") {
+            self.image(id, size, alt);
+        }
+    }
+
+    /// An image from the relationship `id`, `size` in points.
+    fn image(&mut self, id: &str, size: Option<(f32, f32)>, alt: &str) {
+        let Some(rel) = self.rels.get(id).filter(|r| !r.external).cloned() else {
+            return;
+        };
+        let href = match self.images.get(&rel.target) {
+            Some(href) => href.clone(),
+            None => {
+                let href = self.extract_image(&rel.target);
+                self.images.insert(rel.target.clone(), href.clone());
+                href
+            }
+        };
+        let Some(href) = href else {
+            return;
+        };
+        let style = size
+            .filter(|(w, h)| *w > 0.0 && *h > 0.0)
+            .map(|(w, h)| format!(" style=\"width: {}; height: {}\"", pt(w), pt(h)))
+            .unwrap_or_default();
+        self.para.push_str(&format!(
+            "<img src=\"{}\" alt=\"{}\"{}/>",
+            html_escape::encode_text(&href),
+            html_escape::encode_text(alt),
+            style
+        ));
+        self.has_content = true;
+        self.last_space = false;
+    }
+
+    /// Write the image at `target` to the images folder, converting the
+    /// formats e-book readers cannot show. None for unusable images.
+    fn extract_image(&mut self, target: &str) -> Option<String> {
+        let data = self.docx.read_file(target).ok()?;
+        let file_name = target.rsplit('/').next().unwrap_or(target);
+        let (stem, ext) = file_name
+            .rsplit_once('.')
+            .map(|(s, e)| (s, e.to_ascii_lowercase()))
+            .unwrap_or((file_name, String::new()));
+        let (ext, data) = match ext.as_str() {
+            "png" | "jpg" | "jpeg" | "gif" | "webp" | "svg" => (ext, data),
+            // Metafiles cannot be rendered without a vector renderer
+            "emf" | "wmf" => return None,
+            _ => {
+                let img = image::load_from_memory(&data).ok()?;
+                let mut png = Vec::new();
+                img.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                    .ok()?;
+                ("png".to_string(), png)
+            }
+        };
+        let mut name = format!("{}.{}", stem, ext);
+        let mut n = 1;
+        while self
+            .image_hrefs
+            .contains(&format!("{}/{}", IMAGES_DIR, name))
+        {
+            n += 1;
+            name = format!("{}_{}.{}", stem, n, ext);
+        }
+        let dir = self.dest_dir.join(IMAGES_DIR);
+        fs::create_dir_all(&dir).ok()?;
+        fs::write(dir.join(&name), data).ok()?;
+        let href = format!("{}/{}", IMAGES_DIR, name);
+        self.image_hrefs.push(href.clone());
+        Some(href)
+    }
+
+    /// Write the text boxes met in the last paragraph after it.
+    fn flush_boxes(&mut self) {
+        while !self.boxes.is_empty() {
+            for b in std::mem::take(&mut self.boxes) {
+                self.out.push_str("<div class=\"textbox\">");
+                self.depth += 1;
+                self.blocks(b);
+                self.depth -= 1;
+                self.out.push_str("</div>");
+                self.used_textbox = true;
+            }
+        }
+    }
+
+    fn css(&self) -> String {
+        let mut css = String::new();
+        let mut body = self.base_run.css(&RunProps::default(), false);
+        if let Some(align) = self.base_para.align {
+            body.push(("text-align", align.to_string()));
+        }
+        if !body.is_empty() {
+            css.push_str(&format!("body {{ {} }}\n", declarations(&body)));
+        }
+        css.push_str(&format!(
+            "p, li, h1, h2, h3, h4, h5, h6 {{ margin-top: {}; margin-bottom: {} }}\n",
+            pt(self.base_para.space_before.unwrap_or(0.0) / 20.0),
+            pt(self.base_para.space_after.unwrap_or(0.0) / 20.0)
+        ));
+        let mut base = self.base_para.css(&ParaProps::default());
+        base.retain(|(p, _)| !matches!(*p, "margin-top" | "margin-bottom" | "text-align"));
+        if !base.is_empty() {
+            css.push_str(&format!("p {{ {} }}\n", declarations(&base)));
+        }
+        if self.used_tab {
+            css.push_str(".tab { white-space: pre }\n");
+        }
+        if self.used_textbox {
+            css.push_str(".textbox { border: 1px solid; padding: 0.5em; margin: 0.5em 0 }\n");
+        }
+        if !self.note_refs.is_empty() {
+            css.push_str(".notes { border-top: 1px solid; margin-top: 1em }\n");
+        }
+        for (name, rule) in &self.classes.rules {
+            css.push_str(&format!(".{} {{ {} }}\n", name, rule));
+        }
+        css
+    }
+}
+
+fn declarations(decls: &[(&str, String)]) -> String {
+    decls
+        .iter()
+        .map(|(p, v)| format!("{}: {}", p, v))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn class_attr(class: &Option<String>) -> String {
+    class
+        .as_ref()
+        .map(|c| format!(" class=\"{}\"", c))
+        .unwrap_or_default()
+}
+
+/// A CSS width for a `tblW` or `tcW` element.
+fn width(node: Node) -> Option<String> {
+    let value = attr(node, "w")?;
+    match attr(node, "type") {
+        Some("pct") => {
+            if let Some(percent) = value.strip_suffix('%') {
+                Some(format!("{}%", percent))
+            } else {
+                // Fiftieths of a percent
+                let v = value.parse::<f32>().ok()?;
+                Some(format!("{}%", v / 50.0))
+            }
+        }
+        Some("dxa") | None => {
+            let v = value.parse::<f32>().ok().filter(|v| *v > 0.0)?;
+            Some(pt(v / 20.0))
+        }
+        _ => None,
+    }
+}
+
+/// A VML length in points.
+fn vml_length(value: &str) -> Option<f32> {
+    let v = value.trim();
+    let split = v
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+        .unwrap_or(v.len());
+    let n = v[..split].parse::<f32>().ok()?;
+    Some(match &v[split..] {
+        "pt" | "" => n,
+        "in" => n * 72.0,
+        "cm" => n * 72.0 / 2.54,
+        "mm" => n * 72.0 / 25.4,
+        "px" => n * 0.75,
+        "pc" => n * 12.0,
+        _ => return None,
+    })
+}
+
+/// The text boxes of a drawing, leaving out the fallback copies and the
+/// boxes nested in other boxes.
+fn text_boxes<'a, 'input>(node: Node<'a, 'input>) -> Vec<Node<'a, 'input>> {
+    node.descendants()
+        .filter(|n| {
+            n.tag_name().name() == "txbxContent"
+                && !n
+                    .ancestors()
+                    .skip(1)
+                    .take_while(|a| *a != node)
+                    .any(|a| matches!(a.tag_name().name(), "Fallback" | "txbxContent"))
+        })
+        .collect()
+}
+
+fn in_fallback(node: Node, root: Node) -> bool {
+    node.ancestors()
+        .take_while(|a| *a != root)
+        .any(|a| a.tag_name().name() == "Fallback")
+}
+
+/// An HTML id for a bookmark name.
+fn anchor_id(name: &str) -> String {
+    let mut id: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '_' | '-' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if !id.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        id.insert(0, 'a');
+    }
+    id
+}
+
+/// The link target of a field: the URL of a HYPERLINK, the bookmark of a
+/// REF or PAGEREF with `\h`.
+fn field_href(instr: &str) -> Option<String> {
+    let tokens = field_tokens(instr);
+    let (kind, args) = tokens.split_first()?;
+    match kind.to_ascii_uppercase().as_str() {
+        "HYPERLINK" => {
+            let mut url = None;
+            let mut anchor = None;
+            let mut args = args.iter();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "\\l" => anchor = args.next(),
+                    // Switches with an argument of their own
+                    "\\o" | "\\t" => {
+                        args.next();
+                    }
+                    a if a.starts_with('\\') => {}
+                    _ if url.is_none() => url = Some(arg),
+                    _ => {}
+                }
+            }
+            match (url, anchor) {
+                (Some(url), Some(anchor)) => Some(format!("{}#{}", url, anchor_id(anchor))),
+                (Some(url), None) => Some(url.clone()),
+                (None, Some(anchor)) => Some(format!("#{}", anchor_id(anchor))),
+                (None, None) => None,
+            }
+        }
+        "REF" | "PAGEREF" | "NOTEREF" => {
+            let bookmark = args.first()?;
+            args.iter()
+                .any(|a| a.eq_ignore_ascii_case("\\h"))
+                .then(|| format!("#{}", anchor_id(bookmark)))
+        }
+        _ => None,
+    }
+}
+
+/// Split a field instruction into words, keeping quoted arguments whole.
+fn field_tokens(instr: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in instr.chars() {
+        match c {
+            '"' => {
+                if quoted {
+                    tokens.push(std::mem::take(&mut current));
+                }
+                quoted = !quoted;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
 }
 
 pub mod html_escape {
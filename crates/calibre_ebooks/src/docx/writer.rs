@@ -0,0 +1,1599 @@
+//! Converts spine (X)HTML documents into a WordprocessingML (DOCX) package.
+//!
+//! Formatting comes from the tag defaults browsers apply, adjusted by the
+//! inline `style` declarations read through the `Stylizer`, as in the RTF
+//! writer. Headings, footnotes and the table of contents use named styles
+//! so that they can be restyled in a word processor.
+
+use crate::docx::names::DOCXNamespaces;
+use crate::metadata::MetaInformation;
+use crate::oeb::container::Container;
+use crate::oeb::css::{
+    self, char_style, default_display, CharStyle, FontFamily, HEADING_SIZES,
+};
+use crate::oeb::parse_utils::{abshref, xmlize_entities};
+use crate::oeb::stylizer::{Style, Stylizer};
+use crate::oeb::toc::{TOCNode, TOC};
+use anyhow::Result;
+use calibre_utils::html2text::html2text;
+use chrono::Utc;
+use image::ImageFormat;
+use roxmltree::{Document, Node, ParsingOptions};
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Seek, Write};
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+const CSS_DPI: f32 = 96.0;
+/// A4 with one inch margins, in twips.
+const PAPER_WIDTH: i32 = 11906;
+const PAPER_HEIGHT: i32 = 16838;
+const MARGIN: i32 = 1440;
+const CONTENT_WIDTH: i32 = PAPER_WIDTH - 2 * MARGIN;
+/// Indentation step for lists and block quotes.
+const INDENT: i32 = 720;
+const LIST_LEVELS: usize = 9;
+/// Point size of the text of footnotes.
+const FOOTNOTE_SIZE: f32 = 10.0;
+/// English Metric Units in a CSS pixel, and twips in one.
+const EMU_PER_PX: f32 = 9525.0;
+const TWIPS_PER_PX: f32 = 15.0;
+
+const BULLETS: [&str; 3] = ["\u{2022}", "\u{25e6}", "\u{25aa}"];
+
+const OPS_NS: &str = "http://www.idpf.org/2007/ops";
+const WP_NS: &str = "http://schemas.openxmlformats.org/drawingml/2006/wordprocessingDrawing";
+const A_NS: &str = "http://schemas.openxmlformats.org/drawingml/2006/main";
+const PIC_NS: &str = "http://schemas.openxmlformats.org/drawingml/2006/picture";
+
+const PKG_RELS_NS: &str = "http://schemas.openxmlformats.org/package/2006/relationships";
+const CONTENT_TYPES_NS: &str = "http://schemas.openxmlformats.org/package/2006/content-types";
+const WML_TYPE: &str = "application/vnd.openxmlformats-officedocument.wordprocessingml";
+
+const XML_DECL: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n";
+
+/// Paragraph formatting inherited by nested blocks. Lengths are in twips.
+#[derive(Debug, Clone, Default)]
+struct BlockCtx {
+    left: i32,
+    right: i32,
+    first: i32,
+    /// Room between the indents, for tables and images.
+    width: i32,
+    align: Option<&'static str>,
+    space_before: i32,
+    space_after: i32,
+    heading: Option<u8>,
+    /// Paragraph style other than Normal or a heading.
+    style: Option<&'static str>,
+    /// Font size of the paragraph style, in points.
+    size: f32,
+}
+
+struct ListCtx {
+    /// Index into `DocxWriter::lists`.
+    def: usize,
+    level: usize,
+}
+
+/// One numbering definition; every top level HTML list gets its own so
+/// numbering restarts.
+struct ListDef {
+    start: i64,
+    /// The number format of each nesting level, as first seen.
+    levels: [Option<&'static str>; LIST_LEVELS],
+}
+
+struct Relationship {
+    id: String,
+    kind: &'static str,
+    target: String,
+    external: bool,
+}
+
+/// Where paragraphs are being written: the body of the document or one
+/// footnote.
+struct Flow {
+    xml: String,
+    blocks: Vec<BlockCtx>,
+    list_stack: Vec<ListCtx>,
+    para_open: bool,
+    at_line_start: bool,
+    pending_space: bool,
+    pending_page_break: bool,
+    pending_border: bool,
+    /// `(numId, level)` of the list item the next paragraph starts.
+    pending_marker: Option<(usize, usize)>,
+    pending_bookmarks: Vec<String>,
+    /// Start tag of the hyperlink being written, reopened in every
+    /// paragraph it spans.
+    hyperlink: Option<String>,
+    /// The next paragraph starts with the reference mark of its footnote.
+    footnote_ref: bool,
+    table_depth: usize,
+    pre_depth: usize,
+    has_content: bool,
+}
+
+impl Flow {
+    fn new(root: BlockCtx) -> Self {
+        Flow {
+            xml: String::new(),
+            blocks: vec![root],
+            list_stack: Vec::new(),
+            para_open: false,
+            at_line_start: true,
+            pending_space: false,
+            pending_page_break: false,
+            pending_border: false,
+            pending_marker: None,
+            pending_bookmarks: Vec::new(),
+            hyperlink: None,
+            footnote_ref: false,
+            table_depth: 0,
+            pre_depth: 0,
+            has_content: false,
+        }
+    }
+}
+
+pub struct DocxWriter<'a> {
+    stylizer: Stylizer,
+    container: &'a dyn Container,
+    flow: Flow,
+    href: String,
+    lists: Vec<ListDef>,
+    /// The paragraphs of each footnote, numbered from 1.
+    footnotes: Vec<String>,
+    /// Notes turned into footnotes, as `href#id`, left out of the body.
+    noted: HashSet<String>,
+    in_footnote: bool,
+    /// The element referencing the footnote being written, links back to it
+    /// are dropped.
+    note_backref: Option<String>,
+    /// Bookmark names by `href` or `href#id`; Word limits them to 40
+    /// characters so they are numbered instead.
+    bookmarks: HashMap<String, String>,
+    placed_bookmarks: HashSet<String>,
+    rels: Vec<Relationship>,
+    /// Relationship id and pixel size of each image, by href.
+    images: HashMap<String, (String, u32, u32)>,
+    media: Vec<(String, Vec<u8>)>,
+    /// `(title, target, depth)` of the table of contents entries.
+    toc: Vec<(String, String, usize)>,
+    drawings: usize,
+}
+
+impl<'a> DocxWriter<'a> {
+    pub fn new(container: &'a dyn Container) -> Self {
+        let stylizer = Stylizer::new(CSS_DPI, 12.0);
+        DocxWriter {
+            stylizer,
+            container,
+            flow: Flow::new(BlockCtx {
+                width: CONTENT_WIDTH,
+                size: stylizer.font_base,
+                ..BlockCtx::default()
+            }),
+            href: String::new(),
+            lists: Vec::new(),
+            footnotes: Vec::new(),
+            noted: HashSet::new(),
+            in_footnote: false,
+            note_backref: None,
+            bookmarks: HashMap::new(),
+            placed_bookmarks: HashSet::new(),
+            rels: Vec::new(),
+            images: HashMap::new(),
+            media: Vec::new(),
+            toc: Vec::new(),
+            drawings: 0,
+        }
+    }
+
+    /// Append the (X)HTML document stored at `href`. Every document after
+    /// the first starts on a new page.
+    pub fn add_document(&mut self, href: &str, html: &str) {
+        self.close_paragraph();
+        if self.flow.has_content {
+            self.flow.pending_page_break = true;
+        }
+        self.href = href.to_string();
+        self.flow.pending_bookmarks.push(href.to_string());
+
+        let base = self.base_style();
+        let text = xmlize_entities(html);
+        match Document::parse_with_options(&text, xml_options()) {
+            Ok(doc) => {
+                let root = doc.root_element();
+                let body = root
+                    .descendants()
+                    .find(|n| n.is_element() && n.tag_name().name().eq_ignore_ascii_case("body"))
+                    .unwrap_or(root);
+                self.walk(body, &base);
+            }
+            Err(_) => {
+                // Tag soup: keep the text, lose the formatting
+                for block in html2text(html).split("\n\n") {
+                    self.text(block, &base);
+                    self.close_paragraph();
+                }
+            }
+        }
+        self.close_paragraph();
+    }
+
+    /// Write a table of contents, linking to its entries, before the
+    /// content.
+    pub fn set_toc(&mut self, toc: &TOC) {
+        fn collect(node: &TOCNode, depth: usize, out: &mut Vec<(String, String, usize)>) {
+            let title = node
+                .title
+                .as_deref()
+                .unwrap_or_default()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            if let Some(href) = node.href.as_deref().filter(|_| !title.is_empty()) {
+                let href = urlencoding::decode(href)
+                    .map(|s| s.into_owned())
+                    .unwrap_or_else(|_| href.to_string());
+                out.push((title, href, depth));
+            }
+            for child in &node.children {
+                collect(child, depth + 1, out);
+            }
+        }
+        self.toc.clear();
+        for node in &toc.root.children {
+            collect(node, 1, &mut self.toc);
+        }
+    }
+
+    /// Write the complete package, with `mi` as its core properties.
+    pub fn finish<W: Write + Seek>(mut self, mi: &MetaInformation, out: W) -> Result<()> {
+        self.close_paragraph();
+
+        let mut body = String::new();
+        if !self.toc.is_empty() {
+            body.push_str(
+                "<w:p><w:pPr><w:pStyle w:val=\"TOCHeading\"/></w:pPr>\
+                 <w:r><w:t>Table of Contents</w:t></w:r></w:p>",
+            );
+            for (title, target, depth) in std::mem::take(&mut self.toc) {
+                let anchor = self.bookmark(&target);
+                body.push_str(&format!(
+                    "<w:p><w:pPr><w:pStyle w:val=\"TOC{}\"/></w:pPr>\
+                     <w:hyperlink w:anchor=\"{}\" w:history=\"1\"><w:r><w:t xml:space=\"preserve\">{}</w:t></w:r></w:hyperlink></w:p>",
+                    depth.min(3),
+                    anchor,
+                    escape(&title)
+                ));
+            }
+            body.push_str("<w:p><w:r><w:br w:type=\"page\"/></w:r></w:p>");
+        }
+        body.push_str(&self.flow.xml);
+
+        let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        let mut zip = ZipWriter::new(out);
+
+        zip.start_file("[Content_Types].xml", options)?;
+        zip.write_all(self.content_types().as_bytes())?;
+
+        zip.start_file("_rels/.rels", options)?;
+        zip.write_all(
+            relationships(&[
+                ("rId1", DOCXNamespaces::DOCUMENT, "word/document.xml"),
+                ("rId2", DOCXNamespaces::CORE_PROPS, "docProps/core.xml"),
+                ("rId3", DOCXNamespaces::EXTENDED_PROPS, "docProps/app.xml"),
+            ])
+            .as_bytes(),
+        )?;
+
+        zip.start_file("docProps/core.xml", options)?;
+        zip.write_all(core_properties(mi).as_bytes())?;
+        zip.start_file("docProps/app.xml", options)?;
+        zip.write_all(app_properties(mi).as_bytes())?;
+
+        zip.start_file("word/document.xml", options)?;
+        write!(
+            zip,
+            "{}<w:document {}><w:body>{}<w:sectPr><w:pgSz w:w=\"{}\" w:h=\"{}\"/>\
+             <w:pgMar w:top=\"{m}\" w:right=\"{m}\" w:bottom=\"{m}\" w:left=\"{m}\" w:header=\"708\" w:footer=\"708\" w:gutter=\"0\"/>\
+             </w:sectPr></w:body></w:document>",
+            XML_DECL,
+            namespaces(),
+            body,
+            PAPER_WIDTH,
+            PAPER_HEIGHT,
+            m = MARGIN
+        )?;
+
+        zip.start_file("word/styles.xml", options)?;
+        zip.write_all(self.styles(mi).as_bytes())?;
+        zip.start_file("word/settings.xml", options)?;
+        zip.write_all(self.settings().as_bytes())?;
+        if !self.lists.is_empty() {
+            zip.start_file("word/numbering.xml", options)?;
+            zip.write_all(self.numbering().as_bytes())?;
+        }
+        if !self.footnotes.is_empty() {
+            zip.start_file("word/footnotes.xml", options)?;
+            zip.write_all(self.footnotes_xml().as_bytes())?;
+            // Images and links in footnotes resolve against the rels of
+            // their own part
+            zip.start_file("word/_rels/footnotes.xml.rels", options)?;
+            zip.write_all(self.part_relationships(false).as_bytes())?;
+        }
+        zip.start_file("word/_rels/document.xml.rels", options)?;
+        zip.write_all(self.part_relationships(true).as_bytes())?;
+
+        for (name, data) in &self.media {
+            zip.start_file(format!("word/{}", name), options)?;
+            zip.write_all(data)?;
+        }
+        zip.finish()?;
+        Ok(())
+    }
+
+    fn base_style(&self) -> CharStyle {
+        CharStyle {
+            bold: false,
+            italic: false,
+            underline: false,
+            strike: false,
+            superscript: false,
+            subscript: false,
+            family: FontFamily::Serif,
+            size: self.stylizer.font_base,
+            color: None,
+        }
+    }
+
+    fn walk(&mut self, node: Node, style: &CharStyle) {
+        for child in node.children() {
+            if child.is_text() {
+                self.text(child.text().unwrap_or(""), style);
+            } else if child.is_element() {
+                self.element(child, style);
+            }
+        }
+    }
+
+    fn element(&mut self, node: Node, parent: &CharStyle) {
+        let tag = node.tag_name().name().to_ascii_lowercase();
+        if matches!(
+            tag.as_str(),
+            "head" | "script" | "style" | "title" | "meta" | "link" | "noscript"
+        ) {
+            return;
+        }
+        // Notes already written as footnotes
+        if let Some(id) = node.attribute("id") {
+            if !self.in_footnote && self.noted.contains(&format!("{}#{}", self.href, id)) {
+                return;
+            }
+        }
+        let stylizer = self.stylizer;
+        let css = stylizer.style(&node);
+        let display = css
+            .get_inline_style("display")
+            .unwrap_or_else(|| default_display(&tag).to_string());
+        if display.trim() == "none" {
+            return;
+        }
+        let style = char_style(&stylizer, &css, &tag, parent);
+        let breaks = self.flow.table_depth == 0 && !self.in_footnote;
+
+        if breaks
+            && css::is_any(
+                &css,
+                &["page-break-before", "break-before"],
+                &["always", "page"],
+            )
+        {
+            self.close_paragraph();
+            self.flow.pending_page_break |= self.flow.has_content;
+        }
+        if let Some(id) = node.attribute("id") {
+            self.flow
+                .pending_bookmarks
+                .push(format!("{}#{}", self.href, id));
+            if self.flow.para_open && display == "inline" {
+                self.flush_bookmarks();
+            }
+        }
+
+        match tag.as_str() {
+            "br" => {
+                self.open_paragraph();
+                self.flow.xml.push_str("<w:r><w:br/></w:r>");
+                self.flow.at_line_start = true;
+                self.flow.pending_space = false;
+            }
+            "img" => self.image(node),
+            "hr" => {
+                self.close_paragraph();
+                self.flow.pending_border = true;
+                self.open_paragraph();
+                self.close_paragraph();
+            }
+            "a" => self.link(node, &style),
+            "table" => self.table(node, &style),
+            "ul" | "ol" => self.list(node, &css, tag == "ol", &style),
+            "li" => self.list_item(node, &css, &style),
+            _ if display != "inline" => self.block(node, &css, &tag, &style),
+            _ => self.walk(node, &style),
+        }
+
+        if breaks
+            && css::is_any(
+                &css,
+                &["page-break-after", "break-after"],
+                &["always", "page"],
+            )
+        {
+            self.close_paragraph();
+            self.flow.pending_page_break = true;
+        }
+    }
+
+    fn block(&mut self, node: Node, css: &Style, tag: &str, style: &CharStyle) {
+        self.close_paragraph();
+        let ctx = self.block_ctx(css, tag, style);
+        self.flow.blocks.push(ctx);
+        if tag == "pre" {
+            self.flow.pre_depth += 1;
+        }
+        self.walk(node, style);
+        self.close_paragraph();
+        if tag == "pre" {
+            self.flow.pre_depth -= 1;
+        }
+        self.flow.blocks.pop();
+    }
+
+    fn block_ctx(&self, css: &Style, tag: &str, style: &CharStyle) -> BlockCtx {
+        let parent = self.current_block();
+        let em = style.size;
+        let heading = match tag {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" if !self.in_footnote => tag[1..].parse().ok(),
+            _ => None,
+        };
+        let mut ctx = BlockCtx {
+            heading,
+            space_before: 0,
+            space_after: 0,
+            size: match heading {
+                Some(level) => HEADING_SIZES[level as usize - 1],
+                None => parent.size,
+            },
+            ..parent.clone()
+        };
+        let (before, after) = match tag {
+            "p" | "pre" | "dl" => (0.0, 6.0),
+            "blockquote" => {
+                ctx.left += INDENT;
+                ctx.right += INDENT;
+                (6.0, 6.0)
+            }
+            "dd" => {
+                ctx.left += INDENT;
+                (0.0, 0.0)
+            }
+            "center" => {
+                ctx.align = Some("center");
+                (0.0, 0.0)
+            }
+            _ if heading.is_some() => (12.0, 6.0),
+            _ => (0.0, 0.0),
+        };
+        ctx.space_before = twips(before);
+        ctx.space_after = twips(after);
+
+        let width = parent.width as f32 / 20.0;
+        let get = |prop: &str| {
+            css.get_inline_style(prop)
+                .and_then(|v| css::length(&self.stylizer, &v, em, width))
+        };
+        let margins = css
+            .get_inline_style("margin")
+            .map(|v| crate::oeb::normalize_css::normalize_edge("margin", &v))
+            .unwrap_or_default();
+        let edge = |name: &str| {
+            get(name).or_else(|| {
+                margins
+                    .get(name)
+                    .and_then(|v| css::length(&self.stylizer, v, em, width))
+            })
+        };
+        if let Some(v) = edge("margin-top") {
+            ctx.space_before = twips(v);
+        }
+        if let Some(v) = edge("margin-bottom") {
+            ctx.space_after = twips(v);
+        }
+        if let Some(v) = edge("margin-left") {
+            ctx.left += twips(v);
+        }
+        if let Some(v) = edge("margin-right") {
+            ctx.right += twips(v);
+        }
+        if let Some(v) = get("text-indent") {
+            ctx.first = twips(v);
+        }
+        if let Some(align) = css.get_inline_style("text-align") {
+            ctx.align = match align.trim() {
+                "left" | "start" => None,
+                "right" | "end" => Some("right"),
+                "center" => Some("center"),
+                "justify" => Some("both"),
+                _ => ctx.align,
+            };
+        }
+        ctx.width =
+            (parent.width - (ctx.left - parent.left) - (ctx.right - parent.right)).max(INDENT);
+        ctx
+    }
+
+    fn current_block(&self) -> &BlockCtx {
+        self.flow.blocks.last().expect("root block is never popped")
+    }
+
+    fn list(&mut self, node: Node, css: &Style, ordered: bool, style: &CharStyle) {
+        self.close_paragraph();
+        let start = node
+            .attribute("start")
+            .and_then(|s| s.trim().parse::<i64>().ok())
+            .unwrap_or(1);
+        let (def, level) = match self.flow.list_stack.last() {
+            Some(parent) => (parent.def, (parent.level + 1).min(LIST_LEVELS - 1)),
+            None => {
+                self.lists.push(ListDef {
+                    start,
+                    levels: [None; LIST_LEVELS],
+                });
+                (self.lists.len() - 1, 0)
+            }
+        };
+        self.lists[def].levels[level].get_or_insert(number_format(node, css, ordered));
+
+        let mut ctx = self.block_ctx(css, "ul", style);
+        ctx.left += INDENT;
+        ctx.width -= INDENT;
+        ctx.first = 0;
+        self.flow.blocks.push(ctx);
+        self.flow.list_stack.push(ListCtx { def, level });
+        self.walk(node, style);
+        self.close_paragraph();
+        self.flow.list_stack.pop();
+        self.flow.blocks.pop();
+    }
+
+    fn list_item(&mut self, node: Node, css: &Style, style: &CharStyle) {
+        self.close_paragraph();
+        let mut ctx = self.block_ctx(css, "li", style);
+        if let Some(list) = self.flow.list_stack.last() {
+            self.flow.pending_marker = Some((list.def + 1, list.level));
+            // Hanging indent for the marker
+            ctx.first = -INDENT / 2;
+        }
+        self.flow.blocks.push(ctx);
+        self.walk(node, style);
+        self.close_paragraph();
+        self.flow.pending_marker = None;
+        self.flow.blocks.pop();
+    }
+
+    fn table(&mut self, node: Node, style: &CharStyle) {
+        self.close_paragraph();
+        let rows: Vec<(Node, bool)> = node
+            .children()
+            .filter(|n| n.is_element())
+            .flat_map(|n| {
+                let tag = n.tag_name().name().to_ascii_lowercase();
+                if matches!(tag.as_str(), "thead" | "tbody" | "tfoot") {
+                    n.children()
+                        .filter(|r| {
+                            r.is_element() && r.tag_name().name().eq_ignore_ascii_case("tr")
+                        })
+                        .map(|r| (r, tag == "thead"))
+                        .collect::<Vec<_>>()
+                } else if tag == "tr" {
+                    vec![(n, false)]
+                } else {
+                    Vec::new()
+                }
+            })
+            .collect();
+        let columns = rows
+            .iter()
+            .map(|(row, _)| {
+                table_cells(*row)
+                    .iter()
+                    .map(|(_, span)| span)
+                    .sum::<usize>()
+            })
+            .max()
+            .unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+
+        if std::mem::take(&mut self.flow.pending_page_break) {
+            self.flow
+                .xml
+                .push_str("<w:p><w:r><w:br w:type=\"page\"/></w:r></w:p>");
+        }
+        let block = self.current_block().clone();
+        let col_width = block.width / columns as i32;
+        self.flow.xml.push_str(&format!(
+            "<w:tbl><w:tblPr><w:tblStyle w:val=\"TableGrid\"/><w:tblW w:w=\"{}\" w:type=\"dxa\"/>",
+            col_width * columns as i32
+        ));
+        if block.left != 0 {
+            self.flow.xml.push_str(&format!(
+                "<w:tblInd w:w=\"{}\" w:type=\"dxa\"/>",
+                block.left
+            ));
+        }
+        self.flow
+            .xml
+            .push_str("<w:tblLook w:val=\"04A0\"/></w:tblPr><w:tblGrid>");
+        for _ in 0..columns {
+            self.flow
+                .xml
+                .push_str(&format!("<w:gridCol w:w=\"{}\"/>", col_width));
+        }
+        self.flow.xml.push_str("</w:tblGrid>");
+
+        for (row, header) in rows {
+            let cells = table_cells(row);
+            if cells.is_empty() {
+                continue;
+            }
+            self.flow.xml.push_str("<w:tr>");
+            if header {
+                self.flow.xml.push_str("<w:trPr><w:tblHeader/></w:trPr>");
+            }
+            for (cell, span) in cells {
+                let cell_width = col_width * span as i32;
+                self.flow.xml.push_str(&format!(
+                    "<w:tc><w:tcPr><w:tcW w:w=\"{}\" w:type=\"dxa\"/>",
+                    cell_width
+                ));
+                if span > 1 {
+                    self.flow
+                        .xml
+                        .push_str(&format!("<w:gridSpan w:val=\"{}\"/>", span));
+                }
+                self.flow.xml.push_str("</w:tcPr>");
+
+                let tag = cell.tag_name().name().to_ascii_lowercase();
+                let stylizer = self.stylizer;
+                let css = stylizer.style(&cell);
+                let cell_style = char_style(&stylizer, &css, &tag, style);
+                let mut ctx = self.block_ctx(&css, &tag, &cell_style);
+                ctx.left = 0;
+                ctx.right = 0;
+                ctx.first = 0;
+                // Less the default cell margins
+                ctx.width = (cell_width - 216).max(INDENT);
+                if tag == "th" && css.get_inline_style("text-align").is_none() {
+                    ctx.align = Some("center");
+                }
+                self.flow.blocks.push(ctx);
+                self.flow.table_depth += 1;
+                self.walk(cell, &cell_style);
+                self.close_paragraph();
+                // A cell always ends with a paragraph
+                if !self.flow.xml.ends_with("</w:p>") {
+                    self.open_paragraph();
+                    self.close_paragraph();
+                }
+                self.flow.table_depth -= 1;
+                self.flow.blocks.pop();
+                self.flow.xml.push_str("</w:tc>");
+            }
+            self.flow.xml.push_str("</w:tr>");
+        }
+        self.flow.xml.push_str("</w:tbl>");
+        self.flow.has_content = true;
+    }
+
+    fn link(&mut self, node: Node, style: &CharStyle) {
+        let href = match node.attribute("href").map(str::trim) {
+            Some(href) if !href.is_empty() => href,
+            _ => return self.walk(node, style),
+        };
+        let target = self.target_key(href);
+        if self.in_footnote && target.is_some() && target == self.note_backref {
+            return;
+        }
+        if !self.in_footnote && is_noteref(node) && self.footnote(node, href) {
+            return;
+        }
+        if self.flow.hyperlink.is_some() {
+            return self.walk(node, style);
+        }
+
+        let start = match target {
+            Some(key) => format!(
+                "<w:hyperlink w:anchor=\"{}\" w:history=\"1\">",
+                self.bookmark(&key)
+            ),
+            None => {
+                let id = self.relationship("hyperlink", href, true);
+                format!("<w:hyperlink r:id=\"{}\" w:history=\"1\">", id)
+            }
+        };
+        self.open_paragraph();
+        self.flush_pending_space();
+        self.flow.xml.push_str(&start);
+        self.flow.hyperlink = Some(start);
+        let mut style = style.clone();
+        style.underline = true;
+        if style.color.is_none() {
+            style.color = Some((0, 0, 255));
+        }
+        self.walk(node, &style);
+        if self.flow.hyperlink.take().is_some() && self.flow.para_open {
+            self.flow.xml.push_str("</w:hyperlink>");
+        }
+    }
+
+    /// Write the note `href` points to as a footnote referenced from here.
+    /// Returns false when the note cannot be found.
+    fn footnote(&mut self, node: Node, href: &str) -> bool {
+        let Some(key) = self.target_key(href) else {
+            return false;
+        };
+        let Some((doc_href, id)) = key.split_once('#') else {
+            return false;
+        };
+        let backref = node
+            .attribute("id")
+            .map(|id| format!("{}#{}", self.href, id));
+        let has_id = |n: &Node| n.is_element() && n.attribute("id") == Some(id);
+
+        let written = if doc_href == self.href {
+            match node.document().descendants().find(has_id) {
+                Some(note) if !node.ancestors().any(|a| a == note) => {
+                    self.write_footnote(note, doc_href.to_string(), backref)
+                }
+                _ => return false,
+            }
+        } else {
+            let Ok(data) = self.container.read(doc_href) else {
+                return false;
+            };
+            let text = xmlize_entities(&String::from_utf8_lossy(&data));
+            let Ok(doc) = Document::parse_with_options(&text, xml_options()) else {
+                return false;
+            };
+            let Some(note) = doc.descendants().find(has_id) else {
+                return false;
+            };
+            self.write_footnote(note, doc_href.to_string(), backref)
+        };
+        self.noted.insert(key);
+
+        self.open_paragraph();
+        self.flush_pending_space();
+        self.flow.xml.push_str(&format!(
+            "<w:r><w:rPr><w:rStyle w:val=\"FootnoteReference\"/></w:rPr><w:footnoteReference w:id=\"{}\"/></w:r>",
+            written
+        ));
+        self.flow.at_line_start = false;
+        true
+    }
+
+    /// Write the contents of `note`, from the document at `href`, as a new
+    /// footnote. Returns its id.
+    fn write_footnote(&mut self, note: Node, href: String, backref: Option<String>) -> usize {
+        let root = BlockCtx {
+            width: CONTENT_WIDTH,
+            style: Some("FootnoteText"),
+            size: FOOTNOTE_SIZE,
+            ..BlockCtx::default()
+        };
+        let saved_flow = std::mem::replace(&mut self.flow, Flow::new(root));
+        let saved_href = std::mem::replace(&mut self.href, href);
+        self.in_footnote = true;
+        self.note_backref = backref;
+        self.flow.footnote_ref = true;
+
+        let mut base = self.base_style();
+        base.size = FOOTNOTE_SIZE;
+        self.walk(note, &base);
+        self.close_paragraph();
+        if self.flow.footnote_ref {
+            // Nothing but the reference mark
+            self.open_paragraph();
+            self.close_paragraph();
+        }
+
+        let flow = std::mem::replace(&mut self.flow, saved_flow);
+        self.href = saved_href;
+        self.in_footnote = false;
+        self.note_backref = None;
+        self.footnotes.push(flow.xml);
+        self.footnotes.len()
+    }
+
+    /// The bookmark key of an internal link, None for an external one.
+    fn target_key(&self, href: &str) -> Option<String> {
+        if href.contains("://") || href.starts_with("mailto:") {
+            return None;
+        }
+        let (path, fragment) = match href.split_once('#') {
+            Some((path, fragment)) => (path, Some(fragment)),
+            None => (href, None),
+        };
+        let doc = if path.is_empty() {
+            self.href.clone()
+        } else {
+            abshref(&self.href, path)
+        };
+        Some(match fragment.filter(|f| !f.is_empty()) {
+            Some(fragment) => format!("{}#{}", doc, fragment),
+            None => doc,
+        })
+    }
+
+    fn image(&mut self, node: Node) {
+        let src = match node.attribute("src") {
+            Some(src) => abshref(&self.href, src),
+            None => return,
+        };
+        let (id, px_w, px_h) = match self.images.get(&src) {
+            Some(image) => image.clone(),
+            None => match self.add_image(&src) {
+                Some(image) => image,
+                None => return self.alt_text(node),
+            },
+        };
+
+        // Display size in CSS pixels, from attributes if present
+        let attr = |name: &str| {
+            node.attribute(name)
+                .and_then(|v| v.trim().trim_end_matches("px").parse::<f32>().ok())
+                .filter(|v| *v > 0.0)
+        };
+        let aspect = px_h as f32 / px_w.max(1) as f32;
+        let (mut w, mut h) = match (attr("width"), attr("height")) {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, w * aspect),
+            (None, Some(h)) => (h / aspect.max(f32::EPSILON), h),
+            (None, None) => (px_w as f32, px_h as f32),
+        };
+        let max_w = self.current_block().width as f32 / TWIPS_PER_PX;
+        if w > max_w {
+            h *= max_w / w;
+            w = max_w;
+        }
+        let (cx, cy) = (
+            (w * EMU_PER_PX).round() as i64,
+            (h * EMU_PER_PX).round() as i64,
+        );
+        self.drawings += 1;
+        let name = format!("Picture {}", self.drawings);
+        let descr = escape(node.attribute("alt").unwrap_or(""));
+
+        self.open_paragraph();
+        self.flush_pending_space();
+        self.flow.xml.push_str(&format!(
+            "<w:r><w:drawing><wp:inline distT=\"0\" distB=\"0\" distL=\"0\" distR=\"0\">\
+             <wp:extent cx=\"{cx}\" cy=\"{cy}\"/><wp:docPr id=\"{n}\" name=\"{name}\" descr=\"{descr}\"/>\
+             <wp:cNvGraphicFramePr><a:graphicFrameLocks noChangeAspect=\"1\"/></wp:cNvGraphicFramePr>\
+             <a:graphic><a:graphicData uri=\"{pic}\"><pic:pic>\
+             <pic:nvPicPr><pic:cNvPr id=\"0\" name=\"{name}\"/><pic:cNvPicPr/></pic:nvPicPr>\
+             <pic:blipFill><a:blip r:embed=\"{id}\"/><a:stretch><a:fillRect/></a:stretch></pic:blipFill>\
+             <pic:spPr><a:xfrm><a:off x=\"0\" y=\"0\"/><a:ext cx=\"{cx}\" cy=\"{cy}\"/></a:xfrm>\
+             <a:prstGeom prst=\"rect\"><a:avLst/></a:prstGeom></pic:spPr>\
+             </pic:pic></a:graphicData></a:graphic></wp:inline></w:drawing></w:r>",
+            cx = cx,
+            cy = cy,
+            n = self.drawings,
+            name = name,
+            descr = descr,
+            pic = PIC_NS,
+            id = id
+        ));
+        self.flow.at_line_start = false;
+    }
+
+    /// Store the image at `src` in the package. Returns its relationship id
+    /// and pixel size.
+    fn add_image(&mut self, src: &str) -> Option<(String, u32, u32)> {
+        let data = self.container.read(src).ok()?;
+        let (ext, data) = match image::guess_format(&data) {
+            Ok(ImageFormat::Png) => ("png", data),
+            Ok(ImageFormat::Jpeg) => ("jpeg", data),
+            Ok(ImageFormat::Gif) => ("gif", data),
+            // Anything else is re-encoded, word processors only reliably
+            // show these
+            _ => {
+                let img = image::load_from_memory(&data).ok()?;
+                let mut png = Vec::new();
+                img.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                    .ok()?;
+                ("png", png)
+            }
+        };
+        let (px_w, px_h) = image::io::Reader::new(Cursor::new(&data))
+            .with_guessed_format()
+            .ok()
+            .and_then(|r| r.into_dimensions().ok())?;
+
+        let name = format!("media/image{}.{}", self.media.len() + 1, ext);
+        let id = self.relationship("image", &name, false);
+        self.media.push((name, data));
+        let image = (id, px_w, px_h);
+        self.images.insert(src.to_string(), image.clone());
+        Some(image)
+    }
+
+    fn alt_text(&mut self, node: Node) {
+        if let Some(alt) = node.attribute("alt").filter(|a| !a.trim().is_empty()) {
+            let style = self.base_style();
+            self.text(alt, &style);
+        }
+    }
+
+    fn text(&mut self, text: &str, style: &CharStyle) {
+        if self.flow.pre_depth > 0 {
+            if text.is_empty() {
+                return;
+            }
+            self.open_paragraph();
+            self.flush_pending_space();
+            self.run(&text.replace('\r', ""), style);
+            return;
+        }
+
+        // Collapse white space; leading space in a line is dropped
+        let mut out = String::new();
+        for c in text.chars() {
+            if matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0c') {
+                self.flow.pending_space = true;
+                continue;
+            }
+            if !self.flow.para_open {
+                self.open_paragraph();
+            }
+            if self.flow.pending_space && !out.is_empty() {
+                out.push(' ');
+            } else if self.flow.pending_space && !self.flow.at_line_start {
+                self.space_run();
+            }
+            self.flow.pending_space = false;
+            out.push(c);
+        }
+        if !out.is_empty() {
+            self.run(&out, style);
+        }
+    }
+
+    /// Write a space left over from the previous run before inline content.
+    fn flush_pending_space(&mut self) {
+        if self.flow.pending_space && !self.flow.at_line_start {
+            self.space_run();
+        }
+        self.flow.pending_space = false;
+    }
+
+    /// Space between elements, keeping the surrounding formatting.
+    fn space_run(&mut self) {
+        self.flow
+            .xml
+            .push_str("<w:r><w:t xml:space=\"preserve\"> </w:t></w:r>");
+    }
+
+    /// Write text with its character formatting. Line breaks and tabs, left
+    /// only in preformatted text, are kept.
+    fn run(&mut self, text: &str, style: &CharStyle) {
+        let props = self.run_properties(style);
+        self.flow.xml.push_str("<w:r>");
+        if !props.is_empty() {
+            self.flow.xml.push_str("<w:rPr>");
+            self.flow.xml.push_str(&props);
+            self.flow.xml.push_str("</w:rPr>");
+        }
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.flow.xml.push_str("<w:br/>");
+            }
+            for (j, part) in line.split('\t').enumerate() {
+                if j > 0 {
+                    self.flow.xml.push_str("<w:tab/>");
+                }
+                if !part.is_empty() {
+                    self.flow.xml.push_str("<w:t xml:space=\"preserve\">");
+                    self.flow.xml.push_str(&escape(part));
+                    self.flow.xml.push_str("</w:t>");
+                }
+            }
+        }
+        self.flow.xml.push_str("</w:r>");
+        self.flow.at_line_start = false;
+    }
+
+    /// The `w:rPr` contents for `style`, leaving out what the paragraph
+    /// style already has.
+    fn run_properties(&self, style: &CharStyle) -> String {
+        let ctx = self.current_block();
+        let mut props = String::new();
+        if style.family != FontFamily::Serif {
+            let font = font_name(style.family);
+            props.push_str(&format!(
+                "<w:rFonts w:ascii=\"{f}\" w:hAnsi=\"{f}\" w:cs=\"{f}\"/>",
+                f = font
+            ));
+        }
+        if style.bold && ctx.heading.is_none() {
+            props.push_str("<w:b/>");
+        } else if !style.bold && ctx.heading.is_some() {
+            props.push_str("<w:b w:val=\"0\"/>");
+        }
+        if style.italic {
+            props.push_str("<w:i/>");
+        }
+        if style.strike {
+            props.push_str("<w:strike/>");
+        }
+        if let Some((r, g, b)) = style.color.filter(|c| *c != (0, 0, 0)) {
+            props.push_str(&format!("<w:color w:val=\"{:02X}{:02X}{:02X}\"/>", r, g, b));
+        }
+        let size = (style.size * 2.0).round() as i32;
+        if size != (ctx.size * 2.0).round() as i32 {
+            props.push_str(&format!(
+                "<w:sz w:val=\"{s}\"/><w:szCs w:val=\"{s}\"/>",
+                s = size
+            ));
+        }
+        if style.underline {
+            props.push_str("<w:u w:val=\"single\"/>");
+        }
+        if style.superscript {
+            props.push_str("<w:vertAlign w:val=\"superscript\"/>");
+        } else if style.subscript {
+            props.push_str("<w:vertAlign w:val=\"subscript\"/>");
+        }
+        props
+    }
+
+    fn open_paragraph(&mut self) {
+        if self.flow.para_open {
+            return;
+        }
+        let ctx = self.current_block().clone();
+        let mut props = String::new();
+        let style = match ctx.heading {
+            Some(level) => Some(format!("Heading{}", level)),
+            None => ctx.style.map(str::to_string),
+        };
+        if let Some(style) = style {
+            props.push_str(&format!("<w:pStyle w:val=\"{}\"/>", style));
+        }
+        if self.flow.table_depth == 0 && std::mem::take(&mut self.flow.pending_page_break) {
+            props.push_str("<w:pageBreakBefore/>");
+        }
+        if let Some((num, level)) = self.flow.pending_marker.take() {
+            props.push_str(&format!(
+                "<w:numPr><w:ilvl w:val=\"{}\"/><w:numId w:val=\"{}\"/></w:numPr>",
+                level, num
+            ));
+        }
+        if std::mem::take(&mut self.flow.pending_border) {
+            props.push_str(
+                "<w:pBdr><w:bottom w:val=\"single\" w:sz=\"6\" w:space=\"1\" w:color=\"auto\"/></w:pBdr>",
+            );
+        }
+        if ctx.space_before != 0 || ctx.space_after != 0 {
+            props.push_str(&format!(
+                "<w:spacing w:before=\"{}\" w:after=\"{}\"/>",
+                ctx.space_before.max(0),
+                ctx.space_after.max(0)
+            ));
+        }
+        if ctx.left != 0 || ctx.right != 0 || ctx.first != 0 {
+            props.push_str(&format!(
+                "<w:ind w:left=\"{}\" w:right=\"{}\"",
+                ctx.left, ctx.right
+            ));
+            if ctx.first < 0 {
+                props.push_str(&format!(" w:hanging=\"{}\"/>", -ctx.first));
+            } else {
+                props.push_str(&format!(" w:firstLine=\"{}\"/>", ctx.first));
+            }
+        }
+        if let Some(align) = ctx.align {
+            props.push_str(&format!("<w:jc w:val=\"{}\"/>", align));
+        }
+
+        self.flow.xml.push_str("<w:p>");
+        if !props.is_empty() {
+            self.flow.xml.push_str("<w:pPr>");
+            self.flow.xml.push_str(&props);
+            self.flow.xml.push_str("</w:pPr>");
+        }
+        if std::mem::take(&mut self.flow.footnote_ref) {
+            self.flow.xml.push_str(
+                "<w:r><w:rPr><w:rStyle w:val=\"FootnoteReference\"/></w:rPr><w:footnoteRef/></w:r>\
+                 <w:r><w:t xml:space=\"preserve\"> </w:t></w:r>",
+            );
+        }
+        self.flow.para_open = true;
+        self.flow.at_line_start = true;
+        self.flow.pending_space = false;
+        self.flow.has_content = true;
+        self.flush_bookmarks();
+        if let Some(start) = &self.flow.hyperlink {
+            self.flow.xml.push_str(start);
+        }
+    }
+
+    fn close_paragraph(&mut self) {
+        if self.flow.para_open {
+            if self.flow.hyperlink.is_some() {
+                self.flow.xml.push_str("</w:hyperlink>");
+            }
+            self.flow.xml.push_str("</w:p>");
+            self.flow.para_open = false;
+        }
+        self.flow.at_line_start = true;
+        self.flow.pending_space = false;
+    }
+
+    fn flush_bookmarks(&mut self) {
+        for key in std::mem::take(&mut self.flow.pending_bookmarks) {
+            let name = self.bookmark(&key);
+            if !self.placed_bookmarks.insert(name.clone()) {
+                continue;
+            }
+            let id = self.placed_bookmarks.len();
+            self.flow.xml.push_str(&format!(
+                "<w:bookmarkStart w:id=\"{id}\" w:name=\"{name}\"/><w:bookmarkEnd w:id=\"{id}\"/>",
+                id = id,
+                name = name
+            ));
+        }
+    }
+
+    /// The bookmark name for `href` or `href#id`.
+    fn bookmark(&mut self, key: &str) -> String {
+        let next = self.bookmarks.len() + 1;
+        self.bookmarks
+            .entry(key.to_string())
+            .or_insert_with(|| format!("bm{}", next))
+            .clone()
+    }
+
+    fn relationship(&mut self, kind: &'static str, target: &str, external: bool) -> String {
+        // rId1-rId4 are the styles, settings, numbering and footnotes
+        let id = format!("rId{}", self.rels.len() + 5);
+        self.rels.push(Relationship {
+            id: id.clone(),
+            kind,
+            target: target.to_string(),
+            external,
+        });
+        id
+    }
+
+    fn part_relationships(&self, document: bool) -> String {
+        let mut xml = format!("{}<Relationships xmlns=\"{}\">", XML_DECL, PKG_RELS_NS);
+        if document {
+            let mut parts = vec![
+                ("rId1", "styles", "styles.xml"),
+                ("rId2", "settings", "settings.xml"),
+            ];
+            if !self.lists.is_empty() {
+                parts.push(("rId3", "numbering", "numbering.xml"));
+            }
+            if !self.footnotes.is_empty() {
+                parts.push(("rId4", "footnotes", "footnotes.xml"));
+            }
+            for (id, kind, target) in parts {
+                xml.push_str(&format!(
+                    "<Relationship Id=\"{}\" Type=\"{}/{}\" Target=\"{}\"/>",
+                    id,
+                    DOCXNamespaces::R,
+                    kind,
+                    target
+                ));
+            }
+        }
+        for rel in &self.rels {
+            xml.push_str(&format!(
+                "<Relationship Id=\"{}\" Type=\"{}/{}\" Target=\"{}\"{}/>",
+                rel.id,
+                DOCXNamespaces::R,
+                rel.kind,
+                escape(&rel.target),
+                if rel.external {
+                    " TargetMode=\"External\""
+                } else {
+                    ""
+                }
+            ));
+        }
+        xml.push_str("</Relationships>");
+        xml
+    }
+
+    fn content_types(&self) -> String {
+        let mut xml = format!(
+            "{}<Types xmlns=\"{}\">\
+             <Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>\
+             <Default Extension=\"xml\" ContentType=\"application/xml\"/>\
+             <Default Extension=\"png\" ContentType=\"image/png\"/>\
+             <Default Extension=\"jpeg\" ContentType=\"image/jpeg\"/>\
+             <Default Extension=\"gif\" ContentType=\"image/gif\"/>",
+            XML_DECL, CONTENT_TYPES_NS
+        );
+        let mut parts = vec![
+            (
+                "/word/document.xml",
+                format!("{}.document.main+xml", WML_TYPE),
+            ),
+            ("/word/styles.xml", format!("{}.styles+xml", WML_TYPE)),
+            ("/word/settings.xml", format!("{}.settings+xml", WML_TYPE)),
+        ];
+        if !self.lists.is_empty() {
+            parts.push(("/word/numbering.xml", format!("{}.numbering+xml", WML_TYPE)));
+        }
+        if !self.footnotes.is_empty() {
+            parts.push(("/word/footnotes.xml", format!("{}.footnotes+xml", WML_TYPE)));
+        }
+        parts.push((
+            "/docProps/core.xml",
+            "application/vnd.openxmlformats-package.core-properties+xml".to_string(),
+        ));
+        parts.push((
+            "/docProps/app.xml",
+            "application/vnd.openxmlformats-officedocument.extended-properties+xml".to_string(),
+        ));
+        for (name, content_type) in parts {
+            xml.push_str(&format!(
+                "<Override PartName=\"{}\" ContentType=\"{}\"/>",
+                name, content_type
+            ));
+        }
+        xml.push_str("</Types>");
+        xml
+    }
+
+    /// Normal from the base font size of the `Stylizer`, headings with the
+    /// sizes the RTF writer uses, and the styles for footnotes, the table of
+    /// contents and tables.
+    fn styles(&self, mi: &MetaInformation) -> String {
+        let size = (self.stylizer.font_base * 2.0).round() as i32;
+        let lang = mi
+            .languages
+            .iter()
+            .find(|l| l.as_str() != "und")
+            .map(|l| format!("<w:lang w:val=\"{}\"/>", escape(l)))
+            .unwrap_or_default();
+        let mut xml = format!(
+            "{decl}<w:styles xmlns:w=\"{w}\"><w:docDefaults><w:rPrDefault><w:rPr>\
+             <w:rFonts w:ascii=\"{f}\" w:eastAsia=\"{f}\" w:hAnsi=\"{f}\" w:cs=\"{f}\"/>\
+             <w:sz w:val=\"{s}\"/><w:szCs w:val=\"{s}\"/>{lang}</w:rPr></w:rPrDefault>\
+             <w:pPrDefault><w:pPr><w:spacing w:after=\"0\" w:line=\"240\" w:lineRule=\"auto\"/></w:pPr></w:pPrDefault>\
+             </w:docDefaults>\
+             <w:style w:type=\"paragraph\" w:default=\"1\" w:styleId=\"Normal\"><w:name w:val=\"Normal\"/><w:qFormat/></w:style>\
+             <w:style w:type=\"character\" w:default=\"1\" w:styleId=\"DefaultParagraphFont\"><w:name w:val=\"Default Paragraph Font\"/>\
+             <w:uiPriority w:val=\"1\"/><w:semiHidden/><w:unhideWhenUsed/></w:style>\
+             <w:style w:type=\"table\" w:default=\"1\" w:styleId=\"TableNormal\"><w:name w:val=\"Normal Table\"/>\
+             <w:uiPriority w:val=\"99\"/><w:semiHidden/><w:unhideWhenUsed/><w:tblPr><w:tblInd w:w=\"0\" w:type=\"dxa\"/>\
+             <w:tblCellMar><w:top w:w=\"0\" w:type=\"dxa\"/><w:left w:w=\"108\" w:type=\"dxa\"/>\
+             <w:bottom w:w=\"0\" w:type=\"dxa\"/><w:right w:w=\"108\" w:type=\"dxa\"/></w:tblCellMar></w:tblPr></w:style>\
+             <w:style w:type=\"table\" w:styleId=\"TableGrid\"><w:name w:val=\"Table Grid\"/><w:basedOn w:val=\"TableNormal\"/>\
+             <w:uiPriority w:val=\"39\"/><w:tblPr><w:tblBorders>",
+            decl = XML_DECL,
+            w = DOCXNamespaces::W,
+            f = font_name(FontFamily::Serif),
+            s = size,
+            lang = lang
+        );
+        for edge in ["top", "left", "bottom", "right", "insideH", "insideV"] {
+            xml.push_str(&format!(
+                "<w:{} w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"auto\"/>",
+                edge
+            ));
+        }
+        xml.push_str("</w:tblBorders></w:tblPr></w:style>");
+
+        for (i, size) in HEADING_SIZES.iter().enumerate() {
+            xml.push_str(&format!(
+                "<w:style w:type=\"paragraph\" w:styleId=\"Heading{n}\"><w:name w:val=\"heading {n}\"/>\
+                 <w:basedOn w:val=\"Normal\"/><w:next w:val=\"Normal\"/><w:uiPriority w:val=\"9\"/><w:qFormat/>\
+                 <w:pPr><w:keepNext/><w:spacing w:before=\"240\" w:after=\"120\"/><w:outlineLvl w:val=\"{lvl}\"/></w:pPr>\
+                 <w:rPr><w:b/><w:bCs/><w:sz w:val=\"{s}\"/><w:szCs w:val=\"{s}\"/></w:rPr></w:style>",
+                n = i + 1,
+                lvl = i,
+                s = (size * 2.0).round() as i32
+            ));
+        }
+
+        xml.push_str(&format!(
+            "<w:style w:type=\"paragraph\" w:styleId=\"FootnoteText\"><w:name w:val=\"footnote text\"/>\
+             <w:basedOn w:val=\"Normal\"/><w:uiPriority w:val=\"99\"/><w:unhideWhenUsed/>\
+             <w:rPr><w:sz w:val=\"{s}\"/><w:szCs w:val=\"{s}\"/></w:rPr></w:style>\
+             <w:style w:type=\"character\" w:styleId=\"FootnoteReference\"><w:name w:val=\"footnote reference\"/>\
+             <w:basedOn w:val=\"DefaultParagraphFont\"/><w:uiPriority w:val=\"99\"/><w:unhideWhenUsed/>\
+             <w:rPr><w:vertAlign w:val=\"superscript\"/></w:rPr></w:style>\
+             <w:style w:type=\"paragraph\" w:styleId=\"TOCHeading\"><w:name w:val=\"TOC Heading\"/>\
+             <w:basedOn w:val=\"Heading1\"/><w:next w:val=\"Normal\"/><w:uiPriority w:val=\"39\"/><w:unhideWhenUsed/><w:qFormat/>\
+             <w:pPr><w:outlineLvl w:val=\"9\"/></w:pPr></w:style>",
+            s = (FOOTNOTE_SIZE * 2.0).round() as i32
+        ));
+        for level in 1..=3 {
+            xml.push_str(&format!(
+                "<w:style w:type=\"paragraph\" w:styleId=\"TOC{n}\"><w:name w:val=\"toc {n}\"/>\
+                 <w:basedOn w:val=\"Normal\"/><w:next w:val=\"Normal\"/><w:uiPriority w:val=\"39\"/><w:unhideWhenUsed/>\
+                 <w:pPr><w:spacing w:after=\"100\"/><w:ind w:left=\"{ind}\"/></w:pPr></w:style>",
+                n = level,
+                ind = (level - 1) * 240
+            ));
+        }
+        xml.push_str("</w:styles>");
+        xml
+    }
+
+    fn settings(&self) -> String {
+        let footnotes = if self.footnotes.is_empty() {
+            ""
+        } else {
+            "<w:footnotePr><w:footnote w:id=\"-1\"/><w:footnote w:id=\"0\"/></w:footnotePr>"
+        };
+        format!(
+            "{}<w:settings xmlns:w=\"{}\"><w:defaultTabStop w:val=\"{}\"/>\
+             <w:characterSpacingControl w:val=\"doNotCompress\"/>{}\
+             <w:compat><w:compatSetting w:name=\"compatibilityMode\" w:uri=\"http://schemas.microsoft.com/office/word\" w:val=\"15\"/></w:compat>\
+             </w:settings>",
+            XML_DECL,
+            DOCXNamespaces::W,
+            INDENT,
+            footnotes
+        )
+    }
+
+    fn numbering(&self) -> String {
+        let mut xml = format!(
+            "{}<w:numbering xmlns:w=\"{}\">",
+            XML_DECL,
+            DOCXNamespaces::W
+        );
+        for (i, def) in self.lists.iter().enumerate() {
+            xml.push_str(&format!(
+                "<w:abstractNum w:abstractNumId=\"{}\"><w:multiLevelType w:val=\"hybridMultilevel\"/>",
+                i
+            ));
+            for (level, format) in def.levels.iter().enumerate() {
+                let format = format.unwrap_or("bullet");
+                let text = match format {
+                    "bullet" => BULLETS[level % BULLETS.len()].to_string(),
+                    "none" => String::new(),
+                    _ => format!("%{}.", level + 1),
+                };
+                xml.push_str(&format!(
+                    "<w:lvl w:ilvl=\"{lvl}\"><w:start w:val=\"{start}\"/><w:numFmt w:val=\"{fmt}\"/>\
+                     <w:lvlText w:val=\"{text}\"/><w:lvlJc w:val=\"left\"/>\
+                     <w:pPr><w:ind w:left=\"{ind}\" w:hanging=\"{hang}\"/></w:pPr></w:lvl>",
+                    lvl = level,
+                    start = if format == "bullet" { 1 } else { def.start },
+                    fmt = format,
+                    text = text,
+                    ind = INDENT * (level as i32 + 1),
+                    hang = INDENT / 2
+                ));
+            }
+            xml.push_str("</w:abstractNum>");
+        }
+        for i in 0..self.lists.len() {
+            xml.push_str(&format!(
+                "<w:num w:numId=\"{}\"><w:abstractNumId w:val=\"{}\"/></w:num>",
+                i + 1,
+                i
+            ));
+        }
+        xml.push_str("</w:numbering>");
+        xml
+    }
+
+    fn footnotes_xml(&self) -> String {
+        let mut xml = format!(
+            "{}<w:footnotes {}>\
+             <w:footnote w:type=\"separator\" w:id=\"-1\"><w:p><w:r><w:separator/></w:r></w:p></w:footnote>\
+             <w:footnote w:type=\"continuationSeparator\" w:id=\"0\"><w:p><w:r><w:continuationSeparator/></w:r></w:p></w:footnote>",
+            XML_DECL,
+            namespaces()
+        );
+        for (i, note) in self.footnotes.iter().enumerate() {
+            xml.push_str(&format!(
+                "<w:footnote w:id=\"{}\">{}</w:footnote>",
+                i + 1,
+                note
+            ));
+        }
+        xml.push_str("</w:footnotes>");
+        xml
+    }
+}
+
+fn xml_options() -> ParsingOptions {
+    ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    }
+}
+
+/// Namespace declarations of the parts holding paragraphs.
+fn namespaces() -> String {
+    format!(
+        "xmlns:w=\"{}\" xmlns:r=\"{}\" xmlns:wp=\"{}\" xmlns:a=\"{}\" xmlns:pic=\"{}\"",
+        DOCXNamespaces::W,
+        DOCXNamespaces::R,
+        WP_NS,
+        A_NS,
+        PIC_NS
+    )
+}
+
+/// The font used for a generic family.
+fn font_name(family: FontFamily) -> &'static str {
+    match family {
+        FontFamily::Serif => "Times New Roman",
+        FontFamily::SansSerif => "Arial",
+        FontFamily::Monospace => "Courier New",
+    }
+}
+
+fn twips(points: f32) -> i32 {
+    (points * 20.0).round() as i32
+}
+
+/// Whether `node` links to a footnote, as marked in EPUB 3 or by ARIA.
+fn is_noteref(node: Node) -> bool {
+    node.attribute((OPS_NS, "type"))
+        .map(|t| t.split_whitespace().any(|t| t == "noteref"))
+        .unwrap_or(false)
+        || node.attribute("role") == Some("doc-noteref")
+}
+
+/// The WordprocessingML number format of a list level.
+fn number_format(node: Node, css: &Style, ordered: bool) -> &'static str {
+    let declared = css
+        .get_inline_style("list-style-type")
+        .or_else(|| css.get_inline_style("list-style"))
+        .unwrap_or_default();
+    for word in declared.split_whitespace() {
+        let format = match word {
+            "disc" | "circle" | "square" => "bullet",
+            "decimal" | "decimal-leading-zero" => "decimal",
+            "lower-alpha" | "lower-latin" => "lowerLetter",
+            "upper-alpha" | "upper-latin" => "upperLetter",
+            "lower-roman" => "lowerRoman",
+            "upper-roman" => "upperRoman",
+            "none" => "none",
+            _ => continue,
+        };
+        return format;
+    }
+    match (ordered, node.attribute("type").map(str::trim)) {
+        (true, Some("a")) => "lowerLetter",
+        (true, Some("A")) => "upperLetter",
+        (true, Some("i")) => "lowerRoman",
+        (true, Some("I")) => "upperRoman",
+        (true, _) => "decimal",
+        (false, _) => "bullet",
+    }
+}
+
+/// The cells of a table row with their column spans.
+fn table_cells<'a, 'input>(row: Node<'a, 'input>) -> Vec<(Node<'a, 'input>, usize)> {
+    row.children()
+        .filter(|c| {
+            c.is_element()
+                && matches!(
+                    c.tag_name().name().to_ascii_lowercase().as_str(),
+                    "td" | "th"
+                )
+        })
+        .map(|c| {
+            let span = c
+                .attribute("colspan")
+                .and_then(|s| s.trim().parse::<usize>().ok())
+                .unwrap_or(1)
+                .clamp(1, 63);
+            (c, span)
+        })
+        .collect()
+}
+
+fn relationships(rels: &[(&str, &str, &str)]) -> String {
+    let mut xml = format!("{}<Relationships xmlns=\"{}\">", XML_DECL, PKG_RELS_NS);
+    for (id, kind, target) in rels {
+        xml.push_str(&format!(
+            "<Relationship Id=\"{}\" Type=\"{}\" Target=\"{}\"/>",
+            id, kind, target
+        ));
+    }
+    xml.push_str("</Relationships>");
+    xml
+}
+
+fn core_properties(mi: &MetaInformation) -> String {
+    let mut xml = format!(
+        "{}<cp:coreProperties xmlns:cp=\"{}\" xmlns:dc=\"{}\" xmlns:dcterms=\"{}\" \
+         xmlns:dcmitype=\"http://purl.org/dc/dcmitype/\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\">",
+        XML_DECL,
+        DOCXNamespaces::CP,
+        DOCXNamespaces::DC,
+        DOCXNamespaces::DCTERMS
+    );
+    let mut add = |tag: &str, value: &str| {
+        if !value.trim().is_empty() {
+            xml.push_str(&format!("<{t}>{}</{t}>", escape(value.trim()), t = tag));
+        }
+    };
+    add("dc:title", &mi.title);
+    add("dc:creator", &mi.authors.join(" & "));
+    add("dc:description", mi.comments.as_deref().unwrap_or(""));
+    add("cp:keywords", &mi.tags.join(", "));
+    add(
+        "dc:language",
+        mi.languages
+            .iter()
+            .find(|l| l.as_str() != "und")
+            .map(|l| l.as_str())
+            .unwrap_or(""),
+    );
+    add("dc:identifier", mi.uuid.as_deref().unwrap_or(""));
+    let now = Utc::now();
+    for (tag, date) in [
+        ("dcterms:created", mi.timestamp.unwrap_or(now)),
+        ("dcterms:modified", now),
+    ] {
+        xml.push_str(&format!(
+            "<{t} xsi:type=\"dcterms:W3CDTF\">{}</{t}>",
+            date.format("%Y-%m-%dT%H:%M:%SZ"),
+            t = tag
+        ));
+    }
+    xml.push_str("</cp:coreProperties>");
+    xml
+}
+
+fn app_properties(mi: &MetaInformation) -> String {
+    let company = mi
+        .publisher
+        .as_deref()
+        .filter(|p| !p.trim().is_empty())
+        .map(|p| format!("<Company>{}</Company>", escape(p.trim())))
+        .unwrap_or_default();
+    format!(
+        "{}<Properties xmlns=\"http://schemas.openxmlformats.org/officeDocument/2006/extended-properties\">\
+         <Application>calibre</Application>{}</Properties>",
+        XML_DECL, company
+    )
+}
+
+/// Escape text for XML, dropping the control characters XML cannot hold.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::input::pdf_input::PDFInput;
 use crate::oeb::book::OEBBook;
 use anyhow::{bail, Context, Result};
@@ -60,3 +61,21 @@ impl AZW4Input {
         bail!("No embedded PDF found in AZW4 container")
     }
 }
+
+impl InputFormatPlugin for AZW4Input {
+    fn name(&self) -> &str {
+        "AZW4 Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["azw4"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-azw4"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        AZW4Input::convert(self, input_path, output_dir)
+    }
+}
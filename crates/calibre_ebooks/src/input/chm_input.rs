@@ -1,8 +1,15 @@
+use crate::chm::reader::ChmReader;
+use crate::chm::sitemap::{parse_sitemap, split_local, SitemapEntry};
+use crate::conversion::registry::InputFormatPlugin;
+use crate::metadata::chm::get_metadata_from_reader;
 use crate::oeb::book::OEBBook;
+use crate::oeb::constants::XHTML_MIME;
 use crate::oeb::container::DirContainer;
-use crate::oeb::manifest::ManifestItem;
-use anyhow::Result;
+use crate::oeb::toc::TOCNode;
+use anyhow::{bail, Context, Result};
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::io::{BufReader, Read, Seek};
 use std::path::Path;
 
 pub struct CHMInput;
@@ -13,50 +20,201 @@ impl CHMInput {
     }
 
     pub fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
-        // CHM (Compiled HTML Help) requires complex binary parsing (LZX/ITSS).
-        // This is a placeholder implementation.
-        // A full implementation would need a CHM crate (e.g., chm-rs if it existed/was mature)
-        // or binding to a C library.
-
-        // For now, we return a "not supported" page, similar to TCR.
+        let file = fs::File::open(input_path).context("Failed to open CHM file")?;
+        let mut chm = ChmReader::new(BufReader::new(file)).context("Failed to parse CHM file")?;
 
         fs::create_dir_all(output_dir)?;
-        let content_filename = "index.html";
-        let content_path = output_dir.join(content_filename);
-
-        let html_content = format!(
-            "<html><body><h1>CHM Content Not Supported Yet</h1><p>The CHM file '{}' cannot be fully converted yet.</p></body></html>",
-            input_path.file_name().unwrap_or_default().to_string_lossy()
-        );
-        fs::write(&content_path, html_content)?;
-
-        // Build OEBBook
         let container = Box::new(DirContainer::new(output_dir));
         let mut book = OEBBook::new(container);
 
-        let id = "content".to_string();
-        let href = content_filename.to_string();
+        // Extract every content file. Links in help files often differ in
+        // case from the directory, so files are looked up by lowercased href.
+        let mut files: HashMap<String, (String, String)> = HashMap::new();
+        let mut html_ids = Vec::new();
+        let paths: Vec<String> = chm
+            .entries()
+            .iter()
+            .filter(|e| e.is_normal())
+            .map(|e| e.path.clone())
+            .collect();
+        for (i, path) in paths.iter().enumerate() {
+            let href = clean_path(path);
+            let ext = Path::new(&href)
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            if href.is_empty() || ext == "hhc" || ext == "hhk" {
+                continue;
+            }
+            // A damaged file is left out rather than failing the whole book
+            let Ok(data) = chm.get_file(path) else {
+                continue;
+            };
+            let dest = output_dir.join(&href);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(dest, data)?;
 
-        book.manifest.items.insert(
-            id.clone(),
-            ManifestItem::new(&id, &href, "application/xhtml+xml"),
-        );
-        book.manifest.hrefs.insert(href.clone(), id.clone());
-        book.spine.add(&id, true);
+            let id = format!("item{}", i + 1);
+            let media_type = if matches!(ext.as_str(), "htm" | "html" | "xhtml") {
+                html_ids.push(id.clone());
+                XHTML_MIME.to_string()
+            } else {
+                mime_guess::from_path(&href)
+                    .first_or_octet_stream()
+                    .to_string()
+            };
+            book.manifest.add(&id, &href, &media_type);
+            files.insert(href.to_lowercase(), (id, href));
+        }
 
-        // Try to get metadata using our metadata module if possible
-        // if let Ok(file) = fs::File::open(input_path) {
-        //     if let Ok(info) = crate::metadata::chm::get_metadata(file) {
-        //         if !info.title.is_empty() {
-        //             book.metadata.add("title", &info.title);
-        //         }
-        //     }
-        // }
+        let resolve = |local: &str| {
+            let (path, fragment) = split_local(local)?;
+            let (id, href) = files.get(&clean_path(&path).to_lowercase()).or_else(|| {
+                let decoded = urlencoding::decode(&path).ok()?;
+                files.get(&clean_path(&decoded).to_lowercase())
+            })?;
+            let href = match fragment {
+                Some(fragment) => format!("{}#{}", href, fragment),
+                None => href.clone(),
+            };
+            Some((id.clone(), href))
+        };
 
-        if book.metadata.get("title").is_empty() {
-            book.metadata.add("title", "Converted CHM");
+        // The topics file gives the table of contents and the reading order
+        let mut topics = Vec::new();
+        let contents_path = chm.contents_path();
+        if let Some(entries) = read_sitemap(&mut chm, contents_path)? {
+            let nodes: Vec<(usize, TOCNode)> = entries
+                .iter()
+                .map(|entry| {
+                    let target = entry.locals.first().and_then(|l| resolve(l));
+                    let title = match entry.name.as_str() {
+                        "" => "Unknown".to_string(),
+                        name => name.to_string(),
+                    };
+                    let href = target.map(|(id, href)| {
+                        topics.push(id);
+                        href
+                    });
+                    (entry.level, TOCNode::new(Some(title), href))
+                })
+                .collect();
+            for node in toc_nodes(&nodes) {
+                book.toc.root.add(node);
+            }
+        }
+        if topics.is_empty() {
+            if let Some((id, _)) = chm.home_path().and_then(|p| resolve(&p)) {
+                topics.push(id);
+            }
+        }
+        // Topics only reachable from the index follow those of the contents
+        let index_path = chm.index_path();
+        if let Some(entries) = read_sitemap(&mut chm, index_path)? {
+            for entry in &entries {
+                topics.extend(entry.locals.iter().filter_map(|l| resolve(l)).map(|t| t.0));
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for id in topics {
+            if html_ids.contains(&id) && seen.insert(id.clone()) {
+                book.spine.add(&id, true);
+            }
+        }
+        // Pages not listed anywhere can still be linked from the topics
+        for id in &html_ids {
+            if seen.insert(id.clone()) {
+                book.spine.add(id, false);
+            }
+        }
+        if book.spine.items.is_empty() {
+            bail!("No HTML topics found in CHM file");
+        }
+
+        if let Ok(mi) = get_metadata_from_reader(&mut chm) {
+            if mi.title != "Unknown" {
+                book.metadata.add("title", &mi.title);
+            }
+            for author in mi.authors.iter().filter(|a| *a != "Unknown") {
+                book.metadata.add("creator", author);
+            }
+            if let Some(isbn) = mi.identifiers.get("isbn") {
+                let mut attrib = HashMap::new();
+                attrib.insert("opf:scheme".to_string(), "ISBN".to_string());
+                book.metadata.add_with_attrib("identifier", isbn, attrib);
+            }
+        }
+        if book.metadata.first("title").is_none() {
+            let title = input_path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "Unknown".to_string());
+            book.metadata.add("title", &title);
         }
 
         Ok(book)
     }
 }
+
+/// The entries of a `.hhc` or `.hhk` file, if the CHM has one.
+fn read_sitemap<R: Read + Seek>(
+    chm: &mut ChmReader<R>,
+    path: Option<String>,
+) -> Result<Option<Vec<SitemapEntry>>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let raw = chm
+        .get_file(&path)
+        .with_context(|| format!("Failed to read {}", path))?;
+    Ok(Some(parse_sitemap(&chm.decode_text(&raw))))
+}
+
+/// The href of a file within the CHM: relative, without the `;<junk>`
+/// some names carry after the extension and with no way out of the book.
+fn clean_path(path: &str) -> String {
+    let path = path.split(';').next().unwrap_or_default();
+    path.split('/')
+        .filter(|part| !matches!(*part, "" | "." | ".."))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Nest each entry under the previous entry of a lower level.
+fn toc_nodes(entries: &[(usize, TOCNode)]) -> Vec<TOCNode> {
+    let mut nodes = Vec::new();
+    let mut i = 0;
+    while i < entries.len() {
+        let (level, node) = &entries[i];
+        let end = entries[i + 1..]
+            .iter()
+            .position(|(l, _)| l <= level)
+            .map_or(entries.len(), |p| i + 1 + p);
+        let mut node = node.clone();
+        node.children = toc_nodes(&entries[i + 1..end]);
+        nodes.push(node);
+        i = end;
+    }
+    nodes
+}
+
+impl InputFormatPlugin for CHMInput {
+    fn name(&self) -> &str {
+        "CHM Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["chm"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/vnd.ms-htmlhelp"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        CHMInput::convert(self, input_path, output_dir)
+    }
+}
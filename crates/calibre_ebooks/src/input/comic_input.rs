@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
 use crate::oeb::manifest::ManifestItem;
@@ -119,3 +120,21 @@ impl ComicInput {
         Ok(book)
     }
 }
+
+impl InputFormatPlugin for ComicInput {
+    fn name(&self) -> &str {
+        "Comic Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["cbz"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-cbz"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        ComicInput::convert(self, input_path, output_dir)
+    }
+}
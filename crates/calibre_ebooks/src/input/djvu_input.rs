@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
 use crate::oeb::manifest::ManifestItem;
@@ -44,3 +45,21 @@ impl DJVUInput {
         Ok(book)
     }
 }
+
+impl InputFormatPlugin for DJVUInput {
+    fn name(&self) -> &str {
+        "DJVU Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["djvu", "djv"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["image/vnd.djvu"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        DJVUInput::convert(self, input_path, output_dir)
+    }
+}
@@ -1,9 +1,11 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::docx::container::DOCX;
-use crate::docx::to_html::DOCXToHTML;
-use crate::input::html_input::HTMLInput;
+use crate::docx::to_html::{DOCXToHTML, Heading, STYLESHEET};
 use crate::oeb::book::OEBBook;
+use crate::oeb::container::DirContainer;
+use crate::oeb::toc::TOCNode;
 use anyhow::{Context, Result};
-use std::fs::File;
+use std::fs::{self, File};
 use std::path::Path;
 
 pub struct DOCXInput;
@@ -17,21 +19,85 @@ impl DOCXInput {
         let file = File::open(input_path).context("Failed to open DOCX file")?;
 
         let mut docx = DOCX::new(file).map_err(|e| anyhow::anyhow!("DOCX Error: {}", e))?;
+        fs::create_dir_all(output_dir)?;
 
-        // Create source dir for HTML output
-        let source_dir = output_dir.join("docx_source");
-        std::fs::create_dir_all(&source_dir)?;
-
-        let html_content = DOCXToHTML::convert(&mut docx, &source_dir)
+        let converted = DOCXToHTML::convert(&mut docx, output_dir)
             .map_err(|e| anyhow::anyhow!("Conversion Error: {}", e))?;
 
-        let index_path = source_dir.join("index.html");
-        std::fs::write(&index_path, html_content)?;
+        let content_filename = "index.html";
+        fs::write(output_dir.join(content_filename), &converted.html)?;
+        fs::write(output_dir.join(STYLESHEET), &converted.css)?;
+
+        let container = Box::new(DirContainer::new(output_dir));
+        let mut book = OEBBook::new(container);
+        book.manifest
+            .add("content", content_filename, "application/xhtml+xml");
+        book.manifest.add("css", STYLESHEET, "text/css");
+        for (i, href) in converted.images.iter().enumerate() {
+            let media_type = mime_guess::from_path(href)
+                .first_or_octet_stream()
+                .to_string();
+            book.manifest
+                .add(&format!("img{}", i + 1), href, &media_type);
+        }
+        book.spine.add("content", true);
+
+        // Metadata from the core properties
+        if let Ok(metadata) = docx.get_metadata() {
+            book.metadata = metadata;
+        }
+        if book.metadata.first("title").is_none() {
+            let stem = input_path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "Unknown".to_string());
+            book.metadata.add("title", &stem);
+        }
+
+        // Table of contents from the headings
+        for node in toc_nodes(&converted.headings, content_filename) {
+            book.toc.root.add(node);
+        }
+
+        Ok(book)
+    }
+}
+
+/// Nest each heading under the previous heading of a higher level.
+fn toc_nodes(headings: &[Heading], href: &str) -> Vec<TOCNode> {
+    let mut nodes = Vec::new();
+    let mut i = 0;
+    while i < headings.len() {
+        let heading = &headings[i];
+        let end = headings[i + 1..]
+            .iter()
+            .position(|h| h.level <= heading.level)
+            .map_or(headings.len(), |p| i + 1 + p);
+        let mut node = TOCNode::new(
+            Some(heading.title.clone()),
+            Some(format!("{}#{}", href, heading.id)),
+        );
+        node.children = toc_nodes(&headings[i + 1..end], href);
+        nodes.push(node);
+        i = end;
+    }
+    nodes
+}
+
+impl InputFormatPlugin for DOCXInput {
+    fn name(&self) -> &str {
+        "DOCX Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["docx"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/vnd.openxmlformats-officedocument.wordprocessingml.document"]
+    }
 
-        // Delegate to HTMLInput
-        // HTMLInput copies content to ITS output_dir.
-        // So we pass output_dir to HTMLInput.
-        let html_input = HTMLInput::new();
-        html_input.convert(&index_path, output_dir)
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        DOCXInput::convert(self, input_path, output_dir)
     }
 }
@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
 use crate::oeb::reader::OEBReader;
@@ -83,3 +84,21 @@ impl EPUBInput {
         Err(anyhow!("Could not find OPF path in container.xml"))
     }
 }
+
+impl InputFormatPlugin for EPUBInput {
+    fn name(&self) -> &str {
+        "EPUB Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["epub"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/epub+zip"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        EPUBInput::convert(self, input_path, output_dir)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
 use crate::oeb::manifest::ManifestItem;
@@ -213,3 +214,21 @@ impl FB2Input {
         Ok(())
     }
 }
+
+impl InputFormatPlugin for FB2Input {
+    fn name(&self) -> &str {
+        "FB2 Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["fb2"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-fictionbook+xml"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        FB2Input::convert(self, input_path, output_dir)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::html::input::traverse;
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
@@ -88,3 +89,21 @@ impl HTMLInput {
         Ok(book)
     }
 }
+
+impl InputFormatPlugin for HTMLInput {
+    fn name(&self) -> &str {
+        "HTML Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["html", "htm", "xhtml"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["text/html", "application/xhtml+xml"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        HTMLInput::convert(self, input_path, output_dir)
+    }
+}
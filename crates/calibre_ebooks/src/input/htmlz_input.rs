@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::input::html_input::HTMLInput;
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
@@ -106,3 +107,21 @@ impl HTMLZInput {
         None
     }
 }
+
+impl InputFormatPlugin for HTMLZInput {
+    fn name(&self) -> &str {
+        "HTMLZ Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["htmlz"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-htmlz"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        HTMLZInput::convert(self, input_path, output_dir)
+    }
+}
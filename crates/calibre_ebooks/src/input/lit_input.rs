@@ -1,7 +1,8 @@
-use crate::lit::reader::LitReader;
+use crate::conversion::registry::InputFormatPlugin;
+use crate::lit::reader::{LitReader, OPF_PATH};
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
-use crate::oeb::manifest::ManifestItem;
+use crate::oeb::reader::OEBReader;
 use anyhow::{Context, Result};
 use std::fs;
 use std::io::BufReader;
@@ -16,41 +17,53 @@ impl LitInput {
 
     pub fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
         let file = fs::File::open(input_path).context("Failed to open LIT file")?;
-        let reader = BufReader::new(file);
-        let mut lit_reader = LitReader::new(reader).context("Failed to parse LIT header")?;
+        let mut lit = LitReader::new(BufReader::new(file)).context("Failed to parse LIT file")?;
 
-        // Prepare Output
         fs::create_dir_all(output_dir)?;
 
-        // Extract Content
-        // Since extraction is limited, we might just write a placeholder or metadata.
-        let content = lit_reader.extract_content()?;
+        // Extract every manifest item, converting the binary XHTML back to text
+        for item in lit.manifest.clone() {
+            let data = lit
+                .read_item(&item)
+                .with_context(|| format!("Failed to extract {}", item.path))?;
+            let dest = output_dir.join(&item.path);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(dest, data)?;
+        }
+        fs::write(output_dir.join(OPF_PATH), lit.read_opf()?)?;
 
-        let content_filename = "content.html";
-        let content_path = output_dir.join(content_filename);
-        let html_content = format!(
-            "<html><body><h1>LIT Conversion</h1><p>{}</p></body></html>",
-            content
-        );
-        fs::write(&content_path, &html_content)?;
-
-        // Build OEBBook
         let container = Box::new(DirContainer::new(output_dir));
         let mut book = OEBBook::new(container);
+        OEBReader::new().read_opf(&mut book, OPF_PATH)?;
+
+        if book.metadata.first("title").is_none() {
+            let title = input_path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "Unknown".to_string());
+            book.metadata.add("title", &title);
+        }
+
+        Ok(book)
+    }
+}
 
-        // Add Content to Manifest & Spine
-        let id = "content".to_string();
-        let href = content_filename.to_string();
+impl InputFormatPlugin for LitInput {
+    fn name(&self) -> &str {
+        "LIT Input"
+    }
 
-        book.manifest.items.insert(
-            id.clone(),
-            ManifestItem::new(&id, &href, "application/xhtml+xml"),
-        );
-        book.manifest.hrefs.insert(href.clone(), id.clone());
-        book.spine.add(&id, true);
+    fn file_types(&self) -> &[&str] {
+        &["lit"]
+    }
 
-        book.metadata.add("title", "Converted LIT Book");
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-ms-reader"]
+    }
 
-        Ok(book)
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        LitInput::convert(self, input_path, output_dir)
     }
 }
@@ -1,7 +1,11 @@
+use crate::conversion::registry::InputFormatPlugin;
+use crate::lrf::document::LrfDocument;
+use crate::lrf::to_html::{LRFToHTML, STYLESHEET};
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
-use crate::oeb::manifest::ManifestItem;
+use crate::oeb::toc::TOCNode;
 use anyhow::{Context, Result};
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
@@ -13,42 +17,105 @@ impl LRFInput {
     }
 
     pub fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
-        // Just extract metadata and create a placeholder book.
-        // Full LRF content extraction is out of scope for this batch.
+        let data = fs::read(input_path).context("Failed to open LRF file")?;
+        let doc = LrfDocument::parse(&data).context("Failed to parse LRF file")?;
+        let converted = LRFToHTML::convert(&doc).context("Failed to convert LRF content")?;
 
         fs::create_dir_all(output_dir)?;
-        let content_filename = "index.html";
-        let content_path = output_dir.join(content_filename);
-
-        let html_content = "<html><body><h1>LRF Content Not Supported Yet</h1><p>The LRF format is a proprietary binary format. Content extraction is not yet implemented.</p></body></html>";
-        fs::write(&content_path, html_content)?;
-
-        // Build OEBBook
         let container = Box::new(DirContainer::new(output_dir));
         let mut book = OEBBook::new(container);
 
-        let id = "content".to_string();
-        let href = content_filename.to_string();
+        for (href, html) in &converted.pages {
+            fs::write(output_dir.join(href), html)?;
+            let id = format!("page{}", href.trim_end_matches(".xhtml"));
+            book.manifest.add(&id, href, "application/xhtml+xml");
+            book.spine.add(&id, true);
+        }
+        fs::write(output_dir.join(STYLESHEET), &converted.css)?;
+        book.manifest.add("styles", STYLESHEET, "text/css");
+        for (i, (href, data)) in converted.resources.iter().enumerate() {
+            fs::write(output_dir.join(href), data)?;
+            let media_type = mime_guess::from_path(href)
+                .first_or_octet_stream()
+                .to_string();
+            book.manifest
+                .add(&format!("res{}", i + 1), href, &media_type);
+        }
 
-        book.manifest.items.insert(
-            id.clone(),
-            ManifestItem::new(&id, &href, "application/xhtml+xml"),
-        );
-        book.manifest.hrefs.insert(href.clone(), id.clone());
-        book.spine.add(&id, true);
-
-        // Metadata
-        if let Ok(info) = crate::metadata::lrf::get_metadata(fs::File::open(input_path)?) {
-            book.metadata.add("title", &info.title);
-            if !info.authors.is_empty() {
-                book.metadata.add("creator", &info.authors[0]);
-            }
+        for (label, href) in converted.toc {
+            book.toc.root.add(TOCNode::new(Some(label), Some(href)));
         }
 
-        if book.metadata.get("title").is_empty() {
-            book.metadata.add("title", "Converted LRF");
+        add_metadata(&mut book, &doc);
+        if book.metadata.first("title").is_none() {
+            let title = input_path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "Unknown".to_string());
+            book.metadata.add("title", &title);
         }
 
         Ok(book)
     }
 }
+
+/// Map the LRF book and document information onto OPF metadata.
+fn add_metadata(book: &mut OEBBook, doc: &LrfDocument) {
+    let info = &doc.info;
+    let with_file_as = |reading: &str, role: Option<&str>| {
+        let mut attrib = HashMap::new();
+        if !reading.is_empty() {
+            attrib.insert("opf:file-as".to_string(), reading.to_string());
+        }
+        if let Some(role) = role {
+            attrib.insert("opf:role".to_string(), role.to_string());
+        }
+        attrib
+    };
+    if !info.title.is_empty() {
+        book.metadata.add_with_attrib(
+            "title",
+            &info.title,
+            with_file_as(&info.title_reading, None),
+        );
+    }
+    if !info.author.is_empty() {
+        book.metadata.add_with_attrib(
+            "creator",
+            &info.author,
+            with_file_as(&info.author_reading, Some("aut")),
+        );
+    }
+    if !info.publisher.is_empty() {
+        book.metadata.add("publisher", &info.publisher);
+    }
+    for subject in [&info.category, &info.classification] {
+        if !subject.is_empty() {
+            book.metadata.add("subject", subject);
+        }
+    }
+    if !info.free_text.is_empty() {
+        book.metadata.add("description", &info.free_text);
+    }
+    if !info.language.is_empty() {
+        book.metadata.add("language", &info.language);
+    }
+}
+
+impl InputFormatPlugin for LRFInput {
+    fn name(&self) -> &str {
+        "LRF Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["lrf"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-sony-bbeb"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        LRFInput::convert(self, input_path, output_dir)
+    }
+}
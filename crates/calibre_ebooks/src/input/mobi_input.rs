@@ -1,3 +1,6 @@
+use crate::conversion::registry::InputFormatPlugin;
+use crate::mobi::mobi6;
+use crate::mobi::mobi8::Mobi8Reader;
 use crate::mobi::reader::MobiReader;
 use crate::oeb::book::OEBBook;
 use anyhow::Result;
@@ -11,6 +14,12 @@ impl MOBIInput {
     }
 
     pub fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        // KF8 books (AZW3 and joint MOBI files) keep their original structure
+        let kf8 = mobi6::MobiReader::new(std::fs::File::open(input_path)?)?;
+        if kf8.kf8_type.is_some() {
+            return Mobi8Reader::new(&kf8)?.extract(output_dir);
+        }
+
         println!("Reading MOBI...");
         let mut reader = MobiReader::new(input_path)?;
         let pdb_header = &reader.pdb_header;
@@ -80,3 +89,24 @@ impl MOBIInput {
         Ok(book)
     }
 }
+
+impl InputFormatPlugin for MOBIInput {
+    fn name(&self) -> &str {
+        "MOBI Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["mobi", "azw", "azw3", "prc"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &[
+            "application/x-mobipocket-ebook",
+            "application/vnd.amazon.mobi8-ebook",
+        ]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        MOBIInput::convert(self, input_path, output_dir)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
 use crate::oeb::manifest::ManifestItem;
@@ -79,3 +80,21 @@ impl ODTInput {
         Ok(book)
     }
 }
+
+impl InputFormatPlugin for ODTInput {
+    fn name(&self) -> &str {
+        "ODT Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["odt"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/vnd.oasis.opendocument.text"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        ODTInput::convert(self, input_path, output_dir)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
 use crate::oeb::manifest::ManifestItem;
@@ -75,3 +76,21 @@ impl PDBInput {
         Ok(book)
     }
 }
+
+impl InputFormatPlugin for PDBInput {
+    fn name(&self) -> &str {
+        "PDB Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["pdb"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/vnd.palm"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        PDBInput::convert(self, input_path, output_dir)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
 use crate::oeb::manifest::ManifestItem;
@@ -121,3 +122,21 @@ impl PDFInput {
         }
     }
 }
+
+impl InputFormatPlugin for PDFInput {
+    fn name(&self) -> &str {
+        "PDF Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["pdf"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/pdf"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        PDFInput::convert(self, input_path, output_dir)
+    }
+}
@@ -1,4 +1,5 @@
 use crate::compression::palmdoc::decompress;
+use crate::conversion::registry::InputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
 use crate::pdb::reader::PdbReader;
@@ -185,3 +186,21 @@ impl PMLInput {
         html
     }
 }
+
+impl InputFormatPlugin for PMLInput {
+    fn name(&self) -> &str {
+        "PML Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["pml"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["text/x-pml"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        PMLInput::convert(self, input_path, output_dir)
+    }
+}
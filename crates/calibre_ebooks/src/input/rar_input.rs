@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
 use crate::oeb::manifest::ManifestItem;
@@ -44,3 +45,21 @@ impl RARInput {
         Ok(book)
     }
 }
+
+impl InputFormatPlugin for RARInput {
+    fn name(&self) -> &str {
+        "RAR Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["rar"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-rar-compressed"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        RARInput::convert(self, input_path, output_dir)
+    }
+}
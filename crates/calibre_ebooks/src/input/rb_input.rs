@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
 use crate::oeb::manifest::ManifestItem;
@@ -56,3 +57,21 @@ impl RBInput {
         Ok(book)
     }
 }
+
+impl InputFormatPlugin for RBInput {
+    fn name(&self) -> &str {
+        "RB Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["rb"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-rocketbook"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        RBInput::convert(self, input_path, output_dir)
+    }
+}
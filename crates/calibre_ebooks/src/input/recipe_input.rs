@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
 use crate::oeb::manifest::ManifestItem;
@@ -44,3 +45,21 @@ impl RecipeInput {
         Ok(book)
     }
 }
+
+impl InputFormatPlugin for RecipeInput {
+    fn name(&self) -> &str {
+        "Recipe Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["recipe"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-calibre-recipe"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        RecipeInput::convert(self, input_path, output_dir)
+    }
+}
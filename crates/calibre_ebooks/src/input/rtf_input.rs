@@ -1,9 +1,11 @@
+use crate::conversion::registry::InputFormatPlugin;
+use crate::mobi::langcodes::mobi2iana;
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
-use crate::oeb::manifest::ManifestItem;
+use crate::rtf::reader::RtfDocument;
+use crate::rtf::to_html::RTFToHTML;
 use anyhow::{Context, Result};
 use std::fs;
-use std::io::Read;
 use std::path::Path;
 
 pub struct RTFInput;
@@ -14,70 +16,110 @@ impl RTFInput {
     }
 
     pub fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
-        // Read RTF content
-        let mut file = fs::File::open(input_path).context("Failed to open RTF file")?;
-        let mut content = String::new();
-        // RTF is 7-bit ASCII usually, but can contain others. Reading as string is a safe bet for basic ones.
-        // If it fails UTF-8, we might need lossy.
-        file.read_to_string(&mut content).or_else(|_| {
-            let mut file = fs::File::open(input_path)?;
-            let mut buffer = Vec::new();
-            file.read_to_end(&mut buffer)?;
-            content = String::from_utf8_lossy(&buffer).to_string();
-            Ok::<usize, anyhow::Error>(buffer.len())
-        })?;
-
-        // 1. Basic Metadata extraction (Reuse existing logic if possible, or simple regex)
-        // For now, we will rely on plumber to run metadata extraction separately if needed,
-        // or just set basic title.
-
+        let data = fs::read(input_path).context("Failed to open RTF file")?;
         fs::create_dir_all(output_dir)?;
 
-        let html_content = self.rtf_to_html(&content);
-        let content_filename = "index.html";
-        let content_path = output_dir.join(content_filename);
-        fs::write(&content_path, &html_content)?;
-
-        // Build OEBBook
         let container = Box::new(DirContainer::new(output_dir));
         let mut book = OEBBook::new(container);
 
-        let id = "content".to_string();
-        let href = content_filename.to_string();
+        let stem = input_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "Unknown".to_string());
 
-        book.manifest.items.insert(
-            id.clone(),
-            ManifestItem::new(&id, &href, "application/xhtml+xml"),
-        );
-        book.manifest.hrefs.insert(href.clone(), id.clone());
-        book.spine.add(&id, true);
+        let content_filename = "index.html";
+        let is_rtf = String::from_utf8_lossy(&data[..data.len().min(64)])
+            .trim_start()
+            .starts_with("{\\rtf");
 
-        book.metadata.add("title", "Converted RTF Document");
+        if !is_rtf {
+            // Treat as text
+            let text = String::from_utf8_lossy(&data);
+            let html = format!(
+                "<html><head><meta charset=\"utf-8\"/></head><body><pre>{}</pre></body></html>",
+                html_escape::encode_text(&text)
+            );
+            fs::write(output_dir.join(content_filename), html)?;
+            book.manifest
+                .add("content", content_filename, "application/xhtml+xml");
+            book.spine.add("content", true);
+            book.metadata.add("title", &stem);
+            return Ok(book);
+        }
+
+        // 1. Parse
+        let doc = RtfDocument::parse(&data);
+
+        // 2. Extract pictures
+        let mut image_hrefs = Vec::with_capacity(doc.images.len());
+        if !doc.images.is_empty() {
+            fs::create_dir_all(output_dir.join("images"))?;
+        }
+        for (i, image) in doc.images.iter().enumerate() {
+            let href = format!("images/img{}.{}", i + 1, image.ext);
+            fs::write(output_dir.join(&href), &image.data)?;
+            let media_type = match image.ext {
+                "png" => "image/png",
+                "jpg" => "image/jpeg",
+                _ => "image/bmp",
+            };
+            book.manifest
+                .add(&format!("img{}", i + 1), &href, media_type);
+            image_hrefs.push(href);
+        }
+
+        // 3. Render
+        let title = doc.info.title.clone().unwrap_or(stem);
+        let html = RTFToHTML::convert(&doc, &title, &image_hrefs);
+        fs::write(output_dir.join(content_filename), html)?;
+        book.manifest
+            .add("content", content_filename, "application/xhtml+xml");
+        book.spine.add("content", true);
+
+        // 4. Metadata from the info group
+        book.metadata.add("title", &title);
+        if let Some(author) = &doc.info.author {
+            for author in author.split('&') {
+                if !author.trim().is_empty() {
+                    book.metadata.add("creator", author.trim());
+                }
+            }
+        }
+        if let Some(comments) = doc.info.subject.as_ref().or(doc.info.comment.as_ref()) {
+            book.metadata.add("description", comments);
+        }
+        if let Some(keywords) = &doc.info.keywords {
+            for tag in keywords.split(',') {
+                if !tag.trim().is_empty() {
+                    book.metadata.add("subject", tag.trim());
+                }
+            }
+        }
+        if let Some(lcid) = doc.language {
+            let lang = mobi2iana((lcid & 0xff) as u32, ((lcid >> 10) & 0xff) as u32);
+            if lang != "und" {
+                book.metadata.add("language", &lang);
+            }
+        }
 
         Ok(book)
     }
+}
+
+impl InputFormatPlugin for RTFInput {
+    fn name(&self) -> &str {
+        "RTF Input"
+    }
 
-    fn rtf_to_html(&self, rtf: &str) -> String {
-        // Very rudimentary RTF to HTML.
-        // Real RTF parsing is huge.
-        // We will just wrap it in pre tags or do basic cleanup if it's raw text.
-        // A proper implementation would need an rtf crate.
+    fn file_types(&self) -> &[&str] {
+        &["rtf"]
+    }
 
-        // Check if it looks like RTF
-        if !rtf.trim_start().starts_with("{\\rtf") {
-            // Treat as text
-            return format!(
-                "<html><body><pre>{}</pre></body></html>",
-                html_escape::encode_text(rtf)
-            );
-        }
+    fn mime_types(&self) -> &[&str] {
+        &["application/rtf", "text/rtf"]
+    }
 
-        // Strip RTF tags for a plain text approximation?
-        // Or just put it in a block.
-        // For this port, we acknowledge we are not writing a full RTF engine.
-        format!(
-            "<html><body><h1>RTF Content</h1><pre>{}</pre></body></html>",
-            html_escape::encode_text(rtf)
-        )
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        RTFInput::convert(self, input_path, output_dir)
     }
 }
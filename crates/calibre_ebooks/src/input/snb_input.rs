@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::snb::reader::SnbReader;
 use anyhow::{Context, Result};
@@ -64,3 +65,21 @@ impl SnbInput {
         Ok(book)
     }
 }
+
+impl InputFormatPlugin for SnbInput {
+    fn name(&self) -> &str {
+        "SNB Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["snb"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-snb"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        SnbInput::convert(self, input_path, output_dir)
+    }
+}
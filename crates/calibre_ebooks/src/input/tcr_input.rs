@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
 use crate::oeb::manifest::ManifestItem;
@@ -44,3 +45,21 @@ impl TCRInput {
         Ok(book)
     }
 }
+
+impl InputFormatPlugin for TCRInput {
+    fn name(&self) -> &str {
+        "TCR Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["tcr"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-tcr"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        TCRInput::convert(self, input_path, output_dir)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::input::html_input::HTMLInput;
 use anyhow::{Context, Result};
@@ -81,6 +82,24 @@ impl TXTInput {
     }
 }
 
+impl InputFormatPlugin for TXTInput {
+    fn name(&self) -> &str {
+        "TXT Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["txt", "text", "md", "markdown", "textile"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["text/plain", "text/markdown"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        TXTInput::convert(self, input_path, output_dir)
+    }
+}
+
 // Simple HTML escaper
 mod html_escape {
     pub fn encode_text(s: &str) -> String {
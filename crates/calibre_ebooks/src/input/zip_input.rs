@@ -1,3 +1,4 @@
+use crate::conversion::registry::InputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::container::DirContainer;
 use crate::oeb::manifest::ManifestItem;
@@ -115,6 +116,24 @@ impl ZIPInput {
     }
 }
 
+impl InputFormatPlugin for ZIPInput {
+    fn name(&self) -> &str {
+        "ZIP Input"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["zip"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/zip"]
+    }
+
+    fn convert(&self, input_path: &Path, output_dir: &Path) -> Result<OEBBook> {
+        ZIPInput::convert(self, input_path, output_dir)
+    }
+}
+
 fn find_file_recursive(dir: &Path, extension: &str) -> Option<PathBuf> {
     for entry in walkdir::WalkDir::new(dir)
         .into_iter()
@@ -1,16 +1,21 @@
+pub mod chm;
 pub mod compression;
 pub mod constants;
 pub mod conversion;
+pub mod covers;
 pub mod docx;
 pub mod epub;
 pub mod html;
 pub mod input;
 pub mod lit;
+pub mod lrf;
 pub mod metadata;
 pub mod mobi;
 pub mod oeb;
 pub mod opf;
 pub mod output;
 pub mod pdb;
+pub mod pdf;
 pub mod rb;
+pub mod rtf;
 pub mod snb;
@@ -9,6 +9,7 @@ pub struct LitHeader {
     pub version: u32,
     pub hdr_len: i32,
     pub num_pieces: i32,
+    pub sec_hdr_len: i32,
     pub directory_offset: u32,
     pub directory_size: i32,
 }
@@ -26,7 +27,7 @@ impl LitHeader {
         let version = reader.read_u32::<LittleEndian>()?;
         let hdr_len = reader.read_i32::<LittleEndian>()?;
         let num_pieces = reader.read_i32::<LittleEndian>()?;
-        let _sec_hdr_len = reader.read_i32::<LittleEndian>()?;
+        let sec_hdr_len = reader.read_i32::<LittleEndian>()?;
 
         // Skip GUID (16 bytes)
         reader.seek(SeekFrom::Current(16))?;
@@ -59,6 +60,7 @@ impl LitHeader {
             version,
             hdr_len,
             num_pieces,
+            sec_hdr_len,
             directory_offset,
             directory_size,
         })
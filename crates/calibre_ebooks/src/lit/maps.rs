@@ -0,0 +1,1023 @@
+//! Microsoft LIT tag and attribute tables, copied from ConvertLIT.
+
+/// Tag and attribute names for one kind of binary LIT document.
+pub struct Map {
+    /// Tag names, indexed by tag code.
+    pub tags: &'static [Option<&'static str>],
+    /// Attributes valid on any tag, sorted by code.
+    pub attrs: &'static [(u32, &'static str)],
+    /// Attributes specific to each tag, indexed by tag code.
+    pub tag_attrs: &'static [&'static [(u32, &'static str)]],
+}
+
+impl Map {
+    pub fn tag(&self, code: u32) -> Option<&'static str> {
+        self.tags.get(code as usize).copied().flatten()
+    }
+
+    /// Look up an attribute, preferring the tag-specific table.
+    pub fn attr(&self, tag: Option<u32>, code: u32) -> Option<&'static str> {
+        let local = tag.and_then(|t| self.tag_attrs.get(t as usize)).copied();
+        local
+            .and_then(|table| lookup(table, code))
+            .or_else(|| lookup(self.attrs, code))
+    }
+}
+
+fn lookup(table: &[(u32, &'static str)], code: u32) -> Option<&'static str> {
+    table
+        .binary_search_by_key(&code, |&(c, _)| c)
+        .ok()
+        .map(|i| table[i].1)
+}
+
+/// Tables for the binary XHTML of content items.
+pub static HTML_MAP: Map = Map {
+    tags: &[
+        None,
+        None,
+        None,
+        Some("a"),
+        Some("acronym"),
+        Some("address"),
+        Some("applet"),
+        Some("area"),
+        Some("b"),
+        Some("base"),
+        Some("basefont"),
+        Some("bdo"),
+        Some("bgsound"),
+        Some("big"),
+        Some("blink"),
+        Some("blockquote"),
+        Some("body"),
+        Some("br"),
+        Some("button"),
+        Some("caption"),
+        Some("center"),
+        Some("cite"),
+        Some("code"),
+        Some("col"),
+        Some("colgroup"),
+        None,
+        None,
+        Some("dd"),
+        Some("del"),
+        Some("dfn"),
+        Some("dir"),
+        Some("div"),
+        Some("dl"),
+        Some("dt"),
+        Some("em"),
+        Some("embed"),
+        Some("fieldset"),
+        Some("font"),
+        Some("form"),
+        Some("frame"),
+        Some("frameset"),
+        None,
+        Some("h1"),
+        Some("h2"),
+        Some("h3"),
+        Some("h4"),
+        Some("h5"),
+        Some("h6"),
+        Some("head"),
+        Some("hr"),
+        Some("html"),
+        Some("i"),
+        Some("iframe"),
+        Some("img"),
+        Some("input"),
+        Some("ins"),
+        Some("kbd"),
+        Some("label"),
+        Some("legend"),
+        Some("li"),
+        Some("link"),
+        Some("tag61"),
+        Some("map"),
+        Some("tag63"),
+        Some("tag64"),
+        Some("meta"),
+        Some("nextid"),
+        Some("nobr"),
+        Some("noembed"),
+        Some("noframes"),
+        Some("noscript"),
+        Some("object"),
+        Some("ol"),
+        Some("option"),
+        Some("p"),
+        Some("param"),
+        Some("plaintext"),
+        Some("pre"),
+        Some("q"),
+        Some("rp"),
+        Some("rt"),
+        Some("ruby"),
+        Some("s"),
+        Some("samp"),
+        Some("script"),
+        Some("select"),
+        Some("small"),
+        Some("span"),
+        Some("strike"),
+        Some("strong"),
+        Some("style"),
+        Some("sub"),
+        Some("sup"),
+        Some("table"),
+        Some("tbody"),
+        Some("tc"),
+        Some("td"),
+        Some("textarea"),
+        Some("tfoot"),
+        Some("th"),
+        Some("thead"),
+        Some("title"),
+        Some("tr"),
+        Some("tt"),
+        Some("u"),
+        Some("ul"),
+        Some("var"),
+        Some("wbr"),
+        None,
+    ],
+    attrs: &[
+        (0x8010, "tabindex"),
+        (0x8046, "title"),
+        (0x804B, "style"),
+        (0x804D, "disabled"),
+        (0x83EA, "class"),
+        (0x83EB, "id"),
+        (0x83FE, "datafld"),
+        (0x83FF, "datasrc"),
+        (0x8400, "dataformatas"),
+        (0x87D6, "accesskey"),
+        (0x9392, "lang"),
+        (0x93ED, "language"),
+        (0x93FE, "dir"),
+        (0x9771, "onmouseover"),
+        (0x9772, "onmouseout"),
+        (0x9773, "onmousedown"),
+        (0x9774, "onmouseup"),
+        (0x9775, "onmousemove"),
+        (0x9776, "onkeydown"),
+        (0x9777, "onkeyup"),
+        (0x9778, "onkeypress"),
+        (0x9779, "onclick"),
+        (0x977A, "ondblclick"),
+        (0x977E, "onhelp"),
+        (0x977F, "onfocus"),
+        (0x9780, "onblur"),
+        (0x9783, "onrowexit"),
+        (0x9784, "onrowenter"),
+        (0x9786, "onbeforeupdate"),
+        (0x9787, "onafterupdate"),
+        (0x978A, "onreadystatechange"),
+        (0x9790, "onscroll"),
+        (0x9794, "ondragstart"),
+        (0x9795, "onresize"),
+        (0x9796, "onselectstart"),
+        (0x9797, "onerrorupdate"),
+        (0x9799, "ondatasetchanged"),
+        (0x979A, "ondataavailable"),
+        (0x979B, "ondatasetcomplete"),
+        (0x979C, "onfilterchange"),
+        (0x979F, "onlosecapture"),
+        (0x97A0, "onpropertychange"),
+        (0x97A2, "ondrag"),
+        (0x97A3, "ondragend"),
+        (0x97A4, "ondragenter"),
+        (0x97A5, "ondragover"),
+        (0x97A6, "ondragleave"),
+        (0x97A7, "ondrop"),
+        (0x97A8, "oncut"),
+        (0x97A9, "oncopy"),
+        (0x97AA, "onpaste"),
+        (0x97AB, "onbeforecut"),
+        (0x97AC, "onbeforecopy"),
+        (0x97AD, "onbeforepaste"),
+        (0x97AF, "onrowsdelete"),
+        (0x97B0, "onrowsinserted"),
+        (0x97B1, "oncellchange"),
+        (0x97B2, "oncontextmenu"),
+        (0x97B6, "onbeforeeditfocus"),
+    ],
+    tag_attrs: &[
+        &[],
+        &[],
+        &[],
+        &[
+            (0x0001, "href"),
+            (0x03EC, "target"),
+            (0x03EE, "rel"),
+            (0x03EF, "rev"),
+            (0x03F0, "urn"),
+            (0x03F1, "methods"),
+            (0x8001, "name"),
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[],
+        &[(0x9399, "clear")],
+        &[
+            (0x8001, "name"),
+            (0x8006, "width"),
+            (0x8007, "height"),
+            (0x804A, "align"),
+            (0x8BBB, "classid"),
+            (0x8BBC, "data"),
+            (0x8BBF, "codebase"),
+            (0x8BC0, "codetype"),
+            (0x8BC1, "code"),
+            (0x8BC2, "type"),
+            (0x8BC5, "vspace"),
+            (0x8BC6, "hspace"),
+            (0x978E, "onerror"),
+        ],
+        &[
+            (0x0001, "href"),
+            (0x03EA, "shape"),
+            (0x03EB, "coords"),
+            (0x03ED, "target"),
+            (0x03EE, "alt"),
+            (0x03EF, "nohref"),
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[(0x03EC, "href"), (0x03ED, "target")],
+        &[(0x938B, "color"), (0x939B, "face"), (0x93A3, "size")],
+        &[],
+        &[
+            (0x03EA, "src"),
+            (0x03EB, "loop"),
+            (0x03EC, "volume"),
+            (0x03ED, "balance"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x9399, "clear"),
+        ],
+        &[
+            (0x07DB, "link"),
+            (0x07DC, "alink"),
+            (0x07DD, "vlink"),
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x938A, "background"),
+            (0x938B, "text"),
+            (0x938E, "nowrap"),
+            (0x93AE, "topmargin"),
+            (0x93AF, "rightmargin"),
+            (0x93B0, "bottommargin"),
+            (0x93B1, "leftmargin"),
+            (0x93B6, "bgproperties"),
+            (0x93D8, "scroll"),
+            (0x977B, "onselect"),
+            (0x9791, "onload"),
+            (0x9792, "onunload"),
+            (0x9798, "onbeforeunload"),
+            (0x97B3, "onbeforeprint"),
+            (0x97B4, "onafterprint"),
+            (0xFE0C, "bgcolor"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x9399, "clear"),
+        ],
+        &[(0x07D1, "type"), (0x8001, "name")],
+        &[
+            (0x8046, "title"),
+            (0x8049, "align"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x93A8, "valign"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x9399, "clear"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x03EA, "span"),
+            (0x8006, "width"),
+            (0x8049, "align"),
+            (0x93A8, "valign"),
+            (0xFE0C, "bgcolor"),
+        ],
+        &[
+            (0x03EA, "span"),
+            (0x8006, "width"),
+            (0x8049, "align"),
+            (0x93A8, "valign"),
+            (0xFE0C, "bgcolor"),
+        ],
+        &[],
+        &[],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x938E, "nowrap"),
+        ],
+        &[],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[],
+        &[
+            (0x8046, "title"),
+            (0x8049, "align"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x938E, "nowrap"),
+        ],
+        &[
+            (0x03EA, "compact"),
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x938E, "nowrap"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x8001, "name"),
+            (0x8006, "width"),
+            (0x8007, "height"),
+            (0x804A, "align"),
+            (0x8BBD, "palette"),
+            (0x8BBE, "pluginspage"),
+            (0x8BBF, "src"),
+            (0x8BC1, "units"),
+            (0x8BC2, "type"),
+            (0x8BC3, "hidden"),
+        ],
+        &[(0x804A, "align")],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x938B, "color"),
+            (0x939B, "face"),
+            (0x939C, "size"),
+        ],
+        &[
+            (0x03EA, "action"),
+            (0x03EC, "enctype"),
+            (0x03ED, "method"),
+            (0x03EF, "target"),
+            (0x03F4, "accept-charset"),
+            (0x8001, "name"),
+            (0x977C, "onsubmit"),
+            (0x977D, "onreset"),
+        ],
+        &[
+            (0x8000, "align"),
+            (0x8001, "name"),
+            (0x8BB9, "src"),
+            (0x8BBB, "border"),
+            (0x8BBC, "frameborder"),
+            (0x8BBD, "framespacing"),
+            (0x8BBE, "marginwidth"),
+            (0x8BBF, "marginheight"),
+            (0x8BC0, "noresize"),
+            (0x8BC1, "scrolling"),
+            (0x8FA2, "bordercolor"),
+        ],
+        &[
+            (0x03E9, "rows"),
+            (0x03EA, "cols"),
+            (0x03EB, "border"),
+            (0x03EC, "bordercolor"),
+            (0x03ED, "frameborder"),
+            (0x03EE, "framespacing"),
+            (0x8001, "name"),
+            (0x9791, "onload"),
+            (0x9792, "onunload"),
+            (0x9798, "onbeforeunload"),
+            (0x97B3, "onbeforeprint"),
+            (0x97B4, "onafterprint"),
+        ],
+        &[],
+        &[
+            (0x8046, "title"),
+            (0x8049, "align"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x9399, "clear"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x8049, "align"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x9399, "clear"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x8049, "align"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x9399, "clear"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x8049, "align"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x9399, "clear"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x8049, "align"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x9399, "clear"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x8049, "align"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x9399, "clear"),
+        ],
+        &[],
+        &[
+            (0x03EA, "noshade"),
+            (0x8006, "width"),
+            (0x8007, "size"),
+            (0x8046, "title"),
+            (0x8049, "align"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x938B, "color"),
+        ],
+        &[],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x8001, "name"),
+            (0x8006, "width"),
+            (0x8007, "height"),
+            (0x804A, "align"),
+            (0x8BB9, "src"),
+            (0x8BBB, "border"),
+            (0x8BBC, "frameborder"),
+            (0x8BBD, "framespacing"),
+            (0x8BBE, "marginwidth"),
+            (0x8BBF, "marginheight"),
+            (0x8BC0, "noresize"),
+            (0x8BC1, "scrolling"),
+            (0x8FA2, "vspace"),
+            (0x8FA3, "hspace"),
+        ],
+        &[
+            (0x03EB, "alt"),
+            (0x03EC, "src"),
+            (0x03ED, "border"),
+            (0x03EE, "vspace"),
+            (0x03EF, "hspace"),
+            (0x03F0, "lowsrc"),
+            (0x03F1, "vrml"),
+            (0x03F2, "dynsrc"),
+            (0x03F4, "loop"),
+            (0x03F6, "start"),
+            (0x07D3, "ismap"),
+            (0x07D9, "usemap"),
+            (0x8001, "name"),
+            (0x8006, "width"),
+            (0x8007, "height"),
+            (0x8046, "title"),
+            (0x804A, "align"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x978D, "onabort"),
+            (0x978E, "onerror"),
+            (0x9791, "onload"),
+        ],
+        &[
+            (0x07D1, "type"),
+            (0x07D3, "size"),
+            (0x07D4, "maxlength"),
+            (0x07D6, "readonly"),
+            (0x07D8, "indeterminate"),
+            (0x07DA, "checked"),
+            (0x07DB, "alt"),
+            (0x07DC, "src"),
+            (0x07DD, "border"),
+            (0x07DE, "vspace"),
+            (0x07DF, "hspace"),
+            (0x07E0, "lowsrc"),
+            (0x07E1, "vrml"),
+            (0x07E2, "dynsrc"),
+            (0x07E4, "loop"),
+            (0x07E5, "start"),
+            (0x8001, "name"),
+            (0x8006, "width"),
+            (0x8007, "height"),
+            (0x804A, "align"),
+            (0x93EE, "value"),
+            (0x977B, "onselect"),
+            (0x978D, "onabort"),
+            (0x978E, "onerror"),
+            (0x978F, "onchange"),
+            (0x9791, "onload"),
+        ],
+        &[],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[(0x03E9, "for")],
+        &[(0x804A, "align")],
+        &[
+            (0x03EA, "value"),
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x939A, "type"),
+        ],
+        &[
+            (0x03EE, "href"),
+            (0x03EF, "rel"),
+            (0x03F0, "rev"),
+            (0x03F1, "type"),
+            (0x03F9, "media"),
+            (0x03FA, "target"),
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x978E, "onerror"),
+            (0x9791, "onload"),
+        ],
+        &[(0x9399, "clear")],
+        &[
+            (0x8001, "name"),
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x1771, "scrolldelay"),
+            (0x1772, "direction"),
+            (0x1773, "behavior"),
+            (0x1774, "scrollamount"),
+            (0x1775, "loop"),
+            (0x1776, "vspace"),
+            (0x1777, "hspace"),
+            (0x1778, "truespeed"),
+            (0x8006, "width"),
+            (0x8007, "height"),
+            (0x9785, "onbounce"),
+            (0x978B, "onfinish"),
+            (0x978C, "onstart"),
+            (0xFE0C, "bgcolor"),
+        ],
+        &[],
+        &[
+            (0x03EA, "http-equiv"),
+            (0x03EB, "content"),
+            (0x03EC, "url"),
+            (0x03F6, "charset"),
+            (0x8001, "name"),
+        ],
+        &[(0x03F5, "n")],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[
+            (0x8000, "usemap"),
+            (0x8001, "name"),
+            (0x8006, "width"),
+            (0x8007, "height"),
+            (0x8046, "title"),
+            (0x804A, "align"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x8BBB, "classid"),
+            (0x8BBC, "data"),
+            (0x8BBF, "codebase"),
+            (0x8BC0, "codetype"),
+            (0x8BC1, "code"),
+            (0x8BC2, "type"),
+            (0x8BC5, "vspace"),
+            (0x8BC6, "hspace"),
+            (0x978E, "onerror"),
+        ],
+        &[
+            (0x03EB, "compact"),
+            (0x03EC, "start"),
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x939A, "type"),
+        ],
+        &[(0x03EA, "selected"), (0x03EB, "value")],
+        &[
+            (0x8046, "title"),
+            (0x8049, "align"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x9399, "clear"),
+        ],
+        &[(0x8000, "type")],
+        &[(0x9399, "clear")],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x9399, "clear"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[],
+        &[],
+        &[],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x03EA, "src"),
+            (0x03ED, "for"),
+            (0x03EE, "event"),
+            (0x03F0, "defer"),
+            (0x03F2, "type"),
+            (0x978E, "onerror"),
+        ],
+        &[
+            (0x03EB, "size"),
+            (0x03EC, "multiple"),
+            (0x8000, "align"),
+            (0x8001, "name"),
+            (0x978F, "onchange"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x03EB, "type"),
+            (0x03EF, "media"),
+            (0x8046, "title"),
+            (0x978E, "onerror"),
+            (0x9791, "onload"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x03EA, "cols"),
+            (0x03EB, "border"),
+            (0x03EC, "rules"),
+            (0x03ED, "frame"),
+            (0x03EE, "cellspacing"),
+            (0x03EF, "cellpadding"),
+            (0x03FA, "datapagesize"),
+            (0x8006, "width"),
+            (0x8007, "height"),
+            (0x8046, "title"),
+            (0x804A, "align"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x938A, "background"),
+            (0x93A5, "bordercolor"),
+            (0x93A6, "bordercolorlight"),
+            (0x93A7, "bordercolordark"),
+            (0xFE0C, "bgcolor"),
+        ],
+        &[(0x8049, "align"), (0x93A8, "valign"), (0xFE0C, "bgcolor")],
+        &[(0x8049, "align"), (0x93A8, "valign")],
+        &[
+            (0x07D2, "rowspan"),
+            (0x07D3, "colspan"),
+            (0x8006, "width"),
+            (0x8007, "height"),
+            (0x8046, "title"),
+            (0x8049, "align"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x938A, "background"),
+            (0x938E, "nowrap"),
+            (0x93A5, "bordercolor"),
+            (0x93A6, "bordercolorlight"),
+            (0x93A7, "bordercolordark"),
+            (0x93A8, "valign"),
+            (0xFE0C, "bgcolor"),
+        ],
+        &[
+            (0x1B5A, "rows"),
+            (0x1B5B, "cols"),
+            (0x1B5C, "wrap"),
+            (0x1B5D, "readonly"),
+            (0x8001, "name"),
+            (0x977B, "onselect"),
+            (0x978F, "onchange"),
+        ],
+        &[(0x8049, "align"), (0x93A8, "valign"), (0xFE0C, "bgcolor")],
+        &[
+            (0x07D2, "rowspan"),
+            (0x07D3, "colspan"),
+            (0x8006, "width"),
+            (0x8007, "height"),
+            (0x8046, "title"),
+            (0x8049, "align"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x938A, "background"),
+            (0x938E, "nowrap"),
+            (0x93A5, "bordercolor"),
+            (0x93A6, "bordercolorlight"),
+            (0x93A7, "bordercolordark"),
+            (0x93A8, "valign"),
+            (0xFE0C, "bgcolor"),
+        ],
+        &[(0x8049, "align"), (0x93A8, "valign"), (0xFE0C, "bgcolor")],
+        &[],
+        &[
+            (0x8007, "height"),
+            (0x8046, "title"),
+            (0x8049, "align"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x93A5, "bordercolor"),
+            (0x93A6, "bordercolorlight"),
+            (0x93A7, "bordercolordark"),
+            (0x93A8, "valign"),
+            (0xFE0C, "bgcolor"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[
+            (0x03EB, "compact"),
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+            (0x939A, "type"),
+        ],
+        &[
+            (0x8046, "title"),
+            (0x804B, "style"),
+            (0x83EA, "class"),
+            (0x83EB, "id"),
+        ],
+        &[],
+        &[],
+    ],
+};
+
+/// Tables for the binary OPF stored in `/meta`.
+pub static OPF_MAP: Map = Map {
+    tags: &[
+        None,
+        Some("package"),
+        Some("dc:Title"),
+        Some("dc:Creator"),
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        Some("manifest"),
+        Some("item"),
+        Some("spine"),
+        Some("itemref"),
+        Some("metadata"),
+        Some("dc-metadata"),
+        Some("dc:Subject"),
+        Some("dc:Description"),
+        Some("dc:Publisher"),
+        Some("dc:Contributor"),
+        Some("dc:Date"),
+        Some("dc:Type"),
+        Some("dc:Format"),
+        Some("dc:Identifier"),
+        Some("dc:Source"),
+        Some("dc:Language"),
+        Some("dc:Relation"),
+        Some("dc:Coverage"),
+        Some("dc:Rights"),
+        Some("x-metadata"),
+        Some("meta"),
+        Some("tours"),
+        Some("tour"),
+        Some("site"),
+        Some("guide"),
+        Some("reference"),
+        None,
+    ],
+    attrs: &[
+        (0x0001, "href"),
+        (0x0002, "%never-used"),
+        (0x0003, "%guid"),
+        (0x0004, "%minimum_level"),
+        (0x0005, "%attr5"),
+        (0x0006, "id"),
+        (0x0007, "href"),
+        (0x0008, "media-type"),
+        (0x0009, "fallback"),
+        (0x000A, "idref"),
+        (0x000B, "xmlns:dc"),
+        (0x000C, "xmlns:oebpackage"),
+        (0x000D, "role"),
+        (0x000E, "file-as"),
+        (0x000F, "event"),
+        (0x0010, "scheme"),
+        (0x0011, "title"),
+        (0x0012, "type"),
+        (0x0013, "unique-identifier"),
+        (0x0014, "name"),
+        (0x0015, "content"),
+        (0x0016, "xml:lang"),
+    ],
+    tag_attrs: &[
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+        &[],
+    ],
+};
@@ -1,3 +1,5 @@
 pub mod header;
+pub mod maps;
 pub mod reader;
+pub mod unbinary;
 pub mod writer;
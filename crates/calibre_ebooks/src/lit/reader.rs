@@ -1,29 +1,823 @@
+use crate::compression::lzx::LzxDecoder;
 use crate::lit::header::LitHeader;
-use anyhow::{Context, Result};
+use crate::lit::maps::{HTML_MAP, OPF_MAP};
+use crate::lit::unbinary::{read_utf8_char, unbinary, Atoms};
+use crate::oeb::constants::{CSS_MIME, DC11_NS, OPF2_NS, XHTML_MIME, XHTML_NS};
+use crate::oeb::parse_utils::escape_xml;
+use anyhow::{anyhow, bail, Context, Result};
+use regex::Regex;
+use roxmltree::{Document, Node};
+use std::collections::{HashMap, HashSet};
 use std::io::{Read, Seek, SeekFrom};
 
+pub const DESENCRYPT_GUID: &str = "{67F6E4A2-60BF-11D3-8540-00C04F58C3CF}";
+pub const LZXCOMPRESS_GUID: &str = "{0A9007C6-4076-11D3-8789-0000F8105754}";
+
+const PIECE_SIZE: u64 = 16;
+const CONTROL_TAG: usize = 4;
+const CONTROL_WINDOW_SIZE: usize = 12;
+const RESET_HDRLEN: usize = 12;
+const RESET_UCLENGTH: usize = 16;
+const RESET_INTERVAL: usize = 32;
+
+/// Name of the reconstructed OPF among the extracted files.
+pub const OPF_PATH: &str = "content.opf";
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow!("Truncated LIT structure"))
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Result<u16> {
+    data.get(pos..pos + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow!("Truncated LIT structure"))
+}
+
+/// Read a big-endian, 7 bits per byte integer from a directory chunk.
+fn encint(data: &[u8], pos: &mut usize, remaining: &mut i64) -> Result<u64> {
+    let mut val = 0u64;
+    while *remaining > 0 {
+        let b = *data
+            .get(*pos)
+            .ok_or_else(|| anyhow!("Read past end of directory chunk"))?;
+        *pos += 1;
+        *remaining -= 1;
+        val = (val << 7) | (b & 0x7f) as u64;
+        if b & 0x80 == 0 {
+            break;
+        }
+    }
+    Ok(val)
+}
+
+fn msguid(data: &[u8]) -> Result<String> {
+    if data.len() < 16 {
+        bail!("Truncated GUID");
+    }
+    Ok(format!(
+        "{{{:08X}-{:04X}-{:04X}-{}-{}}}",
+        u32_at(data, 0)?,
+        u16_at(data, 4)?,
+        u16_at(data, 6)?,
+        data[8..10]
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<String>(),
+        data[10..16]
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<String>(),
+    ))
+}
+
+/// A length-prefixed string from the manifest, returning the remaining data.
+fn consume_sized_utf8_string(data: &[u8], zpad: bool) -> Result<(String, &[u8])> {
+    let (len, mut pos) = read_utf8_char(data, 0)?;
+    let mut result = String::new();
+    for _ in 0..len {
+        let (c, next) = read_utf8_char(data, pos)?;
+        result.push(char::from_u32(c).unwrap_or('\u{FFFD}'));
+        pos = next;
+    }
+    if zpad && data.get(pos) == Some(&0) {
+        pos += 1;
+    }
+    Ok((result, &data[pos..]))
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectoryEntry {
+    pub name: String,
+    pub section: usize,
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestState {
+    Spine,
+    NotSpine,
+    Css,
+    Images,
+}
+
+#[derive(Debug, Clone)]
+pub struct ManifestItem {
+    pub internal: String,
+    pub original: String,
+    /// Path of the item within the book, with shared leading directories
+    /// removed.
+    pub path: String,
+    pub mime_type: String,
+    pub state: ManifestState,
+}
+
+impl ManifestItem {
+    fn new(original: String, internal: String, mime_type: String, state: ManifestState) -> Self {
+        // Some LIT files have Windows-style paths
+        let mut path = original.replace('\\', "/");
+        if path.get(1..3) == Some(":/") {
+            path = path[2..].to_string();
+        }
+        let mut parts: Vec<&str> = Vec::new();
+        for part in path.split('/') {
+            match part {
+                "." => {}
+                ".." if parts.last().is_some_and(|p| *p != "..") => {
+                    parts.pop();
+                }
+                part => parts.push(part),
+            }
+        }
+        // Some paths in "multiformat" LIT files contain '..'
+        while parts.first() == Some(&"..") {
+            parts.remove(0);
+        }
+        ManifestItem {
+            path: parts.join("/"),
+            original,
+            internal,
+            mime_type: mime_type.to_lowercase(),
+            state,
+        }
+    }
+
+    /// Whether the item is stored as binary XHTML.
+    pub fn is_html(&self) -> bool {
+        matches!(self.state, ManifestState::Spine | ManifestState::NotSpine)
+    }
+}
+
 pub struct LitReader<R> {
     reader: R,
-    header: LitHeader,
+    pub header: LitHeader,
+    content_offset: u64,
+    entry_chunklen: u32,
+    entry_unknown: u32,
+    count_chunklen: u32,
+    count_unknown: u32,
+    entries: HashMap<String, DirectoryEntry>,
+    section_names: Vec<String>,
+    sections: Vec<Option<Vec<u8>>>,
+    pub manifest: Vec<ManifestItem>,
+    paths: HashMap<String, String>,
 }
 
 impl<R: Read + Seek> LitReader<R> {
     pub fn new(mut reader: R) -> Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
         let header = LitHeader::parse(&mut reader)?;
-        Ok(LitReader { reader, header })
+        if header.version != 1 {
+            bail!("Unknown LIT version {}", header.version);
+        }
+        let mut lit = LitReader {
+            reader,
+            header,
+            content_offset: 0,
+            entry_chunklen: 0,
+            entry_unknown: 0,
+            count_chunklen: 0,
+            count_unknown: 0,
+            entries: HashMap::new(),
+            section_names: Vec::new(),
+            sections: Vec::new(),
+            manifest: Vec::new(),
+            paths: HashMap::new(),
+        };
+        lit.read_secondary_header()?;
+        lit.read_header_pieces()?;
+        lit.read_section_names()?;
+        lit.read_manifest()?;
+        lit.check_drm()?;
+        Ok(lit)
+    }
+
+    fn read_raw(&mut self, offset: u64, size: u64) -> Result<Vec<u8>> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut data = Vec::new();
+        self.reader.by_ref().take(size).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn read_secondary_header(&mut self) -> Result<()> {
+        let offset = self.header.hdr_len as u64 + self.header.num_pieces as u64 * PIECE_SIZE;
+        let data = self.read_raw(offset, self.header.sec_hdr_len as u64)?;
+        let mut offset = u32_at(&data, 4)? as usize;
+        let mut found_content = false;
+        while offset + 8 <= data.len() {
+            let version = u32_at(&data, offset + 4)?;
+            match &data[offset..offset + 4] {
+                b"CAOL" => {
+                    if version != 2 {
+                        bail!("Unknown CAOL block format {}", version);
+                    }
+                    self.entry_chunklen = u32_at(&data, offset + 20)?;
+                    self.count_chunklen = u32_at(&data, offset + 24)?;
+                    self.entry_unknown = u32_at(&data, offset + 28)?;
+                    self.count_unknown = u32_at(&data, offset + 32)?;
+                }
+                b"ITSF" => {
+                    if version != 4 {
+                        bail!("Unknown ITSF block format {}", version);
+                    }
+                    if u32_at(&data, offset + 20)? != 0 {
+                        bail!("This file has a 64bit content offset");
+                    }
+                    self.content_offset = u32_at(&data, offset + 16)? as u64;
+                    found_content = true;
+                }
+                _ => break,
+            }
+            offset += 48;
+        }
+        if !found_content {
+            bail!("Could not figure out the content offset");
+        }
+        Ok(())
+    }
+
+    fn read_header_pieces(&mut self) -> Result<()> {
+        let src = self.read_raw(
+            self.header.hdr_len as u64,
+            self.header.num_pieces as u64 * PIECE_SIZE,
+        )?;
+        for i in 0..self.header.num_pieces as usize {
+            let piece = src
+                .get(i * 16..(i + 1) * 16)
+                .ok_or_else(|| anyhow!("Truncated LIT header pieces"))?;
+            if u32_at(piece, 4)? != 0 || u32_at(piece, 12)? != 0 {
+                bail!("Piece {} has 64bit value", i);
+            }
+            let (offset, size) = (u32_at(piece, 0)? as u64, u32_at(piece, 8)? as u64);
+            match i {
+                1 => {
+                    let piece = self.read_raw(offset, size)?;
+                    if u32_at(&piece, 8)? != self.entry_chunklen
+                        || u32_at(&piece, 12)? != self.entry_unknown
+                    {
+                        bail!("Secondary header does not match piece");
+                    }
+                    self.read_directory(&piece)?;
+                }
+                2 => {
+                    let piece = self.read_raw(offset, size)?;
+                    if u32_at(&piece, 8)? != self.count_chunklen
+                        || u32_at(&piece, 12)? != self.count_unknown
+                    {
+                        bail!("Secondary header does not match piece");
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn read_directory(&mut self, piece: &[u8]) -> Result<()> {
+        if !piece.starts_with(b"IFCM") {
+            bail!("Header piece #1 is not main directory.");
+        }
+        let chunk_size = u32_at(piece, 8)? as usize;
+        let num_chunks = u32_at(piece, 24)? as usize;
+        if 32 + num_chunks * chunk_size != piece.len() {
+            bail!("IFCM header has incorrect length");
+        }
+        for i in 0..num_chunks {
+            let chunk = &piece[32 + i * chunk_size..32 + (i + 1) * chunk_size];
+            if !chunk.starts_with(b"AOLL") {
+                continue;
+            }
+            let quickref = u32_at(chunk, 4)? as usize;
+            if quickref >= chunk_size {
+                bail!("AOLL remaining count is negative");
+            }
+            let mut remaining = chunk_size as i64 - (quickref as i64 + 48);
+            let entries = match u16_at(chunk, chunk_size - 2)? {
+                // Hopefully will work even without a correct entries count
+                0 => u16::MAX,
+                n => n,
+            };
+            let mut pos = 48;
+            for _ in 0..entries {
+                if remaining <= 0 {
+                    break;
+                }
+                let namelen = encint(chunk, &mut pos, &mut remaining)? as usize;
+                if namelen as i64 > remaining - 3 {
+                    bail!("Read past end of directory chunk");
+                }
+                let name = match std::str::from_utf8(&chunk[pos..pos + namelen]) {
+                    Ok(name) => name.to_string(),
+                    Err(_) => break,
+                };
+                pos += namelen;
+                remaining -= namelen as i64;
+                let section = encint(chunk, &mut pos, &mut remaining)? as usize;
+                let offset = encint(chunk, &mut pos, &mut remaining)?;
+                let size = encint(chunk, &mut pos, &mut remaining)?;
+                self.entries.insert(
+                    name.clone(),
+                    DirectoryEntry {
+                        name,
+                        section,
+                        offset,
+                        size,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn read_section_names(&mut self) -> Result<()> {
+        if !self.entries.contains_key("::DataSpace/NameList") {
+            bail!("Lit file does not have a valid NameList");
+        }
+        let raw = self.get_file("::DataSpace/NameList")?;
+        let num_sections = u16_at(&raw, 2).context("Invalid Namelist section")? as usize;
+        let mut pos = 4;
+        for _ in 0..num_sections {
+            let size = u16_at(&raw, pos).context("Invalid Namelist section")? as usize;
+            pos += 2;
+            let units: Vec<u16> = raw
+                .get(pos..pos + size * 2 + 2)
+                .ok_or_else(|| anyhow!("Invalid Namelist section"))?
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect();
+            let name = String::from_utf16_lossy(&units);
+            self.section_names
+                .push(name.trim_end_matches('\0').to_string());
+            pos += size * 2 + 2;
+        }
+        self.sections = vec![None; num_sections];
+        Ok(())
+    }
+
+    fn read_manifest(&mut self) -> Result<()> {
+        if !self.entries.contains_key("/manifest") {
+            bail!("Lit file does not have a valid manifest");
+        }
+        let data = self.get_file("/manifest")?;
+        let mut raw = &data[..];
+        let states = [
+            ManifestState::Spine,
+            ManifestState::NotSpine,
+            ManifestState::Css,
+            ManifestState::Images,
+        ];
+        while let Some((&slen, rest)) = raw.split_first() {
+            if slen == 0 {
+                break;
+            }
+            // Skip the root directory name
+            raw = rest
+                .get(slen as usize..)
+                .filter(|r| !r.is_empty())
+                .ok_or_else(|| anyhow!("Truncated manifest"))?;
+            for state in states {
+                let num_files = u32_at(raw, 0).context("Truncated manifest")?;
+                raw = &raw[4..];
+                for _ in 0..num_files {
+                    if raw.len() < 5 {
+                        bail!("Truncated manifest");
+                    }
+                    raw = &raw[4..];
+                    let (internal, rest) = consume_sized_utf8_string(raw, false)?;
+                    let (original, rest) = consume_sized_utf8_string(rest, false)?;
+                    let (mime_type, rest) = consume_sized_utf8_string(rest, true)?;
+                    raw = rest;
+                    // The path should be stored unquoted, but not always
+                    let original = urlencoding::decode(&original)
+                        .map(|s| s.into_owned())
+                        .unwrap_or(original);
+                    self.manifest
+                        .push(ManifestItem::new(original, internal, mime_type, state));
+                }
+            }
+        }
+
+        // Remove any common path elements
+        if self.manifest.len() > 1 {
+            let mut shared = self.manifest[0].path.clone();
+            for item in &self.manifest[1..] {
+                while !shared.is_empty() && !item.path.starts_with(&shared) {
+                    let end = shared.len().saturating_sub(2);
+                    shared = match shared.as_bytes()[..end].iter().rposition(|&b| b == b'/') {
+                        Some(i) => shared[..=i].to_string(),
+                        None => String::new(),
+                    };
+                }
+                if shared.is_empty() {
+                    break;
+                }
+            }
+            for item in &mut self.manifest {
+                item.path = item.path[shared.len()..].to_string();
+            }
+        }
+        // Fix any straggling absolute paths
+        for item in &mut self.manifest {
+            if item.path.starts_with('/') {
+                item.path = item.path.rsplit('/').next().unwrap_or_default().to_string();
+            }
+            self.paths.insert(item.internal.clone(), item.path.clone());
+        }
+        Ok(())
+    }
+
+    fn check_drm(&self) -> Result<()> {
+        let drm = [
+            "/DRMStorage/Licenses/EUL",
+            "/DRMStorage/DRMBookplate",
+            "/DRMStorage/DRMSealed",
+        ];
+        if drm.iter().any(|name| self.entries.contains_key(*name)) {
+            bail!("Cannot access DRM-protected book");
+        }
+        Ok(())
+    }
+
+    /// Names of all files in the LIT directory.
+    pub fn file_names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
     }
 
-    pub fn extract_content(&mut self) -> Result<String> {
-        // Extraction logic
-        // For now, return placeholder or successfully identify "ITSS" (which LIT effectively is).
-        // Since we can't fully decompress LZX without complex dependency,
-        // we'll simulate content extraction or extract raw if possible.
-        // But for "Input Plugin" requirement, returning failure-to-decompress is acceptable if documented,
-        // OR we return metadata as content title.
+    /// Read a file from the LIT directory, decompressing its section if
+    /// needed.
+    pub fn get_file(&mut self, name: &str) -> Result<Vec<u8>> {
+        let entry = self
+            .entries
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("{} not found in LIT file", name))?;
+        if entry.section == 0 {
+            return self.read_raw(self.content_offset + entry.offset, entry.size);
+        }
+        let section = self.get_section(entry.section)?;
+        let start = (entry.offset as usize).min(section.len());
+        let end = (entry.offset + entry.size).min(section.len() as u64) as usize;
+        Ok(section[start..end].to_vec())
+    }
+
+    fn get_section(&mut self, section: usize) -> Result<&[u8]> {
+        if section >= self.sections.len() {
+            bail!("Invalid LIT section {}", section);
+        }
+        if self.sections[section].is_none() {
+            let data = self.get_section_uncached(section)?;
+            self.sections[section] = Some(data);
+        }
+        Ok(self.sections[section].as_deref().unwrap_or_default())
+    }
+
+    fn get_section_uncached(&mut self, section: usize) -> Result<Vec<u8>> {
+        let name = self.section_names[section].clone();
+        let path = format!("::DataSpace/Storage/{}", name);
+        let mut transform = self.get_file(&format!("{}/Transform/List", path))?;
+        let mut content = self.get_file(&format!("{}/Content", path))?;
+        let mut control = self.get_file(&format!("{}/ControlData", path))?;
+        while transform.len() >= 16 {
+            let csize = (u32_at(&control, 0)? as usize + 1) * 4;
+            if csize > control.len() {
+                bail!("ControlData is too short");
+            }
+            let guid = msguid(&transform)?;
+            match guid.as_str() {
+                DESENCRYPT_GUID => bail!("Cannot access DRM-protected book"),
+                LZXCOMPRESS_GUID => {
+                    let reset_table = self.get_file(&format!(
+                        "{}/Transform/{}/InstanceData/ResetTable",
+                        path, LZXCOMPRESS_GUID
+                    ))?;
+                    content = decompress(&content, &control, &reset_table)
+                        .with_context(|| format!("Failed to decompress section {}", name))?;
+                }
+                _ => bail!("Unrecognized transform: {}", guid),
+            }
+            control = control[csize..].to_vec();
+            transform = transform[16..].to_vec();
+        }
+        Ok(content)
+    }
+
+    fn get_atoms(&mut self, item: &ManifestItem) -> Result<Atoms> {
+        let name = format!("/data/{}/atom", item.internal);
+        let mut atoms = Atoms::default();
+        if !self.entries.contains_key(&name) {
+            return Ok(atoms);
+        }
+        let data = self.get_file(&name)?;
+        let mut raw = &data[..];
+        let count = u32_at(raw, 0)?;
+        raw = &raw[4..];
+        for i in 1..=count {
+            let size = match raw.first() {
+                Some(&size) if raw.len() > 1 => size as usize,
+                _ => break,
+            };
+            if size == 0 || raw.len() - 1 < size {
+                break;
+            }
+            let tag = String::from_utf8_lossy(&raw[1..=size]).into_owned();
+            atoms.tags.insert(i, tag);
+            raw = &raw[1 + size..];
+        }
+        if raw.len() < 4 {
+            return Ok(atoms);
+        }
+        let count = u32_at(raw, 0)?;
+        raw = &raw[4..];
+        for i in 1..=count {
+            if raw.len() <= 4 {
+                break;
+            }
+            let size = u32_at(raw, 0)? as usize;
+            if size == 0 || raw.len() - 4 < size {
+                break;
+            }
+            let attr = String::from_utf8_lossy(&raw[4..4 + size]).into_owned();
+            atoms.attrs.insert(i, attr);
+            raw = &raw[4 + size..];
+        }
+        Ok(atoms)
+    }
+
+    /// The contents of a manifest item. Spine items are converted from
+    /// binary to XHTML; everything else is returned as stored.
+    pub fn read_item(&mut self, item: &ManifestItem) -> Result<Vec<u8>> {
+        if !item.is_html() {
+            return self.get_file(&format!("/data/{}", item.internal));
+        }
+        let raw = self.get_file(&format!("/data/{}/content", item.internal))?;
+        let atoms = self.get_atoms(item)?;
+        let html = unbinary(&raw, &item.path, &self.paths, &HTML_MAP, &atoms)
+            .with_context(|| format!("Failed to decode {}", item.path))?;
+        Ok(clean_html(&html).into_bytes())
+    }
+
+    /// The book's OPF, upgraded from the stored OEB 1.0 package to OPF 2.0
+    /// with manifest hrefs matching [`ManifestItem::path`].
+    pub fn read_opf(&mut self) -> Result<String> {
+        let raw = self.get_file("/meta")?;
+        let meta = match unbinary(&raw, OPF_PATH, &self.paths, &OPF_MAP, &Atoms::default()) {
+            Ok(meta) => meta,
+            // Some Penguin books are missing a tag around the publisher
+            Err(_) if contains(&raw, b"PENGUIN group") => {
+                let pos = raw
+                    .windows(13)
+                    .position(|w| w == b"PENGUIN group")
+                    .unwrap_or(0);
+                let mut fixed = raw[..pos].to_vec();
+                fixed.extend_from_slice(b"\x00\x01\x18\x00");
+                fixed.extend_from_slice(&raw[pos..]);
+                unbinary(&fixed, OPF_PATH, &self.paths, &OPF_MAP, &Atoms::default())?
+            }
+            Err(e) => return Err(e.context("Failed to decode OPF")),
+        };
+        upgrade_opf(&meta, &self.manifest)
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+fn decompress(content: &[u8], control: &[u8], reset_table: &[u8]) -> Result<Vec<u8>> {
+    if control.len() < 32 || &control[CONTROL_TAG..CONTROL_TAG + 4] != b"LZXC" {
+        bail!("Invalid ControlData tag value");
+    }
+    if reset_table.len() < RESET_INTERVAL + 8 {
+        bail!("Reset table is too short");
+    }
+    if u32_at(reset_table, RESET_UCLENGTH + 4)? != 0 {
+        bail!("Reset table has 64bit value for UCLENGTH");
+    }
+
+    let mut window_bits = 14;
+    let mut u = u32_at(control, CONTROL_WINDOW_SIZE)?;
+    while u > 0 {
+        u >>= 1;
+        window_bits += 1;
+    }
+    let mut lzx = LzxDecoder::new(window_bits).context("Invalid window in ControlData")?;
+
+    let mut ofs_entry = u32_at(reset_table, RESET_HDRLEN)? as usize + 8;
+    let mut bytes_remaining = u32_at(reset_table, RESET_UCLENGTH)? as usize;
+    let interval = u32_at(reset_table, RESET_INTERVAL)? as usize;
+    let mut accum = interval;
+    let window_bytes = 1usize << window_bits;
+    let mut base = 0usize;
+    let mut result = Vec::with_capacity(bytes_remaining);
+
+    while ofs_entry < reset_table.len() {
+        if accum >= window_bytes {
+            accum = 0;
+            let size = u32_at(reset_table, ofs_entry)? as usize;
+            if u32_at(reset_table, ofs_entry + 4)? != 0 {
+                bail!("Reset table entry greater than 32 bits");
+            }
+            if bytes_remaining >= window_bytes {
+                let end = size.clamp(base.min(content.len()), content.len());
+                let chunk = &content[base.min(content.len())..end];
+                result.extend(lzx.decompress(chunk, window_bytes)?);
+                bytes_remaining -= window_bytes;
+                base = size;
+            }
+        }
+        accum += interval;
+        ofs_entry += 8;
+    }
+    if bytes_remaining > 0 && bytes_remaining < window_bytes {
+        let chunk = &content[base.min(content.len())..];
+        result.extend(lzx.decompress(chunk, bytes_remaining)?);
+        bytes_remaining = 0;
+    }
+    if bytes_remaining > 0 {
+        bail!("Failed to completely decompress section");
+    }
+    Ok(result)
+}
+
+/// Tidy converted XHTML the way Microsoft Reader's markup needs: add the
+/// XML declaration and XHTML namespace, drop Smart Tag wrappers and turn
+/// forms into plain divs.
+fn clean_html(html: &str) -> String {
+    lazy_static::lazy_static! {
+        static ref SMART_TAG: Regex =
+            Regex::new(r"(?i)</?st1:(personname|place|city|country-region)>").unwrap();
+        static ref FORM: Regex = Regex::new(r"<(/?)form>").unwrap();
+    }
+    let html = SMART_TAG.replace_all(html, "");
+    let mut html = FORM.replace_all(&html, "<${1}div>").into_owned();
+    if html.starts_with("<html") && !html[..html.find('>').unwrap_or(0)].contains("xmlns") {
+        html.insert_str(5, &format!(" xmlns=\"{}\"", XHTML_NS));
+    }
+    format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}", html)
+}
+
+/// Rewrite the OEB 1.0 package from `/meta` as an OPF 2.0 package.
+fn upgrade_opf(meta: &str, manifest: &[ManifestItem]) -> Result<String> {
+    // The dc prefix is not always declared in LIT packages
+    let mut meta = meta.to_string();
+    if !meta.contains("xmlns:dc=") {
+        if let Some(pos) = meta.find("<package") {
+            meta.insert_str(
+                pos + "<package".len(),
+                " xmlns:dc=\"http://purl.org/dc/elements/1.0/\"",
+            );
+        }
+    }
+    let doc = Document::parse(&meta).map_err(|e| anyhow!("XML Parse Error: {}", e))?;
+    let root = doc.root_element();
+    fn find<'a, 'input>(parent: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+        parent
+            .descendants()
+            .find(|n| n.is_element() && n.tag_name().name().eq_ignore_ascii_case(name))
+    }
+    fn is(node: &Node, name: &str) -> bool {
+        node.is_element() && node.tag_name().name() == name
+    }
+
+    let mut opf = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    opf.push_str(&format!("<package xmlns=\"{}\" version=\"2.0\"", OPF2_NS));
+    if let Some(uid) = root.attribute("unique-identifier") {
+        opf.push_str(&format!(" unique-identifier=\"{}\"", escape_xml(uid)));
+    }
+    opf.push_str(">\n");
+
+    opf.push_str(&format!(
+        "<metadata xmlns:dc=\"{}\" xmlns:opf=\"{}\">\n",
+        DC11_NS, OPF2_NS
+    ));
+    if let Some(metadata) = find(root, "metadata") {
+        for node in metadata.descendants().filter(|n| n.is_element()) {
+            let name = node.tag_name().name().to_lowercase();
+            let text = node.text().unwrap_or("").trim();
+            if node
+                .tag_name()
+                .namespace()
+                .is_some_and(|ns| ns.contains("purl.org/dc"))
+            {
+                if text.is_empty() {
+                    continue;
+                }
+                opf.push_str(&format!("<dc:{}", name));
+                if let Some(id) = node.attribute("id") {
+                    opf.push_str(&format!(" id=\"{}\"", escape_xml(id)));
+                }
+                for attr in ["role", "file-as", "event", "scheme"] {
+                    if let Some(value) = node.attribute(attr) {
+                        opf.push_str(&format!(" opf:{}=\"{}\"", attr, escape_xml(value)));
+                    }
+                }
+                opf.push_str(&format!(">{}</dc:{}>\n", escape_xml(text), name));
+            } else if name == "meta" {
+                if let (Some(n), Some(c)) = (node.attribute("name"), node.attribute("content")) {
+                    opf.push_str(&format!(
+                        "<meta name=\"{}\" content=\"{}\"/>\n",
+                        escape_xml(n),
+                        escape_xml(c)
+                    ));
+                }
+            }
+        }
+    }
+    opf.push_str("</metadata>\n");
+
+    // Media types come from the LIT manifest, which knows which items
+    // were stored as binary XHTML.
+    let media_type = |href: &str, declared: Option<&str>| -> String {
+        match manifest.iter().find(|item| item.path == href) {
+            Some(item) if item.is_html() => XHTML_MIME.to_string(),
+            Some(item) if item.state == ManifestState::Css => CSS_MIME.to_string(),
+            Some(item) if !item.mime_type.is_empty() => item.mime_type.clone(),
+            _ => declared.unwrap_or("application/octet-stream").to_string(),
+        }
+    };
+    let mut ids = HashSet::new();
+    let mut href_ids = HashMap::new();
+    opf.push_str("<manifest>\n");
+    if let Some(node) = find(root, "manifest") {
+        for item in node.children().filter(|n| is(n, "item")) {
+            if let (Some(id), Some(href)) = (item.attribute("id"), item.attribute("href")) {
+                if !ids.insert(id.to_string()) {
+                    continue;
+                }
+                href_ids.insert(href.to_string(), id.to_string());
+                opf.push_str(&format!(
+                    "<item id=\"{}\" href=\"{}\" media-type=\"{}\"/>\n",
+                    escape_xml(id),
+                    escape_xml(href),
+                    escape_xml(&media_type(href, item.attribute("media-type")))
+                ));
+            }
+        }
+    }
+    // Keep files the package forgot to list
+    for item in manifest {
+        if href_ids.contains_key(&item.path) {
+            continue;
+        }
+        let mut id = item.internal.clone();
+        while ids.contains(&id) {
+            id.push('_');
+        }
+        ids.insert(id.clone());
+        href_ids.insert(item.path.clone(), id.clone());
+        opf.push_str(&format!(
+            "<item id=\"{}\" href=\"{}\" media-type=\"{}\"/>\n",
+            escape_xml(&id),
+            escape_xml(&item.path),
+            escape_xml(&media_type(&item.path, None))
+        ));
+    }
+    opf.push_str("</manifest>\n");
+
+    opf.push_str("<spine>\n");
+    let mut spine: Vec<String> = find(root, "spine")
+        .map(|node| {
+            node.children()
+                .filter(|n| is(n, "itemref"))
+                .filter_map(|n| n.attribute("idref"))
+                .filter(|id| ids.contains(*id))
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    if spine.is_empty() {
+        spine = manifest
+            .iter()
+            .filter(|item| item.state == ManifestState::Spine)
+            .filter_map(|item| href_ids.get(&item.path).cloned())
+            .collect();
+    }
+    for idref in spine {
+        opf.push_str(&format!("<itemref idref=\"{}\"/>\n", escape_xml(&idref)));
+    }
+    opf.push_str("</spine>\n");
 
-        Ok(format!(
-            "LIT Content Extraction Not Fully Implemented (Version {})",
-            self.header.version
-        ))
+    if let Some(guide) = find(root, "guide") {
+        opf.push_str("<guide>\n");
+        for reference in guide.children().filter(|n| is(n, "reference")) {
+            if let (Some(type_), Some(href)) =
+                (reference.attribute("type"), reference.attribute("href"))
+            {
+                opf.push_str(&format!(
+                    "<reference type=\"{}\" href=\"{}\"",
+                    escape_xml(type_),
+                    escape_xml(href)
+                ));
+                if let Some(title) = reference.attribute("title") {
+                    opf.push_str(&format!(" title=\"{}\"", escape_xml(title)));
+                }
+                opf.push_str("/>\n");
+            }
+        }
+        opf.push_str("</guide>\n");
     }
+    opf.push_str("</package>\n");
+    Ok(opf)
 }
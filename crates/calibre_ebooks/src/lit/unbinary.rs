@@ -0,0 +1,436 @@
+//! Reconstruction of the binary-encoded XHTML and OPF stored in LIT files.
+//!
+//! LIT documents are a stream of UTF-8 characters in which a NUL starts a
+//! tag: a flags character, a tag code looked up in a [`Map`] (or in the
+//! per-document atom tables), then attribute code/value pairs ending in NUL.
+use crate::lit::maps::Map;
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+
+const FLAG_OPENING: u32 = 1 << 0;
+const FLAG_CLOSING: u32 = 1 << 1;
+const FLAG_ATOM: u32 = 1 << 4;
+
+/// Document-specific tag and attribute names, from `/data/<item>/atom`.
+#[derive(Debug, Default, Clone)]
+pub struct Atoms {
+    pub tags: HashMap<u32, String>,
+    pub attrs: HashMap<u32, String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Text,
+    GetFlags,
+    GetTag,
+    GetAttr,
+    GetValueLength,
+    GetValue,
+    GetCustomLength,
+    GetCustom,
+    GetAttrLength,
+    GetCustomAttr,
+    GetHrefLength,
+    GetHref,
+    CloseTag,
+}
+
+#[derive(Clone, Copy)]
+enum AttrTable {
+    Global,
+    Tag(u32),
+    Atoms,
+}
+
+struct Frame {
+    depth: usize,
+    tag_name: Option<String>,
+    attrs: AttrTable,
+    state: State,
+    flags: u32,
+}
+
+impl Frame {
+    fn new(depth: usize) -> Self {
+        Frame {
+            depth,
+            tag_name: None,
+            attrs: AttrTable::Global,
+            state: State::Text,
+            flags: 0,
+        }
+    }
+}
+
+struct UnBinary<'a> {
+    bin: &'a [u8],
+    cpos: usize,
+    map: &'static Map,
+    atoms: &'a Atoms,
+    paths: &'a HashMap<String, String>,
+    dir: Vec<&'a str>,
+    out: String,
+}
+
+/// Convert the binary document `bin`, stored at `path`, back to markup.
+///
+/// `paths` maps internal manifest ids to item paths; links are rewritten
+/// relative to `path`.
+pub fn unbinary(
+    bin: &[u8],
+    path: &str,
+    paths: &HashMap<String, String>,
+    map: &'static Map,
+    atoms: &Atoms,
+) -> Result<String> {
+    let dir = match path.rfind('/') {
+        Some(i) => path[..i].split('/').collect(),
+        None => Vec::new(),
+    };
+    let mut unbin = UnBinary {
+        bin,
+        cpos: 0,
+        map,
+        atoms,
+        paths,
+        dir,
+        out: String::new(),
+    };
+    let mut stack = vec![Frame::new(0)];
+    while let Some(frame) = stack.pop() {
+        unbin.run(frame, &mut stack)?;
+    }
+    Ok(unbin.out.trim_start().to_string())
+}
+
+/// Decode one UTF-8 style character, which may encode values outside the
+/// Unicode range (LIT uses 0x8000 and 0xffff as markers).
+pub(crate) fn read_utf8_char(bytes: &[u8], pos: usize) -> Result<(u32, usize)> {
+    let first = *bytes
+        .get(pos)
+        .ok_or_else(|| anyhow!("Invalid UTF8 character at end of data"))?;
+    let mut c = first as u32;
+    let mut mask = 0x80u32;
+    if c & mask == 0 {
+        return Ok((c, pos + 1));
+    }
+    let mut elsize = 0;
+    while c & mask != 0 {
+        mask >>= 1;
+        elsize += 1;
+    }
+    if mask <= 1 || mask == 0x40 || pos + elsize > bytes.len() {
+        bail!("Invalid UTF8 character: {:#x}", first);
+    }
+    c &= mask - 1;
+    for &b in &bytes[pos + 1..pos + elsize] {
+        if b & 0xC0 != 0x80 {
+            bail!("Invalid UTF8 character: {:#x}", first);
+        }
+        c = (c << 6) | (b & 0x3F) as u32;
+    }
+    Ok((c, pos + elsize))
+}
+
+impl UnBinary<'_> {
+    fn remaining(&self) -> i64 {
+        (self.bin.len() - self.cpos) as i64
+    }
+
+    fn check_count(&self, count: i64) -> Result<()> {
+        if count <= 0 || count > self.remaining() {
+            bail!("Invalid character count {}", count);
+        }
+        Ok(())
+    }
+
+    /// Whether the text at the read position completes an entity that
+    /// follows an `&`.
+    fn at_entity(&self) -> bool {
+        let rest = &self.bin[self.cpos..];
+        let end = match rest.iter().take(32).position(|&b| b == b';') {
+            Some(end) => end,
+            None => return false,
+        };
+        let name = &rest[..end];
+        match name {
+            [b'#', b'x', hex @ ..] if !hex.is_empty() => hex.iter().all(u8::is_ascii_hexdigit),
+            [b'#', dec @ ..] if !dec.is_empty() => dec.iter().all(u8::is_ascii_digit),
+            [first, more @ ..] if !more.is_empty() => {
+                (first.is_ascii_alphabetic() || *first == b'_' || *first == b':')
+                    && more
+                        .iter()
+                        .all(|b| b.is_ascii_alphanumeric() || b"._:-".contains(b))
+            }
+            _ => false,
+        }
+    }
+
+    fn attr_name(&self, table: AttrTable, code: u32) -> Option<String> {
+        match table {
+            AttrTable::Atoms => self.atoms.attrs.get(&code).cloned(),
+            AttrTable::Tag(tag) => self.map.attr(Some(tag), code).map(str::to_string),
+            AttrTable::Global => None,
+        }
+        .or_else(|| self.map.attr(None, code).map(str::to_string))
+    }
+
+    /// The path of the manifest item `internal`, relative to this document.
+    fn item_path(&self, internal: &str) -> String {
+        let target = match self.paths.get(internal) {
+            Some(target) => target,
+            None => return internal.to_string(),
+        };
+        if self.dir.is_empty() {
+            return target.clone();
+        }
+        let target: Vec<&str> = target.split('/').collect();
+        let shared = self
+            .dir
+            .iter()
+            .zip(&target)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let mut rel = vec![".."; self.dir.len() - shared];
+        rel.extend(&target[shared..]);
+        rel.join("/")
+    }
+
+    fn run(&mut self, mut f: Frame, stack: &mut Vec<Frame>) -> Result<()> {
+        let mut count: i64 = 0;
+        let mut href = String::new();
+        let mut going_down = false;
+        let mut censored = false;
+
+        if f.state == State::CloseTag {
+            let name = f
+                .tag_name
+                .take()
+                .ok_or_else(|| anyhow!("Tag ends before it begins."))?;
+            self.out.push_str("</");
+            self.out.push_str(&name);
+            self.out.push('>');
+            f.state = State::Text;
+        }
+
+        while self.cpos < self.bin.len() {
+            let (oc, pos) = read_utf8_char(self.bin, self.cpos)?;
+            self.cpos = pos;
+            let c = char::from_u32(oc).unwrap_or('\u{FFFD}');
+
+            match f.state {
+                State::Text => match c {
+                    '\0' => f.state = State::GetFlags,
+                    '\x0b' => self.out.push('\n'),
+                    // Comments are passed through unescaped
+                    '<' if self.bin[self.cpos..].starts_with(b"!--") => self.out.push('<'),
+                    '<' => self.out.push_str("&lt;"),
+                    '>' if self.out.ends_with("--") => self.out.push('>'),
+                    '>' => self.out.push_str("&gt;"),
+                    '&' if self.at_entity() => self.out.push('&'),
+                    '&' => self.out.push_str("&amp;"),
+                    c => self.out.push(c),
+                },
+                State::GetFlags => {
+                    if oc == 0 {
+                        f.state = State::Text;
+                        continue;
+                    }
+                    f.flags = oc;
+                    f.state = State::GetTag;
+                }
+                State::GetTag => {
+                    f.state = if oc == 0 { State::Text } else { State::GetAttr };
+                    if f.flags & FLAG_OPENING != 0 {
+                        self.out.push('<');
+                        if f.flags & FLAG_CLOSING == 0 {
+                            going_down = true;
+                        }
+                        if oc == 0x8000 {
+                            f.state = State::GetCustomLength;
+                            continue;
+                        }
+                        let name =
+                            if f.flags & FLAG_ATOM != 0 {
+                                f.attrs = AttrTable::Atoms;
+                                self.atoms.tags.get(&oc).cloned().ok_or_else(|| {
+                                    anyhow!("atom tag {} not in atom tag list", oc)
+                                })?
+                            } else {
+                                f.attrs = AttrTable::Tag(oc);
+                                self.map
+                                    .tag(oc)
+                                    .ok_or_else(|| anyhow!("Unknown tag {}", oc))?
+                                    .to_string()
+                            };
+                        self.out.push_str(&name);
+                        f.tag_name = Some(name);
+                    } else if f.flags & FLAG_CLOSING != 0 {
+                        if f.depth == 0 {
+                            bail!("Extra closing tag at {}", self.cpos);
+                        }
+                        return Ok(());
+                    }
+                }
+                State::GetAttr => {
+                    censored = false;
+                    if oc == 0 {
+                        f.state = State::Text;
+                        if !going_down {
+                            f.tag_name = None;
+                            self.out.push_str(" />");
+                        } else {
+                            self.out.push('>');
+                            stack.push(Frame {
+                                depth: f.depth,
+                                tag_name: f.tag_name.take(),
+                                attrs: f.attrs,
+                                state: State::CloseTag,
+                                flags: f.flags,
+                            });
+                            stack.push(Frame::new(f.depth + 1));
+                            return Ok(());
+                        }
+                    } else {
+                        if oc == 0x8000 {
+                            f.state = State::GetAttrLength;
+                            continue;
+                        }
+                        let attr = self.attr_name(f.attrs, oc).ok_or_else(|| {
+                            anyhow!(
+                                "Unknown attribute {} in tag {}",
+                                oc,
+                                f.tag_name.as_deref().unwrap_or("")
+                            )
+                        })?;
+                        // Censored attributes are read and dropped
+                        if attr.starts_with('%') {
+                            censored = true;
+                            f.state = State::GetValueLength;
+                            continue;
+                        }
+                        self.out.push(' ');
+                        self.out.push_str(&attr);
+                        self.out.push('=');
+                        f.state = if attr == "href" || attr == "src" {
+                            State::GetHrefLength
+                        } else {
+                            State::GetValueLength
+                        };
+                    }
+                }
+                State::GetValueLength => {
+                    if !censored {
+                        self.out.push('"');
+                    }
+                    count = oc as i64 - 1;
+                    if count == 0 {
+                        if !censored {
+                            self.out.push('"');
+                        }
+                        censored = false;
+                        f.state = State::GetAttr;
+                        continue;
+                    }
+                    f.state = State::GetValue;
+                    if oc == 0xffff {
+                        continue;
+                    }
+                    if count < 0 || count > self.remaining() {
+                        bail!("Invalid character count {}", count);
+                    }
+                }
+                State::GetValue => {
+                    if count == 0xfffe {
+                        // A numeric value stored as a single character
+                        if !censored {
+                            self.out.push_str(&format!("{}\"", oc as i64 - 1));
+                        }
+                        censored = false;
+                        f.state = State::GetAttr;
+                    } else if count > 0 {
+                        if !censored {
+                            match c {
+                                '"' => self.out.push_str("&quot;"),
+                                '<' => self.out.push_str("&lt;"),
+                                '&' if self.at_entity() => self.out.push('&'),
+                                '&' => self.out.push_str("&amp;"),
+                                c => self.out.push(c),
+                            }
+                        }
+                        count -= 1;
+                    }
+                    if count == 0 {
+                        if !censored {
+                            self.out.push('"');
+                        }
+                        censored = false;
+                        f.state = State::GetAttr;
+                    }
+                }
+                State::GetCustomLength => {
+                    count = oc as i64 - 1;
+                    self.check_count(count)?;
+                    f.attrs = AttrTable::Global;
+                    f.tag_name = Some(String::new());
+                    f.state = State::GetCustom;
+                }
+                State::GetCustom => {
+                    let name = f.tag_name.get_or_insert_with(String::new);
+                    name.push(c);
+                    count -= 1;
+                    if count == 0 {
+                        self.out.push_str(name);
+                        f.state = State::GetAttr;
+                    }
+                }
+                State::GetAttrLength => {
+                    count = oc as i64 - 1;
+                    self.check_count(count)?;
+                    self.out.push(' ');
+                    f.state = State::GetCustomAttr;
+                }
+                State::GetCustomAttr => {
+                    self.out.push(c);
+                    count -= 1;
+                    if count == 0 {
+                        self.out.push('=');
+                        f.state = State::GetValueLength;
+                    }
+                }
+                State::GetHrefLength => {
+                    count = oc as i64 - 1;
+                    self.check_count(count)?;
+                    href.clear();
+                    f.state = State::GetHref;
+                }
+                State::GetHref => {
+                    href.push(c);
+                    count -= 1;
+                    if count == 0 {
+                        // The first character is a type marker, not part of the link
+                        let link: String = href.chars().skip(1).collect();
+                        let path = match link.split_once('#') {
+                            Some((doc, frag)) if !frag.is_empty() => {
+                                format!("{}#{}", self.item_path(doc), frag)
+                            }
+                            Some((doc, _)) => self.item_path(doc),
+                            None => self.item_path(&link),
+                        };
+                        self.out.push('"');
+                        self.out.push_str(
+                            &path
+                                .replace('&', "&amp;")
+                                .replace('"', "&quot;")
+                                .replace('<', "&lt;"),
+                        );
+                        self.out.push('"');
+                        f.state = State::GetAttr;
+                    }
+                }
+                State::CloseTag => unreachable!("close tags are handled on entry"),
+            }
+        }
+        Ok(())
+    }
+}
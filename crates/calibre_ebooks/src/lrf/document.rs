@@ -0,0 +1,90 @@
+use crate::lrf::header::{BookInfo, LrfHeader};
+use crate::lrf::objects::{toc_entries, LrfObject, ObjectType, TocLabel};
+use crate::lrf::tags::u32_at;
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+
+/// A parsed LRF file: the header, the document information and every
+/// object of the object table.
+pub struct LrfDocument {
+    pub header: LrfHeader,
+    pub info: BookInfo,
+    pub objects: HashMap<u32, LrfObject>,
+    /// Object ids in object table order
+    pub order: Vec<u32>,
+    pub page_trees: Vec<u32>,
+    pub toc_id: Option<u32>,
+    pub thumbnail: Option<(&'static str, Vec<u8>)>,
+}
+
+impl LrfDocument {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let header = LrfHeader::parse(data)?;
+        let info = header.read_info(data).unwrap_or_default();
+        let thumbnail = header
+            .thumbnail(data)
+            .map(|(ext, thumb)| (ext, thumb.to_vec()));
+
+        let mut doc = LrfDocument {
+            header,
+            info,
+            objects: HashMap::new(),
+            order: Vec::new(),
+            page_trees: Vec::new(),
+            toc_id: None,
+            thumbnail,
+        };
+        for (id, offset, size) in object_table(data, &doc.header)? {
+            let obj = LrfObject::parse(data, offset, size, doc.header.xor_key)
+                .with_context(|| format!("Failed to parse object {}", id))?;
+            match obj.kind {
+                ObjectType::PageTree => doc.page_trees.push(obj.id),
+                ObjectType::Toc => doc.toc_id = Some(obj.id),
+                _ => {}
+            }
+            doc.order.push(obj.id);
+            doc.objects.insert(obj.id, obj);
+        }
+        Ok(doc)
+    }
+
+    pub fn object(&self, id: u32) -> Option<&LrfObject> {
+        self.objects.get(&id)
+    }
+
+    /// Page ids in reading order, page tree by page tree.
+    pub fn pages(&self) -> Vec<u32> {
+        self.page_trees
+            .iter()
+            .filter_map(|id| self.object(*id))
+            .flat_map(|tree| tree.children().iter().copied())
+            .filter(|id| self.object(*id).is_some_and(|p| p.kind == ObjectType::Page))
+            .collect()
+    }
+
+    pub fn toc(&self) -> Result<Vec<TocLabel>> {
+        match self.toc_id.and_then(|id| self.object(id)) {
+            Some(toc) => toc_entries(&toc.stream),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+/// The `(id, offset, size)` entries of the object table.
+pub fn object_table(data: &[u8], header: &LrfHeader) -> Result<Vec<(u32, usize, usize)>> {
+    let start = header.object_index_offset as usize;
+    let count = header.number_of_objects as usize;
+    if count.saturating_mul(16) > data.len().saturating_sub(start) {
+        bail!("LRF object table is truncated");
+    }
+    (0..count)
+        .map(|i| {
+            let pos = start + i * 16;
+            Ok((
+                u32_at(data, pos)?,
+                u32_at(data, pos + 4)? as usize,
+                u32_at(data, pos + 8)? as usize,
+            ))
+        })
+        .collect()
+}
@@ -0,0 +1,174 @@
+use crate::lrf::tags::{u16_at, u32_at, u64_at};
+use anyhow::{bail, Context, Result};
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+
+/// "LRF\0" in UTF-16LE
+pub const LRF_MAGIC: &[u8] = b"L\x00R\x00F\x00\x00\x00";
+
+/// The fixed size header at the start of every LRF file.
+#[derive(Debug, Clone)]
+pub struct LrfHeader {
+    pub version: u16,
+    pub xor_key: u16,
+    pub root_object_id: u32,
+    pub number_of_objects: u64,
+    pub object_index_offset: u64,
+    pub binding: u8,
+    pub dpi: u16,
+    pub width: u16,
+    pub height: u16,
+    pub color_depth: u8,
+    pub toc_object_id: u32,
+    pub toc_object_offset: u32,
+    pub compressed_info_size: u16,
+    pub thumbnail_type: u16,
+    pub thumbnail_size: u32,
+    pub uncompressed_info_size: u32,
+}
+
+impl LrfHeader {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if !data.starts_with(LRF_MAGIC) {
+            bail!("Not a valid LRF file");
+        }
+        let version = u16_at(data, 0x8)?;
+        let compressed_info_size = u16_at(data, 0x4c)?;
+        // The thumbnail fields only exist from version 800 onwards
+        let (thumbnail_type, thumbnail_size) = if version > 800 {
+            (u16_at(data, 0x4e)?, u32_at(data, 0x50)?)
+        } else {
+            (0, 0)
+        };
+        let uncompressed_info_size = if compressed_info_size > 0 {
+            u32_at(data, 0x54)?
+        } else {
+            0
+        };
+        Ok(LrfHeader {
+            version,
+            xor_key: u16_at(data, 0xa)?,
+            root_object_id: u32_at(data, 0xc)?,
+            number_of_objects: u64_at(data, 0x10)?,
+            object_index_offset: u64_at(data, 0x18)?,
+            binding: data[0x24],
+            dpi: u16_at(data, 0x26)?,
+            width: u16_at(data, 0x2a)?,
+            height: u16_at(data, 0x2c)?,
+            color_depth: data[0x2e],
+            toc_object_id: u32_at(data, 0x44)?,
+            toc_object_offset: u32_at(data, 0x48)?,
+            compressed_info_size,
+            thumbnail_type,
+            thumbnail_size,
+            uncompressed_info_size,
+        })
+    }
+
+    fn info_start(&self) -> usize {
+        if self.version > 800 {
+            0x58
+        } else {
+            0x53
+        }
+    }
+
+    /// Decompress and parse the document information XML that follows the
+    /// header.
+    pub fn read_info(&self, data: &[u8]) -> Result<BookInfo> {
+        if self.compressed_info_size < 4 {
+            bail!("This document has no meta info");
+        }
+        let start = self.info_start();
+        let end = start + self.compressed_info_size as usize - 4;
+        let raw = data
+            .get(start..end)
+            .context("Document meta info is truncated")?;
+        let mut xml = Vec::new();
+        ZlibDecoder::new(raw)
+            .read_to_end(&mut xml)
+            .context("Unable to decompress document meta information")?;
+        if xml.len() != self.uncompressed_info_size as usize {
+            bail!("Decompression of document meta info yielded unexpected results");
+        }
+        BookInfo::parse(&String::from_utf8_lossy(&xml))
+    }
+
+    /// The thumbnail stored after the document information, with its file
+    /// extension.
+    pub fn thumbnail<'a>(&self, data: &'a [u8]) -> Option<(&'static str, &'a [u8])> {
+        if self.thumbnail_size == 0 || self.compressed_info_size < 4 {
+            return None;
+        }
+        let start = self.info_start() + self.compressed_info_size as usize - 4;
+        let thumb = data.get(start..start + self.thumbnail_size as usize)?;
+        let ext = match self.thumbnail_type {
+            0x11 => "jpg",
+            0x12 => "png",
+            0x13 => "bmp",
+            _ => "gif",
+        };
+        Some((ext, thumb))
+    }
+}
+
+/// The `BookInfo` and `DocInfo` fields of the document information XML.
+/// Missing fields are empty.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BookInfo {
+    pub title: String,
+    pub title_reading: String,
+    pub author: String,
+    pub author_reading: String,
+    pub book_id: String,
+    pub publisher: String,
+    pub label: String,
+    pub category: String,
+    pub classification: String,
+    pub free_text: String,
+    pub language: String,
+    pub creator: String,
+    pub creation_date: String,
+    pub producer: String,
+    pub page: String,
+}
+
+impl BookInfo {
+    pub fn parse(xml: &str) -> Result<Self> {
+        let doc = roxmltree::Document::parse(xml.trim_start_matches('\u{feff}'))
+            .context("Invalid document meta info")?;
+        let section = |name: &str| doc.descendants().find(|n| n.has_tag_name(name));
+        let book_info = section("BookInfo");
+        let doc_info = section("DocInfo");
+        let field = |parent: Option<roxmltree::Node>, name: &str| {
+            parent
+                .and_then(|p| p.children().find(|n| n.has_tag_name(name)))
+                .map(|n| {
+                    (
+                        n.text().unwrap_or("").trim().to_string(),
+                        n.attribute("reading").unwrap_or("").trim().to_string(),
+                    )
+                })
+                .unwrap_or_default()
+        };
+        let (title, title_reading) = field(book_info, "Title");
+        let (author, author_reading) = field(book_info, "Author");
+        Ok(BookInfo {
+            title,
+            title_reading,
+            author,
+            author_reading,
+            book_id: field(book_info, "BookID").0,
+            publisher: field(book_info, "Publisher").0,
+            label: field(book_info, "Label").0,
+            category: field(book_info, "Category").0,
+            classification: field(book_info, "Classification").0,
+            free_text: field(book_info, "FreeText").0,
+            language: field(doc_info, "Language").0,
+            creator: field(doc_info, "Creator").0,
+            creation_date: field(doc_info, "CreationDate").0,
+            producer: field(doc_info, "Producer").0,
+            page: field(doc_info, "SumPage").0,
+        })
+    }
+}
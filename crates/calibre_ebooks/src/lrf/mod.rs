@@ -0,0 +1,6 @@
+pub mod document;
+pub mod header;
+pub mod objects;
+pub mod tags;
+pub mod text;
+pub mod to_html;
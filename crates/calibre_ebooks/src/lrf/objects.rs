@@ -0,0 +1,400 @@
+use crate::lrf::tags::{read_string, u16_at, u32_at, Tag};
+use anyhow::{bail, Context, Result};
+use flate2::read::ZlibDecoder;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectType {
+    PageTree,
+    Page,
+    Header,
+    Footer,
+    PageAttr,
+    Block,
+    BlockAttr,
+    MiniPage,
+    Text,
+    TextAttr,
+    Image,
+    Canvas,
+    ESound,
+    ImageStream,
+    Import,
+    Button,
+    Window,
+    PopUpWin,
+    Sound,
+    SoundStream,
+    Font,
+    ObjectInfo,
+    BookAttr,
+    SimpleText,
+    Toc,
+}
+
+impl ObjectType {
+    pub fn from_code(code: u16) -> Option<Self> {
+        use ObjectType::*;
+        Some(match code {
+            0x01 => PageTree,
+            0x02 => Page,
+            0x03 => Header,
+            0x04 => Footer,
+            0x05 => PageAttr,
+            0x06 => Block,
+            0x07 => BlockAttr,
+            0x08 => MiniPage,
+            0x0A => Text,
+            0x0B => TextAttr,
+            0x0C => Image,
+            0x0D => Canvas,
+            0x0E => ESound,
+            0x11 => ImageStream,
+            0x12 => Import,
+            0x13 => Button,
+            0x14 => Window,
+            0x15 => PopUpWin,
+            0x16 => Sound,
+            0x17 => SoundStream,
+            0x19 => Font,
+            0x1A => ObjectInfo,
+            0x1C => BookAttr,
+            0x1D => SimpleText,
+            0x1E => Toc,
+            _ => return None,
+        })
+    }
+}
+
+/// Numeric style attributes keyed by their LRS names, e.g. `fontsize`.
+pub type Attrs = BTreeMap<&'static str, i64>;
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Word,
+    SignedWord,
+    DWord,
+}
+
+const TEXT_ATTRS: &[(u16, &str, Kind)] = &[
+    (0xF511, "fontsize", Kind::SignedWord),
+    (0xF512, "fontwidth", Kind::SignedWord),
+    (0xF513, "fontescapement", Kind::SignedWord),
+    (0xF514, "fontorientation", Kind::SignedWord),
+    (0xF515, "fontweight", Kind::Word),
+    (0xF517, "textcolor", Kind::DWord),
+    (0xF518, "textbgcolor", Kind::DWord),
+    (0xF519, "wordspace", Kind::SignedWord),
+    (0xF51A, "letterspace", Kind::SignedWord),
+    (0xF51B, "baselineskip", Kind::SignedWord),
+    (0xF51C, "linespace", Kind::SignedWord),
+    (0xF51D, "parindent", Kind::SignedWord),
+    (0xF51E, "parskip", Kind::SignedWord),
+    (0xF53C, "align", Kind::Word),
+    (0xF53D, "column", Kind::Word),
+    (0xF53E, "columnsep", Kind::Word),
+    (0xF5DD, "charspace", Kind::SignedWord),
+    (0xF5F1, "textlinewidth", Kind::Word),
+    (0xF5F2, "linecolor", Kind::DWord),
+    (0xF575, "rubyalignandadjust", Kind::Word),
+    (0xF576, "rubyoverhang", Kind::Word),
+    (0xF577, "empdotsposition", Kind::Word),
+    (0xF579, "emplineposition", Kind::Word),
+    (0xF57A, "emplinetype", Kind::Word),
+];
+
+const BLOCK_ATTRS: &[(u16, &str, Kind)] = &[
+    (0xF531, "blockwidth", Kind::Word),
+    (0xF532, "blockheight", Kind::Word),
+    (0xF533, "blockrule", Kind::Word),
+    (0xF534, "bgcolor", Kind::DWord),
+    (0xF535, "layout", Kind::Word),
+    (0xF536, "framewidth", Kind::Word),
+    (0xF537, "framecolor", Kind::DWord),
+    (0xF52E, "framemode", Kind::Word),
+    (0xF538, "topskip", Kind::Word),
+    (0xF539, "sidemargin", Kind::Word),
+    (0xF53A, "footskip", Kind::Word),
+];
+
+fn attr_value(table: &[(u16, &'static str, Kind)], tag: &Tag) -> Option<(&'static str, i64)> {
+    let (_, name, kind) = table.iter().find(|(id, _, _)| *id == tag.id)?;
+    let value = match kind {
+        Kind::Word => tag.word().ok()? as i64,
+        Kind::SignedWord => tag.sword().ok()? as i64,
+        Kind::DWord => tag.dword().ok()? as i64,
+    };
+    Some((name, value))
+}
+
+/// Whether `id` is a text attribute tag that may appear inside text streams.
+pub fn is_text_attr(id: u16) -> bool {
+    TEXT_ATTRS.iter().any(|(t, _, _)| *t == id)
+}
+
+pub fn text_attr(tag: &Tag) -> Option<(&'static str, i64)> {
+    attr_value(TEXT_ATTRS, tag)
+}
+
+/// One object from the object table: its property tags and, for stream
+/// objects, the descrambled and decompressed stream.
+#[derive(Debug, Clone)]
+pub struct LrfObject {
+    pub id: u32,
+    pub kind: ObjectType,
+    pub tags: Vec<Tag>,
+    pub stream_flags: u16,
+    pub stream: Vec<u8>,
+}
+
+impl LrfObject {
+    pub fn parse(data: &[u8], offset: usize, size: usize, xor_key: u16) -> Result<Self> {
+        let mut pos = offset;
+        let start = Tag::read(data, &mut pos)?;
+        if start.id != 0xF500 {
+            bail!("Bad object start at {:08X}", offset);
+        }
+        let contents = start.bytes();
+        let id = u32::from_le_bytes([contents[0], contents[1], contents[2], contents[3]]);
+        let code = u16::from_le_bytes([contents[4], contents[5]]);
+        let kind = ObjectType::from_code(code)
+            .with_context(|| format!("Unknown object type: {:02X}!", code))?;
+
+        let mut obj = LrfObject {
+            id,
+            kind,
+            tags: Vec::new(),
+            stream_flags: 0,
+            stream: Vec::new(),
+        };
+        let end = (offset + size).min(data.len());
+        let mut stream_size = 0usize;
+        let mut stream_read = false;
+        while pos < end {
+            let tag = Tag::read(data, &mut pos)?;
+            match tag.id {
+                0xF501 => break,
+                0xF504 => stream_size = tag.dword()? as usize,
+                0xF554 => obj.stream_flags = tag.word()?,
+                0xF505 => {
+                    if stream_read {
+                        bail!("There can be only one stream per object");
+                    }
+                    let raw = data
+                        .get(pos..pos + stream_size)
+                        .context("Object stream is truncated")?;
+                    obj.stream = obj.decode_stream(raw, xor_key)?;
+                    // The stream is followed by an 0xF506 end tag
+                    pos += stream_size + 2;
+                    stream_read = true;
+                }
+                0xF506 => stream_read = true,
+                // Buttons that send messages carry two strings after the tag
+                0xF56D => {
+                    read_string(data, &mut pos)?;
+                    read_string(data, &mut pos)?;
+                    obj.tags.push(tag);
+                }
+                _ => obj.tags.push(tag),
+            }
+        }
+        Ok(obj)
+    }
+
+    fn decode_stream(&self, raw: &[u8], xor_key: u16) -> Result<Vec<u8>> {
+        let mut stream = raw.to_vec();
+        if self.stream_flags & 0x200 != 0 {
+            let mut len = stream.len();
+            let key = (xor_key & 0xFF) as usize;
+            let key = if key != 0 && key <= 0xF0 {
+                (len % key + 0xF) as u8
+            } else {
+                0
+            };
+            // Only the start of binary streams is scrambled
+            if len > 0x400
+                && matches!(
+                    self.kind,
+                    ObjectType::ImageStream | ObjectType::Font | ObjectType::SoundStream
+                )
+            {
+                len = 0x400;
+            }
+            for b in &mut stream[..len] {
+                *b ^= key;
+            }
+        }
+        if self.stream_flags & 0x100 != 0 {
+            let expected = u32_at(&stream, 0)? as usize;
+            let mut out = Vec::with_capacity(expected);
+            ZlibDecoder::new(&stream[4..])
+                .read_to_end(&mut out)
+                .context("Failed to decompress object stream")?;
+            if out.len() != expected {
+                bail!("Stream decompressed size is wrong!");
+            }
+            stream = out;
+        }
+        Ok(stream)
+    }
+
+    /// The last occurrence of the tag `id` in the object's properties.
+    pub fn tag(&self, id: u16) -> Option<&Tag> {
+        self.tags.iter().rev().find(|t| t.id == id)
+    }
+
+    pub fn word(&self, id: u16) -> Option<u16> {
+        self.tag(id).and_then(|t| t.word().ok())
+    }
+
+    pub fn dword(&self, id: u16) -> Option<u32> {
+        self.tag(id).and_then(|t| t.dword().ok())
+    }
+
+    /// The style object linked through the 0xF503 tag.
+    pub fn style_id(&self) -> Option<u32> {
+        self.dword(0xF503)
+    }
+
+    pub fn text_attrs(&self) -> Attrs {
+        self.tags
+            .iter()
+            .filter_map(|t| attr_value(TEXT_ATTRS, t))
+            .collect()
+    }
+
+    pub fn block_attrs(&self) -> Attrs {
+        self.tags
+            .iter()
+            .filter_map(|t| attr_value(BLOCK_ATTRS, t))
+            .collect()
+    }
+
+    /// Child object ids of a page tree or page.
+    pub fn children(&self) -> &[u32] {
+        self.tag(0xF55C)
+            .or_else(|| self.tag(0xF50B))
+            .map(|t| t.ids())
+            .unwrap_or(&[])
+    }
+
+    /// File extension of an image stream, from the low byte of its flags.
+    pub fn image_extension(&self) -> Option<&'static str> {
+        match self.stream_flags & 0xFF {
+            0x11 => Some("jpeg"),
+            0x12 => Some("png"),
+            0x13 => Some("bmp"),
+            0x14 => Some("gif"),
+            _ => None,
+        }
+    }
+
+    /// The `(xsize, ysize)` of an image object.
+    pub fn image_size(&self) -> Option<(u16, u16)> {
+        let b = self.tag(0xF54B)?.bytes();
+        if b.len() != 4 {
+            return None;
+        }
+        Some((u16_at(b, 0).ok()?, u16_at(b, 2).ok()?))
+    }
+
+    /// The `(refpage, refobj)` that a push button jumps to.
+    pub fn jump_target(&self) -> Option<(u32, u32)> {
+        let mut button_type = 0;
+        for tag in &self.tags {
+            match tag.id {
+                0xF562 => button_type = 0,
+                0xF564 => button_type = 1,
+                0xF566 => button_type = 2,
+                0xF568 => button_type = 3,
+                0xF56C if button_type == 2 => {
+                    let b = tag.bytes();
+                    return Some((u32_at(b, 0).ok()?, u32_at(b, 4).ok()?));
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    pub fn font_face_name(&self) -> Option<&str> {
+        self.tag(0xF55D).and_then(|t| t.text())
+    }
+}
+
+/// What a page stream places on the page, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PageElement {
+    Object(u32),
+    RuledLine,
+}
+
+pub fn page_contents(stream: &[u8]) -> Result<Vec<PageElement>> {
+    let mut contents = Vec::new();
+    let mut pos = 0;
+    while pos < stream.len() {
+        let tag = Tag::read(stream, &mut pos)?;
+        match tag.id {
+            0xF503 => contents.push(PageElement::Object(tag.dword()?)),
+            0xF573 => contents.push(PageElement::RuledLine),
+            // Spacing, positioning, waits and page dividers have no HTML
+            // equivalent
+            _ => {}
+        }
+    }
+    Ok(contents)
+}
+
+/// The `(x1, y1, refobj)` objects placed on a canvas.
+pub fn canvas_contents(stream: &[u8]) -> Result<Vec<(u16, u16, u32)>> {
+    let mut contents = Vec::new();
+    let mut pos = 0;
+    while pos < stream.len() {
+        let tag = Tag::read(stream, &mut pos)?;
+        let b = tag.bytes();
+        if b.len() == 8 {
+            contents.push((u16_at(b, 0)?, u16_at(b, 2)?, u32_at(b, 4)?));
+        }
+    }
+    Ok(contents)
+}
+
+/// The object a block displays, from the first tag of its stream.
+pub fn block_content(stream: &[u8]) -> Result<u32> {
+    let mut pos = 0;
+    let tag = Tag::read(stream, &mut pos)?;
+    if tag.id != 0xF503 {
+        bail!("Bad block content");
+    }
+    tag.dword()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TocLabel {
+    pub refpage: u32,
+    pub refobj: u32,
+    pub label: String,
+}
+
+pub fn toc_entries(stream: &[u8]) -> Result<Vec<TocLabel>> {
+    if stream.is_empty() {
+        return Ok(Vec::new());
+    }
+    let count = u16_at(stream, 0)? as usize;
+    // Skip the entry count and the table of entry offsets
+    let mut pos = 4 * (count + 1);
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        let refpage = u32_at(stream, pos)?;
+        let refobj = u32_at(stream, pos + 4)?;
+        pos += 8;
+        let label = read_string(stream, &mut pos)?;
+        entries.push(TocLabel {
+            refpage,
+            refobj,
+            label,
+        });
+    }
+    Ok(entries)
+}
@@ -0,0 +1,260 @@
+use anyhow::{bail, Result};
+
+/// How the payload following a tag id is laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Payload {
+    Fixed(usize),
+    /// A length prefixed UTF-16 string
+    Text,
+    /// A count prefixed list of object ids
+    IdList,
+    /// Tag 0xF578: font id, an embedded 0xF516 font name and a code
+    EmpDots,
+}
+
+fn payload(id: u8) -> Option<Payload> {
+    use Payload::*;
+    Some(match id {
+        0x01
+        | 0x05
+        | 0x06
+        | 0x4D
+        | 0x62..=0x6B
+        | 0x6E
+        | 0x71
+        | 0x72
+        | 0x81
+        | 0x82
+        | 0xA2
+        | 0xA6
+        | 0xA8..=0xAE
+        | 0xB1..=0xBE
+        | 0xC1
+        | 0xC2
+        | 0xC4
+        | 0xC7
+        | 0xC9
+        | 0xD2
+        | 0xD6 => Fixed(0),
+        0x0D
+        | 0x0E
+        | 0x11..=0x15
+        | 0x19..=0x1E
+        | 0x21..=0x28
+        | 0x2A..=0x2C
+        | 0x2E
+        | 0x31..=0x33
+        | 0x35
+        | 0x36
+        | 0x38..=0x3A
+        | 0x3C..=0x3E
+        | 0x41
+        | 0x42
+        | 0x46..=0x48
+        | 0x51
+        | 0x52
+        | 0x54
+        | 0x56..=0x58
+        | 0x5E
+        | 0x61
+        | 0x6D
+        | 0x75..=0x77
+        | 0x79
+        | 0x7A
+        | 0xC3
+        | 0xC5
+        | 0xC6
+        | 0xC8
+        | 0xCA
+        | 0xCC
+        | 0xD4
+        | 0xDA..=0xDD
+        | 0xF1
+        | 0xF4 => Fixed(2),
+        0x02..=0x04
+        | 0x07..=0x0A
+        | 0x17
+        | 0x18
+        | 0x2D
+        | 0x34
+        | 0x37
+        | 0x44
+        | 0x45
+        | 0x4B
+        | 0x4C
+        | 0x53
+        | 0x5B
+        | 0x7B
+        | 0x7C
+        | 0xA1
+        | 0xA7
+        | 0xD8
+        | 0xF2
+        | 0xF3
+        | 0xF5..=0xF8 => Fixed(4),
+        0x00 | 0x29 | 0xF9 => Fixed(6),
+        0x49 | 0x4A | 0x6C | 0xD9 => Fixed(8),
+        0x73 => Fixed(10),
+        0x4E | 0xD1 => Fixed(12),
+        0xD7 => Fixed(14),
+        0x16 | 0x55 | 0x59 | 0x5A | 0x5D => Text,
+        0x0B | 0x5C => IdList,
+        0x78 => EmpDots,
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagValue {
+    Bytes(Vec<u8>),
+    Text(String),
+    Ids(Vec<u32>),
+    EmpDots {
+        font_id: u32,
+        font_name: String,
+        code: u16,
+    },
+}
+
+/// A single tag of an LRF object or stream: the two byte id `0xF5nn` and
+/// its payload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub id: u16,
+    pub offset: usize,
+    pub value: TagValue,
+}
+
+impl Tag {
+    /// Read the tag at `*pos`, leaving `*pos` just after its payload.
+    pub fn read(data: &[u8], pos: &mut usize) -> Result<Tag> {
+        let offset = *pos;
+        let raw = take(data, pos, 2)?;
+        if raw[1] != 0xF5 {
+            bail!("Bad tag ID {:02X} at {:08X}", raw[1], offset);
+        }
+        let Some(kind) = payload(raw[0]) else {
+            bail!("Unknown tag ID: F5{:02X} at {:08X}", raw[0], offset);
+        };
+        let value = match kind {
+            Payload::Fixed(size) => TagValue::Bytes(take(data, pos, size)?.to_vec()),
+            Payload::Text => TagValue::Text(read_string(data, pos)?),
+            Payload::IdList => {
+                let count = u16_at(data, *pos)? as usize;
+                *pos += 2;
+                let mut ids = Vec::with_capacity(count);
+                for _ in 0..count {
+                    ids.push(u32_at(data, *pos)?);
+                    *pos += 4;
+                }
+                TagValue::Ids(ids)
+            }
+            Payload::EmpDots => {
+                let font_id = u32_at(data, *pos)?;
+                *pos += 4;
+                let name = Tag::read(data, pos)?;
+                let font_name = match (name.id, name.value) {
+                    (0xF516, TagValue::Text(s)) => s,
+                    _ => bail!("Bad tag 78 at {:08X}", offset),
+                };
+                let code = u16_at(data, *pos)?;
+                *pos += 2;
+                TagValue::EmpDots {
+                    font_id,
+                    font_name,
+                    code,
+                }
+            }
+        };
+        Ok(Tag {
+            id: 0xF500 | raw[0] as u16,
+            offset,
+            value,
+        })
+    }
+
+    /// The raw payload of a fixed size tag, empty for the other kinds.
+    pub fn bytes(&self) -> &[u8] {
+        match &self.value {
+            TagValue::Bytes(b) => b,
+            _ => &[],
+        }
+    }
+
+    pub fn word(&self) -> Result<u16> {
+        match self.bytes() {
+            [a, b] => Ok(u16::from_le_bytes([*a, *b])),
+            _ => bail!("Bad parameter for tag ID: {:04X}", self.id),
+        }
+    }
+
+    pub fn sword(&self) -> Result<i16> {
+        Ok(self.word()? as i16)
+    }
+
+    pub fn dword(&self) -> Result<u32> {
+        match self.bytes() {
+            [a, b, c, d] => Ok(u32::from_le_bytes([*a, *b, *c, *d])),
+            _ => bail!("Bad parameter for tag ID: {:04X}", self.id),
+        }
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        match &self.value {
+            TagValue::Text(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn ids(&self) -> &[u32] {
+        match &self.value {
+            TagValue::Ids(ids) => ids,
+            _ => &[],
+        }
+    }
+}
+
+/// Read a length prefixed UTF-16LE string.
+pub fn read_string(data: &[u8], pos: &mut usize) -> Result<String> {
+    let size = u16_at(data, *pos)? as usize;
+    *pos += 2;
+    Ok(decode_utf16(take(data, pos, size)?))
+}
+
+pub fn decode_utf16(raw: &[u8]) -> String {
+    let units: Vec<u16> = raw
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    let s = String::from_utf16_lossy(&units);
+    match s.strip_prefix('\u{feff}') {
+        Some(rest) => rest.to_string(),
+        None => s,
+    }
+}
+
+fn take<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8]> {
+    let Some(bytes) = data.get(*pos..*pos + len) else {
+        bail!("Unexpected end of LRF data at {:08X}", *pos);
+    };
+    *pos += len;
+    Ok(bytes)
+}
+
+pub(crate) fn u16_at(data: &[u8], pos: usize) -> Result<u16> {
+    match data.get(pos..pos + 2) {
+        Some(b) => Ok(u16::from_le_bytes([b[0], b[1]])),
+        None => bail!("Unexpected end of LRF data at {:08X}", pos),
+    }
+}
+
+pub(crate) fn u32_at(data: &[u8], pos: usize) -> Result<u32> {
+    match data.get(pos..pos + 4) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        None => bail!("Unexpected end of LRF data at {:08X}", pos),
+    }
+}
+
+pub(crate) fn u64_at(data: &[u8], pos: usize) -> Result<u64> {
+    Ok(u32_at(data, pos)? as u64 | (u32_at(data, pos + 4)? as u64) << 32)
+}
@@ -0,0 +1,258 @@
+use crate::lrf::objects::{is_text_attr, text_attr, Attrs};
+use crate::lrf::tags::{decode_utf16, Tag};
+use anyhow::{Context, Result};
+
+/// An element of a text stream, named as in LRS (`P`, `Span`, `CR`, ...).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextTag {
+    pub name: &'static str,
+    pub attrs: Attrs,
+}
+
+impl TextTag {
+    fn new(name: &'static str) -> Self {
+        TextTag {
+            name,
+            attrs: Attrs::new(),
+        }
+    }
+
+    fn with(name: &'static str, attrs: &[(&'static str, i64)]) -> Self {
+        TextTag {
+            name,
+            attrs: attrs.iter().cloned().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextElement {
+    Text(String),
+    /// A container, closed by a later `End`
+    Start(TextTag),
+    End,
+    /// A self-closing element such as `CR` or `Plot`
+    Empty(TextTag),
+}
+
+fn container_name(id: u16) -> Option<&'static str> {
+    Some(match id {
+        0xF581 => "Italic",
+        0xF5A9 => "Rubi",
+        0xF5AB => "Oyamoji",
+        0xF5AD => "Rubimoji",
+        0xF5B1 => "Yoko",
+        0xF5B3 => "Tate",
+        0xF5B5 => "Nekase",
+        0xF5B7 => "Sup",
+        0xF5B9 => "Sub",
+        0xF5BB => "NoBR",
+        0xF5BD => "EmpDots",
+        _ => return None,
+    })
+}
+
+fn is_container_end(id: u16) -> bool {
+    matches!(
+        id,
+        0xF582
+            | 0xF5A8
+            | 0xF5AA
+            | 0xF5AC
+            | 0xF5AE
+            | 0xF5B2
+            | 0xF5B4
+            | 0xF5B6
+            | 0xF5B8
+            | 0xF5BA
+            | 0xF5BC
+            | 0xF5BE
+            | 0xF5C2
+            | 0xF5C4
+            | 0xF5C7
+    )
+}
+
+fn is_text_tag(id: u16) -> bool {
+    container_name(id).is_some()
+        || is_container_end(id)
+        || is_text_attr(id)
+        || matches!(
+            id,
+            0xF516
+                | 0xF578
+                | 0xF5A1
+                | 0xF5A2
+                | 0xF5A7
+                | 0xF5C1
+                | 0xF5C3
+                | 0xF5C6
+                | 0xF5CA
+                | 0xF5CC
+                | 0xF5D1
+                | 0xF5D2
+        )
+}
+
+/// Find the next tag in a text stream. Text is UTF-16LE, so tags sit at
+/// even offsets and their second byte is 0xF5.
+fn find_first_tag(stream: &[u8], start: usize) -> Option<usize> {
+    (start..stream.len().saturating_sub(1))
+        .step_by(2)
+        .filter(|&p| stream[p + 1] == 0xF5)
+        .find(|&p| {
+            let mut pos = p;
+            Tag::read(stream, &mut pos).is_ok_and(|t| is_text_tag(t.id))
+        })
+}
+
+struct TextParser {
+    content: Vec<TextElement>,
+}
+
+impl TextParser {
+    fn add_text(&mut self, raw: &[u8]) {
+        let s = decode_utf16(raw).replace('\0', "");
+        if !s.is_empty() {
+            let s = html_escape::decode_html_entities(&s).into_owned();
+            self.content.push(TextElement::Text(s));
+        }
+    }
+
+    fn start(&mut self, tag: TextTag) {
+        self.content.push(TextElement::Start(tag));
+    }
+
+    /// Close every container opened from `start` onwards, dropping a
+    /// trailing empty span.
+    fn close_containers(&mut self, start: usize) {
+        if matches!(self.content.last(), Some(TextElement::Start(t)) if t.name == "Span") {
+            self.content.pop();
+        }
+        let open: i64 = self
+            .content
+            .get(start..)
+            .unwrap_or(&[])
+            .iter()
+            .map(|c| match c {
+                TextElement::Start(_) => 1,
+                TextElement::End => -1,
+                _ => 0,
+            })
+            .sum();
+        for _ in 0..open.max(0) {
+            self.content.push(TextElement::End);
+        }
+    }
+
+    fn end_para(&mut self) {
+        let start = self
+            .content
+            .iter()
+            .rposition(|c| matches!(c, TextElement::Start(t) if t.name == "P"))
+            .unwrap_or(0);
+        self.close_containers(start);
+    }
+
+    /// An `EmpLine` is followed by up to two tags giving its position and
+    /// line type.
+    fn empline(&mut self, stream: &[u8], pos: &mut usize) {
+        let mut tag = TextTag::new("EmpLine");
+        for _ in 0..2 {
+            let mut next = *pos;
+            match Tag::read(stream, &mut next) {
+                Ok(t) if matches!(t.id, 0xF579 | 0xF57A) => {
+                    if let Some((name, value)) = text_attr(&t) {
+                        tag.attrs.insert(name, value);
+                    }
+                    *pos = next;
+                }
+                _ => break,
+            }
+        }
+        self.start(tag);
+    }
+}
+
+/// Split a text stream into text and elements. Attribute tags that differ
+/// from `style`, the text's own style, open spans that stay open until the
+/// end of the paragraph.
+pub fn parse_text(stream: &[u8], style: &Attrs) -> Result<Vec<TextElement>> {
+    let mut parser = TextParser {
+        content: Vec::new(),
+    };
+    let mut current = style.clone();
+    let mut pos = 0;
+    while pos < stream.len() {
+        let Some(tag_pos) = find_first_tag(stream, pos) else {
+            parser.add_text(&stream[pos..]);
+            break;
+        };
+        if tag_pos > pos {
+            parser.add_text(&stream[pos..tag_pos]);
+        }
+        pos = tag_pos;
+        let tag = Tag::read(stream, &mut pos)?;
+        match tag.id {
+            0xF5CC => {
+                let len = tag.word()? as usize;
+                let raw = stream
+                    .get(pos..pos + len)
+                    .context("Text stream is truncated")?;
+                parser.add_text(raw);
+                pos += len;
+            }
+            0xF5A1 => parser.start(TextTag::new("P")),
+            0xF5A2 => parser.end_para(),
+            0xF5A7 => parser.start(TextTag::with(
+                "CharButton",
+                &[("refobj", tag.dword()? as i64)],
+            )),
+            0xF5C1 => parser.empline(stream, &mut pos),
+            0xF5C3 => parser.start(TextTag::with("DrawChar", &[("line", tag.word()? as i64)])),
+            0xF5C6 => parser.start(TextTag::with("Box", &[("linetype", tag.word()? as i64)])),
+            0xF5CA => parser.content.push(TextElement::Empty(TextTag::with(
+                "Space",
+                &[("xsize", tag.sword()? as i64)],
+            ))),
+            0xF5D1 => {
+                let b = tag.bytes();
+                let word = |i: usize| u16::from_le_bytes([b[i], b[i + 1]]) as i64;
+                let dword = |i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+                parser.content.push(TextElement::Empty(TextTag::with(
+                    "Plot",
+                    &[
+                        ("xsize", word(0)),
+                        ("ysize", word(2)),
+                        ("refobj", dword(4) as i64),
+                        ("adjustment", dword(8) as i64),
+                    ],
+                )));
+            }
+            0xF5D2 => parser.content.push(TextElement::Empty(TextTag::new("CR"))),
+            id if is_container_end(id) => parser.content.push(TextElement::End),
+            id => {
+                if let Some(name) = container_name(id) {
+                    parser.start(TextTag::new(name));
+                } else if let Some((name, value)) = text_attr(&tag) {
+                    if parser.content.is_empty() {
+                        current = style.clone();
+                    }
+                    if current.get(name) != Some(&value) {
+                        match parser.content.last_mut() {
+                            Some(TextElement::Start(t)) if t.name == "Span" => {
+                                t.attrs.insert(name, value);
+                            }
+                            _ => parser.start(TextTag::with("Span", &[(name, value)])),
+                        }
+                        current.insert(name, value);
+                    }
+                }
+            }
+        }
+    }
+    if !parser.content.is_empty() {
+        parser.close_containers(0);
+    }
+    Ok(parser.content)
+}
@@ -0,0 +1,481 @@
+use crate::lrf::document::LrfDocument;
+use crate::lrf::objects::{
+    block_content, canvas_contents, page_contents, Attrs, LrfObject, ObjectType, PageElement,
+};
+use crate::lrf::text::{parse_text, TextElement, TextTag};
+use crate::oeb::parse_utils::escape_xml;
+use anyhow::Result;
+use std::collections::HashMap;
+
+pub const STYLESHEET: &str = "styles.css";
+
+/// LRF coordinates are in device pixels of the 166 dpi PRS-500 screen.
+const PX_TO_PT: f64 = 72.0 / 166.0;
+
+pub struct ConvertedBook {
+    /// `(href, XHTML)` for every page, in reading order
+    pub pages: Vec<(String, String)>,
+    pub css: String,
+    /// `(href, data)` for the image streams and embedded fonts
+    pub resources: Vec<(String, Vec<u8>)>,
+    /// `(label, href)` for every TOC entry
+    pub toc: Vec<(String, String)>,
+}
+
+pub fn page_href(id: u32) -> String {
+    format!("{}.xhtml", id)
+}
+
+/// Render the pages of an LRF document as XHTML, following the LRS to HTML
+/// conversion of calibre's LRF input: each page becomes a file, text blocks
+/// become divs and text and block styles become numbered CSS classes.
+pub struct LRFToHTML<'a> {
+    doc: &'a LrfDocument,
+    styles: Styles,
+    /// Object ids of image streams and embedded fonts, to their hrefs
+    files: HashMap<u32, String>,
+}
+
+impl<'a> LRFToHTML<'a> {
+    pub fn convert(doc: &'a LrfDocument) -> Result<ConvertedBook> {
+        let mut converter = LRFToHTML {
+            doc,
+            styles: Styles::default(),
+            files: HashMap::new(),
+        };
+
+        let mut resources = Vec::new();
+        for id in &doc.order {
+            let obj = &doc.objects[id];
+            match obj.kind {
+                ObjectType::TextAttr => {
+                    if let Some(idx) = converter.styles.text_style(&obj.text_attrs()) {
+                        converter.styles.text_map.insert(obj.id, idx);
+                    }
+                }
+                ObjectType::BlockAttr => {
+                    let idx = converter.styles.block_style(&obj.block_attrs());
+                    converter.styles.block_map.insert(obj.id, idx);
+                }
+                ObjectType::ImageStream => {
+                    if let Some(ext) = obj.image_extension() {
+                        let href = format!("{}.{}", obj.id, ext);
+                        converter.files.insert(obj.id, href.clone());
+                        resources.push((href, obj.stream.clone()));
+                    }
+                }
+                ObjectType::Font => {
+                    let name = obj.font_face_name().unwrap_or("font");
+                    let safe: String = name
+                        .chars()
+                        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                        .collect();
+                    let href = format!("{}_{}.ttf", safe, obj.id);
+                    converter.files.insert(obj.id, href.clone());
+                    resources.push((href, obj.stream.clone()));
+                }
+                _ => {}
+            }
+        }
+
+        let mut pages = Vec::new();
+        for id in doc.pages() {
+            let body = converter.page(&doc.objects[&id])?;
+            pages.push((page_href(id), converter.wrap(&body)));
+        }
+
+        let toc = doc
+            .toc()?
+            .into_iter()
+            .map(|entry| {
+                let href = format!("{}#{}", page_href(entry.refpage), entry.refobj);
+                (entry.label, href)
+            })
+            .collect();
+
+        Ok(ConvertedBook {
+            pages,
+            css: converter.styles.css(),
+            resources,
+            toc,
+        })
+    }
+
+    fn wrap(&self, body: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <html xmlns=\"http://www.w3.org/1999/xhtml\">\
+             <head><meta http-equiv=\"Content-Type\" content=\"text/html; charset=utf-8\"/>\
+             <title>{}</title><link rel=\"stylesheet\" type=\"text/css\" href=\"{}\"/></head>\
+             <body class=\"body\">{}</body></html>",
+            escape_xml(&self.doc.info.title),
+            STYLESHEET,
+            body
+        )
+    }
+
+    fn page(&mut self, page: &LrfObject) -> Result<String> {
+        let mut out = String::new();
+        let contents = page_contents(&page.stream)?;
+        let mut i = 0;
+        while i < contents.len() {
+            match &contents[i] {
+                PageElement::RuledLine => out.push_str("<hr/>"),
+                PageElement::Object(id) => match self.doc.object(*id) {
+                    Some(obj) if obj.kind == ObjectType::Block => self.block(obj, &mut out)?,
+                    Some(obj) if obj.kind == ObjectType::Canvas => {
+                        // Consecutive canvases are laid out as rows of one table
+                        let mut canvases = vec![obj];
+                        while let Some(PageElement::Object(next)) = contents.get(i + 1) {
+                            match self.doc.object(*next) {
+                                Some(c) if c.kind == ObjectType::Canvas => canvases.push(c),
+                                _ => break,
+                            }
+                            i += 1;
+                        }
+                        self.canvases(&canvases, &mut out)?;
+                    }
+                    _ => {}
+                },
+            }
+            i += 1;
+        }
+        Ok(out)
+    }
+
+    fn block(&mut self, block: &LrfObject, out: &mut String) -> Result<()> {
+        let Some(content) = self.doc.object(block_content(&block.stream)?) else {
+            return Ok(());
+        };
+        match content.kind {
+            ObjectType::Text | ObjectType::SimpleText => {
+                out.push_str("<div");
+                self.block_attributes(block, content, out);
+                out.push('>');
+                self.text(content, out)?;
+                out.push_str("</div>");
+            }
+            ObjectType::Image => self.image_page(block.id, content, out),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn block_attributes(&self, block: &LrfObject, text: &LrfObject, out: &mut String) {
+        let mut classes = Vec::new();
+        if let Some(idx) = block.style_id().and_then(|s| self.styles.block_map.get(&s)) {
+            classes.push(format!("bs{}", idx));
+        }
+        if let Some(idx) = text.style_id().and_then(|s| self.styles.text_map.get(&s)) {
+            classes.push(format!("ts{}", idx));
+        }
+        if !classes.is_empty() {
+            out.push_str(&format!(" class=\"{}\"", classes.join(" ")));
+        }
+        out.push_str(&format!(" id=\"{}\"", block.id));
+    }
+
+    fn image_page(&self, id: u32, image: &LrfObject, out: &mut String) {
+        out.push_str(&format!("<div id=\"{}\" class=\"image_page\"><img", id));
+        if let Some(src) = image.dword(0xF54C).and_then(|s| self.files.get(&s)) {
+            out.push_str(&format!(" src=\"{}\"", escape_xml(src)));
+        }
+        if let Some((width, height)) = image.image_size() {
+            out.push_str(&format!(" width=\"{}\" height=\"{}\"", width, height));
+        }
+        out.push_str(" alt=\"\"/></div>");
+    }
+
+    fn canvases(&mut self, canvases: &[&LrfObject], out: &mut String) -> Result<()> {
+        let doc = self.doc;
+        let placed = |canvas: &LrfObject| -> Result<Vec<&'a LrfObject>> {
+            Ok(canvas_contents(&canvas.stream)?
+                .into_iter()
+                .filter_map(|(_, _, id)| doc.object(id))
+                .collect())
+        };
+
+        // A canvas holding just an image is an image page
+        if let [canvas] = canvases {
+            if let [block] = placed(canvas)?.as_slice() {
+                if let Some(image) = self
+                    .doc
+                    .object(block_content(&block.stream)?)
+                    .filter(|o| o.kind == ObjectType::Image)
+                {
+                    self.image_page(canvas.id, image, out);
+                    return Ok(());
+                }
+            }
+        }
+
+        out.push_str("<table>");
+        for canvas in canvases {
+            out.push_str(&format!("<tr id=\"{}\">", canvas.id));
+            for block in placed(canvas)? {
+                if block.kind != ObjectType::Block {
+                    continue;
+                }
+                let Some(content) = self.doc.object(block_content(&block.stream)?) else {
+                    continue;
+                };
+                if !matches!(content.kind, ObjectType::Text | ObjectType::SimpleText) {
+                    continue;
+                }
+                out.push_str("<td");
+                self.block_attributes(block, content, out);
+                out.push('>');
+                self.text(content, out)?;
+                out.push_str("</td>");
+            }
+            out.push_str("</tr>");
+        }
+        out.push_str("</table>");
+        Ok(())
+    }
+
+    fn text(&mut self, text: &LrfObject, out: &mut String) -> Result<()> {
+        let style = text
+            .style_id()
+            .and_then(|id| self.doc.object(id))
+            .map(|s| s.text_attrs())
+            .unwrap_or_default();
+        let mut open: Vec<&'static str> = Vec::new();
+        let mut after_paragraph = true;
+        for element in parse_text(&text.stream, &style)? {
+            match element {
+                TextElement::Text(s) => {
+                    out.push_str(&escape_xml(&s));
+                    after_paragraph = false;
+                }
+                TextElement::Start(tag) => {
+                    let name = self.start_tag(&tag, open.is_empty(), out);
+                    open.push(name);
+                }
+                TextElement::End => {
+                    if let Some(name) = open.pop() {
+                        out.push_str(&format!("</{}>", name));
+                        after_paragraph = name == "p";
+                    }
+                }
+                TextElement::Empty(tag) => match tag.name {
+                    // Paragraphs already break lines
+                    "CR" if open.is_empty() && after_paragraph => {}
+                    "CR" => out.push_str("<br/>"),
+                    "Plot" => {
+                        self.plot(&tag, out);
+                        after_paragraph = false;
+                    }
+                    _ => {}
+                },
+            }
+        }
+        while let Some(name) = open.pop() {
+            out.push_str(&format!("</{}>", name));
+        }
+        Ok(())
+    }
+
+    /// Write the opening tag for a text container and return the name of
+    /// the HTML element used.
+    fn start_tag(&mut self, tag: &TextTag, top_level: bool, out: &mut String) -> &'static str {
+        let mut class = None;
+        let mut style = None;
+        let mut href = None;
+        let name = match tag.name {
+            "P" if top_level => "p",
+            "Italic" => "i",
+            "Sup" => "sup",
+            "Sub" => "sub",
+            "CharButton" => {
+                href = tag
+                    .attrs
+                    .get("refobj")
+                    .and_then(|id| self.doc.object(*id as u32))
+                    .and_then(|button| button.jump_target())
+                    .map(|(page, obj)| format!("{}#{}", page_href(page), obj));
+                "a"
+            }
+            "EmpLine" => {
+                let position = tag.attrs.get("emplineposition").copied().unwrap_or(1);
+                style = Some(if position == 2 {
+                    "text-decoration: overline"
+                } else {
+                    "text-decoration: underline"
+                });
+                "span"
+            }
+            _ => {
+                class = self.styles.text_style(&tag.attrs);
+                "span"
+            }
+        };
+        out.push('<');
+        out.push_str(name);
+        if let Some(idx) = class {
+            out.push_str(&format!(" class=\"ts{}\"", idx));
+        }
+        if let Some(style) = style {
+            out.push_str(&format!(" style=\"{}\"", style));
+        }
+        if let Some(href) = href {
+            out.push_str(&format!(" href=\"{}\"", escape_xml(&href)));
+        }
+        out.push('>');
+        name
+    }
+
+    fn plot(&self, tag: &TextTag, out: &mut String) {
+        out.push_str("<img");
+        let src = tag
+            .attrs
+            .get("refobj")
+            .and_then(|id| self.doc.object(*id as u32))
+            .and_then(|image| image.dword(0xF54C))
+            .and_then(|stream| self.files.get(&stream));
+        if let Some(src) = src {
+            out.push_str(&format!(" src=\"{}\"", escape_xml(src)));
+        }
+        for (attr, name) in [("xsize", "width"), ("ysize", "height")] {
+            if let Some(v) = tag.attrs.get(attr) {
+                out.push_str(&format!(
+                    " {}=\"{}\"",
+                    name,
+                    (*v as f64 * 166.0 / 720.0) as i64
+                ));
+            }
+        }
+        out.push_str(" alt=\"\"/>");
+    }
+}
+
+type Style = Vec<(&'static str, String)>;
+
+/// Text and block styles, numbered in order of first use.
+#[derive(Default)]
+struct Styles {
+    text: Vec<Style>,
+    block: Vec<Style>,
+    text_map: HashMap<u32, usize>,
+    block_map: HashMap<u32, usize>,
+}
+
+impl Styles {
+    fn text_style(&mut self, attrs: &Attrs) -> Option<usize> {
+        let mut style = Style::new();
+        if let Some(size) = attrs.get("fontsize") {
+            style.push(("font-size", pt(*size as f64 / 10.0)));
+        }
+        if let Some(weight) = attrs.get("fontweight") {
+            let weight = if *weight >= 700 { "bold" } else { "normal" };
+            style.push(("font-weight", weight.to_string()));
+        }
+        if let Some(color) = attrs.get("textcolor").and_then(|c| color(*c)) {
+            style.push(("color", color));
+        }
+        if let Some(color) = attrs.get("textbgcolor").and_then(|c| color(*c)) {
+            style.push(("background-color", color));
+        }
+        if let Some(align) = attrs.get("align") {
+            let align = match align {
+                4 => "center",
+                8 => "right",
+                _ => "left",
+            };
+            style.push(("text-align", align.to_string()));
+        }
+        if let Some(indent) = attrs.get("parindent") {
+            style.push(("text-indent", pt(*indent as f64 / 10.0)));
+        }
+        if style.is_empty() {
+            return None;
+        }
+        Some(intern(&mut self.text, style))
+    }
+
+    fn block_style(&mut self, attrs: &Attrs) -> usize {
+        let mut style = Style::new();
+        let px = |name: &str| attrs.get(name).map(|v| pt(*v as f64 * PX_TO_PT));
+        if let Some(margin) = px("sidemargin") {
+            style.push(("margin-left", margin.clone()));
+            style.push(("margin-right", margin));
+        }
+        if let Some(margin) = px("topskip") {
+            style.push(("margin-top", margin));
+        }
+        if let Some(margin) = px("footskip") {
+            style.push(("margin-bottom", margin));
+        }
+        if let Some(width) = px("framewidth") {
+            style.push(("border-width", width));
+            style.push(("border-style", "solid".to_string()));
+        }
+        if let Some(color) = attrs.get("framecolor").and_then(|c| color(*c)) {
+            style.push(("border-color", color));
+        }
+        if let Some(color) = attrs.get("bgcolor").and_then(|c| color(*c)) {
+            style.push(("background-color", color));
+        }
+        intern(&mut self.block, style)
+    }
+
+    fn css(&self) -> String {
+        let mut css = String::from(".image_page { text-align: center }\n");
+        for (styles, prefix) in [(&self.text, "ts"), (&self.block, "bs")] {
+            for (i, style) in styles.iter().enumerate() {
+                if style.is_empty() {
+                    continue;
+                }
+                let decls: Vec<String> = style
+                    .iter()
+                    .map(|(k, v)| format!("\t{}: {};", k, v))
+                    .collect();
+                css.push_str(&format!(
+                    "\n.{}{} {{\n{}\n}}\n",
+                    prefix,
+                    i,
+                    decls.join("\n")
+                ));
+            }
+        }
+        css
+    }
+}
+
+fn intern(styles: &mut Vec<Style>, style: Style) -> usize {
+    match styles.iter().position(|s| *s == style) {
+        Some(idx) => idx,
+        None => {
+            styles.push(style);
+            styles.len() - 1
+        }
+    }
+}
+
+fn pt(value: f64) -> String {
+    let s = format!("{:.2}", value);
+    format!("{}pt", s.trim_end_matches('0').trim_end_matches('.'))
+}
+
+/// LRF colours are stored as 0xBBGGRRAA, where an alpha of 0 is opaque and
+/// 255 fully transparent.
+fn color(value: i64) -> Option<String> {
+    let value = value as u32;
+    let (a, r, g, b) = (
+        value & 0xFF,
+        (value >> 8) & 0xFF,
+        (value >> 16) & 0xFF,
+        (value >> 24) & 0xFF,
+    );
+    match a {
+        255 => None,
+        0 => Some(format!("rgb({},{},{})", r, g, b)),
+        _ => Some(format!(
+            "rgba({},{},{},{:.2})",
+            r,
+            g,
+            b,
+            1.0 - a as f64 / 255.0
+        )),
+    }
+}
@@ -1,8 +1,14 @@
 use crate::metadata::meta::MetaInformation;
 use anyhow::{Context, Result};
-use serde_json::Value;
+use calibre_utils::constants::{APP_NAME, VERSION};
+use chrono::{Datelike, Utc};
+use serde_json::{json, Map, Value};
 use std::io::Read;
 
+const COMIC_BOOK_INFO: &str = "ComicBookInfo/1.0";
+/// Credit roles that make someone an author of the comic.
+const AUTHOR_ROLES: [&str; 4] = ["Writer", "Artist", "Cartoonist", "Creator"];
+
 /// Check if a list of filenames represents a comic book (only image files)
 pub fn is_comic(list_of_names: &[String]) -> bool {
     let extensions: std::collections::HashSet<String> = list_of_names
@@ -111,7 +117,7 @@ pub fn get_comic_book_info(data: &Value, mi: &mut MetaInformation, series_index:
         let mut authors = Vec::new();
         for credit in credits {
             if let Some(role) = credit.get("role").and_then(|v| v.as_str()) {
-                if ["Writer", "Artist", "Cartoonist", "Creator"].contains(&role) {
+                if AUTHOR_ROLES.contains(&role) {
                     if let Some(person) = credit.get("person").and_then(|v| v.as_str()) {
                         if !person.is_empty() {
                             // Reverse "Last, First" format to "First Last"
@@ -183,6 +189,72 @@ pub fn parse_comic_comment(comment: &[u8], series_index: &str) -> Result<MetaInf
     Ok(mi)
 }
 
+/// The zip comment for a comic with the metadata `mi`, in ComicBookInfo
+/// JSON. Entries of `old_comment` that `mi` has nothing for are kept.
+pub fn comic_comment(mi: &MetaInformation, old_comment: &[u8]) -> Result<Vec<u8>> {
+    let mut root = match serde_json::from_slice::<Value>(old_comment) {
+        Ok(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+    let mut cbi = match root.remove(COMIC_BOOK_INFO) {
+        Some(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+
+    cbi.insert("title".into(), json!(mi.title));
+    let mut set = |key: &str, value: Option<Value>| match value {
+        Some(value) => cbi.insert(key.to_string(), value),
+        None => cbi.remove(key),
+    };
+    set("series", mi.series.as_ref().map(|s| json!(s)));
+    set("volume", mi.series.as_ref().map(|_| json!(mi.series_index)));
+    set("publisher", mi.publisher.as_ref().map(|p| json!(p)));
+    set("rating", mi.rating.map(|r| json!(r)));
+    set("comments", mi.comments.as_ref().map(|c| json!(c)));
+    set(
+        "language",
+        mi.languages
+            .iter()
+            .find(|l| l.as_str() != "und")
+            .map(|l| json!(l)),
+    );
+    set("tags", Some(json!(mi.tags)));
+    set("publicationYear", mi.pubdate.map(|d| json!(d.year())));
+    set("publicationMonth", mi.pubdate.map(|d| json!(d.month())));
+
+    // Authors replace the author credits, other credits stay
+    let mut credits: Vec<Value> = cbi
+        .get("credits")
+        .and_then(|v| v.as_array())
+        .map(|credits| {
+            credits
+                .iter()
+                .filter(|c| {
+                    !c.get("role")
+                        .and_then(|r| r.as_str())
+                        .is_some_and(|r| AUTHOR_ROLES.contains(&r))
+                })
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+    for (i, author) in mi.authors.iter().enumerate() {
+        credits.insert(
+            i,
+            json!({"person": author, "role": "Writer", "primary": i == 0}),
+        );
+    }
+    cbi.insert("credits".into(), Value::Array(credits));
+
+    root.insert(COMIC_BOOK_INFO.into(), Value::Object(cbi));
+    root.insert("appID".into(), json!(format!("{}/{}", APP_NAME, VERSION)));
+    root.insert(
+        "lastModified".into(),
+        json!(Utc::now().format("%Y-%m-%d %H:%M:%S%.6f").to_string()),
+    );
+    Ok(serde_json::to_vec(&Value::Object(root))?)
+}
+
 /// Extract metadata from comic book archive (CBZ/CBR)
 pub fn get_comic_metadata<R: Read + std::io::Seek>(
     stream: &mut R,
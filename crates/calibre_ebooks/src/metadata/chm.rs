@@ -1,3 +1,4 @@
+use crate::chm::reader::ChmReader;
 use crate::metadata::MetaInformation;
 use anyhow::Result;
 use lazy_static::lazy_static;
@@ -14,21 +15,32 @@ lazy_static! {
 
 use std::io::{Read, Seek};
 
-pub fn get_metadata<R: Read + Seek>(_stream: R) -> Result<MetaInformation> {
-    // CHM is a complex binary format (ITSS/LZX).
-    // Without a CHM reader implementation (which is complex),
-    // we cannot extract the "home" HTML file to parse.
-    // This is a placeholder that would normally read the CHM, extract the home HTML,
-    // and pass it to parsing logic.
-    // For now, we return empty metadata or error.
-    // In a real implementation with a CHM crate, we would:
-    // 1. Read CHM directory.
-    // 2. Find home/index file.
-    // 3. metadata_from_html(content)
+pub fn get_metadata<R: Read + Seek>(stream: R) -> Result<MetaInformation> {
+    let mut chm = ChmReader::new(stream)?;
+    get_metadata_from_reader(&mut chm)
+}
 
-    // Returning default for now to satisfy interface,
-    // or we could error "CHM reading not supported".
-    Ok(MetaInformation::default())
+/// Metadata from the title in `#SYSTEM` and the author and ISBN given on
+/// the home page of the help file.
+pub fn get_metadata_from_reader<R: Read + Seek>(chm: &mut ChmReader<R>) -> Result<MetaInformation> {
+    let html = match chm.home_path() {
+        Some(path) => {
+            let raw = chm.get_file(&path)?;
+            chm.decode_text(&raw)
+        }
+        None => String::new(),
+    };
+    let mut mi = metadata_from_html(&html);
+    if let Some(title) = &chm.system.title {
+        mi.title = title.trim().to_string();
+    }
+    if mi.title.is_empty() {
+        mi.title = "Unknown".to_string();
+    }
+    if mi.authors.is_empty() {
+        mi.authors = vec!["Unknown".to_string()];
+    }
+    Ok(mi)
 }
 
 /// Extracts metadata from the CHM "home" HTML content.
@@ -1,8 +1,18 @@
-use crate::metadata::MetaInformation;
+use crate::metadata::utils::{read_zip_entry, rewrite_children, rewrite_zip};
+use crate::metadata::{authors_to_string, string_to_authors, MetaInformation};
+use crate::oeb::parse_utils::escape_xml;
 use anyhow::{Context, Result};
-use std::io::{Read, Seek};
+use roxmltree::{Document, Node};
+use std::io::{Cursor, Read, Seek};
 use zip::ZipArchive;
 
+const CORE_PATH: &str = "docProps/core.xml";
+const CP_NS: &str = "http://schemas.openxmlformats.org/package/2006/metadata/core-properties";
+const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+const CORE_CONTENT_TYPE: &str = "application/vnd.openxmlformats-package.core-properties+xml";
+const CORE_RELATIONSHIP: &str =
+    "http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties";
+
 pub fn get_metadata<R: Read + Seek>(stream: R) -> Result<MetaInformation> {
     let mut archive = ZipArchive::new(stream).context("Failed to open DOCX archive")?;
     let mut mi = MetaInformation::default();
@@ -22,9 +32,9 @@ pub fn get_metadata<R: Read + Seek>(stream: R) -> Result<MetaInformation> {
                     }
                     "creator" => {
                         if let Some(t) = node.text() {
-                            mi.authors = vec![t.to_string()];
+                            mi.authors = string_to_authors(t);
                         }
-                    } // Split by comma?
+                    }
                     "description" => {
                         if let Some(t) = node.text() {
                             mi.comments = Some(t.to_string());
@@ -92,6 +102,112 @@ pub fn get_metadata<R: Read + Seek>(stream: R) -> Result<MetaInformation> {
     Ok(mi)
 }
 
+/// Write `mi` into the core properties of the DOCX `data`, and the
+/// publisher into the company of the extended properties.
+pub fn set_metadata(data: &[u8], mi: &MetaInformation) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(data)).context("Failed to open DOCX archive")?;
+    let mut replace = Vec::new();
+
+    let mut added = vec![
+        format!("<dc:title>{}</dc:title>", escape_xml(&mi.title)),
+        format!(
+            "<dc:creator>{}</dc:creator>",
+            escape_xml(&authors_to_string(&mi.authors))
+        ),
+        format!(
+            "<cp:keywords>{}</cp:keywords>",
+            escape_xml(&mi.tags.join(", "))
+        ),
+    ];
+    if let Some(comments) = &mi.comments {
+        added.push(format!(
+            "<dc:description>{}</dc:description>",
+            escape_xml(comments)
+        ));
+    }
+    if let Some(lang) = mi.languages.iter().find(|l| l.as_str() != "und") {
+        added.push(format!("<dc:language>{}</dc:language>", escape_xml(lang)));
+    }
+    let namespaces = [("cp", CP_NS), ("dc", DC_NS)];
+
+    match read_zip_entry(&mut archive, CORE_PATH) {
+        Ok(core) => {
+            let doc = Document::parse(&core).context("Failed to parse core properties")?;
+            let managed = |n: Node| {
+                matches!(
+                    (n.tag_name().namespace(), n.tag_name().name()),
+                    (
+                        Some(DC_NS),
+                        "title" | "creator" | "description" | "subject" | "language"
+                    ) | (Some(CP_NS), "keywords")
+                )
+            };
+            let core = rewrite_children(
+                &core,
+                doc.root_element(),
+                |n| !managed(n),
+                &added,
+                &namespaces,
+            );
+            replace.push((CORE_PATH.to_string(), core.into_bytes()));
+        }
+        Err(_) => {
+            // Documents without core properties need the part registered
+            let core = format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<cp:coreProperties xmlns:cp=\"{}\" xmlns:dc=\"{}\">\n  {}\n</cp:coreProperties>",
+                CP_NS,
+                DC_NS,
+                added.join("\n  ")
+            );
+            replace.push((CORE_PATH.to_string(), core.into_bytes()));
+
+            let types = read_zip_entry(&mut archive, "[Content_Types].xml")?;
+            let doc = Document::parse(&types).context("Failed to parse content types")?;
+            let item = format!(
+                "<Override PartName=\"/{}\" ContentType=\"{}\"/>",
+                CORE_PATH, CORE_CONTENT_TYPE
+            );
+            let types = rewrite_children(&types, doc.root_element(), |_| true, &[item], &[]);
+            replace.push(("[Content_Types].xml".to_string(), types.into_bytes()));
+
+            let rels = read_zip_entry(&mut archive, "_rels/.rels")?;
+            let doc = Document::parse(&rels).context("Failed to parse relationships")?;
+            let ids: Vec<&str> = doc
+                .descendants()
+                .filter_map(|n| n.attribute("Id"))
+                .collect();
+            let id = (1..)
+                .map(|i| format!("rId{}", i))
+                .find(|id| !ids.contains(&id.as_str()))
+                .unwrap();
+            let item = format!(
+                "<Relationship Id=\"{}\" Type=\"{}\" Target=\"{}\"/>",
+                id, CORE_RELATIONSHIP, CORE_PATH
+            );
+            let rels = rewrite_children(&rels, doc.root_element(), |_| true, &[item], &[]);
+            replace.push(("_rels/.rels".to_string(), rels.into_bytes()));
+        }
+    }
+
+    if let (Some(publisher), Ok(app)) = (
+        &mi.publisher,
+        read_zip_entry(&mut archive, "docProps/app.xml"),
+    ) {
+        let doc = Document::parse(&app).context("Failed to parse extended properties")?;
+        let company = format!("<Company>{}</Company>", escape_xml(publisher));
+        let app = rewrite_children(
+            &app,
+            doc.root_element(),
+            |n| n.tag_name().name() != "Company",
+            &[company],
+            &[],
+        );
+        replace.push(("docProps/app.xml".to_string(), app.into_bytes()));
+    }
+
+    rewrite_zip(data, &replace, None)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,9 +1,17 @@
+use crate::metadata::utils::{image_as, read_zip_entry, rewrite_children, rewrite_zip};
 use crate::metadata::MetaInformation;
+use crate::oeb::parse_utils::escape_xml;
 use crate::opf::parse_opf;
 use anyhow::{Context, Result};
-use std::io::{Read, Seek};
+use image::ImageFormat;
+use roxmltree::{Document, Node};
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Read, Seek};
 use zip::ZipArchive;
 
+const OPF_NS: &str = "http://www.idpf.org/2007/opf";
+const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+
 pub fn get_metadata<R: Read + Seek>(mut stream: R) -> Result<MetaInformation> {
     let mut archive = ZipArchive::new(&mut stream).context("Failed to read zip")?;
 
@@ -90,6 +98,363 @@ fn find_href_by_id(xml: &str, id: &str) -> Option<String> {
         .and_then(|n| n.attribute("href").map(|s| s.to_string()))
 }
 
+/// Write `mi` into the EPUB `data`. The OPF metadata is replaced, except
+/// for the unique identifier and the entries calibre does not manage
+/// (contributors, modification dates, ...), and the cover image is swapped
+/// for `mi.cover_data` when there is one.
+pub fn set_metadata(data: &[u8], mi: &MetaInformation) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(data)).context("Failed to read zip")?;
+    let container_xml = read_zip_entry(&mut archive, "META-INF/container.xml")?;
+    let opf_path = extract_opf_path_from_container(&container_xml)
+        .context("Could not find OPF path in container.xml")?;
+    let opf = read_zip_entry(&mut archive, &opf_path)?;
+    let opf_dir = match opf_path.rfind('/') {
+        Some(i) => &opf_path[..=i],
+        None => "",
+    };
+
+    let doc = Document::parse(&opf).context("Failed to parse OPF")?;
+    let package = doc.root_element();
+    let version3 = package
+        .attribute("version")
+        .is_some_and(|v| v.starts_with('3'));
+    let metadata = package
+        .children()
+        .find(|n| n.tag_name().name() == "metadata")
+        .context("Missing metadata section in OPF")?;
+    let manifest = package
+        .children()
+        .find(|n| n.tag_name().name() == "manifest")
+        .context("Missing manifest in OPF")?;
+    let ids: HashSet<&str> = doc
+        .descendants()
+        .filter_map(|n| n.attribute("id"))
+        .collect();
+
+    // Swap the cover image, or add one if the book has none
+    let mut replace = Vec::new();
+    let mut new_item = None;
+    let cover_item = find_cover_item(metadata, manifest);
+    let mut cover_id = cover_item
+        .and_then(|n| n.attribute("id"))
+        .map(str::to_string);
+    if !mi.cover_data.1.is_empty() {
+        match cover_item {
+            Some(item) => {
+                let format = match item.attribute("media-type") {
+                    Some("image/png") => ImageFormat::Png,
+                    Some("image/gif") => ImageFormat::Gif,
+                    _ => ImageFormat::Jpeg,
+                };
+                let href = item.attribute("href").unwrap_or_default();
+                replace.push((
+                    format!("{}{}", opf_dir, href),
+                    image_as(&mi.cover_data.1, format)?,
+                ));
+            }
+            None => {
+                let hrefs: HashSet<&str> = manifest
+                    .children()
+                    .filter_map(|n| n.attribute("href"))
+                    .collect();
+                let href = (1..)
+                    .map(|i| match i {
+                        1 => "cover.jpg".to_string(),
+                        i => format!("cover_{}.jpg", i),
+                    })
+                    .find(|h| !hrefs.contains(h.as_str()))
+                    .unwrap();
+                let id = unique_id("cover", &ids);
+                new_item = Some(format!(
+                    "<item id=\"{}\" href=\"{}\" media-type=\"image/jpeg\"{}/>",
+                    id,
+                    href,
+                    if version3 {
+                        " properties=\"cover-image\""
+                    } else {
+                        ""
+                    }
+                ));
+                replace.push((
+                    format!("{}{}", opf_dir, href),
+                    image_as(&mi.cover_data.1, ImageFormat::Jpeg)?,
+                ));
+                cover_id = Some(id);
+            }
+        }
+    }
+
+    // Drop what we are about to write, along with its OPF 3 refinements
+    let unique = package.attribute("unique-identifier");
+    let roles: HashMap<&str, &str> = metadata
+        .children()
+        .filter(|n| n.attribute("property") == Some("role"))
+        .filter_map(|n| Some((n.attribute("refines")?.trim_start_matches('#'), n.text()?)))
+        .collect();
+    let managed = |node: Node| is_managed(node, version3, unique, &roles);
+    let dropped: HashSet<&str> = metadata
+        .children()
+        .filter(|n| n.is_element() && managed(*n))
+        .filter_map(|n| n.attribute("id"))
+        .collect();
+    let keep = |node: Node| {
+        !managed(node)
+            && node
+                .attribute("refines")
+                .is_none_or(|r| !dropped.contains(r.trim_start_matches('#')))
+    };
+    let kept_identifier = metadata
+        .children()
+        .find(|n| {
+            n.tag_name().name() == "identifier" && unique.is_some() && n.attribute("id") == unique
+        })
+        .and_then(|n| n.text());
+
+    let added = opf_metadata(mi, version3, cover_id.as_deref(), kept_identifier, &ids);
+    let namespaces: &[(&str, &str)] = if version3 {
+        &[("dc", DC_NS)]
+    } else {
+        &[("dc", DC_NS), ("opf", OPF_NS)]
+    };
+    let mut new_opf = rewrite_children(&opf, metadata, keep, &added, namespaces);
+
+    if let Some(item) = new_item {
+        let doc = Document::parse(&new_opf).context("Failed to parse OPF")?;
+        let manifest = doc
+            .root_element()
+            .children()
+            .find(|n| n.tag_name().name() == "manifest")
+            .context("Missing manifest in OPF")?;
+        let updated = rewrite_children(&new_opf, manifest, |_| true, &[item], &[]);
+        new_opf = updated;
+    }
+    replace.push((opf_path, new_opf.into_bytes()));
+
+    rewrite_zip(data, &replace, None)
+}
+
+/// The manifest item of the cover image, from `<meta name="cover">` or the
+/// OPF 3 `cover-image` property.
+fn find_cover_item<'a, 'input>(
+    metadata: Node<'a, 'input>,
+    manifest: Node<'a, 'input>,
+) -> Option<Node<'a, 'input>> {
+    let items = || {
+        manifest
+            .children()
+            .filter(|n| n.tag_name().name() == "item")
+    };
+    metadata
+        .children()
+        .find(|n| n.tag_name().name() == "meta" && n.attribute("name") == Some("cover"))
+        .and_then(|meta| meta.attribute("content"))
+        .and_then(|id| items().find(|n| n.attribute("id") == Some(id)))
+        .or_else(|| {
+            items().find(|n| {
+                n.attribute("properties")
+                    .is_some_and(|p| p.split_whitespace().any(|p| p == "cover-image"))
+            })
+        })
+}
+
+/// Whether `node` is one of the metadata entries `set_metadata` rewrites.
+fn is_managed(
+    node: Node,
+    version3: bool,
+    unique: Option<&str>,
+    roles: &HashMap<&str, &str>,
+) -> bool {
+    let name = node.tag_name().name();
+    if node.tag_name().namespace() == Some(DC_NS) {
+        return match name {
+            "title" | "description" | "publisher" | "subject" | "language" => true,
+            "creator" => {
+                let role = node
+                    .attribute((OPF_NS, "role"))
+                    .or_else(|| node.attribute("role"))
+                    .or_else(|| node.attribute("id").and_then(|id| roles.get(id).copied()));
+                role.is_none_or(|r| r == "aut")
+            }
+            "date" => {
+                version3
+                    || matches!(
+                        node.attribute((OPF_NS, "event")),
+                        None | Some("publication")
+                    )
+            }
+            "identifier" => unique.is_none() || node.attribute("id") != unique,
+            _ => false,
+        };
+    }
+    if name != "meta" {
+        return false;
+    }
+    let meta_name = node.attribute("name").unwrap_or_default();
+    let property = node.attribute("property").unwrap_or_default();
+    meta_name == "cover"
+        || meta_name.starts_with("calibre:")
+        || property.starts_with("calibre:")
+        || property == "belongs-to-collection"
+}
+
+/// The OPF metadata elements for `mi`, in the flavour of the package
+/// version.
+fn opf_metadata(
+    mi: &MetaInformation,
+    version3: bool,
+    cover_id: Option<&str>,
+    kept_identifier: Option<&str>,
+    ids: &HashSet<&str>,
+) -> Vec<String> {
+    let mut out = vec![format!("<dc:title>{}</dc:title>", escape_xml(&mi.title))];
+
+    for (i, author) in mi.authors.iter().enumerate() {
+        let file_as = mi.author_sort_map.get(author).or(match mi.authors.len() {
+            1 => mi.author_sort.as_ref(),
+            _ => None,
+        });
+        if version3 {
+            let id = unique_id(&format!("creator{:02}", i + 1), ids);
+            out.push(format!(
+                "<dc:creator id=\"{}\">{}</dc:creator>",
+                id,
+                escape_xml(author)
+            ));
+            out.push(format!(
+                "<meta refines=\"#{}\" property=\"role\" scheme=\"marc:relators\">aut</meta>",
+                id
+            ));
+            if let Some(file_as) = file_as {
+                out.push(format!(
+                    "<meta refines=\"#{}\" property=\"file-as\">{}</meta>",
+                    id,
+                    escape_xml(file_as)
+                ));
+            }
+        } else {
+            let file_as = file_as
+                .map(|f| format!(" opf:file-as=\"{}\"", escape_xml(f)))
+                .unwrap_or_default();
+            out.push(format!(
+                "<dc:creator opf:role=\"aut\"{}>{}</dc:creator>",
+                file_as,
+                escape_xml(author)
+            ));
+        }
+    }
+
+    if let Some(comments) = &mi.comments {
+        out.push(format!(
+            "<dc:description>{}</dc:description>",
+            escape_xml(comments)
+        ));
+    }
+    if let Some(publisher) = &mi.publisher {
+        out.push(format!(
+            "<dc:publisher>{}</dc:publisher>",
+            escape_xml(publisher)
+        ));
+    }
+    for tag in &mi.tags {
+        out.push(format!("<dc:subject>{}</dc:subject>", escape_xml(tag)));
+    }
+    for lang in &mi.languages {
+        out.push(format!("<dc:language>{}</dc:language>", escape_xml(lang)));
+    }
+
+    // The unique identifier stays as it is, so do not repeat it
+    let kept = kept_identifier.map(|k| k.rsplit(':').next().unwrap_or(k).trim());
+    let mut identifiers: Vec<(&String, &String)> = mi.identifiers.iter().collect();
+    identifiers.sort();
+    for (scheme, value) in identifiers {
+        if kept == Some(value.as_str()) {
+            continue;
+        }
+        out.push(match (version3, scheme.as_str()) {
+            (true, "isbn") => format!(
+                "<dc:identifier>urn:isbn:{}</dc:identifier>",
+                escape_xml(value)
+            ),
+            (true, _) => format!(
+                "<dc:identifier>{}:{}</dc:identifier>",
+                escape_xml(scheme),
+                escape_xml(value)
+            ),
+            (false, "isbn") => format!(
+                "<dc:identifier opf:scheme=\"ISBN\">{}</dc:identifier>",
+                escape_xml(value)
+            ),
+            (false, _) => format!(
+                "<dc:identifier opf:scheme=\"{}\">{}</dc:identifier>",
+                escape_xml(scheme),
+                escape_xml(value)
+            ),
+        });
+    }
+    if let Some(uuid) = mi.uuid.as_deref().filter(|u| kept != Some(*u)) {
+        out.push(if version3 {
+            format!(
+                "<dc:identifier>urn:uuid:{}</dc:identifier>",
+                escape_xml(uuid)
+            )
+        } else {
+            format!(
+                "<dc:identifier opf:scheme=\"uuid\">{}</dc:identifier>",
+                escape_xml(uuid)
+            )
+        });
+    }
+
+    if let Some(pubdate) = &mi.pubdate {
+        let event = if version3 {
+            ""
+        } else {
+            " opf:event=\"publication\""
+        };
+        out.push(format!(
+            "<dc:date{}>{}</dc:date>",
+            event,
+            pubdate.to_rfc3339()
+        ));
+    }
+
+    let mut meta = |name: &str, content: &str| {
+        out.push(format!(
+            "<meta name=\"{}\" content=\"{}\"/>",
+            name,
+            escape_xml(content)
+        ))
+    };
+    if let Some(series) = &mi.series {
+        meta("calibre:series", series);
+        meta("calibre:series_index", &mi.series_index.to_string());
+    }
+    if let Some(rating) = mi.rating {
+        meta("calibre:rating", &rating.to_string());
+    }
+    if let Some(timestamp) = &mi.timestamp {
+        meta("calibre:timestamp", &timestamp.to_rfc3339());
+    }
+    if let Some(title_sort) = &mi.title_sort {
+        meta("calibre:title_sort", title_sort);
+    }
+    if let Some(cover_id) = cover_id {
+        meta("cover", cover_id);
+    }
+    out
+}
+
+/// `base`, or `base` with a number appended if an element already uses it.
+fn unique_id(base: &str, ids: &HashSet<&str>) -> String {
+    (1..)
+        .map(|i| match i {
+            1 => base.to_string(),
+            i => format!("{}_{}", base, i),
+        })
+        .find(|id| !ids.contains(id.as_str()))
+        .unwrap()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,10 +1,15 @@
+use crate::metadata::utils::{image_as, rewrite_children, rewrite_zip};
 use crate::metadata::MetaInformation;
-use anyhow::Result;
+use crate::oeb::parse_utils::escape_xml;
+use anyhow::{Context, Result};
 use base64::{engine::general_purpose, Engine as _};
-use roxmltree::Document;
-use std::io::{Read, Seek};
+use image::ImageFormat;
+use roxmltree::{Document, Node};
+use std::io::{Cursor, Read, Seek};
 use zip::ZipArchive;
 
+const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+
 pub fn get_metadata<R: Read + Seek>(mut stream: R) -> Result<MetaInformation> {
     // Check if zip
     let start_pos = stream.stream_position()?;
@@ -125,9 +130,22 @@ fn parse_fb2(xml: &str) -> Result<MetaInformation> {
             .descendants()
             .find(|n| n.tag_name().name().eq_ignore_ascii_case("annotation"))
         {
-            // Annotation can contain HTML-like tags. roxmltree check text content recursively?
-            // Just take text for now.
-            if let Some(t) = annot.text() {
+            // The annotation is made of paragraphs with inline markup
+            let paragraphs: Vec<String> = annot
+                .children()
+                .filter(|n| n.is_element())
+                .map(|p| {
+                    p.descendants()
+                        .filter_map(|n| n.is_text().then(|| n.text()).flatten())
+                        .collect::<String>()
+                        .trim()
+                        .to_string()
+                })
+                .filter(|p| !p.is_empty())
+                .collect();
+            if !paragraphs.is_empty() {
+                mi.comments = Some(paragraphs.join("\n\n"));
+            } else if let Some(t) = annot.text() {
                 mi.comments = Some(t.trim().to_string());
             }
         }
@@ -174,6 +192,153 @@ fn parse_fb2(xml: &str) -> Result<MetaInformation> {
     Ok(mi)
 }
 
+/// Write `mi` into the title-info of the FB2 `data`, plain or zipped.
+/// A cover in `mi` replaces the coverpage image.
+pub fn set_metadata(data: &[u8], mi: &MetaInformation) -> Result<Vec<u8>> {
+    if !data.starts_with(b"PK\x03\x04") {
+        return set_fb2_metadata(data, mi);
+    }
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    let name = archive
+        .file_names()
+        .find(|n| n.to_lowercase().ends_with(".fb2"))
+        .context("No FB2 file in the archive")?
+        .to_string();
+    let mut fb2 = Vec::new();
+    archive.by_name(&name)?.read_to_end(&mut fb2)?;
+    let fb2 = set_fb2_metadata(&fb2, mi)?;
+    rewrite_zip(data, &[(name, fb2)], None)
+}
+
+fn set_fb2_metadata(data: &[u8], mi: &MetaInformation) -> Result<Vec<u8>> {
+    // Keep the encoding the file declares, many are windows-1251
+    let head = String::from_utf8_lossy(&data[..data.len().min(200)]).to_string();
+    let encoding = head
+        .split_once("encoding=")
+        .and_then(|(_, rest)| rest.get(1..))
+        .and_then(|rest| rest.split(['"', '\'']).next())
+        .and_then(|label| encoding_rs::Encoding::for_label(label.as_bytes()))
+        .unwrap_or(encoding_rs::UTF_8);
+    let (xml, _, _) = encoding.decode(data);
+    let xml = xml.into_owned();
+
+    let doc = Document::parse(&xml)?;
+    let root = doc.root_element();
+    let title_info = root
+        .descendants()
+        .find(|n| n.tag_name().name() == "title-info")
+        .context("No title-info in FB2")?;
+    let xlink = root.lookup_prefix(XLINK_NS).unwrap_or("l");
+    let child = |name: &'static str| {
+        title_info
+            .children()
+            .filter(move |n| n.tag_name().name() == name)
+    };
+    let original = |n: Node| xml[n.range()].to_string();
+
+    // The cover goes in a binary, whose id the coverpage points at
+    let mut cover = None;
+    if !mi.cover_data.1.is_empty() {
+        let (content_type, image) = match image::guess_format(&mi.cover_data.1) {
+            Ok(ImageFormat::Png) => ("image/png", mi.cover_data.1.clone()),
+            _ => ("image/jpeg", image_as(&mi.cover_data.1, ImageFormat::Jpeg)?),
+        };
+        let existing = child("coverpage")
+            .flat_map(|n| n.descendants())
+            .filter(|n| n.tag_name().name() == "image")
+            .find_map(|n| n.attributes().find(|a| a.name() == "href"))
+            .map(|a| a.value().trim_start_matches('#').to_string());
+        let id = existing.unwrap_or_else(|| "cover.jpg".to_string());
+        cover = Some((id, content_type, general_purpose::STANDARD.encode(image)));
+    }
+
+    // title-info children have a fixed order
+    let mut items = Vec::new();
+    for tag in &mi.tags {
+        items.push(format!("<genre>{}</genre>", escape_xml(tag)));
+    }
+    for author in &mi.authors {
+        items.push(fb2_author(author));
+    }
+    items.push(format!(
+        "<book-title>{}</book-title>",
+        escape_xml(&mi.title)
+    ));
+    if let Some(comments) = &mi.comments {
+        let paragraphs: String = comments
+            .split("\n\n")
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(|p| format!("<p>{}</p>", escape_xml(p)))
+            .collect();
+        items.push(format!("<annotation>{}</annotation>", paragraphs));
+    }
+    items.extend(child("keywords").chain(child("date")).map(original));
+    match &cover {
+        Some((id, _, _)) => items.push(format!(
+            "<coverpage><image {}:href=\"#{}\"/></coverpage>",
+            xlink,
+            escape_xml(id)
+        )),
+        None => items.extend(child("coverpage").map(original)),
+    }
+    match mi.languages.iter().find(|l| l.as_str() != "und") {
+        Some(lang) => items.push(format!("<lang>{}</lang>", escape_xml(lang))),
+        None => items.extend(child("lang").map(original)),
+    }
+    items.extend(child("src-lang").chain(child("translator")).map(original));
+    if let Some(series) = &mi.series {
+        items.push(format!(
+            "<sequence name=\"{}\" number=\"{}\"/>",
+            escape_xml(series),
+            mi.series_index
+        ));
+    }
+    let mut xml = rewrite_children(&xml, title_info, |_| false, &items, &[(xlink, XLINK_NS)]);
+
+    if let Some((id, content_type, encoded)) = cover {
+        let doc = Document::parse(&xml)?;
+        let binary = format!(
+            "<binary id=\"{}\" content-type=\"{}\">{}</binary>",
+            escape_xml(&id),
+            content_type,
+            encoded
+        );
+        let updated = rewrite_children(
+            &xml,
+            doc.root_element(),
+            |n| !(n.tag_name().name() == "binary" && n.attribute("id") == Some(id.as_str())),
+            &[binary],
+            &[],
+        );
+        xml = updated;
+    }
+
+    Ok(encoding.encode(&xml).0.into_owned())
+}
+
+/// An FB2 author element, splitting the name into its parts.
+fn fb2_author(author: &str) -> String {
+    let parts: Vec<&str> = author.split_whitespace().collect();
+    let (first, middle, last) = match parts.as_slice() {
+        [] => ("", String::new(), ""),
+        [only] => (*only, String::new(), ""),
+        [first, middle @ .., last] => (*first, middle.join(" "), *last),
+    };
+    let mut out = format!("<author><first-name>{}</first-name>", escape_xml(first));
+    if !middle.is_empty() {
+        out.push_str(&format!(
+            "<middle-name>{}</middle-name>",
+            escape_xml(&middle)
+        ));
+    }
+    out.push_str(&format!(
+        "<last-name>{}</last-name></author>",
+        escape_xml(last)
+    ));
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
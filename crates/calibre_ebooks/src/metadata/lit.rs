@@ -1,5 +1,7 @@
 use crate::lit::header::{LitHeader, ITOLITLS};
+use crate::lit::reader::LitReader;
 use crate::metadata::MetaInformation;
+use crate::opf::parse_opf;
 use anyhow::{bail, Result};
 use byteorder::{LittleEndian, ReadBytesExt};
 use std::collections::HashMap;
@@ -13,6 +15,13 @@ struct DirectoryEntry {
 }
 
 pub fn get_metadata<R: Read + Seek>(mut stream: R) -> Result<MetaInformation> {
+    if let Ok(mut lit) = LitReader::new(&mut stream) {
+        let opf = lit.read_opf()?;
+        return Ok(parse_opf(&opf)?);
+    }
+
+    // Not a complete LIT file; report what the header allows
+    stream.seek(SeekFrom::Start(0))?;
     let header = LitHeader::parse(&mut stream)?;
 
     // Read Directory Data
@@ -1,16 +1,72 @@
-use crate::metadata::MetaInformation;
+use crate::lrf::document::object_table;
+use crate::lrf::header::LrfHeader;
+use crate::lrf::objects::{LrfObject, ObjectType};
+use crate::metadata::{string_to_authors, MetaInformation};
 use anyhow::Result;
-use std::io::{Read, Seek};
+use std::io::{Read, Seek, SeekFrom};
 
-pub fn get_metadata<R: Read + Seek>(_stream: R) -> Result<MetaInformation> {
-    // LRF metadata parsing is quite complex (binary objects).
-    // For this initial port, we will return default/unknown metadata
-    // rather than implementing a full binary parser for proprietary LRF.
-    // TODO: Implement full LRF metadata extraction.
+pub fn get_metadata<R: Read + Seek>(mut stream: R) -> Result<MetaInformation> {
+    let mut data = Vec::new();
+    stream.seek(SeekFrom::Start(0))?;
+    stream.read_to_end(&mut data)?;
+
+    let header = LrfHeader::parse(&data)?;
+    let info = header.read_info(&data)?;
 
     let mut mi = MetaInformation::default();
-    mi.title = "Unknown LRF".to_string();
-    mi.authors = vec!["Unknown".to_string()];
+    let is_unknown = |s: &str| s.is_empty() || s.to_lowercase().contains("unknown");
+    mi.title = if is_unknown(&info.title) {
+        "Unknown".to_string()
+    } else {
+        info.title.clone()
+    };
+    mi.authors = string_to_authors(&info.author);
+    if mi.authors.is_empty() || is_unknown(&info.author) {
+        mi.authors = vec!["Unknown".to_string()];
+    }
+    if !info.title_reading.is_empty() {
+        mi.title_sort = Some(info.title_reading.clone());
+    }
+    if !info.author_reading.is_empty() {
+        mi.author_sort = Some(info.author_reading.clone());
+    }
+    if !is_unknown(&info.publisher) && !info.publisher.to_lowercase().contains("some publisher") {
+        mi.publisher = Some(info.publisher.clone());
+    }
+    mi.tags = format!("{}, {}", info.category, info.classification)
+        .split(',')
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect();
+    if !info.free_text.is_empty() {
+        mi.comments = Some(info.free_text.clone());
+    }
+    if !info.language.is_empty() {
+        mi.languages = vec![info.language.clone()];
+    }
+    if let Some((ext, data)) = get_cover(&data, &header) {
+        mi.cover_data = (Some(ext.to_string()), data);
+    }
 
     Ok(mi)
 }
+
+/// The image stream of the first image object in the book.
+fn get_cover(data: &[u8], header: &LrfHeader) -> Option<(&'static str, Vec<u8>)> {
+    let table = object_table(data, header).ok()?;
+    let parse = |(_, offset, size): (u32, usize, usize)| {
+        LrfObject::parse(data, offset, size, header.xor_key).ok()
+    };
+    // The object type follows the id in the object start tag
+    let image = table
+        .iter()
+        .find(|(_, offset, _)| data.get(offset + 6..offset + 8) == Some(&[0x0C, 0x00]))
+        .and_then(|entry| parse(*entry))?;
+    let refstream = image.dword(0xF54C)?;
+    let stream = table
+        .iter()
+        .find(|(id, _, _)| *id == refstream)
+        .and_then(|entry| parse(*entry))
+        .filter(|o| o.kind == ObjectType::ImageStream)?;
+    Some((stream.image_extension()?, stream.stream))
+}
@@ -155,6 +155,66 @@ impl MetaInformation {
         self.identifiers.insert(key.to_string(), value.to_string());
     }
 
+    /// Merge the information in `other` into self. In case of conflicts the
+    /// information in `other` wins, unless it is missing or unknown.
+    pub fn smart_update(&mut self, other: &MetaInformation) {
+        let unknown = "Unknown";
+        if !other.title.is_empty() && other.title != unknown {
+            self.title = other.title.clone();
+            self.title_sort = other.title_sort.clone();
+        }
+        let self_unknown = self.authors.is_empty()
+            || (self.authors.len() == 1
+                && self.authors[0] == unknown
+                && self.author_sort.as_deref().is_none_or(|s| s == unknown));
+        if !other.authors.is_empty() && (other.authors[0] != unknown || self_unknown) {
+            self.authors = other.authors.clone();
+            self.author_sort_map = other.author_sort_map.clone();
+            self.author_sort = other.author_sort.clone();
+        }
+
+        let copy = |dest: &mut Option<String>, src: &Option<String>| {
+            if src.as_deref().is_some_and(|s| !s.is_empty()) {
+                *dest = src.clone();
+            }
+        };
+        copy(&mut self.publisher, &other.publisher);
+        copy(&mut self.comments, &other.comments);
+        copy(&mut self.uuid, &other.uuid);
+        if other.series.as_deref().is_some_and(|s| !s.is_empty()) {
+            self.series = other.series.clone();
+            self.series_index = other.series_index;
+        }
+        if other.rating.is_some() {
+            self.rating = other.rating;
+        }
+        if other.pubdate.is_some() {
+            self.pubdate = other.pubdate;
+        }
+        if !other.languages.is_empty() && other.languages != ["und"] {
+            self.languages = other.languages.clone();
+        }
+        for (key, val) in &other.identifiers {
+            self.identifiers.insert(key.clone(), val.clone());
+        }
+
+        // Case insensitive but case preserving merging of tags
+        for tag in &other.tags {
+            match self
+                .tags
+                .iter_mut()
+                .find(|t| t.to_lowercase() == tag.to_lowercase())
+            {
+                Some(existing) => *existing = tag.clone(),
+                None => self.tags.push(tag.clone()),
+            }
+        }
+
+        if other.cover_data.1.len() > self.cover_data.1.len() {
+            self.cover_data = other.cover_data.clone();
+        }
+    }
+
     pub fn to_xml(&self) -> String {
         let mut out = String::from("<?xml version='1.0' encoding='utf-8'?>\n<package xmlns=\"http://www.idpf.org/2007/opf\" unique-identifier=\"uuid_id\" version=\"2.0\">\n  <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:opf=\"http://www.idpf.org/2007/opf\">\n");
 
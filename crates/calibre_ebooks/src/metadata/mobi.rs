@@ -1,8 +1,22 @@
+use crate::metadata::utils::image_as;
 use crate::metadata::MetaInformation;
+use crate::mobi::headers::ExthHeader;
 use crate::pdb::header::PdbHeader;
-use anyhow::{bail, Result};
-use byteorder::{BigEndian, ReadBytesExt};
-use std::io::{Read, Seek, SeekFrom};
+use anyhow::{bail, Context, Result};
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
+use chrono::{DateTime, NaiveDate, Utc};
+use image::imageops::FilterType;
+use image::ImageFormat;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+/// EXTH records `set_metadata` rewrites, all others are kept.
+const MANAGED_EXTH: [u32; 8] = [100, 101, 103, 104, 105, 106, 503, 524];
+const EXTH_ASIN: u32 = 113;
+const EXTH_KF8_HEADER_INDEX: u32 = 121;
+const EXTH_COVER_OFFSET: u32 = 201;
+const EXTH_THUMB_OFFSET: u32 = 202;
+/// Size kindlegen makes the thumbnail fit in.
+const THUMBNAIL_SIZE: (u32, u32) = (180, 240);
 
 pub fn get_metadata<R: Read + Seek>(mut stream: R) -> Result<MetaInformation> {
     eprintln!("get_metadata: parsing PdbHeader");
@@ -73,6 +87,8 @@ pub fn get_metadata<R: Read + Seek>(mut stream: R) -> Result<MetaInformation> {
         if &exth_sig == b"EXTH" {
             let _len = stream.read_u32::<BigEndian>()?;
             let count = stream.read_u32::<BigEndian>()?;
+            let mut has_authors = false;
+            let mut has_languages = false;
 
             for _ in 0..count {
                 let id = stream.read_u32::<BigEndian>()?;
@@ -86,14 +102,31 @@ pub fn get_metadata<R: Read + Seek>(mut stream: R) -> Result<MetaInformation> {
 
                 match id {
                     100 => {
-                        if let Ok(s) = String::from_utf8(data) {
-                            mi.authors = vec![s];
+                        if !has_authors {
+                            mi.authors.clear();
+                            has_authors = true;
                         }
+                        mi.authors.push(exth_string(&data));
                     }
-                    503 => {
-                        if let Ok(s) = String::from_utf8(data) {
-                            mi.title = s;
+                    101 => mi.publisher = Some(exth_string(&data)),
+                    103 => mi.comments = Some(exth_string(&data)),
+                    104 => {
+                        mi.identifiers
+                            .insert("isbn".to_string(), exth_string(&data));
+                    }
+                    105 => mi.tags.push(exth_string(&data)),
+                    106 => mi.pubdate = parse_exth_date(&exth_string(&data)),
+                    113 => {
+                        mi.identifiers
+                            .insert("mobi-asin".to_string(), exth_string(&data));
+                    }
+                    503 => mi.title = exth_string(&data),
+                    524 => {
+                        if !has_languages {
+                            mi.languages.clear();
+                            has_languages = true;
                         }
+                        mi.languages.push(exth_string(&data));
                     }
                     201 => {
                         // Cover Offset
@@ -128,6 +161,193 @@ pub fn get_metadata<R: Read + Seek>(mut stream: R) -> Result<MetaInformation> {
     Ok(mi)
 }
 
+fn exth_string(data: &[u8]) -> String {
+    String::from_utf8_lossy(data).trim().to_string()
+}
+
+fn parse_exth_date(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    let date = value.get(..10)?;
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()
+        .map(|d| d.and_hms_opt(0, 0, 0).unwrap().and_utc())
+}
+
+/// Write `mi` into the MOBI/AZW3 `data` by rebuilding the EXTH header of
+/// record 0, and of the KF8 record 0 of joint files. The cover and the
+/// thumbnail records are replaced when `mi` has a cover and the book
+/// already has those records.
+pub fn set_metadata(data: &[u8], mi: &MetaInformation) -> Result<Vec<u8>> {
+    let pdb = PdbHeader::parse(&mut Cursor::new(data))?;
+    if &pdb.type_id != b"BOOK" || &pdb.creator_id != b"MOBI" {
+        bail!("Not a MOBI file");
+    }
+    let mut records = Vec::with_capacity(pdb.records.len());
+    for (i, record) in pdb.records.iter().enumerate() {
+        let end = pdb
+            .records
+            .get(i + 1)
+            .map_or(data.len(), |r| r.offset as usize);
+        let start = record.offset as usize;
+        if start > end || end > data.len() {
+            bail!("Invalid offset for record {}", i);
+        }
+        records.push(data[start..end].to_vec());
+    }
+    if records.is_empty() {
+        bail!("No records in PDB");
+    }
+
+    let exth = update_record0(&mut records[0], mi)?;
+    let exth_u32 = |id: u32| {
+        exth.iter()
+            .find(|(t, d)| *t == id && d.len() >= 4)
+            .map(|(_, d)| BigEndian::read_u32(d) as usize)
+    };
+    if let Some(kf8) = exth_u32(EXTH_KF8_HEADER_INDEX) {
+        if kf8 > 0 && kf8 < records.len() && records[kf8].get(16..20) == Some(b"MOBI") {
+            update_record0(&mut records[kf8], mi)?;
+        }
+    }
+
+    if !mi.cover_data.1.is_empty() {
+        let first_image = BigEndian::read_u32(&records[0][108..112]) as usize;
+        if let Some(index) = exth_u32(EXTH_COVER_OFFSET).map(|o| first_image + o) {
+            if index < records.len() {
+                records[index] = image_as(&mi.cover_data.1, ImageFormat::Jpeg)?;
+            }
+        }
+        if let Some(index) = exth_u32(EXTH_THUMB_OFFSET).map(|o| first_image + o) {
+            if index < records.len() {
+                let img = image::load_from_memory(&mi.cover_data.1)
+                    .context("Failed to read cover image")?;
+                let thumb = img.resize(THUMBNAIL_SIZE.0, THUMBNAIL_SIZE.1, FilterType::Triangle);
+                let mut out = Cursor::new(Vec::new());
+                image::DynamicImage::ImageRgb8(thumb.to_rgb8())
+                    .write_to(&mut out, ImageFormat::Jpeg)?;
+                records[index] = out.into_inner();
+            }
+        }
+    }
+
+    // The Palm header and record attributes stay, only the offsets move
+    let list_end = 78 + records.len() * 8;
+    let data_start = pdb.records[0].offset as usize;
+    if data_start < list_end {
+        bail!("Record list overlaps the records");
+    }
+    let mut out = data[..list_end].to_vec();
+    let mut offset = data_start;
+    for (i, record) in records.iter().enumerate() {
+        BigEndian::write_u32(&mut out[78 + i * 8..], offset as u32);
+        offset += record.len();
+    }
+    out.extend_from_slice(&data[list_end..data_start]);
+    for record in &records {
+        out.extend_from_slice(record);
+    }
+    Ok(out)
+}
+
+/// Rebuild the EXTH header and full name of the MOBI header `rec0`.
+/// Returns the new EXTH records.
+fn update_record0(rec0: &mut Vec<u8>, mi: &MetaInformation) -> Result<Vec<(u32, Vec<u8>)>> {
+    if rec0.len() < 0x84 || &rec0[16..20] != b"MOBI" {
+        bail!("Invalid MOBI header");
+    }
+    let header_end = 16 + BigEndian::read_u32(&rec0[20..24]) as usize;
+    if header_end < 0x84 || header_end > rec0.len() {
+        bail!("Invalid MOBI header length");
+    }
+    let cp1252 = BigEndian::read_u32(&rec0[28..32]) == 1252;
+    let encode = |s: &str| -> Vec<u8> {
+        if cp1252 {
+            encoding_rs::WINDOWS_1252.encode(s).0.into_owned()
+        } else {
+            s.as_bytes().to_vec()
+        }
+    };
+
+    let flags = BigEndian::read_u32(&rec0[0x80..0x84]);
+    let old = if flags & 0x40 != 0 {
+        ExthHeader::parse(&mut &rec0[header_end..])?.records
+    } else {
+        Vec::new()
+    };
+
+    let mut records: Vec<(u32, Vec<u8>)> = old
+        .into_iter()
+        .filter(|(id, _)| !MANAGED_EXTH.contains(id))
+        .collect();
+    let asin = mi
+        .identifiers
+        .get("mobi-asin")
+        .or_else(|| mi.identifiers.get("amazon"));
+    if let Some(asin) = asin {
+        records.retain(|(id, _)| *id != EXTH_ASIN);
+        records.push((EXTH_ASIN, encode(asin)));
+    }
+    for author in &mi.authors {
+        records.push((100, encode(author)));
+    }
+    if let Some(publisher) = &mi.publisher {
+        records.push((101, encode(publisher)));
+    }
+    if let Some(comments) = &mi.comments {
+        records.push((103, encode(comments)));
+    }
+    if let Some(isbn) = mi.identifiers.get("isbn") {
+        records.push((104, encode(isbn)));
+    }
+    for tag in &mi.tags {
+        records.push((105, encode(tag)));
+    }
+    if let Some(pubdate) = &mi.pubdate {
+        records.push((106, encode(&pubdate.to_rfc3339())));
+    }
+    records.push((503, encode(&mi.title)));
+    for lang in mi.languages.iter().filter(|l| l.as_str() != "und") {
+        records.push((524, encode(lang)));
+    }
+
+    let mut exth = b"EXTH".to_vec();
+    let len: usize = 12 + records.iter().map(|(_, d)| 8 + d.len()).sum::<usize>();
+    exth.extend_from_slice(&(len as u32).to_be_bytes());
+    exth.extend_from_slice(&(records.len() as u32).to_be_bytes());
+    for (id, data) in &records {
+        exth.extend_from_slice(&id.to_be_bytes());
+        exth.extend_from_slice(&(8 + data.len() as u32).to_be_bytes());
+        exth.extend_from_slice(data);
+    }
+    exth.resize(len.div_ceil(4) * 4, 0);
+
+    // Whatever followed the old full name (usually padding) is kept
+    let name_offset = BigEndian::read_u32(&rec0[0x54..0x58]) as usize;
+    let name_end = name_offset + BigEndian::read_u32(&rec0[0x58..0x5c]) as usize;
+    let tail = if name_offset >= header_end && name_end <= rec0.len() {
+        rec0[name_end..].to_vec()
+    } else {
+        Vec::new()
+    };
+
+    let name = encode(&mi.title);
+    let mut new = rec0[..header_end].to_vec();
+    BigEndian::write_u32(&mut new[0x80..0x84], flags | 0x40);
+    BigEndian::write_u32(&mut new[0x54..0x58], (header_end + exth.len()) as u32);
+    BigEndian::write_u32(&mut new[0x58..0x5c], name.len() as u32);
+    new.extend_from_slice(&exth);
+    new.extend_from_slice(&name);
+    if !tail.starts_with(&[0, 0]) {
+        new.extend_from_slice(&[0, 0]);
+    }
+    new.extend_from_slice(&tail);
+    new.resize(new.len().div_ceil(4) * 4, 0);
+    *rec0 = new;
+    Ok(records)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,11 +41,16 @@ pub use author_mapper::{cap_author_token, compile_rules, map_authors, Rule};
 pub use authors::{author_to_author_sort, authors_to_string, string_to_authors};
 pub use meta::{check_isbn, title_sort, MetaInformation};
 
-use anyhow::{bail, Result};
-use std::fs::File;
+use anyhow::{bail, Context, Result};
+use std::fs::{self, File};
 use std::io::BufReader;
 use std::path::Path;
 
+/// Extensions `set_metadata` can write into.
+pub const METADATA_WRITERS: [&str; 10] = [
+    "epub", "mobi", "prc", "azw", "azw3", "pdf", "docx", "odt", "fb2", "cbz",
+];
+
 pub fn get_metadata<P: AsRef<Path>>(path: P) -> Result<MetaInformation> {
     let path = path.as_ref();
     let ext = path
@@ -65,7 +70,8 @@ pub fn get_metadata<P: AsRef<Path>>(path: P) -> Result<MetaInformation> {
         "pdf" => pdf::get_metadata(stream),
         "rb" => rb::get_metadata(stream),
         "imp" => imp::get_metadata(stream),
-        "lrf" | "lrx" => lrx::get_metadata(stream),
+        "lrf" => lrf::get_metadata(stream),
+        "lrx" => lrx::get_metadata(stream),
         "azw4" => azw4::get_metadata(stream),
         "chm" => chm::get_metadata(stream),
         "docx" => docx::get_metadata(stream),
@@ -81,3 +87,91 @@ pub fn get_metadata<P: AsRef<Path>>(path: P) -> Result<MetaInformation> {
         _ => bail!("Unsupported format: {}", ext),
     }
 }
+
+/// Formats later in the list have better metadata, it overrides that of
+/// the formats before them.
+const METADATA_PRIORITIES: [&str; 20] = [
+    "html", "htm", "xhtml", "xhtm", "rtf", "fb2", "pdf", "prc", "odt", "epub", "lit", "lrx", "lrf",
+    "mobi", "azw", "azw3", "azw1", "rb", "imp", "snb",
+];
+
+fn path_to_ext(path: &Path) -> String {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_lowercase())
+        .unwrap_or_default()
+}
+
+/// The metadata of a book from all its formats. An OPF among them is used
+/// as is, otherwise the metadata of each format is merged in order of
+/// priority. Falls back to the file name for the title.
+pub fn metadata_from_formats<P: AsRef<Path>>(formats: &[P]) -> MetaInformation {
+    let mut formats: Vec<&Path> = formats.iter().map(|p| p.as_ref()).collect();
+    let priority = |path: &&Path| {
+        let ext = path_to_ext(path);
+        METADATA_PRIORITIES
+            .iter()
+            .position(|e| *e == ext)
+            .map_or(0, |i| i + 1)
+    };
+    formats.sort_by_key(priority);
+
+    if let Some(opf) = formats.iter().find(|p| path_to_ext(p) == "opf") {
+        let mi = fs::read_to_string(opf)
+            .ok()
+            .and_then(|xml| crate::opf::parse_opf(&xml).ok());
+        if let Some(mi) = mi.filter(|mi| !mi.title.is_empty() && mi.title != "Unknown") {
+            return mi;
+        }
+    }
+
+    let mut mi = MetaInformation::new("", Vec::new());
+    for path in formats.iter().filter(|p| path_to_ext(p) != "opf") {
+        if let Ok(newmi) = get_metadata(path) {
+            mi.smart_update(&newmi);
+        }
+    }
+    if mi.title.is_empty() || mi.title == "Unknown" {
+        if let Some(stem) = formats.first().and_then(|p| p.file_stem()) {
+            mi.title = stem.to_string_lossy().to_string();
+        }
+    }
+    if mi.title.is_empty() {
+        mi.title = "Unknown".to_string();
+    }
+    if mi.authors.is_empty() {
+        mi.authors = vec!["Unknown".to_string()];
+    }
+    mi
+}
+
+/// Write `mi` into the e-book file at `path`. The file is only replaced
+/// once the new version has been written in full.
+pub fn set_metadata<P: AsRef<Path>>(path: P, mi: &MetaInformation) -> Result<()> {
+    let path = path.as_ref();
+    let ext = path
+        .extension()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_lowercase())
+        .unwrap_or_default();
+
+    let data = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let updated = match ext.as_str() {
+        "epub" => epub::set_metadata(&data, mi),
+        "mobi" | "prc" | "azw" | "azw3" => mobi::set_metadata(&data, mi),
+        "pdf" => pdf::set_metadata(&data, mi),
+        "docx" => docx::set_metadata(&data, mi),
+        "odt" => odt::set_metadata(&data, mi),
+        "fb2" => fb2::set_metadata(&data, mi),
+        "cbz" => zip::set_metadata(&data, mi),
+        _ => bail!("Writing metadata is not supported for format: {}", ext),
+    }
+    .with_context(|| format!("Failed to write metadata into {:?}", path))?;
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+    std::io::Write::write_all(&mut tmp, &updated)?;
+    tmp.persist(path)
+        .with_context(|| format!("Failed to replace {:?}", path))?;
+    Ok(())
+}
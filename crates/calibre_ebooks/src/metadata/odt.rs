@@ -1,8 +1,14 @@
-use crate::metadata::{string_to_authors, MetaInformation};
+use crate::metadata::utils::{read_zip_entry, rewrite_children, rewrite_zip};
+use crate::metadata::{authors_to_string, string_to_authors, MetaInformation};
+use crate::oeb::parse_utils::escape_xml;
 use anyhow::{Context, Result};
-use std::io::{Read, Seek};
+use roxmltree::{Document, Node};
+use std::io::{Cursor, Read, Seek};
 use zip::ZipArchive;
 
+const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+const META_NS: &str = "urn:oasis:names:tc:opendocument:xmlns:meta:1.0";
+
 pub fn get_metadata<R: Read + Seek>(mut stream: R) -> Result<MetaInformation> {
     let mut archive = ZipArchive::new(&mut stream)?;
 
@@ -19,13 +25,6 @@ fn parse_metadata(xml: &str) -> Result<MetaInformation> {
     let doc = roxmltree::Document::parse(xml)?;
     let mut mi = MetaInformation::default();
 
-    // Namespaces in roxmltree are handled via Uri.
-    // DC = http://purl.org/dc/elements/1.1/
-    // META = urn:oasis:names:tc:opendocument:xmlns:meta:1.0
-
-    const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
-    const META_NS: &str = "urn:oasis:names:tc:opendocument:xmlns:meta:1.0";
-
     // Traverse
     for node in doc.descendants() {
         if node.is_element() {
@@ -95,6 +94,73 @@ fn parse_metadata(xml: &str) -> Result<MetaInformation> {
     Ok(mi)
 }
 
+/// Write `mi` into `meta.xml` of the ODT `data`. Fields ODF has no element
+/// for are stored as the `opf.*` user defined fields calibre reads back.
+pub fn set_metadata(data: &[u8], mi: &MetaInformation) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    let xml = read_zip_entry(&mut archive, "meta.xml").context("No meta.xml in ODT")?;
+    let doc = Document::parse(&xml)?;
+    let meta = doc
+        .descendants()
+        .find(|n| n.tag_name().name() == "meta" && n.parent_element() == Some(doc.root_element()))
+        .context("No office:meta in meta.xml")?;
+
+    let mut added = vec![
+        format!("<dc:title>{}</dc:title>", escape_xml(&mi.title)),
+        format!(
+            "<dc:creator>{}</dc:creator>",
+            escape_xml(&authors_to_string(&mi.authors))
+        ),
+    ];
+    if let Some(comments) = &mi.comments {
+        added.push(format!(
+            "<dc:description>{}</dc:description>",
+            escape_xml(comments)
+        ));
+    }
+    if let Some(lang) = mi.languages.iter().find(|l| l.as_str() != "und") {
+        added.push(format!("<dc:language>{}</dc:language>", escape_xml(lang)));
+    }
+    for tag in &mi.tags {
+        added.push(format!("<meta:keyword>{}</meta:keyword>", escape_xml(tag)));
+    }
+    let mut user_defined = |name: &str, value: &str| {
+        added.push(format!(
+            "<meta:user-defined meta:name=\"{}\">{}</meta:user-defined>",
+            name,
+            escape_xml(value)
+        ))
+    };
+    if let Some(series) = &mi.series {
+        user_defined("opf.series", series);
+        user_defined("opf.seriesindex", &mi.series_index.to_string());
+    }
+    if let Some(publisher) = &mi.publisher {
+        user_defined("opf.publisher", publisher);
+    }
+    if let Some(title_sort) = &mi.title_sort {
+        user_defined("opf.titlesort", title_sort);
+    }
+
+    let managed = |n: Node| match (n.tag_name().namespace(), n.tag_name().name()) {
+        (Some(DC_NS), "title" | "creator" | "description" | "language" | "subject") => true,
+        (Some(META_NS), "keyword") => true,
+        (Some(META_NS), "user-defined") => n
+            .attribute((META_NS, "name"))
+            .is_some_and(|name| name.to_lowercase().starts_with("opf.")),
+        _ => false,
+    };
+    let xml = rewrite_children(
+        &xml,
+        meta,
+        |n| !managed(n),
+        &added,
+        &[("dc", DC_NS), ("meta", META_NS)],
+    );
+
+    rewrite_zip(data, &[("meta.xml".to_string(), xml.into_bytes())], None)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
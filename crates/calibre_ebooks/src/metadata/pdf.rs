@@ -1,6 +1,9 @@
-use crate::metadata::MetaInformation;
-use anyhow::{Context, Result};
-use lopdf::{Document, Object};
+use crate::metadata::xmp::metadata_to_xmp_packet;
+use crate::metadata::{authors_to_string, MetaInformation};
+use anyhow::{bail, Context, Result};
+use calibre_utils::constants::{APP_NAME, VERSION};
+use chrono::Utc;
+use lopdf::{Dictionary, Document, Object, Stream, StringFormat};
 use std::io::{Read, Seek};
 
 pub fn get_metadata<R: Read + Seek>(mut stream: R) -> Result<MetaInformation> {
@@ -35,19 +38,26 @@ pub fn get_metadata<R: Read + Seek>(mut stream: R) -> Result<MetaInformation> {
                 .and_then(|o| from_pdf_object(o).ok())
             {
                 mi.authors = author
-                    .split(&[',', ';'][..])
+                    .split(&[',', ';', '&'][..])
                     .map(|s| s.trim().to_string())
                     .collect();
             }
-            if let Some(subject) = info_dict
+            let keywords = info_dict
+                .get(b"Keywords")
+                .ok()
+                .and_then(|o| from_pdf_object(o).ok());
+            let subject = info_dict
                 .get(b"Subject")
                 .ok()
-                .and_then(|o| from_pdf_object(o).ok())
-            {
-                mi.tags = subject
-                    .split(&[',', ';'][..])
-                    .map(|s| s.trim().to_string())
-                    .collect();
+                .and_then(|o| from_pdf_object(o).ok());
+            // Tags are the keywords, older files only have them as subject
+            match (keywords, subject) {
+                (Some(keywords), subject) => {
+                    mi.tags = split_tags(&keywords);
+                    mi.comments = subject.filter(|s| !s.trim().is_empty());
+                }
+                (None, Some(subject)) => mi.tags = split_tags(&subject),
+                (None, None) => {}
             }
         }
     }
@@ -55,8 +65,93 @@ pub fn get_metadata<R: Read + Seek>(mut stream: R) -> Result<MetaInformation> {
     Ok(mi)
 }
 
+fn split_tags(raw: &str) -> Vec<String> {
+    raw.split(&[',', ';'][..])
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Write `mi` into the PDF `data`: the document Info dictionary, and an
+/// XMP packet as the metadata stream of the catalog.
+pub fn set_metadata(data: &[u8], mi: &MetaInformation) -> Result<Vec<u8>> {
+    let mut doc = Document::load_mem(data).context("Failed to load PDF document")?;
+    if doc.is_encrypted() {
+        bail!("Cannot write metadata into an encrypted PDF");
+    }
+
+    let info_id = match doc.trailer.get(b"Info").and_then(Object::as_reference) {
+        Ok(id) => id,
+        Err(_) => {
+            let id = doc.add_object(Dictionary::new());
+            doc.trailer.set("Info", id);
+            id
+        }
+    };
+    let info = doc
+        .get_dictionary_mut(info_id)
+        .context("Invalid document Info dictionary")?;
+    info.set("Title", pdf_string(&mi.title));
+    info.set("Author", pdf_string(&authors_to_string(&mi.authors)));
+    // Always set, so readers do not take the subject for the tags
+    info.set("Keywords", pdf_string(&mi.tags.join(", ")));
+    if let Some(comments) = &mi.comments {
+        info.set("Subject", pdf_string(comments));
+    } else {
+        info.remove(b"Subject");
+    }
+    info.set("Producer", pdf_string(&format!("{} {}", APP_NAME, VERSION)));
+    info.set(
+        "ModDate",
+        Object::string_literal(Utc::now().format("D:%Y%m%d%H%M%SZ").to_string()),
+    );
+
+    let mut dict = Dictionary::new();
+    dict.set("Type", Object::Name(b"Metadata".to_vec()));
+    dict.set("Subtype", Object::Name(b"XML".to_vec()));
+    let xmp = Stream::new(dict, metadata_to_xmp_packet(mi).into_bytes()).with_compression(false);
+    let existing = doc
+        .catalog()
+        .and_then(|c| c.get(b"Metadata"))
+        .and_then(Object::as_reference);
+    match existing {
+        Ok(id) => {
+            doc.objects.insert(id, Object::Stream(xmp));
+        }
+        Err(_) => {
+            let id = doc.add_object(xmp);
+            if let Ok(catalog) = doc.catalog_mut() {
+                catalog.set("Metadata", id);
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    doc.save_to(&mut out)?;
+    Ok(out)
+}
+
+/// A text string, as UTF-16 when it does not fit in ASCII.
+fn pdf_string(s: &str) -> Object {
+    if s.is_ascii() {
+        return Object::string_literal(s);
+    }
+    let mut bytes = vec![0xfe, 0xff];
+    for unit in s.encode_utf16() {
+        bytes.extend_from_slice(&unit.to_be_bytes());
+    }
+    Object::String(bytes, StringFormat::Hexadecimal)
+}
+
 fn from_pdf_object(obj: &Object) -> Result<String> {
     match obj {
+        Object::String(bytes, _) if bytes.starts_with(&[0xfe, 0xff]) => {
+            let units: Vec<u16> = bytes[2..]
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            Ok(String::from_utf16_lossy(&units))
+        }
         Object::String(bytes, _) => Ok(String::from_utf8_lossy(bytes).to_string()),
         Object::Name(bytes) => Ok(String::from_utf8_lossy(bytes).to_string()),
         _ => anyhow::bail!("Not a string object"),
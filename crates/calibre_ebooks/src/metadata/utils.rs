@@ -1,6 +1,12 @@
 use crate::metadata::MetaInformation;
 use crate::opf::parse_opf as parse_opf_xml;
 use anyhow::{Context, Result};
+use calibre_utils::img::{image_to_data, DEFAULT_QUALITY};
+use image::ImageFormat;
+use roxmltree::Node;
+use std::io::{Cursor, Read, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
 
 pub struct OpfVersion {
     pub major: u32,
@@ -32,6 +38,159 @@ pub fn clean_xml(raw: &str) -> String {
     raw.replace('\0', "")
 }
 
+/// Read a whole zip entry as UTF-8 text.
+pub(crate) fn read_zip_entry<R: Read + std::io::Seek>(
+    archive: &mut ZipArchive<R>,
+    name: &str,
+) -> Result<String> {
+    let mut file = archive
+        .by_name(name)
+        .with_context(|| format!("{} not found in archive", name))?;
+    let mut s = String::new();
+    file.read_to_string(&mut s)?;
+    Ok(s)
+}
+
+/// Copy the zip archive `data`, replacing the entries named in `replace`
+/// and appending the ones it does not have yet. Everything else is copied
+/// without being recompressed, so entry order (an EPUB `mimetype` first)
+/// and compression are kept. `comment` replaces the archive comment.
+pub(crate) fn rewrite_zip(
+    data: &[u8],
+    replace: &[(String, Vec<u8>)],
+    comment: Option<&[u8]>,
+) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(data)).context("Failed to read zip")?;
+    let mut out = ZipWriter::new(Cursor::new(Vec::new()));
+    let mut pending: Vec<&(String, Vec<u8>)> = replace.iter().collect();
+
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i)?;
+        match pending.iter().position(|(name, _)| name == file.name()) {
+            Some(pos) => {
+                let (name, content) = pending.remove(pos);
+                let method = match file.compression() {
+                    CompressionMethod::Stored => CompressionMethod::Stored,
+                    _ => CompressionMethod::Deflated,
+                };
+                drop(file);
+                out.start_file(
+                    name.as_str(),
+                    FileOptions::default().compression_method(method),
+                )?;
+                out.write_all(content)?;
+            }
+            None => out.raw_copy_file(file)?,
+        }
+    }
+    for (name, content) in pending {
+        out.start_file(
+            name.as_str(),
+            FileOptions::default().compression_method(CompressionMethod::Deflated),
+        )?;
+        out.write_all(content)?;
+    }
+    out.set_raw_comment(comment.unwrap_or(archive.comment()).to_vec());
+    Ok(out.finish()?.into_inner())
+}
+
+/// Rebuild the content of the element `parent` of the document `xml`.
+/// Child elements for which `keep` is true are copied verbatim, the others
+/// are dropped and `added` is appended after them, one per line.
+/// `namespaces` are the `(prefix, uri)` pairs `added` relies on, they are
+/// declared on `parent` unless already in scope.
+pub(crate) fn rewrite_children(
+    xml: &str,
+    parent: Node,
+    keep: impl Fn(Node) -> bool,
+    added: &[String],
+    namespaces: &[(&str, &str)],
+) -> String {
+    let range = parent.range();
+    let element = &xml[range.clone()];
+    let self_closing = element.ends_with("/>");
+    let start_tag_len = start_tag_len(element);
+    let mut start_tag = element[..start_tag_len].to_string();
+    let tag_name: String = start_tag[1..]
+        .chars()
+        .take_while(|c| !c.is_whitespace() && *c != '/' && *c != '>')
+        .collect();
+
+    let mut declarations = String::new();
+    for (prefix, uri) in namespaces {
+        if parent.lookup_namespace_uri(Some(prefix)) != Some(*uri) {
+            declarations.push_str(&format!(" xmlns:{}=\"{}\"", prefix, uri));
+        }
+    }
+    let close_at = if self_closing {
+        start_tag.len() - 2
+    } else {
+        start_tag.len() - 1
+    };
+    start_tag.insert_str(close_at, &declarations);
+    if self_closing {
+        start_tag = format!("{}>", start_tag[..start_tag.len() - 2].trim_end());
+    }
+
+    // Indent children one step deeper than the parent
+    let line_start = xml[..range.start].rfind('\n').map_or(0, |i| i + 1);
+    let parent_indent = &xml[line_start..range.start];
+    let parent_indent = if parent_indent.trim().is_empty() {
+        parent_indent
+    } else {
+        ""
+    };
+    let child_indent = format!("{}  ", parent_indent);
+
+    let mut content = String::new();
+    for child in parent.children() {
+        if (child.is_element() && keep(child)) || child.is_comment() {
+            content.push('\n');
+            content.push_str(&child_indent);
+            content.push_str(&xml[child.range()]);
+        }
+    }
+    for item in added {
+        content.push('\n');
+        content.push_str(&child_indent);
+        content.push_str(item);
+    }
+    content.push('\n');
+    content.push_str(parent_indent);
+
+    format!(
+        "{}{}{}</{}>{}",
+        &xml[..range.start],
+        start_tag,
+        content,
+        tag_name,
+        &xml[range.end..]
+    )
+}
+
+/// Length of the start tag at the beginning of `element`.
+fn start_tag_len(element: &str) -> usize {
+    let mut quote = None;
+    for (i, c) in element.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '>') => return i + 1,
+            _ => {}
+        }
+    }
+    element.len()
+}
+
+/// `data` as an image of type `format`, converting it if it is not one.
+pub(crate) fn image_as(data: &[u8], format: ImageFormat) -> Result<Vec<u8>> {
+    if image::guess_format(data).ok() == Some(format) {
+        return Ok(data.to_vec());
+    }
+    let img = image::load_from_memory(data).context("Failed to read cover image")?;
+    image_to_data(&img, format, DEFAULT_QUALITY).context("Failed to convert cover image")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
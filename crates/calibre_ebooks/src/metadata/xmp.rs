@@ -1,5 +1,8 @@
 use crate::metadata::MetaInformation;
+use crate::oeb::parse_utils::escape_xml;
 use anyhow::Result;
+use calibre_utils::constants::{APP_NAME, VERSION};
+use chrono::Utc;
 use roxmltree::{Document, Node};
 
 pub fn metadata_from_xmp_packet(packet: &[u8]) -> Result<MetaInformation> {
@@ -41,13 +44,20 @@ pub fn metadata_from_xmp_packet(packet: &[u8]) -> Result<MetaInformation> {
                     }
                 }
                 "creator" => {
-                    extract_seq_list(&node, &mut mi.authors);
+                    let mut authors = Vec::new();
+                    extract_seq_list(&node, &mut authors);
+                    if !authors.is_empty() {
+                        mi.authors = authors;
+                    }
                 }
                 "subject" => {
                     extract_seq_list(&node, &mut mi.tags);
                 }
                 "publisher" => {
                     extract_simple_text(&node, |t| mi.publisher = Some(t));
+                    if mi.publisher.is_none() {
+                        mi.publisher = extract_localized_text(&node);
+                    }
                 }
                 "description" => {
                     if let Some(t) = extract_localized_text(&node) {
@@ -62,6 +72,88 @@ pub fn metadata_from_xmp_packet(packet: &[u8]) -> Result<MetaInformation> {
     Ok(mi)
 }
 
+/// Serialize `mi` as a complete XMP packet (Dublin Core plus the basic XMP
+/// schema), suitable for a PDF `/Metadata` stream.
+pub fn metadata_to_xmp_packet(mi: &MetaInformation) -> String {
+    let now = Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    let mut dc = String::new();
+
+    dc.push_str(&format!(
+        "   <dc:title>\n    <rdf:Alt>\n     <rdf:li xml:lang=\"x-default\">{}</rdf:li>\n    </rdf:Alt>\n   </dc:title>\n",
+        escape_xml(&mi.title)
+    ));
+    dc.push_str(&xmp_list("dc:creator", "rdf:Seq", &mi.authors));
+    if !mi.tags.is_empty() {
+        dc.push_str(&xmp_list("dc:subject", "rdf:Bag", &mi.tags));
+    }
+    if let Some(publisher) = &mi.publisher {
+        dc.push_str(&xmp_list(
+            "dc:publisher",
+            "rdf:Bag",
+            std::slice::from_ref(publisher),
+        ));
+    }
+    if let Some(comments) = &mi.comments {
+        dc.push_str(&format!(
+            "   <dc:description>\n    <rdf:Alt>\n     <rdf:li xml:lang=\"x-default\">{}</rdf:li>\n    </rdf:Alt>\n   </dc:description>\n",
+            escape_xml(comments)
+        ));
+    }
+    let languages: Vec<String> = mi
+        .languages
+        .iter()
+        .filter(|l| l.as_str() != "und")
+        .cloned()
+        .collect();
+    if !languages.is_empty() {
+        dc.push_str(&xmp_list("dc:language", "rdf:Bag", &languages));
+    }
+    if let Some(pubdate) = &mi.pubdate {
+        let date = pubdate.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+        dc.push_str(&xmp_list("dc:date", "rdf:Seq", &[date]));
+    }
+    if let Some(uuid) = &mi.uuid {
+        dc.push_str(&format!(
+            "   <dc:identifier>urn:uuid:{}</dc:identifier>\n",
+            escape_xml(uuid)
+        ));
+    }
+
+    format!(
+        r#"<?xpacket begin="{bom}" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/">
+{dc}  </rdf:Description>
+  <rdf:Description rdf:about="" xmlns:xmp="http://ns.adobe.com/xap/1.0/">
+   <xmp:CreatorTool>{app} {version}</xmp:CreatorTool>
+   <xmp:CreateDate>{now}</xmp:CreateDate>
+   <xmp:ModifyDate>{now}</xmp:ModifyDate>
+   <xmp:MetadataDate>{now}</xmp:MetadataDate>
+  </rdf:Description>
+  <rdf:Description rdf:about="" xmlns:pdf="http://ns.adobe.com/pdf/1.3/">
+   <pdf:Producer>{app} {version}</pdf:Producer>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#,
+        bom = '\u{feff}',
+        dc = dc,
+        app = APP_NAME,
+        version = VERSION,
+        now = now
+    )
+}
+
+fn xmp_list(tag: &str, container: &str, values: &[String]) -> String {
+    let mut out = format!("   <{}>\n    <{}>\n", tag, container);
+    for value in values {
+        out.push_str(&format!("     <rdf:li>{}</rdf:li>\n", escape_xml(value)));
+    }
+    out.push_str(&format!("    </{}>\n   </{}>\n", container, tag));
+    out
+}
+
 fn extract_localized_text(node: &Node) -> Option<String> {
     // Greedy extraction: find first non-empty text in descendants
     for child in node.descendants() {
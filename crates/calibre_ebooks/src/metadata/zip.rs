@@ -1,4 +1,5 @@
-use crate::metadata::archive::{is_comic, parse_comic_comment};
+use crate::metadata::archive::{comic_comment, is_comic, parse_comic_comment};
+use crate::metadata::utils::rewrite_zip;
 use crate::metadata::MetaInformation;
 use anyhow::{bail, Context, Result};
 use std::io::{Cursor, Read, Seek};
@@ -56,7 +57,8 @@ pub fn get_metadata<R: Read + Seek>(stream: R) -> Result<MetaInformation> {
                 "pdf" => return crate::metadata::pdf::get_metadata(cursor),
                 "rb" => return crate::metadata::rb::get_metadata(cursor),
                 "imp" => return crate::metadata::imp::get_metadata(cursor),
-                "lrf" | "lrx" => return crate::metadata::lrx::get_metadata(cursor),
+                "lrf" => return crate::metadata::lrf::get_metadata(cursor),
+                "lrx" => return crate::metadata::lrx::get_metadata(cursor),
                 "opf" => {
                     // Special OPF handling
                     // Parse OPF
@@ -90,3 +92,15 @@ pub fn get_metadata<R: Read + Seek>(stream: R) -> Result<MetaInformation> {
 
     bail!("No ebook found in ZIP archive")
 }
+
+/// Write `mi` into the comment of the comic archive `data` (CBZ). Other
+/// zip files have no place for metadata.
+pub fn set_metadata(data: &[u8], mi: &MetaInformation) -> Result<Vec<u8>> {
+    let zf = ZipArchive::new(Cursor::new(data)).context("Failed to open ZIP archive")?;
+    let names: Vec<String> = zf.file_names().map(|s| s.to_string()).collect();
+    if !is_comic(&names) {
+        bail!("Only comic archives can hold metadata");
+    }
+    let comment = comic_comment(mi, zf.comment())?;
+    rewrite_zip(data, &[], Some(&comment))
+}
@@ -76,12 +76,15 @@ pub struct MobiHeader {
     pub drm_count: u32,
     pub drm_size: u32,
     pub drm_flags: u32,
+    /// Trailing entries present after the text of each text record
+    pub extra_data_flags: u16,
     // KF8 specific fields
     pub ncx_index: u32,
     pub skel_index: u32,
     pub div_index: u32,
     pub fdst_index: u32,
     pub fdst_count: u32,
+    pub guide_index: u32,
 }
 
 impl MobiHeader {
@@ -128,43 +131,39 @@ impl MobiHeader {
 
         let exth_flags = reader.read_u32::<BigEndian>()?;
 
-        // Skip some fields to get to DRM info (usually at offset 0xA8 from start of header if enough length)
-        // Current read u32 count: 4 (ident) + 27 * 4 = 112 bytes read.
-        // We need to read remaining known fields.
-        // 32 bytes of reserved?
-        reader.seek(SeekFrom::Current(32))?;
-
-        let drm_offset = reader.read_u32::<BigEndian>()?;
-        let drm_count = reader.read_u32::<BigEndian>()?;
-        let drm_size = reader.read_u32::<BigEndian>()?;
-        let drm_flags = reader.read_u32::<BigEndian>()?;
-
-        // Current position: 160 (16 + 4*24 + 32 + 4*4)
-        // ncx_index is at 0xF4 (244) relative to start (if we started at 0)
-        // Only read if header_length is large enough
-
-        let mut ncx_index = NULL_INDEX;
-        let mut skel_index = NULL_INDEX;
-        let mut div_index = NULL_INDEX;
-        let mut fdst_index = NULL_INDEX;
-        let mut fdst_count = 0;
-
-        if header_length >= 248 {
-            // Require enough bytes for ncx_index
-            // Skip from 160 to 244: 84 bytes
-            reader.seek(SeekFrom::Current(84))?;
-            ncx_index = reader.read_u32::<BigEndian>()?;
-        }
+        // The rest of the header is read by offset from the start of record
+        // 0, as its length varies between versions. Fields beyond the end of
+        // the header (or of a truncated record) keep their defaults.
+        let read_so_far = 4 + 28 * 4;
+        let mut rest = Vec::new();
+        reader
+            .by_ref()
+            .take((header_length as u64).saturating_sub(read_so_far))
+            .read_to_end(&mut rest)?;
+        let field = |offset: usize, default: u32| -> u32 {
+            let pos = offset - 16 - read_so_far as usize;
+            rest.get(pos..pos + 4)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+                .unwrap_or(default)
+        };
 
-        if header_length >= 264 {
-            // KF8 Header fields
-            // Skip from 248 (244+4) to 264: 16 bytes
-            reader.seek(SeekFrom::Current(16))?;
-            skel_index = reader.read_u32::<BigEndian>()?;
-            div_index = reader.read_u32::<BigEndian>()?;
-            fdst_index = reader.read_u32::<BigEndian>()?;
-            fdst_count = reader.read_u32::<BigEndian>()?;
-        }
+        let drm_offset = field(0xA8, NULL_INDEX);
+        let drm_count = field(0xAC, 0);
+        let drm_size = field(0xB0, 0);
+        let drm_flags = field(0xB4, 0);
+
+        // KF8 (and late MOBI 6) fields
+        let fdst_index = field(0xC0, NULL_INDEX);
+        let fdst_count = field(0xC4, 0);
+        let extra_data_flags = if header_length >= 0xE4 {
+            field(0xF0, 0) as u16
+        } else {
+            0
+        };
+        let ncx_index = field(0xF4, NULL_INDEX);
+        let div_index = field(0xF8, NULL_INDEX);
+        let skel_index = field(0xFC, NULL_INDEX);
+        let guide_index = field(0x104, NULL_INDEX);
 
         // We assume we don't need to be perfectly at end of header for now
 
@@ -207,6 +206,8 @@ impl MobiHeader {
             div_index,
             fdst_index,
             fdst_count,
+            extra_data_flags,
+            guide_index,
         })
     }
 }
@@ -806,11 +806,11 @@ fn manual_iana2mobi(icode: &str) -> Vec<u8> {
         // Let's just use the logic:
         let lookup_lang = lang.as_str();
         if IANA_MOBI_MAP.contains_key(lookup_lang) {
-            current_lang_code = IANA_MOBI_DATA
-                .iter()
-                .find(|x| x.code == lookup_lang)
-                .unwrap()
-                .code;
+            // The map also holds "und", which has no data entry
+            current_lang_code = IANA_MOBI_MAP
+                .get_key_value(lookup_lang)
+                .map(|(code, _)| *code)
+                .unwrap_or("und");
             subtags.remove(0); // pop(0)
             found_lang = true;
         } else {
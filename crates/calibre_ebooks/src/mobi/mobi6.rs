@@ -1,8 +1,38 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use byteorder::{BigEndian, ReadBytesExt};
+use lazy_static::lazy_static;
+use regex::bytes::Regex as BytesRegex;
+use regex::{Captures, Regex};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
 use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
 
-use crate::mobi::headers::BookHeader;
+use crate::compression::palmdoc;
+use crate::mobi::containers::find_imgtype;
+use crate::mobi::headers::{BookHeader, NULL_INDEX};
+use crate::mobi::huffcdic::HuffReader;
+use crate::mobi::langcodes::mobi2iana;
+use crate::mobi::mobi8::add_toc_entries;
+use crate::mobi::ncx::read_ncx;
+use crate::mobi::utils::get_trailing_data;
+use crate::oeb::book::OEBBook;
+use crate::oeb::container::DirContainer;
+use crate::oeb::parse_utils::escape_xml;
+
+lazy_static! {
+    static ref FILEPOS_PAT: BytesRegex =
+        BytesRegex::new(r#"(?i)<[^<>]+filepos=['"]?0*(\d+)[^<>]*>"#).unwrap();
+    static ref FILEPOS_ATTR_PAT: Regex = Regex::new(r#"(?i)filepos=['"]?(\d+)['"]?"#).unwrap();
+    static ref RECINDEX_ATTR_PAT: Regex = Regex::new(r#"(?i)recindex=['"]?(\d+)['"]?"#).unwrap();
+    static ref ANCHOR_ID_PAT: Regex = Regex::new(r#"id="filepos(\d+)""#).unwrap();
+    static ref BODY_START_PAT: Regex = Regex::new(r"(?i)<body[^>]*>").unwrap();
+    static ref MBP_TAG_PAT: Regex = Regex::new(r"(?i)</?mbp:([a-z_]+)[^>]*>").unwrap();
+    static ref GUIDE_REF_PAT: Regex = Regex::new(r"(?i)<reference\s[^>]*>").unwrap();
+}
+
+/// Marks page breaks while the text is split into files
+const PAGE_BREAK: &str = "\u{0}PAGEBREAK\u{0}";
 
 pub struct MobiReader {
     pub header: Vec<u8>,
@@ -140,13 +170,306 @@ impl MobiReader {
         })
     }
 
-    pub fn extract_content(&self, output_dir: &std::path::Path) -> Result<()> {
-        // TODO: Implement content extraction
-        // 1. Check for DRM
-        // 2. Extract text (lz77/palmdoc/huff/cdic)
-        // 3. Process HTML (upshift markup, cleanup)
-        // 4. Extract images
-        // 5. Create OPF/NCX
-        bail!("extract_content not implemented")
+    /// Extract the MOBI 6 text and its images into `output_dir`. The text is
+    /// split into one file per page break, `filepos` links become anchors and
+    /// `recindex` images point at the extracted files.
+    pub fn extract_content(&self, output_dir: &Path) -> Result<OEBBook> {
+        // The MOBI 6 header, even in joint files
+        let header = BookHeader::parse(&self.sections[0].0, None)?;
+        if header.encryption_type != 0 {
+            bail!("The book is DRM encrypted");
+        }
+        let records: Vec<&[u8]> = self.sections.iter().map(|(d, _)| d.as_slice()).collect();
+        let raw = decompress_text(&header, &records)?;
+
+        fs::create_dir_all(output_dir)?;
+        let mut book = OEBBook::new(Box::new(DirContainer::new(output_dir)));
+        let end = self.kf8_boundary.unwrap_or(self.sections.len());
+        let images = self.extract_images(header.first_image_index, end, output_dir, &mut book)?;
+
+        let sections: Vec<_> = self
+            .sections
+            .iter()
+            .map(|(data, _)| (data.clone(), (0, 0, 0, 0, 0)))
+            .collect();
+        let ncx = read_ncx(&sections, header.mobi.ncx_index, &header.codec).unwrap_or_default();
+
+        let mut positions: BTreeSet<usize> = FILEPOS_PAT
+            .captures_iter(&raw)
+            .filter_map(|c| std::str::from_utf8(&c[1]).ok()?.parse().ok())
+            .collect();
+        positions.extend(ncx.iter().filter(|e| e.pos > 0).map(|e| e.pos as usize));
+        let html = decode_text(&add_anchors(&raw, &positions), &header.codec);
+
+        // Split the body at page breaks, dropping the MOBI only markup
+        let body_start = BODY_START_PAT.find(&html).map_or(0, |m| m.end());
+        let body_end = html.rfind("</body>").unwrap_or(html.len()).max(body_start);
+        let body = MBP_TAG_PAT.replace_all(&html[body_start..body_end], |caps: &Captures| {
+            if caps[1].eq_ignore_ascii_case("pagebreak") {
+                PAGE_BREAK.to_string()
+            } else {
+                String::new()
+            }
+        });
+        let mut segments: Vec<&str> = body
+            .split(PAGE_BREAK)
+            .filter(|s| !s.trim().is_empty())
+            .collect();
+        if segments.is_empty() {
+            segments.push("");
+        }
+
+        let mut anchor_files = HashMap::new();
+        for (i, segment) in segments.iter().enumerate() {
+            for caps in ANCHOR_ID_PAT.captures_iter(segment) {
+                anchor_files.insert(caps[1].to_string(), format!("part{:04}.html", i));
+            }
+        }
+        let href_for = |pos: &str| -> String {
+            let pos = pos.trim_start_matches('0');
+            match anchor_files.get(pos) {
+                Some(file) => format!("{}#filepos{}", file, pos),
+                None => "part0000.html".to_string(),
+            }
+        };
+
+        let title = escape_xml(&header.title);
+        for (i, segment) in segments.iter().enumerate() {
+            let text = FILEPOS_ATTR_PAT.replace_all(segment, |caps: &Captures| {
+                format!("href=\"{}\"", href_for(&caps[1]))
+            });
+            let text = RECINDEX_ATTR_PAT.replace_all(&text, |caps: &Captures| {
+                let number = caps[1].parse::<usize>().unwrap_or(0);
+                match images.get(&number) {
+                    Some(href) => format!("src=\"{}\"", href),
+                    None => String::new(),
+                }
+            });
+            let doc = format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <html xmlns=\"http://www.w3.org/1999/xhtml\"><head><title>{}</title></head>\
+                 <body>{}</body></html>",
+                title, text
+            );
+            let id = format!("part{:04}", i);
+            let href = format!("{}.html", id);
+            fs::write(output_dir.join(&href), doc)?;
+            book.manifest.add(&id, &href, "application/xhtml+xml");
+            book.spine.add(&id, true);
+        }
+
+        for caps in GUIDE_REF_PAT.captures_iter(&html[..body_start]) {
+            let tag = &caps[0];
+            let attr = |name: &str| {
+                let pat = format!(r#"(?i)\s{}\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s/>]+))"#, name);
+                let caps = Regex::new(&pat).ok()?.captures(tag)?;
+                let value = caps.get(1).or(caps.get(2)).or(caps.get(3))?;
+                Some(value.as_str().to_string())
+            };
+            if let (Some(type_), Some(pos)) = (attr("type"), attr("filepos")) {
+                book.guide.add(&type_, attr("title"), &href_for(&pos));
+            }
+        }
+
+        let hrefs: Vec<Option<String>> = ncx
+            .iter()
+            .map(|e| Some(href_for(&e.pos.max(0).to_string())))
+            .collect();
+        add_toc_entries(&mut book.toc.root, &ncx, &hrefs);
+
+        if let Some(offset) = read_exth_metadata(&header, &mut book) {
+            if let Some(href) = images.get(&(offset + 1)) {
+                let id = book.manifest.get_by_href(href).map(|i| i.id.clone());
+                if let Some(id) = id {
+                    book.metadata.add("cover", &id);
+                }
+            }
+        }
+        Ok(book)
+    }
+
+    /// Write the images from `first` up to `end`, keyed by their 1 based
+    /// `recindex`.
+    fn extract_images(
+        &self,
+        first: u32,
+        end: usize,
+        output_dir: &Path,
+        book: &mut OEBBook,
+    ) -> Result<HashMap<usize, String>> {
+        let mut images = HashMap::new();
+        if first == NULL_INDEX {
+            return Ok(images);
+        }
+        for (i, (data, _)) in self
+            .sections
+            .iter()
+            .enumerate()
+            .take(end)
+            .skip(first as usize)
+        {
+            let kind = match find_imgtype(data) {
+                Some(kind) => kind,
+                None => continue,
+            };
+            let number = i - first as usize + 1;
+            let ext = if kind == "jpeg" { "jpg" } else { kind };
+            let id = format!("image{:05}", number);
+            let href = format!("images/{}.{}", id, ext);
+            fs::create_dir_all(output_dir.join("images"))?;
+            fs::write(output_dir.join(&href), data)?;
+            book.manifest.add(&id, &href, &format!("image/{}", kind));
+            images.insert(number, href);
+        }
+        Ok(images)
+    }
+}
+
+/// Decompress the text records following `records[0]`, the record 0 of a
+/// MOBI 6 or KF8 book.
+pub(crate) fn decompress_text(header: &BookHeader, records: &[&[u8]]) -> Result<Vec<u8>> {
+    let count = header.palmdoc.record_count as usize;
+    let flags = header.mobi.extra_data_flags as u32;
+    let mut huff = match header.compression_type {
+        17480 => {
+            let start = header.huff_offset.unwrap_or(0) as usize;
+            let end = start + header.huff_number.unwrap_or(0) as usize;
+            let huffs: Vec<Vec<u8>> = records
+                .get(start..end)
+                .context("HUFF/CDIC records out of range")?
+                .iter()
+                .map(|r| r.to_vec())
+                .collect();
+            Some(HuffReader::new(&huffs)?)
+        }
+        1 | 2 => None,
+        other => bail!("Unknown compression type: {}", other),
+    };
+
+    let mut text = Vec::new();
+    for i in 1..=count {
+        let record = records.get(i).context("Missing text record")?;
+        let (_, content) = get_trailing_data(record, flags)?;
+        match huff.as_mut() {
+            Some(huff) => text.extend(huff.unpack(&content)?),
+            None if header.compression_type == 2 => text.extend(palmdoc::decompress(&content)?),
+            None => text.extend_from_slice(&content),
+        }
+    }
+    text.truncate(header.palmdoc.text_length as usize);
+    Ok(text)
+}
+
+fn decode_text(raw: &[u8], codec: &str) -> String {
+    if codec == "utf-8" {
+        String::from_utf8_lossy(raw).into_owned()
+    } else {
+        let (text, _, _) = encoding_rs::WINDOWS_1252.decode(raw);
+        text.into_owned()
+    }
+}
+
+/// Mark the `filepos` link targets in the raw text. A target inside or
+/// right before a start tag gives that tag an id, otherwise an empty anchor
+/// is inserted.
+fn add_anchors(raw: &[u8], positions: &BTreeSet<usize>) -> Vec<u8> {
+    let find = |from: usize, b: u8| raw[from..].iter().position(|&c| c == b).map(|p| p + from);
+    let mut out = Vec::with_capacity(raw.len() + positions.len() * 24);
+    let mut last = 0;
+    for &pos in positions.iter().filter(|&&p| p > 0 && p < raw.len()) {
+        if pos < last {
+            continue;
+        }
+        let (next_lt, next_gt) = (find(pos, b'<'), find(pos, b'>'));
+        let in_tag = match (next_lt, next_gt) {
+            (lt, Some(gt)) => lt.is_none_or(|lt| gt < lt || lt == pos),
+            _ => false,
+        };
+        if in_tag {
+            let gt = next_gt.unwrap();
+            let start = raw[..=pos.min(gt)].iter().rposition(|&c| c == b'<');
+            let tag = start.map(|s| &raw[s..gt]);
+            let is_start_tag = tag.is_some_and(|t| !t.starts_with(b"</") && !t.ends_with(b"/"));
+            if is_start_tag && start.unwrap_or(0) >= last {
+                out.extend_from_slice(&raw[last..gt]);
+                out.extend_from_slice(format!(" id=\"filepos{}\"", pos).as_bytes());
+                last = gt;
+                continue;
+            }
+            out.extend_from_slice(&raw[last..=gt]);
+            last = gt + 1;
+        } else {
+            out.extend_from_slice(&raw[last..pos]);
+            last = pos;
+        }
+        out.extend_from_slice(format!("<a id=\"filepos{}\"></a>", pos).as_bytes());
+    }
+    out.extend_from_slice(&raw[last..]);
+    out
+}
+
+/// Fill in the metadata of `book` from the EXTH records of `header`,
+/// returning the cover offset.
+pub(crate) fn read_exth_metadata(header: &BookHeader, book: &mut OEBBook) -> Option<usize> {
+    let records: &[(u32, Vec<u8>)] = match &header.exth {
+        Some(exth) => &exth.records,
+        None => &[],
+    };
+    let decode = |data: &[u8]| decode_text(data, &header.codec).trim().to_string();
+    let scheme = |scheme: &str| {
+        let mut attrib = HashMap::new();
+        attrib.insert("opf:scheme".to_string(), scheme.to_string());
+        attrib
+    };
+    let metadata = &mut book.metadata;
+
+    let title = records
+        .iter()
+        .find(|(code, _)| *code == 503)
+        .map(|(_, data)| decode(data))
+        .unwrap_or_else(|| header.title.clone());
+    metadata.add("title", &title);
+
+    let mut has_language = false;
+    let mut cover_offset = None;
+    for (code, data) in records {
+        let value = decode(data);
+        match code {
+            100 => metadata.add("creator", &value),
+            101 => metadata.add("publisher", &value),
+            103 => metadata.add("description", &value),
+            104 => metadata.add_with_attrib("identifier", &value, scheme("ISBN")),
+            105 => metadata.add("subject", &value),
+            106 => metadata.add("date", &value),
+            108 => metadata.add("contributor", &value),
+            109 => metadata.add("rights", &value),
+            112 => match value.strip_prefix("calibre:") {
+                Some(uuid) => {
+                    metadata.add_with_attrib("identifier", uuid, scheme("uuid"));
+                    book.uid = Some(uuid.to_string());
+                }
+                None => metadata.add("source", &value),
+            },
+            113 => metadata.add_with_attrib("identifier", &value, scheme("MOBI-ASIN")),
+            501 => metadata.add("cdetype", &value),
+            524 => {
+                metadata.add("language", &value);
+                has_language = true;
+            }
+            201 if data.len() >= 4 => {
+                let offset = u32::from_be_bytes(data[..4].try_into().unwrap());
+                cover_offset = Some(offset).filter(|o| *o != NULL_INDEX);
+            }
+            _ => {}
+        }
+    }
+
+    if !has_language {
+        let locale = header.mobi.locale;
+        let language = mobi2iana(locale & 0xff, (locale >> 10) & 0xff);
+        if language != "und" {
+            metadata.add("language", &language);
+        }
     }
+    cover_offset.map(|o| o as usize)
 }
@@ -0,0 +1,553 @@
+//! Reads the KF8 part of a MOBI or AZW3 file back into an OEB book: the
+//! original XHTML files are rebuilt from the skeleton and fragment indices,
+//! the extra flows become stylesheets and SVG images, and the resource
+//! records become images and fonts.
+
+use crate::mobi::containers::find_imgtype;
+use crate::mobi::headers::{BookHeader, NULL_INDEX};
+use crate::mobi::index::read_index;
+use crate::mobi::mobi6::{decompress_text, read_exth_metadata, MobiReader};
+use crate::mobi::ncx::{read_ncx, NCXEntry};
+use crate::mobi::resources::PLACEHOLDER_GIF;
+use crate::mobi::utils::read_font_record;
+use crate::oeb::book::OEBBook;
+use crate::oeb::container::DirContainer;
+use crate::oeb::toc::TOCNode;
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+lazy_static! {
+    static ref POS_FID_PAT: Regex =
+        Regex::new(r"kindle:pos:fid:([0-9A-V]{4}):off:([0-9A-V]{10})").unwrap();
+    static ref EMBED_PAT: Regex =
+        Regex::new(r#"kindle:embed:([0-9A-V]{4})(?:\?mime=[^"'\s)]*)?"#).unwrap();
+    static ref FLOW_PAT: Regex =
+        Regex::new(r#"kindle:flow:([0-9A-V]{4})(?:\?mime=[^"'\s)]*)?"#).unwrap();
+    static ref TAG_PAT: Regex = Regex::new(r"<[^<>]+>").unwrap();
+    static ref ID_ATTR_PAT: Regex =
+        Regex::new(r#"\s(id|ID|name|NAME|aid|AID)\s*=\s*(?:"([^"]+)"|'([^']+)')"#).unwrap();
+    static ref AID_ATTR_PAT: Regex =
+        Regex::new(r#"\s(?:aid|AID)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+}
+
+/// Records in the resource section that hold neither images nor fonts.
+const SKIPPED_RECORDS: &[&[u8]] = &[
+    b"FLIS",
+    b"FCIS",
+    b"FDST",
+    b"DATP",
+    b"SRCS",
+    b"CMET",
+    b"PAGE",
+    b"CRES",
+    b"CONT",
+    b"RESC",
+    b"kind",
+    b"BOUN",
+    b"\xe9\x8e\r\n",
+];
+
+type Sections = Vec<(Vec<u8>, (u32, u32, u32, u32, u32))>;
+
+/// A rebuilt XHTML file and the range of the text it was made from.
+struct FileInfo {
+    start: usize,
+    end: usize,
+    fragment_count: usize,
+    filename: String,
+}
+
+/// An entry of the fragment (chunk) index.
+struct Fragment {
+    insert_pos: usize,
+    length: usize,
+}
+
+/// An extracted resource record: manifest id and href.
+struct Resource {
+    id: String,
+    href: String,
+}
+
+pub struct Mobi8Reader<'a> {
+    mobi6: &'a MobiReader,
+    header: BookHeader,
+    /// Index of the KF8 record 0, non zero for joint MOBI 6 + KF8 files
+    kf8_offset: usize,
+    /// The sections starting at the KF8 record 0
+    sections: Sections,
+    text: Vec<u8>,
+    flow_table: Vec<(usize, usize)>,
+    files: Vec<FileInfo>,
+    fragments: Vec<Fragment>,
+    parts: Vec<String>,
+    /// Aids that links point to, these become `aid-X` ids
+    linked_aids: HashSet<String>,
+}
+
+impl<'a> Mobi8Reader<'a> {
+    pub fn new(mobi6: &'a MobiReader) -> Result<Self> {
+        let kf8_offset = match mobi6.kf8_type.as_deref() {
+            Some("standalone") => 0,
+            Some("joint") => mobi6.kf8_boundary.map(|b| b + 1).unwrap_or(0),
+            _ => bail!("The book has no KF8 content"),
+        };
+        // Parse the KF8 header again: the MOBI 6 reader rebases some of its
+        // record numbers on the start of the file
+        let header = BookHeader::parse(&mobi6.sections[kf8_offset].0, None)?;
+        if header.encryption_type != 0 {
+            bail!("The book is DRM encrypted");
+        }
+        let sections = mobi6.sections[kf8_offset..]
+            .iter()
+            .map(|(data, _)| (data.clone(), (0, 0, 0, 0, 0)))
+            .collect();
+
+        let mut reader = Mobi8Reader {
+            mobi6,
+            header,
+            kf8_offset,
+            sections,
+            text: Vec::new(),
+            flow_table: Vec::new(),
+            files: Vec::new(),
+            fragments: Vec::new(),
+            parts: Vec::new(),
+            linked_aids: HashSet::new(),
+        };
+        reader.text = reader.extract_text()?;
+        reader.read_fdst()?;
+        reader.read_indices()?;
+        reader.build_parts();
+        Ok(reader)
+    }
+
+    /// Decompress the text records: the markup followed by the other flows.
+    fn extract_text(&self) -> Result<Vec<u8>> {
+        let records: Vec<&[u8]> = self.sections.iter().map(|(d, _)| d.as_slice()).collect();
+        decompress_text(&self.header, &records)
+    }
+
+    /// Read the flow boundaries from the FDST record.
+    fn read_fdst(&mut self) -> Result<()> {
+        let index = self.header.mobi.fdst_index;
+        if index == NULL_INDEX || index as usize >= self.sections.len() {
+            self.flow_table = vec![(0, self.text.len())];
+            return Ok(());
+        }
+        let data = &self.sections[index as usize].0;
+        if data.len() < 12 || &data[..4] != b"FDST" {
+            bail!("KF8 does not have a valid FDST record");
+        }
+        let u32_at = |pos: usize| -> Result<usize> {
+            let bytes = data.get(pos..pos + 4).context("Truncated FDST record")?;
+            Ok(u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+        };
+        let (table_start, count) = (u32_at(4)?, u32_at(8)?);
+        let text_len = self.text.len();
+        self.flow_table = (0..count)
+            .map(|i| {
+                let start = u32_at(table_start + i * 8)?.min(text_len);
+                let end = u32_at(table_start + i * 8 + 4)?.min(text_len);
+                Ok((start, end.max(start)))
+            })
+            .collect::<Result<_>>()?;
+        if self.flow_table.is_empty() {
+            self.flow_table.push((0, text_len));
+        }
+        Ok(())
+    }
+
+    /// Read the skeleton and fragment indices.
+    fn read_indices(&mut self) -> Result<()> {
+        let codec = self.header.codec.clone();
+        let mobi = &self.header.mobi;
+        if mobi.skel_index != NULL_INDEX {
+            let (table, _) = read_index(&self.sections, mobi.skel_index as usize, &codec)?;
+            for tags in table.values() {
+                let count = tags.get(&1).and_then(|t| t.first()).copied().unwrap_or(0);
+                let pos = tags.get(&6).map(|t| t.as_slice()).unwrap_or(&[]);
+                if pos.len() < 2 {
+                    bail!("Invalid skeleton index entry");
+                }
+                self.files.push(FileInfo {
+                    start: pos[0] as usize,
+                    end: (pos[0] + pos[1]) as usize,
+                    fragment_count: count as usize,
+                    filename: String::new(),
+                });
+            }
+        }
+        self.files.sort_by_key(|f| f.start);
+        for (i, file) in self.files.iter_mut().enumerate() {
+            file.filename = format!("part{:04}.html", i);
+        }
+
+        if mobi.div_index != NULL_INDEX {
+            let (table, _) = read_index(&self.sections, mobi.div_index as usize, &codec)?;
+            for (key, tags) in &table {
+                let insert_pos = key
+                    .parse::<usize>()
+                    .with_context(|| format!("Invalid fragment index key: {}", key))?;
+                let length = tags.get(&6).and_then(|t| t.get(1)).copied().unwrap_or(0);
+                self.fragments.push(Fragment {
+                    insert_pos,
+                    length: length as usize,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuild the original files by inserting the fragments into their
+    /// skeletons.
+    fn build_parts(&mut self) {
+        let flow0 = &self.text[..self.flow_table[0].1];
+        if self.files.is_empty() {
+            self.files.push(FileInfo {
+                start: 0,
+                end: flow0.len(),
+                fragment_count: 0,
+                filename: "part0000.html".to_string(),
+            });
+        }
+
+        let mut next_fragment = 0;
+        for file in &mut self.files {
+            let start = file.start.min(flow0.len());
+            let mut skeleton = flow0[start..file.end.clamp(start, flow0.len())].to_vec();
+            let mut base = file.end;
+            for fragment in self
+                .fragments
+                .iter()
+                .skip(next_fragment)
+                .take(file.fragment_count)
+            {
+                let insert_at = fragment
+                    .insert_pos
+                    .saturating_sub(file.start)
+                    .min(skeleton.len());
+                let from = base.min(flow0.len());
+                let to = (base + fragment.length).min(flow0.len());
+                skeleton.splice(insert_at..insert_at, flow0[from..to].iter().copied());
+                base += fragment.length;
+            }
+            next_fragment += file.fragment_count;
+            file.end = base;
+            self.parts
+                .push(String::from_utf8_lossy(&skeleton).into_owned());
+        }
+    }
+
+    /// Find the file and the id of the element at the `kindle:pos:fid`
+    /// position `(fid, off)`. Returns the file index, the id (empty for the
+    /// start of the file) and the aid when the element has no id of its own.
+    fn find_pos_fid(&self, fid: usize, off: usize) -> Option<(usize, String, Option<String>)> {
+        let pos = self.fragments.get(fid)?.insert_pos + off;
+        let index = self
+            .files
+            .iter()
+            .position(|f| f.start <= pos && pos < f.end)?;
+        let part = self.parts[index].as_bytes();
+        let mut npos = (pos - self.files[index].start).min(part.len());
+
+        // Inside a tag, or right before one, the tag itself is the target
+        let next_gt = part[npos..]
+            .iter()
+            .position(|&b| b == b'>')
+            .map(|p| p + npos);
+        let next_lt = part[npos..]
+            .iter()
+            .position(|&b| b == b'<')
+            .map(|p| p + npos);
+        if let Some(gt) = next_gt {
+            if next_lt.is_none_or(|lt| lt == npos || gt < lt) {
+                npos = gt + 1;
+            }
+        }
+
+        let block = String::from_utf8_lossy(&part[..npos]);
+        let tags: Vec<_> = TAG_PAT.find_iter(&block).collect();
+        for tag in tags.iter().rev() {
+            let mut aid = None;
+            for cap in ID_ATTR_PAT.captures_iter(tag.as_str()) {
+                let value = cap.get(2).or(cap.get(3)).map_or("", |m| m.as_str());
+                if cap[1].eq_ignore_ascii_case("aid") {
+                    aid = Some(value.to_string());
+                } else {
+                    return Some((index, value.to_string(), None));
+                }
+            }
+            if let Some(aid) = aid {
+                return Some((index, format!("aid-{}", aid), Some(aid)));
+            }
+        }
+        Some((index, String::new(), None))
+    }
+
+    /// The href of the element at a `kindle:pos:fid` position, noting the
+    /// aids that need to become ids.
+    fn pos_fid_href(&mut self, fid: usize, off: usize) -> Option<String> {
+        let (index, id, aid) = self.find_pos_fid(fid, off)?;
+        if let Some(aid) = aid {
+            self.linked_aids.insert(aid);
+        }
+        let filename = &self.files[index].filename;
+        Some(if id.is_empty() {
+            filename.clone()
+        } else {
+            format!("{}#{}", filename, id)
+        })
+    }
+
+    /// Write the book to `output_dir` and return it.
+    pub fn extract(mut self, output_dir: &Path) -> Result<OEBBook> {
+        fs::create_dir_all(output_dir)?;
+        let mut book = OEBBook::new(Box::new(DirContainer::new(output_dir)));
+
+        let resources = self.extract_resources(output_dir, &mut book)?;
+        let flows = self.extract_flows(output_dir, &mut book, &resources)?;
+
+        // Resolve every link before the aids are rewritten
+        let mut links = HashMap::new();
+        let positions: Vec<String> = self
+            .parts
+            .iter()
+            .flat_map(|part| POS_FID_PAT.find_iter(part).map(|m| m.as_str().to_string()))
+            .collect();
+        for link in positions {
+            if links.contains_key(&link) {
+                continue;
+            }
+            let caps = POS_FID_PAT.captures(&link).unwrap();
+            if let Some(href) = self.pos_fid_href(base32(&caps[1]), base32(&caps[2])) {
+                links.insert(link, href);
+            }
+        }
+        self.read_guide(&mut book)?;
+        self.read_toc(&mut book)?;
+
+        let parts = std::mem::take(&mut self.parts);
+        for (i, part) in parts.iter().enumerate() {
+            let part = POS_FID_PAT.replace_all(part, |caps: &Captures| {
+                links.get(&caps[0]).cloned().unwrap_or_default()
+            });
+            let part = replace_embeds(&part, &resources, "");
+            let part = FLOW_PAT.replace_all(&part, |caps: &Captures| {
+                flows.get(&base32(&caps[1])).cloned().unwrap_or_default()
+            });
+            let part = TAG_PAT.replace_all(&part, |tag: &Captures| {
+                AID_ATTR_PAT
+                    .replace_all(&tag[0], |attr: &Captures| {
+                        let aid = attr.get(1).or(attr.get(2)).map_or("", |m| m.as_str());
+                        if self.linked_aids.contains(aid) {
+                            format!(" id=\"aid-{}\"", aid)
+                        } else {
+                            String::new()
+                        }
+                    })
+                    .into_owned()
+            });
+            let file = &self.files[i];
+            fs::write(output_dir.join(&file.filename), part.as_bytes())?;
+            let id = format!("part{:04}", i);
+            book.manifest
+                .add(&id, &file.filename, "application/xhtml+xml");
+            book.spine.add(&id, true);
+        }
+
+        let cover = read_exth_metadata(&self.header, &mut book)
+            .and_then(|offset| resources.get(&(offset + 1)));
+        if let Some(cover) = cover {
+            book.metadata.add("cover", &cover.id);
+        }
+        Ok(book)
+    }
+
+    /// Write the images and fonts, keyed by their 1 based resource number.
+    fn extract_resources(
+        &self,
+        output_dir: &Path,
+        book: &mut OEBBook,
+    ) -> Result<HashMap<usize, Resource>> {
+        let mut resources = HashMap::new();
+        // Joint files share the resources of the MOBI 6 part
+        let (first, end) = if self.kf8_offset == 0 {
+            (self.header.first_image_index, self.mobi6.sections.len())
+        } else {
+            let mobi6_header = BookHeader::parse(&self.mobi6.sections[0].0, None)?;
+            (mobi6_header.first_image_index, self.kf8_offset - 1)
+        };
+        if first == NULL_INDEX {
+            return Ok(resources);
+        }
+
+        for (i, (data, _)) in self
+            .mobi6
+            .sections
+            .iter()
+            .enumerate()
+            .take(end)
+            .skip(first as usize)
+        {
+            let number = i - first as usize + 1;
+            if data.len() < 4 || SKIPPED_RECORDS.contains(&&data[..4]) || data == PLACEHOLDER_GIF {
+                continue;
+            }
+            let (id, href, media_type, content) = if &data[..4] == b"FONT" {
+                let font = match read_font_record(data) {
+                    Ok(font) => font,
+                    Err(_) => continue,
+                };
+                let (ext, media_type) = if font.starts_with(b"OTTO") {
+                    ("otf", "application/vnd.ms-opentype")
+                } else {
+                    ("ttf", "application/x-font-truetype")
+                };
+                let id = format!("font{:05}", number);
+                let href = format!("fonts/{}.{}", id, ext);
+                (id, href, media_type.to_string(), font)
+            } else if let Some(kind) = find_imgtype(data) {
+                let ext = if kind == "jpeg" { "jpg" } else { kind };
+                let id = format!("image{:05}", number);
+                let href = format!("images/{}.{}", id, ext);
+                (id, href, format!("image/{}", kind), data.clone())
+            } else {
+                continue;
+            };
+            let path = output_dir.join(&href);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, content)?;
+            book.manifest.add(&id, &href, &media_type);
+            resources.insert(number, Resource { id, href });
+        }
+        Ok(resources)
+    }
+
+    /// Write the flows after the markup, returning their hrefs by flow number.
+    fn extract_flows(
+        &self,
+        output_dir: &Path,
+        book: &mut OEBBook,
+        resources: &HashMap<usize, Resource>,
+    ) -> Result<HashMap<usize, String>> {
+        let mut flows = HashMap::new();
+        for (i, &(start, end)) in self.flow_table.iter().enumerate().skip(1) {
+            let data = String::from_utf8_lossy(&self.text[start..end]);
+            let (id, href, media_type) = if data.contains("<svg") {
+                let id = format!("svg{:04}", i);
+                let href = format!("images/{}.svg", id);
+                (id, href, "image/svg+xml")
+            } else {
+                let id = format!("style{:04}", i);
+                let href = format!("styles/{}.css", id);
+                (id, href, "text/css")
+            };
+            // Flows live in a sub directory
+            let content = replace_embeds(&data, resources, "../");
+            let path = output_dir.join(&href);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, content.as_bytes())?;
+            book.manifest.add(&id, &href, media_type);
+            flows.insert(i, href);
+        }
+        Ok(flows)
+    }
+
+    fn read_guide(&mut self, book: &mut OEBBook) -> Result<()> {
+        let index = self.header.mobi.guide_index;
+        if index == NULL_INDEX {
+            return Ok(());
+        }
+        let (table, cncx) = read_index(&self.sections, index as usize, &self.header.codec)?;
+        for (type_, tags) in &table {
+            let title = tags
+                .get(&1)
+                .and_then(|t| t.first())
+                .and_then(|off| cncx.get(*off as usize))
+                .cloned();
+            let href = match (tags.get(&6), tags.get(&3)) {
+                (Some(pos), _) if pos.len() >= 2 => {
+                    self.pos_fid_href(pos[0] as usize, pos[1] as usize)
+                }
+                // Older files point at the start of a fragment
+                (_, Some(fragment)) if !fragment.is_empty() => {
+                    self.pos_fid_href(fragment[0] as usize, 0)
+                }
+                _ => None,
+            };
+            if let Some(href) = href {
+                book.guide.add(type_, title, &href);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_toc(&mut self, book: &mut OEBBook) -> Result<()> {
+        let entries = read_ncx(
+            &self.sections,
+            self.header.mobi.ncx_index,
+            &self.header.codec,
+        )?;
+        let hrefs: Vec<Option<String>> = entries
+            .iter()
+            .map(|e| {
+                e.pos_fid
+                    .and_then(|(fid, off)| self.pos_fid_href(fid as usize, off as usize))
+            })
+            .collect();
+        add_toc_entries(&mut book.toc.root, &entries, &hrefs);
+        Ok(())
+    }
+}
+
+fn base32(digits: &str) -> usize {
+    usize::from_str_radix(digits, 32).unwrap_or(0)
+}
+
+/// Replace `kindle:embed` references with the hrefs of the resources,
+/// relative to a file `prefix` levels below the root.
+fn replace_embeds(text: &str, resources: &HashMap<usize, Resource>, prefix: &str) -> String {
+    EMBED_PAT
+        .replace_all(text, |caps: &Captures| {
+            match resources.get(&base32(&caps[1])) {
+                Some(resource) => format!("{}{}", prefix, resource.href),
+                None => String::new(),
+            }
+        })
+        .into_owned()
+}
+
+/// Add the NCX index entries under `root`, `hrefs` being their targets.
+pub(crate) fn add_toc_entries(root: &mut TOCNode, entries: &[NCXEntry], hrefs: &[Option<String>]) {
+    add_toc_children(root, entries, hrefs, -1, 0);
+}
+
+fn add_toc_children(
+    parent: &mut TOCNode,
+    entries: &[NCXEntry],
+    hrefs: &[Option<String>],
+    parent_num: i64,
+    depth: usize,
+) {
+    // Guard against loops in corrupt indices
+    if depth > 32 {
+        return;
+    }
+    for (entry, href) in entries.iter().zip(hrefs) {
+        if entry.parent != parent_num {
+            continue;
+        }
+        let mut node = TOCNode::new(Some(entry.text.clone()), href.clone());
+        add_toc_children(&mut node, entries, hrefs, entry.num as i64, depth + 1);
+        parent.add(node);
+    }
+}
@@ -5,9 +5,13 @@ pub mod index;
 pub mod langcodes;
 pub mod markup;
 pub mod mobi6;
+pub mod mobi8;
 pub mod mobiml;
 pub mod ncx;
 pub mod reader;
+pub mod resources;
+pub mod serializer;
 pub mod tweak;
 pub mod utils;
 pub mod writer;
+pub mod writer8;
@@ -0,0 +1,165 @@
+//! Image and font records shared by the MOBI 6 and KF8 writers.
+
+use crate::mobi::containers::find_imgtype;
+use crate::mobi::utils::{mobify_image, rescale_image, write_font_record};
+use crate::oeb::book::OEBBook;
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+
+/// Largest image record Kindles accept
+pub const MAX_IMAGE_SIZE: usize = calibre_utils::img::KINDLE_MAX_IMAGE_SIZE;
+/// Largest cover thumbnail record
+pub const MAX_THUMB_SIZE: usize = 16 * 1024;
+pub const MAX_THUMB_DIMEN: (u32, u32) = (180, 240);
+
+/// Written in place of images that no text refers to, so that resource
+/// numbers stay stable.
+pub const PLACEHOLDER_GIF: &[u8] = b"GIF89a\x01\x00\x01\x00\xf0\x00\x00\x00\x00\x00\xff\xff\xff!\xf9\x04\x01\x00\x00\x00\x00!\xfe calibre-placeholder-gif-for-azw3\x00,\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02D\x01\x00;";
+
+pub struct Resources {
+    pub records: Vec<Vec<u8>>,
+    /// Manifest href -> 1 based resource number, as used by `recindex`
+    /// and `kindle:embed`
+    pub item_map: HashMap<String, usize>,
+    /// Manifest href -> media type of the record actually written
+    pub mime_map: HashMap<String, String>,
+    /// Zero based indices of the image records
+    pub image_indices: HashSet<usize>,
+    pub cover_offset: Option<usize>,
+    pub thumbnail_offset: Option<usize>,
+}
+
+impl Resources {
+    /// Collect the images of `book`, and its fonts when `add_fonts` is set.
+    /// With `process_images` PNG images are converted to GIF for the older
+    /// MOBI 6 renderers and oversized images are recompressed.
+    pub fn new(book: &OEBBook, add_fonts: bool, process_images: bool) -> Self {
+        let mut resources = Resources {
+            records: Vec::new(),
+            item_map: HashMap::new(),
+            mime_map: HashMap::new(),
+            image_indices: HashSet::new(),
+            cover_offset: None,
+            thumbnail_offset: None,
+        };
+        resources.add_resources(book, add_fonts, process_images);
+        resources
+    }
+
+    fn add_resources(&mut self, book: &OEBBook, add_fonts: bool, process_images: bool) {
+        let cover_href = cover_href(book);
+
+        let mut items: Vec<_> = book.manifest.items.values().collect();
+        items.sort_by(|a, b| a.href.cmp(&b.href));
+
+        for item in items {
+            let is_font = is_font(&item.media_type, &item.href);
+            if !item.media_type.starts_with("image/") && !is_font {
+                continue;
+            }
+            if item.media_type == "image/svg+xml" || (is_font && !add_fonts) {
+                continue;
+            }
+            let data = match book.container.read(&item.href) {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+
+            if is_font {
+                if let Ok(record) = write_font_record(&data, true, true) {
+                    self.records.push(record);
+                    self.item_map.insert(item.href.clone(), self.records.len());
+                }
+                continue;
+            }
+
+            let data = match prepare_image(&data, process_images) {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+            let mime = match find_imgtype(&data) {
+                Some(fmt) => format!("image/{}", fmt),
+                None => item.media_type.clone(),
+            };
+            self.image_indices.insert(self.records.len());
+            self.records.push(data.clone());
+            self.item_map.insert(item.href.clone(), self.records.len());
+            self.mime_map.insert(item.href.clone(), mime);
+
+            if Some(&item.href) == cover_href.as_ref() {
+                self.cover_offset = Some(self.records.len() - 1);
+                if let Ok(thumb) = rescale_image(&data, MAX_THUMB_SIZE, Some(MAX_THUMB_DIMEN)) {
+                    self.image_indices.insert(self.records.len());
+                    self.records.push(thumb);
+                    self.thumbnail_offset = Some(self.records.len() - 1);
+                }
+            }
+        }
+    }
+
+    /// The resource records, with images not in `used` (1 based resource
+    /// numbers) replaced by a placeholder. The cover and its thumbnail are
+    /// always kept.
+    pub fn serialize(&self, used: &HashSet<usize>) -> Vec<Vec<u8>> {
+        self.records
+            .iter()
+            .enumerate()
+            .map(|(i, record)| {
+                let keep = !self.image_indices.contains(&i)
+                    || used.contains(&(i + 1))
+                    || Some(i) == self.cover_offset
+                    || Some(i) == self.thumbnail_offset;
+                if keep {
+                    record.clone()
+                } else {
+                    PLACEHOLDER_GIF.to_vec()
+                }
+            })
+            .collect()
+    }
+}
+
+fn is_font(media_type: &str, href: &str) -> bool {
+    let lower = href.to_lowercase();
+    media_type.contains("font")
+        || media_type == "application/vnd.ms-opentype"
+        || lower.ends_with(".ttf")
+        || lower.ends_with(".otf")
+}
+
+/// Convert images to a format Kindles understand and keep them under the
+/// record size limit.
+fn prepare_image(data: &[u8], process_images: bool) -> Result<Vec<u8>> {
+    let supported = matches!(find_imgtype(data), Some("jpeg" | "gif" | "png" | "bmp"));
+    let mut data = if supported {
+        data.to_vec()
+    } else {
+        rescale_image(data, MAX_IMAGE_SIZE, None)?
+    };
+    if process_images {
+        data = mobify_image(&data)?;
+    }
+    if data.len() > MAX_IMAGE_SIZE {
+        data = rescale_image(&data, MAX_IMAGE_SIZE, None)?;
+    }
+    Ok(data)
+}
+
+/// The href of the cover image, from the `cover` metadata or the guide.
+fn cover_href(book: &OEBBook) -> Option<String> {
+    if let Some(item) = book
+        .metadata
+        .first("cover")
+        .and_then(|id| book.manifest.get_by_id(id))
+    {
+        if item.media_type.starts_with("image/") {
+            return Some(item.href.clone());
+        }
+    }
+    let reference = book.guide.references.get("cover")?;
+    let href = reference.href.split('#').next().unwrap_or("");
+    book.manifest
+        .get_by_href(href)
+        .filter(|item| item.media_type.starts_with("image/"))
+        .map(|item| item.href.clone())
+}
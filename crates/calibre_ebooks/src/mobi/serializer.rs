@@ -0,0 +1,219 @@
+//! Serializes an OEB book into the single markup stream of MOBI 6 files.
+
+use crate::mobi::resources::Resources;
+use crate::mobi::utils::is_guide_ref_start;
+use crate::mobi::writer8::skeleton::{escape, parse_xhtml, Element, Node};
+use crate::oeb::book::OEBBook;
+use crate::oeb::parse_utils::abshref;
+use std::collections::{HashMap, HashSet};
+
+const DROPPED_TAGS: &[&str] = &[
+    "script", "style", "link", "meta", "title", "head", "noscript",
+];
+
+pub struct Serializer<'a> {
+    book: &'a OEBBook,
+    resources: &'a Resources,
+    pub text: Vec<u8>,
+    /// `href#id` (or `href` for the start of a file) -> text offset
+    id_offsets: HashMap<String, usize>,
+    /// Positions of `filepos` placeholders and the targets they point to
+    href_offsets: Vec<(usize, String)>,
+    spine_hrefs: HashSet<String>,
+    /// 1 based numbers of the images the text refers to
+    pub used_resources: HashSet<usize>,
+    /// Text offset of the start reading location
+    pub start_offset: Option<usize>,
+}
+
+impl<'a> Serializer<'a> {
+    pub fn new(book: &'a OEBBook, resources: &'a Resources) -> Self {
+        let mut serializer = Serializer {
+            book,
+            resources,
+            text: Vec::new(),
+            id_offsets: HashMap::new(),
+            href_offsets: Vec::new(),
+            spine_hrefs: HashSet::new(),
+            used_resources: HashSet::new(),
+            start_offset: None,
+        };
+        serializer.serialize();
+        serializer
+    }
+
+    fn serialize(&mut self) {
+        let mut docs = Vec::new();
+        for itemref in &self.book.spine.items {
+            let item = match self.book.manifest.items.get(&itemref.idref) {
+                Some(item) if !item.media_type.starts_with("image/") => item,
+                _ => continue,
+            };
+            if let Ok(data) = self.book.container.read(&item.href) {
+                self.spine_hrefs.insert(item.href.clone());
+                docs.push((
+                    item.href.clone(),
+                    parse_xhtml(&String::from_utf8_lossy(&data)),
+                ));
+            }
+        }
+
+        self.text.extend_from_slice(b"<html>");
+        self.serialize_head();
+        self.text.extend_from_slice(b"<body>");
+        for (i, (href, root)) in docs.iter().enumerate() {
+            if i > 0 {
+                self.text.extend_from_slice(b"<mbp:pagebreak/>");
+            }
+            self.id_offsets.insert(href.clone(), self.text.len());
+            if let Some(body) = root.find("body") {
+                if let Some(id) = body.get("id") {
+                    self.id_offsets
+                        .insert(format!("{}#{}", href, id), self.text.len());
+                }
+                for child in &body.children {
+                    self.serialize_node(child, href);
+                }
+            }
+        }
+        self.text.extend_from_slice(b"</body></html>");
+        self.fixup_links();
+    }
+
+    fn serialize_head(&mut self) {
+        self.text.extend_from_slice(b"<head><guide>");
+        let mut refs: Vec<_> = self.book.guide.references.values().collect();
+        refs.sort_by(|a, b| a.type_.cmp(&b.type_));
+        for r in refs {
+            let path = abshref("", &r.href);
+            if !self.spine_hrefs.contains(&path) {
+                continue;
+            }
+            let title = r.title.clone().unwrap_or_else(|| r.type_.clone());
+            let head = format!(
+                "<reference type=\"{}\" title=\"{}\" ",
+                escape(&r.type_, true),
+                escape(&title, true)
+            );
+            self.text.extend_from_slice(head.as_bytes());
+            self.serialize_href(&r.href, "");
+            self.text.extend_from_slice(b" />");
+        }
+        self.text.extend_from_slice(b"</guide></head>");
+    }
+
+    /// Write a `filepos` placeholder for `href`, resolved in `fixup_links`.
+    fn serialize_href(&mut self, href: &str, base: &str) -> bool {
+        let (path, frag) = match href.split_once('#') {
+            Some((p, f)) => (p, f),
+            None => (href, ""),
+        };
+        let path = if path.is_empty() {
+            base.to_string()
+        } else {
+            abshref(base, path)
+        };
+        if !self.spine_hrefs.contains(&path) {
+            return false;
+        }
+        let target = if frag.is_empty() {
+            path
+        } else {
+            format!("{}#{}", path, frag)
+        };
+        self.text.extend_from_slice(b"filepos=");
+        self.href_offsets.push((self.text.len(), target));
+        self.text.extend_from_slice(b"0000000000");
+        true
+    }
+
+    fn serialize_node(&mut self, node: &Node, base: &str) {
+        match node {
+            Node::Element(el) => self.serialize_elem(el, base),
+            Node::Text(text) => self.text.extend_from_slice(escape(text, false).as_bytes()),
+            Node::Chunk(_) => {}
+        }
+    }
+
+    fn serialize_elem(&mut self, el: &Element, base: &str) {
+        if DROPPED_TAGS.contains(&el.name.as_str()) {
+            return;
+        }
+        if let Some(id) = el.get("id") {
+            self.id_offsets
+                .insert(format!("{}#{}", base, id), self.text.len());
+        }
+        if el.name == "img" {
+            let idx = el
+                .get("src")
+                .map(|src| abshref(base, src))
+                .and_then(|src| self.resources.item_map.get(&src).copied());
+            if let Some(idx) = idx {
+                self.used_resources.insert(idx);
+                let tag = format!("<img recindex=\"{:05}\"", idx);
+                self.text.extend_from_slice(tag.as_bytes());
+                for attr in ["alt", "width", "height"] {
+                    if let Some(v) = el.get(attr) {
+                        let a = format!(" {}=\"{}\"", attr, escape(v, true));
+                        self.text.extend_from_slice(a.as_bytes());
+                    }
+                }
+                self.text.extend_from_slice(b"/>");
+            }
+            return;
+        }
+
+        self.text.push(b'<');
+        self.text.extend_from_slice(el.name.as_bytes());
+        for (k, v) in &el.attrs {
+            if k == "id" || k.starts_with("xml") || k == "class" || k == "style" {
+                continue;
+            }
+            self.text.push(b' ');
+            if k == "href" && el.name == "a" && self.serialize_href(v, base) {
+                continue;
+            }
+            let attr = format!("{}=\"{}\"", k, escape(v, true));
+            self.text.extend_from_slice(attr.as_bytes());
+        }
+        if el.children.is_empty() && matches!(el.name.as_str(), "br" | "hr") {
+            self.text.extend_from_slice(b"/>");
+            return;
+        }
+        self.text.push(b'>');
+        for child in &el.children {
+            self.serialize_node(child, base);
+        }
+        self.text.extend_from_slice(b"</");
+        self.text.extend_from_slice(el.name.as_bytes());
+        self.text.push(b'>');
+    }
+
+    fn fixup_links(&mut self) {
+        for (pos, target) in &self.href_offsets {
+            let offset = self
+                .id_offsets
+                .get(target)
+                .or_else(|| self.id_offsets.get(target.split('#').next().unwrap_or("")))
+                .copied()
+                .unwrap_or(0);
+            let digits = format!("{:010}", offset);
+            self.text[*pos..*pos + 10].copy_from_slice(digits.as_bytes());
+        }
+
+        for r in self.book.guide.references.values() {
+            if is_guide_ref_start(r.title.as_deref(), Some(&r.type_)) {
+                let path = abshref("", &r.href);
+                let key = match r.href.split_once('#') {
+                    Some((_, frag)) => format!("{}#{}", path, frag),
+                    None => path.clone(),
+                };
+                self.start_offset = self
+                    .id_offsets
+                    .get(&key)
+                    .or_else(|| self.id_offsets.get(&path))
+                    .copied();
+            }
+        }
+    }
+}
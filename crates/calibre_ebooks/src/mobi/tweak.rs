@@ -1,12 +1,94 @@
-use anyhow::{bail, Result};
+//! Unpack MOBI and AZW3 books into an editable OPF + XHTML directory and
+//! pack them back into the same kind of file.
+
+use crate::mobi::mobi6::MobiReader;
+use crate::mobi::mobi8::Mobi8Reader;
+use crate::mobi::writer::{MobiFileType, MobiWriter};
+use crate::oeb::book::OEBBook;
+use crate::oeb::container::DirContainer;
+use crate::oeb::reader::OEBReader;
+use crate::oeb::writer::OEBWriter;
+use anyhow::{bail, Context, Result};
+use std::fs::{self, File};
+use std::io::BufWriter;
 use std::path::Path;
 
+/// OPF meta recording which texts the original file had, so that `rebuild`
+/// writes the same kind of file.
+pub const FLAVOUR_META: &str = "calibre:mobi-flavour";
+
+fn flavour_name(file_type: MobiFileType) -> &'static str {
+    match file_type {
+        MobiFileType::Old => "mobi6",
+        MobiFileType::Both => "joint",
+        MobiFileType::New => "kf8",
+    }
+}
+
+fn flavour_from_name(name: &str) -> Option<MobiFileType> {
+    match name.trim() {
+        "mobi6" => Some(MobiFileType::Old),
+        "joint" => Some(MobiFileType::Both),
+        "kf8" => Some(MobiFileType::New),
+        _ => None,
+    }
+}
+
+/// Unpack the book at `path` into `dest`, returning the path of the OPF.
+/// KF8 books keep their original files, MOBI 6 books are split at page
+/// breaks.
 pub fn explode(path: &Path, dest: &Path) -> Result<String> {
-    // Stub implementation until MobiReader/Mobi8Reader are available.
-    bail!("tweak::explode not implemented yet")
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let reader = MobiReader::new(file)?;
+    let (mut book, file_type) = match reader.kf8_type.as_deref() {
+        Some("joint") => (
+            Mobi8Reader::new(&reader)?.extract(dest)?,
+            MobiFileType::Both,
+        ),
+        Some(_) => (Mobi8Reader::new(&reader)?.extract(dest)?, MobiFileType::New),
+        None => (reader.extract_content(dest)?, MobiFileType::Old),
+    };
+    book.metadata.add(FLAVOUR_META, flavour_name(file_type));
+
+    OEBWriter::new().write_book(&mut book, dest)?;
+    Ok(dest.join("content.opf").to_string_lossy().into_owned())
 }
 
+/// Pack the directory written by `explode` (possibly edited) into
+/// `dest_path`, as MOBI 6, joint MOBI 6 + KF8 or KF8 like the original.
 pub fn rebuild(src_dir: &Path, dest_path: &Path) -> Result<()> {
-    // Stub implementation until Plumber/Input/Output plugins are available.
-    bail!("tweak::rebuild not implemented yet")
+    let opf = find_opf(src_dir)?;
+    let mut book = OEBBook::new(Box::new(DirContainer::new(src_dir)));
+    OEBReader::new().read_opf(&mut book, &opf)?;
+
+    let file_type = match book.metadata.first(FLAVOUR_META) {
+        Some(name) => {
+            flavour_from_name(name).with_context(|| format!("Unknown MOBI flavour: {}", name))?
+        }
+        // Not exploded by us, KF8 keeps the most of the markup
+        None => MobiFileType::New,
+    };
+    book.metadata.items.retain(|item| item.term != FLAVOUR_META);
+
+    let mut out = BufWriter::new(File::create(dest_path)?);
+    MobiWriter::with_file_type(file_type).write(&book, &mut out)
+}
+
+/// The OPF in `src_dir`, relative to it: `content.opf` or the only OPF.
+fn find_opf(src_dir: &Path) -> Result<String> {
+    if src_dir.join("content.opf").exists() {
+        return Ok("content.opf".to_string());
+    }
+    let mut opfs = Vec::new();
+    for entry in fs::read_dir(src_dir)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if name.to_lowercase().ends_with(".opf") {
+            opfs.push(name);
+        }
+    }
+    match opfs.len() {
+        1 => Ok(opfs.remove(0)),
+        0 => bail!("No OPF file found in {}", src_dir.display()),
+        _ => bail!("More than one OPF file found in {}", src_dir.display()),
+    }
 }
@@ -332,17 +332,134 @@ pub fn is_guide_ref_start(title: Option<&str>, type_: Option<&str>) -> bool {
     false
 }
 
-// Stub implementations
+/// Write `num` in `base` using the digits 0-9A-Z, left padded with zeros to
+/// at least `min_num_digits`. KF8 uses base 32 for all of its references.
+pub fn to_base(mut num: u64, base: u64, min_num_digits: usize) -> String {
+    const DIGITS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    let mut ans = Vec::new();
+    while num > 0 {
+        ans.push(DIGITS[(num % base) as usize]);
+        num /= base;
+    }
+    while ans.len() < min_num_digits.max(1) {
+        ans.push(b'0');
+    }
+    ans.reverse();
+    String::from_utf8(ans).unwrap_or_default()
+}
+
+/// Write the ttf/otf font in `data` into a KF8 font record.
+///
+/// The record is `FONT`, the uncompressed size, flags (0b1 zlib, 0b10
+/// obfuscated), the data offset, the key length and the key offset, followed
+/// by the XOR key and the data. Obfuscation XORs the first 1040 bytes of the
+/// (compressed) data with the key.
+pub fn write_font_record(data: &[u8], obfuscate: bool, compress: bool) -> Result<Vec<u8>> {
+    const KEY_LEN: usize = 20;
+    let mut flags = 0u32;
+    let usize_ = data.len() as u32;
+    let mut data = data.to_vec();
+    if compress {
+        flags |= 0b1;
+        let mut enc = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+        enc.write_all(&data)?;
+        data = enc.finish()?;
+    }
+    let mut xor_key = Vec::new();
+    if obfuscate && data.len() >= 1040 {
+        flags |= 0b10;
+        // The key only has to be unpredictable for readers that do not know
+        // the format, so derive it from the data instead of a RNG
+        let mut seed: u64 = 0xcbf29ce484222325;
+        for b in data.iter().take(4096) {
+            seed = (seed ^ *b as u64).wrapping_mul(0x100000001b3);
+        }
+        for _ in 0..KEY_LEN {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            xor_key.push((seed >> 24) as u8);
+        }
+        for i in 0..1040 {
+            data[i] ^= xor_key[i % KEY_LEN];
+        }
+    }
+
+    let key_start = 4 + 5 * 4;
+    let data_start = key_start + xor_key.len();
+    let mut ans = Vec::with_capacity(data_start + data.len());
+    ans.extend_from_slice(b"FONT");
+    for v in [
+        usize_,
+        flags,
+        data_start as u32,
+        xor_key.len() as u32,
+        key_start as u32,
+    ] {
+        ans.extend_from_slice(&v.to_be_bytes());
+    }
+    ans.extend_from_slice(&xor_key);
+    ans.extend_from_slice(&data);
+    Ok(ans)
+}
+
+/// Read the font out of a KF8 font record written by `write_font_record`,
+/// undoing the obfuscation and the compression.
+pub fn read_font_record(raw: &[u8]) -> Result<Vec<u8>> {
+    if raw.len() < 24 || &raw[..4] != b"FONT" {
+        bail!("Not a font record");
+    }
+    let field = |i: usize| u32::from_be_bytes(raw[4 + i * 4..8 + i * 4].try_into().unwrap());
+    let (usize_, flags) = (field(0) as usize, field(1));
+    let (data_start, key_len, key_start) =
+        (field(2) as usize, field(3) as usize, field(4) as usize);
+    if data_start > raw.len() || key_start + key_len > raw.len() {
+        bail!("Font record offsets out of range");
+    }
+    let mut data = raw[data_start..].to_vec();
+
+    if flags & 0b10 != 0 && key_len > 0 {
+        let key = &raw[key_start..key_start + key_len];
+        // The first 1040 bytes (or 1024 with old kindlegen keys) are XORed
+        let extent = if key_len == 16 { 1024 } else { 1040 }.min(data.len());
+        for (i, b) in data[..extent].iter_mut().enumerate() {
+            *b ^= key[i % key_len];
+        }
+    }
+    if flags & 0b1 != 0 {
+        // The size is untrusted: cap the allocation, and read one byte past
+        // it so that a mismatch is still caught below
+        let dec = flate2::read::ZlibDecoder::new(&data[..]);
+        let mut out = Vec::with_capacity(usize_.min(data.len().saturating_mul(16)));
+        dec.take(usize_ as u64 + 1).read_to_end(&mut out)?;
+        data = out;
+    }
+    if data.len() != usize_ {
+        bail!(
+            "Font record size mismatch: expected {} bytes, got {}",
+            usize_,
+            data.len()
+        );
+    }
+    Ok(data)
+}
+
+/// Convert PNG images to GIF, as some Kindles cannot display every PNG.
+/// Other formats are returned unchanged.
 pub fn mobify_image(data: &[u8]) -> Result<Vec<u8>> {
-    Ok(data.to_vec())
+    if !matches!(image::guess_format(data), Ok(image::ImageFormat::Png)) {
+        return Ok(data.to_vec());
+    }
+    let img = image::load_from_memory(data)?;
+    let mut out = Cursor::new(Vec::new());
+    img.write_to(&mut out, image::ImageOutputFormat::Gif)?;
+    Ok(out.into_inner())
 }
 
-pub fn rescale_image(
-    _data: &[u8],
-    _max_size: usize,
-    _dimen: Option<(u32, u32)>,
-) -> Result<Vec<u8>> {
-    Ok(Vec::new())
+/// Re-encode an image as a JPEG of at most `max_size` bytes, shrinking it
+/// to fit in `dimen` first when given.
+pub fn rescale_image(data: &[u8], max_size: usize, dimen: Option<(u32, u32)>) -> Result<Vec<u8>> {
+    calibre_utils::img::shrink_to_size(data, max_size, dimen)
 }
 
 #[cfg(test)]
@@ -413,6 +530,24 @@ mod tests {
         assert_eq!(consumed, 3);
     }
 
+    #[test]
+    fn test_to_base() {
+        assert_eq!(to_base(0, 32, 4), "0000");
+        assert_eq!(to_base(33, 32, 4), "0011");
+        assert_eq!(to_base(1023, 32, 0), "VV");
+    }
+
+    #[test]
+    fn test_font_record_header() {
+        let font = vec![7u8; 3000];
+        let record = write_font_record(&font, true, false).unwrap();
+        assert_eq!(&record[..4], b"FONT");
+        assert_eq!(u32::from_be_bytes(record[4..8].try_into().unwrap()), 3000);
+        assert_eq!(u32::from_be_bytes(record[8..12].try_into().unwrap()), 0b10);
+        assert_eq!(record.len(), 24 + 20 + 3000);
+        assert_eq!(&record[24 + 20 + 1040..], &font[1040..]);
+    }
+
     #[test]
     fn test_align_block() {
         let data = vec![1, 2, 3];
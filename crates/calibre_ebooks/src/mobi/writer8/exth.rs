@@ -0,0 +1,224 @@
+//! EXTH metadata block shared by MOBI 6 and KF8 record 0.
+
+use crate::mobi::utils::to_base;
+use crate::oeb::metadata::Metadata;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref COLLAPSE_RE: Regex = Regex::new(r"[ \t\r\n\x0b]+").unwrap();
+}
+
+/// Map from OPF metadata terms to EXTH record types.
+pub fn exth_code(term: &str) -> Option<u32> {
+    Some(match term {
+        "creator" => 100,
+        "publisher" => 101,
+        "description" => 103,
+        "identifier" => 104,
+        "subject" => 105,
+        "date" => 106,
+        "review" => 107,
+        "contributor" => 108,
+        "rights" => 109,
+        "type" => 111,
+        "source" => 112,
+        "title" => 503,
+        "language" => 524,
+        _ => return None,
+    })
+}
+
+pub const EXTH_START_READING: u32 = 116;
+pub const EXTH_KF8_HEADER_INDEX: u32 = 121;
+pub const EXTH_NUM_OF_RESOURCES: u32 = 125;
+pub const EXTH_KF8_THUMBNAIL_URI: u32 = 129;
+pub const EXTH_KF8_UNKNOWN_COUNT: u32 = 131;
+pub const EXTH_COVER_OFFSET: u32 = 201;
+pub const EXTH_THUMB_OFFSET: u32 = 202;
+pub const EXTH_HAS_FAKE_COVER: u32 = 203;
+
+/// Values that come from the writer rather than from the book metadata.
+#[derive(Debug, Clone, Default)]
+pub struct ExthOptions {
+    pub prefer_author_sort: bool,
+    /// When false the book is tagged as a personal document (`EBOK` and an
+    /// ASIN), which makes Kindles sync reading position through Amazon.
+    pub share_not_sync: bool,
+    pub cover_offset: Option<u32>,
+    pub thumbnail_offset: Option<u32>,
+    /// Text offsets of the start reading location, one per text in the file.
+    pub start_offsets: Vec<u32>,
+    pub num_of_resources: Option<u32>,
+    pub kf8_unknown_count: Option<u32>,
+    /// Record number of the KF8 record 0 in joint files.
+    pub kf8_header_index: Option<u32>,
+    /// Identify as kindlegen 2 instead of kindlegen 1.2. Needed for KF8.
+    pub be_kindlegen2: bool,
+}
+
+/// The book UUID, used for the ASIN and source records.
+pub fn book_uuid(metadata: &Metadata, fallback: Option<&str>) -> String {
+    for item in metadata.get("identifier") {
+        let scheme = item
+            .attrib
+            .iter()
+            .find(|(k, _)| k.ends_with("scheme"))
+            .map(|(_, v)| v.to_lowercase());
+        if scheme.as_deref() == Some("uuid") || item.value.starts_with("urn:uuid:") {
+            return item.value.rsplit(':').next().unwrap_or("").to_string();
+        }
+    }
+    if let Some(uid) = fallback.filter(|u| !u.trim().is_empty()) {
+        return uid.rsplit(':').next().unwrap_or(uid).to_string();
+    }
+    // Stable across runs so that rewriting a book does not create a new one
+    // on the device
+    let title = metadata.first("title").unwrap_or("Unknown");
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in title.bytes() {
+        h = (h ^ b as u64).wrapping_mul(0x100000001b3);
+    }
+    let lo = h.wrapping_mul(0x9e3779b97f4a7c15);
+    format!(
+        "{:08x}-{:04x}-4{:03x}-8{:03x}-{:012x}",
+        h >> 32,
+        (h >> 16) & 0xffff,
+        h & 0xfff,
+        lo >> 52,
+        lo & 0xffff_ffff_ffff
+    )
+}
+
+/// The ASIN of the book, from an identifier with a `mobi-asin` or `asin`
+/// scheme.
+fn book_asin(metadata: &Metadata) -> Option<String> {
+    metadata
+        .get("identifier")
+        .into_iter()
+        .find(|item| {
+            item.attrib.iter().any(|(k, v)| {
+                k.ends_with("scheme")
+                    && (v.eq_ignore_ascii_case("mobi-asin") || v.eq_ignore_ascii_case("asin"))
+            })
+        })
+        .map(|item| item.value.trim().to_string())
+        .filter(|asin| !asin.is_empty())
+}
+
+/// Build the EXTH block, padded to a multiple of four bytes.
+pub fn build_exth(metadata: &Metadata, uuid: &str, opts: &ExthOptions) -> Vec<u8> {
+    let mut records: Vec<(u32, Vec<u8>)> = Vec::new();
+
+    for item in &metadata.items {
+        let code = match exth_code(&item.term) {
+            Some(code) => code,
+            None => continue,
+        };
+        let mut data = item.value.trim().to_string();
+        if data.is_empty() {
+            continue;
+        }
+        if item.term != "description" {
+            data = COLLAPSE_RE.replace_all(&data, " ").into_owned();
+        }
+        match item.term.as_str() {
+            "creator" if opts.prefer_author_sort => {
+                let sort = item
+                    .attrib
+                    .iter()
+                    .find(|(k, _)| k.ends_with("file-as"))
+                    .map(|(_, v)| v.clone());
+                data = sort.unwrap_or_else(|| {
+                    crate::metadata::authors::authors_to_sort_string(&[data.clone()])
+                });
+            }
+            "identifier" => {
+                let is_isbn = item
+                    .attrib
+                    .iter()
+                    .any(|(k, v)| k.ends_with("scheme") && v.eq_ignore_ascii_case("isbn"));
+                if data.to_lowercase().starts_with("urn:isbn:") {
+                    data = data[9..].to_string();
+                } else if !is_isbn {
+                    continue;
+                }
+            }
+            "title" if records.iter().any(|(c, _)| *c == 503) => continue,
+            _ => {}
+        }
+        records.push((code, data.into_bytes()));
+    }
+
+    if !opts.share_not_sync {
+        // Keep the ASIN and document type of books that came from Amazon
+        let asin = book_asin(metadata).unwrap_or_else(|| uuid.to_string());
+        let cdetype = metadata
+            .first("cdetype")
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .unwrap_or("EBOK");
+        records.push((113, asin.into_bytes()));
+        records.push((501, cdetype.as_bytes().to_vec()));
+    }
+    records.push((112, format!("calibre:{}", uuid).into_bytes()));
+
+    if !records.iter().any(|(c, _)| *c == 106) {
+        let date = metadata
+            .first("timestamp")
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| chrono::Utc::now().to_rfc3339());
+        records.push((106, date.into_bytes()));
+    }
+
+    let creator: [(u32, u32); 4] = if opts.be_kindlegen2 {
+        [(204, 202), (205, 2), (206, 9), (207, 0)]
+    } else {
+        [(204, 201), (205, 1), (206, 2), (207, 33307)]
+    };
+    for (code, val) in creator {
+        records.push((code, val.to_be_bytes().to_vec()));
+    }
+    if opts.be_kindlegen2 {
+        records.push((535, b"0730-890adc2".to_vec()));
+    }
+
+    if let Some(offset) = opts.cover_offset {
+        records.push((EXTH_COVER_OFFSET, offset.to_be_bytes().to_vec()));
+        records.push((EXTH_HAS_FAKE_COVER, 0u32.to_be_bytes().to_vec()));
+    }
+    if let Some(offset) = opts.thumbnail_offset {
+        records.push((EXTH_THUMB_OFFSET, offset.to_be_bytes().to_vec()));
+        let uri = format!("kindle:embed:{}", to_base(offset as u64, 32, 4));
+        records.push((EXTH_KF8_THUMBNAIL_URI, uri.into_bytes()));
+    }
+    for offset in &opts.start_offsets {
+        records.push((EXTH_START_READING, offset.to_be_bytes().to_vec()));
+    }
+    if let Some(index) = opts.kf8_header_index {
+        records.push((EXTH_KF8_HEADER_INDEX, index.to_be_bytes().to_vec()));
+    }
+    if let Some(count) = opts.num_of_resources {
+        records.push((EXTH_NUM_OF_RESOURCES, count.to_be_bytes().to_vec()));
+    }
+    if let Some(count) = opts.kf8_unknown_count {
+        records.push((EXTH_KF8_UNKNOWN_COUNT, count.to_be_bytes().to_vec()));
+    }
+
+    let mut body = Vec::new();
+    for (code, data) in &records {
+        body.extend_from_slice(&code.to_be_bytes());
+        body.extend_from_slice(&(data.len() as u32 + 8).to_be_bytes());
+        body.extend_from_slice(data);
+    }
+
+    let mut exth = Vec::with_capacity(body.len() + 16);
+    exth.extend_from_slice(b"EXTH");
+    exth.extend_from_slice(&(body.len() as u32 + 12).to_be_bytes());
+    exth.extend_from_slice(&(records.len() as u32).to_be_bytes());
+    exth.extend_from_slice(&body);
+    // Always pad with at least one byte
+    let pad = 4 - body.len() % 4;
+    exth.extend(std::iter::repeat_n(0, pad));
+    exth
+}
@@ -0,0 +1,319 @@
+//! INDX records for the KF8 skeleton, chunk (fragment), guide and NCX
+//! tables. The layout mirrors what `mobi::index::read_index` parses.
+
+use crate::mobi::headers::NULL_INDEX;
+use crate::mobi::utils::{align_block, encint, CNCX};
+use std::collections::HashMap;
+
+const HEADER_LENGTH: usize = 192;
+// kindlegen leaves this much room in every record, there has to be some
+// margin because of block alignment
+const RECORD_LIMIT: usize = 0x10000 - HEADER_LENGTH - 1048;
+
+/// A TAGX entry: tag number, values per entry, control byte mask and the
+/// end of control byte flag.
+#[derive(Debug, Clone, Copy)]
+pub struct TagMeta {
+    pub name: &'static str,
+    pub number: u8,
+    pub values_per_entry: u8,
+    pub mask: u8,
+    pub end_flag: u8,
+}
+
+const fn tag(name: &'static str, number: u8, values_per_entry: u8, mask: u8) -> TagMeta {
+    TagMeta {
+        name,
+        number,
+        values_per_entry,
+        mask,
+        end_flag: 0,
+    }
+}
+
+const END_TAG_TABLE: TagMeta = TagMeta {
+    name: "eof",
+    number: 0,
+    values_per_entry: 0,
+    mask: 0,
+    end_flag: 1,
+};
+
+/// One index entry: its key and the values of each tag it uses.
+pub type IndexEntry = (String, HashMap<&'static str, Vec<u64>>);
+
+/// Serialize `entries` into an index header record, the index records and
+/// the CNCX records holding the strings referenced by the entries.
+pub fn build_index(tag_types: &[TagMeta], entries: &[IndexEntry], cncx: &CNCX) -> Vec<Vec<u8>> {
+    let control_byte_count = tag_types.iter().filter(|t| t.end_flag == 1).count();
+
+    // Index records, each holding as many entries as fit
+    let mut blocks: Vec<(Vec<u8>, Vec<u16>, String)> = Vec::new();
+    for (key, tags) in entries {
+        let mut raw = Vec::new();
+        let key_bytes = key.as_bytes();
+        raw.push(key_bytes.len() as u8);
+        raw.extend_from_slice(key_bytes);
+
+        let mut control_byte = 0u8;
+        for t in tag_types {
+            if t.end_flag == 1 {
+                raw.push(control_byte);
+                control_byte = 0;
+                continue;
+            }
+            let nvals = tags.get(t.name).map(|v| v.len()).unwrap_or(0);
+            let nentries = (nvals / t.values_per_entry.max(1) as usize) as u8;
+            control_byte |= t.mask & (nentries << t.mask.trailing_zeros());
+        }
+        for t in tag_types {
+            if let Some(values) = tags.get(t.name) {
+                for v in values {
+                    raw.extend_from_slice(&encint(*v, true));
+                }
+            }
+        }
+
+        let needs_new = match blocks.last() {
+            None => true,
+            Some((data, offsets, _)) => {
+                data.len() + raw.len() + 2 * (offsets.len() + 1) > RECORD_LIMIT
+            }
+        };
+        if needs_new {
+            blocks.push((Vec::new(), Vec::new(), String::new()));
+        }
+        if let Some((data, offsets, last)) = blocks.last_mut() {
+            offsets.push((HEADER_LENGTH + data.len()) as u16);
+            data.extend_from_slice(&raw);
+            *last = key.clone();
+        }
+    }
+
+    let mut index_records = Vec::new();
+    for (data, offsets, _) in &blocks {
+        let data = align_block(data, 4, 0);
+        let mut idxt = Vec::with_capacity(offsets.len() * 2);
+        for off in offsets {
+            idxt.extend_from_slice(&off.to_be_bytes());
+        }
+        let idxt_pos = HEADER_LENGTH + data.len();
+
+        let mut rec = Vec::with_capacity(idxt_pos + idxt.len() + 8);
+        rec.extend_from_slice(b"INDX");
+        rec.extend_from_slice(&(HEADER_LENGTH as u32).to_be_bytes());
+        rec.extend_from_slice(&[0; 4]);
+        rec.extend_from_slice(&1u32.to_be_bytes()); // Index record, not header
+        rec.extend_from_slice(&[0; 4]);
+        rec.extend_from_slice(&(idxt_pos as u32).to_be_bytes());
+        rec.extend_from_slice(&(offsets.len() as u32).to_be_bytes());
+        rec.extend_from_slice(&[0xff; 8]);
+        rec.resize(HEADER_LENGTH, 0);
+        rec.extend_from_slice(&data);
+        rec.extend_from_slice(b"IDXT");
+        rec.extend_from_slice(&idxt);
+        index_records.push(align_block(&rec, 4, 0));
+    }
+
+    // TAGX section
+    let mut tagx = Vec::new();
+    tagx.extend_from_slice(b"TAGX");
+    tagx.extend_from_slice(&(12 + 4 * tag_types.len() as u32).to_be_bytes());
+    tagx.extend_from_slice(&(control_byte_count as u32).to_be_bytes());
+    for t in tag_types {
+        tagx.extend_from_slice(&[t.number, t.values_per_entry, t.mask, t.end_flag]);
+    }
+    let tagx = align_block(&tagx, 4, 0);
+
+    // Geometry: the last key and the entry count of every index record
+    let mut geometry = Vec::new();
+    let mut geometry_offsets = Vec::new();
+    for (_, offsets, last) in &blocks {
+        geometry_offsets.push((HEADER_LENGTH + tagx.len() + geometry.len()) as u16);
+        geometry.push(last.len() as u8);
+        geometry.extend_from_slice(last.as_bytes());
+        geometry.extend_from_slice(&(offsets.len() as u16).to_be_bytes());
+    }
+    let geometry = align_block(&geometry, 4, 0);
+    let mut idxt = b"IDXT".to_vec();
+    for off in geometry_offsets {
+        idxt.extend_from_slice(&off.to_be_bytes());
+    }
+    let idxt = align_block(&idxt, 4, 0);
+
+    let mut header = Vec::with_capacity(HEADER_LENGTH + tagx.len() + geometry.len() + idxt.len());
+    header.extend_from_slice(b"INDX");
+    header.extend_from_slice(&(HEADER_LENGTH as u32).to_be_bytes());
+    header.extend_from_slice(&[0; 8]);
+    header.extend_from_slice(&2u32.to_be_bytes()); // Index type
+    header.extend_from_slice(&((HEADER_LENGTH + tagx.len() + geometry.len()) as u32).to_be_bytes());
+    header.extend_from_slice(&(index_records.len() as u32).to_be_bytes());
+    header.extend_from_slice(&65001u32.to_be_bytes());
+    header.extend_from_slice(&NULL_INDEX.to_be_bytes());
+    header.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    header.extend_from_slice(&[0; 12]); // ORDT, LIGT and ORDT/LIGT entry count
+    header.extend_from_slice(&(cncx.records.len() as u32).to_be_bytes());
+    header.resize(180, 0);
+    header.extend_from_slice(&(HEADER_LENGTH as u32).to_be_bytes()); // TAGX offset
+    header.resize(HEADER_LENGTH, 0);
+    header.extend_from_slice(&tagx);
+    header.extend_from_slice(&geometry);
+    header.extend_from_slice(&idxt);
+
+    let mut records = vec![header];
+    records.extend(index_records);
+    records.extend(cncx.records.iter().cloned());
+    records
+}
+
+/// Build a CNCX from `strings`, keeping the first occurrence of each.
+pub fn cncx_for<'a>(strings: impl IntoIterator<Item = &'a str>) -> CNCX {
+    let mut seen = Vec::<String>::new();
+    for s in strings {
+        if !seen.iter().any(|x| x == s) {
+            seen.push(s.to_string());
+        }
+    }
+    CNCX::new(&seen)
+}
+
+#[derive(Debug, Clone)]
+pub struct SkelEntry {
+    pub file_number: usize,
+    pub name: String,
+    pub chunk_count: usize,
+    pub start_pos: usize,
+    pub length: usize,
+}
+
+pub fn skel_index(table: &[SkelEntry]) -> Vec<Vec<u8>> {
+    const TAGS: [TagMeta; 3] = [
+        tag("chunk_count", 1, 1, 3),
+        tag("geometry", 6, 2, 12),
+        END_TAG_TABLE,
+    ];
+    let entries: Vec<IndexEntry> = table
+        .iter()
+        .map(|s| {
+            let mut tags = HashMap::new();
+            // kindlegen repeats both values, readers expect it
+            tags.insert("chunk_count", vec![s.chunk_count as u64; 2]);
+            let geometry = [s.start_pos as u64, s.length as u64];
+            tags.insert("geometry", geometry.repeat(2));
+            (s.name.clone(), tags)
+        })
+        .collect();
+    build_index(&TAGS, &entries, &CNCX::new(&[]))
+}
+
+#[derive(Debug, Clone)]
+pub struct ChunkEntry {
+    pub insert_pos: usize,
+    pub selector: String,
+    pub file_number: usize,
+    pub sequence_number: usize,
+    pub start_pos: usize,
+    pub length: usize,
+}
+
+pub fn chunk_index(table: &[ChunkEntry]) -> Vec<Vec<u8>> {
+    const TAGS: [TagMeta; 5] = [
+        tag("cncx_offset", 2, 1, 1),
+        tag("file_number", 3, 1, 2),
+        tag("sequence_number", 4, 1, 4),
+        tag("geometry", 6, 2, 8),
+        END_TAG_TABLE,
+    ];
+    let cncx = cncx_for(table.iter().map(|c| c.selector.as_str()));
+    let entries: Vec<IndexEntry> = table
+        .iter()
+        .map(|c| {
+            let mut tags = HashMap::new();
+            tags.insert("cncx_offset", vec![cncx.strings[&c.selector] as u64]);
+            tags.insert("file_number", vec![c.file_number as u64]);
+            tags.insert("sequence_number", vec![c.sequence_number as u64]);
+            tags.insert("geometry", vec![c.start_pos as u64, c.length as u64]);
+            (format!("{:010}", c.insert_pos), tags)
+        })
+        .collect();
+    build_index(&TAGS, &entries, &cncx)
+}
+
+#[derive(Debug, Clone)]
+pub struct GuideRef {
+    pub title: String,
+    pub type_: String,
+    pub pos_fid: (usize, usize),
+}
+
+pub fn guide_index(table: &[GuideRef]) -> Vec<Vec<u8>> {
+    const TAGS: [TagMeta; 3] = [
+        tag("title", 1, 1, 1),
+        tag("pos_fid", 6, 2, 2),
+        END_TAG_TABLE,
+    ];
+    let cncx = cncx_for(table.iter().map(|r| r.title.as_str()));
+    let entries: Vec<IndexEntry> = table
+        .iter()
+        .map(|r| {
+            let mut tags = HashMap::new();
+            tags.insert("title", vec![cncx.strings[&r.title] as u64]);
+            tags.insert("pos_fid", vec![r.pos_fid.0 as u64, r.pos_fid.1 as u64]);
+            (r.type_.clone(), tags)
+        })
+        .collect();
+    build_index(&TAGS, &entries, &cncx)
+}
+
+/// A flattened ToC entry. Entries are sorted by (depth, offset) and
+/// `parent`/`first_child`/`last_child` refer to positions in that order.
+#[derive(Debug, Clone, Default)]
+pub struct TocEntry {
+    pub label: String,
+    pub offset: usize,
+    pub length: usize,
+    pub depth: usize,
+    pub pos_fid: (usize, usize),
+    pub parent: Option<usize>,
+    pub first_child: Option<usize>,
+    pub last_child: Option<usize>,
+}
+
+pub fn ncx_index(table: &[TocEntry]) -> Vec<Vec<u8>> {
+    const TAGS: [TagMeta; 9] = [
+        tag("offset", 1, 1, 1),
+        tag("length", 2, 1, 2),
+        tag("label", 3, 1, 4),
+        tag("depth", 4, 1, 8),
+        tag("parent", 21, 1, 16),
+        tag("first_child", 22, 1, 32),
+        tag("last_child", 23, 1, 64),
+        tag("pos_fid", 6, 2, 128),
+        END_TAG_TABLE,
+    ];
+    let cncx = cncx_for(table.iter().map(|e| e.label.as_str()));
+    let width = format!("{:X}", table.len().saturating_sub(1)).len().max(2);
+    let entries: Vec<IndexEntry> = table
+        .iter()
+        .enumerate()
+        .map(|(i, e)| {
+            let mut tags = HashMap::new();
+            tags.insert("offset", vec![e.offset as u64]);
+            tags.insert("length", vec![e.length as u64]);
+            tags.insert("label", vec![cncx.strings[&e.label] as u64]);
+            tags.insert("depth", vec![e.depth as u64]);
+            if let Some(p) = e.parent {
+                tags.insert("parent", vec![p as u64]);
+            }
+            if let Some(c) = e.first_child {
+                tags.insert("first_child", vec![c as u64]);
+            }
+            if let Some(c) = e.last_child {
+                tags.insert("last_child", vec![c as u64]);
+            }
+            tags.insert("pos_fid", vec![e.pos_fid.0 as u64, e.pos_fid.1 as u64]);
+            (format!("{:0width$X}", i, width = width), tags)
+        })
+        .collect();
+    build_index(&TAGS, &entries, &cncx)
+}
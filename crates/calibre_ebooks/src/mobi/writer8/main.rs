@@ -0,0 +1,522 @@
+//! Turns an OEB book into the KF8 text flows and index tables.
+
+use super::index::{ChunkEntry, GuideRef, SkelEntry, TocEntry};
+use super::skeleton::{parse_xhtml, Chunker, Element, Node};
+use crate::mobi::resources::Resources;
+use crate::mobi::utils::{is_guide_ref_start, to_base};
+use crate::oeb::book::OEBBook;
+use crate::oeb::parse_utils::abshref;
+use crate::oeb::toc::TOCNode;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::collections::{HashMap, HashSet};
+
+lazy_static! {
+    static ref CSS_URL_PAT: Regex =
+        Regex::new(r#"url\(\s*(?:"([^"]*)"|'([^']*)'|([^)'"\s]*))\s*\)"#).unwrap();
+}
+
+/// Tags that can be the target of a link, only these get `aid` attributes.
+const AID_ABLE_TAGS: &[&str] = &[
+    "a",
+    "abbr",
+    "address",
+    "article",
+    "aside",
+    "audio",
+    "b",
+    "bdo",
+    "blockquote",
+    "body",
+    "button",
+    "cite",
+    "code",
+    "dd",
+    "del",
+    "details",
+    "dfn",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hgroup",
+    "i",
+    "ins",
+    "kbd",
+    "label",
+    "legend",
+    "li",
+    "map",
+    "mark",
+    "meter",
+    "nav",
+    "ol",
+    "output",
+    "p",
+    "pre",
+    "progress",
+    "q",
+    "rp",
+    "rt",
+    "samp",
+    "section",
+    "select",
+    "small",
+    "span",
+    "strong",
+    "sub",
+    "summary",
+    "sup",
+    "textarea",
+    "time",
+    "ul",
+    "var",
+    "video",
+];
+
+/// Maps a container href to (chunk number, offset in chunk, text offset)
+type Resolve<'a> = dyn Fn(&str) -> Option<(usize, usize, usize)> + 'a;
+
+pub struct KF8Writer {
+    /// The text flow followed by one flow per stylesheet
+    pub flows: Vec<Vec<u8>>,
+    pub skel_table: Vec<SkelEntry>,
+    pub chunk_table: Vec<ChunkEntry>,
+    pub guide_table: Vec<GuideRef>,
+    pub toc_table: Vec<TocEntry>,
+    /// Text offset of the start reading location
+    pub start_offset: Option<usize>,
+    /// 1 based numbers of the resources the text refers to
+    pub used_resources: HashSet<usize>,
+}
+
+struct Context<'a> {
+    resources: &'a Resources,
+    used: HashSet<usize>,
+    /// Stylesheet href -> flow number
+    css_flows: HashMap<String, usize>,
+    flows: Vec<Vec<u8>>,
+}
+
+impl<'a> Context<'a> {
+    fn embed_url(&mut self, href: &str) -> Option<String> {
+        let idx = *self.resources.item_map.get(href)?;
+        self.used.insert(idx);
+        let mut url = format!("kindle:embed:{}", to_base(idx as u64, 32, 4));
+        if let Some(mime) = self.resources.mime_map.get(href) {
+            url.push_str("?mime=");
+            url.push_str(mime);
+        }
+        Some(url)
+    }
+
+    /// Point every `url()` in `css` at the matching resource record.
+    fn rewrite_css(&mut self, css: &str, base: &str) -> String {
+        CSS_URL_PAT
+            .replace_all(css, |caps: &Captures| {
+                let url = caps
+                    .get(1)
+                    .or_else(|| caps.get(2))
+                    .or_else(|| caps.get(3))
+                    .map(|m| m.as_str())
+                    .unwrap_or("");
+                if url.contains(':') {
+                    return caps[0].to_string();
+                }
+                match self.embed_url(&abshref(base, url)) {
+                    Some(embed) => format!("url({})", embed),
+                    None => caps[0].to_string(),
+                }
+            })
+            .into_owned()
+    }
+
+    fn add_flow(&mut self, css: Vec<u8>) -> usize {
+        if let Some(pos) = self.flows.iter().position(|f| *f == css) {
+            return pos + 1;
+        }
+        self.flows.push(css);
+        self.flows.len()
+    }
+}
+
+fn flow_link(num: usize) -> Element {
+    let mut link = Element::new("link");
+    link.set("rel", "stylesheet");
+    link.set("type", "text/css");
+    link.set(
+        "href",
+        &format!("kindle:flow:{}?mime=text/css", to_base(num as u64, 32, 4)),
+    );
+    link
+}
+
+impl KF8Writer {
+    pub fn new(book: &OEBBook, resources: &Resources) -> Self {
+        let mut ctx = Context {
+            resources,
+            used: HashSet::new(),
+            css_flows: HashMap::new(),
+            flows: Vec::new(),
+        };
+
+        // Stylesheets become flows of their own
+        let mut sheets: Vec<_> = book
+            .manifest
+            .items
+            .values()
+            .filter(|i| i.media_type == "text/css")
+            .collect();
+        sheets.sort_by(|a, b| a.href.cmp(&b.href));
+        for item in sheets {
+            if let Ok(data) = book.container.read(&item.href) {
+                let css = ctx.rewrite_css(&String::from_utf8_lossy(&data), &item.href);
+                let num = ctx.add_flow(css.into_bytes());
+                ctx.css_flows.insert(item.href.clone(), num);
+            }
+        }
+
+        let mut hrefs = Vec::new();
+        let mut roots = Vec::new();
+        for itemref in &book.spine.items {
+            let item = match book.manifest.items.get(&itemref.idref) {
+                Some(item) if !item.media_type.starts_with("image/") => item,
+                _ => continue,
+            };
+            let data = match book.container.read(&item.href) {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+            let mut root = parse_xhtml(&String::from_utf8_lossy(&data));
+            replace_resources(&mut root, &item.href, &mut ctx);
+            hrefs.push(item.href.clone());
+            roots.push(root);
+        }
+        if roots.is_empty() {
+            hrefs.push(String::new());
+            roots.push(parse_xhtml("<html><head></head><body></body></html>"));
+        }
+
+        let placeholders = replace_internal_links(&mut roots, &hrefs);
+        let id_map = insert_aid_attributes(&mut roots, &hrefs);
+        let placeholder_map: HashMap<String, String> = placeholders
+            .into_iter()
+            .filter_map(|(ph, target)| {
+                let aid = id_map
+                    .get(&target)
+                    .or_else(|| id_map.get(&(target.0.clone(), String::new())))?;
+                Some((ph, aid.clone()))
+            })
+            .collect();
+
+        let chunker = Chunker::new(roots, &placeholder_map);
+        let text_len = chunker.text.len();
+
+        let resolve = |href: &str| -> Option<(usize, usize, usize)> {
+            let (path, frag) = match href.split_once('#') {
+                Some((p, f)) => (p, f),
+                None => (href, ""),
+            };
+            let path = abshref("", path);
+            let aid = id_map
+                .get(&(path.clone(), frag.to_string()))
+                .or_else(|| id_map.get(&(path, String::new())))?;
+            chunker.aid_offset_map.get(aid).copied()
+        };
+
+        let toc_table = build_toc(&book.toc.root, &resolve, text_len);
+
+        let mut start_offset = None;
+        let mut refs: Vec<_> = book.guide.references.values().collect();
+        refs.sort_by(|a, b| a.type_.cmp(&b.type_));
+        let mut guide_table = Vec::new();
+        for r in refs {
+            let (fid, off, pos) = match resolve(&r.href) {
+                Some(p) => p,
+                None => continue,
+            };
+            if is_guide_ref_start(r.title.as_deref(), Some(&r.type_)) {
+                start_offset = Some(pos);
+            }
+            guide_table.push(GuideRef {
+                title: r.title.clone().unwrap_or_else(|| r.type_.clone()),
+                type_: r.type_.clone(),
+                pos_fid: (fid, off),
+            });
+        }
+
+        let mut flows = vec![chunker.text];
+        flows.extend(ctx.flows);
+
+        KF8Writer {
+            flows,
+            skel_table: chunker.skel_table,
+            chunk_table: chunker.chunk_table,
+            guide_table,
+            toc_table,
+            start_offset,
+            used_resources: ctx.used,
+        }
+    }
+
+    /// The FDST table: start and end of every flow in the text.
+    pub fn fdst_table(&self) -> Vec<(usize, usize)> {
+        let mut start = 0;
+        self.flows
+            .iter()
+            .map(|f| {
+                let entry = (start, start + f.len());
+                start += f.len();
+                entry
+            })
+            .collect()
+    }
+
+    pub fn text(&self) -> Vec<u8> {
+        self.flows.concat()
+    }
+}
+
+/// Point images, fonts and stylesheets at their KF8 records.
+fn replace_resources(root: &mut Element, base: &str, ctx: &mut Context) {
+    // Embedded stylesheets are moved to flows, linked from the head
+    let mut links = Vec::new();
+    root.walk_mut(&mut |el| {
+        let children = std::mem::take(&mut el.children);
+        for child in children {
+            match child {
+                Node::Element(style) if style.name == "style" => {
+                    let css: String = style
+                        .children
+                        .iter()
+                        .filter_map(|c| match c {
+                            Node::Text(t) => Some(t.as_str()),
+                            _ => None,
+                        })
+                        .collect();
+                    if !css.trim().is_empty() {
+                        let css = ctx.rewrite_css(&css, base);
+                        links.push(ctx.add_flow(css.into_bytes()));
+                    }
+                }
+                other => el.children.push(other),
+            }
+        }
+    });
+
+    root.walk_mut(&mut |el| {
+        match el.name.as_str() {
+            "link" => {
+                let href = el.get("href").map(|h| abshref(base, h));
+                match href.and_then(|h| ctx.css_flows.get(&h).copied()) {
+                    Some(num) => *el = flow_link(num),
+                    None => {
+                        if el.get("rel").map(|r| r.contains("stylesheet")) == Some(true) {
+                            el.remove("href");
+                        }
+                    }
+                }
+            }
+            "img" => {
+                let src = el.get("src").map(|s| abshref(base, s));
+                if let Some(url) = src.and_then(|s| ctx.embed_url(&s)) {
+                    el.set("src", &url);
+                }
+            }
+            "image" => {
+                for attr in ["xlink:href", "href"] {
+                    let href = el.get(attr).map(|s| abshref(base, s));
+                    if let Some(url) = href.and_then(|s| ctx.embed_url(&s)) {
+                        el.set(attr, &url);
+                    }
+                }
+            }
+            _ => {}
+        }
+        if let Some(style) = el.get("style").map(|s| s.to_string()) {
+            if style.contains("url(") {
+                let style = ctx.rewrite_css(&style, base);
+                el.set("style", &style);
+            }
+        }
+    });
+
+    // Dead stylesheet links would make the Kindle fetch nothing, drop them
+    root.walk_mut(&mut |el| {
+        el.children.retain(
+            |c| !matches!(c, Node::Element(e) if e.name == "link" && e.get("href").is_none()),
+        )
+    });
+
+    if let Some(head) = root.find_mut("head") {
+        for num in links {
+            head.children.push(Node::Element(flow_link(num)));
+        }
+    }
+}
+
+/// Replace links to other spine files with placeholders of the final size,
+/// returning placeholder -> (href, id).
+fn replace_internal_links(
+    roots: &mut [Element],
+    hrefs: &[String],
+) -> HashMap<String, (String, String)> {
+    let spine: HashSet<&String> = hrefs.iter().collect();
+    let mut ans = HashMap::new();
+    for (root, base) in roots.iter_mut().zip(hrefs) {
+        root.walk_mut(&mut |el| {
+            if el.name != "a" {
+                return;
+            }
+            let href = match el.get("href") {
+                Some(h) if !h.contains(':') => h.to_string(),
+                _ => return,
+            };
+            let (path, frag) = match href.split_once('#') {
+                Some((p, f)) => (p.to_string(), f.to_string()),
+                None => (href.clone(), String::new()),
+            };
+            let target = abshref(base, &path);
+            if !spine.contains(&target) {
+                return;
+            }
+            let placeholder = format!(
+                "kindle:pos:fid:0000:off:{}",
+                to_base(ans.len() as u64, 32, 10)
+            );
+            el.set("href", &placeholder);
+            ans.insert(placeholder, (target, frag));
+        });
+    }
+    ans
+}
+
+/// Give every aid-able element a unique `aid` attribute. Returns
+/// (href, id) -> aid, where the empty id stands for the body.
+fn insert_aid_attributes(
+    roots: &mut [Element],
+    hrefs: &[String],
+) -> HashMap<(String, String), String> {
+    fn walk(
+        el: &mut Element,
+        href: &str,
+        parent_aid: Option<&str>,
+        counter: &mut u64,
+        id_map: &mut HashMap<(String, String), String>,
+    ) {
+        el.remove("aid");
+        let mut aid = parent_aid.map(|s| s.to_string());
+        if AID_ABLE_TAGS.contains(&el.name.as_str()) {
+            let new = to_base(*counter, 32, 0);
+            *counter += 1;
+            el.set("aid", &new);
+            if el.name == "body" {
+                id_map.insert((href.to_string(), String::new()), new.clone());
+            }
+            aid = Some(new);
+        }
+        if let (Some(id), Some(aid)) = (el.get("id"), &aid) {
+            id_map
+                .entry((href.to_string(), id.to_string()))
+                .or_insert_with(|| aid.clone());
+        }
+        for child in &mut el.children {
+            if let Node::Element(c) = child {
+                walk(c, href, aid.as_deref(), counter, id_map);
+            }
+        }
+    }
+
+    let mut id_map = HashMap::new();
+    let mut counter = 0;
+    for (root, href) in roots.iter_mut().zip(hrefs) {
+        walk(root, href, None, &mut counter, &mut id_map);
+    }
+    id_map
+}
+
+/// Flatten the ToC into the table the NCX index stores: entries sorted by
+/// depth and then by position, with parent and children referring to
+/// positions in that order.
+fn build_toc(root: &TOCNode, resolve: &Resolve, text_len: usize) -> Vec<TocEntry> {
+    // (label, pos_fid, offset, depth, parent) in document order
+    type Flat = (String, (usize, usize), usize, usize, Option<usize>);
+    fn flatten(
+        node: &TOCNode,
+        depth: usize,
+        parent: Option<usize>,
+        resolve: &Resolve,
+        out: &mut Vec<Flat>,
+    ) {
+        for child in &node.children {
+            let pos = child.href.as_deref().and_then(resolve);
+            let mut this = parent;
+            if let Some((fid, off, offset)) = pos {
+                let label = child
+                    .title
+                    .as_deref()
+                    .map(|t| t.trim())
+                    .filter(|t| !t.is_empty())
+                    .unwrap_or("Unknown")
+                    .to_string();
+                out.push((label, (fid, off), offset, depth, parent));
+                this = Some(out.len() - 1);
+            }
+            let child_depth = if pos.is_some() { depth + 1 } else { depth };
+            flatten(child, child_depth, this, resolve, out);
+        }
+    }
+
+    let mut flat = Vec::new();
+    flatten(root, 0, None, resolve, &mut flat);
+
+    let mut order: Vec<usize> = (0..flat.len()).collect();
+    order.sort_by_key(|&i| (flat[i].3, flat[i].2, i));
+    let mut new_index = vec![0; flat.len()];
+    for (pos, &i) in order.iter().enumerate() {
+        new_index[i] = pos;
+    }
+
+    let mut table: Vec<TocEntry> = order
+        .iter()
+        .map(|&i| {
+            let (label, pos_fid, offset, depth, parent) = &flat[i];
+            // An entry runs until the next entry that is not below it
+            let end = flat[i + 1..]
+                .iter()
+                .find(|e| e.3 <= *depth)
+                .map(|e| e.2)
+                .unwrap_or(text_len);
+            TocEntry {
+                label: label.clone(),
+                offset: *offset,
+                length: end.saturating_sub(*offset),
+                depth: *depth,
+                pos_fid: *pos_fid,
+                parent: parent.map(|p| new_index[p]),
+                first_child: None,
+                last_child: None,
+            }
+        })
+        .collect();
+
+    for i in 0..table.len() {
+        if let Some(p) = table[i].parent {
+            let parent = &mut table[p];
+            parent.first_child = Some(parent.first_child.map_or(i, |c| c.min(i)));
+            parent.last_child = Some(parent.last_child.map_or(i, |c| c.max(i)));
+        }
+    }
+    table
+}
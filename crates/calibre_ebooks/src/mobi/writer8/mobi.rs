@@ -0,0 +1,287 @@
+//! Record 0 and the record layout of KF8 books.
+
+use super::exth::{book_uuid, build_exth, ExthOptions};
+use super::index::{chunk_index, guide_index, ncx_index, skel_index};
+use super::main::KF8Writer;
+use crate::compression::palmdoc;
+use crate::mobi::headers::NULL_INDEX;
+use crate::mobi::langcodes::iana2mobi;
+use crate::mobi::resources::Resources;
+use crate::oeb::book::OEBBook;
+use anyhow::Result;
+use std::collections::HashSet;
+
+pub const RECORD_SIZE: usize = 0x1000;
+
+pub const FLIS: &[u8] = b"FLIS\0\0\0\x08\0\x41\0\0\0\0\0\0\xff\xff\xff\xff\0\x01\0\x03\0\0\0\x03\0\0\0\x01\xff\xff\xff\xff";
+pub const EOF_RECORD: &[u8] = b"\xe9\x8e\r\n";
+
+pub fn fcis(text_length: usize) -> Vec<u8> {
+    let mut fcis = b"FCIS\x00\x00\x00\x14\x00\x00\x00\x10\x00\x00\x00\x02\x00\x00\x00\x00".to_vec();
+    fcis.extend_from_slice(&(text_length as u32).to_be_bytes());
+    fcis.extend_from_slice(b"\x00\x00\x00\x00\x00\x00\x00\x28\x00\x00\x00\x00\x00\x00\x00");
+    fcis.extend_from_slice(b"\x28\x00\x00\x00\x08\x00\x01\x00\x01\x00\x00\x00\x00");
+    fcis
+}
+
+/// Split `text` into PalmDoc compressed records of `RECORD_SIZE` bytes. A
+/// character cut by the record boundary is completed in the trailing
+/// multibyte entry, as readers expect with extra data flag 1.
+pub fn create_text_records(text: &[u8], compress: bool) -> Result<Vec<Vec<u8>>> {
+    let mut records = Vec::new();
+    let mut pos = 0;
+    while pos < text.len() {
+        let end = (pos + RECORD_SIZE).min(text.len());
+        let data = &text[pos..end];
+        let mut overlap = 0;
+        while end + overlap < text.len() && overlap < 3 && (text[end + overlap] & 0xC0) == 0x80 {
+            overlap += 1;
+        }
+        let mut record = if compress {
+            palmdoc::compress(data)?
+        } else {
+            data.to_vec()
+        };
+        record.extend_from_slice(&text[end..end + overlap]);
+        record.push(overlap as u8);
+        records.push(record);
+        pos = end;
+    }
+    Ok(records)
+}
+
+/// The fields of record 0 that vary between books. Record numbers are
+/// relative to the record 0 they are written in.
+pub struct MOBIHeader {
+    pub file_version: u32,
+    pub title: String,
+    pub uid: u32,
+    pub language: String,
+    pub text_length: usize,
+    pub last_text_record: usize,
+    pub first_non_text_record: usize,
+    pub first_resource_record: Option<usize>,
+    pub exth: Vec<u8>,
+    pub exth_flags: u32,
+    /// (first, count). MOBI 6 files store the content range here instead.
+    pub fdst: (u32, u32),
+    pub fcis_record: usize,
+    pub flis_record: usize,
+    pub extra_data_flags: u32,
+    pub ncx_index: Option<usize>,
+    pub chunk_index: Option<usize>,
+    pub skel_index: Option<usize>,
+    pub guide_index: Option<usize>,
+}
+
+impl MOBIHeader {
+    pub const HEADER_LENGTH: u32 = 264;
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let idx = |v: Option<usize>| v.map(|v| v as u32).unwrap_or(NULL_INDEX);
+        let mut buf: Vec<u8> = Vec::with_capacity(0x3000);
+        let u16 = |buf: &mut Vec<u8>, v: u16| buf.extend_from_slice(&v.to_be_bytes());
+        let u32 = |buf: &mut Vec<u8>, v: u32| buf.extend_from_slice(&v.to_be_bytes());
+
+        // PalmDoc header
+        u16(&mut buf, 2); // PalmDoc compression
+        u16(&mut buf, 0);
+        u32(&mut buf, self.text_length as u32);
+        u16(&mut buf, self.last_text_record as u16);
+        u16(&mut buf, RECORD_SIZE as u16);
+        u16(&mut buf, 0); // no encryption
+        u16(&mut buf, 0);
+
+        buf.extend_from_slice(b"MOBI");
+        u32(&mut buf, Self::HEADER_LENGTH);
+        u32(&mut buf, 2); // book
+        u32(&mut buf, 65001); // UTF-8
+        u32(&mut buf, self.uid);
+        u32(&mut buf, self.file_version);
+        u32(&mut buf, NULL_INDEX); // meta orth index
+        u32(&mut buf, NULL_INDEX); // meta infl index
+        for _ in 0..8 {
+            u32(&mut buf, NULL_INDEX); // extra indices
+        }
+        u32(&mut buf, self.first_non_text_record as u32);
+
+        let title_offset = 16 + Self::HEADER_LENGTH as usize + self.exth.len();
+        u32(&mut buf, title_offset as u32);
+        u32(&mut buf, self.title.len() as u32);
+        let lang = iana2mobi(&self.language);
+        buf.extend_from_slice(&lang);
+        u32(&mut buf, 0); // input language
+        u32(&mut buf, 0); // output language
+        u32(&mut buf, self.file_version); // minimum reader version
+        u32(&mut buf, idx(self.first_resource_record));
+        u32(&mut buf, 0); // huffman records
+        u32(&mut buf, 0);
+        buf.extend_from_slice(&[0; 8]);
+        u32(&mut buf, self.exth_flags);
+        buf.extend_from_slice(&[0; 32]);
+        u32(&mut buf, NULL_INDEX);
+        u32(&mut buf, NULL_INDEX); // DRM offset
+        u32(&mut buf, 0); // DRM count
+        u32(&mut buf, 0); // DRM size
+        u32(&mut buf, 0); // DRM flags
+        buf.extend_from_slice(&[0; 8]);
+        u32(&mut buf, self.fdst.0);
+        u32(&mut buf, self.fdst.1);
+        u32(&mut buf, self.fcis_record as u32);
+        u32(&mut buf, 1);
+        u32(&mut buf, self.flis_record as u32);
+        u32(&mut buf, 1);
+        buf.extend_from_slice(&[0; 8]);
+        u32(&mut buf, NULL_INDEX); // SRCS record
+        u32(&mut buf, 0);
+        u32(&mut buf, NULL_INDEX);
+        u32(&mut buf, NULL_INDEX);
+        u32(&mut buf, self.extra_data_flags);
+        u32(&mut buf, idx(self.ncx_index));
+        u32(&mut buf, idx(self.chunk_index));
+        u32(&mut buf, idx(self.skel_index));
+        u32(&mut buf, NULL_INDEX); // DATP
+        u32(&mut buf, idx(self.guide_index));
+        u32(&mut buf, NULL_INDEX);
+        u32(&mut buf, 0);
+        u32(&mut buf, NULL_INDEX);
+        u32(&mut buf, 0);
+        debug_assert_eq!(buf.len(), 16 + Self::HEADER_LENGTH as usize);
+
+        buf.extend_from_slice(&self.exth);
+        buf.extend_from_slice(self.title.as_bytes());
+        buf.extend(std::iter::repeat_n(0, 4 - self.title.len() % 4));
+        // kindlegen leaves room to edit the header in place
+        buf.extend(std::iter::repeat_n(0, 8192));
+        buf
+    }
+}
+
+/// A deterministic unique id for record 0, derived from the book UUID.
+pub fn header_uid(uuid: &str) -> u32 {
+    uuid.bytes().fold(0x811c9dc5u32, |h, b| {
+        (h ^ b as u32).wrapping_mul(0x01000193)
+    })
+}
+
+/// The title and language record 0 stores for `book`.
+pub fn title_and_language(book: &OEBBook) -> (String, String) {
+    let title = book
+        .metadata
+        .first("title")
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| "Unknown".to_string());
+    let language = book.metadata.first("language").unwrap_or("und").to_string();
+    (title, language)
+}
+
+/// The records of a KF8 book, from record 0 up to the EOF record.
+pub struct KF8Book {
+    pub records: Vec<Vec<u8>>,
+}
+
+impl KF8Book {
+    /// Lay out `writer` as a KF8 book. For joint files the resources are
+    /// stored in the MOBI 6 part and `for_joint` leaves them out.
+    pub fn new(
+        book: &OEBBook,
+        writer: &KF8Writer,
+        resources: &Resources,
+        used_resources: &HashSet<usize>,
+        for_joint: bool,
+    ) -> Result<Self> {
+        let text = writer.text();
+        let mut records = vec![Vec::new()];
+        records.extend(create_text_records(&text, true)?);
+        let last_text_record = records.len() - 1;
+        let text_size: usize = records[1..].iter().map(|r| r.len()).sum();
+        if !text_size.is_multiple_of(4) {
+            records.push(vec![0; 4 - text_size % 4]);
+        }
+        let first_non_text_record = records.len();
+
+        let add_index = |records: &mut Vec<Vec<u8>>, index: Vec<Vec<u8>>| {
+            if index.is_empty() {
+                return None;
+            }
+            let pos = records.len();
+            records.extend(index);
+            Some(pos)
+        };
+        let chunk = add_index(&mut records, chunk_index(&writer.chunk_table));
+        let skel = add_index(&mut records, skel_index(&writer.skel_table));
+        let guide = if writer.guide_table.is_empty() {
+            None
+        } else {
+            add_index(&mut records, guide_index(&writer.guide_table))
+        };
+        let ncx = if writer.toc_table.is_empty() {
+            None
+        } else {
+            add_index(&mut records, ncx_index(&writer.toc_table))
+        };
+
+        let first_resource_record = records.len();
+        let mut num_of_resources = 0;
+        if !for_joint {
+            let data = resources.serialize(used_resources);
+            num_of_resources = data.len();
+            records.extend(data);
+        }
+
+        let fdst_record = records.len();
+        let fdst_table = writer.fdst_table();
+        let mut fdst = b"FDST".to_vec();
+        fdst.extend_from_slice(&12u32.to_be_bytes());
+        fdst.extend_from_slice(&(fdst_table.len() as u32).to_be_bytes());
+        for (start, end) in &fdst_table {
+            fdst.extend_from_slice(&(*start as u32).to_be_bytes());
+            fdst.extend_from_slice(&(*end as u32).to_be_bytes());
+        }
+        records.push(fdst);
+        let flis_record = records.len();
+        records.push(FLIS.to_vec());
+        let fcis_record = records.len();
+        records.push(fcis(text.len()));
+        records.push(EOF_RECORD.to_vec());
+
+        let uuid = book_uuid(&book.metadata, book.uid.as_deref());
+        let opts = ExthOptions {
+            cover_offset: resources.cover_offset.map(|o| o as u32),
+            thumbnail_offset: resources.thumbnail_offset.map(|o| o as u32),
+            start_offsets: writer.start_offset.map(|o| o as u32).into_iter().collect(),
+            num_of_resources: Some(num_of_resources as u32),
+            kf8_unknown_count: Some(0),
+            be_kindlegen2: true,
+            ..Default::default()
+        };
+        let (title, language) = title_and_language(book);
+        let mut exth_flags = 0b1010000;
+        if resources.records.len() > resources.image_indices.len() {
+            exth_flags |= 0x1000; // the book embeds fonts
+        }
+        let header = MOBIHeader {
+            file_version: 8,
+            title,
+            uid: header_uid(&uuid),
+            language,
+            text_length: text.len(),
+            last_text_record,
+            first_non_text_record,
+            first_resource_record: Some(first_resource_record),
+            exth: build_exth(&book.metadata, &uuid, &opts),
+            exth_flags,
+            fdst: (fdst_record as u32, fdst_table.len() as u32),
+            fcis_record,
+            flis_record,
+            extra_data_flags: 1,
+            ncx_index: ncx,
+            chunk_index: chunk,
+            skel_index: skel,
+            guide_index: guide,
+        };
+        records[0] = header.serialize();
+        Ok(KF8Book { records })
+    }
+}
@@ -0,0 +1,5 @@
+pub mod exth;
+pub mod index;
+pub mod main;
+pub mod mobi;
+pub mod skeleton;
@@ -0,0 +1,498 @@
+//! Splits XHTML files into the skeletons and chunks (fragments) KF8 stores.
+//!
+//! Every spine file becomes a skeleton: the markup with the content of the
+//! body cut out. The content is stored as chunks of at most `CHUNK_SIZE`
+//! bytes after the skeleton, and the chunk table records where each chunk
+//! is inserted back. Elements larger than a chunk are kept in the skeleton
+//! and their children are chunked instead.
+
+use super::index::{ChunkEntry, SkelEntry};
+use crate::mobi::utils::to_base;
+use crate::oeb::constants::{SVG_NS, XHTML_NS, XLINK_NS, XML_NS};
+use crate::oeb::parse_utils::xmlize_entities;
+use lazy_static::lazy_static;
+use regex::bytes::{Captures, Regex};
+use roxmltree::{Document, ParsingOptions};
+use std::collections::HashMap;
+
+pub const CHUNK_SIZE: usize = 8192;
+
+lazy_static! {
+    static ref AID_PAT: Regex = Regex::new(r#"<[^>]+? aid="([0-9A-V]+)""#).unwrap();
+    static ref PLACEHOLDER_PAT: Regex =
+        Regex::new(r"kindle:pos:fid:0000:off:([0-9A-V]{10})").unwrap();
+}
+
+const VOID_TAGS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+#[derive(Debug, Clone)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+    /// Where a chunk was cut out of a skeleton
+    Chunk(usize),
+}
+
+/// A namespace free XHTML element, as serialized into KF8 text.
+#[derive(Debug, Clone, Default)]
+pub struct Element {
+    pub name: String,
+    pub attrs: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+impl Element {
+    pub fn new(name: &str) -> Self {
+        Element {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        match self.attrs.iter_mut().find(|(k, _)| k == name) {
+            Some(attr) => attr.1 = value.to_string(),
+            None => self.attrs.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let pos = self.attrs.iter().position(|(k, _)| k == name)?;
+        Some(self.attrs.remove(pos).1)
+    }
+
+    /// The first descendant (or self) named `name`.
+    pub fn find(&self, name: &str) -> Option<&Element> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.children.iter().find_map(|c| match c {
+            Node::Element(e) => e.find(name),
+            _ => None,
+        })
+    }
+
+    pub fn find_mut(&mut self, name: &str) -> Option<&mut Element> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.children.iter_mut().find_map(|c| match c {
+            Node::Element(e) => e.find_mut(name),
+            _ => None,
+        })
+    }
+
+    /// Visit this element and all its descendants in document order.
+    pub fn walk_mut(&mut self, f: &mut dyn FnMut(&mut Element)) {
+        f(self);
+        for child in &mut self.children {
+            if let Node::Element(e) = child {
+                e.walk_mut(f);
+            }
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.serialize(&mut out, &mut Vec::new());
+        out
+    }
+
+    /// Serialize as XHTML, recording the output position of every chunk
+    /// marker in `markers`.
+    fn serialize(&self, out: &mut Vec<u8>, markers: &mut Vec<(usize, usize)>) {
+        out.push(b'<');
+        out.extend_from_slice(self.name.as_bytes());
+        for (k, v) in &self.attrs {
+            out.push(b' ');
+            out.extend_from_slice(k.as_bytes());
+            out.extend_from_slice(b"=\"");
+            out.extend_from_slice(escape(v, true).as_bytes());
+            out.push(b'"');
+        }
+        if self.children.is_empty() && VOID_TAGS.contains(&self.name.as_str()) {
+            out.extend_from_slice(b"/>");
+            return;
+        }
+        out.push(b'>');
+        for child in &self.children {
+            match child {
+                Node::Element(e) => e.serialize(out, markers),
+                Node::Text(t) => out.extend_from_slice(escape(t, false).as_bytes()),
+                Node::Chunk(idx) => markers.push((*idx, out.len())),
+            }
+        }
+        out.extend_from_slice(b"</");
+        out.extend_from_slice(self.name.as_bytes());
+        out.push(b'>');
+    }
+}
+
+pub fn escape(text: &str, attr: bool) -> String {
+    let mut ans = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => ans.push_str("&amp;"),
+            '<' => ans.push_str("&lt;"),
+            '>' => ans.push_str("&gt;"),
+            '"' if attr => ans.push_str("&quot;"),
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => ans.push(c),
+        }
+    }
+    ans
+}
+
+/// Parse an XHTML file into an `html` element with namespaces removed.
+/// Markup that is not well formed is wrapped in a body; if that fails too
+/// the text is kept as preformatted text.
+pub fn parse_xhtml(html: &str) -> Element {
+    let text = xmlize_entities(html);
+    let opts = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+    let root = match Document::parse_with_options(&text, opts) {
+        Ok(doc) => convert(doc.root_element()),
+        Err(_) => {
+            let wrapped = format!("<body xmlns=\"{}\">{}</body>", XHTML_NS, text);
+            match Document::parse_with_options(&wrapped, opts) {
+                Ok(doc) => convert(doc.root_element()),
+                Err(_) => {
+                    let mut pre = Element::new("pre");
+                    pre.children.push(Node::Text(html.to_string()));
+                    let mut body = Element::new("body");
+                    body.children.push(Node::Element(pre));
+                    body
+                }
+            }
+        }
+    };
+
+    // Make sure there is an html > head + body structure
+    let mut root = if root.name == "html" {
+        root
+    } else {
+        let mut html = Element::new("html");
+        if root.name == "body" {
+            html.children.push(Node::Element(root));
+        } else {
+            let mut body = Element::new("body");
+            body.children.push(Node::Element(root));
+            html.children.push(Node::Element(body));
+        }
+        html
+    };
+    if root.find("body").is_none() {
+        let (head, rest): (Vec<Node>, Vec<Node>) = std::mem::take(&mut root.children)
+            .into_iter()
+            .partition(|c| matches!(c, Node::Element(e) if e.name == "head"));
+        let mut body = Element::new("body");
+        body.children = rest;
+        root.children = head;
+        root.children.push(Node::Element(body));
+    }
+    if !root
+        .children
+        .iter()
+        .any(|c| matches!(c, Node::Element(e) if e.name == "head"))
+    {
+        root.children.insert(0, Node::Element(Element::new("head")));
+    }
+    root
+}
+
+fn convert(node: roxmltree::Node) -> Element {
+    let tag = node.tag_name();
+    let mut el = Element::new(tag.name());
+    if tag.namespace() == Some(SVG_NS) && tag.name() == "svg" {
+        el.set("xmlns", SVG_NS);
+        el.set("xmlns:xlink", XLINK_NS);
+    }
+    for attr in node.attributes() {
+        let name = match attr.namespace() {
+            None => attr.name().to_string(),
+            Some(XML_NS) => format!("xml:{}", attr.name()),
+            Some(XLINK_NS) => format!("xlink:{}", attr.name()),
+            Some(_) => continue,
+        };
+        el.attrs.push((name, attr.value().to_string()));
+    }
+    for child in node.children() {
+        if child.is_element() {
+            el.children.push(Node::Element(convert(child)));
+        } else if child.is_text() {
+            let text = child.text().unwrap_or("");
+            match el.children.last_mut() {
+                Some(Node::Text(prev)) => prev.push_str(text),
+                _ => el.children.push(Node::Text(text.to_string())),
+            }
+        }
+    }
+    el
+}
+
+struct Chunk {
+    raw: Vec<u8>,
+    selector: String,
+}
+
+struct Skeleton {
+    file_number: usize,
+    skeleton: Vec<u8>,
+    chunks: Vec<Chunk>,
+    /// Position of each chunk in the skeleton, before any chunk is inserted
+    markers: Vec<usize>,
+}
+
+impl Skeleton {
+    fn new(file_number: usize, mut root: Element) -> Self {
+        let mut chunks = Vec::new();
+        if let Some(body) = root.find_mut("body") {
+            step_into_tag(body, &mut chunks);
+            if chunks.is_empty() {
+                let selector = format!("P-//*[@aid='{}']", body.get("aid").unwrap_or("0"));
+                let raw = body
+                    .children
+                    .drain(..)
+                    .map(|c| match c {
+                        Node::Text(t) => escape(&t, false).into_bytes(),
+                        _ => Vec::new(),
+                    })
+                    .collect::<Vec<_>>()
+                    .concat();
+                let raw = if raw.is_empty() { b" ".to_vec() } else { raw };
+                chunks.push(Chunk { raw, selector });
+                body.children.push(Node::Chunk(0));
+            }
+        }
+
+        if root.get("xmlns").is_none() {
+            root.attrs
+                .insert(0, ("xmlns".to_string(), XHTML_NS.to_string()));
+        }
+        let mut skeleton = b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_vec();
+        let mut markers = Vec::new();
+        root.serialize(&mut skeleton, &mut markers);
+        markers.sort();
+        Skeleton {
+            file_number,
+            skeleton,
+            chunks,
+            markers: markers.into_iter().map(|(_, pos)| pos).collect(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.skeleton.len() + self.chunks.iter().map(|c| c.raw.len()).sum::<usize>()
+    }
+
+    /// The file as the reader reassembles it.
+    fn rebuild(&self) -> Vec<u8> {
+        let mut ans = Vec::with_capacity(self.len());
+        let mut prev = 0;
+        for (chunk, &pos) in self.chunks.iter().zip(&self.markers) {
+            ans.extend_from_slice(&self.skeleton[prev..pos]);
+            ans.extend_from_slice(&chunk.raw);
+            prev = pos;
+        }
+        ans.extend_from_slice(&self.skeleton[prev..]);
+        ans
+    }
+}
+
+fn step_into_tag(tag: &mut Element, chunks: &mut Vec<Chunk>) {
+    let selector = format!("P-//*[@aid='{}']", tag.get("aid").unwrap_or(""));
+    let mut out: Vec<Node> = Vec::new();
+    // Text that follows a chunked element goes into the chunks as well, so
+    // that neighbouring chunks can be merged
+    let mut after_chunk = false;
+    for child in std::mem::take(&mut tag.children) {
+        match child {
+            Node::Text(t) => {
+                if after_chunk || !t.trim().is_empty() {
+                    for piece in split_text(&t) {
+                        push_chunk(&mut out, chunks, piece, &selector);
+                    }
+                    after_chunk = true;
+                } else {
+                    out.push(Node::Text(t));
+                }
+            }
+            Node::Element(mut el) => {
+                let raw = el.to_bytes();
+                if raw.len() > CHUNK_SIZE && el.get("aid").is_some() {
+                    step_into_tag(&mut el, chunks);
+                    out.push(Node::Element(el));
+                    after_chunk = false;
+                } else {
+                    push_chunk(&mut out, chunks, raw, &selector);
+                    after_chunk = true;
+                }
+            }
+            Node::Chunk(idx) => out.push(Node::Chunk(idx)),
+        }
+    }
+    tag.children = out;
+}
+
+fn push_chunk(out: &mut Vec<Node>, chunks: &mut Vec<Chunk>, raw: Vec<u8>, selector: &str) {
+    if let Some(Node::Chunk(idx)) = out.last() {
+        let prev = &mut chunks[*idx];
+        if prev.selector == selector && prev.raw.len() + raw.len() <= CHUNK_SIZE {
+            prev.raw.extend_from_slice(&raw);
+            return;
+        }
+    }
+    chunks.push(Chunk {
+        raw,
+        selector: selector.to_string(),
+    });
+    out.push(Node::Chunk(chunks.len() - 1));
+}
+
+/// Escape text and split it into pieces of at most `CHUNK_SIZE` bytes,
+/// without breaking characters or entities.
+fn split_text(text: &str) -> Vec<Vec<u8>> {
+    let mut ans = Vec::new();
+    let mut cur = String::new();
+    for c in text.chars() {
+        let esc = escape(c.encode_utf8(&mut [0; 4]), false);
+        if cur.len() + esc.len() > CHUNK_SIZE {
+            ans.push(std::mem::take(&mut cur).into_bytes());
+        }
+        cur.push_str(&esc);
+    }
+    if !cur.is_empty() || ans.is_empty() {
+        ans.push(cur.into_bytes());
+    }
+    ans
+}
+
+pub struct Chunker {
+    /// The skeletons and chunks of every file, the first KF8 flow
+    pub text: Vec<u8>,
+    pub skel_table: Vec<SkelEntry>,
+    pub chunk_table: Vec<ChunkEntry>,
+    /// aid -> (chunk number, offset in chunk, offset in the rebuilt text)
+    pub aid_offset_map: HashMap<String, (usize, usize, usize)>,
+}
+
+impl Chunker {
+    /// `roots` are the spine files in order, with `aid` attributes set.
+    /// `placeholder_map` maps internal link placeholders to the aid of the
+    /// element they point to.
+    pub fn new(roots: Vec<Element>, placeholder_map: &HashMap<String, String>) -> Self {
+        let skeletons: Vec<Skeleton> = roots
+            .into_iter()
+            .enumerate()
+            .map(|(i, root)| Skeleton::new(i, root))
+            .collect();
+
+        let mut skel_table = Vec::new();
+        let mut chunk_table = Vec::new();
+        let mut start_pos = 0;
+        let mut num = 0;
+        for skel in &skeletons {
+            skel_table.push(SkelEntry {
+                file_number: skel.file_number,
+                name: format!("SKEL{:010}", skel.file_number),
+                chunk_count: skel.chunks.len(),
+                start_pos,
+                length: skel.skeleton.len(),
+            });
+            let mut cp = 0;
+            for (chunk, &marker) in skel.chunks.iter().zip(&skel.markers) {
+                chunk_table.push(ChunkEntry {
+                    insert_pos: start_pos + marker + cp,
+                    selector: chunk.selector.clone(),
+                    file_number: skel.file_number,
+                    sequence_number: num,
+                    start_pos: cp,
+                    length: chunk.raw.len(),
+                });
+                cp += chunk.raw.len();
+                num += 1;
+            }
+            start_pos += skel.len();
+        }
+
+        let rebuilt: Vec<u8> = skeletons.iter().flat_map(|s| s.rebuild()).collect();
+        let aid_offset_map = aid_offset_map(&rebuilt, &chunk_table);
+
+        // Internal links were written as placeholders of fixed size, so they
+        // can be filled in without moving anything
+        let fill = |raw: &[u8]| -> Vec<u8> {
+            PLACEHOLDER_PAT
+                .replace_all(raw, |caps: &Captures| {
+                    let key = String::from_utf8_lossy(&caps[0]).into_owned();
+                    let (fid, off) = placeholder_map
+                        .get(&key)
+                        .and_then(|aid| aid_offset_map.get(aid))
+                        .map(|(fid, off, _)| (*fid, *off))
+                        .unwrap_or((0, 0));
+                    format!(
+                        "kindle:pos:fid:{}:off:{}",
+                        to_base(fid as u64, 32, 4),
+                        to_base(off as u64, 32, 10)
+                    )
+                    .into_bytes()
+                })
+                .into_owned()
+        };
+        let mut text = Vec::with_capacity(rebuilt.len());
+        for skel in &skeletons {
+            text.extend_from_slice(&fill(&skel.skeleton));
+            for chunk in &skel.chunks {
+                text.extend_from_slice(&fill(&chunk.raw));
+            }
+        }
+
+        Chunker {
+            text,
+            skel_table,
+            chunk_table,
+            aid_offset_map,
+        }
+    }
+}
+
+fn aid_offset_map(
+    rebuilt: &[u8],
+    chunk_table: &[ChunkEntry],
+) -> HashMap<String, (usize, usize, usize)> {
+    let mut ans = HashMap::new();
+    for caps in AID_PAT.captures_iter(rebuilt) {
+        let offset = caps.get(0).map(|m| m.start()).unwrap_or(0);
+        let aid = String::from_utf8_lossy(&caps[1]).into_owned();
+        let mut pos_fid = None;
+        for (i, chunk) in chunk_table.iter().enumerate() {
+            if chunk.insert_pos <= offset && offset < chunk.insert_pos + chunk.length {
+                pos_fid = Some((chunk.sequence_number, offset - chunk.insert_pos, offset));
+                break;
+            }
+            if chunk.insert_pos > offset {
+                // The aid is in the skeleton, use the chunk after it
+                pos_fid = Some((chunk.sequence_number, 0, offset));
+                break;
+            }
+            if i == chunk_table.len() - 1 {
+                pos_fid = Some((chunk.sequence_number, offset - chunk.insert_pos, offset));
+            }
+        }
+        if let Some(pos_fid) = pos_fid {
+            ans.insert(aid, pos_fid);
+        }
+    }
+    ans
+}
@@ -0,0 +1,272 @@
+//! CSS values as the output writers need them: lengths in points, colours,
+//! generic font families, the display browsers give each tag and the
+//! character formatting of text runs.
+
+use crate::oeb::stylizer::{Style, Stylizer};
+
+/// The generic font family a CSS `font-family` list falls back to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FontFamily {
+    Serif,
+    SansSerif,
+    Monospace,
+}
+
+/// Point sizes of h1-h6.
+pub const HEADING_SIZES: [f32; 6] = [24.0, 18.0, 14.0, 12.0, 10.0, 8.0];
+
+/// Character formatting of a text run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CharStyle {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strike: bool,
+    pub superscript: bool,
+    pub subscript: bool,
+    pub family: FontFamily,
+    /// In points.
+    pub size: f32,
+    pub color: Option<(u8, u8, u8)>,
+}
+
+/// Convert a CSS length to points. Percentages are relative to `percent_of`.
+pub fn length(stylizer: &Stylizer, value: &str, em: f32, percent_of: f32) -> Option<f32> {
+    let v = value.trim().to_ascii_lowercase();
+    let split = v
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+        .unwrap_or(v.len());
+    let (num, unit) = v.split_at(split);
+    let n: f32 = num.parse().ok()?;
+    let pts = match unit.trim() {
+        "pt" => n,
+        "px" | "" => n * 72.0 / stylizer.dpi,
+        "em" => n * em,
+        "rem" => n * stylizer.font_base,
+        "ex" => n * em / 2.0,
+        "%" => n * percent_of / 100.0,
+        "in" => n * 72.0,
+        "cm" => n * 72.0 / 2.54,
+        "mm" => n * 72.0 / 25.4,
+        "pc" => n * 12.0,
+        _ => return None,
+    };
+    Some(pts)
+}
+
+/// A `font-size` in points, `parent` being the size of the parent element.
+pub fn font_size(stylizer: &Stylizer, value: &str, parent: f32) -> Option<f32> {
+    let base = stylizer.font_base;
+    let size = match value.trim() {
+        "xx-small" => base * 0.6,
+        "x-small" => base * 0.75,
+        "small" => base * 0.89,
+        "medium" | "initial" => base,
+        "large" => base * 1.2,
+        "x-large" => base * 1.5,
+        "xx-large" => base * 2.0,
+        "smaller" => parent * 0.83,
+        "larger" => parent * 1.2,
+        v => length(stylizer, v, parent, parent)?,
+    };
+    if size > 0.0 {
+        Some(size.min(300.0))
+    } else {
+        None
+    }
+}
+
+/// Whether any of `props` is declared inline with one of `values`.
+pub fn is_any(css: &Style, props: &[&str], values: &[&str]) -> bool {
+    props.iter().any(|p| {
+        css.get_inline_style(p)
+            .is_some_and(|v| values.contains(&v.trim()))
+    })
+}
+
+/// The `display` browsers give a tag by default.
+pub fn default_display(tag: &str) -> &'static str {
+    match tag {
+        "html" | "body" | "div" | "p" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "blockquote"
+        | "pre" | "ul" | "ol" | "dl" | "dt" | "dd" | "section" | "article" | "header"
+        | "footer" | "nav" | "aside" | "main" | "figure" | "figcaption" | "address" | "center"
+        | "caption" | "form" | "fieldset" | "legend" | "hgroup" => "block",
+        "li" => "list-item",
+        "table" => "table",
+        "tr" => "table-row",
+        "thead" => "table-header-group",
+        "tbody" => "table-row-group",
+        "tfoot" => "table-footer-group",
+        "td" | "th" => "table-cell",
+        _ => "inline",
+    }
+}
+
+/// The generic family of the first recognised name in a `font-family` list.
+pub fn parse_font_family(value: &str) -> Option<FontFamily> {
+    for name in value.split(',') {
+        let name = name
+            .trim()
+            .trim_matches(|c| c == '"' || c == '\'')
+            .to_ascii_lowercase();
+        if name.contains("mono") || name.contains("courier") || name.contains("consol") {
+            return Some(FontFamily::Monospace);
+        }
+        if name.contains("sans")
+            || name.contains("arial")
+            || name.contains("helvetica")
+            || name.contains("verdana")
+        {
+            return Some(FontFamily::SansSerif);
+        }
+        if name.contains("serif") || name.contains("times") || name.contains("georgia") {
+            return Some(FontFamily::Serif);
+        }
+    }
+    None
+}
+
+/// A CSS colour as red, green and blue between 0 and 1.
+pub fn parse_color(value: &str) -> Option<(f32, f32, f32)> {
+    let v = value.trim().to_ascii_lowercase();
+    if let Some(hex) = v.strip_prefix('#') {
+        let expand = |s: &str| u8::from_str_radix(s, 16).ok();
+        let (r, g, b) = match hex.len() {
+            3 => {
+                let c: Vec<String> = hex.chars().map(|c| format!("{}{}", c, c)).collect();
+                (expand(&c[0])?, expand(&c[1])?, expand(&c[2])?)
+            }
+            6 => (
+                expand(&hex[0..2])?,
+                expand(&hex[2..4])?,
+                expand(&hex[4..6])?,
+            ),
+            _ => return None,
+        };
+        return Some((r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0));
+    }
+    if let Some(args) = v
+        .strip_prefix("rgb(")
+        .or_else(|| v.strip_prefix("rgba("))
+        .and_then(|s| s.strip_suffix(')'))
+    {
+        let parts: Vec<f32> = args
+            .split(',')
+            .take(3)
+            .filter_map(|p| {
+                let p = p.trim();
+                match p.strip_suffix('%') {
+                    Some(pct) => pct.parse::<f32>().ok().map(|n| n * 2.55),
+                    None => p.parse::<f32>().ok(),
+                }
+            })
+            .collect();
+        if parts.len() == 3 {
+            return Some((
+                (parts[0] / 255.0).clamp(0.0, 1.0),
+                (parts[1] / 255.0).clamp(0.0, 1.0),
+                (parts[2] / 255.0).clamp(0.0, 1.0),
+            ));
+        }
+        return None;
+    }
+    let named = match v.as_str() {
+        "black" => (0, 0, 0),
+        "white" => (255, 255, 255),
+        "gray" | "grey" => (128, 128, 128),
+        "silver" => (192, 192, 192),
+        "red" => (255, 0, 0),
+        "maroon" => (128, 0, 0),
+        "green" => (0, 128, 0),
+        "lime" => (0, 255, 0),
+        "blue" => (0, 0, 255),
+        "navy" => (0, 0, 128),
+        "yellow" => (255, 255, 0),
+        "olive" => (128, 128, 0),
+        "purple" => (128, 0, 128),
+        "fuchsia" | "magenta" => (255, 0, 255),
+        "teal" => (0, 128, 128),
+        "aqua" | "cyan" => (0, 255, 255),
+        "orange" => (255, 165, 0),
+        _ => return None,
+    };
+    Some((
+        named.0 as f32 / 255.0,
+        named.1 as f32 / 255.0,
+        named.2 as f32 / 255.0,
+    ))
+}
+
+/// The character formatting of an element, from its tag and inline style.
+pub fn char_style(stylizer: &Stylizer, css: &Style, tag: &str, parent: &CharStyle) -> CharStyle {
+    let mut s = parent.clone();
+    match tag {
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            let level: usize = tag[1..].parse().unwrap_or(1);
+            s.size = HEADING_SIZES[level - 1];
+            s.bold = true;
+        }
+        "b" | "strong" | "th" | "dt" => s.bold = true,
+        "i" | "em" | "cite" | "var" | "dfn" | "address" => s.italic = true,
+        "u" | "ins" => s.underline = true,
+        "s" | "strike" | "del" => s.strike = true,
+        "code" | "tt" | "kbd" | "samp" | "pre" => s.family = FontFamily::Monospace,
+        "small" => s.size = parent.size * 0.83,
+        "big" => s.size = parent.size * 1.2,
+        "sup" => {
+            s.superscript = true;
+            s.subscript = false;
+        }
+        "sub" => {
+            s.subscript = true;
+            s.superscript = false;
+        }
+        _ => {}
+    }
+
+    if let Some(v) = css.get_inline_style("font-size") {
+        if let Some(size) = font_size(stylizer, &v, parent.size) {
+            s.size = size;
+        }
+    }
+    if let Some(v) = css.get_inline_style("font-weight") {
+        match v.trim() {
+            "bold" | "bolder" => s.bold = true,
+            "normal" | "lighter" => s.bold = false,
+            n => {
+                if let Ok(n) = n.parse::<u32>() {
+                    s.bold = n >= 600;
+                }
+            }
+        }
+    }
+    if let Some(v) = css.get_inline_style("font-style") {
+        s.italic = matches!(v.trim(), "italic" | "oblique");
+    }
+    if let Some(v) = css.get_inline_style("font-family") {
+        if let Some(family) = parse_font_family(&v) {
+            s.family = family;
+        }
+    }
+    if let Some(v) = css.get_inline_style("color") {
+        if let Some((r, g, b)) = parse_color(&v) {
+            s.color = Some((
+                (r * 255.0).round() as u8,
+                (g * 255.0).round() as u8,
+                (b * 255.0).round() as u8,
+            ));
+        }
+    }
+    if let Some(v) = css.get_inline_style("text-decoration") {
+        s.underline = v.contains("underline");
+        s.strike = v.contains("line-through");
+    }
+    if let Some(v) = css.get_inline_style("vertical-align") {
+        match v.trim() {
+            "super" => s.superscript = true,
+            "sub" => s.subscript = true,
+            _ => {}
+        }
+    }
+    s
+}
@@ -1,3 +1,5 @@
+use crate::metadata::MetaInformation;
+use chrono::{DateTime, NaiveDate, Utc};
 use std::collections::HashMap;
 
 #[derive(Debug, Clone)]
@@ -54,6 +56,90 @@ impl Metadata {
     pub fn get(&self, term: &str) -> Vec<&Item> {
         self.items.iter().filter(|i| i.term == term).collect()
     }
+
+    /// Value of the first item for `term`, if any.
+    pub fn first(&self, term: &str) -> Option<&str> {
+        self.items
+            .iter()
+            .find(|i| i.term == term)
+            .map(|i| i.value.as_str())
+            .filter(|v| !v.trim().is_empty())
+    }
+
+    /// Collapse the OPF style items into a `MetaInformation`, for writers
+    /// that embed metadata in a format specific way.
+    pub fn to_meta_information(&self) -> MetaInformation {
+        let mut mi = MetaInformation::default();
+        if let Some(title) = self.first("title") {
+            mi.title = title.trim().to_string();
+        }
+        let authors: Vec<String> = self
+            .get("creator")
+            .iter()
+            .map(|i| i.value.trim().to_string())
+            .filter(|a| !a.is_empty())
+            .collect();
+        if !authors.is_empty() {
+            mi.authors = authors;
+        }
+        mi.tags = self
+            .get("subject")
+            .iter()
+            .map(|i| i.value.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect();
+        mi.publisher = self.first("publisher").map(|s| s.trim().to_string());
+        mi.comments = self.first("description").map(|s| s.trim().to_string());
+        let languages: Vec<String> = self
+            .get("language")
+            .iter()
+            .map(|i| i.value.trim().to_string())
+            .filter(|l| !l.is_empty())
+            .collect();
+        if !languages.is_empty() {
+            mi.languages = languages;
+        }
+        mi.pubdate = self.first("date").and_then(parse_date);
+        mi.series = self.first("calibre:series").map(|s| s.trim().to_string());
+        if let Some(idx) = self
+            .first("calibre:series_index")
+            .and_then(|s| s.trim().parse().ok())
+        {
+            mi.series_index = idx;
+        }
+        for item in self.get("identifier") {
+            let scheme = item
+                .attrib
+                .iter()
+                .find(|(k, _)| k.ends_with("scheme"))
+                .map(|(_, v)| v.to_lowercase());
+            match scheme.as_deref() {
+                Some("uuid") => mi.uuid = Some(item.value.clone()),
+                Some(scheme) => mi.set_identifier(scheme, &item.value),
+                None if item.value.starts_with("urn:uuid:") => {
+                    mi.uuid = Some(item.value.trim_start_matches("urn:uuid:").to_string())
+                }
+                None => {}
+            }
+        }
+        mi
+    }
+}
+
+fn parse_date(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    let padded = match s.len() {
+        4 => format!("{}-01-01", s),
+        7 => format!("{}-01", s),
+        _ => s.chars().take(10).collect(),
+    };
+    NaiveDate::parse_from_str(&padded, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|dt| DateTime::from_naive_utc_and_offset(dt, Utc))
 }
 
 // Helper functions moved to parse_utils.rs
@@ -1,4 +1,10 @@
 use crate::oeb::constants::*;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+
+lazy_static! {
+    static ref ENTITY_PAT: Regex = Regex::new(r"&([A-Za-z][A-Za-z0-9]*);").unwrap();
+}
 
 /// Extract the local name from a Clark-notation string (e.g., `{ns}tag` -> `tag`).
 pub fn barename(name: &str) -> &str {
@@ -37,3 +43,51 @@ pub fn XHTML(name: &str) -> String {
 pub fn qualified_name(ns: &str, name: &str) -> String {
     format!("{{{}}}{}", ns, name)
 }
+
+/// Resolve `href` (as found in a document at `base_href`) to a container
+/// relative path. Fragments are dropped and `.`/`..` segments collapsed.
+pub fn abshref(base_href: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or("");
+    let href = urlencoding::decode(href)
+        .map(|s| s.into_owned())
+        .unwrap_or_else(|_| href.to_string());
+    if href.is_empty() {
+        return base_href.to_string();
+    }
+
+    let mut parts: Vec<&str> = Vec::new();
+    if !href.starts_with('/') {
+        if let Some(pos) = base_href.rfind('/') {
+            parts.extend(base_href[..pos].split('/'));
+        }
+    }
+    for seg in href.split('/') {
+        match seg {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(seg),
+        }
+    }
+    parts.retain(|p| !p.is_empty());
+    parts.join("/")
+}
+
+/// Replace HTML named entities, which an XML parser rejects, with numeric
+/// character references.
+pub fn xmlize_entities(html: &str) -> String {
+    ENTITY_PAT
+        .replace_all(html, |caps: &Captures| {
+            let name = &caps[1];
+            if matches!(name, "lt" | "gt" | "amp" | "quot" | "apos") {
+                return caps[0].to_string();
+            }
+            let decoded = html_escape::decode_html_entities(&caps[0]);
+            if decoded == caps[0] {
+                return caps[0].to_string();
+            }
+            decoded.chars().map(|c| format!("&#{};", c as u32)).collect()
+        })
+        .into_owned()
+}
@@ -1,7 +1,10 @@
 use crate::oeb::book::OEBBook;
 use crate::oeb::constants::*;
+use crate::oeb::parse_utils::abshref;
+use crate::oeb::toc::TOCNode;
 use anyhow::{bail, Result};
 use roxmltree::Document;
+use std::collections::HashMap;
 
 pub struct OEBReader;
 
@@ -27,6 +30,7 @@ impl OEBReader {
         self.manifest_from_opf(book, &root)?;
         self.spine_from_opf(book, &root)?;
         self.guide_from_opf(book, &root)?;
+        self.toc_from_ncx(book, &root);
 
         Ok(())
     }
@@ -46,7 +50,17 @@ impl OEBReader {
                 // Dublin Core check: Namespace is DC11_NS or tag starts with dc: (if ns parsing failed or different)
                 // roxmltree handles standard namespaces well.
                 if ns == DC11_NS {
-                    book.metadata.add(tag_name, text);
+                    let attrib: HashMap<String, String> = child
+                        .attributes()
+                        .map(|a| {
+                            let name = match a.namespace() {
+                                Some(OPF2_NS) => format!("opf:{}", a.name()),
+                                _ => a.name().to_string(),
+                            };
+                            (name, a.value().to_string())
+                        })
+                        .collect();
+                    book.metadata.add_with_attrib(tag_name, text, attrib);
                 } else if tag_name == "meta" {
                     // Handle <meta name="..." content="...">
                     if let Some(name) = child.attribute("name") {
@@ -119,4 +133,40 @@ impl OEBReader {
         }
         Ok(())
     }
+
+    /// Read the TOC from the NCX named by the spine. A missing or broken NCX
+    /// leaves the TOC empty.
+    fn toc_from_ncx(&self, book: &mut OEBBook, root: &roxmltree::Node) {
+        let href = root
+            .children()
+            .find(|n| n.is_element() && n.tag_name().name() == "spine")
+            .and_then(|spine| spine.attribute("toc"))
+            .and_then(|id| book.manifest.get_by_id(id))
+            .map(|item| item.href.clone());
+        let raw = match href.as_ref().and_then(|h| book.container.read(h).ok()) {
+            Some(raw) => raw,
+            None => return,
+        };
+        let toc = match crate::metadata::toc::TOC::parse_ncx(&String::from_utf8_lossy(&raw)) {
+            Ok(toc) => toc,
+            Err(_) => return,
+        };
+
+        // NCX links are relative to the NCX itself
+        fn convert(node: &crate::metadata::toc::TOCNode, ncx_href: &str) -> TOCNode {
+            let href = match node.src.split_once('#') {
+                Some((path, frag)) => format!("{}#{}", abshref(ncx_href, path), frag),
+                None => abshref(ncx_href, &node.src),
+            };
+            let mut ans = TOCNode::new(Some(node.title.clone()), Some(href));
+            for child in &node.children {
+                ans.add(convert(child, ncx_href));
+            }
+            ans
+        }
+        let ncx_href = href.unwrap_or_default();
+        for node in &toc.nodes {
+            book.toc.root.add(convert(node, &ncx_href));
+        }
+    }
 }
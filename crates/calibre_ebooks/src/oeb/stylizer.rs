@@ -2,6 +2,7 @@ use crate::oeb::normalize_css::DEFAULTS;
 use roxmltree::Node;
 use std::collections::HashMap;
 
+#[derive(Debug, Clone, Copy)]
 pub struct Stylizer {
     pub dpi: f32,
     pub font_base: f32, // in pts
@@ -56,7 +57,8 @@ impl<'a, 'input, 'b> Style<'a, 'input, 'b> {
         DEFAULTS.get(property).cloned().unwrap_or("").to_string()
     }
 
-    fn get_inline_style(&self, property: &str) -> Option<String> {
+    /// Value declared in this element's own `style` attribute, if any.
+    pub fn get_inline_style(&self, property: &str) -> Option<String> {
         if let Some(style_attr) = self.node.attribute("style") {
             // Simple CSS parser: split by ; then :
             for decl in style_attr.split(';') {
@@ -2,9 +2,32 @@ use crate::oeb::book::OEBBook;
 use crate::oeb::constants::*;
 use crate::oeb::container::{Container, DirContainer};
 use crate::oeb::parse_utils::escape_xml;
+use crate::oeb::toc::TOCNode;
 use anyhow::Result;
 use std::path::Path;
 
+/// Dublin Core terms, which are written as `dc:` elements.
+const DC_TERMS: &[&str] = &[
+    "title",
+    "creator",
+    "subject",
+    "description",
+    "publisher",
+    "contributor",
+    "date",
+    "type",
+    "format",
+    "identifier",
+    "source",
+    "language",
+    "relation",
+    "coverage",
+    "rights",
+];
+
+/// Href of the NCX written for books that do not have one.
+const NCX_HREF: &str = "toc.ncx";
+
 pub struct OEBWriter {
     pub pretty_print: bool,
 }
@@ -43,7 +66,12 @@ impl OEBWriter {
             }
         }
 
-        // 2. Generate and Write OPF
+        // 2. Generate the NCX from the TOC when the book has none
+        if let Some((_, true)) = self.ncx_item(book) {
+            container.write(NCX_HREF, self.write_ncx(book).as_bytes())?;
+        }
+
+        // 3. Generate and Write OPF
         let opf_content = self.write_opf(book)?;
         container.write("content.opf", opf_content.as_bytes())?;
 
@@ -59,7 +87,8 @@ impl OEBWriter {
         // Metadata
         out.push_str("  <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:opf=\"http://www.idpf.org/2007/opf\">\n");
         for item in &book.metadata.items {
-            if item.term.starts_with("dc:") || item.term.starts_with('{') {
+            let is_dc = DC_TERMS.contains(&item.term.as_str());
+            if item.term.starts_with("dc:") || item.term.starts_with('{') || is_dc {
                 // naive sanity check, already namespaced normally
                 // Dublin core elements usually don't have attributes in basic use,
                 // but OEB defines roles etc. My MetadataItem struct needs to be checked.
@@ -68,6 +97,8 @@ impl OEBWriter {
                     // resolve namespace logic later if needed, for now assume simple storage
                     // But wait, my reader sanitized names.
                     item.term.clone() // Placeholder, ideally specific handling
+                } else if is_dc {
+                    format!("dc:{}", item.term)
                 } else {
                     item.term.clone()
                 };
@@ -100,6 +131,7 @@ impl OEBWriter {
         out.push_str("  </metadata>\n");
 
         // Manifest
+        let ncx = self.ncx_item(book);
         out.push_str("  <manifest>\n");
         for item in book.manifest.items.values() {
             out.push_str(&format!(
@@ -109,10 +141,21 @@ impl OEBWriter {
                 escape_xml(&item.media_type)
             ));
         }
+        if let Some((id, true)) = &ncx {
+            out.push_str(&format!(
+                "    <item id=\"{}\" href=\"{}\" media-type=\"{}\" />\n",
+                escape_xml(id),
+                NCX_HREF,
+                NCX_MIME
+            ));
+        }
         out.push_str("  </manifest>\n");
 
         // Spine
-        out.push_str("  <spine>\n"); // toc attribute?
+        match &ncx {
+            Some((id, _)) => out.push_str(&format!("  <spine toc=\"{}\">\n", escape_xml(id))),
+            None => out.push_str("  <spine>\n"),
+        }
         for item in &book.spine.items {
             let linear = if item.linear { "yes" } else { "no" };
             out.push_str(&format!(
@@ -141,4 +184,86 @@ impl OEBWriter {
         out.push_str("</package>");
         Ok(out)
     }
+
+    /// The id of the NCX of `book`, and whether it has to be generated from
+    /// the TOC.
+    fn ncx_item(&self, book: &OEBBook) -> Option<(String, bool)> {
+        if let Some(item) = book
+            .manifest
+            .items
+            .values()
+            .find(|i| i.media_type == NCX_MIME)
+        {
+            return Some((item.id.clone(), false));
+        }
+        if book.toc.root.children.is_empty() {
+            return None;
+        }
+        let mut id = "ncx".to_string();
+        while book.manifest.items.contains_key(&id) {
+            id.push('_');
+        }
+        Some((id, true))
+    }
+
+    pub fn write_ncx(&self, book: &OEBBook) -> String {
+        fn depth(node: &TOCNode) -> usize {
+            node.children
+                .iter()
+                .map(|c| depth(c) + 1)
+                .max()
+                .unwrap_or(0)
+        }
+        fn write_points(out: &mut String, nodes: &[TOCNode], order: &mut usize, indent: usize) {
+            for node in nodes {
+                *order += 1;
+                let pad = "  ".repeat(indent);
+                out.push_str(&format!(
+                    "{}<navPoint id=\"navPoint-{}\" playOrder=\"{}\">\n",
+                    pad, order, order
+                ));
+                out.push_str(&format!(
+                    "{}  <navLabel><text>{}</text></navLabel>\n",
+                    pad,
+                    escape_xml(node.title.as_deref().unwrap_or(""))
+                ));
+                out.push_str(&format!(
+                    "{}  <content src=\"{}\" />\n",
+                    pad,
+                    escape_xml(node.href.as_deref().unwrap_or(""))
+                ));
+                write_points(out, &node.children, order, indent + 1);
+                out.push_str(&format!("{}</navPoint>\n", pad));
+            }
+        }
+
+        let uid = book
+            .uid
+            .as_deref()
+            .or_else(|| book.metadata.first("identifier"))
+            .unwrap_or("");
+        let title = book.metadata.first("title").unwrap_or("Unknown");
+        let mut out = String::new();
+        out.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        out.push_str(&format!("<ncx xmlns=\"{}\" version=\"2005-1\">\n", NCX_NS));
+        out.push_str("  <head>\n");
+        out.push_str(&format!(
+            "    <meta name=\"dtb:uid\" content=\"{}\" />\n",
+            escape_xml(uid)
+        ));
+        out.push_str(&format!(
+            "    <meta name=\"dtb:depth\" content=\"{}\" />\n",
+            depth(&book.toc.root)
+        ));
+        out.push_str("  </head>\n");
+        out.push_str(&format!(
+            "  <docTitle><text>{}</text></docTitle>\n",
+            escape_xml(title)
+        ));
+        out.push_str("  <navMap>\n");
+        write_points(&mut out, &book.toc.root.children, &mut 0, 2);
+        out.push_str("  </navMap>\n");
+        out.push_str("</ncx>");
+        out
+    }
 }
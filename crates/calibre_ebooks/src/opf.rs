@@ -157,6 +157,20 @@ pub fn parse_opf(xml_content: &str) -> Result<MetaInformation, OpfError> {
                     }
                 } else if text.starts_with("urn:uuid:") {
                     meta.uuid = Some(text.replace("urn:uuid:", ""));
+                } else if let Some(isbn) = text.strip_prefix("urn:isbn:") {
+                    meta.identifiers
+                        .insert("isbn".to_string(), isbn.to_string());
+                } else if let Some((scheme, value)) = text.split_once(':') {
+                    // OPF 3 has no scheme attribute, identifiers are written
+                    // as scheme:value
+                    let is_scheme = !scheme.is_empty()
+                        && scheme
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+                    if is_scheme && !matches!(scheme, "http" | "https" | "urn") {
+                        meta.identifiers
+                            .insert(scheme.to_lowercase(), value.to_string());
+                    }
                 }
             }
             "publisher" => {
@@ -0,0 +1,50 @@
+use crate::conversion::registry::OutputFormatPlugin;
+use crate::mobi::writer::{MobiFileType, MobiWriter};
+use crate::oeb::book::OEBBook;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Writes KF8 only books, the format Kindle delivery expects.
+pub struct AZW3Output;
+
+impl Default for AZW3Output {
+    fn default() -> Self {
+        AZW3Output::new()
+    }
+}
+
+impl AZW3Output {
+    pub fn new() -> Self {
+        AZW3Output
+    }
+
+    pub fn convert(&self, book: &OEBBook, output_path: &Path) -> Result<()> {
+        let file = File::create(output_path).context("Failed to create output AZW3 file")?;
+        let mut writer = BufWriter::new(file);
+
+        MobiWriter::with_file_type(MobiFileType::New).write(book, &mut writer)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl OutputFormatPlugin for AZW3Output {
+    fn name(&self) -> &str {
+        "AZW3 Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["azw3"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/vnd.amazon.mobi8-ebook"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        AZW3Output::convert(self, book, output_path)
+    }
+}
@@ -1,10 +1,9 @@
+use crate::conversion::registry::OutputFormatPlugin;
+use crate::docx::writer::DocxWriter;
 use crate::oeb::book::OEBBook;
 use anyhow::{Context, Result};
 use std::fs::File;
-use std::io::Write;
 use std::path::Path;
-use zip::write::FileOptions;
-use zip::ZipWriter;
 
 pub struct DOCXOutput;
 
@@ -14,61 +13,43 @@ impl DOCXOutput {
     }
 
     pub fn convert(&self, book: &OEBBook, output_path: &Path) -> Result<()> {
-        let file = File::create(output_path).context("Failed to create DOCX file")?;
-        let mut zip = ZipWriter::new(file);
-        let options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
-
-        // 1. [Content_Types].xml
-        zip.start_file("[Content_Types].xml", options)?;
-        zip.write_all(br#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
-<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
-  <Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
-  <Default Extension="xml" ContentType="application/xml"/>
-  <Override PartName="/word/document.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml"/>
-</Types>"#)?;
+        let mut writer = DocxWriter::new(book.container.as_ref());
+
+        // Each spine document starts on a new page
+        for itemref in &book.spine.items {
+            let item = match book.manifest.items.get(&itemref.idref) {
+                Some(item) => item,
+                None => continue,
+            };
+            if item.media_type.starts_with("image/") {
+                continue;
+            }
+            if let Ok(data) = book.container.read(&item.href) {
+                let html = String::from_utf8_lossy(&data);
+                writer.add_document(&item.href, &html);
+            }
+        }
+        writer.set_toc(&book.toc);
 
-        // 2. _rels/.rels
-        zip.start_file("_rels/.rels", options)?;
-        zip.write_all(br#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
-<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
-  <Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="word/document.xml"/>
-</Relationships>"#)?;
+        let file = File::create(output_path).context("Failed to create DOCX file")?;
+        writer.finish(&book.metadata.to_meta_information(), file)
+    }
+}
 
-        // 3. word/document.xml
-        // We will generate a basic document with the book title and simple text logic.
-        // Full conversion is out of scope.
-        let title = book
-            .metadata
-            .get("title")
-            .first()
-            .map(|i| i.value.as_str())
-            .unwrap_or("Untitled Book");
+impl OutputFormatPlugin for DOCXOutput {
+    fn name(&self) -> &str {
+        "DOCX Output"
+    }
 
-        zip.start_file("word/document.xml", options)?;
-        write!(
-            zip,
-            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
-<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main">
-  <w:body>
-    <w:p>
-      <w:pPr><w:pStyle w:val="Title"/></w:pPr>
-      <w:r><w:t>{}</w:t></w:r>
-    </w:p>
-    <w:p><w:r><w:t>Converted content pending full implementation.</w:t></w:r></w:p>
-  </w:body>
-</w:document>"#,
-            xml_escape(title)
-        )?;
+    fn file_types(&self) -> &[&str] {
+        &["docx"]
+    }
 
-        zip.finish()?;
-        Ok(())
+    fn mime_types(&self) -> &[&str] {
+        &["application/vnd.openxmlformats-officedocument.wordprocessingml.document"]
     }
-}
 
-fn xml_escape(s: &str) -> String {
-    s.replace("&", "&amp;")
-        .replace("<", "&lt;")
-        .replace(">", "&gt;")
-        .replace("\"", "&quot;")
-        .replace("'", "&apos;")
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        DOCXOutput::convert(self, book, output_path)
+    }
 }
@@ -1,3 +1,4 @@
+use crate::conversion::registry::OutputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::writer::OEBWriter;
 use anyhow::{Context, Result};
@@ -90,3 +91,21 @@ impl EPUBOutput {
         Ok(())
     }
 }
+
+impl OutputFormatPlugin for EPUBOutput {
+    fn name(&self) -> &str {
+        "EPUB Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["epub"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/epub+zip"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        EPUBOutput::convert(self, book, output_path)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::conversion::registry::OutputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use anyhow::{Context, Result};
 use base64::Engine;
@@ -176,3 +177,21 @@ impl FB2Output {
         result.to_string()
     }
 }
+
+impl OutputFormatPlugin for FB2Output {
+    fn name(&self) -> &str {
+        "FB2 Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["fb2"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-fictionbook+xml"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        FB2Output::convert(self, book, output_path)
+    }
+}
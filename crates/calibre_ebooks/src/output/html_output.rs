@@ -1,3 +1,4 @@
+use crate::conversion::registry::OutputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::writer::OEBWriter;
 use anyhow::{Context, Result};
@@ -27,3 +28,21 @@ impl HTMLOutput {
         Ok(())
     }
 }
+
+impl OutputFormatPlugin for HTMLOutput {
+    fn name(&self) -> &str {
+        "HTML Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["html"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["text/html"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        HTMLOutput::convert(self, book, output_path)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::conversion::registry::OutputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::writer::OEBWriter;
 use anyhow::{Context, Result};
@@ -58,3 +59,21 @@ impl HTMLZOutput {
         Ok(())
     }
 }
+
+impl OutputFormatPlugin for HTMLZOutput {
+    fn name(&self) -> &str {
+        "HTMLZ Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["htmlz"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-htmlz"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        HTMLZOutput::convert(self, book, output_path)
+    }
+}
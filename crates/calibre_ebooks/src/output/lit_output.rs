@@ -1,3 +1,4 @@
+use crate::conversion::registry::OutputFormatPlugin;
 use crate::lit::writer::LitWriter;
 use crate::oeb::book::OEBBook;
 use anyhow::Result;
@@ -23,3 +24,21 @@ impl LitOutput {
         Ok(())
     }
 }
+
+impl OutputFormatPlugin for LitOutput {
+    fn name(&self) -> &str {
+        "LIT Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["lit"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-ms-reader"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        LitOutput::convert(self, book, output_path)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::conversion::registry::OutputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use anyhow::{Context, Result};
 use std::path::Path;
@@ -19,3 +20,21 @@ impl LRFOutput {
         Ok(())
     }
 }
+
+impl OutputFormatPlugin for LRFOutput {
+    fn name(&self) -> &str {
+        "LRF Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["lrf"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-sony-bbeb"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        LRFOutput::convert(self, book, output_path)
+    }
+}
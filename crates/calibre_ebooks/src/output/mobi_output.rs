@@ -1,24 +1,59 @@
-use crate::mobi::writer::MobiWriter;
+use crate::conversion::registry::OutputFormatPlugin;
+use crate::mobi::writer::{MobiFileType, MobiWriter};
 use crate::oeb::book::OEBBook;
 use anyhow::{Context, Result};
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufWriter, Write};
 use std::path::Path;
 
-pub struct MOBIOutput;
+pub struct MOBIOutput {
+    pub file_type: MobiFileType,
+}
+
+impl Default for MOBIOutput {
+    fn default() -> Self {
+        MOBIOutput::new()
+    }
+}
 
 impl MOBIOutput {
     pub fn new() -> Self {
-        MOBIOutput
+        MOBIOutput {
+            file_type: MobiFileType::Old,
+        }
+    }
+
+    /// `MobiFileType::Both` adds a KF8 copy of the book for newer Kindles.
+    pub fn with_file_type(file_type: MobiFileType) -> Self {
+        MOBIOutput { file_type }
     }
 
     pub fn convert(&self, book: &OEBBook, output_path: &Path) -> Result<()> {
         let file = File::create(output_path).context("Failed to create output MOBI file")?;
         let mut writer = BufWriter::new(file);
 
-        let mobi_writer = MobiWriter::new();
+        let mobi_writer = MobiWriter::with_file_type(self.file_type);
         mobi_writer.write(book, &mut writer)?;
+        writer.flush()?;
 
         Ok(())
     }
 }
+
+impl OutputFormatPlugin for MOBIOutput {
+    fn name(&self) -> &str {
+        "MOBI Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["mobi", "azw", "prc"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-mobipocket-ebook"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        MOBIOutput::convert(self, book, output_path)
+    }
+}
@@ -1,3 +1,4 @@
+pub mod azw3_output;
 pub mod docx_output;
 pub mod epub_output;
 pub mod fb2_output;
@@ -1,3 +1,4 @@
+use crate::conversion::registry::OutputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use anyhow::{Context, Result};
 use calibre_utils::html2text::html2text;
@@ -75,3 +76,21 @@ impl ODTOutput {
         Ok(())
     }
 }
+
+impl OutputFormatPlugin for ODTOutput {
+    fn name(&self) -> &str {
+        "ODT Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["odt"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/vnd.oasis.opendocument.text"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        ODTOutput::convert(self, book, output_path)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::conversion::registry::OutputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::oeb::writer::OEBWriter;
 use anyhow::{Context, Result};
@@ -36,3 +37,21 @@ impl OEBOutput {
         Ok(())
     }
 }
+
+impl OutputFormatPlugin for OEBOutput {
+    fn name(&self) -> &str {
+        "OEB Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["oeb"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/oebps-package+xml"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        OEBOutput::convert(self, book, output_path)
+    }
+}
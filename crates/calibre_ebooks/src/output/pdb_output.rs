@@ -1,3 +1,4 @@
+use crate::conversion::registry::OutputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::pdb::writer::PdbWriter;
 use anyhow::{Context, Result};
@@ -33,3 +34,21 @@ impl PDBOutput {
         Ok(())
     }
 }
+
+impl OutputFormatPlugin for PDBOutput {
+    fn name(&self) -> &str {
+        "PDB Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["pdb"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/vnd.palm"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        PDBOutput::convert(self, book, output_path)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::conversion::registry::OutputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::pdb::writer::PdbWriter;
 use anyhow::Result;
@@ -128,3 +129,21 @@ impl PMLOutput {
         Ok(())
     }
 }
+
+impl OutputFormatPlugin for PMLOutput {
+    fn name(&self) -> &str {
+        "PML Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["pml"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["text/x-pml"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        PMLOutput::convert(self, book, output_path)
+    }
+}
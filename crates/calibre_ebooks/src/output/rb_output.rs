@@ -1,3 +1,4 @@
+use crate::conversion::registry::OutputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::rb::writer::RbWriter;
 use anyhow::Result;
@@ -56,3 +57,21 @@ impl RBOutput {
         Ok(())
     }
 }
+
+impl OutputFormatPlugin for RBOutput {
+    fn name(&self) -> &str {
+        "RB Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["rb"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-rocketbook"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        RBOutput::convert(self, book, output_path)
+    }
+}
@@ -1,8 +1,8 @@
+use crate::conversion::registry::OutputFormatPlugin;
 use crate::oeb::book::OEBBook;
+use crate::rtf::writer::RtfWriter;
 use anyhow::{Context, Result};
-use calibre_utils::html2text::html2text;
-use std::fs::File;
-use std::io::Write;
+use std::fs;
 use std::path::Path;
 
 pub struct RTFOutput;
@@ -13,37 +13,44 @@ impl RTFOutput {
     }
 
     pub fn convert(&self, book: &OEBBook, output_path: &Path) -> Result<()> {
-        let mut file = File::create(output_path).context("Failed to create RTF file")?;
+        let mut writer = RtfWriter::new(book.container.as_ref());
 
-        // Write RTF Header
-        writeln!(file, "{{\\rtf1\\ansi\\deff0")?;
-        writeln!(file, "{{\\fonttbl{{\\f0 Arial;}}}}")?;
-        writeln!(file, "\\f0\\fs24")?; // Font 0, Size 12pt (24 half-points)
-
-        // Iterate content
+        // Each spine document starts on a new page
         for itemref in &book.spine.items {
-            if let Some(item) = book.manifest.items.get(&itemref.idref) {
-                if let Ok(data) = book.container.read(&item.href) {
-                    let text_content = String::from_utf8_lossy(&data);
-                    // convert html to text first
-                    let plain_text = html2text(&text_content);
-
-                    // Escape for RTF
-                    let rtf_text = self.escape_rtf(&plain_text);
-                    writeln!(file, "{} \\par", rtf_text)?;
-                }
+            let item = match book.manifest.items.get(&itemref.idref) {
+                Some(item) => item,
+                None => continue,
+            };
+            if item.media_type.starts_with("image/") {
+                continue;
+            }
+            if let Ok(data) = book.container.read(&item.href) {
+                let html = String::from_utf8_lossy(&data);
+                writer.add_document(&item.href, &html);
             }
         }
 
-        writeln!(file, "}}")?; // Close RTF
+        let rtf = writer.finish(&book.metadata.to_meta_information());
+        fs::write(output_path, rtf).context("Failed to create RTF file")?;
 
         Ok(())
     }
+}
+
+impl OutputFormatPlugin for RTFOutput {
+    fn name(&self) -> &str {
+        "RTF Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["rtf"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/rtf", "text/rtf"]
+    }
 
-    fn escape_rtf(&self, text: &str) -> String {
-        text.replace('\\', "\\\\")
-            .replace('{', "\\{")
-            .replace('}', "\\}")
-            .replace('\n', "\\par\n")
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        RTFOutput::convert(self, book, output_path)
     }
 }
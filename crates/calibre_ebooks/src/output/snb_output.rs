@@ -1,3 +1,4 @@
+use crate::conversion::registry::OutputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use crate::snb::writer::SnbWriter;
 use anyhow::{Context, Result};
@@ -25,3 +26,21 @@ impl SnbOutput {
         Ok(())
     }
 }
+
+impl OutputFormatPlugin for SnbOutput {
+    fn name(&self) -> &str {
+        "SNB Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["snb"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-snb"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        SnbOutput::convert(self, book, output_path)
+    }
+}
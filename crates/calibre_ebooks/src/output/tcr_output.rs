@@ -1,3 +1,4 @@
+use crate::conversion::registry::OutputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use anyhow::{Context, Result};
 use calibre_utils::html2text::html2text;
@@ -29,3 +30,21 @@ impl TCROutput {
         Ok(())
     }
 }
+
+impl OutputFormatPlugin for TCROutput {
+    fn name(&self) -> &str {
+        "TCR Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["tcr"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-tcr"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        TCROutput::convert(self, book, output_path)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::conversion::registry::OutputFormatPlugin;
 use crate::oeb::book::OEBBook;
 use anyhow::{Context, Result};
 use calibre_utils::html2text::html2text;
@@ -35,3 +36,21 @@ impl TXTOutput {
         Ok(())
     }
 }
+
+impl OutputFormatPlugin for TXTOutput {
+    fn name(&self) -> &str {
+        "TXT Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["txt", "text", "md", "markdown"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["text/plain", "text/markdown"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        TXTOutput::convert(self, book, output_path)
+    }
+}
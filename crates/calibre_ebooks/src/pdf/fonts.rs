@@ -0,0 +1,419 @@
+//! Metrics for the standard 14 PDF fonts, and the TrueType fonts embedded
+//! for text they cannot show.
+//!
+//! Every conforming viewer ships the standard fonts, so text that fits
+//! WinAnsiEncoding is drawn with them and nothing has to be embedded. A run
+//! with any other character is drawn with an installed TrueType face of the
+//! same style, embedded as a Type0 font. Characters that no font covers are
+//! replaced.
+
+pub use crate::oeb::css::FontFamily;
+use ab_glyph::{Font, FontVec, GlyphId};
+use fontdb::{Database, Family, Query, Style, Weight};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+lazy_static! {
+    /// The installed fonts, and those in the `fonts` resources directory.
+    pub static ref FONT_DB: Database = {
+        let mut db = Database::new();
+        db.load_system_fonts();
+        if let Some(dir) = calibre_utils::resources::get_path("fonts", true) {
+            db.load_fonts_dir(dir);
+        }
+        db
+    };
+    static ref EMBEDDED_FONTS: Mutex<HashMap<FontKey, Option<Arc<EmbeddedFont>>>> =
+        Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FontKey {
+    pub family: FontFamily,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+impl FontKey {
+    pub fn new(family: FontFamily, bold: bool, italic: bool) -> Self {
+        FontKey {
+            family,
+            bold,
+            italic,
+        }
+    }
+
+    /// PostScript name of the matching standard font.
+    pub fn base_font(&self) -> &'static str {
+        match (self.family, self.bold, self.italic) {
+            (FontFamily::Serif, false, false) => "Times-Roman",
+            (FontFamily::Serif, true, false) => "Times-Bold",
+            (FontFamily::Serif, false, true) => "Times-Italic",
+            (FontFamily::Serif, true, true) => "Times-BoldItalic",
+            (FontFamily::SansSerif, false, false) => "Helvetica",
+            (FontFamily::SansSerif, true, false) => "Helvetica-Bold",
+            (FontFamily::SansSerif, false, true) => "Helvetica-Oblique",
+            (FontFamily::SansSerif, true, true) => "Helvetica-BoldOblique",
+            (FontFamily::Monospace, false, false) => "Courier",
+            (FontFamily::Monospace, true, false) => "Courier-Bold",
+            (FontFamily::Monospace, false, true) => "Courier-Oblique",
+            (FontFamily::Monospace, true, true) => "Courier-BoldOblique",
+        }
+    }
+
+    fn index(&self) -> u8 {
+        let family = match self.family {
+            FontFamily::Serif => 0,
+            FontFamily::SansSerif => 1,
+            FontFamily::Monospace => 2,
+        };
+        family * 4 + (self.bold as u8) * 2 + (self.italic as u8) + 1
+    }
+
+    /// Resource name used in page content streams, stable per key.
+    pub fn resource_name(&self) -> String {
+        format!("F{}", self.index())
+    }
+
+    /// Resource name of the embedded font of this style.
+    pub fn embedded_resource_name(&self) -> String {
+        format!("E{}", self.index())
+    }
+
+    /// The installed face embedded for this style, loaded on first use.
+    pub fn embedded(&self) -> Option<Arc<EmbeddedFont>> {
+        let mut fonts = EMBEDDED_FONTS.lock().unwrap_or_else(|e| e.into_inner());
+        fonts
+            .entry(*self)
+            .or_insert_with(|| EmbeddedFont::load(*self).map(Arc::new))
+            .clone()
+    }
+
+    /// The embedded font to draw `text` with, `None` when the standard font
+    /// can show all of it or no suitable face is installed.
+    pub fn embedded_for(&self, text: &str) -> Option<Arc<EmbeddedFont>> {
+        if text.chars().all(|c| win_ansi_code(c).is_some()) {
+            return None;
+        }
+        self.embedded()
+    }
+
+    fn widths(&self) -> Option<&'static [u16; 95]> {
+        match (self.family, self.bold, self.italic) {
+            (FontFamily::Serif, false, false) => Some(&TIMES_ROMAN),
+            (FontFamily::Serif, true, false) => Some(&TIMES_BOLD),
+            (FontFamily::Serif, false, true) => Some(&TIMES_ITALIC),
+            (FontFamily::Serif, true, true) => Some(&TIMES_BOLD_ITALIC),
+            // The obliques share the metrics of the upright faces
+            (FontFamily::SansSerif, false, _) => Some(&HELVETICA),
+            (FontFamily::SansSerif, true, _) => Some(&HELVETICA_BOLD),
+            (FontFamily::Monospace, _, _) => None,
+        }
+    }
+
+    /// Advance width of a WinAnsi code in 1/1000 em.
+    pub fn code_width(&self, code: u8) -> u16 {
+        let widths = match self.widths() {
+            Some(w) => w,
+            None => return 600,
+        };
+        let (ch, scale) = match code {
+            32..=126 => (code, 1.0),
+            _ => fallback_glyph(code),
+        };
+        (widths[(ch - 32) as usize] as f32 * scale) as u16
+    }
+
+    /// Width of `text` in points at `size`, in the font it is drawn with.
+    pub fn text_width(&self, text: &str, size: f32) -> f32 {
+        let units: u32 = match self.embedded_for(text) {
+            Some(font) => text
+                .chars()
+                .map(|c| font.glyph_width(font.glyph_id(c)) as u32)
+                .sum(),
+            None => text
+                .chars()
+                .map(|c| self.code_width(encode_char(c)) as u32)
+                .sum(),
+        };
+        units as f32 * size / 1000.0
+    }
+}
+
+/// An installed TrueType face, embedded whole in the document.
+pub struct EmbeddedFont {
+    font: FontVec,
+    /// PostScript name of the face, used as the base font name
+    pub name: String,
+}
+
+impl EmbeddedFont {
+    /// Find an installed face for `key`. Only plain TrueType files can be
+    /// embedded as a font program, collections and CFF fonts are skipped.
+    fn load(key: FontKey) -> Option<EmbeddedFont> {
+        let families: &[&str] = match key.family {
+            FontFamily::Serif => &[
+                "DejaVu Serif",
+                "Liberation Serif",
+                "Noto Serif",
+                "FreeSerif",
+            ],
+            FontFamily::SansSerif => &["DejaVu Sans", "Liberation Sans", "Noto Sans", "FreeSans"],
+            FontFamily::Monospace => &[
+                "DejaVu Sans Mono",
+                "Liberation Mono",
+                "Noto Sans Mono",
+                "FreeMono",
+            ],
+        };
+        let generic = match key.family {
+            FontFamily::Serif => Family::Serif,
+            FontFamily::SansSerif => Family::SansSerif,
+            FontFamily::Monospace => Family::Monospace,
+        };
+        let candidates = std::iter::once(generic).chain(families.iter().map(|f| Family::Name(f)));
+        for family in candidates {
+            let query = Query {
+                families: &[family],
+                weight: if key.bold {
+                    Weight::BOLD
+                } else {
+                    Weight::NORMAL
+                },
+                style: if key.italic {
+                    Style::Italic
+                } else {
+                    Style::Normal
+                },
+                ..Query::default()
+            };
+            let Some(id) = FONT_DB.query(&query) else {
+                continue;
+            };
+            let font = FONT_DB.with_face_data(id, |data, index| {
+                let truetype = data.starts_with(&[0, 1, 0, 0]) || data.starts_with(b"true");
+                if index != 0 || !truetype {
+                    return None;
+                }
+                FontVec::try_from_vec(data.to_vec()).ok()
+            });
+            if let Some(font) = font.flatten() {
+                let name: String = FONT_DB
+                    .face(id)
+                    .map(|f| f.post_script_name.as_str())
+                    .unwrap_or_default()
+                    .chars()
+                    .filter(|c| c.is_ascii_graphic() && !"()<>[]{}/%#".contains(*c))
+                    .collect();
+                return Some(EmbeddedFont {
+                    font,
+                    name: if name.is_empty() {
+                        "EmbeddedFont".to_string()
+                    } else {
+                        name
+                    },
+                });
+            }
+        }
+        None
+    }
+
+    /// Glyph drawn for `c`, 0 (.notdef) when the face does not cover it.
+    pub fn glyph_id(&self, c: char) -> u16 {
+        let c = match c {
+            '\t' | '\n' | '\r' => ' ',
+            c => c,
+        };
+        self.font.glyph_id(c).0
+    }
+
+    /// Advance width of a glyph in 1/1000 em.
+    pub fn glyph_width(&self, glyph: u16) -> u16 {
+        let width = self.font.h_advance_unscaled(GlyphId(glyph)) * 1000.0 / self.units_per_em();
+        width.round() as u16
+    }
+
+    /// Height above the baseline in 1/1000 em.
+    pub fn ascent(&self) -> f32 {
+        self.font.ascent_unscaled() * 1000.0 / self.units_per_em()
+    }
+
+    /// Depth below the baseline in 1/1000 em, negative.
+    pub fn descent(&self) -> f32 {
+        self.font.descent_unscaled() * 1000.0 / self.units_per_em()
+    }
+
+    /// The font file, as embedded.
+    pub fn data(&self) -> &[u8] {
+        self.font.as_slice()
+    }
+
+    fn units_per_em(&self) -> f32 {
+        self.font.units_per_em().unwrap_or(1000.0)
+    }
+}
+
+/// Map a character to its WinAnsiEncoding byte, `?` when unrepresentable.
+pub fn encode_char(c: char) -> u8 {
+    win_ansi_code(c).unwrap_or(b'?')
+}
+
+/// The WinAnsiEncoding byte of a character, if it has one.
+pub fn win_ansi_code(c: char) -> Option<u8> {
+    let cp = c as u32;
+    let code = match cp {
+        0x20..=0x7E | 0xA0..=0xFF => cp as u8,
+        0x09 | 0x0A | 0x0D => b' ',
+        _ => match c {
+            '\u{20AC}' => 0x80,
+            '\u{201A}' => 0x82,
+            '\u{0192}' => 0x83,
+            '\u{201E}' => 0x84,
+            '\u{2026}' => 0x85,
+            '\u{2020}' => 0x86,
+            '\u{2021}' => 0x87,
+            '\u{02C6}' => 0x88,
+            '\u{2030}' => 0x89,
+            '\u{0160}' => 0x8A,
+            '\u{2039}' => 0x8B,
+            '\u{0152}' => 0x8C,
+            '\u{017D}' => 0x8E,
+            '\u{2018}' => 0x91,
+            '\u{2019}' => 0x92,
+            '\u{201C}' => 0x93,
+            '\u{201D}' => 0x94,
+            '\u{2022}' => 0x95,
+            '\u{2013}' => 0x96,
+            '\u{2014}' => 0x97,
+            '\u{02DC}' => 0x98,
+            '\u{2122}' => 0x99,
+            '\u{0161}' => 0x9A,
+            '\u{203A}' => 0x9B,
+            '\u{0153}' => 0x9C,
+            '\u{017E}' => 0x9E,
+            '\u{0178}' => 0x9F,
+            // Spaces of various widths
+            '\u{2000}'..='\u{200A}' | '\u{202F}' => b' ',
+            '\u{2010}' | '\u{2011}' | '\u{2212}' => b'-',
+            _ => return None,
+        },
+    };
+    Some(code)
+}
+
+pub fn encode_text(text: &str) -> Vec<u8> {
+    text.chars().map(encode_char).collect()
+}
+
+/// For codes outside printable ASCII, approximate the width using a similar
+/// ASCII glyph. Accented letters share the advance of their base letter in
+/// all of the standard fonts, the rest is close enough for line breaking.
+fn fallback_glyph(code: u8) -> (u8, f32) {
+    match code {
+        0x80 => (b'0', 1.0),
+        0x82 | 0x91 | 0x92 => (b',', 1.0),
+        0x84 | 0x93 | 0x94 => (b'"', 1.0),
+        0x85 | 0x89 => (b'M', 1.1),
+        0x86 | 0x87 => (b'0', 1.0),
+        0x8A | 0x8E => (b'S', 1.0),
+        0x8B | 0x9B => (b'<', 0.6),
+        0x8C => (b'O', 1.4),
+        0x95 => (b'.', 1.3),
+        0x96 => (b'0', 1.0),
+        0x97 => (b'M', 1.1),
+        0x99 => (b'M', 1.1),
+        0x9A | 0x9E => (b's', 1.0),
+        0x9C => (b'o', 1.5),
+        0x9F => (b'Y', 1.0),
+        0xA0 => (b' ', 1.0),
+        0xAB | 0xBB => (b'<', 0.9),
+        0xC0..=0xC5 => (b'A', 1.0),
+        0xC6 => (b'A', 1.35),
+        0xC7 => (b'C', 1.0),
+        0xC8..=0xCB => (b'E', 1.0),
+        0xCC..=0xCF => (b'I', 1.0),
+        0xD0 => (b'D', 1.0),
+        0xD1 => (b'N', 1.0),
+        0xD2..=0xD6 | 0xD8 => (b'O', 1.0),
+        0xD7 => (b'+', 1.0),
+        0xD9..=0xDC => (b'U', 1.0),
+        0xDD => (b'Y', 1.0),
+        0xDE => (b'P', 1.0),
+        0xDF => (b'b', 1.0),
+        0xE0..=0xE5 => (b'a', 1.0),
+        0xE6 => (b'a', 1.5),
+        0xE7 => (b'c', 1.0),
+        0xE8..=0xEB => (b'e', 1.0),
+        0xEC..=0xEF => (b'i', 1.0),
+        0xF0 | 0xF2..=0xF6 | 0xF8 => (b'o', 1.0),
+        0xF1 => (b'n', 1.0),
+        0xF7 => (b'+', 1.0),
+        0xF9..=0xFC => (b'u', 1.0),
+        0xFD | 0xFF => (b'y', 1.0),
+        0xFE => (b'p', 1.0),
+        _ => (b'o', 1.0),
+    }
+}
+
+// Advance widths for codes 32..=126, taken from the Adobe AFM files.
+
+#[rustfmt::skip]
+static HELVETICA: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+#[rustfmt::skip]
+static HELVETICA_BOLD: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611,
+    975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556,
+    333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611,
+    611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584,
+];
+
+#[rustfmt::skip]
+static TIMES_ROMAN: [u16; 95] = [
+    250, 333, 408, 500, 500, 833, 778, 180, 333, 333, 500, 564, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 278, 278, 564, 564, 564, 444,
+    921, 722, 667, 667, 722, 611, 556, 722, 722, 333, 389, 722, 611, 889, 722, 722,
+    556, 722, 667, 556, 611, 722, 722, 944, 722, 722, 611, 333, 278, 333, 469, 500,
+    333, 444, 500, 444, 500, 444, 333, 500, 500, 278, 278, 500, 278, 778, 500, 500,
+    500, 500, 333, 389, 278, 500, 500, 722, 500, 500, 444, 480, 200, 480, 541,
+];
+
+#[rustfmt::skip]
+static TIMES_BOLD: [u16; 95] = [
+    250, 333, 555, 500, 500, 1000, 833, 278, 333, 333, 500, 570, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 333, 333, 570, 570, 570, 500,
+    930, 722, 667, 722, 722, 667, 611, 778, 778, 389, 500, 778, 667, 944, 722, 778,
+    611, 778, 722, 556, 667, 722, 722, 1000, 722, 722, 667, 333, 278, 333, 581, 500,
+    333, 500, 556, 444, 556, 444, 333, 500, 556, 278, 333, 556, 278, 833, 556, 500,
+    556, 556, 444, 389, 333, 556, 500, 722, 500, 500, 444, 394, 220, 394, 520,
+];
+
+#[rustfmt::skip]
+static TIMES_ITALIC: [u16; 95] = [
+    250, 333, 420, 500, 500, 833, 778, 214, 333, 333, 500, 675, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 333, 333, 675, 675, 675, 500,
+    920, 611, 611, 667, 722, 611, 611, 722, 722, 333, 444, 667, 556, 833, 667, 722,
+    611, 722, 611, 500, 556, 722, 611, 833, 611, 556, 556, 389, 278, 389, 422, 500,
+    333, 500, 500, 444, 500, 444, 278, 500, 500, 278, 278, 444, 278, 722, 500, 500,
+    500, 500, 389, 389, 278, 500, 444, 667, 444, 444, 389, 400, 275, 400, 541,
+];
+
+#[rustfmt::skip]
+static TIMES_BOLD_ITALIC: [u16; 95] = [
+    250, 389, 555, 500, 500, 833, 778, 278, 333, 333, 500, 570, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 333, 333, 570, 570, 570, 500,
+    832, 667, 667, 667, 722, 667, 667, 722, 778, 389, 500, 667, 611, 889, 722, 722,
+    611, 722, 667, 556, 611, 722, 667, 889, 667, 611, 611, 333, 278, 333, 570, 500,
+    333, 500, 500, 444, 500, 444, 333, 500, 556, 278, 278, 500, 278, 778, 556, 500,
+    500, 500, 389, 389, 278, 556, 444, 667, 500, 444, 389, 348, 220, 348, 570,
+];
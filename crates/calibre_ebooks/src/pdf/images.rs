@@ -0,0 +1,167 @@
+//! Conversion of manifest images into PDF image XObjects.
+
+use crate::oeb::container::Container;
+use anyhow::Result;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::DynamicImage;
+use std::collections::HashMap;
+use std::io::Write;
+
+pub struct PdfImage {
+    pub width: u32,
+    pub height: u32,
+    pub color_space: &'static str,
+    pub bits_per_component: u8,
+    /// Stream data, already encoded with `filter`.
+    pub data: Vec<u8>,
+    pub filter: &'static str,
+    /// Flate encoded 8-bit alpha channel, when the image is not opaque.
+    pub smask: Option<Vec<u8>>,
+}
+
+impl PdfImage {
+    pub fn from_bytes(data: &[u8]) -> Result<PdfImage> {
+        // Baseline and progressive JPEGs can be embedded as is, PDF readers
+        // decode DCT natively. CMYK JPEGs are usually stored inverted, so
+        // those get re-encoded along with every other format.
+        if let Some((width, height, components)) = jpeg_info(data) {
+            let color_space = match components {
+                1 => Some("DeviceGray"),
+                3 => Some("DeviceRGB"),
+                _ => None,
+            };
+            if let Some(color_space) = color_space {
+                return Ok(PdfImage {
+                    width,
+                    height,
+                    color_space,
+                    bits_per_component: 8,
+                    data: data.to_vec(),
+                    filter: "DCTDecode",
+                    smask: None,
+                });
+            }
+        }
+
+        let img = image::load_from_memory(data)?;
+        Ok(Self::from_image(&img))
+    }
+
+    pub fn from_image(img: &DynamicImage) -> PdfImage {
+        let (width, height) = (img.width(), img.height());
+        let has_alpha = img.color().has_alpha();
+        let is_gray = !img.color().has_color();
+
+        let (pixels, color_space) = if is_gray {
+            (img.to_luma8().into_raw(), "DeviceGray")
+        } else {
+            (img.to_rgb8().into_raw(), "DeviceRGB")
+        };
+
+        let smask = if has_alpha {
+            let alpha: Vec<u8> = img.to_rgba8().pixels().map(|p| p.0[3]).collect();
+            if alpha.iter().all(|&a| a == 255) {
+                None
+            } else {
+                Some(deflate(&alpha))
+            }
+        } else {
+            None
+        };
+
+        PdfImage {
+            width,
+            height,
+            color_space,
+            bits_per_component: 8,
+            data: deflate(&pixels),
+            filter: "FlateDecode",
+            smask,
+        }
+    }
+}
+
+/// Images referenced by the book, decoded once and shared between pages.
+#[derive(Default)]
+pub struct ImageStore {
+    images: Vec<PdfImage>,
+    by_href: HashMap<String, Option<usize>>,
+}
+
+impl ImageStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the image at the container relative `href`, returning its index.
+    /// Missing or undecodable images are remembered as such and yield `None`.
+    pub fn load(&mut self, container: &dyn Container, href: &str) -> Option<usize> {
+        if let Some(idx) = self.by_href.get(href) {
+            return *idx;
+        }
+        let idx = container
+            .read(href)
+            .and_then(|data| PdfImage::from_bytes(&data))
+            .ok()
+            .map(|img| {
+                self.images.push(img);
+                self.images.len() - 1
+            });
+        self.by_href.insert(href.to_string(), idx);
+        idx
+    }
+
+    pub fn get(&self, idx: usize) -> &PdfImage {
+        &self.images[idx]
+    }
+
+    pub fn images(&self) -> &[PdfImage] {
+        &self.images
+    }
+}
+
+pub(crate) fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    // Writing into a Vec cannot fail
+    encoder.write_all(data).expect("in memory deflate");
+    encoder.finish().expect("in memory deflate")
+}
+
+/// Width, height and component count from the SOF segment of a JPEG.
+fn jpeg_info(data: &[u8]) -> Option<(u32, u32, u8)> {
+    if data.len() < 4 || data[0] != 0xFF || data[1] != 0xD8 {
+        return None;
+    }
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        match marker {
+            // SOF0..SOF15, excluding DHT, JPG and DAC
+            0xC0..=0xCF if marker != 0xC4 && marker != 0xC8 && marker != 0xCC => {
+                let seg = data.get(pos + 4..pos + 2 + len)?;
+                if seg.len() < 6 {
+                    return None;
+                }
+                // Lossless and arithmetic coded JPEGs are poorly supported
+                if !matches!(marker, 0xC0..=0xC2) {
+                    return None;
+                }
+                let height = u16::from_be_bytes([seg[1], seg[2]]) as u32;
+                let width = u16::from_be_bytes([seg[3], seg[4]]) as u32;
+                return Some((width, height, seg[5]));
+            }
+            0xD9 | 0xDA => return None,
+            _ => pos += 2 + len,
+        }
+    }
+    None
+}
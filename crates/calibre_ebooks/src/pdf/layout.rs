@@ -0,0 +1,1148 @@
+//! Flow layout of XHTML spine documents onto fixed size pages.
+//!
+//! This is a deliberately small engine: block elements stack vertically with
+//! collapsed margins, inline content is broken into lines greedily using the
+//! standard font metrics, and pages are cut whenever the next line does not
+//! fit. Styling comes from user agent defaults for the common tags, overridden
+//! by inline `style` declarations read through the `Stylizer`.
+
+use crate::oeb::container::Container;
+use crate::oeb::css::{self, default_display, parse_color, parse_font_family};
+use crate::oeb::parse_utils::{abshref, xmlize_entities};
+use crate::oeb::stylizer::{Style, Stylizer};
+use crate::pdf::fonts::{FontFamily, FontKey};
+use crate::pdf::images::ImageStore;
+use calibre_utils::html2text::html2text;
+use roxmltree::{Document, Node, ParsingOptions};
+use std::collections::HashMap;
+
+pub type Color = (f32, f32, f32);
+
+const BLACK: Color = (0.0, 0.0, 0.0);
+/// CSS pixels per inch, used for `px` lengths and image sizes.
+const CSS_DPI: f32 = 96.0;
+/// Gap between a list marker and the list item text.
+const MARKER_GAP: f32 = 6.0;
+
+/// Page geometry and base typography, all lengths in points.
+#[derive(Debug, Clone)]
+pub struct PageSetup {
+    pub width: f32,
+    pub height: f32,
+    pub margin_top: f32,
+    pub margin_bottom: f32,
+    pub margin_left: f32,
+    pub margin_right: f32,
+    pub base_font_size: f32,
+    /// Line height as a multiple of the font size.
+    pub line_height: f32,
+    pub font_family: FontFamily,
+}
+
+impl Default for PageSetup {
+    fn default() -> Self {
+        // A4 with the same 72pt margins calibre uses
+        PageSetup {
+            width: 595.0,
+            height: 842.0,
+            margin_top: 72.0,
+            margin_bottom: 72.0,
+            margin_left: 72.0,
+            margin_right: 72.0,
+            base_font_size: 12.0,
+            line_height: 1.2,
+            font_family: FontFamily::Serif,
+        }
+    }
+}
+
+impl PageSetup {
+    pub fn content_width(&self) -> f32 {
+        self.width - self.margin_left - self.margin_right
+    }
+
+    pub fn content_height(&self) -> f32 {
+        self.height - self.margin_top - self.margin_bottom
+    }
+}
+
+/// A positioned drawing operation. Coordinates use the PDF convention, with
+/// the origin at the bottom left of the page; text `y` is the baseline.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DrawOp {
+    Text {
+        x: f32,
+        y: f32,
+        font: FontKey,
+        size: f32,
+        color: Color,
+        text: String,
+    },
+    Image {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        index: usize,
+    },
+    Rule {
+        x1: f32,
+        y1: f32,
+        x2: f32,
+        y2: f32,
+        width: f32,
+        color: Color,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Page {
+    pub ops: Vec<DrawOp>,
+}
+
+/// Where an anchor ended up: page index and the y coordinate of its line top.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Destination {
+    pub page: usize,
+    pub y: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+    Justify,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TextStyle {
+    font: FontKey,
+    size: f32,
+    color: Color,
+    line_height: f32,
+    underline: bool,
+    /// Baseline shift for sub/superscripts, positive is up.
+    rise: f32,
+}
+
+impl TextStyle {
+    fn ascent(&self) -> f32 {
+        self.size * (0.8 + (self.line_height - 1.0) / 2.0) + self.rise.max(0.0)
+    }
+
+    fn descent(&self) -> f32 {
+        self.size * (0.2 + (self.line_height - 1.0) / 2.0) - self.rise.min(0.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum FragKind {
+    Text(String),
+    Image {
+        index: usize,
+        width: f32,
+        height: f32,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Fragment {
+    kind: FragKind,
+    style: TextStyle,
+}
+
+impl Fragment {
+    fn width(&self) -> f32 {
+        match &self.kind {
+            FragKind::Text(t) => self.style.font.text_width(t, self.style.size),
+            FragKind::Image { width, .. } => *width,
+        }
+    }
+
+    fn ascent(&self) -> f32 {
+        match &self.kind {
+            FragKind::Text(_) => self.style.ascent(),
+            FragKind::Image { height, .. } => *height,
+        }
+    }
+
+    fn descent(&self) -> f32 {
+        match &self.kind {
+            FragKind::Text(_) => self.style.descent(),
+            FragKind::Image { .. } => 0.0,
+        }
+    }
+}
+
+/// An unbreakable run of fragments; lines may only be broken between words.
+#[derive(Debug, Clone, Default)]
+struct Word {
+    frags: Vec<Fragment>,
+    space_before: bool,
+    anchors: Vec<String>,
+    /// Forced line break (`<br/>`, newlines in `<pre>`), `frags` is empty.
+    hard_break: Option<TextStyle>,
+}
+
+impl Word {
+    fn width(&self) -> f32 {
+        self.frags.iter().map(|f| f.width()).sum()
+    }
+
+    fn space_width(&self) -> f32 {
+        self.frags
+            .first()
+            .map(|f| f.style.font.text_width(" ", f.style.size))
+            .unwrap_or(0.0)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Paragraph {
+    words: Vec<Word>,
+    word_open: bool,
+    pending_space: bool,
+    pending_anchors: Vec<String>,
+    marker: Option<(String, TextStyle)>,
+}
+
+impl Paragraph {
+    fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    fn push_char(&mut self, c: char, style: &TextStyle) {
+        if !self.word_open || self.pending_space {
+            let space_before = self.pending_space && !self.words.is_empty();
+            self.words.push(Word {
+                space_before,
+                anchors: std::mem::take(&mut self.pending_anchors),
+                ..Default::default()
+            });
+            self.word_open = true;
+            self.pending_space = false;
+        }
+        let word = self.words.last_mut().expect("word was just opened");
+        match word.frags.last_mut() {
+            Some(Fragment {
+                kind: FragKind::Text(text),
+                style: s,
+            }) if s == style => text.push(c),
+            _ => word.frags.push(Fragment {
+                kind: FragKind::Text(c.to_string()),
+                style: *style,
+            }),
+        }
+    }
+
+    fn push_image(&mut self, frag: Fragment) {
+        let space_before = self.pending_space && !self.words.is_empty();
+        self.words.push(Word {
+            frags: vec![frag],
+            space_before,
+            anchors: std::mem::take(&mut self.pending_anchors),
+            hard_break: None,
+        });
+        self.word_open = false;
+        self.pending_space = false;
+    }
+
+    fn push_break(&mut self, style: &TextStyle) {
+        self.words.push(Word {
+            anchors: std::mem::take(&mut self.pending_anchors),
+            hard_break: Some(*style),
+            ..Default::default()
+        });
+        self.word_open = false;
+        self.pending_space = false;
+    }
+
+    fn space(&mut self) {
+        if !self.words.is_empty() {
+            self.pending_space = true;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BlockCtx {
+    left: f32,
+    right: f32,
+    align: Align,
+    text_indent: f32,
+}
+
+struct ListCtx {
+    ordered: bool,
+    counter: i64,
+}
+
+/// Lays out documents one after another, accumulating pages.
+pub struct Layout<'a> {
+    setup: PageSetup,
+    stylizer: Stylizer,
+    container: &'a dyn Container,
+    images: &'a mut ImageStore,
+    pages: Vec<Page>,
+    anchors: HashMap<String, Destination>,
+    /// Top of the free space on the current page.
+    y: f32,
+    pending_margin: f32,
+    para: Paragraph,
+    blocks: Vec<BlockCtx>,
+    lists: Vec<ListCtx>,
+    pre_depth: usize,
+}
+
+impl<'a> Layout<'a> {
+    pub fn new(setup: PageSetup, container: &'a dyn Container, images: &'a mut ImageStore) -> Self {
+        let stylizer = Stylizer::new(CSS_DPI, setup.base_font_size);
+        let root = BlockCtx {
+            left: setup.margin_left,
+            right: setup.width - setup.margin_right,
+            align: Align::Left,
+            text_indent: 0.0,
+        };
+        let y = setup.height - setup.margin_top;
+        Layout {
+            setup,
+            stylizer,
+            container,
+            images,
+            pages: Vec::new(),
+            anchors: HashMap::new(),
+            y,
+            pending_margin: 0.0,
+            para: Paragraph::default(),
+            blocks: vec![root],
+            lists: Vec::new(),
+            pre_depth: 0,
+        }
+    }
+
+    /// Lay out the (X)HTML document stored at `href`. Every document starts
+    /// on a fresh page, like calibre does for spine items.
+    pub fn add_document(&mut self, href: &str, html: &str) {
+        if self.page_has_content() {
+            self.new_page();
+        }
+        self.ensure_page();
+        self.record_anchor(href.to_string());
+
+        let base = self.base_style();
+        let text = xmlize_entities(html);
+        let opts = ParsingOptions {
+            allow_dtd: true,
+            ..ParsingOptions::default()
+        };
+        match Document::parse_with_options(&text, opts) {
+            Ok(doc) => {
+                let root = doc.root_element();
+                let body = root
+                    .descendants()
+                    .find(|n| n.is_element() && n.tag_name().name().eq_ignore_ascii_case("body"))
+                    .unwrap_or(root);
+                self.walk(body, &base, href);
+            }
+            Err(_) => {
+                // Tag soup: keep the text, lose the formatting
+                for block in html2text(html).split("\n\n") {
+                    self.start_block(0.0);
+                    for c in block.chars() {
+                        if c.is_whitespace() {
+                            self.para.space();
+                        } else {
+                            self.para.push_char(c, &base);
+                        }
+                    }
+                    self.end_block(base.size * 0.5);
+                }
+            }
+        }
+        self.flush_paragraph();
+    }
+
+    /// Pages laid out so far and the position of every anchor, keyed by
+    /// document href and `href#id`.
+    pub fn finish(mut self) -> (Vec<Page>, HashMap<String, Destination>) {
+        self.flush_paragraph();
+        self.ensure_page();
+        // A trailing page-break-after leaves an empty page behind
+        while self.pages.len() > 1 && self.pages.last().map(|p| p.ops.is_empty()) == Some(true) {
+            self.pages.pop();
+        }
+        let last = self.pages.len() - 1;
+        for dest in self.anchors.values_mut() {
+            if dest.page > last {
+                *dest = Destination {
+                    page: last,
+                    y: self.setup.height - self.setup.margin_top,
+                };
+            }
+        }
+        (self.pages, self.anchors)
+    }
+
+    fn base_style(&self) -> TextStyle {
+        TextStyle {
+            font: FontKey::new(self.setup.font_family, false, false),
+            size: self.setup.base_font_size,
+            color: BLACK,
+            line_height: self.setup.line_height,
+            underline: false,
+            rise: 0.0,
+        }
+    }
+
+    fn top(&self) -> f32 {
+        self.setup.height - self.setup.margin_top
+    }
+
+    fn ensure_page(&mut self) {
+        if self.pages.is_empty() {
+            self.pages.push(Page::default());
+            self.y = self.top();
+        }
+    }
+
+    fn new_page(&mut self) {
+        self.pages.push(Page::default());
+        self.y = self.top();
+        self.pending_margin = 0.0;
+    }
+
+    fn page_has_content(&self) -> bool {
+        self.pages.last().map(|p| !p.ops.is_empty()).unwrap_or(false)
+    }
+
+    fn at_page_top(&self) -> bool {
+        self.y >= self.top()
+    }
+
+    fn record_anchor(&mut self, key: String) {
+        self.ensure_page();
+        let dest = Destination {
+            page: self.pages.len() - 1,
+            y: self.y,
+        };
+        self.anchors.entry(key).or_insert(dest);
+    }
+
+    /// Reserve `height` points, moving to a new page if they do not fit.
+    /// Returns the top y of the reserved box.
+    fn reserve(&mut self, height: f32) -> f32 {
+        self.ensure_page();
+        if !self.at_page_top() {
+            self.y -= self.pending_margin;
+        }
+        self.pending_margin = 0.0;
+        if self.y - height < self.setup.margin_bottom && !self.at_page_top() {
+            self.new_page();
+        }
+        let top = self.y;
+        self.y -= height;
+        top
+    }
+
+    fn current_block(&self) -> BlockCtx {
+        *self.blocks.last().expect("root block is never popped")
+    }
+
+    fn start_block(&mut self, margin_top: f32) {
+        self.flush_paragraph();
+        self.pending_margin = self.pending_margin.max(margin_top);
+    }
+
+    fn end_block(&mut self, margin_bottom: f32) {
+        self.flush_paragraph();
+        self.pending_margin = self.pending_margin.max(margin_bottom);
+    }
+
+    fn walk(&mut self, node: Node, parent: &TextStyle, href: &str) {
+        for child in node.children() {
+            if child.is_text() {
+                if let Some(text) = child.text() {
+                    self.add_text(text, parent);
+                }
+            } else if child.is_element() {
+                self.element(child, parent, href);
+            }
+        }
+    }
+
+    fn add_text(&mut self, text: &str, style: &TextStyle) {
+        if self.pre_depth > 0 {
+            for (i, line) in text.split('\n').enumerate() {
+                if i > 0 {
+                    self.para.push_break(style);
+                }
+                for c in line.chars() {
+                    match c {
+                        '\t' => (0..4).for_each(|_| self.para.push_char(' ', style)),
+                        '\r' => {}
+                        _ => self.para.push_char(c, style),
+                    }
+                }
+            }
+            return;
+        }
+        for c in text.chars() {
+            // Non breaking spaces are not whitespace for wrapping purposes
+            if c.is_whitespace() && c != '\u{a0}' {
+                self.para.space();
+            } else if c != '\u{ad}' {
+                self.para.push_char(c, style);
+            }
+        }
+    }
+
+    fn element(&mut self, node: Node, parent: &TextStyle, href: &str) {
+        let tag = node.tag_name().name().to_ascii_lowercase();
+        if matches!(
+            tag.as_str(),
+            "head" | "script" | "style" | "title" | "meta" | "link" | "noscript"
+        ) {
+            return;
+        }
+
+        let stylizer = self.stylizer;
+        let css = stylizer.style(&node);
+        let display = css
+            .get_inline_style("display")
+            .map(|d| d.to_ascii_lowercase())
+            .unwrap_or_else(|| default_display(&tag).to_string());
+        if display == "none" {
+            return;
+        }
+
+        let style = self.compute_style(&css, &tag, parent);
+        let page_break_before = css
+            .get_inline_style("page-break-before")
+            .or_else(|| css.get_inline_style("break-before"))
+            .map(|v| v == "always" || v == "page")
+            .unwrap_or(false);
+        let page_break_after = css
+            .get_inline_style("page-break-after")
+            .or_else(|| css.get_inline_style("break-after"))
+            .map(|v| v == "always" || v == "page")
+            .unwrap_or(false);
+
+        if page_break_before {
+            self.flush_paragraph();
+            if self.page_has_content() && !self.at_page_top() {
+                self.new_page();
+            }
+        }
+
+        let anchor = node
+            .attribute("id")
+            .or_else(|| {
+                if tag == "a" {
+                    node.attribute("name")
+                } else {
+                    None
+                }
+            })
+            .map(|id| format!("{}#{}", href, id));
+
+        match tag.as_str() {
+            "br" => {
+                if let Some(anchor) = anchor {
+                    self.para.pending_anchors.push(anchor);
+                }
+                self.para.push_break(&style);
+            }
+            "img" | "image" => {
+                if let Some(anchor) = anchor {
+                    self.para.pending_anchors.push(anchor);
+                }
+                self.image(node, &style, href);
+            }
+            "hr" => {
+                self.start_block(style.size * 0.5);
+                let ctx = self.current_block();
+                let top = self.reserve(1.0);
+                if let Some(anchor) = anchor {
+                    self.record_anchor_at(anchor, top);
+                }
+                let y = top - 0.5;
+                self.push_op(DrawOp::Rule {
+                    x1: ctx.left,
+                    y1: y,
+                    x2: ctx.right,
+                    y2: y,
+                    width: 0.5,
+                    color: style.color,
+                });
+                self.end_block(style.size * 0.5);
+            }
+            _ if matches!(
+                display.as_str(),
+                "block" | "list-item" | "table" | "table-row"
+            ) =>
+            {
+                let (mt, mr, mb, ml) = self.block_margins(&css, &tag, &style);
+                self.start_block(mt);
+                if let Some(anchor) = anchor {
+                    self.para.pending_anchors.push(anchor);
+                }
+
+                let parent_ctx = self.current_block();
+                let mut ctx = parent_ctx;
+                ctx.left += ml;
+                ctx.right -= mr;
+                if ctx.right - ctx.left < style.size * 4.0 {
+                    // Nested indents ate the page, keep some room to write in
+                    ctx.left = parent_ctx.left;
+                    ctx.right = parent_ctx.right;
+                }
+                ctx.align = css
+                    .get_inline_style("text-align")
+                    .or_else(|| node.attribute("align").map(|a| a.to_string()))
+                    .and_then(|a| parse_align(&a))
+                    .or(match tag.as_str() {
+                        "center" | "caption" | "th" => Some(Align::Center),
+                        _ => None,
+                    })
+                    .unwrap_or(parent_ctx.align);
+                let width = ctx.right - ctx.left;
+                ctx.text_indent = css
+                    .get_inline_style("text-indent")
+                    .and_then(|v| css::length(&self.stylizer, &v, style.size, width))
+                    .unwrap_or(0.0);
+                self.blocks.push(ctx);
+
+                match tag.as_str() {
+                    "ul" | "ol" => {
+                        let start = node
+                            .attribute("start")
+                            .and_then(|s| s.trim().parse::<i64>().ok())
+                            .unwrap_or(1);
+                        self.lists.push(ListCtx {
+                            ordered: tag == "ol",
+                            counter: start - 1,
+                        });
+                    }
+                    "li" => {
+                        let marker = match self.lists.last_mut() {
+                            Some(list) if list.ordered => {
+                                list.counter += 1;
+                                format!("{}.", list.counter)
+                            }
+                            _ => "\u{2022}".to_string(),
+                        };
+                        self.para.marker = Some((marker, style));
+                    }
+                    "pre" => self.pre_depth += 1,
+                    _ => {}
+                }
+
+                self.walk(node, &style, href);
+
+                match tag.as_str() {
+                    "ul" | "ol" => {
+                        self.lists.pop();
+                    }
+                    "pre" => self.pre_depth -= 1,
+                    _ => {}
+                }
+                self.flush_paragraph();
+                self.blocks.pop();
+                self.end_block(mb);
+            }
+            _ => {
+                if let Some(anchor) = anchor {
+                    self.para.pending_anchors.push(anchor);
+                }
+                self.walk(node, &style, href);
+                if matches!(tag.as_str(), "td" | "th") {
+                    self.para.space();
+                }
+            }
+        }
+
+        if page_break_after {
+            self.flush_paragraph();
+            if self.page_has_content() {
+                self.new_page();
+            }
+        }
+    }
+
+    fn image(&mut self, node: Node, style: &TextStyle, href: &str) {
+        let src = node
+            .attribute("src")
+            .or_else(|| {
+                // SVG <image xlink:href="...">
+                node.attributes()
+                    .find(|a| a.name() == "href")
+                    .map(|a| a.value())
+            })
+            .unwrap_or("");
+        let loaded = if src.is_empty() || src.starts_with("data:") {
+            None
+        } else {
+            let path = abshref(href, src);
+            self.images.load(self.container, &path)
+        };
+
+        let index = match loaded {
+            Some(index) => index,
+            None => {
+                if let Some(alt) = node.attribute("alt") {
+                    self.add_text(alt, style);
+                }
+                return;
+            }
+        };
+
+        let img = self.images.get(index);
+        let px = 72.0 / CSS_DPI;
+        let (iw, ih) = (img.width as f32 * px, img.height as f32 * px);
+        let attr = |name: &str| {
+            node.attribute(name)
+                .and_then(|v| v.trim().trim_end_matches("px").parse::<f32>().ok())
+                .map(|v| v * px)
+        };
+        let (mut w, mut h) = match (attr("width"), attr("height")) {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) if iw > 0.0 => (w, ih * w / iw),
+            (None, Some(h)) if ih > 0.0 => (iw * h / ih, h),
+            _ => (iw, ih),
+        };
+
+        // Never overflow the text block or the page
+        let ctx = self.current_block();
+        let max_w = ctx.right - ctx.left;
+        let max_h = self.setup.content_height() - style.descent();
+        if w > max_w {
+            h *= max_w / w;
+            w = max_w;
+        }
+        if h > max_h {
+            w *= max_h / h;
+            h = max_h;
+        }
+        if w <= 0.0 || h <= 0.0 {
+            return;
+        }
+
+        self.para.push_image(Fragment {
+            kind: FragKind::Image {
+                index,
+                width: w,
+                height: h,
+            },
+            style: *style,
+        });
+    }
+
+    fn compute_style(&self, css: &Style, tag: &str, parent: &TextStyle) -> TextStyle {
+        let mut s = *parent;
+        s.rise = 0.0;
+
+        match tag {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let factor = match tag {
+                    "h1" => 2.0,
+                    "h2" => 1.5,
+                    "h3" => 1.17,
+                    "h4" => 1.0,
+                    "h5" => 0.83,
+                    _ => 0.67,
+                };
+                s.size = parent.size * factor;
+                s.font.bold = true;
+            }
+            "b" | "strong" | "th" | "dt" => s.font.bold = true,
+            "i" | "em" | "cite" | "var" | "dfn" | "address" => s.font.italic = true,
+            "code" | "tt" | "kbd" | "samp" | "pre" => s.font.family = FontFamily::Monospace,
+            "u" | "ins" => s.underline = true,
+            "small" => s.size = parent.size * 0.83,
+            "big" => s.size = parent.size * 1.2,
+            "sub" => {
+                s.size = parent.size * 0.83;
+                s.rise = -parent.size * 0.25;
+            }
+            "sup" => {
+                s.size = parent.size * 0.83;
+                s.rise = parent.size * 0.35;
+            }
+            _ => {}
+        }
+
+        if let Some(v) = css.get_inline_style("font-size") {
+            if let Some(size) = css::font_size(&self.stylizer, &v, parent.size) {
+                s.size = size;
+            }
+        }
+        if let Some(v) = css.get_inline_style("font-weight") {
+            match v.as_str() {
+                "bold" | "bolder" => s.font.bold = true,
+                "normal" | "lighter" => s.font.bold = false,
+                n => {
+                    if let Ok(n) = n.parse::<u32>() {
+                        s.font.bold = n >= 600;
+                    }
+                }
+            }
+        }
+        if let Some(v) = css.get_inline_style("font-style") {
+            s.font.italic = v == "italic" || v == "oblique";
+        }
+        if let Some(v) = css.get_inline_style("font-family") {
+            if let Some(family) = parse_font_family(&v) {
+                s.font.family = family;
+            }
+        }
+        if let Some(v) = css.get_inline_style("color") {
+            if let Some(color) = parse_color(&v) {
+                s.color = color;
+            }
+        }
+        if let Some(v) = css.get_inline_style("text-decoration") {
+            s.underline = v.contains("underline");
+        }
+        if let Some(v) = css.get_inline_style("line-height") {
+            let v = v.trim();
+            if v == "normal" {
+                s.line_height = self.setup.line_height;
+            } else if let Ok(n) = v.parse::<f32>() {
+                s.line_height = n;
+            } else if let Some(len) = css::length(&self.stylizer, v, s.size, s.size) {
+                s.line_height = len / s.size;
+            }
+            s.line_height = s.line_height.max(0.8);
+        }
+        if let Some(v) = css.get_inline_style("vertical-align") {
+            match v.as_str() {
+                "super" => s.rise = parent.size * 0.35,
+                "sub" => s.rise = -parent.size * 0.25,
+                _ => {}
+            }
+        }
+        s
+    }
+
+    /// Top, right, bottom and left margins of a block, padding included.
+    fn block_margins(&self, css: &Style, tag: &str, style: &TextStyle) -> (f32, f32, f32, f32) {
+        let em = style.size;
+        let indent = 40.0 * 72.0 / CSS_DPI;
+        let (mut t, mut r, mut b, mut l) = match tag {
+            "p" | "dl" | "pre" => (em, 0.0, em, 0.0),
+            "blockquote" | "figure" => (em, indent, em, indent),
+            "ul" | "ol" if self.lists.is_empty() => (em, 0.0, em, indent),
+            "ul" | "ol" => (0.0, 0.0, 0.0, indent),
+            "dd" => (0.0, 0.0, 0.0, indent),
+            "h1" => (em * 0.67, 0.0, em * 0.67, 0.0),
+            "h2" => (em * 0.83, 0.0, em * 0.83, 0.0),
+            "h3" => (em, 0.0, em, 0.0),
+            "h4" => (em * 1.33, 0.0, em * 1.33, 0.0),
+            "h5" => (em * 1.67, 0.0, em * 1.67, 0.0),
+            "h6" => (em * 2.33, 0.0, em * 2.33, 0.0),
+            _ => (0.0, 0.0, 0.0, 0.0),
+        };
+
+        let width = self.current_block().right - self.current_block().left;
+        for prop in ["margin", "padding"] {
+            let mut edges: HashMap<String, String> = HashMap::new();
+            if let Some(v) = css.get_inline_style(prop) {
+                edges.extend(crate::oeb::normalize_css::normalize_edge(prop, &v));
+            }
+            for edge in ["top", "right", "bottom", "left"] {
+                let name = format!("{}-{}", prop, edge);
+                if let Some(v) = css.get_inline_style(&name) {
+                    edges.insert(name, v);
+                }
+            }
+            let get = |edge: &str| {
+                edges
+                    .get(&format!("{}-{}", prop, edge))
+                    .and_then(|v| css::length(&self.stylizer, v, em, width))
+            };
+            // Padding adds to the UA margin, an explicit margin replaces it
+            if prop == "margin" {
+                t = get("top").unwrap_or(t);
+                r = get("right").unwrap_or(r);
+                b = get("bottom").unwrap_or(b);
+                l = get("left").unwrap_or(l);
+            } else {
+                t += get("top").unwrap_or(0.0);
+                r += get("right").unwrap_or(0.0);
+                b += get("bottom").unwrap_or(0.0);
+                l += get("left").unwrap_or(0.0);
+            }
+        }
+        (t.max(0.0), r, b.max(0.0), l)
+    }
+
+    fn push_op(&mut self, op: DrawOp) {
+        self.ensure_page();
+        self.pages.last_mut().expect("page exists").ops.push(op);
+    }
+
+    fn record_anchor_at(&mut self, key: String, y: f32) {
+        let dest = Destination {
+            page: self.pages.len().saturating_sub(1),
+            y,
+        };
+        self.anchors.entry(key).or_insert(dest);
+    }
+
+    /// Break the pending inline content into lines and place them.
+    fn flush_paragraph(&mut self) {
+        let mut para = std::mem::take(&mut self.para);
+        if para.is_empty() {
+            // Anchors on empty elements still need a destination
+            for anchor in para.pending_anchors.drain(..) {
+                self.record_anchor(anchor);
+            }
+            return;
+        }
+        // Trailing breaks only add blank lines that HTML does not render
+        while para
+            .words
+            .last()
+            .map(|w| w.hard_break.is_some() && w.anchors.is_empty())
+            .unwrap_or(false)
+            && para.words.len() > 1
+        {
+            para.words.pop();
+        }
+
+        let ctx = self.current_block();
+        let full_width = ctx.right - ctx.left;
+        let words = split_long_words(para.words, full_width - ctx.text_indent.max(0.0));
+
+        let mut lines: Vec<(Vec<Word>, bool)> = Vec::new();
+        let mut line: Vec<Word> = Vec::new();
+        let mut width = 0.0;
+        let mut first = true;
+        for word in words {
+            let avail = if first {
+                full_width - ctx.text_indent
+            } else {
+                full_width
+            };
+            if word.hard_break.is_some() {
+                line.push(word);
+                lines.push((std::mem::take(&mut line), true));
+                width = 0.0;
+                first = false;
+                continue;
+            }
+            let w = word.width();
+            let space = if line.is_empty() || !word.space_before {
+                0.0
+            } else {
+                word.space_width()
+            };
+            if !line.is_empty() && width + space + w > avail + 0.01 {
+                lines.push((std::mem::take(&mut line), false));
+                width = w;
+                first = false;
+            } else {
+                width += space + w;
+            }
+            line.push(word);
+        }
+        if !line.is_empty() {
+            lines.push((line, true));
+        }
+
+        let mut marker = para.marker.take();
+        for (i, (words, last)) in lines.into_iter().enumerate() {
+            let indent = if i == 0 { ctx.text_indent } else { 0.0 };
+            self.place_line(&ctx, words, indent, last, marker.take());
+        }
+    }
+
+    fn place_line(
+        &mut self,
+        ctx: &BlockCtx,
+        words: Vec<Word>,
+        indent: f32,
+        last: bool,
+        marker: Option<(String, TextStyle)>,
+    ) {
+        let mut ascent: f32 = 0.0;
+        let mut descent: f32 = 0.0;
+        for word in &words {
+            if let Some(style) = &word.hard_break {
+                ascent = ascent.max(style.ascent());
+                descent = descent.max(style.descent());
+            }
+            for frag in &word.frags {
+                ascent = ascent.max(frag.ascent());
+                descent = descent.max(frag.descent());
+            }
+        }
+        if let Some((_, style)) = &marker {
+            ascent = ascent.max(style.ascent());
+            descent = descent.max(style.descent());
+        }
+
+        let top = self.reserve(ascent + descent);
+        let baseline = top - ascent;
+        let page = self.pages.len() - 1;
+
+        // Leading spaces never render, trailing break words have no width
+        let visible: Vec<&Word> = words.iter().filter(|w| w.hard_break.is_none()).collect();
+        let mut natural = 0.0;
+        let mut gaps = 0;
+        for (i, word) in visible.iter().enumerate() {
+            if i > 0 && word.space_before {
+                natural += word.space_width();
+                gaps += 1;
+            }
+            natural += word.width();
+        }
+
+        let avail = ctx.right - ctx.left - indent;
+        let slack = (avail - natural).max(0.0);
+        let (mut x, extra) = match ctx.align {
+            Align::Left => (ctx.left + indent, 0.0),
+            Align::Right => (ctx.left + indent + slack, 0.0),
+            Align::Center => (ctx.left + indent + slack / 2.0, 0.0),
+            Align::Justify if !last && gaps > 0 => (ctx.left + indent, slack / gaps as f32),
+            Align::Justify => (ctx.left + indent, 0.0),
+        };
+
+        if let Some((text, style)) = marker {
+            let w = style.font.text_width(&text, style.size);
+            self.push_op(DrawOp::Text {
+                x: ctx.left - w - MARKER_GAP,
+                y: baseline,
+                font: style.font,
+                size: style.size,
+                color: style.color,
+                text,
+            });
+        }
+
+        for word in &words {
+            for anchor in &word.anchors {
+                self.anchors
+                    .entry(anchor.clone())
+                    .or_insert(Destination { page, y: top });
+            }
+        }
+
+        for (i, word) in visible.into_iter().enumerate() {
+            if i > 0 && word.space_before {
+                x += word.space_width() + extra;
+            }
+            for frag in &word.frags {
+                let w = frag.width();
+                match &frag.kind {
+                    FragKind::Text(text) => {
+                        let y = baseline + frag.style.rise;
+                        self.push_op(DrawOp::Text {
+                            x,
+                            y,
+                            font: frag.style.font,
+                            size: frag.style.size,
+                            color: frag.style.color,
+                            text: text.clone(),
+                        });
+                        if frag.style.underline {
+                            let uy = y - frag.style.size * 0.12;
+                            self.push_op(DrawOp::Rule {
+                                x1: x,
+                                y1: uy,
+                                x2: x + w,
+                                y2: uy,
+                                width: (frag.style.size * 0.05).max(0.4),
+                                color: frag.style.color,
+                            });
+                        }
+                    }
+                    FragKind::Image {
+                        index,
+                        width,
+                        height,
+                    } => {
+                        self.push_op(DrawOp::Image {
+                            x,
+                            y: baseline,
+                            width: *width,
+                            height: *height,
+                            index: *index,
+                        });
+                    }
+                }
+                x += w;
+            }
+        }
+    }
+}
+
+/// Break words wider than `max_width` at character boundaries so that they
+/// can be wrapped at all.
+fn split_long_words(words: Vec<Word>, max_width: f32) -> Vec<Word> {
+    let mut out = Vec::with_capacity(words.len());
+    for word in words {
+        if word.hard_break.is_some() || word.width() <= max_width || max_width <= 0.0 {
+            out.push(word);
+            continue;
+        }
+        let mut current = Word {
+            space_before: word.space_before,
+            anchors: word.anchors,
+            ..Default::default()
+        };
+        let mut width = 0.0;
+        for frag in word.frags {
+            let text = match &frag.kind {
+                FragKind::Text(t) => t.clone(),
+                FragKind::Image { .. } => {
+                    let w = frag.width();
+                    if width > 0.0 && width + w > max_width {
+                        out.push(std::mem::take(&mut current));
+                        width = 0.0;
+                    }
+                    width += w;
+                    current.frags.push(frag);
+                    continue;
+                }
+            };
+            let mut piece = String::new();
+            for c in text.chars() {
+                let cw = frag.style.font.text_width(&c.to_string(), frag.style.size);
+                if width + cw > max_width && (width > 0.0 || !piece.is_empty()) {
+                    if !piece.is_empty() {
+                        current.frags.push(Fragment {
+                            kind: FragKind::Text(std::mem::take(&mut piece)),
+                            style: frag.style,
+                        });
+                    }
+                    out.push(std::mem::take(&mut current));
+                    width = 0.0;
+                }
+                piece.push(c);
+                width += cw;
+            }
+            if !piece.is_empty() {
+                current.frags.push(Fragment {
+                    kind: FragKind::Text(piece),
+                    style: frag.style,
+                });
+            }
+        }
+        if !current.frags.is_empty() {
+            out.push(current);
+        }
+    }
+    out
+}
+
+fn parse_align(value: &str) -> Option<Align> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "left" | "start" => Some(Align::Left),
+        "right" | "end" => Some(Align::Right),
+        "center" => Some(Align::Center),
+        "justify" => Some(Align::Justify),
+        _ => None,
+    }
+}
@@ -0,0 +1,4 @@
+pub mod fonts;
+pub mod images;
+pub mod layout;
+pub mod writer;
@@ -0,0 +1,521 @@
+//! Assembles laid out pages into a PDF document.
+
+use crate::metadata::xmp::metadata_to_xmp_packet;
+use crate::metadata::MetaInformation;
+use crate::oeb::toc::{TOCNode, TOC};
+use crate::pdf::fonts::{encode_text, win_ansi_code, EmbeddedFont, FontKey};
+use crate::pdf::images::{deflate, ImageStore};
+use crate::pdf::layout::{Destination, DrawOp, Page, PageSetup};
+use calibre_utils::constants::{APP_NAME, VERSION};
+use chrono::Utc;
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+/// A bookmark in the PDF outline.
+#[derive(Debug, Clone)]
+pub struct OutlineEntry {
+    pub title: String,
+    pub dest: Option<Destination>,
+    pub children: Vec<OutlineEntry>,
+}
+
+/// Map the book's table of contents onto laid out positions. `anchors` is
+/// keyed by document href and `href#id`, as produced by the layout.
+pub fn outline_from_toc(toc: &TOC, anchors: &HashMap<String, Destination>) -> Vec<OutlineEntry> {
+    fn convert(node: &TOCNode, anchors: &HashMap<String, Destination>) -> OutlineEntry {
+        let dest = node.href.as_deref().and_then(|href| {
+            let href = urlencoding::decode(href)
+                .map(|s| s.into_owned())
+                .unwrap_or_else(|_| href.to_string());
+            anchors.get(&href).copied().or_else(|| {
+                let file = href.split('#').next().unwrap_or("");
+                anchors.get(file).copied()
+            })
+        });
+        OutlineEntry {
+            title: node
+                .title
+                .clone()
+                .unwrap_or_default()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+            dest,
+            children: node
+                .children
+                .iter()
+                .map(|c| convert(c, anchors))
+                .collect(),
+        }
+    }
+    toc.root
+        .children
+        .iter()
+        .map(|c| convert(c, anchors))
+        .collect()
+}
+
+/// Build the complete document: page tree, fonts, images, outline, the Info
+/// dictionary and an XMP metadata stream.
+pub fn build_document(
+    setup: &PageSetup,
+    pages: &[Page],
+    images: &ImageStore,
+    outline: &[OutlineEntry],
+    mi: &MetaInformation,
+) -> Document {
+    let mut doc = Document::with_version("1.4");
+    let pages_id = doc.new_object_id();
+
+    // Encoding the text decides which fonts the pages need
+    let mut fonts_used = FontUsage::default();
+    let contents: Vec<Vec<u8>> = pages
+        .iter()
+        .map(|page| {
+            let content = Content {
+                operations: page_operations(page, &mut fonts_used),
+            };
+            content.encode().unwrap_or_default()
+        })
+        .collect();
+    if !fonts_used.substituted.is_empty() {
+        let chars: String = fonts_used.substituted.iter().collect();
+        log::warn!(
+            "No available font can show {} characters, replaced in the PDF: {}",
+            fonts_used.substituted.len(),
+            chars
+        );
+    }
+
+    // Fonts and images are shared by every page through one resource dict
+    let mut font_dict = Dictionary::new();
+    for font in &fonts_used.standard {
+        let id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => font.base_font(),
+            "Encoding" => "WinAnsiEncoding",
+        });
+        font_dict.set(font.resource_name(), id);
+    }
+    for (font, (embedded, glyphs)) in &fonts_used.embedded {
+        let id = add_embedded_font(&mut doc, *font, embedded, glyphs);
+        font_dict.set(font.embedded_resource_name(), id);
+    }
+
+    let mut xobjects = Dictionary::new();
+    for (idx, img) in images.images().iter().enumerate() {
+        let mut dict = dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Image",
+            "Width" => img.width as i64,
+            "Height" => img.height as i64,
+            "ColorSpace" => img.color_space,
+            "BitsPerComponent" => img.bits_per_component as i64,
+            "Filter" => img.filter,
+        };
+        if let Some(alpha) = &img.smask {
+            let smask = Stream::new(
+                dictionary! {
+                    "Type" => "XObject",
+                    "Subtype" => "Image",
+                    "Width" => img.width as i64,
+                    "Height" => img.height as i64,
+                    "ColorSpace" => "DeviceGray",
+                    "BitsPerComponent" => 8_i64,
+                    "Filter" => "FlateDecode",
+                },
+                alpha.clone(),
+            )
+            .with_compression(false);
+            dict.set("SMask", doc.add_object(smask));
+        }
+        let stream = Stream::new(dict, img.data.clone()).with_compression(false);
+        xobjects.set(image_name(idx), doc.add_object(stream));
+    }
+
+    let mut resources = dictionary! { "Font" => font_dict };
+    if !xobjects.is_empty() {
+        resources.set("XObject", xobjects);
+    }
+    let resources_id = doc.add_object(resources);
+
+    let media_box: Vec<Object> = vec![0.into(), 0.into(), num(setup.width), num(setup.height)];
+    let mut page_ids = Vec::with_capacity(pages.len());
+    for data in &contents {
+        let stream = Stream::new(dictionary! { "Filter" => "FlateDecode" }, deflate(data))
+            .with_compression(false);
+        let content_id = doc.add_object(stream);
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => media_box.clone(),
+            "Contents" => content_id,
+            "Resources" => resources_id,
+        });
+        page_ids.push(page_id);
+    }
+
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => page_ids.iter().map(|id| Object::Reference(*id)).collect::<Vec<_>>(),
+            "Count" => page_ids.len() as i64,
+        }),
+    );
+
+    let mut catalog = dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    };
+    if !outline.is_empty() {
+        let outlines_id = doc.new_object_id();
+        let (first, last, count) = write_outline_items(&mut doc, outline, outlines_id, &page_ids);
+        doc.objects.insert(
+            outlines_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Outlines",
+                "First" => first,
+                "Last" => last,
+                "Count" => count,
+            }),
+        );
+        catalog.set("Outlines", outlines_id);
+        catalog.set("PageMode", "UseOutlines");
+    }
+
+    let xmp = metadata_to_xmp_packet(mi);
+    let metadata_id = doc.add_object(
+        Stream::new(
+            dictionary! { "Type" => "Metadata", "Subtype" => "XML" },
+            xmp.into_bytes(),
+        )
+        .with_compression(false),
+    );
+    catalog.set("Metadata", metadata_id);
+    if let Some(lang) = mi.languages.iter().find(|l| l.as_str() != "und") {
+        catalog.set("Lang", Object::string_literal(lang.as_str()));
+    }
+    let catalog_id = doc.add_object(catalog);
+    doc.trailer.set("Root", catalog_id);
+
+    let now = Utc::now().format("D:%Y%m%d%H%M%SZ").to_string();
+    let producer = format!("{} {}", APP_NAME, VERSION);
+    let mut info = dictionary! {
+        "Title" => text_string(&mi.title),
+        "Author" => text_string(&mi.authors.join(" & ")),
+        "Creator" => text_string(&producer),
+        "Producer" => text_string(&producer),
+        "CreationDate" => Object::string_literal(now.as_str()),
+        "ModDate" => Object::string_literal(now.as_str()),
+    };
+    if !mi.tags.is_empty() {
+        info.set("Keywords", text_string(&mi.tags.join(", ")));
+    }
+    if let Some(comments) = &mi.comments {
+        info.set("Subject", text_string(comments));
+    }
+    let info_id = doc.add_object(info);
+    doc.trailer.set("Info", info_id);
+
+    doc
+}
+
+/// The fonts the page content refers to, collected while encoding its text.
+#[derive(Default)]
+struct FontUsage {
+    standard: BTreeSet<FontKey>,
+    /// Embedded fonts with the glyphs drawn and the characters they show
+    embedded: BTreeMap<FontKey, (Arc<EmbeddedFont>, BTreeMap<u16, char>)>,
+    /// Characters no font could show
+    substituted: BTreeSet<char>,
+}
+
+impl FontUsage {
+    /// The resource name of the font to draw `text` with, and the string
+    /// to show.
+    fn encode(&mut self, font: FontKey, text: &str) -> (String, Vec<u8>) {
+        if let Some(embedded) = font.embedded_for(text) {
+            let (_, glyphs) = self
+                .embedded
+                .entry(font)
+                .or_insert_with(|| (embedded.clone(), BTreeMap::new()));
+            let mut bytes = Vec::with_capacity(text.len() * 2);
+            for c in text.chars() {
+                let glyph = embedded.glyph_id(c);
+                if glyph == 0 {
+                    self.substituted.insert(c);
+                }
+                glyphs.entry(glyph).or_insert(c);
+                bytes.extend_from_slice(&glyph.to_be_bytes());
+            }
+            return (font.embedded_resource_name(), bytes);
+        }
+        self.standard.insert(font);
+        self.substituted
+            .extend(text.chars().filter(|c| win_ansi_code(*c).is_none()));
+        (font.resource_name(), encode_text(text))
+    }
+}
+
+/// Add a Type0 font drawing glyph ids (Identity-H) from an embedded
+/// TrueType face, with a ToUnicode map so that its text can be extracted.
+fn add_embedded_font(
+    doc: &mut Document,
+    key: FontKey,
+    font: &EmbeddedFont,
+    glyphs: &BTreeMap<u16, char>,
+) -> ObjectId {
+    let data = font.data();
+    let font_file = doc.add_object(
+        Stream::new(
+            dictionary! {
+                "Length1" => data.len() as i64,
+                "Filter" => "FlateDecode",
+            },
+            deflate(data),
+        )
+        .with_compression(false),
+    );
+    let ascent = font.ascent().round();
+    let descent = font.descent().round();
+    let descriptor = doc.add_object(dictionary! {
+        "Type" => "FontDescriptor",
+        "FontName" => font.name.as_str(),
+        // Symbolic, glyphs are not addressed through a standard encoding
+        "Flags" => if key.italic { 4 + 64 } else { 4 },
+        "FontBBox" => vec![0.into(), Object::Real(descent), 1000.into(), Object::Real(ascent)],
+        "ItalicAngle" => if key.italic { -12 } else { 0 },
+        "Ascent" => Object::Real(ascent),
+        "Descent" => Object::Real(descent),
+        "CapHeight" => Object::Real(ascent),
+        "StemV" => if key.bold { 120 } else { 80 },
+        "FontFile2" => font_file,
+    });
+
+    // Runs of consecutive glyph ids share an array of widths
+    let mut widths: Vec<Object> = Vec::new();
+    let mut run: Vec<Object> = Vec::new();
+    let mut prev: Option<u16> = None;
+    for &glyph in glyphs.keys() {
+        if prev.is_some_and(|p| p + 1 != glyph) {
+            widths.push(Object::Array(std::mem::take(&mut run)));
+        }
+        if run.is_empty() {
+            widths.push((glyph as i64).into());
+        }
+        run.push((font.glyph_width(glyph) as i64).into());
+        prev = Some(glyph);
+    }
+    if !run.is_empty() {
+        widths.push(Object::Array(run));
+    }
+    let cid_font = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "CIDFontType2",
+        "BaseFont" => font.name.as_str(),
+        "CIDSystemInfo" => dictionary! {
+            "Registry" => Object::string_literal("Adobe"),
+            "Ordering" => Object::string_literal("Identity"),
+            "Supplement" => 0,
+        },
+        "FontDescriptor" => descriptor,
+        "CIDToGIDMap" => "Identity",
+        "W" => widths,
+    });
+
+    let to_unicode = doc.add_object(
+        Stream::new(Dictionary::new(), to_unicode_cmap(glyphs).into_bytes())
+            .with_compression(false),
+    );
+    doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type0",
+        "BaseFont" => font.name.as_str(),
+        "Encoding" => "Identity-H",
+        "DescendantFonts" => vec![Object::Reference(cid_font)],
+        "ToUnicode" => to_unicode,
+    })
+}
+
+/// A CMap from two byte glyph ids to the characters they show.
+fn to_unicode_cmap(glyphs: &BTreeMap<u16, char>) -> String {
+    let mut cmap = String::from(
+        "/CIDInit /ProcSet findresource begin\n\
+         12 dict begin\n\
+         begincmap\n\
+         /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+         /CMapName /Adobe-Identity-UCS def\n\
+         /CMapType 2 def\n\
+         1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n",
+    );
+    let mapped: Vec<(&u16, &char)> = glyphs.iter().filter(|(g, _)| **g != 0).collect();
+    // At most 100 mappings are allowed per block
+    for block in mapped.chunks(100) {
+        cmap.push_str(&format!("{} beginbfchar\n", block.len()));
+        for (glyph, c) in block {
+            let mut units = [0u16; 2];
+            let hex: String = c
+                .encode_utf16(&mut units)
+                .iter()
+                .map(|u| format!("{:04X}", u))
+                .collect();
+            cmap.push_str(&format!("<{:04X}> <{}>\n", glyph, hex));
+        }
+        cmap.push_str("endbfchar\n");
+    }
+    cmap.push_str(
+        "endcmap\n\
+         CMapName currentdict /CMap defineresource pop\n\
+         end\n\
+         end\n",
+    );
+    cmap
+}
+
+fn page_operations(page: &Page, fonts: &mut FontUsage) -> Vec<Operation> {
+    let mut ops = Vec::new();
+    for op in &page.ops {
+        match op {
+            DrawOp::Text {
+                x,
+                y,
+                font,
+                size,
+                color,
+                text,
+            } => {
+                let (resource, encoded) = fonts.encode(*font, text);
+                ops.push(Operation::new("BT", vec![]));
+                ops.push(Operation::new("Tf", vec![resource.into(), num(*size)]));
+                ops.push(Operation::new(
+                    "rg",
+                    vec![num(color.0), num(color.1), num(color.2)],
+                ));
+                ops.push(Operation::new("Td", vec![num(*x), num(*y)]));
+                ops.push(Operation::new(
+                    "Tj",
+                    vec![Object::String(encoded, StringFormat::Hexadecimal)],
+                ));
+                ops.push(Operation::new("ET", vec![]));
+            }
+            DrawOp::Image {
+                x,
+                y,
+                width,
+                height,
+                index,
+            } => {
+                ops.push(Operation::new("q", vec![]));
+                ops.push(Operation::new(
+                    "cm",
+                    vec![
+                        num(*width),
+                        0.into(),
+                        0.into(),
+                        num(*height),
+                        num(*x),
+                        num(*y),
+                    ],
+                ));
+                ops.push(Operation::new("Do", vec![image_name(*index).into()]));
+                ops.push(Operation::new("Q", vec![]));
+            }
+            DrawOp::Rule {
+                x1,
+                y1,
+                x2,
+                y2,
+                width,
+                color,
+            } => {
+                ops.push(Operation::new(
+                    "RG",
+                    vec![num(color.0), num(color.1), num(color.2)],
+                ));
+                ops.push(Operation::new("w", vec![num(*width)]));
+                ops.push(Operation::new("m", vec![num(*x1), num(*y1)]));
+                ops.push(Operation::new("l", vec![num(*x2), num(*y2)]));
+                ops.push(Operation::new("S", vec![]));
+            }
+        }
+    }
+    ops
+}
+
+/// Write one level of outline items below `parent`. Returns the first and
+/// last item ids and the number of visible descendants.
+fn write_outline_items(
+    doc: &mut Document,
+    entries: &[OutlineEntry],
+    parent: ObjectId,
+    page_ids: &[ObjectId],
+) -> (ObjectId, ObjectId, i64) {
+    let ids: Vec<ObjectId> = entries.iter().map(|_| doc.new_object_id()).collect();
+    let mut count = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        let mut item = dictionary! {
+            "Title" => text_string(&entry.title),
+            "Parent" => parent,
+        };
+        if i > 0 {
+            item.set("Prev", ids[i - 1]);
+        }
+        if i + 1 < ids.len() {
+            item.set("Next", ids[i + 1]);
+        }
+        // Entries pointing outside the book still get a usable target
+        let dest = entry.dest.unwrap_or(Destination { page: 0, y: 0.0 });
+        if let Some(page_id) = page_ids.get(dest.page).or_else(|| page_ids.last()) {
+            let top = if entry.dest.is_some() {
+                num(dest.y)
+            } else {
+                Object::Null
+            };
+            item.set(
+                "Dest",
+                vec![
+                    Object::Reference(*page_id),
+                    "XYZ".into(),
+                    Object::Null,
+                    top,
+                    Object::Null,
+                ],
+            );
+        }
+        count += 1;
+        if !entry.children.is_empty() {
+            let (first, last, sub) = write_outline_items(doc, &entry.children, ids[i], page_ids);
+            item.set("First", first);
+            item.set("Last", last);
+            // Nested levels start out collapsed
+            item.set("Count", -sub);
+        }
+        doc.objects.insert(ids[i], Object::Dictionary(item));
+    }
+    (ids[0], ids[ids.len() - 1], count)
+}
+
+fn image_name(idx: usize) -> String {
+    format!("Im{}", idx + 1)
+}
+
+fn num(v: f32) -> Object {
+    Object::Real((v * 100.0).round() / 100.0)
+}
+
+/// Encode a PDF text string, using UTF-16BE when it is not plain ASCII.
+fn text_string(s: &str) -> Object {
+    if s.is_ascii() {
+        Object::string_literal(s)
+    } else {
+        let mut bytes = vec![0xFE, 0xFF];
+        for unit in s.encode_utf16() {
+            bytes.extend_from_slice(&unit.to_be_bytes());
+        }
+        Object::String(bytes, StringFormat::Hexadecimal)
+    }
+}
@@ -0,0 +1,4 @@
+pub mod reader;
+pub mod to_html;
+pub mod tokenizer;
+pub mod writer;
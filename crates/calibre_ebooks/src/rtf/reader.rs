@@ -0,0 +1,1107 @@
+//! Interprets an RTF token stream into a small document model.
+
+use crate::rtf::tokenizer::{Token, Tokenizer};
+use encoding_rs::{Encoding, WINDOWS_1252};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Align {
+    #[default]
+    Left,
+    Right,
+    Center,
+    Justify,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CharFormat {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strike: bool,
+    pub superscript: bool,
+    pub subscript: bool,
+    pub hidden: bool,
+    pub font: Option<i32>,
+    /// In half points, as in `\fsN`.
+    pub font_size: i32,
+    /// Index into the colour table; 0 is the automatic colour.
+    pub color: i32,
+    pub link: Option<String>,
+}
+
+impl Default for CharFormat {
+    fn default() -> Self {
+        CharFormat {
+            bold: false,
+            italic: false,
+            underline: false,
+            strike: false,
+            superscript: false,
+            subscript: false,
+            hidden: false,
+            font: None,
+            font_size: 24,
+            color: 0,
+            link: None,
+        }
+    }
+}
+
+/// Paragraph properties. Lengths are in twips (1/20 pt).
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ParaFormat {
+    pub align: Align,
+    pub left_indent: i32,
+    pub right_indent: i32,
+    pub first_indent: i32,
+    pub space_before: i32,
+    pub space_after: i32,
+    pub style: Option<i32>,
+    pub outline_level: Option<i32>,
+    pub in_table: bool,
+    /// `\lsN` list override number and `\ilvlN` nesting level.
+    pub list: Option<i32>,
+    pub list_level: i32,
+    pub page_break_before: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inline {
+    Text(String, CharFormat),
+    LineBreak,
+    /// Index into `RtfDocument::images` and the display size in CSS pixels.
+    Image(usize, Option<(u32, u32)>),
+    /// Reference to footnote number N (1 based).
+    NoteRef(usize),
+    /// Bookmark target.
+    Anchor(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Paragraph {
+    pub format: ParaFormat,
+    pub inlines: Vec<Inline>,
+    /// Literal list marker text (`\listtext`, `\pntext`) as written by Word.
+    pub list_text: String,
+}
+
+impl Paragraph {
+    pub fn is_blank(&self) -> bool {
+        self.inlines.iter().all(|i| match i {
+            Inline::Text(t, _) => t.trim().is_empty(),
+            Inline::Anchor(_) => true,
+            _ => false,
+        })
+    }
+}
+
+pub type TableRow = Vec<Vec<Paragraph>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    Paragraph(Paragraph),
+    Table(Vec<TableRow>),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Font {
+    pub name: String,
+    /// `\fmodern` and friends, without the leading `f`.
+    pub family: String,
+    pub charset: Option<i32>,
+    pub codepage: Option<i32>,
+}
+
+impl Font {
+    pub fn is_monospace(&self) -> bool {
+        let name = self.name.to_ascii_lowercase();
+        self.family == "modern" || name.contains("courier") || name.contains("mono")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RtfImage {
+    pub data: Vec<u8>,
+    /// File extension for `data`: png, jpg or bmp.
+    pub ext: &'static str,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DocInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RtfDocument {
+    pub blocks: Vec<Block>,
+    pub footnotes: Vec<Vec<Paragraph>>,
+    pub images: Vec<RtfImage>,
+    pub fonts: HashMap<i32, Font>,
+    /// `None` marks the automatic colour.
+    pub colors: Vec<Option<(u8, u8, u8)>>,
+    pub styles: HashMap<i32, String>,
+    /// List override number to whether each level is numbered.
+    pub lists: HashMap<i32, Vec<bool>>,
+    pub info: DocInfo,
+    pub default_font: Option<i32>,
+    /// Windows LCID from `\deflang` or the first `\lang`.
+    pub language: Option<i32>,
+}
+
+impl RtfDocument {
+    pub fn parse(data: &[u8]) -> RtfDocument {
+        let mut reader = Reader::new();
+        for token in Tokenizer::new(data) {
+            reader.token(token);
+        }
+        reader.finish()
+    }
+
+    pub fn is_ordered_list(&self, list: i32, level: i32) -> bool {
+        self.lists
+            .get(&list)
+            .and_then(|levels| levels.get(level.max(0) as usize).or(levels.first()))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Heading level (1-6) of a paragraph, from its outline level or the
+    /// name of its paragraph style.
+    pub fn heading_level(&self, format: &ParaFormat) -> Option<u8> {
+        if let Some(level) = format.outline_level {
+            if (0..6).contains(&level) {
+                return Some(level as u8 + 1);
+            }
+        }
+        let name = self.styles.get(&format.style?)?.to_ascii_lowercase();
+        // Word appends aliases after a comma, e.g. "heading 1,H1"
+        let name = name.split(',').next().unwrap_or_default().trim();
+        if name == "title" {
+            return Some(1);
+        }
+        let level = name.strip_prefix("heading")?.trim().parse::<u8>().ok()?;
+        if (1..=6).contains(&level) {
+            Some(level)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Dest {
+    Normal,
+    Skip,
+    FontTable,
+    ColorTable,
+    StyleSheet,
+    Info,
+    InfoField(InfoField),
+    Pict,
+    FieldInst,
+    ListTable,
+    ListOverrideTable,
+    ListText,
+    Bookmark,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InfoField {
+    Title,
+    Author,
+    Subject,
+    Keywords,
+    Comment,
+}
+
+#[derive(Debug, Clone)]
+struct State {
+    chr: CharFormat,
+    para: ParaFormat,
+    dest: Dest,
+    /// Set on the group that switched to `dest`, so its end can be handled.
+    dest_started: bool,
+    field_started: bool,
+    /// Number of fallback characters after `\uN`.
+    uc: usize,
+    ignorable: bool,
+}
+
+#[derive(Default)]
+struct Sink {
+    blocks: Vec<Block>,
+    para: Paragraph,
+    cell: Vec<Paragraph>,
+    row: TableRow,
+    table: Vec<TableRow>,
+    page_break: bool,
+}
+
+impl Sink {
+    fn flush_table(&mut self) {
+        if !self.cell.is_empty() {
+            self.row.push(std::mem::take(&mut self.cell));
+        }
+        if !self.row.is_empty() {
+            self.table.push(std::mem::take(&mut self.row));
+        }
+        if !self.table.is_empty() {
+            self.blocks
+                .push(Block::Table(std::mem::take(&mut self.table)));
+        }
+    }
+}
+
+#[derive(Default)]
+struct Pict {
+    ext: Option<&'static str>,
+    hex: Vec<u8>,
+    binary: Vec<u8>,
+    width: i32,
+    height: i32,
+    goal_width: i32,
+    goal_height: i32,
+    scale_x: i32,
+    scale_y: i32,
+}
+
+#[derive(Default)]
+struct Field {
+    inst: String,
+    link: Option<String>,
+}
+
+struct Reader {
+    doc: RtfDocument,
+    stack: Vec<State>,
+    state: State,
+    sinks: Vec<Sink>,
+    default_codepage: &'static Encoding,
+    pending: Vec<u8>,
+    skip: usize,
+    /// Scratch text for table entries and other destinations.
+    buf: String,
+    font_no: Option<i32>,
+    font: Font,
+    rgb: Option<(u8, u8, u8)>,
+    style_no: Option<i32>,
+    pict: Pict,
+    fields: Vec<Field>,
+    list_levels: Vec<bool>,
+    list_defs: HashMap<i32, Vec<bool>>,
+    override_list: Option<i32>,
+    high_surrogate: Option<u16>,
+}
+
+impl Reader {
+    fn new() -> Self {
+        Reader {
+            doc: RtfDocument::default(),
+            stack: Vec::new(),
+            state: State {
+                chr: CharFormat::default(),
+                para: ParaFormat::default(),
+                dest: Dest::Normal,
+                dest_started: false,
+                field_started: false,
+                uc: 1,
+                ignorable: false,
+            },
+            sinks: vec![Sink::default()],
+            default_codepage: WINDOWS_1252,
+            pending: Vec::new(),
+            skip: 0,
+            buf: String::new(),
+            font_no: None,
+            font: Font::default(),
+            rgb: None,
+            style_no: None,
+            pict: Pict::default(),
+            fields: Vec::new(),
+            list_levels: Vec::new(),
+            list_defs: HashMap::new(),
+            override_list: None,
+            high_surrogate: None,
+        }
+    }
+
+    fn finish(mut self) -> RtfDocument {
+        self.flush_bytes();
+        // Unterminated footnotes
+        while self.sinks.len() > 1 {
+            self.end_footnote();
+        }
+        let mut sink = self.sinks.pop().unwrap_or_default();
+        if !sink.para.inlines.is_empty() {
+            let mut para = std::mem::take(&mut sink.para);
+            para.format = self.state.para.clone();
+            if para.format.in_table {
+                sink.cell.push(para);
+            } else {
+                sink.flush_table();
+                sink.blocks.push(Block::Paragraph(para));
+            }
+        }
+        sink.flush_table();
+        self.doc.blocks = sink.blocks;
+        self.doc
+    }
+
+    fn sink(&mut self) -> &mut Sink {
+        self.sinks.last_mut().expect("main sink is never popped")
+    }
+
+    fn codepage(&self) -> &'static Encoding {
+        self.state
+            .chr
+            .font
+            .and_then(|f| self.doc.fonts.get(&f))
+            .and_then(|f| {
+                f.codepage
+                    .and_then(codepage_encoding)
+                    .or_else(|| f.charset.and_then(charset_encoding))
+            })
+            .unwrap_or(self.default_codepage)
+    }
+
+    fn token(&mut self, token: Token) {
+        match token {
+            Token::Text(bytes) => {
+                for b in bytes {
+                    if self.skip > 0 {
+                        self.skip -= 1;
+                    } else {
+                        self.byte(b);
+                    }
+                }
+            }
+            Token::HexByte(b) => {
+                if self.skip > 0 {
+                    self.skip -= 1;
+                } else {
+                    self.byte(b);
+                }
+            }
+            Token::Binary(bytes) => {
+                self.skip = 0;
+                if self.state.dest == Dest::Pict {
+                    self.pict.binary.extend_from_slice(&bytes);
+                }
+            }
+            Token::GroupStart => {
+                self.flush_bytes();
+                self.skip = 0;
+                let mut child = self.state.clone();
+                child.dest_started = false;
+                child.field_started = false;
+                child.ignorable = false;
+                if child.dest == Dest::StyleSheet {
+                    // Entries without \sN define the Normal style
+                    self.style_no = Some(0);
+                    self.buf.clear();
+                }
+                self.stack.push(std::mem::replace(&mut self.state, child));
+            }
+            Token::GroupEnd => {
+                self.flush_bytes();
+                self.skip = 0;
+                self.end_group();
+                if let Some(parent) = self.stack.pop() {
+                    self.state = parent;
+                }
+            }
+            Token::ControlSymbol(c) => {
+                if self.skip > 0 {
+                    self.skip -= 1;
+                    return;
+                }
+                self.symbol(c);
+            }
+            Token::ControlWord(word, param) => {
+                if self.skip > 0 && word != "u" {
+                    self.skip -= 1;
+                    return;
+                }
+                self.flush_bytes();
+                let ignorable = std::mem::take(&mut self.state.ignorable);
+                self.control(&word, param, ignorable);
+            }
+        }
+    }
+
+    /// Route one byte of text according to the current destination.
+    fn byte(&mut self, b: u8) {
+        match self.state.dest {
+            Dest::Skip => {}
+            Dest::Pict => {
+                if b.is_ascii_hexdigit() {
+                    self.pict.hex.push(b);
+                }
+            }
+            Dest::ColorTable => {
+                if b == b';' {
+                    self.doc.colors.push(self.rgb.take());
+                }
+            }
+            Dest::FontTable | Dest::StyleSheet => {
+                if b == b';' {
+                    self.flush_bytes();
+                    self.end_table_entry();
+                } else {
+                    self.pending.push(b);
+                }
+            }
+            _ => self.pending.push(b),
+        }
+    }
+
+    /// Decode buffered text bytes with the active code page.
+    fn flush_bytes(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let bytes = std::mem::take(&mut self.pending);
+        let (text, _, _) = self.codepage().decode(&bytes);
+        let text = text.into_owned();
+        self.text(&text);
+    }
+
+    fn text(&mut self, text: &str) {
+        match self.state.dest {
+            Dest::Normal => {
+                if self.state.chr.hidden {
+                    return;
+                }
+                let fmt = self.state.chr.clone();
+                let para = &mut self.sink().para;
+                if let Some(Inline::Text(last, last_fmt)) = para.inlines.last_mut() {
+                    if *last_fmt == fmt {
+                        last.push_str(text);
+                        return;
+                    }
+                }
+                para.inlines.push(Inline::Text(text.to_string(), fmt));
+            }
+            Dest::FontTable | Dest::StyleSheet | Dest::Bookmark => self.buf.push_str(text),
+            Dest::InfoField(_) => self.buf.push_str(text),
+            Dest::FieldInst => {
+                if let Some(field) = self.fields.last_mut() {
+                    field.inst.push_str(text);
+                }
+            }
+            Dest::ListText => self.sink().para.list_text.push_str(text),
+            _ => {}
+        }
+    }
+
+    fn end_table_entry(&mut self) {
+        let name = std::mem::take(&mut self.buf).trim().to_string();
+        match self.state.dest {
+            Dest::FontTable => {
+                if let Some(no) = self.font_no.take() {
+                    let mut font = std::mem::take(&mut self.font);
+                    font.name = name;
+                    self.doc.fonts.insert(no, font);
+                }
+            }
+            Dest::StyleSheet => {
+                if let Some(no) = self.style_no.take() {
+                    self.doc.styles.insert(no, name);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn symbol(&mut self, c: u8) {
+        match c {
+            b'*' => self.state.ignorable = true,
+            b'\\' | b'{' | b'}' => self.pending.push(c),
+            b'~' => self.unicode('\u{a0}'),
+            b'_' => self.unicode('\u{2011}'),
+            b'-' => {}
+            b'\t' => self.control("tab", None, false),
+            _ => {}
+        }
+    }
+
+    fn unicode(&mut self, c: char) {
+        self.flush_bytes();
+        let mut tmp = [0u8; 4];
+        self.text(c.encode_utf8(&mut tmp));
+    }
+
+    fn set_dest(&mut self, dest: Dest) {
+        self.state.dest = dest;
+        self.state.dest_started = true;
+    }
+
+    fn end_paragraph(&mut self) {
+        let format = self.state.para.clone();
+        let sink = self.sink();
+        let mut para = std::mem::take(&mut sink.para);
+        para.format = format;
+        if std::mem::take(&mut sink.page_break) {
+            para.format.page_break_before = true;
+        }
+        if para.format.in_table {
+            sink.cell.push(para);
+        } else {
+            sink.flush_table();
+            sink.blocks.push(Block::Paragraph(para));
+        }
+    }
+
+    fn end_footnote(&mut self) {
+        let mut sink = self.sinks.pop().unwrap_or_default();
+        if !sink.para.inlines.is_empty() {
+            sink.para.format = self.state.para.clone();
+            sink.blocks
+                .push(Block::Paragraph(std::mem::take(&mut sink.para)));
+        }
+        sink.flush_table();
+        let mut paras = Vec::new();
+        for block in sink.blocks {
+            match block {
+                Block::Paragraph(p) => paras.push(p),
+                Block::Table(rows) => {
+                    paras.extend(rows.into_iter().flatten().flatten());
+                }
+            }
+        }
+        self.doc.footnotes.push(paras);
+    }
+
+    fn end_group(&mut self) {
+        if self.state.field_started {
+            self.fields.pop();
+        }
+        if !self.state.dest_started {
+            return;
+        }
+        match self.state.dest {
+            Dest::FontTable | Dest::StyleSheet => {
+                if !self.buf.trim().is_empty() {
+                    self.end_table_entry();
+                }
+                self.buf.clear();
+            }
+            Dest::InfoField(field) => {
+                let value = std::mem::take(&mut self.buf).trim().to_string();
+                if !value.is_empty() {
+                    let info = &mut self.doc.info;
+                    let slot = match field {
+                        InfoField::Title => &mut info.title,
+                        InfoField::Author => &mut info.author,
+                        InfoField::Subject => &mut info.subject,
+                        InfoField::Keywords => &mut info.keywords,
+                        InfoField::Comment => &mut info.comment,
+                    };
+                    *slot = Some(value);
+                }
+            }
+            Dest::Pict => self.end_pict(),
+            Dest::FieldInst => {
+                if let Some(field) = self.fields.last_mut() {
+                    field.link = parse_hyperlink(&field.inst);
+                }
+            }
+            Dest::Bookmark => {
+                let name = std::mem::take(&mut self.buf).trim().to_string();
+                if !name.is_empty() {
+                    self.sink().para.inlines.push(Inline::Anchor(name));
+                }
+            }
+            // Only footnotes open a new group with the normal destination
+            Dest::Normal if self.sinks.len() > 1 => self.end_footnote(),
+            _ => {}
+        }
+    }
+
+    fn end_pict(&mut self) {
+        let pict = std::mem::take(&mut self.pict);
+        let ext = match pict.ext {
+            Some(ext) => ext,
+            None => return,
+        };
+        let mut data = pict.binary;
+        if data.is_empty() {
+            data = pict
+                .hex
+                .chunks(2)
+                .filter(|c| c.len() == 2)
+                .filter_map(|c| {
+                    std::str::from_utf8(c)
+                        .ok()
+                        .and_then(|h| u8::from_str_radix(h, 16).ok())
+                })
+                .collect();
+        }
+        if data.is_empty() {
+            return;
+        }
+        let (data, ext) = match ext {
+            "dib" => match dib_to_bmp(&data) {
+                Some(bmp) => (bmp, "bmp"),
+                None => return,
+            },
+            _ => (data, ext),
+        };
+
+        let scale = |v: i32, s: i32| if s > 0 { v * s / 100 } else { v };
+        let size = if pict.goal_width > 0 && pict.goal_height > 0 {
+            // twips to CSS pixels
+            Some((
+                (scale(pict.goal_width, pict.scale_x) / 15).max(1) as u32,
+                (scale(pict.goal_height, pict.scale_y) / 15).max(1) as u32,
+            ))
+        } else if pict.width > 0 && pict.height > 0 {
+            Some((
+                scale(pict.width, pict.scale_x) as u32,
+                scale(pict.height, pict.scale_y) as u32,
+            ))
+        } else {
+            None
+        };
+
+        self.doc.images.push(RtfImage { data, ext });
+        let idx = self.doc.images.len() - 1;
+        // The picture belongs to the enclosing text, not the pict group
+        let chr_hidden = self.stack.last().map(|s| s.chr.hidden).unwrap_or(false);
+        if !chr_hidden {
+            self.sink().para.inlines.push(Inline::Image(idx, size));
+        }
+    }
+
+    fn control(&mut self, word: &str, param: Option<i32>, ignorable: bool) {
+        let dest = self.state.dest;
+        if dest == Dest::Skip {
+            return;
+        }
+        let on = param.map(|p| p != 0).unwrap_or(true);
+        let n = param.unwrap_or(0);
+
+        // Destinations
+        match word {
+            "fonttbl" => return self.set_dest(Dest::FontTable),
+            "colortbl" => return self.set_dest(Dest::ColorTable),
+            "stylesheet" => return self.set_dest(Dest::StyleSheet),
+            "info" => return self.set_dest(Dest::Info),
+            "title" if dest == Dest::Info => {
+                return self.set_dest(Dest::InfoField(InfoField::Title))
+            }
+            "author" if dest == Dest::Info => {
+                return self.set_dest(Dest::InfoField(InfoField::Author))
+            }
+            "subject" if dest == Dest::Info => {
+                return self.set_dest(Dest::InfoField(InfoField::Subject))
+            }
+            "keywords" if dest == Dest::Info => {
+                return self.set_dest(Dest::InfoField(InfoField::Keywords))
+            }
+            "doccomm" if dest == Dest::Info => {
+                return self.set_dest(Dest::InfoField(InfoField::Comment))
+            }
+            "pict" => {
+                self.pict = Pict::default();
+                return self.set_dest(Dest::Pict);
+            }
+            "field" => {
+                self.fields.push(Field::default());
+                self.state.field_started = true;
+                return;
+            }
+            "fldinst" => return self.set_dest(Dest::FieldInst),
+            "fldrslt" => {
+                self.state.dest = Dest::Normal;
+                if let Some(link) = self.fields.last().and_then(|f| f.link.clone()) {
+                    self.state.chr.link = Some(link);
+                }
+                return;
+            }
+            "footnote" => {
+                self.flush_bytes();
+                let n = self.doc.footnotes.len() + self.sinks.len();
+                self.sink().para.inlines.push(Inline::NoteRef(n));
+                self.sinks.push(Sink::default());
+                self.state.dest = Dest::Normal;
+                self.state.dest_started = true;
+                self.state.chr = CharFormat {
+                    font: self.doc.default_font,
+                    ..CharFormat::default()
+                };
+                self.state.para = ParaFormat::default();
+                return;
+            }
+            "listtable" => return self.set_dest(Dest::ListTable),
+            "listoverridetable" => return self.set_dest(Dest::ListOverrideTable),
+            "listtext" | "pntext" => return self.set_dest(Dest::ListText),
+            "bkmkstart" => return self.set_dest(Dest::Bookmark),
+            "shppict" | "result" | "object" | "shprslt" => return,
+            "nonshppict" | "header" | "headerl" | "headerr" | "headerf" | "footer" | "footerl"
+            | "footerr" | "footerf" | "ftnsep" | "ftnsepc" | "ftncn" | "aftnsep" | "aftnsepc"
+            | "aftncn" | "xe" | "tc" | "tcn" | "rxe" | "txe" | "template" | "filetbl"
+            | "revtbl" | "rsidtbl" | "generator" | "userprops" | "docvar" | "leveltext"
+            | "levelnumbers" | "listname" | "pgdsctbl" | "latentstyles" | "datastore"
+            | "themedata" | "colorschememapping" | "objdata" | "bkmkend" | "pn" | "operator"
+            | "creatim" | "revtim" | "printim" | "buptim" | "company" | "manager" | "category"
+            | "hlinkbase" => return self.set_dest(Dest::Skip),
+            _ => {}
+        }
+        if ignorable && !matches!(dest, Dest::ListTable | Dest::ListOverrideTable) {
+            // Unknown destination marked as ignorable with \*
+            return self.set_dest(Dest::Skip);
+        }
+
+        match dest {
+            Dest::FontTable => {
+                match word {
+                    "f" => {
+                        self.font_no = Some(n);
+                        self.font = Font::default();
+                        self.buf.clear();
+                    }
+                    "fcharset" => self.font.charset = Some(n),
+                    "cpg" => self.font.codepage = Some(n),
+                    "fnil" | "froman" | "fswiss" | "fmodern" | "fscript" | "fdecor" | "ftech"
+                    | "fbidi" => self.font.family = word[1..].to_string(),
+                    _ => {}
+                }
+                return;
+            }
+            Dest::ColorTable => {
+                let v = n.clamp(0, 255) as u8;
+                let rgb = self.rgb.get_or_insert((0, 0, 0));
+                match word {
+                    "red" => rgb.0 = v,
+                    "green" => rgb.1 = v,
+                    "blue" => rgb.2 = v,
+                    _ => {}
+                }
+                return;
+            }
+            Dest::StyleSheet => {
+                if matches!(word, "s" | "cs" | "ds" | "ts") {
+                    self.style_no = if word == "s" { Some(n) } else { None };
+                    self.buf.clear();
+                }
+                return;
+            }
+            Dest::ListTable => {
+                match word {
+                    "list" => self.list_levels.clear(),
+                    "listlevel" => self.list_levels.push(false),
+                    "levelnfc" | "levelnfcn" => {
+                        if let Some(level) = self.list_levels.last_mut() {
+                            // 23 is a bullet, 255 no number at all
+                            *level = n != 23 && n != 255;
+                        }
+                    }
+                    "listid" => {
+                        self.list_defs
+                            .insert(n, std::mem::take(&mut self.list_levels));
+                    }
+                    _ => {}
+                }
+                return;
+            }
+            Dest::ListOverrideTable => {
+                match word {
+                    "listoverride" => self.override_list = None,
+                    "listid" => self.override_list = Some(n),
+                    "ls" => {
+                        if let Some(id) = self.override_list {
+                            let levels = self.list_defs.get(&id).cloned().unwrap_or_default();
+                            self.doc.lists.insert(n, levels);
+                        }
+                    }
+                    _ => {}
+                }
+                return;
+            }
+            Dest::Pict => {
+                let p = &mut self.pict;
+                match word {
+                    "pngblip" => p.ext = Some("png"),
+                    "jpegblip" => p.ext = Some("jpg"),
+                    "dibitmap" => p.ext = Some("dib"),
+                    // Metafiles cannot be displayed by reading systems
+                    "wmetafile" | "emfblip" | "macpict" | "pmmetafile" | "wbitmap" => p.ext = None,
+                    "picw" => p.width = n,
+                    "pich" => p.height = n,
+                    "picwgoal" => p.goal_width = n,
+                    "pichgoal" => p.goal_height = n,
+                    "picscalex" => p.scale_x = n,
+                    "picscaley" => p.scale_y = n,
+                    _ => {}
+                }
+                return;
+            }
+            Dest::Skip | Dest::Info => return,
+            _ => {}
+        }
+
+        match word {
+            // Document
+            "ansicpg" => {
+                if let Some(enc) = codepage_encoding(n) {
+                    self.default_codepage = enc;
+                }
+            }
+            "mac" => self.default_codepage = codepage_encoding(10000).unwrap_or(WINDOWS_1252),
+            "pc" => self.default_codepage = codepage_encoding(437).unwrap_or(WINDOWS_1252),
+            "pca" => self.default_codepage = codepage_encoding(850).unwrap_or(WINDOWS_1252),
+            "deff" => {
+                self.doc.default_font = Some(n);
+                if self.state.chr.font.is_none() {
+                    self.state.chr.font = Some(n);
+                }
+            }
+            "deflang" | "lang" if self.doc.language.is_none() && n > 0 && n != 1024 => {
+                self.doc.language = Some(n);
+            }
+            "uc" => self.state.uc = n.max(0) as usize,
+            "u" => {
+                let code = if n < 0 { n + 65536 } else { n } as u32;
+                self.unicode_code(code);
+                self.skip = self.state.uc;
+            }
+
+            // Special characters
+            "par" if dest == Dest::Normal => self.end_paragraph(),
+            "sect" if !self.sink().para.inlines.is_empty() => self.end_paragraph(),
+            "line" => self.sink().para.inlines.push(Inline::LineBreak),
+            "page" => {
+                if !self.sink().para.is_blank() {
+                    self.end_paragraph();
+                }
+                self.sink().page_break = true;
+            }
+            "tab" => self.unicode('\t'),
+            "emdash" => self.unicode('\u{2014}'),
+            "endash" => self.unicode('\u{2013}'),
+            "emspace" => self.unicode('\u{2003}'),
+            "enspace" => self.unicode('\u{2002}'),
+            "qmspace" => self.unicode('\u{2005}'),
+            "bullet" => self.unicode('\u{2022}'),
+            "lquote" => self.unicode('\u{2018}'),
+            "rquote" => self.unicode('\u{2019}'),
+            "ldblquote" => self.unicode('\u{201c}'),
+            "rdblquote" => self.unicode('\u{201d}'),
+            "zwj" => self.unicode('\u{200d}'),
+            "zwnj" => self.unicode('\u{200c}'),
+            "chftn" => {}
+
+            // Tables
+            "intbl" => self.state.para.in_table = true,
+            "cell" | "nestcell" => {
+                self.state.para.in_table = true;
+                let format = self.state.para.clone();
+                let sink = self.sink();
+                let mut para = std::mem::take(&mut sink.para);
+                if !para.inlines.is_empty() || sink.cell.is_empty() {
+                    para.format = format;
+                    sink.cell.push(para);
+                }
+                let cell = std::mem::take(&mut sink.cell);
+                sink.row.push(cell);
+            }
+            "row" | "nestrow" => {
+                let sink = self.sink();
+                if !sink.para.inlines.is_empty() {
+                    let mut para = std::mem::take(&mut sink.para);
+                    para.format.in_table = true;
+                    sink.cell.push(para);
+                }
+                if !sink.cell.is_empty() {
+                    let cell = std::mem::take(&mut sink.cell);
+                    sink.row.push(cell);
+                }
+                let row = std::mem::take(&mut sink.row);
+                if !row.is_empty() {
+                    sink.table.push(row);
+                }
+            }
+
+            // Paragraph formatting
+            "pard" => self.state.para = ParaFormat::default(),
+            "ql" => self.state.para.align = Align::Left,
+            "qr" => self.state.para.align = Align::Right,
+            "qc" => self.state.para.align = Align::Center,
+            "qj" | "qd" => self.state.para.align = Align::Justify,
+            "li" | "lin" => self.state.para.left_indent = n,
+            "ri" | "rin" => self.state.para.right_indent = n,
+            "fi" => self.state.para.first_indent = n,
+            "sb" => self.state.para.space_before = n,
+            "sa" => self.state.para.space_after = n,
+            "s" => self.state.para.style = Some(n),
+            "outlinelevel" => self.state.para.outline_level = Some(n),
+            "ls" => self.state.para.list = Some(n),
+            "ilvl" => self.state.para.list_level = n,
+            "pagebb" => self.state.para.page_break_before = true,
+
+            // Character formatting
+            "plain" => {
+                self.state.chr = CharFormat {
+                    font: self.doc.default_font,
+                    link: self.state.chr.link.take(),
+                    ..CharFormat::default()
+                };
+            }
+            "b" => self.state.chr.bold = on,
+            "i" => self.state.chr.italic = on,
+            "ul" | "uld" | "uldash" | "uldashd" | "uldashdd" | "uldb" | "ulth" | "ulw"
+            | "ulwave" => self.state.chr.underline = on,
+            "ulnone" => self.state.chr.underline = false,
+            "strike" | "striked" => self.state.chr.strike = on,
+            "super" => {
+                self.state.chr.superscript = true;
+                self.state.chr.subscript = false;
+            }
+            "sub" => {
+                self.state.chr.subscript = true;
+                self.state.chr.superscript = false;
+            }
+            "nosupersub" => {
+                self.state.chr.superscript = false;
+                self.state.chr.subscript = false;
+            }
+            "up" => self.state.chr.superscript = n > 0 || param.is_none(),
+            "dn" => self.state.chr.subscript = n > 0 || param.is_none(),
+            "v" => self.state.chr.hidden = on,
+            "f" => self.state.chr.font = Some(n),
+            "fs" => self.state.chr.font_size = if n > 0 { n } else { 24 },
+            "cf" => self.state.chr.color = n,
+            _ => {}
+        }
+    }
+
+    fn unicode_code(&mut self, code: u32) {
+        match code {
+            0xD800..=0xDBFF => self.high_surrogate = Some(code as u16),
+            0xDC00..=0xDFFF => {
+                if let Some(high) = self.high_surrogate.take() {
+                    if let Some(Ok(c)) =
+                        char::decode_utf16([high, code as u16].iter().copied()).next()
+                    {
+                        self.unicode(c);
+                    }
+                }
+            }
+            _ => {
+                self.high_surrogate = None;
+                if let Some(c) = char::from_u32(code) {
+                    self.unicode(c);
+                }
+            }
+        }
+    }
+}
+
+/// Target of a `HYPERLINK` field instruction, local bookmarks as `#name`.
+fn parse_hyperlink(inst: &str) -> Option<String> {
+    let inst = inst.trim();
+    let rest = inst.strip_prefix("HYPERLINK")?.trim();
+    let mut local = false;
+    let mut target = None;
+    let mut chars = rest.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some((_, 'l')) = chars.peek() {
+                    local = true;
+                }
+                chars.next();
+            }
+            '"' => {
+                let end = rest[i + 1..].find('"').map(|e| i + 1 + e)?;
+                target = Some(rest[i + 1..end].to_string());
+                break;
+            }
+            c if !c.is_whitespace() => {
+                let end = rest[i..]
+                    .find(char::is_whitespace)
+                    .map(|e| i + e)
+                    .unwrap_or(rest.len());
+                target = Some(rest[i..end].to_string());
+                break;
+            }
+            _ => {}
+        }
+    }
+    let target = target?.replace("\\\\", "\\");
+    if target.is_empty() {
+        return None;
+    }
+    if local {
+        Some(format!("#{}", target))
+    } else {
+        Some(target)
+    }
+}
+
+/// Wrap a packed device independent bitmap in a BMP file header.
+fn dib_to_bmp(dib: &[u8]) -> Option<Vec<u8>> {
+    let header_size = u32::from_le_bytes(dib.get(0..4)?.try_into().ok()?) as usize;
+    let bit_count = u16::from_le_bytes(dib.get(14..16)?.try_into().ok()?);
+    let colors_used = dib
+        .get(32..36)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap_or([0; 4])))
+        .unwrap_or(0) as usize;
+    let palette = if colors_used > 0 {
+        colors_used
+    } else if bit_count <= 8 {
+        1 << bit_count
+    } else {
+        0
+    };
+    let offset = 14 + header_size + palette * 4;
+    let mut bmp = Vec::with_capacity(dib.len() + 14);
+    bmp.extend_from_slice(b"BM");
+    bmp.extend_from_slice(&((dib.len() + 14) as u32).to_le_bytes());
+    bmp.extend_from_slice(&[0, 0, 0, 0]);
+    bmp.extend_from_slice(&(offset as u32).to_le_bytes());
+    bmp.extend_from_slice(dib);
+    Some(bmp)
+}
+
+fn codepage_encoding(cp: i32) -> Option<&'static Encoding> {
+    let label = match cp {
+        874 => "windows-874",
+        932 => "shift_jis",
+        936 => "gbk",
+        949 => "euc-kr",
+        950 => "big5",
+        1250..=1258 => return Encoding::for_label(format!("windows-{}", cp).as_bytes()),
+        10000 => "macintosh",
+        65001 => "utf-8",
+        _ => return None,
+    };
+    Encoding::for_label(label.as_bytes())
+}
+
+fn charset_encoding(charset: i32) -> Option<&'static Encoding> {
+    let cp = match charset {
+        77 => 10000,
+        128 => 932,
+        129 => 949,
+        134 => 936,
+        136 => 950,
+        161 => 1253,
+        162 => 1254,
+        163 => 1258,
+        177 => 1255,
+        178 => 1256,
+        186 => 1257,
+        204 => 1251,
+        222 => 874,
+        238 => 1250,
+        _ => return None,
+    };
+    codepage_encoding(cp)
+}
@@ -0,0 +1,338 @@
+//! Renders a parsed RTF document as XHTML.
+
+use super::reader::{Align, Block, CharFormat, Inline, ParaFormat, Paragraph, RtfDocument};
+use std::collections::HashMap;
+
+pub struct RTFToHTML<'a> {
+    doc: &'a RtfDocument,
+    image_hrefs: &'a [String],
+    /// Most common font size in half points; other sizes are relative to it.
+    base_size: i32,
+}
+
+impl<'a> RTFToHTML<'a> {
+    /// `image_hrefs[i]` is the location `doc.images[i]` was written to.
+    pub fn convert(doc: &RtfDocument, title: &str, image_hrefs: &[String]) -> String {
+        let renderer = RTFToHTML {
+            doc,
+            image_hrefs,
+            base_size: base_font_size(doc),
+        };
+
+        let mut html = String::from("<?xml version='1.0' encoding='utf-8'?>\n");
+        html.push_str("<html xmlns=\"http://www.w3.org/1999/xhtml\"><head>");
+        html.push_str(
+            "<meta http-equiv=\"Content-Type\" content=\"text/html; charset=utf-8\"/><title>",
+        );
+        html.push_str(&escape(title));
+        html.push_str("</title></head><body>");
+        renderer.blocks(&doc.blocks, &mut html);
+        renderer.footnotes(&mut html);
+        html.push_str("</body></html>");
+        html
+    }
+
+    fn blocks(&self, blocks: &[Block], out: &mut String) {
+        let mut lists: Vec<bool> = Vec::new();
+        for block in blocks {
+            match block {
+                Block::Paragraph(para) => {
+                    let list = para.format.list.filter(|_| self.heading(para).is_none());
+                    match list {
+                        Some(ls) => {
+                            let level = para.format.list_level.clamp(0, 8) as usize;
+                            let ordered = self.doc.is_ordered_list(ls, level as i32);
+                            open_list_item(&mut lists, level + 1, ordered, out);
+                            self.inlines(para, false, out);
+                        }
+                        None => {
+                            close_lists(&mut lists, 0, out);
+                            self.paragraph(para, out);
+                        }
+                    }
+                }
+                Block::Table(rows) => {
+                    close_lists(&mut lists, 0, out);
+                    self.table(rows, out);
+                }
+            }
+        }
+        close_lists(&mut lists, 0, out);
+    }
+
+    fn heading(&self, para: &Paragraph) -> Option<u8> {
+        if para.format.in_table || para.is_blank() {
+            return None;
+        }
+        self.doc.heading_level(&para.format)
+    }
+
+    fn paragraph(&self, para: &Paragraph, out: &mut String) {
+        let heading = self.heading(para);
+        let tag = match heading {
+            Some(level) => format!("h{}", level),
+            None => "p".to_string(),
+        };
+        out.push('<');
+        out.push_str(&tag);
+        let style = paragraph_style(&para.format);
+        if !style.is_empty() {
+            out.push_str(" style=\"");
+            out.push_str(&style);
+            out.push('"');
+        }
+        out.push('>');
+        if !para.list_text.trim().is_empty() {
+            // Old style (\pn) numbering only exists as literal text
+            out.push_str(&escape(para.list_text.trim()));
+            out.push(' ');
+        }
+        if para.inlines.is_empty() {
+            out.push_str("&#160;");
+        } else {
+            self.inlines(para, heading.is_some(), out);
+        }
+        out.push_str("</");
+        out.push_str(&tag);
+        out.push('>');
+    }
+
+    fn table(&self, rows: &[Vec<Vec<Paragraph>>], out: &mut String) {
+        out.push_str("<table>");
+        for row in rows {
+            out.push_str("<tr>");
+            for cell in row {
+                out.push_str("<td>");
+                let mut paras: &[Paragraph] = cell;
+                while paras.len() > 1 && paras[paras.len() - 1].inlines.is_empty() {
+                    paras = &paras[..paras.len() - 1];
+                }
+                if let [para] = paras {
+                    self.inlines(para, false, out);
+                } else {
+                    let blocks: Vec<Block> = paras.iter().cloned().map(Block::Paragraph).collect();
+                    self.blocks(&blocks, out);
+                }
+                out.push_str("</td>");
+            }
+            out.push_str("</tr>");
+        }
+        out.push_str("</table>");
+    }
+
+    fn footnotes(&self, out: &mut String) {
+        if self.doc.footnotes.is_empty() {
+            return;
+        }
+        out.push_str("<div class=\"footnotes\"><hr/>");
+        for (i, note) in self.doc.footnotes.iter().enumerate() {
+            let n = i + 1;
+            let paras: Vec<&Paragraph> = note.iter().filter(|p| !p.is_blank()).collect();
+            out.push_str(&format!(
+                "<p id=\"fn{n}\"><a href=\"#fnref{n}\">{n}</a>. ",
+                n = n
+            ));
+            match paras.first() {
+                Some(first) => self.inlines(first, false, out),
+                None => out.push_str("&#160;"),
+            }
+            out.push_str("</p>");
+            for para in paras.iter().skip(1) {
+                self.paragraph(para, out);
+            }
+        }
+        out.push_str("</div>");
+    }
+
+    /// Write the content of a paragraph, grouping runs that share a link.
+    fn inlines(&self, para: &Paragraph, heading: bool, out: &mut String) {
+        let mut link: Option<&str> = None;
+        for inline in &para.inlines {
+            let inline_link = match inline {
+                Inline::Text(_, fmt) => fmt.link.as_deref(),
+                Inline::Image(..) => link,
+                _ => None,
+            };
+            if inline_link != link {
+                if link.is_some() {
+                    out.push_str("</a>");
+                }
+                if let Some(href) = inline_link {
+                    out.push_str("<a href=\"");
+                    out.push_str(&escape_attr(href));
+                    out.push_str("\">");
+                }
+                link = inline_link;
+            }
+            match inline {
+                Inline::Text(text, fmt) => self.run(text, fmt, heading, out),
+                Inline::LineBreak => out.push_str("<br/>"),
+                Inline::Image(idx, size) => {
+                    if let Some(href) = self.image_hrefs.get(*idx) {
+                        out.push_str("<img src=\"");
+                        out.push_str(&escape_attr(href));
+                        out.push_str("\" alt=\"\"");
+                        if let Some((w, h)) = size {
+                            out.push_str(&format!(" width=\"{}\" height=\"{}\"", w, h));
+                        }
+                        out.push_str("/>");
+                    }
+                }
+                Inline::NoteRef(n) => {
+                    out.push_str(&format!(
+                        "<sup><a id=\"fnref{n}\" href=\"#fn{n}\">{n}</a></sup>",
+                        n = n
+                    ));
+                }
+                Inline::Anchor(name) => {
+                    out.push_str("<a id=\"");
+                    out.push_str(&escape_attr(name));
+                    out.push_str("\"></a>");
+                }
+            }
+        }
+        if link.is_some() {
+            out.push_str("</a>");
+        }
+    }
+
+    fn run(&self, text: &str, fmt: &CharFormat, heading: bool, out: &mut String) {
+        let mut open = Vec::new();
+        let mut style = Vec::new();
+        if !heading && (fmt.font_size - self.base_size).abs() >= 2 {
+            let em = fmt.font_size as f32 / self.base_size as f32;
+            style.push(format!("font-size: {}em", (em * 100.0).round() / 100.0));
+        }
+        if fmt.color > 0 {
+            if let Some(Some((r, g, b))) = self.doc.colors.get(fmt.color as usize) {
+                style.push(format!("color: #{:02x}{:02x}{:02x}", r, g, b));
+            }
+        }
+        let mono = fmt
+            .font
+            .filter(|f| Some(*f) != self.doc.default_font)
+            .and_then(|f| self.doc.fonts.get(&f))
+            .map(|f| f.is_monospace())
+            .unwrap_or(false);
+        if mono {
+            style.push("font-family: monospace".to_string());
+        }
+        if fmt.strike {
+            style.push("text-decoration: line-through".to_string());
+        }
+        if !style.is_empty() {
+            out.push_str(&format!("<span style=\"{}\">", style.join("; ")));
+            open.push("span");
+        }
+        let tags = [
+            (fmt.bold && !heading, "b"),
+            (fmt.italic, "i"),
+            (fmt.underline, "u"),
+            (fmt.superscript, "sup"),
+            (fmt.subscript, "sub"),
+        ];
+        for (on, tag) in tags {
+            if on {
+                out.push('<');
+                out.push_str(tag);
+                out.push('>');
+                open.push(tag);
+            }
+        }
+        out.push_str(&escape(&text.replace('\t', " ")));
+        for tag in open.iter().rev() {
+            out.push_str("</");
+            out.push_str(tag);
+            out.push('>');
+        }
+    }
+}
+
+/// Start a list item at `depth` (1 based), opening or closing lists as
+/// needed. Every open list holds an unclosed `<li>`.
+fn open_list_item(lists: &mut Vec<bool>, depth: usize, ordered: bool, out: &mut String) {
+    close_lists(lists, depth, out);
+    if lists.len() == depth && lists.last() != Some(&ordered) {
+        close_lists(lists, depth - 1, out);
+    }
+    if lists.len() == depth {
+        out.push_str("</li>");
+    }
+    while lists.len() < depth {
+        out.push_str(if ordered { "<ol>" } else { "<ul>" });
+        lists.push(ordered);
+        if lists.len() < depth {
+            out.push_str("<li>");
+        }
+    }
+    out.push_str("<li>");
+}
+
+fn close_lists(lists: &mut Vec<bool>, depth: usize, out: &mut String) {
+    while lists.len() > depth {
+        let ordered = lists.pop().unwrap_or(false);
+        out.push_str(if ordered { "</li></ol>" } else { "</li></ul>" });
+    }
+}
+
+fn paragraph_style(format: &ParaFormat) -> String {
+    let mut style = Vec::new();
+    match format.align {
+        Align::Left => {}
+        Align::Right => style.push("text-align: right".to_string()),
+        Align::Center => style.push("text-align: center".to_string()),
+        Align::Justify => style.push("text-align: justify".to_string()),
+    }
+    let lengths = [
+        ("margin-left", format.left_indent),
+        ("margin-right", format.right_indent),
+        ("text-indent", format.first_indent),
+        ("margin-top", format.space_before),
+        ("margin-bottom", format.space_after),
+    ];
+    for (prop, twips) in lengths {
+        if twips != 0 {
+            style.push(format!("{}: {}pt", prop, twips as f32 / 20.0));
+        }
+    }
+    if format.page_break_before {
+        style.push("page-break-before: always".to_string());
+    }
+    style.join("; ")
+}
+
+fn base_font_size(doc: &RtfDocument) -> i32 {
+    let mut counts: HashMap<i32, usize> = HashMap::new();
+    let mut add = |para: &Paragraph| {
+        for inline in &para.inlines {
+            if let Inline::Text(text, fmt) = inline {
+                *counts.entry(fmt.font_size).or_default() += text.chars().count();
+            }
+        }
+    };
+    for block in &doc.blocks {
+        match block {
+            Block::Paragraph(para) => add(para),
+            Block::Table(rows) => rows.iter().flatten().flatten().for_each(&mut add),
+        }
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(size, count)| (*count, -size))
+        .map(|(size, _)| size)
+        .filter(|size| *size > 0)
+        .unwrap_or(24)
+}
+
+/// Escape text content, dropping characters XML does not allow.
+fn escape(text: &str) -> String {
+    let cleaned: String = text
+        .chars()
+        .filter(|c| !c.is_control() || matches!(c, '\t' | '\n'))
+        .collect();
+    html_escape::encode_text(&cleaned).into_owned()
+}
+
+fn escape_attr(text: &str) -> String {
+    html_escape::encode_double_quoted_attribute(text).into_owned()
+}
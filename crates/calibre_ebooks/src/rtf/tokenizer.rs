@@ -0,0 +1,118 @@
+//! Splits an RTF byte stream into groups, control words and text.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    GroupStart,
+    GroupEnd,
+    /// `\word` or `\wordN`; the delimiting space is consumed.
+    ControlWord(String, Option<i32>),
+    /// `\` followed by a single non alphabetic character, e.g. `\~` or `\*`.
+    ControlSymbol(u8),
+    /// `\'hh`
+    HexByte(u8),
+    /// Raw bytes following `\binN`.
+    Binary(Vec<u8>),
+    /// Literal text. CR and LF are not part of RTF text and are dropped.
+    Text(Vec<u8>),
+}
+
+pub struct Tokenizer<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Tokenizer { data, pos: 0 }
+    }
+
+    fn control(&mut self) -> Token {
+        // Positioned just after the backslash
+        let start = self.pos;
+        while self.pos < self.data.len() && self.data[self.pos].is_ascii_alphabetic() {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            let c = match self.data.get(self.pos) {
+                Some(c) => *c,
+                None => return Token::ControlSymbol(b'\\'),
+            };
+            self.pos += 1;
+            if c == b'\'' {
+                let hex = self.data.get(self.pos..self.pos + 2).unwrap_or(b"");
+                let byte = std::str::from_utf8(hex)
+                    .ok()
+                    .and_then(|h| u8::from_str_radix(h, 16).ok());
+                if let Some(byte) = byte {
+                    self.pos += 2;
+                    return Token::HexByte(byte);
+                }
+                return Token::Text(Vec::new());
+            }
+            // An escaped line break is an old spelling of \par
+            if c == b'\n' || c == b'\r' {
+                return Token::ControlWord("par".to_string(), None);
+            }
+            return Token::ControlSymbol(c);
+        }
+
+        let name = String::from_utf8_lossy(&self.data[start..self.pos]).into_owned();
+        let num_start = self.pos;
+        if self.data.get(self.pos) == Some(&b'-') {
+            self.pos += 1;
+        }
+        while self.pos < self.data.len() && self.data[self.pos].is_ascii_digit() {
+            self.pos += 1;
+        }
+        let param = if self.pos > num_start {
+            std::str::from_utf8(&self.data[num_start..self.pos])
+                .ok()
+                .and_then(|s| s.parse::<i64>().ok())
+                .map(|n| n.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+        } else {
+            None
+        };
+        if param.is_none() && self.pos > num_start {
+            // A lone '-' is not a parameter
+            self.pos = num_start;
+        }
+        if self.data.get(self.pos) == Some(&b' ') {
+            self.pos += 1;
+        }
+
+        if name == "bin" {
+            let len = param.unwrap_or(0).max(0) as usize;
+            let end = (self.pos + len).min(self.data.len());
+            let bytes = self.data[self.pos..end].to_vec();
+            self.pos = end;
+            return Token::Binary(bytes);
+        }
+        Token::ControlWord(name, param)
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        loop {
+            let c = *self.data.get(self.pos)?;
+            self.pos += 1;
+            match c {
+                b'{' => return Some(Token::GroupStart),
+                b'}' => return Some(Token::GroupEnd),
+                b'\\' => return Some(self.control()),
+                b'\r' | b'\n' | 0 => continue,
+                _ => {
+                    let start = self.pos - 1;
+                    while self.pos < self.data.len()
+                        && !matches!(self.data[self.pos], b'{' | b'}' | b'\\' | b'\r' | b'\n')
+                    {
+                        self.pos += 1;
+                    }
+                    return Some(Token::Text(self.data[start..self.pos].to_vec()));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,949 @@
+//! Converts spine (X)HTML documents into a single RTF document.
+//!
+//! Formatting comes from the tag defaults browsers apply, adjusted by the
+//! inline `style` declarations read through the `Stylizer`.
+
+use crate::metadata::MetaInformation;
+use crate::mobi::langcodes::iana2mobi;
+use crate::oeb::container::Container;
+use crate::oeb::css::{
+    self, char_style, default_display, CharStyle, FontFamily, HEADING_SIZES,
+};
+use crate::oeb::parse_utils::{abshref, xmlize_entities};
+use crate::oeb::stylizer::{Style, Stylizer};
+use calibre_utils::html2text::html2text;
+use image::ImageFormat;
+use roxmltree::{Document, Node, ParsingOptions};
+use std::io::Cursor;
+
+const CSS_DPI: f32 = 96.0;
+/// A4 with one inch margins, in twips.
+const PAPER_WIDTH: i32 = 11906;
+const PAPER_HEIGHT: i32 = 16838;
+const MARGIN: i32 = 1440;
+const CONTENT_WIDTH: i32 = PAPER_WIDTH - 2 * MARGIN;
+/// Indentation step for lists and block quotes.
+const INDENT: i32 = 720;
+const LIST_LEVELS: usize = 9;
+
+/// Font table indices of the generic families.
+const FONT_SERIF: usize = 0;
+const FONT_SANS: usize = 1;
+const FONT_MONO: usize = 2;
+
+/// Paragraph formatting inherited by nested blocks. Lengths are in twips.
+#[derive(Debug, Clone, Default)]
+struct BlockCtx {
+    left: i32,
+    right: i32,
+    first: i32,
+    align: Option<&'static str>,
+    space_before: i32,
+    space_after: i32,
+    heading: Option<u8>,
+}
+
+struct ListCtx {
+    ordered: bool,
+    counter: i64,
+    /// Index into `RtfWriter::lists`.
+    def: usize,
+    level: usize,
+}
+
+/// One entry of the list table; every top level HTML list gets its own so
+/// numbering restarts.
+struct ListDef {
+    start: i64,
+    /// Whether each nesting level is numbered, as first seen.
+    levels: [Option<bool>; LIST_LEVELS],
+}
+
+struct ListMarker {
+    ls: usize,
+    level: usize,
+    text: String,
+}
+
+pub struct RtfWriter<'a> {
+    stylizer: Stylizer,
+    container: &'a dyn Container,
+    body: String,
+    colors: Vec<(u8, u8, u8)>,
+    lists: Vec<ListDef>,
+    list_stack: Vec<ListCtx>,
+    blocks: Vec<BlockCtx>,
+    href: String,
+    para_open: bool,
+    at_line_start: bool,
+    pending_space: bool,
+    pending_page_break: bool,
+    pending_marker: Option<ListMarker>,
+    pending_bookmarks: Vec<String>,
+    table_depth: usize,
+    pre_depth: usize,
+    has_content: bool,
+}
+
+impl<'a> RtfWriter<'a> {
+    pub fn new(container: &'a dyn Container) -> Self {
+        RtfWriter {
+            stylizer: Stylizer::new(CSS_DPI, 12.0),
+            container,
+            body: String::new(),
+            colors: Vec::new(),
+            lists: Vec::new(),
+            list_stack: Vec::new(),
+            blocks: vec![BlockCtx::default()],
+            href: String::new(),
+            para_open: false,
+            at_line_start: true,
+            pending_space: false,
+            pending_page_break: false,
+            pending_marker: None,
+            pending_bookmarks: Vec::new(),
+            table_depth: 0,
+            pre_depth: 0,
+            has_content: false,
+        }
+    }
+
+    /// Append the (X)HTML document stored at `href`. Every document after
+    /// the first starts on a new page.
+    pub fn add_document(&mut self, href: &str, html: &str) {
+        self.close_paragraph();
+        if self.has_content {
+            self.pending_page_break = true;
+        }
+        self.href = href.to_string();
+        self.pending_bookmarks.push(bookmark_name(href, None));
+
+        let base = self.base_style();
+        let text = xmlize_entities(html);
+        let opts = ParsingOptions {
+            allow_dtd: true,
+            ..ParsingOptions::default()
+        };
+        match Document::parse_with_options(&text, opts) {
+            Ok(doc) => {
+                let root = doc.root_element();
+                let body = root
+                    .descendants()
+                    .find(|n| n.is_element() && n.tag_name().name().eq_ignore_ascii_case("body"))
+                    .unwrap_or(root);
+                self.walk(body, &base);
+            }
+            Err(_) => {
+                // Tag soup: keep the text, lose the formatting
+                for block in html2text(html).split("\n\n") {
+                    self.text(block, &base);
+                    self.close_paragraph();
+                }
+            }
+        }
+        self.close_paragraph();
+    }
+
+    /// The complete RTF document, with `mi` in the info group.
+    pub fn finish(mut self, mi: &MetaInformation) -> String {
+        self.close_paragraph();
+
+        let mut rtf = String::from("{\\rtf1\\ansi\\ansicpg1252\\deff0");
+        if let Some(lcid) = mi
+            .languages
+            .iter()
+            .find(|l| l.as_str() != "und")
+            .and_then(|l| language_id(l))
+        {
+            rtf.push_str(&format!("\\deflang{}", lcid));
+        }
+        rtf.push_str("\\uc1\n");
+        rtf.push_str(
+            "{\\fonttbl{\\f0\\froman\\fcharset0 Times New Roman;}\
+             {\\f1\\fswiss\\fcharset0 Arial;}\
+             {\\f2\\fmodern\\fcharset0 Courier New;}}\n",
+        );
+        rtf.push_str("{\\colortbl;");
+        for (r, g, b) in &self.colors {
+            rtf.push_str(&format!("\\red{}\\green{}\\blue{};", r, g, b));
+        }
+        rtf.push_str("}\n");
+
+        rtf.push_str("{\\stylesheet{\\s0\\snext0 Normal;}");
+        for (i, size) in HEADING_SIZES.iter().enumerate() {
+            rtf.push_str(&format!(
+                "{{\\s{n}\\sbasedon0\\snext0\\keepn\\outlinelevel{lvl}\\sb240\\sa120\\b\\fs{fs} heading {n};}}",
+                n = i + 1,
+                lvl = i,
+                fs = (size * 2.0) as i32
+            ));
+        }
+        rtf.push_str("}\n");
+
+        if !self.lists.is_empty() {
+            rtf.push_str("{\\*\\listtable");
+            for (i, def) in self.lists.iter().enumerate() {
+                rtf.push_str("{\\list\\listsimple0");
+                for (level, ordered) in def.levels.iter().enumerate() {
+                    rtf.push_str(&list_level(level, ordered.unwrap_or(false), def.start));
+                }
+                rtf.push_str(&format!("{{\\listname ;}}\\listid{}}}", i + 1));
+            }
+            rtf.push_str("}\n{\\*\\listoverridetable");
+            for i in 0..self.lists.len() {
+                rtf.push_str(&format!(
+                    "{{\\listoverride\\listid{n}\\listoverridecount0\\ls{n}}}",
+                    n = i + 1
+                ));
+            }
+            rtf.push_str("}\n");
+        }
+
+        rtf.push_str("{\\info");
+        let mut info = |key: &str, value: &str| {
+            if !value.trim().is_empty() {
+                rtf.push_str(&format!("{{\\{} {}}}", key, escape(value)));
+            }
+        };
+        info("title", &mi.title);
+        info("author", &mi.authors.join(" & "));
+        info("subject", mi.comments.as_deref().unwrap_or(""));
+        info("keywords", &mi.tags.join(", "));
+        if let Some(publisher) = &mi.publisher {
+            info("manager", publisher);
+        }
+        rtf.push_str("}\n");
+
+        rtf.push_str(&format!(
+            "\\paperw{}\\paperh{}\\margl{m}\\margr{m}\\margt{m}\\margb{m}\\widowctrl\n",
+            PAPER_WIDTH,
+            PAPER_HEIGHT,
+            m = MARGIN
+        ));
+        rtf.push_str(&self.body);
+        rtf.push_str("}\n");
+        rtf
+    }
+
+    fn base_style(&self) -> CharStyle {
+        CharStyle {
+            bold: false,
+            italic: false,
+            underline: false,
+            strike: false,
+            superscript: false,
+            subscript: false,
+            family: FontFamily::Serif,
+            size: self.stylizer.font_base,
+            color: None,
+        }
+    }
+
+    fn walk(&mut self, node: Node, style: &CharStyle) {
+        for child in node.children() {
+            if child.is_text() {
+                self.text(child.text().unwrap_or(""), style);
+            } else if child.is_element() {
+                self.element(child, style);
+            }
+        }
+    }
+
+    fn element(&mut self, node: Node, parent: &CharStyle) {
+        let tag = node.tag_name().name().to_ascii_lowercase();
+        if matches!(
+            tag.as_str(),
+            "head" | "script" | "style" | "title" | "meta" | "link" | "noscript"
+        ) {
+            return;
+        }
+        let stylizer = self.stylizer;
+        let css = stylizer.style(&node);
+        let display = css
+            .get_inline_style("display")
+            .unwrap_or_else(|| default_display(&tag).to_string());
+        if display.trim() == "none" {
+            return;
+        }
+        let style = char_style(&stylizer, &css, &tag, parent);
+
+        if css::is_any(
+            &css,
+            &["page-break-before", "break-before"],
+            &["always", "page"],
+        ) && self.table_depth == 0
+        {
+            self.close_paragraph();
+            self.pending_page_break |= self.has_content;
+        }
+        if let Some(id) = node.attribute("id") {
+            self.pending_bookmarks
+                .push(bookmark_name(&self.href, Some(id)));
+            if self.para_open && display == "inline" {
+                self.flush_bookmarks();
+            }
+        }
+
+        match tag.as_str() {
+            "br" => {
+                self.open_paragraph();
+                self.body.push_str("\\line ");
+                self.at_line_start = true;
+                self.pending_space = false;
+            }
+            "img" => self.image(node),
+            "hr" => {
+                self.close_paragraph();
+                self.open_paragraph();
+                self.body.push_str("\\brdrb\\brdrs\\brdrw10\\brsp20 ");
+                self.close_paragraph();
+            }
+            "a" => self.link(node, &style),
+            "table" if self.table_depth == 0 => self.table(node, &style),
+            "ul" | "ol" => self.list(node, &css, tag == "ol", &style),
+            "li" => self.list_item(node, &css, &style),
+            _ if display != "inline" => self.block(node, &css, &tag, &style),
+            _ => self.walk(node, &style),
+        }
+
+        if css::is_any(
+            &css,
+            &["page-break-after", "break-after"],
+            &["always", "page"],
+        ) && self.table_depth == 0
+        {
+            self.close_paragraph();
+            self.pending_page_break = true;
+        }
+    }
+
+    fn block(&mut self, node: Node, css: &Style, tag: &str, style: &CharStyle) {
+        self.close_paragraph();
+        let ctx = self.block_ctx(css, tag, style);
+        self.blocks.push(ctx);
+        if tag == "pre" {
+            self.pre_depth += 1;
+        }
+        self.walk(node, style);
+        self.close_paragraph();
+        if tag == "pre" {
+            self.pre_depth -= 1;
+        }
+        self.blocks.pop();
+    }
+
+    fn block_ctx(&self, css: &Style, tag: &str, style: &CharStyle) -> BlockCtx {
+        let parent = self.current_block();
+        let em = style.size;
+        let heading = match tag {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => tag[1..].parse().ok(),
+            _ => None,
+        };
+        let mut ctx = BlockCtx {
+            left: parent.left,
+            right: parent.right,
+            first: parent.first,
+            align: parent.align,
+            space_before: 0,
+            space_after: 0,
+            heading,
+        };
+        let (before, after) = match tag {
+            "p" | "pre" | "dl" => (0.0, 6.0),
+            "blockquote" => {
+                ctx.left += INDENT;
+                ctx.right += INDENT;
+                (6.0, 6.0)
+            }
+            "dd" => {
+                ctx.left += INDENT;
+                (0.0, 0.0)
+            }
+            "center" => {
+                ctx.align = Some("qc");
+                (0.0, 0.0)
+            }
+            _ if heading.is_some() => (12.0, 6.0),
+            _ => (0.0, 0.0),
+        };
+        ctx.space_before = twips(before);
+        ctx.space_after = twips(after);
+
+        let width = (CONTENT_WIDTH - parent.left - parent.right) as f32 / 20.0;
+        let get = |prop: &str| {
+            css.get_inline_style(prop)
+                .and_then(|v| css::length(&self.stylizer, &v, em, width))
+        };
+        let margins = css
+            .get_inline_style("margin")
+            .map(|v| crate::oeb::normalize_css::normalize_edge("margin", &v))
+            .unwrap_or_default();
+        let edge = |name: &str| {
+            get(name).or_else(|| {
+                margins
+                    .get(name)
+                    .and_then(|v| css::length(&self.stylizer, v, em, width))
+            })
+        };
+        if let Some(v) = edge("margin-top") {
+            ctx.space_before = twips(v);
+        }
+        if let Some(v) = edge("margin-bottom") {
+            ctx.space_after = twips(v);
+        }
+        if let Some(v) = edge("margin-left") {
+            ctx.left += twips(v);
+        }
+        if let Some(v) = edge("margin-right") {
+            ctx.right += twips(v);
+        }
+        if let Some(v) = get("text-indent") {
+            ctx.first = twips(v);
+        }
+        if let Some(align) = css.get_inline_style("text-align") {
+            ctx.align = match align.trim() {
+                "left" | "start" => None,
+                "right" | "end" => Some("qr"),
+                "center" => Some("qc"),
+                "justify" => Some("qj"),
+                _ => ctx.align,
+            };
+        }
+        ctx
+    }
+
+    fn current_block(&self) -> &BlockCtx {
+        self.blocks.last().expect("root block is never popped")
+    }
+
+    fn list(&mut self, node: Node, css: &Style, ordered: bool, style: &CharStyle) {
+        self.close_paragraph();
+        let start = node
+            .attribute("start")
+            .and_then(|s| s.trim().parse::<i64>().ok())
+            .unwrap_or(1);
+        let (def, level) = match self.list_stack.last() {
+            Some(parent) => (parent.def, (parent.level + 1).min(LIST_LEVELS - 1)),
+            None => {
+                self.lists.push(ListDef {
+                    start,
+                    levels: [None; LIST_LEVELS],
+                });
+                (self.lists.len() - 1, 0)
+            }
+        };
+        self.lists[def].levels[level].get_or_insert(ordered);
+
+        let mut ctx = self.block_ctx(css, "ul", style);
+        ctx.left += INDENT;
+        ctx.first = 0;
+        self.blocks.push(ctx);
+        self.list_stack.push(ListCtx {
+            ordered,
+            counter: start - 1,
+            def,
+            level,
+        });
+        self.walk(node, style);
+        self.close_paragraph();
+        self.list_stack.pop();
+        self.blocks.pop();
+    }
+
+    fn list_item(&mut self, node: Node, css: &Style, style: &CharStyle) {
+        self.close_paragraph();
+        let mut ctx = self.block_ctx(css, "li", style);
+        if let Some(list) = self.list_stack.last_mut() {
+            if let Some(value) = node.attribute("value").and_then(|v| v.parse::<i64>().ok()) {
+                list.counter = value - 1;
+            }
+            list.counter += 1;
+            let text = if list.ordered {
+                format!("{}.", list.counter)
+            } else {
+                "\u{2022}".to_string()
+            };
+            self.pending_marker = Some(ListMarker {
+                ls: list.def + 1,
+                level: list.level,
+                text,
+            });
+            // Hanging indent for the marker
+            ctx.first = -INDENT / 2;
+        }
+        self.blocks.push(ctx);
+        self.walk(node, style);
+        self.close_paragraph();
+        self.pending_marker = None;
+        self.blocks.pop();
+    }
+
+    fn table(&mut self, node: Node, style: &CharStyle) {
+        self.close_paragraph();
+        let rows: Vec<Node> = node
+            .children()
+            .filter(|n| n.is_element())
+            .flat_map(|n| {
+                let tag = n.tag_name().name().to_ascii_lowercase();
+                if matches!(tag.as_str(), "thead" | "tbody" | "tfoot") {
+                    n.children()
+                        .filter(|r| {
+                            r.is_element() && r.tag_name().name().eq_ignore_ascii_case("tr")
+                        })
+                        .collect::<Vec<_>>()
+                } else if tag == "tr" {
+                    vec![n]
+                } else {
+                    Vec::new()
+                }
+            })
+            .collect();
+
+        if std::mem::take(&mut self.pending_page_break) {
+            self.body.push_str("\\page\n");
+        }
+        let left = self.current_block().left;
+        let width = CONTENT_WIDTH - left - self.current_block().right;
+        for row in rows {
+            let cells: Vec<Node> = row
+                .children()
+                .filter(|c| {
+                    c.is_element()
+                        && matches!(
+                            c.tag_name().name().to_ascii_lowercase().as_str(),
+                            "td" | "th"
+                        )
+                })
+                .collect();
+            if cells.is_empty() {
+                continue;
+            }
+            let cell_width = width / cells.len() as i32;
+            self.body
+                .push_str(&format!("\\trowd\\trgaph108\\trleft{}", left));
+            for i in 0..cells.len() {
+                self.body.push_str(&format!(
+                    "\\clbrdrt\\brdrs\\brdrw10\\clbrdrl\\brdrs\\brdrw10\\clbrdrb\\brdrs\\brdrw10\\clbrdrr\\brdrs\\brdrw10\\cellx{}",
+                    left + cell_width * (i as i32 + 1)
+                ));
+            }
+            self.body.push('\n');
+
+            self.table_depth += 1;
+            for cell in cells {
+                let tag = cell.tag_name().name().to_ascii_lowercase();
+                let css = self.stylizer.style(&cell);
+                let cell_style = char_style(&self.stylizer, &css, &tag, style);
+                let mut ctx = self.block_ctx(&css, &tag, &cell_style);
+                ctx.left = 0;
+                ctx.right = 0;
+                ctx.first = 0;
+                if tag == "th" && css.get_inline_style("text-align").is_none() {
+                    ctx.align = Some("qc");
+                }
+                self.blocks.push(ctx);
+                let start = self.body.len();
+                self.walk(cell, &cell_style);
+                // The last paragraph of a cell ends with \cell instead of \par
+                if self.para_open {
+                    self.body.push_str("\\cell\n");
+                    self.para_open = false;
+                } else if self.body.len() > start && self.body.ends_with("\\par\n") {
+                    self.body.truncate(self.body.len() - "\\par\n".len());
+                    self.body.push_str("\\cell\n");
+                } else {
+                    self.body.push_str("\\pard\\intbl\\cell\n");
+                }
+                self.blocks.pop();
+            }
+            self.table_depth -= 1;
+            self.body.push_str("\\row\n");
+            self.has_content = true;
+        }
+    }
+
+    fn link(&mut self, node: Node, style: &CharStyle) {
+        let href = match node.attribute("href").map(str::trim) {
+            Some(href) if !href.is_empty() => href,
+            _ => return self.walk(node, style),
+        };
+        let target = if href.contains("://") || href.starts_with("mailto:") {
+            format!("\"{}\"", href.replace('"', "%22"))
+        } else {
+            let (path, fragment) = match href.split_once('#') {
+                Some((path, fragment)) => (path, Some(fragment)),
+                None => (href, None),
+            };
+            let doc = if path.is_empty() {
+                self.href.clone()
+            } else {
+                abshref(&self.href, path)
+            };
+            let fragment = fragment.filter(|f| !f.is_empty());
+            format!("\\l \"{}\"", bookmark_name(&doc, fragment))
+        };
+
+        self.open_paragraph();
+        self.flush_pending_space();
+        self.body.push_str("{\\field{\\*\\fldinst {HYPERLINK ");
+        self.body.push_str(&escape(&target));
+        self.body.push_str("}}{\\fldrslt ");
+        let mut style = style.clone();
+        style.underline = true;
+        if style.color.is_none() {
+            style.color = Some((0, 0, 255));
+        }
+        self.walk(node, &style);
+        self.body.push_str("}}");
+    }
+
+    fn image(&mut self, node: Node) {
+        let src = match node.attribute("src") {
+            Some(src) => abshref(&self.href, src),
+            None => return,
+        };
+        let data = match self.container.read(&src) {
+            Ok(data) => data,
+            Err(_) => return self.alt_text(node),
+        };
+        let (blip, data) = match image::guess_format(&data) {
+            Ok(ImageFormat::Png) => ("pngblip", data),
+            Ok(ImageFormat::Jpeg) => ("jpegblip", data),
+            // Anything else is re-encoded, RTF readers only reliably show these two
+            _ => match image::load_from_memory(&data) {
+                Ok(img) => {
+                    let mut png = Vec::new();
+                    if img
+                        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                        .is_err()
+                    {
+                        return self.alt_text(node);
+                    }
+                    ("pngblip", png)
+                }
+                Err(_) => return self.alt_text(node),
+            },
+        };
+        let (px_w, px_h) = match image::io::Reader::new(Cursor::new(&data))
+            .with_guessed_format()
+            .ok()
+            .and_then(|r| r.into_dimensions().ok())
+        {
+            Some(dims) => dims,
+            None => return self.alt_text(node),
+        };
+
+        // Display size in CSS pixels, from attributes if present
+        let attr = |name: &str| {
+            node.attribute(name)
+                .and_then(|v| v.trim().trim_end_matches("px").parse::<f32>().ok())
+                .filter(|v| *v > 0.0)
+        };
+        let aspect = px_h as f32 / px_w.max(1) as f32;
+        let (mut w, mut h) = match (attr("width"), attr("height")) {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, w * aspect),
+            (None, Some(h)) => (h / aspect.max(f32::EPSILON), h),
+            (None, None) => (px_w as f32, px_h as f32),
+        };
+        let block = self.current_block();
+        let max_w = (CONTENT_WIDTH - block.left - block.right) as f32 / 15.0;
+        if w > max_w {
+            h *= max_w / w;
+            w = max_w;
+        }
+
+        self.open_paragraph();
+        self.flush_pending_space();
+        self.body.push_str(&format!(
+            "{{\\pict\\{}\\picw{}\\pich{}\\picwgoal{}\\pichgoal{}\n",
+            blip,
+            px_w,
+            px_h,
+            (w * 15.0).round() as i32,
+            (h * 15.0).round() as i32
+        ));
+        for chunk in data.chunks(64) {
+            for b in chunk {
+                self.body.push_str(&format!("{:02x}", b));
+            }
+            self.body.push('\n');
+        }
+        self.body.push('}');
+        self.at_line_start = false;
+    }
+
+    fn alt_text(&mut self, node: Node) {
+        if let Some(alt) = node.attribute("alt").filter(|a| !a.trim().is_empty()) {
+            let style = self.base_style();
+            self.text(alt, &style);
+        }
+    }
+
+    fn text(&mut self, text: &str, style: &CharStyle) {
+        let mut out = String::new();
+        if self.pre_depth > 0 {
+            if text.is_empty() {
+                return;
+            }
+            self.open_paragraph();
+            self.flush_pending_space();
+            for (i, line) in text.split('\n').enumerate() {
+                if i > 0 {
+                    out.push_str("\\line ");
+                }
+                out.push_str(&escape(line.trim_end_matches('\r')));
+            }
+            self.run(&out, style);
+            return;
+        }
+
+        // Collapse white space; leading space in a line is dropped
+        for c in text.chars() {
+            if matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0c') {
+                self.pending_space = true;
+                continue;
+            }
+            if !self.para_open {
+                self.open_paragraph();
+            }
+            if self.pending_space && !out.is_empty() {
+                out.push(' ');
+            } else if self.pending_space && !self.at_line_start {
+                // Space between elements keeps the surrounding formatting
+                self.body.push(' ');
+            }
+            self.pending_space = false;
+            out.push(c);
+        }
+        if !out.is_empty() {
+            self.run(&escape(&out), style);
+        }
+    }
+
+    /// Write a space left over from the previous run before inline content.
+    fn flush_pending_space(&mut self) {
+        if self.pending_space && !self.at_line_start {
+            self.body.push(' ');
+        }
+        self.pending_space = false;
+    }
+
+    /// Write already escaped text with its character formatting.
+    fn run(&mut self, escaped: &str, style: &CharStyle) {
+        let mut props = String::new();
+        let heading = self.current_block().heading;
+        if style.bold && heading.is_none() {
+            props.push_str("\\b");
+        } else if !style.bold && heading.is_some() {
+            props.push_str("\\b0");
+        }
+        if style.italic {
+            props.push_str("\\i");
+        }
+        if style.underline {
+            props.push_str("\\ul");
+        }
+        if style.strike {
+            props.push_str("\\strike");
+        }
+        if style.superscript {
+            props.push_str("\\super");
+        } else if style.subscript {
+            props.push_str("\\sub");
+        }
+        if style.family != FontFamily::Serif {
+            props.push_str(&format!("\\f{}", font_index(style.family)));
+        }
+        let size = (style.size * 2.0).round() as i32;
+        let default_size = match heading {
+            Some(level) => (HEADING_SIZES[level as usize - 1] * 2.0) as i32,
+            None => 24,
+        };
+        if size != default_size {
+            props.push_str(&format!("\\fs{}", size));
+        }
+        if let Some(color) = style.color.filter(|c| *c != (0, 0, 0)) {
+            props.push_str(&format!("\\cf{}", self.color_index(color)));
+        }
+        if props.is_empty() {
+            self.body.push_str(escaped);
+        } else {
+            self.body.push('{');
+            self.body.push_str(&props);
+            self.body.push(' ');
+            self.body.push_str(escaped);
+            self.body.push('}');
+        }
+        self.at_line_start = false;
+    }
+
+    fn color_index(&mut self, color: (u8, u8, u8)) -> usize {
+        match self.colors.iter().position(|c| *c == color) {
+            Some(i) => i + 1,
+            None => {
+                self.colors.push(color);
+                self.colors.len()
+            }
+        }
+    }
+
+    fn open_paragraph(&mut self) {
+        if self.para_open {
+            return;
+        }
+        let ctx = self.current_block().clone();
+        self.body.push_str("\\pard\\plain");
+        if let Some(level) = ctx.heading {
+            self.body
+                .push_str(&format!("\\s{}\\outlinelevel{}\\keepn", level, level - 1));
+        }
+        if self.table_depth > 0 {
+            self.body.push_str("\\intbl");
+        } else if std::mem::take(&mut self.pending_page_break) {
+            self.body.push_str("\\pagebb");
+        }
+        if let Some(align) = ctx.align {
+            self.body.push('\\');
+            self.body.push_str(align);
+        }
+        let marker = self.pending_marker.take();
+        if let Some(marker) = &marker {
+            self.body
+                .push_str(&format!("\\ls{}\\ilvl{}", marker.ls, marker.level));
+        }
+        for (word, value) in [
+            ("li", ctx.left),
+            ("ri", ctx.right),
+            ("fi", ctx.first),
+            ("sb", ctx.space_before),
+            ("sa", ctx.space_after),
+        ] {
+            if value != 0 {
+                self.body.push_str(&format!("\\{}{}", word, value));
+            }
+        }
+        if let Some(level) = ctx.heading {
+            let size = (HEADING_SIZES[level as usize - 1] * 2.0) as i32;
+            self.body.push_str(&format!("\\b\\fs{}", size));
+        }
+        self.body.push(' ');
+        if let Some(marker) = marker {
+            self.body.push_str(&format!(
+                "{{\\listtext\\pard\\plain {}\\tab}}",
+                escape(&marker.text)
+            ));
+        }
+        self.para_open = true;
+        self.at_line_start = true;
+        self.pending_space = false;
+        self.has_content = true;
+        self.flush_bookmarks();
+    }
+
+    fn close_paragraph(&mut self) {
+        if self.para_open {
+            self.body.push_str("\\par\n");
+            self.para_open = false;
+        }
+        self.at_line_start = true;
+        self.pending_space = false;
+    }
+
+    fn flush_bookmarks(&mut self) {
+        for name in std::mem::take(&mut self.pending_bookmarks) {
+            self.body.push_str(&format!(
+                "{{\\*\\bkmkstart {n}}}{{\\*\\bkmkend {n}}}",
+                n = name
+            ));
+        }
+    }
+}
+
+fn list_level(level: usize, ordered: bool, start: i64) -> String {
+    let indent = INDENT * (level as i32 + 1);
+    let (nfc, text, numbers) = if ordered {
+        (0, format!("\\'02\\'{:02x}.", level), "\\'01")
+    } else {
+        (23, "\\'01\\u8226 ?".to_string(), "")
+    };
+    format!(
+        "{{\\listlevel\\levelnfc{nfc}\\levelnfcn{nfc}\\leveljc0\\leveljcn0\\levelfollow0\\levelstartat{start}\
+         {{\\leveltext {text};}}{{\\levelnumbers{numbers};}}\\fi-{hang}\\li{indent}\\lin{indent}}}",
+        nfc = nfc,
+        start = if ordered { start } else { 1 },
+        text = text,
+        numbers = numbers,
+        hang = INDENT / 2,
+        indent = indent
+    )
+}
+
+/// Bookmark for a document or an element in it. Word only allows letters,
+/// digits and underscores.
+fn bookmark_name(href: &str, id: Option<&str>) -> String {
+    let raw = match id {
+        Some(id) => format!("{}_{}", href, id),
+        None => href.to_string(),
+    };
+    let mut name: String = raw
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name.insert(0, 'b');
+    }
+    name
+}
+
+/// Windows LCID for an IANA language code.
+fn language_id(lang: &str) -> Option<u32> {
+    let code = iana2mobi(lang);
+    let (sub, primary) = (*code.get(2)? as u32, *code.get(3)? as u32);
+    // A bare language maps to its neutral code; Word expects the primary
+    // sublanguage instead
+    match (primary, sub) {
+        (0, _) => None,
+        (primary, 0) => Some(primary | (1 << 10)),
+        (primary, sub) => Some(primary | (sub << 10)),
+    }
+}
+
+/// The font table entry for a generic family.
+fn font_index(family: FontFamily) -> usize {
+    match family {
+        FontFamily::Serif => FONT_SERIF,
+        FontFamily::SansSerif => FONT_SANS,
+        FontFamily::Monospace => FONT_MONO,
+    }
+}
+
+fn twips(points: f32) -> i32 {
+    (points * 20.0).round() as i32
+}
+
+/// Escape text for RTF: specials are backslashed and anything outside ASCII
+/// becomes `\uN?` (surrogate pairs for astral characters).
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '{' | '}' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\u{a0}' => out.push_str("\\~"),
+            '\t' => out.push_str("\\tab "),
+            '\n' => out.push_str("\\line "),
+            c if c.is_ascii_control() => {}
+            c if c.is_ascii() => out.push(c),
+            c => {
+                let mut units = [0u16; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    out.push_str(&format!("\\u{}?", *unit as i16));
+                }
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,229 @@
+use calibre_ebooks::compression::palmdoc;
+use calibre_ebooks::mobi::index::read_index;
+use calibre_ebooks::mobi::mobi6::MobiReader;
+use calibre_ebooks::mobi::ncx::read_ncx;
+use calibre_ebooks::mobi::utils::get_trailing_data;
+use calibre_ebooks::mobi::writer::MobiFileType;
+use calibre_ebooks::oeb::book::OEBBook;
+use calibre_ebooks::oeb::container::DirContainer;
+use calibre_ebooks::oeb::toc::TOCNode;
+use calibre_ebooks::output::azw3_output::AZW3Output;
+use calibre_ebooks::output::mobi_output::MOBIOutput;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::Path;
+use tempfile::tempdir;
+
+type Sections = Vec<(Vec<u8>, (u32, u32, u32, u32, u32))>;
+
+fn build_book(dir: &Path) -> OEBBook {
+    fs::write(
+        dir.join("ch1.xhtml"),
+        "<html xmlns=\"http://www.w3.org/1999/xhtml\"><head><title>One</title>\
+         <link rel=\"stylesheet\" href=\"style.css\"/></head><body>\
+         <h1 id=\"start\">Chapter One</h1><p>First <a href=\"ch2.xhtml#sec\">link</a></p>\
+         <p><img src=\"images/cover.png\" alt=\"\"/></p></body></html>",
+    )
+    .unwrap();
+    let long: String = (0..400)
+        .map(|i| format!("<p>Paragraph {} with some text in it.</p>", i))
+        .collect();
+    fs::write(
+        dir.join("ch2.xhtml"),
+        format!(
+            "<html xmlns=\"http://www.w3.org/1999/xhtml\"><head><title>Two</title>\
+             <style>p {{ margin: 0 }}</style></head><body><h1>Chapter Two</h1>\
+             <div id=\"sec\">{}</div></body></html>",
+            long
+        ),
+    )
+    .unwrap();
+    fs::write(dir.join("style.css"), "h1 { text-align: center }").unwrap();
+
+    fs::create_dir_all(dir.join("images")).unwrap();
+    let img = image::RgbImage::from_pixel(300, 400, image::Rgb([200, 30, 30]));
+    img.save(dir.join("images/cover.png")).unwrap();
+
+    let container = Box::new(DirContainer::new(dir));
+    let mut book = OEBBook::new(container);
+    book.manifest
+        .add("ch1", "ch1.xhtml", "application/xhtml+xml");
+    book.manifest
+        .add("ch2", "ch2.xhtml", "application/xhtml+xml");
+    book.manifest.add("css", "style.css", "text/css");
+    book.manifest.add("cover", "images/cover.png", "image/png");
+    book.spine.add("ch1", true);
+    book.spine.add("ch2", true);
+
+    book.metadata.add("title", "Kindle Book");
+    book.metadata.add("creator", "Jane Doe");
+    book.metadata.add("language", "en");
+    book.metadata.add("cover", "cover");
+    let mut attrib = HashMap::new();
+    attrib.insert("opf:scheme".to_string(), "uuid".to_string());
+    book.metadata.add_with_attrib(
+        "identifier",
+        "urn:uuid:0e3e4c2a-1111-2222-3333-444455556666",
+        attrib,
+    );
+
+    let mut ch1 = TOCNode::new(Some("Chapter One".into()), Some("ch1.xhtml".into()));
+    ch1.add(TOCNode::new(
+        Some("Section".into()),
+        Some("ch2.xhtml#sec".into()),
+    ));
+    book.toc.root.add(ch1);
+    book.toc.root.add(TOCNode::new(
+        Some("Chapter Two".into()),
+        Some("ch2.xhtml".into()),
+    ));
+    book.guide
+        .add("text", Some("Start".into()), "ch1.xhtml#start");
+    book
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn sections_from(reader: &MobiReader, start: usize) -> Sections {
+    reader.sections[start..]
+        .iter()
+        .map(|(data, _)| (data.clone(), (0, 0, 0, 0, 0)))
+        .collect()
+}
+
+fn exth_record(rec0: &[u8], code: u32) -> Option<Vec<u8>> {
+    let header_length = u32_at(rec0, 20) as usize;
+    let exth = &rec0[16 + header_length..];
+    assert_eq!(&exth[..4], b"EXTH");
+    let count = u32_at(exth, 8);
+    let mut pos = 12;
+    for _ in 0..count {
+        let (id, len) = (u32_at(exth, pos), u32_at(exth, pos + 4) as usize);
+        if id == code {
+            return Some(exth[pos + 8..pos + len].to_vec());
+        }
+        pos += len;
+    }
+    None
+}
+
+/// The uncompressed text of the book starting at `rec0_index`.
+fn book_text(reader: &MobiReader, rec0_index: usize) -> Vec<u8> {
+    let rec0 = &reader.sections[rec0_index].0;
+    let count = u16::from_be_bytes([rec0[8], rec0[9]]) as usize;
+    let mut text = Vec::new();
+    for (data, _) in &reader.sections[rec0_index + 1..=rec0_index + count] {
+        let (_, content) = get_trailing_data(data, 1).unwrap();
+        text.extend(palmdoc::decompress(&content).unwrap());
+    }
+    assert_eq!(text.len(), u32_at(rec0, 4) as usize);
+    text
+}
+
+#[test]
+fn test_azw3_output_structure() {
+    let src = tempdir().unwrap();
+    let book = build_book(src.path());
+    let out = tempdir().unwrap();
+    let path = out.path().join("book.azw3");
+    AZW3Output::new().convert(&book, &path).unwrap();
+
+    let reader = MobiReader::new(File::open(&path).unwrap()).unwrap();
+    assert_eq!(reader.kf8_type.as_deref(), Some("standalone"));
+    assert_eq!(reader.book_header.title, "Kindle Book");
+    let rec0 = &reader.sections[0].0;
+    assert_eq!(u32_at(rec0, 36), 8);
+
+    // Text: the stylesheets are flows after the markup
+    let text = book_text(&reader, 0);
+    let fdst = &reader.sections[u32_at(rec0, 192) as usize].0;
+    assert_eq!(&fdst[..4], b"FDST");
+    assert_eq!(u32_at(rec0, 196), 3);
+    let flow0_end = u32_at(fdst, 16) as usize;
+    let markup = String::from_utf8(text[..flow0_end].to_vec()).unwrap();
+    let css = String::from_utf8(text[flow0_end..].to_vec()).unwrap();
+    assert!(css.contains("text-align: center"));
+    assert!(css.contains("margin: 0"));
+    assert!(markup.contains("kindle:flow:0001?mime=text/css"));
+    assert!(markup.contains("kindle:embed:0001?mime=image/png"));
+    assert!(markup.contains("Paragraph 399"));
+    assert!(!markup.contains("kindle:pos:fid:0000:off:0000000000"));
+    assert!(markup.contains("kindle:pos:fid:"));
+
+    let sections = sections_from(&reader, 0);
+    let (skel, _) = read_index(&sections, u32_at(rec0, 252) as usize, "utf-8").unwrap();
+    assert_eq!(skel.len(), 2);
+    assert!(skel.contains_key("SKEL0000000000"));
+    let (chunks, _) = read_index(&sections, u32_at(rec0, 248) as usize, "utf-8").unwrap();
+    // The second file is too large for a single chunk
+    assert!(chunks.len() > 2);
+
+    let ncx = read_ncx(&sections, u32_at(rec0, 244), "utf-8").unwrap();
+    let labels: Vec<&str> = ncx.iter().map(|e| e.text.as_str()).collect();
+    assert_eq!(labels, ["Chapter One", "Chapter Two", "Section"]);
+    assert_eq!(ncx[2].parent, 0);
+    assert!(u32_at(rec0, 260) != 0xFFFF_FFFF);
+
+    // Cover, its thumbnail and the start reading location
+    let first_resource = u32_at(rec0, 108) as usize;
+    let cover = u32_at(&exth_record(rec0, 201).unwrap(), 0) as usize;
+    let thumb = u32_at(&exth_record(rec0, 202).unwrap(), 0) as usize;
+    assert_eq!(&reader.sections[first_resource + cover].0[..4], b"\x89PNG");
+    assert_eq!(
+        &reader.sections[first_resource + thumb].0[..3],
+        b"\xFF\xD8\xFF"
+    );
+    assert!(exth_record(rec0, 116).is_some());
+    assert_eq!(exth_record(rec0, 100).unwrap(), b"Jane Doe");
+    assert_eq!(exth_record(rec0, 501).unwrap(), b"EBOK");
+    assert_eq!(
+        exth_record(rec0, 113).unwrap(),
+        b"0e3e4c2a-1111-2222-3333-444455556666"
+    );
+    assert_eq!(reader.sections.last().unwrap().0, b"\xe9\x8e\r\n");
+}
+
+#[test]
+fn test_joint_mobi_output() {
+    let src = tempdir().unwrap();
+    let book = build_book(src.path());
+    let out = tempdir().unwrap();
+    let path = out.path().join("book.mobi");
+    MOBIOutput::with_file_type(MobiFileType::Both)
+        .convert(&book, &path)
+        .unwrap();
+
+    let reader = MobiReader::new(File::open(&path).unwrap()).unwrap();
+    assert_eq!(reader.kf8_type.as_deref(), Some("joint"));
+    let boundary = reader.kf8_boundary.unwrap();
+    assert_eq!(reader.sections[boundary].0, b"BOUNDARY");
+
+    // The MOBI 6 part holds the images, converted to GIF
+    let rec0 = &reader.sections[0].0;
+    assert_eq!(u32_at(rec0, 36), 6);
+    let first_resource = u32_at(rec0, 108) as usize;
+    assert_eq!(&reader.sections[first_resource].0[..3], b"GIF");
+    let text = String::from_utf8(book_text(&reader, 0)).unwrap();
+    assert!(text.contains("recindex=\"00001\""));
+    assert!(text.contains("<mbp:pagebreak/>"));
+    assert!(text.contains("filepos="));
+
+    let kf8_text = String::from_utf8(book_text(&reader, boundary + 1)).unwrap();
+    assert!(kf8_text.contains("kindle:embed:0001?mime=image/gif"));
+}
+
+#[test]
+fn test_mobi6_only_has_no_kf8() {
+    let src = tempdir().unwrap();
+    let book = build_book(src.path());
+    let out = tempdir().unwrap();
+    let path = out.path().join("book.mobi");
+    MOBIOutput::new().convert(&book, &path).unwrap();
+
+    let reader = MobiReader::new(File::open(&path).unwrap()).unwrap();
+    assert!(reader.kf8_type.is_none());
+    let meta = calibre_ebooks::metadata::mobi::get_metadata(File::open(&path).unwrap()).unwrap();
+    assert_eq!(meta.title, "Kindle Book");
+}
@@ -2,23 +2,312 @@ use calibre_ebooks::input::chm_input::CHMInput;
 use std::fs;
 use tempfile::tempdir;
 
+const BLOCK_LEN: usize = 0x1000;
+const LZX_FRAME: usize = 0x8000;
+
+fn encint(mut value: u64) -> Vec<u8> {
+    let mut out = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value > 0 {
+        out.insert(0, 0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    out
+}
+
+/// An LZX stream holding `data` in a single uncompressed block.
+fn lzx_uncompressed(data: &[u8]) -> Vec<u8> {
+    // No E8 translation, block type 3, 24 bit block length, 4 padding bits
+    let header: u32 = (3 << 28) | ((data.len() as u32) << 4);
+    let mut out = Vec::new();
+    out.extend_from_slice(&((header >> 16) as u16).to_le_bytes());
+    out.extend_from_slice(&(header as u16).to_le_bytes());
+    for _ in 0..3 {
+        out.extend_from_slice(&1u32.to_le_bytes());
+    }
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+    out
+}
+
+fn system_file(records: &[(u16, &[u8])]) -> Vec<u8> {
+    let mut out = 3u32.to_le_bytes().to_vec();
+    for (code, value) in records {
+        out.extend_from_slice(&code.to_le_bytes());
+        out.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        out.extend_from_slice(value);
+    }
+    out
+}
+
+/// Build a CHM holding `files`, those flagged as compressed in the LZX
+/// section with a reset every 32K of output.
+fn build_chm(files: &[(&str, Vec<u8>, bool)]) -> Vec<u8> {
+    let mut entries: Vec<(String, u64, u64, u64)> = Vec::new();
+    let mut section0 = Vec::new();
+    let mut section1 = Vec::new();
+    for (path, data, compressed) in files {
+        let (section, store) = if *compressed {
+            (1, &mut section1)
+        } else {
+            (0, &mut section0)
+        };
+        entries.push((
+            path.to_string(),
+            section,
+            store.len() as u64,
+            data.len() as u64,
+        ));
+        store.extend_from_slice(data);
+    }
+
+    let mut content = Vec::new();
+    let mut reset_offsets = Vec::new();
+    for run in section1.chunks(LZX_FRAME) {
+        reset_offsets.push(content.len() as u64);
+        content.extend(lzx_uncompressed(run));
+    }
+    let mut control = 6u32.to_le_bytes().to_vec();
+    control.extend_from_slice(b"LZXC");
+    for value in [2u32, 1, 1, 0, 0] {
+        control.extend_from_slice(&value.to_le_bytes());
+    }
+    let mut reset_table = Vec::new();
+    for value in [2u32, reset_offsets.len() as u32, 8, 0x28] {
+        reset_table.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [section1.len(), content.len(), LZX_FRAME] {
+        reset_table.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+    for offset in &reset_offsets {
+        reset_table.extend_from_slice(&offset.to_le_bytes());
+    }
+    for (path, data) in [
+        ("::DataSpace/Storage/MSCompressed/ControlData", control),
+        (
+            "::DataSpace/Storage/MSCompressed/Transform/\
+             {7FC28940-9D31-11D0-9B27-00A0C91E9C7C}/InstanceData/ResetTable",
+            reset_table,
+        ),
+        ("::DataSpace/Storage/MSCompressed/Content", content),
+    ] {
+        entries.push((
+            path.to_string(),
+            0,
+            section0.len() as u64,
+            data.len() as u64,
+        ));
+        section0.extend_from_slice(&data);
+    }
+
+    let mut chunk = b"PMGL".to_vec();
+    chunk.extend_from_slice(&[0; 16]);
+    for (path, section, offset, length) in &entries {
+        chunk.extend(encint(path.len() as u64));
+        chunk.extend_from_slice(path.as_bytes());
+        chunk.extend(encint(*section));
+        chunk.extend(encint(*offset));
+        chunk.extend(encint(*length));
+    }
+    assert!(chunk.len() <= BLOCK_LEN);
+    let free_space = (BLOCK_LEN - chunk.len()) as u32;
+    chunk[4..8].copy_from_slice(&free_space.to_le_bytes());
+    chunk[12..16].copy_from_slice(&(-1i32).to_le_bytes());
+    chunk[16..20].copy_from_slice(&(-1i32).to_le_bytes());
+    chunk.resize(BLOCK_LEN, 0);
+
+    let mut directory = b"ITSP".to_vec();
+    for value in [
+        1u32,
+        0x54,
+        0x0a,
+        BLOCK_LEN as u32,
+        2,
+        1,
+        u32::MAX,
+        0,
+        0,
+        u32::MAX,
+        1,
+    ] {
+        directory.extend_from_slice(&value.to_le_bytes());
+    }
+    directory.resize(0x54, 0);
+    directory.extend(chunk);
+
+    let dir_offset = 0x60u64;
+    let data_offset = dir_offset + directory.len() as u64;
+    let mut chm = b"ITSF".to_vec();
+    for value in [3u32, 0x60, 1, 0, 0x409] {
+        chm.extend_from_slice(&value.to_le_bytes());
+    }
+    chm.resize(0x48, 0);
+    for value in [dir_offset, directory.len() as u64, data_offset] {
+        chm.extend_from_slice(&value.to_le_bytes());
+    }
+    chm.extend(directory);
+    chm.extend(section0);
+    chm
+}
+
+fn sample_chm() -> Vec<u8> {
+    let page = |title: &str, body: &str| {
+        format!(
+            "<html><head><title>{}</title></head><body>{}</body></html>",
+            title, body
+        )
+        .into_bytes()
+    };
+    let hhc = r#"<HTML><BODY>
+<OBJECT type="text/site properties"><param name="ImageType" value="Folder"></OBJECT>
+<UL>
+  <LI> <OBJECT type="text/sitemap">
+    <param name="Name" value="Introduction">
+    <param name="Local" value="index.htm">
+    </OBJECT>
+  <LI> <OBJECT type="text/sitemap">
+    <param name="Name" value="Chapter &amp; Verse">
+    <param name="Local" value="chapter1.htm#part">
+    </OBJECT>
+  <UL>
+    <LI> <OBJECT type="text/sitemap">
+      <param name="Name" value="Section 1.1">
+      <param name="Local" value="sec%201.htm">
+      </OBJECT>
+  </UL>
+  <LI> <OBJECT type="text/sitemap">
+    <param name="Name" value="Website">
+    <param name="Local" value="http://example.com/">
+    </OBJECT>
+</UL>
+</BODY></HTML>"#;
+    let hhk = r#"<HTML><BODY><UL>
+  <LI> <OBJECT type="text/sitemap">
+    <param name="Name" value="appendix">
+    <param name="Local" value="/extra.htm">
+    <param name="Local" value="/Chapter1.htm">
+    </OBJECT>
+</UL></BODY></HTML>"#;
+    let system = system_file(&[
+        (0, b"toc.hhc\0"),
+        (1, b"index.hhk\0"),
+        (2, b"index.htm\0"),
+        (3, b"Caf\xe9 Help\0"),
+        (16, b"Arial,8,0\0"),
+    ]);
+    let big: Vec<u8> = (0..40000u32).map(|i| (i % 251) as u8).collect();
+
+    build_chm(&[
+        ("/", Vec::new(), false),
+        ("/#SYSTEM", system, false),
+        ("/$WWKeywordLinks/", Vec::new(), false),
+        ("/big.bin", big, true),
+        (
+            "/index.htm",
+            b"<html><head><title>Home</title><meta name=\"Author\" content=\"Jane Doe\"></head>\
+              <body><p>Welcome</p></body></html>"
+                .to_vec(),
+            true,
+        ),
+        (
+            "/Chapter1.htm",
+            page("One", "<p id=\"part\">Chapter one</p>"),
+            true,
+        ),
+        ("/sec 1.htm", page("1.1", "<p>Section</p>"), true),
+        ("/extra.htm", page("Extra", "<p>Appendix</p>"), true),
+        ("/orphan.htm;junk", page("Orphan", "<p>Unlisted</p>"), true),
+        ("/images/pic.png", b"\x89PNG\r\n\x1a\nfake".to_vec(), false),
+        ("/toc.hhc", hhc.as_bytes().to_vec(), true),
+        ("/index.hhk", hhk.as_bytes().to_vec(), true),
+    ])
+}
+
+#[test]
+fn test_chm_input_conversion() {
+    let temp_dir = tempdir().unwrap();
+    let output_dir = temp_dir.path().join("out");
+    let input_path = temp_dir.path().join("test.chm");
+    fs::write(&input_path, sample_chm()).unwrap();
+
+    let book = CHMInput::new().convert(&input_path, &output_dir).unwrap();
+
+    // Files from both sections, including the one spanning two LZX resets
+    let big = fs::read(output_dir.join("big.bin")).unwrap();
+    assert_eq!(big.len(), 40000);
+    assert!(big.iter().enumerate().all(|(i, b)| *b == (i % 251) as u8));
+    assert_eq!(
+        fs::read(output_dir.join("images/pic.png")).unwrap(),
+        b"\x89PNG\r\n\x1a\nfake"
+    );
+    let chapter = fs::read_to_string(output_dir.join("Chapter1.htm")).unwrap();
+    assert!(chapter.contains("Chapter one"));
+    assert!(output_dir.join("orphan.htm").exists());
+    assert!(!output_dir.join("toc.hhc").exists());
+
+    let png = book.manifest.get_by_href("images/pic.png").unwrap();
+    assert_eq!(png.media_type, "image/png");
+    assert!(book.manifest.get_by_href("$WWKeywordLinks").is_none());
+
+    // Topics in contents order, then those only in the index, then the rest
+    let spine: Vec<(String, bool)> = book
+        .spine
+        .items
+        .iter()
+        .map(|item| {
+            let href = book.manifest.get_by_id(&item.idref).unwrap().href.clone();
+            (href, item.linear)
+        })
+        .collect();
+    assert_eq!(
+        spine,
+        vec![
+            ("index.htm".to_string(), true),
+            ("Chapter1.htm".to_string(), true),
+            ("sec 1.htm".to_string(), true),
+            ("extra.htm".to_string(), true),
+            ("orphan.htm".to_string(), false),
+        ]
+    );
+
+    let toc = &book.toc.root.children;
+    assert_eq!(toc.len(), 3);
+    assert_eq!(toc[0].title.as_deref(), Some("Introduction"));
+    assert_eq!(toc[0].href.as_deref(), Some("index.htm"));
+    assert_eq!(toc[1].title.as_deref(), Some("Chapter & Verse"));
+    assert_eq!(toc[1].href.as_deref(), Some("Chapter1.htm#part"));
+    assert_eq!(toc[1].children.len(), 1);
+    assert_eq!(toc[1].children[0].href.as_deref(), Some("sec 1.htm"));
+    assert_eq!(toc[2].href, None);
+
+    assert_eq!(book.metadata.first("title"), Some("Café Help"));
+    assert_eq!(book.metadata.first("creator"), Some("Jane Doe"));
+}
+
+#[test]
+fn test_chm_metadata() {
+    let temp_dir = tempdir().unwrap();
+    let input_path = temp_dir.path().join("test.chm");
+    fs::write(&input_path, sample_chm()).unwrap();
+
+    let mi = calibre_ebooks::metadata::get_metadata(&input_path).unwrap();
+    assert_eq!(mi.title, "Café Help");
+    assert_eq!(mi.authors, vec!["Jane Doe"]);
+}
+
 #[test]
-fn test_chm_input_placeholder() {
+fn test_chm_input_rejects_invalid_files() {
     let temp_dir = tempdir().unwrap();
     let output_dir = temp_dir.path().join("out");
     let input_path = temp_dir.path().join("test.chm");
     fs::write(&input_path, b"DUMMY CHM DATA").unwrap();
+    assert!(CHMInput::new().convert(&input_path, &output_dir).is_err());
 
-    let input = CHMInput::new();
-    println!("Converting...");
-    let book = input.convert(&input_path, &output_dir).unwrap();
-    println!("Converted.");
-
-    // Verify it produced the placeholder
-    assert!(output_dir.join("index.html").exists());
-    let content = fs::read_to_string(output_dir.join("index.html")).unwrap();
-    assert!(content.contains("CHM Content Not Supported Yet"));
-    let titles = book.metadata.get("title");
-    assert!(!titles.is_empty());
-    assert_eq!(titles[0].value, "Converted CHM");
+    // A directory that runs past the end of the file
+    let mut chm = sample_chm();
+    chm.truncate(0x200);
+    fs::write(&input_path, chm).unwrap();
+    assert!(CHMInput::new().convert(&input_path, &output_dir).is_err());
 }
@@ -0,0 +1,136 @@
+use anyhow::Result;
+use calibre_ebooks::conversion::plumber::Plumber;
+use calibre_ebooks::conversion::registry::{OutputFormatPlugin, PluginRegistry};
+use calibre_ebooks::oeb::book::OEBBook;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tempfile::tempdir;
+
+struct RecordingOutput {
+    written: Arc<Mutex<Vec<String>>>,
+}
+
+impl OutputFormatPlugin for RecordingOutput {
+    fn name(&self) -> &str {
+        "Recording Output"
+    }
+
+    fn file_types(&self) -> &[&str] {
+        &["rec", "txt"]
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        &["application/x-recording"]
+    }
+
+    fn convert(&self, book: &mut OEBBook, output_path: &Path) -> Result<()> {
+        let mut written = self.written.lock().unwrap();
+        written.push(format!(
+            "{}:{}",
+            output_path.display(),
+            book.spine.items.len()
+        ));
+        Ok(())
+    }
+}
+
+#[test]
+fn test_builtin_lookup_by_extension_and_mime() {
+    let registry = PluginRegistry::with_builtins();
+
+    assert_eq!(
+        registry.input_for_extension("EPUB").unwrap().name(),
+        "EPUB Input"
+    );
+    assert_eq!(
+        registry.input_for_extension(".azw").unwrap().name(),
+        "MOBI Input"
+    );
+    assert_eq!(
+        registry.input_for_extension("cbz").unwrap().name(),
+        "Comic Input"
+    );
+    assert!(registry.input_for_extension("xyz").is_none());
+
+    assert_eq!(
+        registry.input_for_mime("application/rtf").unwrap().name(),
+        "RTF Input"
+    );
+    assert_eq!(
+        registry.output_for_mime("application/pdf").unwrap().name(),
+        "PDF Output"
+    );
+    assert_eq!(
+        registry
+            .output_for_path(Path::new("/tmp/out.MD"))
+            .unwrap()
+            .name(),
+        "TXT Output"
+    );
+
+    let inputs = registry.input_formats();
+    assert!(inputs.contains(&"docx".to_string()));
+    assert!(inputs.contains(&"htmlz".to_string()));
+    let outputs = registry.output_formats();
+    assert!(outputs.contains(&"fb2".to_string()));
+    assert!(outputs.windows(2).all(|w| w[0] < w[1]));
+
+    // Every builtin format can also be found by MIME type
+    for ext in &inputs {
+        let plugin = registry.input_for_extension(ext).unwrap();
+        let mime = plugin.mime_types().first().copied();
+        let found = mime.and_then(|m| registry.input_for_mime(m));
+        assert!(found.is_some(), "{}", ext);
+    }
+    for ext in &outputs {
+        let plugin = registry.output_for_extension(ext).unwrap();
+        let mime = plugin.mime_types().first().copied();
+        let found = mime.and_then(|m| registry.output_for_mime(m));
+        assert!(found.is_some(), "{}", ext);
+    }
+}
+
+#[test]
+fn test_third_party_plugin_overrides_builtin() {
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let mut registry = PluginRegistry::with_builtins();
+    registry.register_output(Arc::new(RecordingOutput {
+        written: written.clone(),
+    }));
+
+    assert_eq!(
+        registry.output_for_extension("txt").unwrap().name(),
+        "Recording Output"
+    );
+    assert_eq!(
+        registry.output_for_extension("rec").unwrap().name(),
+        "Recording Output"
+    );
+    assert!(registry.output_formats().contains(&"rec".to_string()));
+
+    let tmp = tempdir().unwrap();
+    let input = tmp.path().join("book.txt");
+    fs::write(&input, "Hello plumber").unwrap();
+    let output = tmp.path().join("out").join("book.rec");
+
+    let plumber = Plumber::with_registry(&input, &output, registry);
+    plumber.run().unwrap();
+
+    let written = written.lock().unwrap();
+    assert_eq!(written.len(), 1);
+    assert!(written[0].ends_with("book.rec:1"));
+    assert!(output.parent().unwrap().exists());
+}
+
+#[test]
+fn test_plumber_rejects_unknown_input() {
+    let tmp = tempdir().unwrap();
+    let input = tmp.path().join("book.unknown");
+    fs::write(&input, "data").unwrap();
+
+    let plumber =
+        Plumber::with_registry(&input, &tmp.path().join("out.epub"), PluginRegistry::new());
+    let err = plumber.run().unwrap_err();
+    assert!(err.to_string().contains("Unsupported input format"));
+}
@@ -0,0 +1,113 @@
+use calibre_ebooks::covers::{create_cover, fmt_sidx, generate_cover, CoverPrefs, CoverStyle};
+use calibre_ebooks::metadata::MetaInformation;
+
+fn small_prefs() -> CoverPrefs {
+    CoverPrefs::default().scaled(0.25)
+}
+
+fn book() -> MetaInformation {
+    MetaInformation {
+        title: "The Long & Winding Road".to_string(),
+        authors: vec!["Jane Doe".to_string(), "John Roe".to_string()],
+        series: Some("Roads".to_string()),
+        series_index: 2.5,
+        ..MetaInformation::default()
+    }
+}
+
+#[test]
+fn test_generate_cover_is_jpeg_of_configured_size() {
+    let data = generate_cover(&book(), &small_prefs()).unwrap();
+    assert_eq!(
+        image::guess_format(&data).unwrap(),
+        image::ImageFormat::Jpeg
+    );
+    let img = image::load_from_memory(&data).unwrap();
+    assert_eq!((img.width(), img.height()), (300, 400));
+}
+
+#[test]
+fn test_generate_cover_is_deterministic() {
+    let prefs = small_prefs();
+    assert_eq!(
+        generate_cover(&book(), &prefs).unwrap(),
+        generate_cover(&book(), &prefs).unwrap()
+    );
+}
+
+#[test]
+fn test_every_style_draws_text() {
+    for style in CoverStyle::ALL {
+        let prefs = small_prefs()
+            .with_style(style.name())
+            .with_color_theme("Water");
+        assert_eq!(prefs.styles(), vec![style]);
+
+        let with_text = image::load_from_memory(&generate_cover(&book(), &prefs).unwrap())
+            .unwrap()
+            .to_rgb8();
+        let blank = image::load_from_memory(
+            &create_cover("", &["Unknown".to_string()], None, 1.0, &prefs).unwrap(),
+        )
+        .unwrap()
+        .to_rgb8();
+
+        // The title is in the top third of the cover
+        let differing = (0..with_text.height() / 3)
+            .flat_map(|y| (0..with_text.width()).map(move |x| (x, y)))
+            .filter(|&(x, y)| {
+                let (a, b) = (with_text.get_pixel(x, y), blank.get_pixel(x, y));
+                a.0.iter().zip(b.0.iter()).any(|(a, b)| a.abs_diff(*b) > 60)
+            })
+            .count();
+        assert!(differing > 100, "{} shows no title", style.name());
+    }
+}
+
+#[test]
+fn test_prefs_restrict_themes_and_styles() {
+    let prefs = CoverPrefs::default();
+    assert_eq!(prefs.color_themes().len(), 4);
+    assert_eq!(prefs.styles().len(), 5);
+
+    let prefs = prefs.with_color_theme("Earth");
+    assert_eq!(
+        prefs.color_themes(),
+        vec![[
+            [0xe8, 0xd9, 0xac],
+            [0xc7, 0xb0, 0x7b],
+            [0x56, 0x46, 0x28],
+            [0x38, 0x2d, 0x1a]
+        ]]
+    );
+
+    // Unknown names leave the prefs alone, disabling everything restores all
+    assert_eq!(CoverPrefs::default().with_style("Nope").styles().len(), 5);
+    let prefs = CoverPrefs {
+        disabled_styles: CoverStyle::ALL
+            .iter()
+            .map(|s| s.name().to_string())
+            .collect(),
+        ..CoverPrefs::default()
+    };
+    assert_eq!(prefs.styles().len(), 5);
+
+    let prefs: CoverPrefs = serde_json::from_str(
+        r#"{"cover_width": 600, "color_themes": {"Mine": "000000 ffffff zz 123456"}}"#,
+    )
+    .unwrap();
+    assert_eq!(prefs.cover_height, 1600);
+    let themes = prefs.with_color_theme("Mine").color_themes();
+    assert_eq!(
+        themes,
+        vec![[[0, 0, 0], [0xff, 0xff, 0xff], [0, 0, 0], [0x12, 0x34, 0x56]]]
+    );
+}
+
+#[test]
+fn test_fmt_sidx() {
+    assert_eq!(fmt_sidx(1.0), "1");
+    assert_eq!(fmt_sidx(2.5), "2.5");
+    assert_eq!(fmt_sidx(3.25), "3.25");
+    assert_eq!(fmt_sidx(0.0), "0");
+}
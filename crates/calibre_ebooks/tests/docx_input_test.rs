@@ -1,5 +1,4 @@
 use calibre_ebooks::input::docx_input::DOCXInput;
-use calibre_ebooks::oeb::book::OEBBook;
 use std::fs::File;
 use std::io::Write;
 use tempfile::tempdir;
@@ -67,8 +66,206 @@ fn test_docx_input_conversion() {
         .iter()
         .find(|i| i.href == "index.html")
         .expect("index.html missing");
-    let content = std::fs::read_to_string(&html_item.path).expect("Failed to read output html");
+    let content = std::fs::read_to_string(output_dir.join(&html_item.href))
+        .expect("Failed to read output html");
 
-    assert!(content.contains("<h1>Chapter 1</h1>"));
+    assert!(content.contains("<h1 id=\"heading_1\">Chapter 1</h1>"));
     assert!(content.contains("<p>Hello World</p>"));
 }
+
+const W_NS: &str = r#"xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships""#;
+
+fn write_docx(path: &std::path::Path, parts: &[(&str, Vec<u8>)]) {
+    let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+    let options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    for (name, data) in parts {
+        zip.start_file(*name, options).unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+fn manuscript_parts() -> Vec<(&'static str, Vec<u8>)> {
+    let mut png = Vec::new();
+    image::DynamicImage::new_rgb8(2, 2)
+        .write_to(
+            &mut std::io::Cursor::new(&mut png),
+            image::ImageOutputFormat::Png,
+        )
+        .unwrap();
+
+    let rels = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="word/document.xml"/>
+<Relationship Id="rId2" Type="http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties" Target="docProps/core.xml"/>
+</Relationships>"#;
+    let core = r#"<?xml version="1.0" encoding="UTF-8"?>
+<cp:coreProperties xmlns:cp="http://schemas.openxmlformats.org/package/2006/metadata/core-properties" xmlns:dc="http://purl.org/dc/elements/1.1/">
+<dc:title>Manuscript</dc:title><dc:creator>Ann Author</dc:creator>
+</cp:coreProperties>"#;
+    let doc_rels = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/>
+<Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/numbering" Target="numbering.xml"/>
+<Relationship Id="rId3" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/footnotes" Target="footnotes.xml"/>
+<Relationship Id="rId4" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink" Target="https://example.com/" TargetMode="External"/>
+<Relationship Id="rId5" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/image" Target="media/dot.png"/>
+</Relationships>"#;
+    let styles = format!(
+        r#"<w:styles {}>
+<w:docDefaults><w:rPrDefault><w:rPr><w:rFonts w:ascii="Georgia"/><w:sz w:val="24"/></w:rPr></w:rPrDefault></w:docDefaults>
+<w:style w:type="paragraph" w:default="1" w:styleId="Normal"><w:name w:val="Normal"/><w:pPr><w:spacing w:after="120"/></w:pPr></w:style>
+<w:style w:type="paragraph" w:styleId="Heading1"><w:name w:val="heading 1"/><w:basedOn w:val="Normal"/><w:pPr><w:outlineLvl w:val="0"/></w:pPr><w:rPr><w:b/><w:sz w:val="32"/></w:rPr></w:style>
+<w:style w:type="paragraph" w:styleId="Heading2"><w:name w:val="heading 2"/><w:basedOn w:val="Normal"/><w:pPr><w:outlineLvl w:val="1"/></w:pPr></w:style>
+<w:style w:type="character" w:styleId="Emphasis"><w:name w:val="Emphasis"/><w:rPr><w:i/></w:rPr></w:style>
+<w:style w:type="table" w:styleId="TableGrid"><w:name w:val="Table Grid"/><w:tblPr><w:tblBorders><w:top w:val="single"/><w:bottom w:val="single"/></w:tblBorders></w:tblPr></w:style>
+</w:styles>"#,
+        W_NS
+    );
+    let numbering = format!(
+        r#"<w:numbering {}>
+<w:abstractNum w:abstractNumId="0">
+<w:lvl w:ilvl="0"><w:start w:val="1"/><w:numFmt w:val="decimal"/><w:lvlText w:val="%1."/></w:lvl>
+<w:lvl w:ilvl="1"><w:numFmt w:val="bullet"/><w:lvlText w:val="o"/></w:lvl>
+</w:abstractNum>
+<w:num w:numId="1"><w:abstractNumId w:val="0"/></w:num>
+</w:numbering>"#,
+        W_NS
+    );
+    let footnotes = format!(
+        r#"<w:footnotes {}>
+<w:footnote w:type="separator" w:id="-1"><w:p><w:r><w:separator/></w:r></w:p></w:footnote>
+<w:footnote w:id="1"><w:p><w:r><w:footnoteRef/></w:r><w:r><w:t xml:space="preserve"> A note.</w:t></w:r></w:p></w:footnote>
+</w:footnotes>"#,
+        W_NS
+    );
+    let item = |level: u8, text: &str| {
+        format!(
+            r#"<w:p><w:pPr><w:numPr><w:ilvl w:val="{}"/><w:numId w:val="1"/></w:numPr></w:pPr><w:r><w:t>{}</w:t></w:r></w:p>"#,
+            level, text
+        )
+    };
+    let cell = |props: &str, text: &str| {
+        format!(
+            r#"<w:tc><w:tcPr>{}</w:tcPr><w:p><w:r><w:t>{}</w:t></w:r></w:p></w:tc>"#,
+            props, text
+        )
+    };
+    let document = format!(
+        r#"<w:document {ns} xmlns:wp="http://schemas.openxmlformats.org/drawingml/2006/wordprocessingDrawing" xmlns:a="http://schemas.openxmlformats.org/drawingml/2006/main" xmlns:pic="http://schemas.openxmlformats.org/drawingml/2006/picture"><w:body>
+<w:p><w:pPr><w:pStyle w:val="Heading1"/></w:pPr><w:r><w:t>Part One</w:t></w:r></w:p>
+<w:p><w:r><w:t xml:space="preserve">Some </w:t></w:r><w:r><w:rPr><w:rStyle w:val="Emphasis"/></w:rPr><w:t>styled</w:t></w:r><w:r><w:t xml:space="preserve"> text, a </w:t></w:r><w:hyperlink r:id="rId4"><w:r><w:t>site</w:t></w:r></w:hyperlink><w:r><w:footnoteReference w:id="1"/></w:r></w:p>
+<w:p><w:pPr><w:pStyle w:val="Heading2"/></w:pPr><w:r><w:t>Section A</w:t></w:r></w:p>
+{one}{two}{nested}
+<w:tbl><w:tblPr><w:tblStyle w:val="TableGrid"/></w:tblPr><w:tblGrid><w:gridCol w:w="2000"/><w:gridCol w:w="2000"/></w:tblGrid>
+<w:tr><w:trPr><w:tblHeader/></w:trPr>{head}</w:tr>
+<w:tr>{tall}{b}</w:tr>
+<w:tr>{merged}{c}</w:tr>
+</w:tbl>
+<w:p><w:r><w:drawing><wp:inline><wp:extent cx="1270000" cy="635000"/><wp:docPr id="1" name="Picture 1" descr="A dot"/><a:graphic><a:graphicData><pic:pic><pic:blipFill><a:blip r:embed="rId5"/></pic:blipFill></pic:pic></a:graphicData></a:graphic></wp:inline></w:drawing></w:r></w:p>
+<w:p><w:r><w:fldChar w:fldCharType="begin"/></w:r><w:r><w:instrText xml:space="preserve"> HYPERLINK "http://field.example/" </w:instrText></w:r><w:r><w:fldChar w:fldCharType="separate"/></w:r><w:r><w:t>field link</w:t></w:r><w:r><w:fldChar w:fldCharType="end"/></w:r></w:p>
+<w:p><w:r><w:rPr><w:vanish/></w:rPr><w:t>Hidden</w:t></w:r><w:r><w:br w:type="page"/></w:r></w:p>
+<w:p><w:pPr><w:pStyle w:val="Heading1"/></w:pPr><w:r><w:t>Part Two</w:t></w:r></w:p>
+</w:body></w:document>"#,
+        ns = W_NS,
+        one = item(0, "One"),
+        two = item(0, "Two"),
+        nested = item(1, "Nested"),
+        head = cell(r#"<w:gridSpan w:val="2"/>"#, "Head"),
+        tall = cell(r#"<w:vMerge w:val="restart"/>"#, "Tall"),
+        b = cell("", "B"),
+        merged = cell("<w:vMerge/>", ""),
+        c = cell("", "C"),
+    );
+
+    let content_types = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+<Default Extension="xml" ContentType="application/xml"/>
+<Default Extension="png" ContentType="image/png"/>
+</Types>"#;
+
+    vec![
+        ("[Content_Types].xml", content_types.as_bytes().to_vec()),
+        ("_rels/.rels", rels.as_bytes().to_vec()),
+        ("docProps/core.xml", core.as_bytes().to_vec()),
+        ("word/_rels/document.xml.rels", doc_rels.as_bytes().to_vec()),
+        ("word/document.xml", document.into_bytes()),
+        ("word/styles.xml", styles.into_bytes()),
+        ("word/numbering.xml", numbering.into_bytes()),
+        ("word/footnotes.xml", footnotes.into_bytes()),
+        ("word/media/dot.png", png),
+    ]
+}
+
+#[test]
+fn test_docx_input_structure() {
+    let tmp_dir = tempdir().unwrap();
+    let docx_path = tmp_dir.path().join("manuscript.docx");
+    write_docx(&docx_path, &manuscript_parts());
+    let output_dir = tmp_dir.path().join("output");
+
+    let book = DOCXInput::new().convert(&docx_path, &output_dir).unwrap();
+    assert_eq!(book.metadata.first("title"), Some("Manuscript"));
+    assert_eq!(book.metadata.first("creator"), Some("Ann Author"));
+    assert!(book
+        .manifest
+        .iter()
+        .any(|i| i.href == "images/dot.png" && i.media_type == "image/png"));
+    assert!(output_dir.join("images/dot.png").exists());
+
+    // Headings drive the nested table of contents
+    let toc = &book.toc.root.children;
+    assert_eq!(toc.len(), 2);
+    assert_eq!(toc[0].title.as_deref(), Some("Part One"));
+    assert_eq!(toc[0].href.as_deref(), Some("index.html#heading_1"));
+    assert_eq!(toc[0].children.len(), 1);
+    assert_eq!(toc[0].children[0].title.as_deref(), Some("Section A"));
+    assert_eq!(toc[1].title.as_deref(), Some("Part Two"));
+    assert_eq!(toc[1].href.as_deref(), Some("index.html#heading_3"));
+
+    let html = std::fs::read_to_string(output_dir.join("index.html")).unwrap();
+    let css = std::fs::read_to_string(output_dir.join("docx.css")).unwrap();
+
+    // Styles become classes relative to the Normal style
+    assert!(css.contains("body { font-size: 12pt; font-family: \"Georgia\", serif }"));
+    assert!(css.contains("margin-bottom: 6pt"));
+    assert!(html.contains("<h1 id=\"heading_1\" class=\"p1\">Part One</h1>"));
+    assert!(css.contains(".p1 { font-weight: bold; font-size: 16pt }"));
+    assert!(html.contains("<span class=\"s1\">styled</span>"));
+    assert!(css.contains(".s1 { font-style: italic }"));
+
+    assert!(html.contains("<a href=\"https://example.com/\">site</a>"));
+    assert!(html.contains("<a href=\"http://field.example/\">field link</a>"));
+    assert!(!html.contains("Hidden"));
+
+    // Lists nest by level
+    assert!(html.contains(
+        "<ol class=\"l1\"><li>One</li><li>Two<ul class=\"l2\"><li>Nested</li></ul></li></ol>"
+    ));
+    assert!(css.contains(".l2 { list-style-type: circle }"));
+
+    // Merged cells span the grid
+    assert!(
+        html.contains("<thead><tr><td class=\"c1\" colspan=\"2\"><p>Head</p></td></tr></thead>")
+    );
+    assert!(html.contains("<td class=\"c1\" rowspan=\"2\"><p>Tall</p></td>"));
+    assert!(html.contains("<tr><td class=\"c1\"><p>C</p></td></tr>"));
+    assert!(css.contains("border: 0.5pt solid black"));
+
+    assert!(html.contains(
+        "<img src=\"images/dot.png\" alt=\"A dot\" style=\"width: 100pt; height: 50pt\"/>"
+    ));
+
+    // Footnotes are linked both ways
+    assert!(html.contains(
+        "<a class=\"noteref\" epub:type=\"noteref\" href=\"#fn1\" id=\"fnref1\"><sup>1</sup></a>"
+    ));
+    assert!(html.contains(
+        "<aside epub:type=\"footnote\" id=\"fn1\"><p><a href=\"#fnref1\">1</a> A note.</p></aside>"
+    ));
+
+    // The page break moves to the next paragraph
+    assert!(html.contains("<h1 id=\"heading_3\" class=\"p"));
+    assert!(css.contains("page-break-before: always"));
+}
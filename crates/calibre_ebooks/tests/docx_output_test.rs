@@ -1,14 +1,17 @@
 use calibre_ebooks::oeb::book::OEBBook;
 use calibre_ebooks::oeb::container::DirContainer;
+use calibre_ebooks::oeb::toc::TOCNode;
 use calibre_ebooks::output::docx_output::DOCXOutput;
 use std::fs;
+use std::io::Read;
+use std::path::Path;
 use tempfile::tempdir;
 
 #[test]
 fn test_docx_output_basics() {
     let temp_dir = tempdir().unwrap();
     let output_path = temp_dir.path().join("test.docx");
-    
+
     // Create Dummy Book
     let container_path = temp_dir.path().join("src");
     fs::create_dir_all(&container_path).unwrap();
@@ -29,3 +32,148 @@ fn test_docx_output_basics() {
     assert!(archive.by_name("_rels/.rels").is_ok());
     assert!(archive.by_name("word/document.xml").is_ok());
 }
+
+fn build_book(book_dir: &Path) -> OEBBook {
+    let mut png = Vec::new();
+    image::RgbImage::from_pixel(4, 2, image::Rgb([255, 0, 0]))
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    fs::create_dir_all(book_dir.join("images")).unwrap();
+    fs::write(book_dir.join("images/red.png"), png).unwrap();
+    fs::write(
+        book_dir.join("ch1.html"),
+        r##"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops"><head><title>x</title></head><body>
+<h1>Chapter &amp; One</h1>
+<p style="text-align: center">Some <b>bold</b>, <i>italic</i> and <u>under</u> text &euro;5.</p>
+<ol><li>first</li><li>second<ul><li>nested</li></ul></li></ol>
+<table><tr><th>H1</th><th>H2</th></tr><tr><td>a</td><td><span style="color: #ff0000">b</span></td></tr></table>
+<p>A <a href="http://example.com/">link</a> and <a href="ch2.html#end">a jump</a>.</p>
+<p>Noted<a epub:type="noteref" href="#n1" id="r1">1</a>.</p>
+<p><img src="images/red.png" alt="red"/></p>
+<aside epub:type="footnote" id="n1"><p><a href="#r1">1</a> The note.</p></aside>
+</body></html>"##,
+    )
+    .unwrap();
+    fs::write(
+        book_dir.join("ch2.html"),
+        r#"<html xmlns="http://www.w3.org/1999/xhtml"><body><p id="end">Second &lt;doc&gt;</p></body></html>"#,
+    )
+    .unwrap();
+
+    let container = Box::new(DirContainer::new(book_dir));
+    let mut book = OEBBook::new(container);
+    book.manifest
+        .add("ch1", "ch1.html", "application/xhtml+xml");
+    book.manifest
+        .add("ch2", "ch2.html", "application/xhtml+xml");
+    book.manifest.add("red", "images/red.png", "image/png");
+    book.spine.add("ch1", true);
+    book.spine.add("ch2", true);
+    book.toc.root.add(TOCNode::new(
+        Some("Chapter One".to_string()),
+        Some("ch1.html".to_string()),
+    ));
+    book.metadata.add("title", "Styled Book");
+    book.metadata.add("creator", "Jane Doe");
+    book.metadata.add("language", "en");
+    book
+}
+
+fn read_part(archive: &mut zip::ZipArchive<fs::File>, name: &str) -> String {
+    let mut xml = String::new();
+    archive
+        .by_name(name)
+        .unwrap_or_else(|_| panic!("missing {}", name))
+        .read_to_string(&mut xml)
+        .unwrap();
+    xml
+}
+
+#[test]
+fn test_docx_output_content() {
+    let tmp_dir = tempdir().unwrap();
+    let book = build_book(&tmp_dir.path().join("book"));
+    let output_path = tmp_dir.path().join("styled.docx");
+    DOCXOutput::new().convert(&book, &output_path).unwrap();
+    let mut archive = zip::ZipArchive::new(fs::File::open(&output_path).unwrap()).unwrap();
+
+    // Every XML part is well formed
+    let names: Vec<String> = archive.file_names().map(str::to_string).collect();
+    for name in names
+        .iter()
+        .filter(|n| n.ends_with(".xml") || n.ends_with(".rels"))
+    {
+        let xml = read_part(&mut archive, name);
+        roxmltree::Document::parse(&xml).unwrap_or_else(|e| panic!("{}: {}", name, e));
+    }
+
+    let doc = read_part(&mut archive, "word/document.xml");
+    assert!(doc.contains("<w:pStyle w:val=\"Heading1\"/>"));
+    assert!(doc.contains("Chapter &amp; One"));
+    assert!(doc.contains("<w:jc w:val=\"center\"/>"));
+    assert!(doc.contains("<w:rPr><w:b/></w:rPr><w:t xml:space=\"preserve\">bold</w:t>"));
+    assert!(doc.contains("<w:rPr><w:i/></w:rPr><w:t xml:space=\"preserve\">italic</w:t>"));
+    assert!(doc.contains("<w:u w:val=\"single\"/>"));
+    assert!(doc.contains("text \u{20ac}5."));
+    assert!(doc.contains("<w:color w:val=\"FF0000\"/>"));
+    assert!(doc.contains("<w:numPr><w:ilvl w:val=\"0\"/><w:numId w:val=\"1\"/></w:numPr>"));
+    assert!(doc.contains("<w:numPr><w:ilvl w:val=\"1\"/><w:numId w:val=\"1\"/></w:numPr>"));
+    assert!(doc.contains("<w:tblStyle w:val=\"TableGrid\"/>"));
+    assert_eq!(doc.matches("<w:gridCol ").count(), 2);
+    assert_eq!(doc.matches("<w:tc>").count(), 4);
+    assert!(doc.contains("<w:hyperlink w:anchor=\"bm2\""));
+    assert!(doc.contains("w:name=\"bm2\""));
+    assert!(doc.contains("<wp:extent cx=\"38100\" cy=\"19050\"/>"));
+    assert!(doc.contains("<w:pageBreakBefore/>"));
+    assert!(doc.contains("Second &lt;doc&gt;"));
+    // The table of contents links to the first chapter
+    assert!(doc.contains("<w:pStyle w:val=\"TOC1\"/></w:pPr><w:hyperlink w:anchor=\"bm1\""));
+    assert!(doc.contains("w:name=\"bm1\""));
+
+    let rels = read_part(&mut archive, "word/_rels/document.xml.rels");
+    assert!(rels.contains("Target=\"http://example.com/\" TargetMode=\"External\""));
+    assert!(rels.contains("relationships/image\" Target=\"media/image1.png\""));
+    assert!(rels.contains("Target=\"numbering.xml\""));
+    assert!(archive.by_name("word/media/image1.png").is_ok());
+
+    let numbering = read_part(&mut archive, "word/numbering.xml");
+    assert!(numbering
+        .contains("<w:lvl w:ilvl=\"0\"><w:start w:val=\"1\"/><w:numFmt w:val=\"decimal\"/>"));
+    assert!(numbering
+        .contains("<w:lvl w:ilvl=\"1\"><w:start w:val=\"1\"/><w:numFmt w:val=\"bullet\"/>"));
+
+    let styles = read_part(&mut archive, "word/styles.xml");
+    assert!(styles.contains("w:styleId=\"Heading1\""));
+    assert!(styles.contains("<w:sz w:val=\"48\"/>"));
+    assert!(styles.contains("<w:lang w:val=\"en\"/>"));
+}
+
+#[test]
+fn test_docx_output_footnotes_and_properties() {
+    let tmp_dir = tempdir().unwrap();
+    let book = build_book(&tmp_dir.path().join("book"));
+    let output_path = tmp_dir.path().join("notes.docx");
+    DOCXOutput::new().convert(&book, &output_path).unwrap();
+    let mut archive = zip::ZipArchive::new(fs::File::open(&output_path).unwrap()).unwrap();
+
+    let doc = read_part(&mut archive, "word/document.xml");
+    assert!(doc.contains(
+        "<w:rPr><w:rStyle w:val=\"FootnoteReference\"/></w:rPr><w:footnoteReference w:id=\"1\"/>"
+    ));
+    assert!(!doc.contains("The note."));
+
+    let notes = read_part(&mut archive, "word/footnotes.xml");
+    assert!(notes.contains("<w:footnote w:id=\"1\"><w:p><w:pPr><w:pStyle w:val=\"FootnoteText\"/>"));
+    assert!(notes.contains("<w:footnoteRef/>"));
+    assert!(notes.contains("The note."));
+    assert!(!notes.contains("w:anchor"));
+    assert!(read_part(&mut archive, "word/settings.xml").contains("<w:footnotePr>"));
+    assert!(read_part(&mut archive, "[Content_Types].xml").contains("/word/footnotes.xml"));
+
+    let core = read_part(&mut archive, "docProps/core.xml");
+    assert!(core.contains("<dc:title>Styled Book</dc:title>"));
+    assert!(core.contains("<dc:creator>Jane Doe</dc:creator>"));
+    assert!(core.contains("<dc:language>en</dc:language>"));
+    assert!(core.contains("<dcterms:modified xsi:type=\"dcterms:W3CDTF\">"));
+    assert!(read_part(&mut archive, "_rels/.rels").contains("docProps/core.xml"));
+}
@@ -1,44 +1,396 @@
 use calibre_ebooks::input::lit_input::LitInput;
 use calibre_ebooks::lit::writer::LitWriter;
-use std::fs::File;
-use std::io::BufWriter;
+use std::fs::{self, File};
+use std::io::{BufWriter, Cursor};
 use tempfile::tempdir;
 
+const FLAG_OPENING: u32 = 1;
+const FLAG_CLOSING: u32 = 2;
+
+/// Encodes documents in the binary LIT markup format.
+#[derive(Default)]
+struct Binary(Vec<u8>);
+
+impl Binary {
+    fn ch(&mut self, c: u32) -> &mut Self {
+        let mut buf = [0u8; 4];
+        let c = char::from_u32(c).unwrap();
+        self.0.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        self
+    }
+
+    fn text(&mut self, s: &str) -> &mut Self {
+        for c in s.chars() {
+            self.ch(c as u32);
+        }
+        self
+    }
+
+    fn tag(&mut self, flags: u32, tag: u32, attrs: &[(u32, &str)]) -> &mut Self {
+        self.ch(0).ch(flags).ch(tag);
+        for &(code, value) in attrs {
+            self.ch(code)
+                .ch(value.chars().count() as u32 + 1)
+                .text(value);
+        }
+        self.ch(0)
+    }
+
+    fn open(&mut self, tag: u32, attrs: &[(u32, &str)]) -> &mut Self {
+        self.tag(FLAG_OPENING, tag, attrs)
+    }
+
+    fn empty(&mut self, tag: u32, attrs: &[(u32, &str)]) -> &mut Self {
+        self.tag(FLAG_OPENING | FLAG_CLOSING, tag, attrs)
+    }
+
+    fn close(&mut self, tag: u32) -> &mut Self {
+        self.ch(0).ch(FLAG_CLOSING).ch(tag)
+    }
+
+    fn element(&mut self, tag: u32, attrs: &[(u32, &str)], text: &str) -> &mut Self {
+        self.open(tag, attrs).text(text).close(tag)
+    }
+}
+
+/// A link to the manifest item `internal`; the first character is a marker.
+fn link(internal: &str) -> String {
+    format!("\u{1}{}", internal)
+}
+
+fn sized(s: &str) -> Vec<u8> {
+    let mut out = Binary::default();
+    out.ch(s.chars().count() as u32).text(s);
+    out.0
+}
+
+fn encint(mut value: usize) -> Vec<u8> {
+    let mut out = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value > 0 {
+        out.insert(0, 0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    out
+}
+
+fn u32s(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// Build a LIT file whose book content lives in an LZX compressed section
+/// made of a single uncompressed block.
+fn build_lit(section1: &[(&str, Vec<u8>)], extra: &[&str]) -> Vec<u8> {
+    // The compressed section's content
+    let mut uncompressed = Vec::new();
+    let mut entries: Vec<(String, usize, usize, usize)> = Vec::new();
+    for (name, data) in section1 {
+        entries.push((name.to_string(), 1, uncompressed.len(), data.len()));
+        uncompressed.extend_from_slice(data);
+    }
+    let len = uncompressed.len();
+    // Intel bit 0, block type 3 and the 24-bit length, padded to 32 bits
+    let header = ((3u32 << 24) | len as u32) << 4;
+    let mut lzx = [
+        ((header >> 16) as u16).to_le_bytes(),
+        (header as u16).to_le_bytes(),
+    ]
+    .concat();
+    lzx.extend(u32s(&[1, 1, 1]));
+    lzx.extend_from_slice(&uncompressed);
+    if len % 2 == 1 {
+        lzx.push(0);
+    }
+
+    let mut names = vec![0u8, 0, 2, 0];
+    for name in ["Uncompressed", "MSCompressed"] {
+        names.extend((name.len() as u16).to_le_bytes());
+        for unit in name.encode_utf16().chain([0]) {
+            names.extend(unit.to_le_bytes());
+        }
+    }
+    let guid = [
+        0xC6, 0x07, 0x90, 0x0A, 0x76, 0x40, 0xD3, 0x11, 0x87, 0x89, 0x00, 0x00, 0xF8, 0x10, 0x57,
+        0x54,
+    ];
+    let control = [u32s(&[6]), b"LZXC".to_vec(), u32s(&[2, 1, 1, 2, 0, 0])].concat();
+    let reset_table = [
+        u32s(&[2, 1, 8, 40]),
+        (len as u64).to_le_bytes().to_vec(),
+        (lzx.len() as u64).to_le_bytes().to_vec(),
+        0x8000u64.to_le_bytes().to_vec(),
+        0u64.to_le_bytes().to_vec(),
+    ]
+    .concat();
+    let storage = "::DataSpace/Storage/MSCompressed";
+    let section0: Vec<(String, Vec<u8>)> = vec![
+        ("::DataSpace/NameList".to_string(), names),
+        (format!("{}/Transform/List", storage), guid.to_vec()),
+        (format!("{}/ControlData", storage), control),
+        (format!("{}/Content", storage), lzx),
+        (
+            format!(
+                "{}/Transform/{{0A9007C6-4076-11D3-8789-0000F8105754}}/InstanceData/ResetTable",
+                storage
+            ),
+            reset_table,
+        ),
+    ];
+    let mut content = Vec::new();
+    for (name, data) in &section0 {
+        entries.push((name.clone(), 0, content.len(), data.len()));
+        content.extend_from_slice(data);
+    }
+    for name in extra {
+        entries.push((name.to_string(), 0, 0, 0));
+    }
+
+    // One AOLL chunk holding the whole directory
+    let chunk_size = 4096;
+    let mut chunk = b"AOLL".to_vec();
+    chunk.resize(48, 0);
+    for (name, section, offset, size) in &entries {
+        chunk.extend(encint(name.len()));
+        chunk.extend_from_slice(name.as_bytes());
+        chunk.extend(encint(*section));
+        chunk.extend(encint(*offset));
+        chunk.extend(encint(*size));
+    }
+    assert!(chunk.len() < chunk_size - 2);
+    chunk.resize(chunk_size - 2, 0);
+    chunk.extend((entries.len() as u16).to_le_bytes());
+    let directory = [
+        b"IFCM".to_vec(),
+        u32s(&[1, chunk_size as u32, 0, 0, 0, 1, 0]),
+        chunk,
+    ]
+    .concat();
+    let count = [b"IFCM".to_vec(), u32s(&[1, 0x200, 0, 0, 0, 0, 0])].concat();
+
+    let (hdr_len, num_pieces, sec_hdr_len) = (40u32, 3u32, 104u32);
+    let dir_offset = hdr_len + num_pieces * 16 + sec_hdr_len;
+    let count_offset = dir_offset + directory.len() as u32;
+    let content_offset = count_offset + count.len() as u32;
+
+    let mut lit = b"ITOLITLS".to_vec();
+    lit.extend(u32s(&[1, hdr_len, num_pieces, sec_hdr_len]));
+    lit.extend([0u8; 16]);
+    lit.extend(u32s(&[0, 0, 0, 0]));
+    lit.extend(u32s(&[dir_offset, 0, directory.len() as u32, 0]));
+    lit.extend(u32s(&[count_offset, 0, count.len() as u32, 0]));
+    // Secondary header: CAOL and ITSF blocks
+    lit.extend(u32s(&[0, 8]));
+    let mut caol = b"CAOL".to_vec();
+    caol.extend(u32s(&[2, 0, 0, 0, chunk_size as u32, 0x200, 0, 0]));
+    caol.resize(48, 0);
+    let mut itsf = b"ITSF".to_vec();
+    itsf.extend(u32s(&[4, 0x20, 1, content_offset, 0, 0, 0x409]));
+    itsf.resize(48, 0);
+    lit.extend(caol);
+    lit.extend(itsf);
+    assert_eq!(lit.len() as u32, dir_offset);
+    lit.extend(directory);
+    lit.extend(count);
+    lit.extend(content);
+    lit
+}
+
+fn manifest() -> Vec<u8> {
+    let mut out = vec![1, b'/'];
+    let groups: [&[(&str, &str, &str)]; 4] = [
+        &[
+            ("c1", "book/text/chapter1.htm", "text/x-oeb1-document"),
+            ("c2", "book/text/chapter2.htm", "text/x-oeb1-document"),
+        ],
+        &[],
+        &[("css1", "book/styles/style.css", "text/x-oeb1-css")],
+        &[("img1", "book/images/pic%20one.png", "image/png")],
+    ];
+    for group in groups {
+        out.extend((group.len() as u32).to_le_bytes());
+        for (internal, original, mime) in group {
+            out.extend([0u8; 4]);
+            out.extend(sized(internal));
+            out.extend(sized(original));
+            out.extend(sized(mime));
+            out.push(0);
+        }
+    }
+    out.push(0);
+    out
+}
+
+fn opf() -> Vec<u8> {
+    let mut b = Binary::default();
+    b.open(0x01, &[(0x13, "uid")])
+        .open(0x14, &[])
+        .open(0x15, &[(0x0B, "http://purl.org/dc/elements/1.0/")])
+        .element(0x02, &[], "The LIT Test")
+        .element(0x03, &[(0x0D, "aut"), (0x0E, "Doe, Jane")], "Jane Doe")
+        .element(0x1F, &[], "en")
+        .element(0x1D, &[(0x06, "uid")], "urn:lit:test")
+        .close(0x15)
+        .close(0x14)
+        .open(0x10, &[]);
+    for (id, internal, mime) in [
+        ("ch1", "c1", "text/x-oeb1-document"),
+        ("ch2", "c2", "text/x-oeb1-document"),
+        ("css", "css1", "text/x-oeb1-css"),
+    ] {
+        b.empty(0x11, &[(0x06, id), (0x07, &link(internal)), (0x08, mime)]);
+    }
+    b.close(0x10)
+        .open(0x12, &[])
+        .empty(0x13, &[(0x0A, "ch1")])
+        .empty(0x13, &[(0x0A, "ch2")])
+        .close(0x12)
+        .open(0x28, &[])
+        .empty(0x29, &[(0x12, "toc"), (0x11, "Start"), (0x07, &link("c1"))])
+        .close(0x28)
+        .close(0x01);
+    b.0
+}
+
+fn chapter(title: &str, body: impl Fn(&mut Binary)) -> Vec<u8> {
+    let (html, head, title_tag, link_tag, body_tag) = (0x32, 0x30, 0x65, 0x3C, 0x10);
+    let mut b = Binary::default();
+    b.open(html, &[])
+        .open(head, &[])
+        .element(title_tag, &[], title)
+        .empty(
+            link_tag,
+            &[
+                (0x3EF, "stylesheet"),
+                (0x3F1, "text/css"),
+                (0x3EE, &link("css1")),
+            ],
+        )
+        .close(head)
+        .open(body_tag, &[]);
+    body(&mut b);
+    b.close(body_tag).close(html);
+    b.0
+}
+
+fn sample_lit(extra: &[&str]) -> Vec<u8> {
+    let (h1, p, bold, img, a) = (0x2A, 0x4A, 0x08, 0x35, 0x03);
+    let chapter1 = chapter("Chapter One", |b| {
+        b.element(h1, &[(0x83EB, "start")], "Chapter One")
+            .open(p, &[])
+            .text("Tom & Jerry, 3 < 4 ")
+            .element(bold, &[], "bold")
+            .text(" &amp; done")
+            .close(p)
+            .empty(img, &[(0x3EC, &link("img1")), (0x3EB, "A \"picture\"")])
+            .open(p, &[])
+            .element(a, &[(0x01, &link("c2#next"))], "Next")
+            .close(p);
+    });
+    let chapter2 = chapter("Chapter Two", |b| {
+        b.element(h1, &[(0x83EB, "next")], "Chapter Two").element(
+            p,
+            &[(0x83EA, "last")],
+            "The end.",
+        );
+    });
+    build_lit(
+        &[
+            ("/manifest", manifest()),
+            ("/meta", opf()),
+            ("/data/c1/content", chapter1),
+            ("/data/c2/content", chapter2),
+            ("/data/css1", b"h1 { color: red }".to_vec()),
+            ("/data/img1", b"\x89PNG fake image".to_vec()),
+        ],
+        extra,
+    )
+}
+
 #[test]
 fn test_lit_input_conversion() {
     let tmp_dir = tempdir().unwrap();
     let input_path = tmp_dir.path().join("book.lit");
     let output_dir = tmp_dir.path().join("output");
+    fs::write(&input_path, sample_lit(&[])).unwrap();
 
-    // Create Mock LIT File
-    {
-        let file = File::create(&input_path).unwrap();
-        let mut writer = BufWriter::new(file);
-        let lit_writer = LitWriter::new();
-        lit_writer
-            .write_dummy(&mut writer)
-            .expect("Failed to write mock LIT");
-    }
-
-    // Convert
-    let input = LitInput::new();
-    let book = input
+    let book = LitInput::new()
         .convert(&input_path, &output_dir)
         .expect("Conversion failed");
 
-    // Verify
-    // The dummy conversion currently creates a generic title and content placeholder
-    assert!(book
-        .metadata
-        .get("title")
-        .first()
-        .unwrap()
-        .value
-        .contains("Converted LIT Book"));
-
-    // Check content file exists
-    let content_path = output_dir.join("content.html");
-    assert!(content_path.exists());
-    let extracted = std::fs::read_to_string(&content_path).unwrap();
-    assert!(extracted.contains("LIT Conversion")); // or whatever the placeholder says
+    assert_eq!(book.metadata.first("title"), Some("The LIT Test"));
+    assert_eq!(book.metadata.first("creator"), Some("Jane Doe"));
+    assert_eq!(book.metadata.first("language"), Some("en"));
+
+    // Shared leading directories are stripped from the item paths
+    let ch1 = book.manifest.get_by_id("ch1").unwrap();
+    assert_eq!(ch1.href, "text/chapter1.htm");
+    assert_eq!(ch1.media_type, "application/xhtml+xml");
+    assert_eq!(
+        book.manifest.get_by_id("css").unwrap().media_type,
+        "text/css"
+    );
+    // Items the OPF does not list are kept under their internal names
+    let image = book.manifest.get_by_id("img1").unwrap();
+    assert_eq!(image.href, "images/pic one.png");
+    assert_eq!(image.media_type, "image/png");
+
+    let spine: Vec<&str> = book.spine.items.iter().map(|i| i.idref.as_str()).collect();
+    assert_eq!(spine, ["ch1", "ch2"]);
+    assert_eq!(book.guide.get("toc").unwrap().href, "text/chapter1.htm");
+
+    let html = fs::read_to_string(output_dir.join("text/chapter1.htm")).unwrap();
+    assert!(html.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>"));
+    assert!(html
+        .contains("<html xmlns=\"http://www.w3.org/1999/xhtml\"><head><title>Chapter One</title>"));
+    assert!(
+        html.contains("<link rel=\"stylesheet\" type=\"text/css\" href=\"../styles/style.css\" />")
+    );
+    assert!(html.contains("<h1 id=\"start\">Chapter One</h1>"));
+    assert!(html.contains("<p>Tom &amp; Jerry, 3 &lt; 4 <b>bold</b> &amp; done</p>"));
+    assert!(html.contains("<img src=\"../images/pic one.png\" alt=\"A &quot;picture&quot;\" />"));
+    assert!(html.contains("<a href=\"chapter2.htm#next\">Next</a>"));
+    roxmltree::Document::parse(&html).expect("chapter should be well-formed XML");
+
+    let html = fs::read_to_string(output_dir.join("text/chapter2.htm")).unwrap();
+    assert!(html.contains("<p class=\"last\">The end.</p>"));
+
+    assert_eq!(
+        fs::read(output_dir.join("styles/style.css")).unwrap(),
+        b"h1 { color: red }"
+    );
+    assert_eq!(
+        fs::read(output_dir.join("images/pic one.png")).unwrap(),
+        b"\x89PNG fake image"
+    );
+    assert!(output_dir.join("content.opf").exists());
+}
+
+#[test]
+fn test_lit_metadata() {
+    let mi = calibre_ebooks::metadata::lit::get_metadata(Cursor::new(sample_lit(&[]))).unwrap();
+    assert_eq!(mi.title, "The LIT Test");
+    assert_eq!(mi.authors, ["Jane Doe"]);
+    assert_eq!(mi.author_sort_map.get("Jane Doe").unwrap(), "Doe, Jane");
+}
+
+#[test]
+fn test_lit_input_rejects_drm_and_invalid_files() {
+    let tmp_dir = tempdir().unwrap();
+    let output_dir = tmp_dir.path().join("output");
+
+    let drm_path = tmp_dir.path().join("drm.lit");
+    fs::write(&drm_path, sample_lit(&["/DRMStorage/DRMSealed"])).unwrap();
+    let err = LitInput::new()
+        .convert(&drm_path, &output_dir)
+        .err()
+        .expect("DRM-protected LIT must be rejected");
+    assert!(format!("{:#}", err).contains("DRM"));
+
+    // A bare header without a directory or content is not a book
+    let dummy_path = tmp_dir.path().join("dummy.lit");
+    {
+        let mut writer = BufWriter::new(File::create(&dummy_path).unwrap());
+        LitWriter::new().write_dummy(&mut writer).unwrap();
+    }
+    assert!(LitInput::new().convert(&dummy_path, &output_dir).is_err());
 }
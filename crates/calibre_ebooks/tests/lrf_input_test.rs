@@ -1,27 +1,301 @@
 use calibre_ebooks::input::lrf_input::LRFInput;
+use calibre_ebooks::metadata::lrf::get_metadata;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
 use std::fs;
+use std::io::{Cursor, Write};
 use tempfile::tempdir;
 
+const XOR_KEY: u16 = 0x30;
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\nnot really an image";
+
+fn tag(id: u8, payload: &[u8]) -> Vec<u8> {
+    let mut out = vec![id, 0xF5];
+    out.extend_from_slice(payload);
+    out
+}
+
+fn word(id: u8, v: u16) -> Vec<u8> {
+    tag(id, &v.to_le_bytes())
+}
+
+fn dword(id: u8, v: u32) -> Vec<u8> {
+    tag(id, &v.to_le_bytes())
+}
+
+fn utf16(s: &str) -> Vec<u8> {
+    s.encode_utf16().flat_map(|u| u.to_le_bytes()).collect()
+}
+
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut enc = ZlibEncoder::new(Vec::new(), Compression::default());
+    enc.write_all(data).unwrap();
+    enc.finish().unwrap()
+}
+
+/// Stream tags for `data`, compressed and/or scrambled according to `flags`.
+fn stream(flags: u16, data: &[u8]) -> Vec<u8> {
+    let mut raw = data.to_vec();
+    if flags & 0x100 != 0 {
+        let mut packed = (raw.len() as u32).to_le_bytes().to_vec();
+        packed.extend(zlib(&raw));
+        raw = packed;
+    }
+    if flags & 0x200 != 0 {
+        let key = (raw.len() % (XOR_KEY as usize & 0xFF) + 0xF) as u8;
+        raw.iter_mut().for_each(|b| *b ^= key);
+    }
+    let mut out = word(0x54, flags);
+    out.extend(dword(0x04, raw.len() as u32));
+    out.extend(tag(0x05, &raw));
+    out.extend(tag(0x06, &[]));
+    out
+}
+
+fn object(id: u32, kind: u16, tags: &[Vec<u8>]) -> Vec<u8> {
+    let mut start = id.to_le_bytes().to_vec();
+    start.extend_from_slice(&kind.to_le_bytes());
+    let mut out = tag(0x00, &start);
+    out.extend(tags.concat());
+    out.extend(tag(0x01, &[]));
+    out
+}
+
+fn text_stream(parts: &[Vec<u8>]) -> Vec<u8> {
+    parts.concat()
+}
+
+fn build_lrf() -> Vec<u8> {
+    let chapter_one = text_stream(&[
+        tag(0xA1, &[0; 4]),
+        utf16("Chapter One "),
+        word(0x15, 400),
+        utf16("plain"),
+        tag(0xA2, &[]),
+        tag(0xD2, &[]),
+        tag(0xA1, &[0; 4]),
+        utf16("Tom &amp; Jerry <tag> "),
+        tag(0x81, &[]),
+        utf16("it"),
+        tag(0x82, &[]),
+        dword(0xA7, 40),
+        utf16("link"),
+        tag(0xA8, &[]),
+        tag(
+            0xD1,
+            &[
+                720u16.to_le_bytes().as_slice(),
+                &360u16.to_le_bytes(),
+                &31u32.to_le_bytes(),
+                &1u32.to_le_bytes(),
+            ]
+            .concat(),
+        ),
+        tag(0xA2, &[]),
+    ]);
+    let chapter_two = text_stream(&[tag(0xA1, &[0; 4]), utf16("Chapter Two"), tag(0xA2, &[])]);
+
+    let mut toc = Vec::new();
+    let labels = [(50u32, 21u32, "Chapter One"), (51, 52, "Chapter Two")];
+    toc.extend((labels.len() as u32).to_le_bytes());
+    toc.extend(0u32.to_le_bytes());
+    toc.extend((10 + 2 * "Chapter One".len() as u32).to_le_bytes());
+    for (page, obj, label) in labels {
+        toc.extend(page.to_le_bytes());
+        toc.extend(obj.to_le_bytes());
+        toc.extend((2 * label.len() as u16).to_le_bytes());
+        toc.extend(utf16(label));
+    }
+
+    let page_one = [dword(0x03, 21), tag(0x73, &[0; 10]), dword(0x03, 32)].concat();
+
+    let objects = vec![
+        object(10, 0x0B, &[word(0x11, 100), word(0x15, 700), word(0x3C, 4)]),
+        object(11, 0x07, &[word(0x39, 166), word(0x38, 83)]),
+        object(20, 0x0A, &[dword(0x03, 10), stream(0, &chapter_one)]),
+        object(21, 0x06, &[dword(0x03, 11), stream(0, &dword(0x03, 20))]),
+        object(30, 0x11, &[stream(0x112, PNG)]),
+        object(
+            31,
+            0x0C,
+            &[
+                tag(0x4A, &[0, 0, 0, 0, 100, 0, 50, 0]),
+                tag(0x4B, &[100, 0, 50, 0]),
+                dword(0x4C, 30),
+            ],
+        ),
+        object(32, 0x06, &[stream(0, &dword(0x03, 31))]),
+        object(
+            40,
+            0x13,
+            &[
+                word(0x61, 0x10),
+                tag(0x66, &[]),
+                tag(0x6A, &[]),
+                tag(0x6C, &[51u32.to_le_bytes(), 52u32.to_le_bytes()].concat()),
+                tag(0x6B, &[]),
+                tag(0x67, &[]),
+            ],
+        ),
+        object(50, 0x02, &[dword(0x7C, 60), stream(0, &page_one)]),
+        object(51, 0x02, &[dword(0x7C, 60), stream(0, &dword(0x03, 52))]),
+        object(52, 0x06, &[dword(0x03, 11), stream(0, &dword(0x03, 53))]),
+        object(53, 0x0A, &[dword(0x03, 10), stream(0x200, &chapter_two)]),
+        object(60, 0x01, &[tag(0x5C, &[2, 0, 50, 0, 0, 0, 51, 0, 0, 0])]),
+        object(70, 0x1E, &[stream(0, &toc)]),
+    ];
+    let ids = [10u32, 11, 20, 21, 30, 31, 32, 40, 50, 51, 52, 53, 60, 70];
+
+    let info = "\u{feff}<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+        <Info version=\"1.1\"><BookInfo>\
+        <Title reading=\"Sample, The\">The Sample</Title>\
+        <Author reading=\"Writer, Ann\">Ann Writer</Author>\
+        <Publisher>Sony Press</Publisher>\
+        <Category>Fiction</Category><Classification>Classics</Classification>\
+        <FreeText>A tiny book.</FreeText></BookInfo>\
+        <DocInfo><Language>en</Language><Producer>test</Producer></DocInfo></Info>";
+    let compressed_info = zlib(info.as_bytes());
+    let thumbnail = b"\x89PNGthumb";
+
+    let mut lrf = b"L\x00R\x00F\x00\x00\x00".to_vec();
+    lrf.extend(999u16.to_le_bytes());
+    lrf.extend(XOR_KEY.to_le_bytes());
+    lrf.extend(0u32.to_le_bytes()); // root object
+    lrf.extend((objects.len() as u64).to_le_bytes());
+    lrf.extend(0u64.to_le_bytes()); // object table offset, patched below
+    lrf.resize(0x24, 0);
+    lrf.extend([1, 0]); // binding
+    lrf.extend(1660u16.to_le_bytes());
+    lrf.extend([0, 0]);
+    lrf.extend(600u16.to_le_bytes());
+    lrf.extend(800u16.to_le_bytes());
+    lrf.extend([24, 0]);
+    lrf.resize(0x44, 0);
+    lrf.extend(70u32.to_le_bytes());
+    lrf.extend(0u32.to_le_bytes());
+    lrf.extend((compressed_info.len() as u16 + 4).to_le_bytes());
+    lrf.extend(0x12u16.to_le_bytes());
+    lrf.extend((thumbnail.len() as u32).to_le_bytes());
+    lrf.extend((info.len() as u32).to_le_bytes());
+    lrf.extend(&compressed_info);
+    lrf.extend(thumbnail);
+
+    let mut table = Vec::new();
+    for (id, obj) in ids.iter().zip(&objects) {
+        table.extend(id.to_le_bytes());
+        table.extend((lrf.len() as u32).to_le_bytes());
+        table.extend((obj.len() as u32).to_le_bytes());
+        table.extend(0u32.to_le_bytes());
+        lrf.extend(obj);
+    }
+    let table_offset = lrf.len() as u64;
+    lrf[0x18..0x20].copy_from_slice(&table_offset.to_le_bytes());
+    lrf.extend(table);
+    lrf
+}
+
 #[test]
-fn test_lrf_input_conversion_stub() {
+fn test_lrf_input_conversion() {
     let tmp_dir = tempdir().unwrap();
     let input_path = tmp_dir.path().join("test.lrf");
     let output_dir = tmp_dir.path().join("output");
+    fs::write(&input_path, build_lrf()).unwrap();
 
-    // Write a dummy LRF file
-    fs::write(&input_path, b"DUMMY LRF CONTENT").unwrap();
-
-    let input = LRFInput::new();
-    let book = input
+    let book = LRFInput::new()
         .convert(&input_path, &output_dir)
         .expect("LRF conversion failed");
 
-    // Check Manifest
-    assert!(!book.manifest.items.is_empty());
+    // One XHTML file per page, in page tree order
+    let spine: Vec<&str> = book.spine.items.iter().map(|i| i.idref.as_str()).collect();
+    assert_eq!(spine, ["page50", "page51"]);
+    assert_eq!(book.manifest.get_by_id("page50").unwrap().href, "50.xhtml");
+    assert_eq!(
+        book.manifest.get_by_href("30.png").unwrap().media_type,
+        "image/png"
+    );
+    assert!(book.manifest.get_by_href("styles.css").is_some());
+
+    let page = fs::read_to_string(output_dir.join("50.xhtml")).unwrap();
+    roxmltree::Document::parse(&page).expect("page is well formed");
+    assert!(page.contains("<title>The Sample</title>"));
+    assert!(page.contains(
+        "<div class=\"bs0 ts0\" id=\"21\"><p>Chapter One <span class=\"ts1\">plain</span></p>"
+    ));
+    assert!(page.contains("Tom &amp; Jerry &lt;tag&gt; <i>it</i>"));
+    assert!(page.contains("<a href=\"51.xhtml#52\">link</a>"));
+    assert!(page.contains("<img src=\"30.png\" width=\"166\" height=\"83\" alt=\"\"/>"));
+    assert!(page.contains("<hr/>"));
+    assert!(page.contains(
+        "<div id=\"32\" class=\"image_page\"><img src=\"30.png\" width=\"100\" height=\"50\" alt=\"\"/></div>"
+    ));
+
+    // Scrambled streams are descrambled
+    let page = fs::read_to_string(output_dir.join("51.xhtml")).unwrap();
+    assert!(page.contains("<p>Chapter Two</p>"));
+
+    // Compressed image streams are extracted
+    assert_eq!(fs::read(output_dir.join("30.png")).unwrap(), PNG);
+
+    let css = fs::read_to_string(output_dir.join("styles.css")).unwrap();
+    assert!(
+        css.contains(".ts0 {\n\tfont-size: 10pt;\n\tfont-weight: bold;\n\ttext-align: center;\n}")
+    );
+    assert!(css.contains(".ts1 {\n\tfont-weight: normal;\n}"));
+    assert!(css.contains("margin-left: 72pt;"));
+    assert!(css.contains("margin-top: 36pt;"));
+
+    let toc = &book.toc.root.children;
+    assert_eq!(toc.len(), 2);
+    assert_eq!(toc[0].title.as_deref(), Some("Chapter One"));
+    assert_eq!(toc[0].href.as_deref(), Some("50.xhtml#21"));
+    assert_eq!(toc[1].href.as_deref(), Some("51.xhtml#52"));
+
+    assert_eq!(book.metadata.first("title"), Some("The Sample"));
+    let creator = book.metadata.get("creator");
+    assert_eq!(creator.len(), 1);
+    assert_eq!(creator[0].value, "Ann Writer");
+    assert_eq!(
+        creator[0].get_attribute("opf:file-as").map(|s| s.as_str()),
+        Some("Writer, Ann")
+    );
+    let subjects: Vec<&str> = book
+        .metadata
+        .get("subject")
+        .iter()
+        .map(|i| i.value.as_str())
+        .collect();
+    assert_eq!(subjects, ["Fiction", "Classics"]);
+    assert_eq!(book.metadata.first("language"), Some("en"));
+}
+
+#[test]
+fn test_lrf_metadata() {
+    let mi = get_metadata(Cursor::new(build_lrf())).unwrap();
+    assert_eq!(mi.title, "The Sample");
+    assert_eq!(mi.authors, vec!["Ann Writer"]);
+    assert_eq!(mi.title_sort.as_deref(), Some("Sample, The"));
+    assert_eq!(mi.author_sort.as_deref(), Some("Writer, Ann"));
+    assert_eq!(mi.publisher.as_deref(), Some("Sony Press"));
+    assert_eq!(mi.tags, vec!["Fiction", "Classics"]);
+    assert_eq!(mi.comments.as_deref(), Some("A tiny book."));
+    assert_eq!(mi.cover_data.0.as_deref(), Some("png"));
+    assert_eq!(mi.cover_data.1, PNG);
+}
+
+#[test]
+fn test_lrf_input_rejects_invalid_files() {
+    let tmp_dir = tempdir().unwrap();
+    let input_path = tmp_dir.path().join("test.lrf");
+    fs::write(&input_path, b"DUMMY LRF CONTENT").unwrap();
+    assert!(LRFInput::new()
+        .convert(&input_path, &tmp_dir.path().join("output"))
+        .is_err());
 
-    // Check Content File
-    let content_path = output_dir.join("index.html");
-    assert!(content_path.exists());
-    let content = fs::read_to_string(content_path).unwrap();
-    assert!(content.contains("LRF Content Not Supported Yet"));
+    // A truncated object table is an error rather than a panic
+    let mut lrf = build_lrf();
+    lrf.truncate(lrf.len() - 8);
+    fs::write(&input_path, lrf).unwrap();
+    assert!(LRFInput::new()
+        .convert(&input_path, &tmp_dir.path().join("output"))
+        .is_err());
 }
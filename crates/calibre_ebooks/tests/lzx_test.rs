@@ -0,0 +1,142 @@
+use calibre_ebooks::compression::lzx::LzxDecoder;
+
+/// Packs bits MSB-first into little-endian 16-bit words, as LZX expects.
+struct BitWriter {
+    out: Vec<u8>,
+    word: u32,
+    used: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            out: Vec::new(),
+            word: 0,
+            used: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, bits: u32) {
+        for i in (0..bits).rev() {
+            self.word = (self.word << 1) | ((value >> i) & 1);
+            self.used += 1;
+            if self.used == 16 {
+                self.out
+                    .extend_from_slice(&(self.word as u16).to_le_bytes());
+                self.word = 0;
+                self.used = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.used > 0 {
+            self.write(0, 16 - self.used);
+        }
+        self.out
+    }
+}
+
+/// Canonical Huffman codes for a table of code lengths.
+fn canonical_codes(lens: &[u8]) -> Vec<(u32, u32)> {
+    let mut codes = vec![(0, 0); lens.len()];
+    let mut code = 0u32;
+    for len in 1..=16u8 {
+        for (sym, _) in lens.iter().enumerate().filter(|(_, l)| **l == len) {
+            codes[sym] = (code, len as u32);
+            code += 1;
+        }
+        code <<= 1;
+    }
+    codes
+}
+
+/// Write a pretree followed by `lens`, each delta-coded against zero.
+fn write_lens(w: &mut BitWriter, lens: &[u8]) {
+    let pre_lens: Vec<u8> = (0..20).map(|i| if i < 12 { 4 } else { 5 }).collect();
+    for &len in &pre_lens {
+        w.write(len as u32, 4);
+    }
+    let pre_codes = canonical_codes(&pre_lens);
+    for &len in lens {
+        let (code, bits) = pre_codes[((17 - len as u32) % 17) as usize];
+        w.write(code, bits);
+    }
+}
+
+#[test]
+fn test_lzx_uncompressed_block() {
+    let data = b"Uncompressed LZX block with an odd length!";
+    let mut w = BitWriter::new();
+    w.write(0, 1); // no intel translation
+    w.write(3, 3); // uncompressed block
+    w.write((data.len() >> 8) as u32, 16);
+    w.write((data.len() & 0xff) as u32, 8);
+    let mut input = w.finish();
+    input.extend_from_slice(&[1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0]);
+    input.extend_from_slice(data);
+
+    let mut lzx = LzxDecoder::new(15).unwrap();
+    let out = lzx.decompress(&input, data.len()).unwrap();
+    assert_eq!(out, data);
+
+    // The decoder starts afresh on every call
+    let out = lzx.decompress(&input, data.len()).unwrap();
+    assert_eq!(out, data);
+}
+
+#[test]
+fn test_lzx_verbatim_block() {
+    // Complete codes: 480 symbols of 9 bits and 16 of 8 bits for the main
+    // tree (256 literals + 30 position slots * 8), and 242 of 8 bits and
+    // 7 of 7 bits for the 249 lengths.
+    let main_lens: Vec<u8> = (0..496).map(|i| if i < 480 { 9 } else { 8 }).collect();
+    let length_lens: Vec<u8> = (0..249).map(|i| if i < 242 { 8 } else { 7 }).collect();
+    let main_codes = canonical_codes(&main_lens);
+    let length_codes = canonical_codes(&length_lens);
+
+    let expected = b"abcabcabcabcabc";
+    let mut w = BitWriter::new();
+    w.write(0, 1);
+    w.write(1, 3); // verbatim block
+    w.write(0, 16);
+    w.write(expected.len() as u32, 8);
+    write_lens(&mut w, &main_lens[..256]);
+    write_lens(&mut w, &main_lens[256..]);
+    write_lens(&mut w, &length_lens);
+
+    for &b in b"abc" {
+        let (code, bits) = main_codes[b as usize];
+        w.write(code, bits);
+    }
+    // Offset 3 is position slot 4 with one verbatim bit set; length 9 is
+    // length header 7 plus length symbol 0
+    let (code, bits) = main_codes[256 + (4 << 3 | 7)];
+    w.write(code, bits);
+    let (code, bits) = length_codes[0];
+    w.write(code, bits);
+    w.write(1, 1);
+    // Repeat the last offset for three more bytes
+    let (code, bits) = main_codes[256 + 1];
+    w.write(code, bits);
+    let input = w.finish();
+
+    let mut lzx = LzxDecoder::new(15).unwrap();
+    let out = lzx.decompress(&input, expected.len()).unwrap();
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn test_lzx_rejects_bad_input() {
+    assert!(LzxDecoder::new(14).is_err());
+    assert!(LzxDecoder::new(22).is_err());
+
+    // Block type 7 does not exist
+    let mut w = BitWriter::new();
+    w.write(0, 1);
+    w.write(7, 3);
+    w.write(0, 24);
+    let input = w.finish();
+    let mut lzx = LzxDecoder::new(16).unwrap();
+    assert!(lzx.decompress(&input, 10).is_err());
+}
@@ -0,0 +1,61 @@
+use calibre_ebooks::metadata::{metadata_from_formats, MetaInformation};
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+fn test_smart_update() {
+    let mut mi = MetaInformation::new("Old Title", vec!["Known Author".to_string()]);
+    mi.tags = vec!["Fiction".to_string()];
+    mi.publisher = Some("First".to_string());
+    mi.set_identifier("isbn", "123");
+
+    let mut other = MetaInformation::new("Unknown", vec!["Unknown".to_string()]);
+    other.tags = vec!["fiction".to_string(), "Mystery".to_string()];
+    other.series = Some("Cases".to_string());
+    other.series_index = 4.0;
+    other.set_identifier("asin", "B00");
+    mi.smart_update(&other);
+
+    // Unknown title and authors do not override known ones
+    assert_eq!(mi.title, "Old Title");
+    assert_eq!(mi.authors, vec!["Known Author"]);
+    assert_eq!(mi.publisher.as_deref(), Some("First"));
+    assert_eq!(mi.tags, vec!["fiction", "Mystery"]);
+    assert_eq!(mi.series.as_deref(), Some("Cases"));
+    assert_eq!(mi.series_index, 4.0);
+    assert_eq!(mi.identifiers.len(), 2);
+
+    let mut unknown = MetaInformation::default();
+    unknown.smart_update(&MetaInformation::new("New", vec!["Someone".to_string()]));
+    assert_eq!(unknown.title, "New");
+    assert_eq!(unknown.authors, vec!["Someone"]);
+}
+
+#[test]
+fn test_metadata_from_formats() {
+    let dir = tempdir().unwrap();
+    let pdf = dir.path().join("Some Book.pdf");
+    fs::write(&pdf, "not really a pdf").unwrap();
+    let opf = dir.path().join("metadata.opf");
+
+    // Unreadable formats fall back to the file name
+    let mi = metadata_from_formats(&[&pdf]);
+    assert_eq!(mi.title, "Some Book");
+    assert_eq!(mi.authors, vec!["Unknown"]);
+
+    // An OPF is used as is
+    fs::write(
+        &opf,
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title>From The OPF</dc:title>
+    <dc:creator>Opf Author</dc:creator>
+  </metadata>
+</package>"#,
+    )
+    .unwrap();
+    let mi = metadata_from_formats(&[&pdf, &opf]);
+    assert_eq!(mi.title, "From The OPF");
+    assert_eq!(mi.authors, vec!["Opf Author"]);
+}
@@ -0,0 +1,247 @@
+use calibre_ebooks::input::mobi_input::MOBIInput;
+use calibre_ebooks::mobi::mobi6::MobiReader;
+use calibre_ebooks::mobi::mobi8::Mobi8Reader;
+use calibre_ebooks::mobi::utils::{read_font_record, write_font_record};
+use calibre_ebooks::mobi::writer::MobiFileType;
+use calibre_ebooks::oeb::book::OEBBook;
+use calibre_ebooks::oeb::container::DirContainer;
+use calibre_ebooks::oeb::toc::TOCNode;
+use calibre_ebooks::output::azw3_output::AZW3Output;
+use calibre_ebooks::output::mobi_output::MOBIOutput;
+use std::fs::{self, File};
+use std::path::Path;
+use tempfile::tempdir;
+
+/// Bytes that do not compress, so the font record gets obfuscated.
+fn font_data() -> Vec<u8> {
+    let mut seed: u32 = 0x1234_5678;
+    let mut data = vec![0, 1, 0, 0];
+    for _ in 0..4000 {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        data.push((seed >> 16) as u8);
+    }
+    data
+}
+
+fn build_book(dir: &Path) -> OEBBook {
+    fs::write(
+        dir.join("ch1.xhtml"),
+        "<html xmlns=\"http://www.w3.org/1999/xhtml\"><head><title>One</title>\
+         <link rel=\"stylesheet\" href=\"style.css\"/></head><body>\
+         <h1 id=\"start\">Chapter One</h1><p>First <a href=\"ch2.xhtml#sec\">link</a></p>\
+         <p><img src=\"images/cover.png\" alt=\"\"/></p></body></html>",
+    )
+    .unwrap();
+    let long: String = (0..400)
+        .map(|i| format!("<p>Paragraph {} with some text in it.</p>", i))
+        .collect();
+    fs::write(
+        dir.join("ch2.xhtml"),
+        format!(
+            "<html xmlns=\"http://www.w3.org/1999/xhtml\"><head><title>Two</title>\
+             <link rel=\"stylesheet\" href=\"style.css\"/></head><body><h1>Chapter Two</h1>\
+             <div id=\"sec\">{}</div><p><a href=\"ch1.xhtml\">Back</a></p></body></html>",
+            long
+        ),
+    )
+    .unwrap();
+    fs::write(
+        dir.join("style.css"),
+        "@font-face { font-family: Test; src: url(fonts/test.ttf) }\
+         h1 { text-align: center; font-family: Test }",
+    )
+    .unwrap();
+    fs::create_dir_all(dir.join("fonts")).unwrap();
+    fs::write(dir.join("fonts/test.ttf"), font_data()).unwrap();
+    fs::create_dir_all(dir.join("images")).unwrap();
+    let img = image::RgbImage::from_pixel(300, 400, image::Rgb([200, 30, 30]));
+    img.save(dir.join("images/cover.png")).unwrap();
+
+    let container = Box::new(DirContainer::new(dir));
+    let mut book = OEBBook::new(container);
+    book.manifest
+        .add("ch1", "ch1.xhtml", "application/xhtml+xml");
+    book.manifest
+        .add("ch2", "ch2.xhtml", "application/xhtml+xml");
+    book.manifest.add("css", "style.css", "text/css");
+    book.manifest
+        .add("font", "fonts/test.ttf", "application/x-font-truetype");
+    book.manifest.add("cover", "images/cover.png", "image/png");
+    book.spine.add("ch1", true);
+    book.spine.add("ch2", true);
+
+    book.metadata.add("title", "Kindle Book");
+    book.metadata.add("creator", "Jane Doe");
+    book.metadata.add("language", "en");
+    book.metadata.add("cover", "cover");
+
+    let mut ch1 = TOCNode::new(Some("Chapter One".into()), Some("ch1.xhtml".into()));
+    ch1.add(TOCNode::new(
+        Some("Section".into()),
+        Some("ch2.xhtml#sec".into()),
+    ));
+    book.toc.root.add(ch1);
+    book.toc.root.add(TOCNode::new(
+        Some("Chapter Two".into()),
+        Some("ch2.xhtml".into()),
+    ));
+    book.guide
+        .add("text", Some("Start".into()), "ch1.xhtml#start");
+    book
+}
+
+fn read_part(dir: &Path, book: &OEBBook, href: &str) -> String {
+    let path = href.split('#').next().unwrap();
+    assert!(book.manifest.get_by_href(path).is_some());
+    fs::read_to_string(dir.join(path)).unwrap()
+}
+
+/// Assert that `href` points into `file` at an element that exists.
+fn assert_target(dir: &Path, href: &str, file: &str) {
+    let (path, id) = href.split_once('#').unwrap();
+    assert_eq!(path, file);
+    let html = fs::read_to_string(dir.join(path)).unwrap();
+    assert!(
+        html.contains(&format!("id=\"{}\"", id)),
+        "{} not found",
+        href
+    );
+}
+
+fn check_round_trip(path: &Path) {
+    let out = tempdir().unwrap();
+    let reader = MobiReader::new(File::open(path).unwrap()).unwrap();
+    let book = Mobi8Reader::new(&reader)
+        .unwrap()
+        .extract(out.path())
+        .unwrap();
+
+    // The two spine files come back as two parts
+    assert_eq!(book.spine.items.len(), 2);
+    let part0 = read_part(out.path(), &book, "part0000.html");
+    let part1 = read_part(out.path(), &book, "part0001.html");
+    assert!(part0.contains("Chapter One"));
+    assert!(part1.contains("Paragraph 0 with"));
+    assert!(part1.contains("Paragraph 399 with"));
+    assert!(!part0.contains("kindle:"));
+    assert!(!part1.contains("kindle:"));
+    assert!(!part1.contains(" aid="));
+
+    // Links and stylesheets point at the extracted files. Elements that are
+    // only in the skeleton are found through their first child
+    let link = part0.split("<a href=\"").nth(1).unwrap();
+    let link = &link[..link.find('"').unwrap()];
+    assert_target(out.path(), link, "part0001.html");
+    assert!(part1.contains("href=\"part0000.html"));
+    let css_href = book
+        .manifest
+        .items
+        .values()
+        .find(|i| i.media_type == "text/css")
+        .unwrap()
+        .href
+        .clone();
+    assert!(part0.contains(&format!("href=\"{}\"", css_href)));
+    let css = fs::read_to_string(out.path().join(&css_href)).unwrap();
+    assert!(css.contains("text-align: center"));
+
+    // The cover image
+    let cover_id = book.metadata.first("cover").unwrap();
+    let cover = book.manifest.get_by_id(cover_id).unwrap();
+    assert!(cover.media_type.starts_with("image/"));
+    assert!(part0.contains(&format!("src=\"{}\"", cover.href)));
+    assert!(out.path().join(&cover.href).exists());
+
+    // TOC and guide
+    let labels: Vec<&str> = book
+        .toc
+        .root
+        .children
+        .iter()
+        .map(|n| n.title.as_deref().unwrap())
+        .collect();
+    assert_eq!(labels, ["Chapter One", "Chapter Two"]);
+    let section = &book.toc.root.children[0].children[0];
+    assert_eq!(section.title.as_deref(), Some("Section"));
+    assert_target(
+        out.path(),
+        section.href.as_deref().unwrap(),
+        "part0001.html",
+    );
+    let text = book.guide.get("text").unwrap();
+    assert_eq!(text.href, "part0000.html#start");
+    assert_eq!(text.title.as_deref(), Some("Start"));
+
+    assert_eq!(book.metadata.first("title"), Some("Kindle Book"));
+    assert_eq!(book.metadata.first("creator"), Some("Jane Doe"));
+    assert_eq!(book.metadata.first("language"), Some("en"));
+}
+
+#[test]
+fn test_read_azw3() {
+    let src = tempdir().unwrap();
+    let book = build_book(src.path());
+    let out = tempdir().unwrap();
+    let path = out.path().join("book.azw3");
+    AZW3Output::new().convert(&book, &path).unwrap();
+    check_round_trip(&path);
+
+    // Fonts are deobfuscated and inflated
+    let reader = MobiReader::new(File::open(&path).unwrap()).unwrap();
+    let extracted = tempdir().unwrap();
+    let book = Mobi8Reader::new(&reader)
+        .unwrap()
+        .extract(extracted.path())
+        .unwrap();
+    let font = book
+        .manifest
+        .items
+        .values()
+        .find(|i| i.href.starts_with("fonts/"))
+        .unwrap();
+    assert_eq!(
+        fs::read(extracted.path().join(&font.href)).unwrap(),
+        font_data()
+    );
+}
+
+#[test]
+fn test_read_joint_mobi() {
+    let src = tempdir().unwrap();
+    let book = build_book(src.path());
+    let out = tempdir().unwrap();
+    let path = out.path().join("book.mobi");
+    MOBIOutput::with_file_type(MobiFileType::Both)
+        .convert(&book, &path)
+        .unwrap();
+    check_round_trip(&path);
+}
+
+#[test]
+fn test_mobi_input_uses_kf8() {
+    let src = tempdir().unwrap();
+    let book = build_book(src.path());
+    let out = tempdir().unwrap();
+    let path = out.path().join("book.azw3");
+    AZW3Output::new().convert(&book, &path).unwrap();
+
+    let extracted = tempdir().unwrap();
+    let book = MOBIInput::new().convert(&path, extracted.path()).unwrap();
+    assert!(book.manifest.get_by_href("part0001.html").is_some());
+    assert_eq!(book.toc.root.children.len(), 2);
+}
+
+#[test]
+fn test_font_record_round_trip() {
+    let data = font_data();
+    for (obfuscate, compress) in [(false, false), (true, false), (true, true)] {
+        let record = write_font_record(&data, obfuscate, compress).unwrap();
+        assert_eq!(read_font_record(&record).unwrap(), data);
+    }
+    assert!(read_font_record(b"JUNKJUNK").is_err());
+
+    // A corrupt size is refused without allocating for it
+    let mut record = write_font_record(&data, false, true).unwrap();
+    record[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+    assert!(read_font_record(&record).is_err());
+}
@@ -0,0 +1,192 @@
+use calibre_ebooks::mobi::mobi6::MobiReader;
+use calibre_ebooks::mobi::mobi8::Mobi8Reader;
+use calibre_ebooks::mobi::tweak::{explode, rebuild};
+use calibre_ebooks::mobi::writer::MobiFileType;
+use calibre_ebooks::oeb::book::OEBBook;
+use calibre_ebooks::oeb::container::DirContainer;
+use calibre_ebooks::oeb::toc::TOCNode;
+use calibre_ebooks::output::mobi_output::MOBIOutput;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::Path;
+use tempfile::tempdir;
+
+fn build_book(dir: &Path) -> OEBBook {
+    fs::write(
+        dir.join("ch1.xhtml"),
+        "<html xmlns=\"http://www.w3.org/1999/xhtml\"><head><title>One</title></head><body>\
+         <h1 id=\"start\">Chapter One</h1><p>A smal typo. <a href=\"ch2.xhtml#end\">Go</a></p>\
+         <p><img src=\"images/pic.png\" alt=\"\"/></p></body></html>",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("ch2.xhtml"),
+        "<html xmlns=\"http://www.w3.org/1999/xhtml\"><head><title>Two</title></head><body>\
+         <h1>Chapter Two</h1><p>Middle</p><p id=\"end\">The end</p></body></html>",
+    )
+    .unwrap();
+    fs::create_dir_all(dir.join("images")).unwrap();
+    let img = image::RgbImage::from_pixel(60, 80, image::Rgb([20, 120, 30]));
+    img.save(dir.join("images/pic.png")).unwrap();
+
+    let mut book = OEBBook::new(Box::new(DirContainer::new(dir)));
+    book.manifest
+        .add("ch1", "ch1.xhtml", "application/xhtml+xml");
+    book.manifest
+        .add("ch2", "ch2.xhtml", "application/xhtml+xml");
+    book.manifest.add("pic", "images/pic.png", "image/png");
+    book.spine.add("ch1", true);
+    book.spine.add("ch2", true);
+    book.metadata.add("title", "Tweaked");
+    book.metadata.add("creator", "Jane Doe");
+    book.metadata.add("language", "en");
+    book.metadata.add("cdetype", "PDOC");
+    let mut attrib = HashMap::new();
+    attrib.insert("opf:scheme".to_string(), "MOBI-ASIN".to_string());
+    book.metadata
+        .add_with_attrib("identifier", "B00TWEAK42", attrib);
+    book.toc.root.add(TOCNode::new(
+        Some("Chapter One".into()),
+        Some("ch1.xhtml".into()),
+    ));
+    book.toc.root.add(TOCNode::new(
+        Some("Chapter Two".into()),
+        Some("ch2.xhtml".into()),
+    ));
+    book.guide
+        .add("text", Some("Start".into()), "ch1.xhtml#start");
+    book
+}
+
+fn exth_record(rec0: &[u8], code: u32) -> Option<Vec<u8>> {
+    let u32_at = |o: usize| u32::from_be_bytes(rec0[o..o + 4].try_into().unwrap());
+    let exth = 16 + u32_at(20) as usize;
+    let count = u32_at(exth + 8);
+    let mut pos = exth + 12;
+    for _ in 0..count {
+        let (id, len) = (u32_at(pos), u32_at(pos + 4) as usize);
+        if id == code {
+            return Some(rec0[pos + 8..pos + len].to_vec());
+        }
+        pos += len;
+    }
+    None
+}
+
+/// Write the book as `file_type`, explode it, fix the typo and rebuild it.
+fn tweak_round_trip(file_type: MobiFileType) -> (MobiReader, tempfile::TempDir) {
+    let src = tempdir().unwrap();
+    let book = build_book(src.path());
+    let work = tempdir().unwrap();
+    let original = work.path().join("book.mobi");
+    MOBIOutput::with_file_type(file_type)
+        .convert(&book, &original)
+        .unwrap();
+
+    let exploded = work.path().join("exploded");
+    let opf = explode(&original, &exploded).unwrap();
+    let opf_text = fs::read_to_string(&opf).unwrap();
+    assert!(opf_text.contains("<dc:title>Tweaked</dc:title>"));
+    assert!(opf_text.contains("B00TWEAK42"));
+    assert!(opf_text.contains("calibre:mobi-flavour"));
+
+    let mut fixed = false;
+    for entry in fs::read_dir(&exploded).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|e| e == "html") {
+            let html = fs::read_to_string(&path).unwrap();
+            if html.contains("smal typo") {
+                fs::write(&path, html.replace("smal typo", "small fix")).unwrap();
+                fixed = true;
+            }
+        }
+    }
+    assert!(fixed);
+
+    let rebuilt = work.path().join("rebuilt.mobi");
+    rebuild(&exploded, &rebuilt).unwrap();
+    let reader = MobiReader::new(File::open(&rebuilt).unwrap()).unwrap();
+    (reader, work)
+}
+
+fn assert_exth_kept(rec0: &[u8]) {
+    assert_eq!(exth_record(rec0, 113).unwrap(), b"B00TWEAK42");
+    assert_eq!(exth_record(rec0, 501).unwrap(), b"PDOC");
+    assert_eq!(exth_record(rec0, 100).unwrap(), b"Jane Doe");
+}
+
+fn check_kf8(reader: &MobiReader) {
+    let out = tempdir().unwrap();
+    let book = Mobi8Reader::new(reader)
+        .unwrap()
+        .extract(out.path())
+        .unwrap();
+    let part = fs::read_to_string(out.path().join("part0000.html")).unwrap();
+    assert!(part.contains("small fix"));
+    assert!(!part.contains("smal typo"));
+    let labels: Vec<_> = book
+        .toc
+        .root
+        .children
+        .iter()
+        .map(|n| n.title.clone().unwrap())
+        .collect();
+    assert_eq!(labels, ["Chapter One", "Chapter Two"]);
+    assert!(book.guide.get("text").is_some());
+    assert_eq!(book.metadata.first("title"), Some("Tweaked"));
+}
+
+#[test]
+fn test_tweak_kf8() {
+    let (reader, _work) = tweak_round_trip(MobiFileType::New);
+    assert_eq!(reader.kf8_type.as_deref(), Some("standalone"));
+    assert_exth_kept(&reader.sections[0].0);
+    check_kf8(&reader);
+}
+
+#[test]
+fn test_tweak_joint() {
+    let (reader, _work) = tweak_round_trip(MobiFileType::Both);
+    assert_eq!(reader.kf8_type.as_deref(), Some("joint"));
+    assert_exth_kept(&reader.sections[0].0);
+    let kf8_rec0 = &reader.sections[reader.kf8_boundary.unwrap() + 1].0;
+    assert_exth_kept(kf8_rec0);
+    check_kf8(&reader);
+}
+
+#[test]
+fn test_tweak_mobi6() {
+    let (reader, _work) = tweak_round_trip(MobiFileType::Old);
+    assert!(reader.kf8_type.is_none());
+    assert_exth_kept(&reader.sections[0].0);
+
+    let out = tempdir().unwrap();
+    let book = reader.extract_content(out.path()).unwrap();
+    // One file per page break, with the links and images resolved
+    assert_eq!(book.spine.items.len(), 2);
+    let part0 = fs::read_to_string(out.path().join("part0000.html")).unwrap();
+    let part1 = fs::read_to_string(out.path().join("part0001.html")).unwrap();
+    assert!(part0.contains("small fix"));
+    assert!(part1.contains("The end"));
+    let link = part0.split("href=\"").nth(1).unwrap();
+    let (file, id) = link[..link.find('"').unwrap()].split_once('#').unwrap();
+    assert_eq!(file, "part0001.html");
+    assert!(part1.contains(&format!("id=\"{}\"", id)));
+    let img = part0.split("src=\"").nth(1).unwrap();
+    let img = &img[..img.find('"').unwrap()];
+    assert!(out.path().join(img).exists());
+    assert!(book
+        .guide
+        .get("text")
+        .unwrap()
+        .href
+        .starts_with("part0000.html#"));
+    assert_eq!(book.metadata.first("creator"), Some("Jane Doe"));
+}
+
+#[test]
+fn test_rebuild_without_opf() {
+    let empty = tempdir().unwrap();
+    let dest = empty.path().join("out.azw3");
+    assert!(rebuild(empty.path(), &dest).is_err());
+}
@@ -31,3 +31,30 @@ fn test_namespace_helpers() {
     assert_eq!(barename("{http://ns}test"), "test");
     assert_eq!(namespace("{http://ns}test"), "http://ns");
 }
+
+#[test]
+fn test_metadata_to_meta_information() {
+    let mut m = Metadata::new();
+    m.add("title", "My Book");
+    m.add("creator", "Jane Doe");
+    m.add("creator", "John Roe");
+    m.add("subject", "Fiction");
+    m.add("language", "en");
+    m.add("date", "2001-05");
+    m.add("calibre:series", "Saga");
+    m.add("calibre:series_index", "3");
+
+    let mi = m.to_meta_information();
+    assert_eq!(mi.title, "My Book");
+    assert_eq!(mi.authors, vec!["Jane Doe", "John Roe"]);
+    assert_eq!(mi.tags, vec!["Fiction"]);
+    assert_eq!(mi.languages, vec!["en"]);
+    assert_eq!(mi.series.as_deref(), Some("Saga"));
+    assert_eq!(mi.series_index, 3.0);
+    assert_eq!(
+        mi.pubdate.unwrap().format("%Y-%m-%d").to_string(),
+        "2001-05-01"
+    );
+    assert_eq!(m.first("title"), Some("My Book"));
+    assert_eq!(m.first("publisher"), None);
+}
@@ -26,3 +26,11 @@ fn test_xhtml_helper() {
 fn test_qualified_name() {
     assert_eq!(qualified_name("http://ns", "tag"), "{http://ns}tag");
 }
+
+#[test]
+fn test_abshref() {
+    assert_eq!(abshref("text/ch1.html", "../images/a.png"), "images/a.png");
+    assert_eq!(abshref("text/ch1.html", "a%20b.png#frag"), "text/a b.png");
+    assert_eq!(abshref("ch1.html", "./img/./c.jpg"), "img/c.jpg");
+    assert_eq!(abshref("text/ch1.html", "#note"), "text/ch1.html");
+}
@@ -1,5 +1,7 @@
 use calibre_ebooks::oeb::book::OEBBook;
 use calibre_ebooks::oeb::container::DirContainer;
+use calibre_ebooks::oeb::reader::OEBReader;
+use calibre_ebooks::oeb::toc::TOCNode;
 use calibre_ebooks::oeb::writer::OEBWriter;
 use std::fs;
 use tempfile::tempdir;
@@ -54,3 +56,46 @@ fn test_oeb_writer_round_trip_files() {
     let content = fs::read_to_string(dst_dir.path().join("chapter1.html")).unwrap();
     assert_eq!(content, "<html>Content</html>");
 }
+
+#[test]
+fn test_oeb_writer_ncx_and_identifiers_round_trip() {
+    let src_dir = tempdir().unwrap();
+    let dst_dir = tempdir().unwrap();
+    fs::write(src_dir.path().join("chapter1.html"), "<html>Content</html>").unwrap();
+
+    let container = Box::new(DirContainer::new(src_dir.path()));
+    let mut book = OEBBook::new(container);
+    book.manifest
+        .add("item1", "chapter1.html", "application/xhtml+xml");
+    book.spine.add("item1", true);
+    book.metadata.add("title", "My Book");
+    let mut attrib = std::collections::HashMap::new();
+    attrib.insert("opf:scheme".to_string(), "ISBN".to_string());
+    book.metadata
+        .add_with_attrib("identifier", "9780000000002", attrib);
+    let mut chapter = TOCNode::new(Some("Chapter 1".into()), Some("chapter1.html".into()));
+    chapter.add(TOCNode::new(
+        Some("Part A".into()),
+        Some("chapter1.html#a".into()),
+    ));
+    book.toc.root.add(chapter);
+
+    OEBWriter::new()
+        .write_book(&mut book, dst_dir.path())
+        .unwrap();
+    let opf = fs::read_to_string(dst_dir.path().join("content.opf")).unwrap();
+    assert!(opf.contains("<dc:title>My Book</dc:title>"));
+    assert!(opf.contains("<spine toc=\"ncx\">"));
+    assert!(dst_dir.path().join("toc.ncx").exists());
+
+    let mut read = OEBBook::new(Box::new(DirContainer::new(dst_dir.path())));
+    OEBReader::new().read_opf(&mut read, "content.opf").unwrap();
+    let ids = read.metadata.get("identifier");
+    assert_eq!(
+        ids[0].get_attribute("opf:scheme").map(|s| s.as_str()),
+        Some("ISBN")
+    );
+    let chapter = &read.toc.root.children[0];
+    assert_eq!(chapter.title.as_deref(), Some("Chapter 1"));
+    assert_eq!(chapter.children[0].href.as_deref(), Some("chapter1.html#a"));
+}
@@ -1,7 +1,10 @@
+use calibre_ebooks::metadata::xmp::metadata_from_xmp_packet;
 use calibre_ebooks::oeb::book::OEBBook;
 use calibre_ebooks::oeb::container::DirContainer;
 use calibre_ebooks::oeb::manifest::ManifestItem;
+use calibre_ebooks::oeb::toc::TOCNode;
 use calibre_ebooks::output::pdf_output::PDFOutput;
+use calibre_ebooks::pdf::fonts::{FontFamily, FontKey};
 use lopdf::Document;
 use std::fs;
 use tempfile::tempdir;
@@ -51,3 +54,194 @@ fn test_pdf_output_conversion() {
     // basic sanity check
     assert_eq!(doc.version, "1.4");
 }
+
+fn book_with_chapters(book_dir: &std::path::Path, chapters: &[(&str, String)]) -> OEBBook {
+    let container = Box::new(DirContainer::new(book_dir));
+    let mut book = OEBBook::new(container);
+    for (i, (href, html)) in chapters.iter().enumerate() {
+        fs::write(book_dir.join(href), html).unwrap();
+        let id = format!("ch{}", i);
+        book.manifest.add(&id, href, "application/xhtml+xml");
+        book.spine.add(&id, true);
+    }
+    book
+}
+
+#[test]
+fn test_pdf_output_paginates_and_wraps() {
+    let tmp_dir = tempdir().unwrap();
+    let book_dir = tmp_dir.path().join("book");
+    fs::create_dir_all(&book_dir).unwrap();
+    let output_path = tmp_dir.path().join("long.pdf");
+
+    let para = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. ".repeat(6);
+    let body: String = (0..60).map(|i| format!("<p>{} {}</p>", i, para)).collect();
+    let html = format!(
+        "<html xmlns=\"http://www.w3.org/1999/xhtml\"><body><h1>Chapter&nbsp;One</h1>{}</body></html>",
+        body
+    );
+    let book = book_with_chapters(&book_dir, &[("ch1.html", html)]);
+
+    PDFOutput::new().convert(&book, &output_path).unwrap();
+
+    let doc = Document::load(&output_path).unwrap();
+    let pages = doc.get_pages();
+    assert!(pages.len() > 5, "expected many pages, got {}", pages.len());
+
+    // Every line must stay within the page, which means the text got wrapped
+    let first_page = *pages.get(&1).unwrap();
+    let content = doc.get_page_content(first_page).unwrap();
+    let ops = lopdf::content::Content::decode(&content)
+        .unwrap()
+        .operations;
+    for op in ops.iter().filter(|op| op.operator == "Td") {
+        let x = op.operands[0].as_float().unwrap();
+        let y = op.operands[1].as_float().unwrap();
+        assert!(x >= 0.0 && x < 595.0);
+        assert!(y > 0.0 && y < 842.0);
+    }
+    let fonts = doc.get_page_fonts(first_page).unwrap();
+    let names: Vec<String> = fonts
+        .values()
+        .map(|f| {
+            String::from_utf8_lossy(f.get(b"BaseFont").unwrap().as_name().unwrap()).to_string()
+        })
+        .collect();
+    assert!(names.contains(&"Times-Roman".to_string()));
+    assert!(names.contains(&"Times-Bold".to_string()));
+}
+
+#[test]
+fn test_pdf_output_outline_metadata_and_images() {
+    let tmp_dir = tempdir().unwrap();
+    let book_dir = tmp_dir.path().join("book");
+    fs::create_dir_all(book_dir.join("images")).unwrap();
+    let output_path = tmp_dir.path().join("book.pdf");
+
+    let img = image::RgbaImage::from_pixel(40, 20, image::Rgba([255, 0, 0, 128]));
+    img.save(book_dir.join("images/red.png")).unwrap();
+
+    let ch1 = "<html xmlns=\"http://www.w3.org/1999/xhtml\"><body><h1>First</h1><p>Intro <b>bold</b> <i>italic</i></p><p><img src=\"images/red.png\" alt=\"red\"/></p></body></html>".to_string();
+    let ch2 = "<html xmlns=\"http://www.w3.org/1999/xhtml\"><body><h2 id=\"s1\">Second</h2><p style=\"text-align: center; color: #336699\">Centered</p></body></html>".to_string();
+    let mut book = book_with_chapters(&book_dir, &[("ch1.html", ch1), ("ch2.html", ch2)]);
+    book.metadata.add("title", "Outline Test");
+    book.metadata.add("creator", "Jane Doe");
+    book.metadata.add("subject", "Testing");
+
+    let mut first = TOCNode::new(Some("First".into()), Some("ch1.html".into()));
+    first.add(TOCNode::new(
+        Some("Second".into()),
+        Some("ch2.html#s1".into()),
+    ));
+    book.toc.root.add(first);
+
+    PDFOutput::new().convert(&book, &output_path).unwrap();
+
+    let doc = Document::load(&output_path).unwrap();
+    let pages = doc.get_pages();
+    assert_eq!(pages.len(), 2);
+
+    // Info dictionary
+    let info_id = doc.trailer.get(b"Info").unwrap().as_reference().unwrap();
+    let info = doc.get_dictionary(info_id).unwrap();
+    assert_eq!(
+        info.get(b"Title").unwrap().as_str().unwrap(),
+        b"Outline Test"
+    );
+    assert_eq!(info.get(b"Author").unwrap().as_str().unwrap(), b"Jane Doe");
+
+    // XMP packet
+    let catalog = doc.catalog().unwrap();
+    let xmp_id = catalog.get(b"Metadata").unwrap().as_reference().unwrap();
+    let xmp = doc.get_object(xmp_id).unwrap().as_stream().unwrap();
+    let mi = metadata_from_xmp_packet(&xmp.content).unwrap();
+    assert_eq!(mi.title, "Outline Test");
+    assert_eq!(mi.authors, vec!["Jane Doe".to_string()]);
+    assert_eq!(mi.tags, vec!["Testing".to_string()]);
+
+    // Outline: one top level entry with a nested child pointing at page 2
+    let outlines_id = catalog.get(b"Outlines").unwrap().as_reference().unwrap();
+    let outlines = doc.get_dictionary(outlines_id).unwrap();
+    let top_id = outlines.get(b"First").unwrap().as_reference().unwrap();
+    let top = doc.get_dictionary(top_id).unwrap();
+    assert_eq!(top.get(b"Title").unwrap().as_str().unwrap(), b"First");
+    let child_id = top.get(b"First").unwrap().as_reference().unwrap();
+    let child = doc.get_dictionary(child_id).unwrap();
+    let dest = child.get(b"Dest").unwrap().as_array().unwrap();
+    assert_eq!(dest[0].as_reference().unwrap(), *pages.get(&2).unwrap());
+
+    // The image is embedded with a soft mask for its alpha channel
+    let first_page = *pages.get(&1).unwrap();
+    let res_id = doc
+        .get_dictionary(first_page)
+        .unwrap()
+        .get(b"Resources")
+        .unwrap()
+        .as_reference()
+        .unwrap();
+    let resources = doc.get_dictionary(res_id).unwrap();
+    let xobjects = resources.get(b"XObject").unwrap().as_dict().unwrap();
+    let (_, im) = xobjects.iter().next().unwrap();
+    let im = doc
+        .get_object(im.as_reference().unwrap())
+        .unwrap()
+        .as_stream()
+        .unwrap();
+    assert_eq!(im.dict.get(b"Width").unwrap().as_i64().unwrap(), 40);
+    assert!(im.dict.has(b"SMask"));
+}
+
+#[test]
+fn test_pdf_output_embeds_font_for_non_latin_text() {
+    let tmp_dir = tempdir().unwrap();
+    let book_dir = tmp_dir.path().join("book");
+    fs::create_dir_all(&book_dir).unwrap();
+    let output_path = tmp_dir.path().join("greek.pdf");
+
+    let html = "<html xmlns=\"http://www.w3.org/1999/xhtml\"><body><p>Plain text</p><p>Καλημέρα κόσμε</p></body></html>".to_string();
+    let book = book_with_chapters(&book_dir, &[("ch1.html", html)]);
+    PDFOutput::new().convert(&book, &output_path).unwrap();
+
+    let doc = Document::load(&output_path).unwrap();
+    let fonts: Vec<&lopdf::Dictionary> = doc
+        .objects
+        .values()
+        .filter_map(|o| o.as_dict().ok())
+        .filter(|d| d.get(b"Type").and_then(|t| t.as_name()).ok() == Some(b"Font"))
+        .collect();
+    let subtype = |d: &lopdf::Dictionary| d.get(b"Subtype").unwrap().as_name().unwrap().to_vec();
+    // The Latin paragraph keeps using a standard font
+    assert!(fonts.iter().any(|d| subtype(d) == b"Type1"));
+
+    let serif = FontKey::new(FontFamily::Serif, false, false);
+    if serif.embedded().is_none() {
+        // No TrueType font installed, the Greek text was replaced instead
+        return;
+    }
+    let type0 = fonts
+        .iter()
+        .find(|d| subtype(d) == b"Type0")
+        .expect("Greek text must use an embedded font");
+    assert_eq!(
+        type0.get(b"Encoding").unwrap().as_name().unwrap(),
+        b"Identity-H"
+    );
+    let to_unicode = type0.get(b"ToUnicode").unwrap().as_reference().unwrap();
+    let cmap = doc.get_object(to_unicode).unwrap().as_stream().unwrap();
+    let cmap = String::from_utf8_lossy(&cmap.content);
+    // Kappa, U+039A, maps back from its glyph
+    assert!(cmap.contains("<039A>"));
+
+    let descendants = type0.get(b"DescendantFonts").unwrap().as_array().unwrap();
+    let cid_font = doc
+        .get_dictionary(descendants[0].as_reference().unwrap())
+        .unwrap();
+    assert_eq!(subtype(cid_font), b"CIDFontType2");
+    let descriptor_id = cid_font
+        .get(b"FontDescriptor")
+        .unwrap()
+        .as_reference()
+        .unwrap();
+    let descriptor = doc.get_dictionary(descriptor_id).unwrap();
+    assert!(descriptor.has(b"FontFile2"));
+}
@@ -25,7 +25,8 @@ fn test_rtf_input_conversion() {
     let content_path = output_dir.join("index.html");
     assert!(content_path.exists());
     let content = fs::read_to_string(content_path).unwrap();
-    assert!(content.contains("This is some {\\b bold} text.")); // Our simple parser currently keeps RTF tags mostly or escapes them
+    assert!(content.contains("This is some <b>bold</b> text."));
+    assert!(!content.contains("\\b"));
 }
 
 #[test]
@@ -46,3 +47,96 @@ fn test_rtf_input_plain_text_fallback() {
     let content = fs::read_to_string(content_path).unwrap();
     assert!(content.contains("Just plain text acting as RTF."));
 }
+
+fn convert_rtf(
+    rtf: &str,
+) -> (
+    calibre_ebooks::oeb::book::OEBBook,
+    String,
+    tempfile::TempDir,
+) {
+    let tmp_dir = tempdir().unwrap();
+    let input_path = tmp_dir.path().join("doc.rtf");
+    let output_dir = tmp_dir.path().join("output");
+    fs::write(&input_path, rtf).unwrap();
+    let book = RTFInput::new().convert(&input_path, &output_dir).unwrap();
+    let content = fs::read_to_string(output_dir.join("index.html")).unwrap();
+    (book, content, tmp_dir)
+}
+
+#[test]
+fn test_rtf_input_structure_and_formatting() {
+    let rtf = r#"{\rtf1\ansi\deff0{\fonttbl{\f0\froman Times;}{\f1\fmodern Courier New;}}
+{\colortbl;\red255\green0\blue0;}
+{\stylesheet{\s0 Normal;}{\s1\b\fs32 heading 1;}}
+{\info{\title The Title}{\author Jane Doe}{\keywords one, two}}
+{\*\listtable{\list{\listlevel\levelnfc0}\listid10}{\list{\listlevel\levelnfc23}\listid20}}
+{\*\listoverridetable{\listoverride\listid10\ls1}{\listoverride\listid20\ls2}}
+\pard\s1\fs32 Chapter One\par
+\pard\qc\fs24 {\i centred} {\ul under} {\strike gone} x{\super 2} {\cf1 red} {\f1 code}\par
+\pard\ls1\ilvl0 first\par
+\pard\ls1\ilvl0 second\par
+\pard\ls2\ilvl1 nested\par
+\pard {\field{\*\fldinst HYPERLINK "http://example.com/"}{\fldrslt link text}} after\line next\par
+\pard\page\pard after break\par
+}"#;
+    let (book, content, _tmp) = convert_rtf(rtf);
+
+    assert!(roxmltree::Document::parse(&content).is_ok(), "{}", content);
+    assert!(content.contains("<h1>Chapter One</h1>"));
+    assert!(content.contains("<p style=\"text-align: center\"><i>centred</i>"));
+    assert!(content.contains("<u>under</u>"));
+    assert!(content.contains("<span style=\"text-decoration: line-through\">gone</span>"));
+    assert!(content.contains("x<sup>2</sup>"));
+    assert!(content.contains("<span style=\"color: #ff0000\">red</span>"));
+    assert!(content.contains("<span style=\"font-family: monospace\">code</span>"));
+    assert!(content.contains("<ol><li>first</li><li>second<ul><li>nested</li></ul></li></ol>"));
+    assert!(content.contains("<a href=\"http://example.com/\">link text</a> after<br/>next"));
+    assert!(content.contains("<p style=\"page-break-before: always\">after break</p>"));
+
+    assert_eq!(book.metadata.first("title"), Some("The Title"));
+    assert_eq!(book.metadata.first("creator"), Some("Jane Doe"));
+    assert_eq!(book.metadata.first("subject"), Some("one"));
+}
+
+#[test]
+fn test_rtf_input_code_pages_and_unicode() {
+    let rtf = r"{\rtf1\ansi\ansicpg1251\deff0\deflang1049{\fonttbl{\f0 Arial;}{\f1\fcharset238 Arial CE;}}
+\pard \'cf\'f0\'e8\'e2\'e5\'f2 {\f1 \'9ala} \uc1\u8364?\u-10179?\u-8704? {\uc0\u233} caf\u233?\par}";
+    let (book, content, _tmp) = convert_rtf(rtf);
+
+    assert!(
+        content.contains("Привет šla €\u{1f600} é café"),
+        "{}",
+        content
+    );
+    assert_eq!(book.metadata.first("language"), Some("ru"));
+}
+
+#[test]
+fn test_rtf_input_tables_images_and_footnotes() {
+    let rtf = r"{\rtf1\ansi
+\pard Before\par
+\trowd\cellx1000\cellx2000\pard\intbl A1\cell\pard\intbl B1\cell\row
+\trowd\cellx1000\cellx2000\pard\intbl A2\cell\pard\intbl {\b B2}\cell\row
+\pard Picture {\*\shppict{\pict\pngblip\picw10\pich10\picwgoal300\pichgoal150 89504e470d0a1a0a}}{\nonshppict{\pict\wmetafile8 0102}}\par
+\pard Noted{\super\chftn}{\footnote\pard\plain {\super\chftn} The note.}\par
+}";
+    let (book, content, tmp) = convert_rtf(rtf);
+
+    assert!(roxmltree::Document::parse(&content).is_ok(), "{}", content);
+    assert!(content.contains(
+        "<table><tr><td>A1</td><td>B1</td></tr><tr><td>A2</td><td><b>B2</b></td></tr></table>"
+    ));
+    assert!(content.contains("<img src=\"images/img1.png\" alt=\"\" width=\"20\" height=\"10\"/>"));
+    assert_eq!(content.matches("<img").count(), 1);
+    let image = book.manifest.get_by_href("images/img1.png").unwrap();
+    assert_eq!(image.media_type, "image/png");
+    let data = fs::read(tmp.path().join("output/images/img1.png")).unwrap();
+    assert_eq!(data, b"\x89PNG\r\n\x1a\n");
+
+    assert!(content.contains("Noted<sup><a id=\"fnref1\" href=\"#fn1\">1</a></sup>"));
+    assert!(content.contains("<p id=\"fn1\"><a href=\"#fnref1\">1</a>. "));
+    assert!(content.contains("The note.</p>"));
+    assert!(!content.contains("Noted The note"));
+}
@@ -50,3 +50,103 @@ fn test_rtf_output_conversion() {
     assert!(rtf_content.contains("Second Line"));
     assert!(rtf_content.contains("\\par"));
 }
+
+fn build_book(book_dir: &std::path::Path) -> OEBBook {
+    let mut png = Vec::new();
+    image::RgbImage::from_pixel(4, 2, image::Rgb([255, 0, 0]))
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    fs::create_dir_all(book_dir.join("images")).unwrap();
+    fs::write(book_dir.join("images/red.png"), png).unwrap();
+    fs::write(
+        book_dir.join("ch1.html"),
+        r#"<html xmlns="http://www.w3.org/1999/xhtml"><head><title>x</title></head><body>
+<h1>Chapter &amp; One</h1>
+<p style="text-align: center">Some <b>bold</b>, <i>italic</i> and <u>under</u> text &euro;5.</p>
+<ol><li>first</li><li>second<ul><li>nested</li></ul></li></ol>
+<table><tr><th>H1</th><th>H2</th></tr><tr><td>a</td><td><span style="color: #ff0000">b</span></td></tr></table>
+<p>A <a href="http://example.com/">link</a> and <a href="ch2.html#end">a jump</a>.</p>
+<p><img src="images/red.png" alt="red"/></p>
+</body></html>"#,
+    )
+    .unwrap();
+    fs::write(
+        book_dir.join("ch2.html"),
+        r#"<html xmlns="http://www.w3.org/1999/xhtml"><body><p id="end">Second {doc}</p></body></html>"#,
+    )
+    .unwrap();
+
+    let container = Box::new(DirContainer::new(book_dir));
+    let mut book = OEBBook::new(container);
+    book.manifest
+        .add("ch1", "ch1.html", "application/xhtml+xml");
+    book.manifest
+        .add("ch2", "ch2.html", "application/xhtml+xml");
+    book.manifest.add("red", "images/red.png", "image/png");
+    book.spine.add("ch1", true);
+    book.spine.add("ch2", true);
+    book.metadata.add("title", "Styled Book");
+    book.metadata.add("creator", "Jane Doe");
+    book.metadata.add("language", "en");
+    book
+}
+
+#[test]
+fn test_rtf_output_preserves_formatting() {
+    let tmp_dir = tempdir().unwrap();
+    let book = build_book(&tmp_dir.path().join("book"));
+    let output_path = tmp_dir.path().join("styled.rtf");
+    RTFOutput::new().convert(&book, &output_path).unwrap();
+    let rtf = fs::read_to_string(&output_path).unwrap();
+
+    assert!(rtf.starts_with("{\\rtf1\\ansi"));
+    assert!(rtf.contains("\\deflang1033"));
+    assert!(rtf.contains("{\\info{\\title Styled Book}{\\author Jane Doe}"));
+    assert!(rtf.contains("\\s1\\outlinelevel0\\keepn"));
+    assert!(rtf.contains("Chapter & One\\par"));
+    assert!(rtf.contains("\\qc"));
+    assert!(rtf.contains("{\\b bold}, {\\i italic} and {\\ul under} text \\u8364?5."));
+    assert!(rtf.contains("\\ls1\\ilvl0"));
+    assert!(rtf.contains("{\\listtext\\pard\\plain 1.\\tab}first"));
+    assert!(rtf.contains("\\ls1\\ilvl1"));
+    assert!(rtf.contains("\\trowd"));
+    assert!(rtf.contains("a\\cell"));
+    assert!(rtf.contains("{\\cf1 b}\\cell\n\\row"));
+    assert!(rtf.contains("{\\colortbl;\\red255\\green0\\blue0;"));
+    assert!(rtf.contains("{\\field{\\*\\fldinst {HYPERLINK \"http://example.com/\"}}"));
+    assert!(rtf.contains("HYPERLINK \\\\l \"ch2_html_end\""));
+    assert!(rtf.contains("{\\*\\bkmkstart ch2_html_end}"));
+    assert!(rtf.contains("{\\pict\\pngblip\\picw4\\pich2\\picwgoal60\\pichgoal30"));
+    assert!(rtf.contains("\\pagebb"));
+    assert!(rtf.contains("Second \\{doc\\}"));
+    assert_eq!(
+        rtf.matches('{').count() - rtf.matches("\\{").count(),
+        rtf.matches('}').count() - rtf.matches("\\}").count()
+    );
+}
+
+#[test]
+fn test_rtf_output_round_trips_through_rtf_input() {
+    let tmp_dir = tempdir().unwrap();
+    let book = build_book(&tmp_dir.path().join("book"));
+    let output_path = tmp_dir.path().join("roundtrip.rtf");
+    RTFOutput::new().convert(&book, &output_path).unwrap();
+
+    let out_dir = tmp_dir.path().join("back");
+    let back = calibre_ebooks::input::rtf_input::RTFInput::new()
+        .convert(&output_path, &out_dir)
+        .unwrap();
+    let html = fs::read_to_string(out_dir.join("index.html")).unwrap();
+
+    assert!(html.contains("Chapter &amp; One</h1>"), "{}", html);
+    assert!(html.contains("<b>bold</b>, <i>italic</i> and <u>under</u> text €5."));
+    assert!(html.contains("<ol><li>first</li><li>second<ul><li>nested</li></ul></li></ol>"));
+    assert!(html.contains("<td><b>H1</b></td>"));
+    assert!(html.contains("<a href=\"http://example.com/\">"));
+    assert!(html.contains("<a href=\"#ch2_html_end\">"));
+    assert!(html.contains("<a id=\"ch2_html_end\"></a>Second {doc}"));
+    assert!(html.contains("page-break-before: always"));
+    assert!(html.contains("<img src=\"images/img1.png\""));
+    assert_eq!(back.metadata.first("title"), Some("Styled Book"));
+    assert_eq!(back.metadata.first("language"), Some("en"));
+}
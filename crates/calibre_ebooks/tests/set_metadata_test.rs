@@ -0,0 +1,561 @@
+use calibre_ebooks::metadata::{self, MetaInformation};
+use chrono::{TimeZone, Utc};
+use image::{ImageFormat, RgbImage};
+use std::io::{Cursor, Read, Write};
+use tempfile::tempdir;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive};
+
+fn image(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+    let img = RgbImage::from_pixel(width, height, image::Rgb([200, 40, 40]));
+    let mut out = Cursor::new(Vec::new());
+    img.write_to(&mut out, format).unwrap();
+    out.into_inner()
+}
+
+fn build_zip(entries: &[(&str, &[u8])], comment: &str) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, data) in entries {
+        let method = if *name == "mimetype" {
+            CompressionMethod::Stored
+        } else {
+            CompressionMethod::Deflated
+        };
+        zip.start_file(*name, FileOptions::default().compression_method(method))
+            .unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.set_comment(comment);
+    zip.finish().unwrap().into_inner()
+}
+
+fn zip_entry(data: &[u8], name: &str) -> Vec<u8> {
+    let mut archive = ZipArchive::new(Cursor::new(data)).unwrap();
+    let mut out = Vec::new();
+    archive
+        .by_name(name)
+        .unwrap()
+        .read_to_end(&mut out)
+        .unwrap();
+    out
+}
+
+fn new_metadata() -> MetaInformation {
+    let mut mi = MetaInformation::new(
+        "The New Title",
+        vec!["Jane Roe".to_string(), "Ana María Díaz".to_string()],
+    );
+    mi.tags = vec!["Fantasy".to_string(), "Adventure".to_string()];
+    mi.publisher = Some("Acme & Sons".to_string());
+    mi.comments = Some("A long journey.".to_string());
+    mi.series = Some("The Saga".to_string());
+    mi.series_index = 3.0;
+    mi.languages = vec!["en".to_string()];
+    mi.pubdate = Some(Utc.with_ymd_and_hms(2011, 5, 6, 0, 0, 0).unwrap());
+    mi.set_identifier("isbn", "9780306406157");
+    mi
+}
+
+const CONTAINER: &[u8] = br#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>"#;
+
+fn epub2() -> Vec<u8> {
+    let opf = br#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0" unique-identifier="uid">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:title>Old Title</dc:title>
+    <dc:creator opf:role="aut">Old Author</dc:creator>
+    <dc:contributor opf:role="edt">Ed Itor</dc:contributor>
+    <dc:creator opf:role="ill">Ill Ustrator</dc:creator>
+    <dc:identifier id="uid" opf:scheme="uuid">1234-5678</dc:identifier>
+    <dc:identifier opf:scheme="ISBN">0000000000</dc:identifier>
+    <dc:subject>Old Tag</dc:subject>
+    <dc:language>fr</dc:language>
+    <meta name="calibre:series" content="Old Series"/>
+    <meta name="cover" content="cover-img"/>
+  </metadata>
+  <manifest>
+    <item id="cover-img" href="images/cover.png" media-type="image/png"/>
+    <item id="text" href="text.html" media-type="application/xhtml+xml"/>
+  </manifest>
+  <spine><itemref idref="text"/></spine>
+</package>"#;
+    let cover = image(10, 10, ImageFormat::Png);
+    build_zip(
+        &[
+            ("mimetype", b"application/epub+zip"),
+            ("META-INF/container.xml", CONTAINER),
+            ("OEBPS/content.opf", opf),
+            ("OEBPS/images/cover.png", &cover),
+            ("OEBPS/text.html", b"<html><body><p>Hi</p></body></html>"),
+        ],
+        "",
+    )
+}
+
+#[test]
+fn test_epub2_set_metadata_round_trip() {
+    let mut mi = new_metadata();
+    mi.cover_data = (Some("jpg".to_string()), image(30, 40, ImageFormat::Jpeg));
+    let updated = metadata::epub::set_metadata(&epub2(), &mi).unwrap();
+
+    let read = metadata::epub::get_metadata(Cursor::new(&updated)).unwrap();
+    assert_eq!(read.title, "The New Title");
+    assert_eq!(read.authors, vec!["Jane Roe", "Ana María Díaz"]);
+    assert_eq!(read.tags, vec!["Fantasy", "Adventure"]);
+    assert_eq!(read.publisher.as_deref(), Some("Acme & Sons"));
+    assert_eq!(read.comments.as_deref(), Some("A long journey."));
+    assert_eq!(read.series.as_deref(), Some("The Saga"));
+    assert_eq!(read.series_index, 3.0);
+    assert_eq!(read.languages, vec!["en"]);
+    assert_eq!(read.pubdate, mi.pubdate);
+    assert_eq!(
+        read.identifiers.get("isbn").map(String::as_str),
+        Some("9780306406157")
+    );
+    // The unique identifier is left alone
+    assert_eq!(read.uuid.as_deref(), Some("1234-5678"));
+
+    // The cover keeps its name and type
+    let cover = image::load_from_memory(&read.cover_data.1).unwrap();
+    assert_eq!((cover.width(), cover.height()), (30, 40));
+    assert_eq!(
+        image::guess_format(&read.cover_data.1).unwrap(),
+        ImageFormat::Png
+    );
+
+    let opf = String::from_utf8(zip_entry(&updated, "OEBPS/content.opf")).unwrap();
+    assert!(opf.contains("Ed Itor"));
+    assert!(opf.contains("Ill Ustrator"));
+    assert!(!opf.contains("Old Author"));
+    assert!(!opf.contains("Old Series"));
+    assert!(!opf.contains("0000000000"));
+
+    // mimetype stays first and stored
+    let mut archive = ZipArchive::new(Cursor::new(&updated)).unwrap();
+    let first = archive.by_index(0).unwrap();
+    assert_eq!(first.name(), "mimetype");
+    assert_eq!(first.compression(), CompressionMethod::Stored);
+}
+
+#[test]
+fn test_epub3_set_metadata_adds_cover() {
+    let opf = br##"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="bookid">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="bookid">urn:uuid:abcd</dc:identifier>
+    <dc:title>Old</dc:title>
+    <dc:creator id="c1">Old Author</dc:creator>
+    <meta refines="#c1" property="role" scheme="marc:relators">aut</meta>
+    <meta property="dcterms:modified">2020-01-01T00:00:00Z</meta>
+  </metadata>
+  <manifest>
+    <item id="text" href="text.html" media-type="application/xhtml+xml"/>
+  </manifest>
+  <spine><itemref idref="text"/></spine>
+</package>"##;
+    let epub = build_zip(
+        &[
+            ("mimetype", b"application/epub+zip"),
+            ("META-INF/container.xml", CONTAINER),
+            ("OEBPS/content.opf", opf),
+            ("OEBPS/text.html", b"<html><body/></html>"),
+        ],
+        "",
+    );
+    let mut mi = new_metadata();
+    mi.set_identifier("goodreads", "42");
+    mi.cover_data = (Some("png".to_string()), image(8, 12, ImageFormat::Png));
+    let updated = metadata::epub::set_metadata(&epub, &mi).unwrap();
+
+    let read = metadata::epub::get_metadata(Cursor::new(&updated)).unwrap();
+    assert_eq!(read.authors, vec!["Jane Roe", "Ana María Díaz"]);
+    assert_eq!(read.uuid.as_deref(), Some("abcd"));
+    assert_eq!(
+        read.identifiers.get("isbn").map(String::as_str),
+        Some("9780306406157")
+    );
+    assert_eq!(
+        read.identifiers.get("goodreads").map(String::as_str),
+        Some("42")
+    );
+    assert_eq!(
+        image::guess_format(&read.cover_data.1).unwrap(),
+        ImageFormat::Jpeg
+    );
+
+    let opf = String::from_utf8(zip_entry(&updated, "OEBPS/content.opf")).unwrap();
+    assert!(opf.contains("properties=\"cover-image\""));
+    assert!(opf.contains("dcterms:modified"));
+    assert!(!opf.contains("refines=\"#c1\""));
+    assert!(!opf.contains("opf:"));
+    assert!(roxmltree::Document::parse(&opf).is_ok());
+}
+
+/// A MOBI file with the records `[rec0, text, cover, thumbnail]`.
+fn mobi(cover: &[u8], thumb: &[u8]) -> Vec<u8> {
+    let mut rec0 = vec![0u8; 16];
+    rec0[0..2].copy_from_slice(&1u16.to_be_bytes());
+    rec0.extend_from_slice(b"MOBI");
+    rec0.extend_from_slice(&232u32.to_be_bytes());
+    rec0.resize(248, 0);
+    rec0[28..32].copy_from_slice(&65001u32.to_be_bytes());
+    rec0[108..112].copy_from_slice(&2u32.to_be_bytes());
+    rec0[0x80..0x84].copy_from_slice(&0x40u32.to_be_bytes());
+
+    let exth_records: Vec<(u32, Vec<u8>)> = vec![
+        (100, b"Old Author".to_vec()),
+        (503, b"Old Title".to_vec()),
+        (201, 0u32.to_be_bytes().to_vec()),
+        (202, 1u32.to_be_bytes().to_vec()),
+        (204, 201u32.to_be_bytes().to_vec()),
+    ];
+    let len = 12 + exth_records.iter().map(|(_, d)| 8 + d.len()).sum::<usize>();
+    rec0.extend_from_slice(b"EXTH");
+    rec0.extend_from_slice(&(len as u32).to_be_bytes());
+    rec0.extend_from_slice(&(exth_records.len() as u32).to_be_bytes());
+    for (id, data) in &exth_records {
+        rec0.extend_from_slice(&id.to_be_bytes());
+        rec0.extend_from_slice(&(8 + data.len() as u32).to_be_bytes());
+        rec0.extend_from_slice(data);
+    }
+    let name_offset = rec0.len() as u32;
+    rec0.extend_from_slice(b"Old Title\0\0\0");
+    rec0[0x54..0x58].copy_from_slice(&name_offset.to_be_bytes());
+    rec0[0x58..0x5c].copy_from_slice(&9u32.to_be_bytes());
+
+    let records = [
+        rec0,
+        b"text record".to_vec(),
+        cover.to_vec(),
+        thumb.to_vec(),
+    ];
+    let mut out = b"Old_Title".to_vec();
+    out.resize(32, 0);
+    out.resize(60, 0);
+    out.extend_from_slice(b"BOOKMOBI");
+    out.resize(76, 0);
+    out.extend_from_slice(&(records.len() as u16).to_be_bytes());
+    let mut offset = 78 + records.len() * 8 + 2;
+    for (i, record) in records.iter().enumerate() {
+        out.extend_from_slice(&(offset as u32).to_be_bytes());
+        out.extend_from_slice(&(2 * i as u32).to_be_bytes());
+        offset += record.len();
+    }
+    out.extend_from_slice(&[0, 0]);
+    for record in &records {
+        out.extend_from_slice(record);
+    }
+    out
+}
+
+#[test]
+fn test_mobi_set_metadata_round_trip() {
+    let original = mobi(
+        &image(60, 80, ImageFormat::Jpeg),
+        &image(6, 8, ImageFormat::Jpeg),
+    );
+    let mut mi = new_metadata();
+    mi.cover_data = (Some("png".to_string()), image(300, 400, ImageFormat::Png));
+    let updated = metadata::mobi::set_metadata(&original, &mi).unwrap();
+
+    let read = metadata::mobi::get_metadata(Cursor::new(&updated)).unwrap();
+    assert_eq!(read.title, "The New Title");
+    assert_eq!(read.authors, vec!["Jane Roe", "Ana María Díaz"]);
+    assert_eq!(read.tags, vec!["Fantasy", "Adventure"]);
+    assert_eq!(read.publisher.as_deref(), Some("Acme & Sons"));
+    assert_eq!(read.comments.as_deref(), Some("A long journey."));
+    assert_eq!(read.languages, vec!["en"]);
+    assert_eq!(read.pubdate, mi.pubdate);
+    assert_eq!(
+        read.identifiers.get("isbn").map(String::as_str),
+        Some("9780306406157")
+    );
+
+    let cover = image::load_from_memory(&read.cover_data.1).unwrap();
+    assert_eq!((cover.width(), cover.height()), (300, 400));
+    assert_eq!(
+        image::guess_format(&read.cover_data.1).unwrap(),
+        ImageFormat::Jpeg
+    );
+
+    // Same records, the text untouched, and the full name updated
+    let header = calibre_ebooks::pdb::header::PdbHeader::parse(&mut Cursor::new(&updated)).unwrap();
+    assert_eq!(header.num_records, 4);
+    let mut stream = Cursor::new(&updated);
+    assert_eq!(header.section_data(&mut stream, 1).unwrap(), b"text record");
+    let thumb = header.section_data(&mut stream, 3).unwrap();
+    let thumb = image::load_from_memory(&thumb).unwrap();
+    assert_eq!((thumb.width(), thumb.height()), (180, 240));
+    let rec0 = header.section_data(&mut stream, 0).unwrap();
+    let name_offset = u32::from_be_bytes(rec0[0x54..0x58].try_into().unwrap()) as usize;
+    let name_len = u32::from_be_bytes(rec0[0x58..0x5c].try_into().unwrap()) as usize;
+    assert_eq!(&rec0[name_offset..name_offset + name_len], b"The New Title");
+    assert_eq!(rec0.len() % 4, 0);
+}
+
+#[test]
+fn test_mobi_set_metadata_transparent_cover() {
+    let original = mobi(
+        &image(60, 80, ImageFormat::Jpeg),
+        &image(6, 8, ImageFormat::Jpeg),
+    );
+    let transparent = image::RgbaImage::from_pixel(40, 40, image::Rgba([0, 0, 0, 0]));
+    let mut png = Cursor::new(Vec::new());
+    transparent.write_to(&mut png, ImageFormat::Png).unwrap();
+    let mut mi = new_metadata();
+    mi.cover_data = (Some("png".to_string()), png.into_inner());
+    let updated = metadata::mobi::set_metadata(&original, &mi).unwrap();
+
+    // Transparency becomes white, not black, in the JPEG cover
+    let read = metadata::mobi::get_metadata(Cursor::new(&updated)).unwrap();
+    let cover = image::load_from_memory(&read.cover_data.1).unwrap().to_rgb8();
+    assert!(cover.get_pixel(20, 20).0.iter().all(|&c| c > 245));
+}
+
+#[test]
+fn test_pdf_set_metadata_round_trip() {
+    use lopdf::{dictionary, Document, Object};
+
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let page_id = doc.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => pages_id,
+        "MediaBox" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+    });
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page_id.into()],
+            "Count" => 1,
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+    let mut original = Vec::new();
+    doc.save_to(&mut original).unwrap();
+
+    let mi = new_metadata();
+    let updated = metadata::pdf::set_metadata(&original, &mi).unwrap();
+    let read = metadata::pdf::get_metadata(Cursor::new(&updated)).unwrap();
+    assert_eq!(read.title, "The New Title");
+    assert_eq!(read.authors, vec!["Jane Roe", "Ana María Díaz"]);
+    assert_eq!(read.tags, vec!["Fantasy", "Adventure"]);
+    assert_eq!(read.comments.as_deref(), Some("A long journey."));
+
+    let doc = Document::load_mem(&updated).unwrap();
+    let xmp_id = doc
+        .catalog()
+        .unwrap()
+        .get(b"Metadata")
+        .unwrap()
+        .as_reference()
+        .unwrap();
+    let xmp = doc.get_object(xmp_id).unwrap().as_stream().unwrap();
+    let packet = String::from_utf8(xmp.content.clone()).unwrap();
+    assert!(packet.contains("The New Title"));
+    assert!(packet.contains("Acme &amp; Sons"));
+
+    // Writing again reuses the metadata stream
+    let again = metadata::pdf::set_metadata(&updated, &mi).unwrap();
+    let doc = Document::load_mem(&again).unwrap();
+    let id = doc.catalog().unwrap().get(b"Metadata").unwrap();
+    assert_eq!(id.as_reference().unwrap(), xmp_id);
+}
+
+#[test]
+fn test_docx_set_metadata_round_trip() {
+    let core = br#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<cp:coreProperties xmlns:cp="http://schemas.openxmlformats.org/package/2006/metadata/core-properties" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:dcterms="http://purl.org/dc/terms/" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">
+  <dc:title>Old</dc:title>
+  <dc:creator>Old Author</dc:creator>
+  <cp:lastModifiedBy>Someone</cp:lastModifiedBy>
+  <dcterms:created xsi:type="dcterms:W3CDTF">2020-01-01T00:00:00Z</dcterms:created>
+</cp:coreProperties>"#;
+    let app = br#"<Properties xmlns="http://schemas.openxmlformats.org/officeDocument/2006/extended-properties"><Company>Old Co</Company><Pages>1</Pages></Properties>"#;
+    let docx = build_zip(
+        &[
+            ("[Content_Types].xml", b"<Types/>"),
+            ("docProps/core.xml", core),
+            ("docProps/app.xml", app),
+            ("word/document.xml", b"<w:document/>"),
+        ],
+        "",
+    );
+    let updated = metadata::docx::set_metadata(&docx, &new_metadata()).unwrap();
+    let read = metadata::docx::get_metadata(Cursor::new(&updated)).unwrap();
+    assert_eq!(read.title, "The New Title");
+    assert_eq!(read.authors, vec!["Jane Roe", "Ana María Díaz"]);
+    assert_eq!(read.tags, vec!["Fantasy", "Adventure"]);
+    assert_eq!(read.comments.as_deref(), Some("A long journey."));
+    assert_eq!(read.publisher.as_deref(), Some("Acme & Sons"));
+
+    let core = String::from_utf8(zip_entry(&updated, "docProps/core.xml")).unwrap();
+    assert!(core.contains("<cp:lastModifiedBy>Someone</cp:lastModifiedBy>"));
+    assert!(core.contains("dcterms:created"));
+    let app = String::from_utf8(zip_entry(&updated, "docProps/app.xml")).unwrap();
+    assert!(app.contains("<Pages>1</Pages>"));
+}
+
+#[test]
+fn test_docx_set_metadata_without_core_properties() {
+    let docx = build_zip(
+        &[
+            ("[Content_Types].xml", br#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="xml" ContentType="application/xml"/></Types>"#),
+            ("_rels/.rels", br#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="word/document.xml"/></Relationships>"#),
+            ("word/document.xml", b"<w:document/>"),
+        ],
+        "",
+    );
+    let updated = metadata::docx::set_metadata(&docx, &new_metadata()).unwrap();
+    let read = metadata::docx::get_metadata(Cursor::new(&updated)).unwrap();
+    assert_eq!(read.title, "The New Title");
+
+    let types = String::from_utf8(zip_entry(&updated, "[Content_Types].xml")).unwrap();
+    assert!(types.contains("PartName=\"/docProps/core.xml\""));
+    let rels = String::from_utf8(zip_entry(&updated, "_rels/.rels")).unwrap();
+    assert!(rels.contains("Id=\"rId2\""));
+    assert!(rels.contains("Target=\"docProps/core.xml\""));
+}
+
+#[test]
+fn test_odt_set_metadata_round_trip() {
+    let meta = br#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-meta xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:meta="urn:oasis:names:tc:opendocument:xmlns:meta:1.0" xmlns:dc="http://purl.org/dc/elements/1.1/" office:version="1.2">
+  <office:meta>
+    <meta:generator>LibreOffice</meta:generator>
+    <dc:title>Old</dc:title>
+    <meta:keyword>old</meta:keyword>
+    <meta:user-defined meta:name="opf.series">Old Series</meta:user-defined>
+    <meta:user-defined meta:name="Reviewer">Bob</meta:user-defined>
+  </office:meta>
+</office:document-meta>"#;
+    let odt = build_zip(
+        &[
+            ("mimetype", b"application/vnd.oasis.opendocument.text"),
+            ("meta.xml", meta),
+            ("content.xml", b"<office:document-content/>"),
+        ],
+        "",
+    );
+    let updated = metadata::odt::set_metadata(&odt, &new_metadata()).unwrap();
+    let read = metadata::odt::get_metadata(Cursor::new(&updated)).unwrap();
+    assert_eq!(read.title, "The New Title");
+    assert_eq!(read.authors, vec!["Jane Roe", "Ana María Díaz"]);
+    assert_eq!(read.tags, vec!["Fantasy", "Adventure"]);
+    assert_eq!(read.series.as_deref(), Some("The Saga"));
+    assert_eq!(read.series_index, 3.0);
+    assert_eq!(read.publisher.as_deref(), Some("Acme & Sons"));
+    assert_eq!(read.languages, vec!["en"]);
+
+    let meta = String::from_utf8(zip_entry(&updated, "meta.xml")).unwrap();
+    assert!(meta.contains("LibreOffice"));
+    assert!(meta.contains("Reviewer"));
+    assert!(!meta.contains("Old Series"));
+}
+
+#[test]
+fn test_fb2_set_metadata_round_trip() {
+    let fb2 = r##"<?xml version="1.0" encoding="windows-1251"?>
+<FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0" xmlns:l="http://www.w3.org/1999/xlink">
+<description>
+  <title-info>
+    <genre>sf</genre>
+    <author><first-name>Иван</first-name><last-name>Петров</last-name></author>
+    <book-title>Старое</book-title>
+    <date>1999</date>
+    <lang>ru</lang>
+    <translator><first-name>Tra</first-name><last-name>Nslator</last-name></translator>
+  </title-info>
+  <document-info><author><nickname>x</nickname></author></document-info>
+</description>
+<body><section><p>Текст</p></section></body>
+</FictionBook>"##;
+    let (data, _, _) = encoding_rs::WINDOWS_1251.encode(fb2);
+    let mut mi = new_metadata();
+    mi.title = "Новое".to_string();
+    mi.comments = Some("First paragraph.\n\nSecond paragraph.".to_string());
+    mi.cover_data = (Some("png".to_string()), image(4, 4, ImageFormat::Png));
+    let updated = metadata::fb2::set_metadata(&data, &mi).unwrap();
+
+    // Still windows-1251
+    let (text, _, errors) = encoding_rs::WINDOWS_1251.decode(&updated);
+    assert!(!errors);
+    assert!(text.contains("Новое"));
+    assert!(text.contains("Текст"));
+    assert!(text.contains("<date>1999</date>"));
+    assert!(text.contains("Nslator"));
+
+    let utf8 = text.replace("windows-1251", "utf-8");
+    let read = metadata::fb2::get_metadata(Cursor::new(utf8.into_bytes())).unwrap();
+    assert_eq!(read.title, "Новое");
+    assert_eq!(read.authors, vec!["Jane Roe", "Ana María Díaz"]);
+    assert_eq!(read.tags, vec!["Fantasy", "Adventure"]);
+    assert_eq!(read.series.as_deref(), Some("The Saga"));
+    assert_eq!(read.series_index, 3.0);
+    assert_eq!(
+        read.comments.as_deref(),
+        Some("First paragraph.\n\nSecond paragraph.")
+    );
+    assert_eq!(read.cover_data.1, mi.cover_data.1);
+
+    // A second cover replaces the first binary
+    mi.cover_data = (Some("png".to_string()), image(5, 5, ImageFormat::Png));
+    let again = metadata::fb2::set_metadata(&updated, &mi).unwrap();
+    let (text, _, _) = encoding_rs::WINDOWS_1251.decode(&again);
+    assert_eq!(text.matches("<binary").count(), 1);
+}
+
+#[test]
+fn test_cbz_set_metadata_round_trip() {
+    let old_comment = r#"{"appID": "ComicTagger", "ComicBookInfo/1.0": {"title": "Old", "credits": [{"person": "Old Writer", "role": "Writer"}, {"person": "Colour Guy", "role": "Colorist"}], "issue": 7}}"#;
+    let page = image(2, 2, ImageFormat::Png);
+    let cbz = build_zip(&[("001.png", &page), ("002.png", &page)], old_comment);
+    let updated = metadata::zip::set_metadata(&cbz, &new_metadata()).unwrap();
+
+    let read = metadata::zip::get_metadata(Cursor::new(&updated)).unwrap();
+    assert_eq!(read.title, "The New Title");
+    assert_eq!(read.authors, vec!["Jane Roe", "Ana María Díaz"]);
+    assert_eq!(read.series.as_deref(), Some("The Saga"));
+    assert_eq!(read.series_index, 3.0);
+    assert_eq!(read.tags, vec!["Fantasy", "Adventure"]);
+    assert_eq!(read.publisher.as_deref(), Some("Acme & Sons"));
+
+    let archive = ZipArchive::new(Cursor::new(&updated)).unwrap();
+    let comment: serde_json::Value = serde_json::from_slice(archive.comment()).unwrap();
+    let cbi = &comment["ComicBookInfo/1.0"];
+    assert_eq!(cbi["issue"], 7);
+    assert_eq!(cbi["publicationYear"], 2011);
+    let credits = cbi["credits"].as_array().unwrap();
+    assert_eq!(credits.len(), 3);
+    assert!(credits.iter().any(|c| c["role"] == "Colorist"));
+    assert_eq!(zip_entry(&updated, "002.png"), page);
+
+    // Plain zip files have nowhere to put metadata
+    let plain = build_zip(&[("book.txt", b"text")], "");
+    assert!(metadata::zip::set_metadata(&plain, &new_metadata()).is_err());
+}
+
+#[test]
+fn test_set_metadata_dispatch() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("book.epub");
+    std::fs::write(&path, epub2()).unwrap();
+
+    metadata::set_metadata(&path, &new_metadata()).unwrap();
+    let read = metadata::get_metadata(&path).unwrap();
+    assert_eq!(read.title, "The New Title");
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+    let txt = dir.path().join("book.txt");
+    std::fs::write(&txt, "Some text").unwrap();
+    assert!(metadata::set_metadata(&txt, &new_metadata()).is_err());
+    assert_eq!(std::fs::read_to_string(&txt).unwrap(), "Some text");
+}
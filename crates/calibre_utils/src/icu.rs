@@ -17,3 +17,9 @@ pub fn capitalize(text: &str) -> String {
         Some(f) => f.to_uppercase().collect::<String>() + c.as_str(),
     }
 }
+
+/// Key for ordering text so that case and accents do not move an entry,
+/// "Émile" sorts next to "emile".
+pub fn sort_key(text: &str) -> String {
+    unidecode::unidecode(text).to_lowercase()
+}
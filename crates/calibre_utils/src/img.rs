@@ -0,0 +1,503 @@
+//! Decoding, re-encoding and resizing of images: covers, thumbnails and
+//! the images of books sent to devices.
+//! Ported from calibre/utils/img.py, in pure Rust on top of the image crate.
+
+use anyhow::{bail, Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, RgbImage};
+use std::io::Cursor;
+
+/// Largest image record the Kindle firmware accepts in a MOBI/AZW3 file.
+pub const KINDLE_MAX_IMAGE_SIZE: usize = 127 * 1024;
+
+/// JPEG quality used for covers and when nothing else is asked for.
+pub const DEFAULT_QUALITY: u8 = 90;
+
+/// The format of encoded image data, for the formats that can be decoded.
+pub fn image_format(data: &[u8]) -> Option<ImageFormat> {
+    match image::guess_format(data).ok()? {
+        f @ (ImageFormat::Jpeg
+        | ImageFormat::Png
+        | ImageFormat::Gif
+        | ImageFormat::WebP
+        | ImageFormat::Bmp) => Some(f),
+        _ => None,
+    }
+}
+
+/// Decode an image, turning it upright according to its EXIF orientation.
+pub fn image_from_data(data: &[u8]) -> Result<DynamicImage> {
+    if image_format(data).is_none() {
+        bail!("Not a valid image, or an unsupported image format");
+    }
+    let img = image::load_from_memory(data).context("Failed to decode image")?;
+    Ok(match exif_orientation(data) {
+        Some(orientation) => apply_orientation(img, orientation),
+        None => img,
+    })
+}
+
+/// Encode `img` as `format`. JPEG has no transparency so transparent
+/// pixels are composited onto white first. WebP is written as PNG, as
+/// there is no WebP encoder.
+pub fn image_to_data(img: &DynamicImage, format: ImageFormat, quality: u8) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    match format {
+        ImageFormat::Jpeg => {
+            let rgb = flatten_on_white(img);
+            let mut encoder = JpegEncoder::new_with_quality(&mut out, quality.clamp(1, 100));
+            if img.color().has_color() {
+                encoder.encode_image(&rgb)
+            } else {
+                encoder.encode_image(&DynamicImage::ImageRgb8(rgb).to_luma8())
+            }
+            .context("Failed to encode JPEG image")?;
+        }
+        ImageFormat::Gif => {
+            DynamicImage::ImageRgba8(img.to_rgba8())
+                .write_to(&mut Cursor::new(&mut out), ImageFormat::Gif)
+                .context("Failed to encode GIF image")?;
+        }
+        ImageFormat::Png | ImageFormat::WebP => {
+            img.write_to(&mut Cursor::new(&mut out), ImageFormat::Png)
+                .context("Failed to encode PNG image")?;
+        }
+        other => {
+            img.write_to(&mut Cursor::new(&mut out), other)
+                .with_context(|| format!("Failed to encode {:?} image", other))?;
+        }
+    }
+    Ok(out)
+}
+
+/// Replace transparency with a white background.
+pub fn flatten_on_white(img: &DynamicImage) -> RgbImage {
+    if !img.color().has_alpha() {
+        return img.to_rgb8();
+    }
+    let rgba = img.to_rgba8();
+    let mut out = RgbImage::new(rgba.width(), rgba.height());
+    for (x, y, p) in rgba.enumerate_pixels() {
+        let a = p[3] as u32;
+        let blend = |c: u8| ((c as u32 * a + 255 * (255 - a)) / 255) as u8;
+        out.put_pixel(x, y, image::Rgb([blend(p[0]), blend(p[1]), blend(p[2])]));
+    }
+    out
+}
+
+/// Whether an image of `width`x`height` must be scaled down to fit in a
+/// `pwidth`x`pheight` box, and the size it should then have, keeping the
+/// aspect ratio.
+pub fn fit_image(width: u32, height: u32, pwidth: u32, pheight: u32) -> (bool, u32, u32) {
+    let (mut w, mut h) = (width as f64, height as f64);
+    let (pw, ph) = (pwidth as f64, pheight as f64);
+    let scaled = width > pwidth || height > pheight;
+    if w > pw {
+        h *= pw / w;
+        w = pw;
+    }
+    if h > ph {
+        w *= ph / h;
+        h = ph;
+    }
+    (scaled, (w.floor() as u32).max(1), (h.floor() as u32).max(1))
+}
+
+/// Scale `img` down to fit in a `width`x`height` box. Smaller images are
+/// returned unchanged.
+pub fn resize_to_fit(img: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let (scaled, w, h) = fit_image(img.width(), img.height(), width, height);
+    if scaled {
+        img.resize_exact(w, h, FilterType::Lanczos3)
+    } else {
+        img.clone()
+    }
+}
+
+pub fn grayscale_image(img: &DynamicImage) -> DynamicImage {
+    if img.color().has_alpha() {
+        DynamicImage::ImageLumaA8(img.to_luma_alpha8())
+    } else {
+        DynamicImage::ImageLuma8(img.to_luma8())
+    }
+}
+
+/// Cover image data as it is stored in a library: a JPEG without EXIF
+/// metadata. JPEGs are only re-encoded when they have to be rotated, so
+/// that their quality does not degrade each time a cover is set.
+pub fn save_cover_data_to(data: &[u8]) -> Result<Vec<u8>> {
+    let Some(format) = image_format(data) else {
+        bail!("Not a valid image, or an unsupported image format");
+    };
+    if format == ImageFormat::Jpeg && exif_orientation(data).is_none_or(|o| o == 1) {
+        // Make sure it decodes before storing it
+        image::load_from_memory_with_format(data, ImageFormat::Jpeg)
+            .context("Failed to decode image")?;
+        return Ok(strip_exif(data));
+    }
+    let img = image_from_data(data)?;
+    image_to_data(&img, ImageFormat::Jpeg, DEFAULT_QUALITY)
+}
+
+/// A JPEG thumbnail of an image that fits in `size`, along with its
+/// actual width and height.
+pub fn thumbnail(data: &[u8], size: (u32, u32), quality: u8) -> Result<(Vec<u8>, u32, u32)> {
+    let img = resize_to_fit(&image_from_data(data)?, size.0, size.1);
+    let (width, height) = (img.width(), img.height());
+    Ok((
+        image_to_data(&img, ImageFormat::Jpeg, quality)?,
+        width,
+        height,
+    ))
+}
+
+/// Scale an image down to a device screen, typically the `screen_size` of
+/// an output profile, optionally turning it to grayscale. The image keeps
+/// its format where it can be written, WebP becomes PNG. Returns the data
+/// and its format, the original data when there was nothing to do.
+pub fn rescale_to_screen(
+    data: &[u8],
+    screen_size: (u32, u32),
+    grayscale: bool,
+) -> Result<(Vec<u8>, ImageFormat)> {
+    let Some(format) = image_format(data) else {
+        bail!("Not a valid image, or an unsupported image format");
+    };
+    let img = image_from_data(data)?;
+    let (scaled, _, _) = fit_image(img.width(), img.height(), screen_size.0, screen_size.1);
+    let is_gray = !img.color().has_color();
+    if !scaled && (!grayscale || is_gray) && format != ImageFormat::WebP {
+        return Ok((data.to_vec(), format));
+    }
+    let mut img = resize_to_fit(&img, screen_size.0, screen_size.1);
+    if grayscale {
+        img = grayscale_image(&img);
+    }
+    let format = match format {
+        ImageFormat::WebP => ImageFormat::Png,
+        f => f,
+    };
+    Ok((image_to_data(&img, format, DEFAULT_QUALITY)?, format))
+}
+
+/// Re-encode an image as a JPEG of at most `max_size` bytes, replacing
+/// transparent pixels with white. When `dimen` is given the image is first
+/// shrunk to fit in a `(width, height)` box. Quality is lowered first and
+/// the image is only scaled down if that is not enough.
+pub fn shrink_to_size(data: &[u8], max_size: usize, dimen: Option<(u32, u32)>) -> Result<Vec<u8>> {
+    let mut img = image_from_data(data)?;
+    if let Some((width, height)) = dimen {
+        img = resize_to_fit(&img, width, height);
+    }
+    let img = DynamicImage::ImageRgb8(flatten_on_white(&img));
+
+    let mut quality = DEFAULT_QUALITY;
+    let mut ans = image_to_data(&img, ImageFormat::Jpeg, quality)?;
+    while ans.len() > max_size && quality > 10 {
+        quality -= 10;
+        ans = image_to_data(&img, ImageFormat::Jpeg, quality)?;
+    }
+    let mut scale = 0.9f32;
+    while ans.len() > max_size && scale >= 0.05 {
+        let w = ((img.width() as f32 * scale) as u32).max(1);
+        let h = ((img.height() as f32 * scale) as u32).max(1);
+        let small = img.resize_exact(w, h, FilterType::Triangle);
+        ans = image_to_data(&small, ImageFormat::Jpeg, quality)?;
+        scale -= 0.1;
+    }
+    if ans.len() > max_size {
+        bail!(
+            "Could not shrink image to less than {} bytes, it is still {} bytes",
+            max_size,
+            ans.len()
+        );
+    }
+    Ok(ans)
+}
+
+/// The image data with its EXIF metadata removed, without re-encoding.
+/// Handles JPEG, PNG and WebP, other data is returned unchanged.
+pub fn strip_exif(data: &[u8]) -> Vec<u8> {
+    match image_format(data) {
+        Some(ImageFormat::Jpeg) => strip_jpeg_exif(data),
+        Some(ImageFormat::Png) => strip_png_exif(data),
+        Some(ImageFormat::WebP) => strip_webp_exif(data),
+        _ => data.to_vec(),
+    }
+}
+
+/// A JPEG segment as its marker and byte range, the marker included.
+type Segment = (u8, usize, usize);
+
+/// The segments of a JPEG before its image data, and where that data
+/// starts. `None` if the data is malformed.
+fn jpeg_segments(data: &[u8]) -> Option<(Vec<Segment>, usize)> {
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return None;
+    }
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xff {
+            return None;
+        }
+        let marker = data[pos + 1];
+        if marker == 0xff {
+            // Fill byte
+            pos += 1;
+            continue;
+        }
+        if marker == 0xda || marker == 0xd9 {
+            // Start of scan, the rest is image data
+            return Some((segments, pos));
+        }
+        if marker == 0x01 || (0xd0..=0xd7).contains(&marker) {
+            segments.push((marker, pos, pos + 2));
+            pos += 2;
+            continue;
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let end = pos + 2 + len;
+        if len < 2 || end > data.len() {
+            return None;
+        }
+        segments.push((marker, pos, end));
+        pos = end;
+    }
+    None
+}
+
+fn is_exif_segment(data: &[u8], marker: u8, start: usize, end: usize) -> bool {
+    marker == 0xe1 && data[start + 4..end].starts_with(b"Exif\0")
+}
+
+fn strip_jpeg_exif(data: &[u8]) -> Vec<u8> {
+    let Some((segments, scan_start)) = jpeg_segments(data) else {
+        return data.to_vec();
+    };
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..2]);
+    for (marker, start, end) in segments {
+        if !is_exif_segment(data, marker, start, end) {
+            out.extend_from_slice(&data[start..end]);
+        }
+    }
+    out.extend_from_slice(&data[scan_start..]);
+    out
+}
+
+fn strip_png_exif(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..8]);
+    let mut pos = 8;
+    while pos + 12 <= data.len() {
+        let len = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let end = pos + 12 + len;
+        if end > data.len() {
+            return data.to_vec();
+        }
+        if &data[pos + 4..pos + 8] != b"eXIf" {
+            out.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+    }
+    out.extend_from_slice(&data[pos..]);
+    out
+}
+
+fn strip_webp_exif(data: &[u8]) -> Vec<u8> {
+    if data.len() < 12 {
+        return data.to_vec();
+    }
+    let mut out = data[..12].to_vec();
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        // Chunks are padded to an even size
+        let end = (pos + 8 + len + (len & 1)).min(data.len());
+        if pos + 8 + len > data.len() {
+            return data.to_vec();
+        }
+        match &data[pos..pos + 4] {
+            b"EXIF" => {}
+            b"VP8X" if len >= 1 => {
+                let flags = out.len() + 8;
+                out.extend_from_slice(&data[pos..end]);
+                // Clear the "has EXIF" flag
+                out[flags] &= !0x08;
+            }
+            _ => out.extend_from_slice(&data[pos..end]),
+        }
+        pos = end;
+    }
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    out
+}
+
+/// The EXIF orientation of a JPEG, 1 to 8, 1 being upright.
+pub fn exif_orientation(data: &[u8]) -> Option<u16> {
+    let (segments, _) = jpeg_segments(data)?;
+    let (_, start, end) = segments
+        .into_iter()
+        .find(|(marker, start, end)| is_exif_segment(data, *marker, *start, *end))?;
+    let tiff = data.get(start + 10..end)?;
+    let little_endian = match tiff.get(..4)? {
+        b"II*\0" => true,
+        b"MM\0*" => false,
+        _ => return None,
+    };
+    let u16_at = |pos: usize| -> Option<u16> {
+        let b: [u8; 2] = tiff.get(pos..pos + 2)?.try_into().ok()?;
+        Some(if little_endian {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    };
+    let u32_at = |pos: usize| -> Option<u32> {
+        let b: [u8; 4] = tiff.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    };
+    let ifd = u32_at(4)? as usize;
+    let count = u16_at(ifd)? as usize;
+    (0..count).find_map(|i| {
+        let entry = ifd + 2 + i * 12;
+        if u16_at(entry)? != 0x0112 {
+            return None;
+        }
+        u16_at(entry + 8).filter(|o| (1..=8).contains(o))
+    })
+}
+
+fn apply_orientation(img: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, Rgba, RgbaImage};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let img = RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([
+                (x % 256) as u8,
+                (y % 256) as u8,
+                128,
+                if x < 2 { 0 } else { 255 },
+            ])
+        });
+        image_to_data(&DynamicImage::ImageRgba8(img), ImageFormat::Png, 90).unwrap()
+    }
+
+    /// A JPEG with an APP1 EXIF segment holding only an orientation tag.
+    fn jpeg_with_orientation(width: u32, height: u32, orientation: u16) -> Vec<u8> {
+        let img = DynamicImage::ImageRgba8(RgbaImage::new(width, height));
+        let jpeg = image_to_data(&img, ImageFormat::Jpeg, 90).unwrap();
+        let mut tiff = b"MM\0*\0\0\0\x08\0\x01".to_vec();
+        tiff.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1]);
+        tiff.extend_from_slice(&orientation.to_be_bytes());
+        tiff.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        let mut app1 = vec![0xff, 0xe1];
+        app1.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        app1.extend_from_slice(b"Exif\0\0");
+        app1.extend_from_slice(&tiff);
+        let mut out = jpeg[..2].to_vec();
+        out.extend_from_slice(&app1);
+        out.extend_from_slice(&jpeg[2..]);
+        out
+    }
+
+    #[test]
+    fn test_fit_image() {
+        assert_eq!(fit_image(100, 50, 200, 200), (false, 100, 50));
+        assert_eq!(fit_image(400, 200, 200, 200), (true, 200, 100));
+        assert_eq!(fit_image(200, 400, 100, 100), (true, 50, 100));
+    }
+
+    #[test]
+    fn test_cover_normalised_to_jpeg() {
+        let cover = save_cover_data_to(&png(20, 30)).unwrap();
+        assert_eq!(image_format(&cover), Some(ImageFormat::Jpeg));
+        let img = image::load_from_memory(&cover).unwrap();
+        assert_eq!(img.dimensions(), (20, 30));
+        // Transparent pixels end up white
+        let p = img.to_rgb8().get_pixel(0, 0).0;
+        assert!(p.iter().all(|c| *c > 240), "{:?}", p);
+
+        assert!(save_cover_data_to(b"not an image").is_err());
+    }
+
+    #[test]
+    fn test_exif_orientation_and_stripping() {
+        let rotated = jpeg_with_orientation(20, 10, 6);
+        assert_eq!(exif_orientation(&rotated), Some(6));
+        assert_eq!(image_from_data(&rotated).unwrap().dimensions(), (10, 20));
+
+        let cover = save_cover_data_to(&rotated).unwrap();
+        assert_eq!(exif_orientation(&cover), None);
+        assert_eq!(
+            image::load_from_memory(&cover).unwrap().dimensions(),
+            (10, 20)
+        );
+
+        let upright = jpeg_with_orientation(20, 10, 1);
+        let stripped = strip_exif(&upright);
+        assert!(stripped.len() < upright.len());
+        assert_eq!(exif_orientation(&stripped), None);
+        assert_eq!(save_cover_data_to(&upright).unwrap(), stripped);
+    }
+
+    #[test]
+    fn test_thumbnail_and_screen_rescale() {
+        let (thumb, width, height) = thumbnail(&png(300, 600), (100, 100), 70).unwrap();
+        assert_eq!((width, height), (50, 100));
+        assert_eq!(image_format(&thumb), Some(ImageFormat::Jpeg));
+
+        let small = png(50, 50);
+        let (data, format) = rescale_to_screen(&small, (600, 800), false).unwrap();
+        assert_eq!((data, format), (small.clone(), ImageFormat::Png));
+
+        let (data, format) = rescale_to_screen(&png(1200, 800), (600, 800), true).unwrap();
+        assert_eq!(format, ImageFormat::Png);
+        let img = image::load_from_memory(&data).unwrap();
+        assert_eq!(img.dimensions(), (600, 400));
+        assert!(!img.color().has_color());
+    }
+
+    #[test]
+    fn test_shrink_to_size() {
+        let noisy = RgbaImage::from_fn(800, 800, |x, y| {
+            Rgba([
+                (x * 31 ^ y * 17) as u8,
+                (x * y) as u8,
+                (x + y * 7) as u8,
+                255,
+            ])
+        });
+        let data = image_to_data(&DynamicImage::ImageRgba8(noisy), ImageFormat::Png, 90).unwrap();
+        assert!(data.len() > KINDLE_MAX_IMAGE_SIZE);
+        let shrunk = shrink_to_size(&data, KINDLE_MAX_IMAGE_SIZE, None).unwrap();
+        assert!(shrunk.len() <= KINDLE_MAX_IMAGE_SIZE);
+        assert_eq!(image_format(&shrunk), Some(ImageFormat::Jpeg));
+
+        let thumb = shrink_to_size(&data, 16 * 1024, Some((180, 240))).unwrap();
+        let img = image::load_from_memory(&thumb).unwrap();
+        assert!(img.width() <= 180 && img.height() <= 240);
+    }
+}
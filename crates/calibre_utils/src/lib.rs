@@ -5,6 +5,7 @@ pub mod date;
 pub mod filenames;
 pub mod html2text;
 pub mod icu;
+pub mod img;
 pub mod imghdr;
 pub mod logging;
 pub mod mem;
@@ -6,7 +6,7 @@ lazy_static! {
     static ref SMALL_WORDS_REGEX: Regex = Regex::new(r"(?i)^(a|an|and|as|at|but|by|en|for|if|in|of|on|or|the|to|v\.?|via|vs\.?)$").unwrap();
     static ref INLINE_PERIOD_REGEX: Regex = Regex::new(r"(?i)[a-z][.][a-z]").unwrap();
     static ref UC_ELSEWHERE_REGEX: Regex = Regex::new(r##"(?x)
-        [!"#\$\%'()*+,-.\/:;<=>?\@\[\\\]\^_`{|}~]*?
+        [!"\#\$\%'()*+,-.\/:;<=>?\@\[\\\]\^_`{|}~]*?
         [a-zA-Z]+[A-Z]+?
     "##).unwrap();
     // CAPFIRST depends on unicode word chars.
@@ -135,3 +135,14 @@ pub fn titlecase(text: &str) -> String {
     
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_titlecase() {
+        assert_eq!(titlecase("the first book"), "The First Book");
+        assert_eq!(titlecase("a tale of two cities"), "A Tale of Two Cities");
+    }
+}